@@ -0,0 +1,49 @@
+use tracing_cassette::{Anonymizer, FieldAction};
+
+use crate::{args::Args, recording, Result};
+
+const USAGE: &str = "usage: cassette anonymize <recording> [--field <pattern>=<action>]... \
+                     [--default <action>] [--salt <salt>] [--strip-paths] [--strip-targets] \
+                     [-o <output>]";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(
+        args,
+        &["--field", "--default", "--salt", "--output"],
+        &["--strip-paths", "--strip-targets"],
+    )?;
+    let [path] = args.positional() else {
+        return Err(USAGE.into());
+    };
+
+    let mut anonymizer = Anonymizer::new()
+        .with_strip_paths(args.flag("--strip-paths"))
+        .with_strip_targets(args.flag("--strip-targets"));
+    for field in args.options("--field") {
+        let Some((pattern, action)) = field.rsplit_once('=') else {
+            return Err(format!("invalid field rule, expected <pattern>=<action>: {field}").into());
+        };
+        anonymizer = anonymizer.with_field(pattern, parse_action(action)?);
+    }
+    if let Some(action) = args.option("--default") {
+        anonymizer = anonymizer.with_default_action(parse_action(action)?);
+    }
+    if let Some(salt) = args.option("--salt") {
+        anonymizer = anonymizer.with_salt(salt);
+    }
+
+    let input = recording::read_input(path)?;
+    anonymizer
+        .anonymize_recording(input.as_slice(), args.output()?)
+        .map_err(|err| format!("failed to anonymize {path}: {err}").into())
+}
+
+fn parse_action(action: &str) -> Result<FieldAction> {
+    Ok(match action {
+        "keep" => FieldAction::Keep,
+        "hash" => FieldAction::Hash,
+        "tokenize" => FieldAction::Tokenize,
+        "remove" => FieldAction::Remove,
+        _ => return Err(format!("unknown field action: {action}").into()),
+    })
+}
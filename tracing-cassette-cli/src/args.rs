@@ -0,0 +1,131 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    time::Duration,
+};
+
+use tracing_cassette::Level;
+
+use crate::Result;
+
+/// The parsed arguments of a command.
+#[derive(Debug, Default)]
+pub(crate) struct Args {
+    positional: Vec<String>,
+    options: Vec<(String, String)>,
+    flags: Vec<String>,
+}
+
+impl Args {
+    /// Parses the arguments of a command, which accepts the `options` that take a value and the
+    /// `flags` which don't.
+    ///
+    /// `-o` is accepted as the short form of `--output`.
+    pub(crate) fn parse(args: Vec<String>, options: &[&str], flags: &[&str]) -> Result<Self> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let name = if arg == "-o" { "--output" } else { &arg };
+            if options.contains(&name) {
+                let Some(value) = args.next() else {
+                    return Err(format!("missing value for {name}").into());
+                };
+                parsed.options.push((name.to_owned(), value));
+            } else if flags.contains(&name) {
+                parsed.flags.push(arg);
+            } else if name.starts_with("--") {
+                return Err(format!("unknown option: {name}").into());
+            } else {
+                parsed.positional.push(arg);
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    pub(crate) fn positional(&self) -> &[String] {
+        &self.positional
+    }
+
+    /// Returns the value of an option, the last one wins if it was given more than once.
+    pub(crate) fn option(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(option, _)| option == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns all the values of an option, in the order they were given.
+    pub(crate) fn options<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.options
+            .iter()
+            .filter(move |(option, _)| option == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub(crate) fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+
+    /// Returns the value of an option parsed as a duration, see [`parse_duration`].
+    pub(crate) fn duration(&self, name: &str) -> Result<Option<Duration>> {
+        self.option(name)
+            .map(|value| {
+                parse_duration(value).ok_or_else(|| format!("invalid time for {name}: {value}"))
+            })
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Returns the writer for `--output`, or stdout if it wasn't given.
+    pub(crate) fn output(&self) -> Result<Box<dyn Write>> {
+        Ok(match self.option("--output") {
+            Some("-") | None => Box::new(BufWriter::new(io::stdout().lock())),
+            Some(path) => Box::new(BufWriter::new(
+                File::create(path).map_err(|err| format!("failed to create {path}: {err}"))?,
+            )),
+        })
+    }
+}
+
+/// Parses a duration such as `1.5s`, `200ms` or `10us`, a number without a unit is taken as
+/// seconds.
+pub(crate) fn parse_duration(value: &str) -> Option<Duration> {
+    let (number, scale) = if let Some(number) = value.strip_suffix("ms") {
+        (number, 1e-3)
+    } else if let Some(number) = value.strip_suffix("us") {
+        (number, 1e-6)
+    } else if let Some(number) = value.strip_suffix("ns") {
+        (number, 1e-9)
+    } else if let Some(number) = value.strip_suffix('s') {
+        (number, 1.0)
+    } else {
+        (value, 1.0)
+    };
+    let number: f64 = number.parse().ok()?;
+    Duration::try_from_secs_f64(number * scale).ok()
+}
+
+/// Parses a level, ignoring case.
+pub(crate) fn parse_level(value: &str) -> Result<Level> {
+    match value.to_ascii_lowercase().as_str() {
+        "trace" => Ok(Level::Trace),
+        "debug" => Ok(Level::Debug),
+        "info" => Ok(Level::Info),
+        "warn" => Ok(Level::Warn),
+        "error" => Ok(Level::Error),
+        _ => Err(format!("invalid level: {value}").into()),
+    }
+}
+
+/// Orders levels from the least to the most severe.
+pub(crate) fn severity(level: &Level) -> u8 {
+    match level {
+        Level::Trace => 0,
+        Level::Debug => 1,
+        Level::Info => 2,
+        Level::Warn => 3,
+        Level::Error => 4,
+    }
+}
@@ -0,0 +1,30 @@
+use std::io::Write;
+
+use tracing_cassette::{PrettyRecord, RecordReader};
+
+use crate::{args::Args, recording, Result};
+
+const USAGE: &str = "usage: cassette cat <recording> [--pretty] [-o <output>]";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(args, &["--output"], &["--pretty"])?;
+    let [path] = args.positional() else {
+        return Err(USAGE.into());
+    };
+    let pretty = args.flag("--pretty");
+
+    let input = recording::read_input(path)?;
+    let mut output = args.output()?;
+    for record in RecordReader::new(input.as_slice()) {
+        let record = record.map_err(|err| format!("failed to read {path}: {err}"))?;
+        if pretty {
+            writeln!(output, "{}", PrettyRecord::new(&record))?;
+        } else {
+            serde_json::to_writer(&mut output, &record)?;
+            output.write_all(b"\n")?;
+        }
+    }
+    output.flush()?;
+
+    Ok(())
+}
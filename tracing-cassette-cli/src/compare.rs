@@ -0,0 +1,87 @@
+use std::io::Write;
+
+use tracing_cassette::LatencyComparison;
+
+use crate::{args::Args, recording, Result};
+
+const USAGE: &str = "usage: cassette compare <baseline> <candidate> [--threshold <percent>] \
+                     [--significance <p-value>] [--min-samples <n>] [-o <output>]";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(
+        args,
+        &["--threshold", "--significance", "--min-samples", "--output"],
+        &[],
+    )?;
+    let [baseline_path, candidate_path] = args.positional() else {
+        return Err(USAGE.into());
+    };
+    let mut comparison = LatencyComparison::new();
+    if let Some(threshold) = args.option("--threshold") {
+        let percent = threshold
+            .strip_suffix('%')
+            .unwrap_or(threshold)
+            .parse::<f64>()
+            .ok()
+            .filter(|percent| *percent >= 0.0)
+            .ok_or_else(|| format!("invalid percentage for --threshold: {threshold}"))?;
+        comparison = comparison.with_threshold(percent / 100.0);
+    }
+    if let Some(significance) = args.option("--significance") {
+        let significance = significance
+            .parse::<f64>()
+            .ok()
+            .filter(|significance| (0.0..=1.0).contains(significance))
+            .ok_or_else(|| format!("invalid p-value for --significance: {significance}"))?;
+        comparison = comparison.with_significance(significance);
+    }
+    if let Some(min_samples) = args.option("--min-samples") {
+        let min_samples = min_samples
+            .parse::<usize>()
+            .map_err(|_| format!("invalid number for --min-samples: {min_samples}"))?;
+        comparison = comparison.with_min_samples(min_samples);
+    }
+
+    let baseline = recording::read_input(baseline_path)?;
+    let candidate = recording::read_input(candidate_path)?;
+    let report = comparison
+        .compare(baseline.as_slice(), candidate.as_slice())
+        .map_err(|err| format!("failed to read {baseline_path} or {candidate_path}: {err}"))?;
+
+    let mut output = args.output()?;
+    for latency in &report.callsites {
+        let p_value = latency.p_value.map_or_else(
+            || "too few spans".to_owned(),
+            |p_value| format!("p={p_value:.4}"),
+        );
+        writeln!(
+            output,
+            "{status} {target}::{name}: median {baseline_median:?} -> {candidate_median:?} \
+             ({change:+.1}%), p95 {baseline_p95:?} -> {candidate_p95:?}, \
+             {baseline_count} -> {candidate_count} spans, {p_value}",
+            status = if latency.regression {
+                "REGRESSED"
+            } else {
+                "ok       "
+            },
+            target = latency.target,
+            name = latency.name,
+            baseline_median = latency.baseline.median,
+            candidate_median = latency.candidate.median,
+            change = latency.median_change() * 100.0,
+            baseline_p95 = latency.baseline.p95,
+            candidate_p95 = latency.candidate.p95,
+            baseline_count = latency.baseline.count,
+            candidate_count = latency.candidate.count,
+        )?;
+    }
+    output.flush()?;
+
+    // Like `diff`, finding regressions is a failure, so that a release process can gate on it.
+    let regressions = report.regressions().count();
+    if regressions == 0 {
+        Ok(())
+    } else {
+        Err(format!("{regressions} span callsites regressed").into())
+    }
+}
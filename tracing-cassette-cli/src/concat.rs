@@ -0,0 +1,18 @@
+use crate::{args::Args, recording, Result};
+
+const USAGE: &str = "usage: cassette concat <recording>... [--gap <time>] [-o <output>]";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(args, &["--gap", "--output"], &[])?;
+    let paths = args.positional();
+    if paths.is_empty() {
+        return Err(USAGE.into());
+    }
+    let gap = args.duration("--gap")?.unwrap_or_default();
+
+    let recordings = paths
+        .iter()
+        .map(|path| recording::read_records(path))
+        .collect::<Result<Vec<_>>>()?;
+    recording::write_records(args.output()?, &tracing_cassette::concat(recordings, gap))
+}
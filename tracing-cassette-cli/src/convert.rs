@@ -0,0 +1,97 @@
+use std::io::Write;
+
+use tracing_cassette::{ChromeSpanPhase, Compression, ContainerWriter, OtlpImport};
+
+use crate::{args::Args, recording, Result};
+
+const USAGE: &str = "usage: cassette convert <input> --to <format> [--from <format>] \
+                     [--service-name <name>] [--compression <compression>] [-o <output>]";
+
+/// The service name given to OpenTelemetry and Jaeger exports, unless one is provided.
+const DEFAULT_SERVICE_NAME: &str = "recording";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(
+        args,
+        &[
+            "--from",
+            "--to",
+            "--service-name",
+            "--compression",
+            "--output",
+        ],
+        &[],
+    )?;
+    let [path] = args.positional() else {
+        return Err(USAGE.into());
+    };
+    let Some(to) = args.option("--to") else {
+        return Err(USAGE.into());
+    };
+    let service_name = args
+        .option("--service-name")
+        .unwrap_or(DEFAULT_SERVICE_NAME);
+    let compression = match args.option("--compression").unwrap_or("none") {
+        "none" => Compression::None,
+        "deflate" => Compression::Deflate,
+        compression => return Err(format!("unknown compression: {compression}").into()),
+    };
+
+    let input = recording::read_input(path)?;
+    // Imported data is turned into a recording first, so that it can be exported to any format.
+    let recording = match args.option("--from").unwrap_or("recording") {
+        "recording" => input,
+        from @ ("otlp-json" | "otlp-traces-protobuf" | "otlp-logs-protobuf") => {
+            let mut import = OtlpImport::new();
+            match from {
+                "otlp-json" => import.add_json(&input)?,
+                "otlp-traces-protobuf" => import.add_protobuf_traces(&input)?,
+                _ => import.add_protobuf_logs(&input)?,
+            }
+            let mut recording = Vec::new();
+            tracing_cassette::write_recording(&mut recording, &import.into_records())?;
+            recording
+        }
+        from => return Err(format!("unknown input format: {from}").into()),
+    };
+
+    let reader = recording.as_slice();
+    let mut output = args.output()?;
+    match to {
+        "recording" => {
+            // Rewriting the records migrates them to the current version of the format.
+            let records = recording::read_records_from(reader)?;
+            recording::write_records(output, &records)?;
+        }
+        "container" => {
+            let mut writer = ContainerWriter::new(output).with_compression(compression);
+            for record in recording::read_records_from(reader)? {
+                writer.write_record(&record)?;
+            }
+            writer.finish()?;
+        }
+        "chrome" => tracing_cassette::to_chrome_trace(reader, output, ChromeSpanPhase::BeginEnd)?,
+        "chrome-complete" => {
+            tracing_cassette::to_chrome_trace(reader, output, ChromeSpanPhase::Complete)?;
+        }
+        "perfetto" => tracing_cassette::to_perfetto_trace(reader, output)?,
+        "speedscope" => tracing_cassette::to_speedscope(reader, output)?,
+        "folded" => tracing_cassette::to_folded_stacks(reader, output)?,
+        "html" => tracing_cassette::to_html(reader, output)?,
+        "jaeger" => tracing_cassette::to_jaeger(reader, output, service_name)?,
+        "tracing-mock" => tracing_cassette::to_tracing_mock(reader, output)?,
+        "otlp-json" => {
+            // The traces and logs requests are written one after the other, which is how the
+            // collector's file exporter writes them, and what `--from otlp-json` reads.
+            let requests = tracing_cassette::to_otlp(reader, service_name)?;
+            serde_json::to_writer(&mut output, &requests.traces)?;
+            output.write_all(b"\n")?;
+            serde_json::to_writer(&mut output, &requests.logs)?;
+            output.write_all(b"\n")?;
+            output.flush()?;
+        }
+        to => return Err(format!("unknown output format: {to}").into()),
+    }
+
+    Ok(())
+}
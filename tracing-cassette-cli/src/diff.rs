@@ -0,0 +1,29 @@
+use std::io::Write;
+
+use crate::{args::Args, recording, Result};
+
+const USAGE: &str = "usage: cassette diff <left> <right> [-o <output>]";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(args, &["--output"], &[])?;
+    let [left_path, right_path] = args.positional() else {
+        return Err(USAGE.into());
+    };
+
+    let left = recording::read_records(left_path)?;
+    let right = recording::read_records(right_path)?;
+    let differences = tracing_cassette::diff(&left, &right);
+
+    let mut output = args.output()?;
+    for difference in &differences {
+        writeln!(output, "{difference}")?;
+    }
+    output.flush()?;
+
+    // Like `diff`, finding differences is a failure, so that scripts can check for them.
+    if differences.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("the recordings differ in {} places", differences.len()).into())
+    }
+}
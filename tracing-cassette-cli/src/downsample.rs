@@ -0,0 +1,32 @@
+use tracing_cassette::Downsampler;
+
+use crate::{args::Args, recording, Result};
+
+const USAGE: &str = "usage: cassette downsample <recording> --drop <fraction> [--stratified] \
+                     [--seed <seed>] [-o <output>]";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(args, &["--drop", "--seed", "--output"], &["--stratified"])?;
+    let ([path], Some(drop_fraction)) = (args.positional(), args.option("--drop")) else {
+        return Err(USAGE.into());
+    };
+    let drop_fraction = drop_fraction
+        .parse::<f64>()
+        .ok()
+        .filter(|fraction| (0.0..=1.0).contains(fraction))
+        .ok_or_else(|| format!("invalid fraction for --drop, expected 0 to 1: {drop_fraction}"))?;
+
+    let mut downsampler =
+        Downsampler::new(drop_fraction).with_stratified(args.flag("--stratified"));
+    if let Some(seed) = args.option("--seed") {
+        let seed = seed
+            .parse::<u64>()
+            .map_err(|_| format!("invalid seed: {seed}"))?;
+        downsampler = downsampler.with_seed(seed);
+    }
+
+    let input = recording::read_input(path)?;
+    downsampler
+        .downsample_recording(input.as_slice(), args.output()?)
+        .map_err(|err| format!("failed to downsample {path}: {err}").into())
+}
@@ -0,0 +1,180 @@
+use std::{collections::HashMap, time::Duration};
+
+use tracing_cassette::{Level, Metadata, Parent, Query, SpanId, Trace, TraceRecord};
+
+use crate::{
+    args::{self, Args},
+    recording, Result,
+};
+
+const USAGE: &str = "usage: cassette filter <recording> [--query <query>] [--level <level>] \
+                     [--target <prefix>] [--since <time>] [--until <time>] [-o <output>]";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(
+        args,
+        &[
+            "--query", "--level", "--target", "--since", "--until", "--output",
+        ],
+        &[],
+    )?;
+    let [path] = args.positional() else {
+        return Err(USAGE.into());
+    };
+    let query = args.option("--query").map(Query::parse).transpose()?;
+    let filter = Filter {
+        level: args.option("--level").map(args::parse_level).transpose()?,
+        target: args.option("--target").map(str::to_owned),
+        since: args.duration("--since")?,
+        until: args.duration("--until")?,
+    };
+
+    let mut records = recording::read_records(path)?;
+    if let Some(query) = query {
+        records = query.filter(records);
+    }
+    recording::write_records(args.output()?, &filter.apply(records))
+}
+
+/// Which spans and events to keep.
+///
+/// Spans are kept if their callsite matches and they were open at any time within the time
+/// range. Events are kept if their callsite matches and they were recorded within the time range.
+/// All the records of a span which isn't kept are removed, and the spans and events within it
+/// are moved up to the closest ancestor which is kept, so the filtered recording stays
+/// consistent.
+#[derive(Debug)]
+struct Filter {
+    /// The least severe level to keep.
+    level: Option<Level>,
+    target: Option<String>,
+    /// The start of the time range, relative to the first record.
+    since: Option<Duration>,
+    /// The end of the time range, relative to the first record.
+    until: Option<Duration>,
+}
+
+impl Filter {
+    fn matches(&self, metadata: &Metadata) -> bool {
+        let too_verbose = self
+            .level
+            .as_ref()
+            .is_some_and(|level| args::severity(&metadata.level) < args::severity(level));
+        let other_target = self
+            .target
+            .as_ref()
+            .is_some_and(|target| !metadata.target.starts_with(target.as_str()));
+        !too_verbose && !other_target
+    }
+
+    fn apply(&self, records: Vec<TraceRecord>) -> Vec<TraceRecord> {
+        let Some(first) = records.first() else {
+            return records;
+        };
+        let origin = first.meta.timestamp();
+        let since = self.since.map(|since| origin + since);
+        let until = self.until.map(|until| origin + until);
+        let overlaps = |start: Duration, end: Duration| {
+            !matches!(since, Some(since) if end < since)
+                && !matches!(until, Some(until) if start > until)
+        };
+
+        // Find when each span is closed, spans which are never closed are open until the end.
+        // Spans are identified by the index of their `NewSpan` record, as span Ids are reused
+        // once spans close.
+        let mut last = origin;
+        let mut created: HashMap<SpanId, usize> = HashMap::new();
+        let mut closed: HashMap<usize, Duration> = HashMap::new();
+        for (idx, record) in records.iter().enumerate() {
+            last = last.max(record.meta.timestamp());
+            match &record.trace {
+                Trace::NewSpan(new_span) => {
+                    created.insert(new_span.id, idx);
+                }
+                Trace::Close(id) => {
+                    if let Some(instance) = created.get(id) {
+                        closed.insert(*instance, record.meta.timestamp());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Whether each span is kept, spans from before the recording started are kept.
+        let mut spans: HashMap<SpanId, bool> = HashMap::new();
+        // The parent that the spans and events within a removed span are moved to.
+        let mut removed_parents: HashMap<SpanId, Parent> = HashMap::new();
+        let mut entered: HashMap<String, Vec<SpanId>> = HashMap::new();
+        let is_kept = |spans: &HashMap<SpanId, bool>, id: &SpanId| spans.get(id) != Some(&false);
+
+        let mut filtered = Vec::new();
+        for (idx, mut record) in records.into_iter().enumerate() {
+            let ts = record.meta.timestamp();
+            let keep = match &mut record.trace {
+                Trace::RegisterCallsite(metadata) => self.matches(metadata),
+                Trace::NewSpan(new_span) => {
+                    let end = closed.get(&idx).copied().unwrap_or(last);
+                    let kept = self.matches(&new_span.metadata) && overlaps(ts, end);
+                    spans.insert(new_span.id, kept);
+                    if kept {
+                        removed_parents.remove(&new_span.id);
+                        move_up(&mut new_span.parent, &removed_parents);
+                    } else {
+                        // The contextual parent is resolved now, as the span won't be entered.
+                        let parent = match &new_span.parent {
+                            Parent::Current => entered
+                                .get(&record.meta.thread_id)
+                                .and_then(|stack| stack.iter().rev().find(|id| is_kept(&spans, id)))
+                                .map_or(Parent::Root, |id| Parent::Explicit(*id)),
+                            parent => {
+                                let mut parent = parent.clone();
+                                move_up(&mut parent, &removed_parents);
+                                parent
+                            }
+                        };
+                        removed_parents.insert(new_span.id, parent);
+                    }
+                    kept
+                }
+                Trace::Event(event) => {
+                    move_up(&mut event.parent, &removed_parents);
+                    self.matches(&event.metadata) && overlaps(ts, ts)
+                }
+                Trace::Enter(id) => {
+                    entered
+                        .entry(record.meta.thread_id.clone())
+                        .or_default()
+                        .push(*id);
+                    is_kept(&spans, id)
+                }
+                Trace::Exit(id) => {
+                    let stack = entered.entry(record.meta.thread_id.clone()).or_default();
+                    if let Some(idx) = stack.iter().rposition(|entered| entered == id) {
+                        stack.remove(idx);
+                    }
+                    is_kept(&spans, id)
+                }
+                Trace::Close(id) => is_kept(&spans, id),
+                Trace::Record(record_values) => is_kept(&spans, &record_values.id),
+                Trace::FollowsFrom(follows_from) => {
+                    is_kept(&spans, &follows_from.cause_id)
+                        && is_kept(&spans, &follows_from.effect_id)
+                }
+            };
+            if keep {
+                filtered.push(record);
+            }
+        }
+
+        filtered
+    }
+}
+
+/// Moves an explicit parent which was removed up to its closest ancestor which is kept.
+fn move_up(parent: &mut Parent, removed_parents: &HashMap<SpanId, Parent>) {
+    if let Parent::Explicit(id) = parent {
+        if let Some(removed_parent) = removed_parents.get(id) {
+            *parent = removed_parent.clone();
+        }
+    }
+}
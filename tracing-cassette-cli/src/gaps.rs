@@ -0,0 +1,91 @@
+use std::io::Write;
+
+use tracing_cassette::GapDetection;
+
+use crate::{args::Args, recording, Result};
+
+const USAGE: &str = "usage: cassette gaps <recording> \
+                     [--heartbeat <message> --heartbeat-interval <time>] \
+                     [--sequence-field <field>] [--record-sequence] \
+                     [--dropped-field <field>] [--max-silence <time>] [-o <output>]";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(
+        args,
+        &[
+            "--heartbeat",
+            "--heartbeat-interval",
+            "--sequence-field",
+            "--dropped-field",
+            "--max-silence",
+            "--output",
+        ],
+        &["--record-sequence"],
+    )?;
+    let [path] = args.positional() else {
+        return Err(USAGE.into());
+    };
+    let mut detection = GapDetection::new();
+    let heartbeat_interval = args.duration("--heartbeat-interval")?;
+    match (args.option("--heartbeat"), heartbeat_interval) {
+        (Some(message), Some(interval)) => {
+            if interval.is_zero() {
+                return Err("the time for --heartbeat-interval must be more than zero".into());
+            }
+            detection = detection.with_heartbeat(message, interval);
+        }
+        (None, None) => {}
+        _ => return Err("--heartbeat and --heartbeat-interval must be given together".into()),
+    }
+    if let Some(field) = args.option("--sequence-field") {
+        detection = detection.with_sequence_field(field);
+    }
+    if args.flag("--record-sequence") {
+        detection = detection.with_record_sequence();
+    }
+    if let Some(field) = args.option("--dropped-field") {
+        detection = detection.with_dropped_field(field);
+    }
+    if let Some(max_silence) = args.duration("--max-silence")? {
+        detection = detection.with_max_silence(max_silence);
+    }
+    let detects_anything = args.option("--heartbeat").is_some()
+        || args.flag("--record-sequence")
+        || ["--sequence-field", "--dropped-field", "--max-silence"]
+            .iter()
+            .any(|option| args.option(option).is_some());
+    if !detects_anything {
+        return Err(format!("nothing to find gaps with\n{USAGE}").into());
+    }
+
+    let input = recording::read_input(path)?;
+    let report = detection
+        .detect(input.as_slice())
+        .map_err(|err| format!("failed to read {path}: {err}"))?;
+
+    // Times are relative to the start of the recording, as they are given to other commands.
+    let mut output = args.output()?;
+    for gap in &report.gaps {
+        writeln!(
+            output,
+            "{start:?}..{end:?} ({duration:?}) {thread}: {kind}",
+            start = gap.start.saturating_sub(report.start),
+            end = gap.end.saturating_sub(report.start),
+            duration = gap.duration(),
+            thread = gap.thread_id.as_deref().unwrap_or("all threads"),
+            kind = gap.kind,
+        )?;
+    }
+    match report.watermark() {
+        Some(watermark) => writeln!(
+            output,
+            "{gaps} gaps, complete up to {watermark:?}",
+            gaps = report.gaps.len(),
+            watermark = watermark.saturating_sub(report.start),
+        )?,
+        None => writeln!(output, "no gaps")?,
+    }
+    output.flush()?;
+
+    Ok(())
+}
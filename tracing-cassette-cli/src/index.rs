@@ -0,0 +1,38 @@
+use std::{fs::File, io::BufReader, time::Duration};
+
+use tracing_cassette::RecordingIndex;
+
+use crate::{args::Args, Result};
+
+const USAGE: &str = "usage: cassette index <recording> [--interval <time>] [-o <output>]";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(args, &["--interval", "--output"], &[])?;
+    let [path] = args.positional() else {
+        return Err(USAGE.into());
+    };
+    let interval = args
+        .duration("--interval")?
+        .unwrap_or(Duration::from_secs(1));
+
+    let file = File::open(path).map_err(|err| format!("failed to read {path}: {err}"))?;
+    let index = RecordingIndex::build(BufReader::new(file), interval)
+        .map_err(|err| format!("failed to index {path}: {err}"))?;
+
+    // The index is written next to the recording by default, where replays will find it.
+    let output = args.option("--output");
+    match output {
+        Some(_) => index.write(args.output()?)?,
+        None => index.write_sidecar(path)?,
+    }
+    eprintln!(
+        "wrote index to {output}: {checkpoints} checkpoints, {threads} threads, {callsites} \
+         callsites",
+        output = output.map_or_else(|| RecordingIndex::sidecar_path(path), str::to_owned),
+        checkpoints = index.checkpoints().len(),
+        threads = index.threads().len(),
+        callsites = index.callsites().len(),
+    );
+
+    Ok(())
+}
@@ -0,0 +1,132 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+use tracing_cassette::{Kind, RecordReader, Trace, TraceRecord};
+
+use crate::{
+    args::{self, Args},
+    recording, Result,
+};
+
+const USAGE: &str = "usage: cassette inspect <recording>";
+
+/// Counts of what a recording contains.
+#[derive(Debug, Default)]
+struct Summary {
+    records: usize,
+    start: Option<Duration>,
+    end: Duration,
+    /// The name and record count of each thread, in the order they first appear.
+    threads: Vec<(String, Option<String>, usize)>,
+    thread_indices: HashMap<String, usize>,
+    records_by_kind: BTreeMap<&'static str, usize>,
+    span_callsites: usize,
+    event_callsites: usize,
+    /// The event count for each level, from trace to error.
+    events_by_level: [usize; 5],
+}
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(args, &[], &[])?;
+    let [path] = args.positional() else {
+        return Err(USAGE.into());
+    };
+
+    let input = recording::read_input(path)?;
+    let mut reader = RecordReader::new(input.as_slice());
+    let mut summary = Summary::default();
+    for record in &mut reader {
+        let record = record.map_err(|err| format!("failed to read {path}: {err}"))?;
+        summary.push(record);
+    }
+    let violations = tracing_cassette::validate_stream(input.as_slice())?;
+
+    let start = summary.start.unwrap_or_default();
+    println!("format version: {}", reader.version());
+    let metadata = &reader.header().metadata;
+    if !metadata.is_empty() {
+        println!("metadata:");
+        for (key, value) in metadata {
+            println!("  {key}: {value}");
+        }
+    }
+    println!("records:        {}", summary.records);
+    println!(
+        "start:          {}.{:06} (seconds since the UNIX epoch)",
+        start.as_secs(),
+        start.subsec_micros()
+    );
+    println!("duration:       {:?}", summary.end.saturating_sub(start));
+    println!("threads:        {}", summary.threads.len());
+    for (thread_id, thread_name, records) in &summary.threads {
+        let thread_name = thread_name.as_deref().unwrap_or("<unnamed>");
+        println!("  {thread_id} ({thread_name}): {records} records");
+    }
+    println!(
+        "callsites:      {} ({} spans, {} events)",
+        summary.span_callsites + summary.event_callsites,
+        summary.span_callsites,
+        summary.event_callsites,
+    );
+    let [trace, debug, info, warn, error] = summary.events_by_level;
+    println!(
+        "events:         {} (TRACE {trace}, DEBUG {debug}, INFO {info}, WARN {warn}, ERROR {error})",
+        summary.events_by_level.iter().sum::<usize>(),
+    );
+    println!("records by kind:");
+    for (kind, count) in &summary.records_by_kind {
+        println!("  {kind}: {count}");
+    }
+    println!("violations:     {}", violations.len());
+    for violation in violations.iter().take(10) {
+        println!("  line {}: {}", violation.line_index, violation.violation);
+    }
+    if violations.len() > 10 {
+        println!("  ... and {} more", violations.len() - 10);
+    }
+
+    Ok(())
+}
+
+impl Summary {
+    fn push(&mut self, record: TraceRecord) {
+        let ts = record.meta.timestamp();
+        self.records += 1;
+        self.start = Some(self.start.map_or(ts, |start| start.min(ts)));
+        self.end = self.end.max(ts);
+
+        let next_index = self.threads.len();
+        let index = *self
+            .thread_indices
+            .entry(record.meta.thread_id.clone())
+            .or_insert_with(|| {
+                self.threads
+                    .push((record.meta.thread_id, record.meta.thread_name, 0));
+                next_index
+            });
+        self.threads[index].2 += 1;
+
+        let kind = match &record.trace {
+            Trace::RegisterCallsite(metadata) => {
+                match metadata.kind {
+                    Kind::Span => self.span_callsites += 1,
+                    Kind::Event => self.event_callsites += 1,
+                }
+                "RegisterCallsite"
+            }
+            Trace::Event(event) => {
+                self.events_by_level[usize::from(args::severity(&event.metadata.level))] += 1;
+                "Event"
+            }
+            Trace::NewSpan(_) => "NewSpan",
+            Trace::Enter(_) => "Enter",
+            Trace::Exit(_) => "Exit",
+            Trace::Close(_) => "Close",
+            Trace::Record(_) => "Record",
+            Trace::FollowsFrom(_) => "FollowsFrom",
+        };
+        *self.records_by_kind.entry(kind).or_default() += 1;
+    }
+}
@@ -0,0 +1,17 @@
+use std::io::Write;
+
+use crate::{args::Args, Result};
+
+const USAGE: &str = "usage: cassette json-schema [-o <output>]";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(args, &["--output"], &[])?;
+    let [] = args.positional() else {
+        return Err(USAGE.into());
+    };
+
+    let mut output = args.output()?;
+    serde_json::to_writer_pretty(&mut output, &tracing_cassette::json_schema())?;
+    writeln!(output)?;
+    Ok(())
+}
@@ -0,0 +1,204 @@
+//! `cassette`, a command line tool for working with `tracing` recordings.
+//!
+//! See the usage below, or run `cassette help`, for the available commands.
+
+use std::{env, error, io, process::ExitCode};
+
+mod anonymize;
+mod args;
+mod cat;
+mod compare;
+mod concat;
+mod convert;
+mod diff;
+mod downsample;
+mod filter;
+mod gaps;
+mod index;
+mod inspect;
+mod json_schema;
+mod merge;
+mod normalize;
+mod open_spans;
+mod recording;
+mod repair;
+mod replay;
+mod schema;
+mod size;
+mod split;
+mod stats;
+mod trim;
+mod view;
+
+type Result<T = ()> = std::result::Result<T, Box<dyn error::Error>>;
+
+const USAGE: &str = "\
+usage: cassette <command> [<args>]
+
+Work with tracing recordings, reading from stdin when the recording is `-`.
+
+commands:
+  inspect <recording>
+      Summarize a recording: its records, threads, callsites, and any violations of the format.
+  cat <recording> [--pretty] [-o <output>]
+      Print the records of a recording as JSON, in the current version of the format, or with
+      --pretty, one record per line in a format for people to read.
+  convert <input> --to <format> [--from <format>] [--service-name <name>]
+          [--compression <compression>] [-o <output>]
+      Convert a recording to or from another format. Recordings may be JSON lines or containers.
+      --from: recording (default), otlp-json, otlp-traces-protobuf, otlp-logs-protobuf
+      --to: recording, container, chrome, chrome-complete, perfetto, speedscope, folded, html,
+            jaeger, otlp-json, tracing-mock
+      --compression: the compression of container segments, none (default) or deflate
+  diff <left> <right> [-o <output>]
+      Compare two recordings, ignoring timestamps and Ids, and print the callsites, spans,
+      events, and field values which differ. Fails when there are any differences.
+  normalize <recording> [-o <output>]
+      Rewrite a recording into a canonical form, with its Ids numbered in order, its timestamps
+      starting at 0, and the fields of each trace sorted by name, so that recordings of the same
+      traces can be compared as text.
+  filter <recording> [--query <query>] [--level <level>] [--target <prefix>] [--since <time>]
+         [--until <time>] [-o <output>]
+      Keep only the spans and events which match a query, such as
+      'level >= WARN && field(\"user_id\") == 42', together with their callsites and ancestor
+      spans. Then keep only those at or above a level, with a target starting with a prefix, or
+      within a time range. Times are relative to the start of the recording, such as `1.5s` or
+      `200ms`.
+  index <recording> [--interval <time>] [-o <output>]
+      Write an index of a recording, so that replays and viewers can seek to a point in time
+      without reading the whole recording. The index is written next to the recording, as
+      <recording>.idx, unless an output is given. The default interval is 1s.
+  anonymize <recording> [--field <pattern>=<action>]... [--default <action>] [--salt <salt>]
+            [--strip-paths] [--strip-targets] [-o <output>]
+      Rewrite a recording so that it can be shared, with the values of the fields hashed,
+      replaced with tokens, or removed. Actions are keep, hash, tokenize, and remove, the
+      default is hash. Patterns match field names and may contain `*`, such as `user.*`.
+  downsample <recording> --drop <fraction> [--stratified] [--seed <seed>] [-o <output>]
+      Make a recording smaller by dropping a fraction of its events, from 0 to 1, keeping the
+      callsites and all the records of the spans. Events are dropped at random, or with
+      --stratified, the same fraction of the events of each callsite is dropped, keeping at
+      least one.
+  merge <recording>... [-o <output>]
+      Merge recordings into one, in timestamp order, keeping their threads and spans apart.
+  split <recording> [--by <target|crate>] [--dir <directory>]
+      Split a recording into one recording for each target, or each crate, keeping the spans
+      that their spans and events were recorded in. Each one is written to <target>.tracing in
+      the directory, with `::` replaced by `.`.
+  compare <baseline> <candidate> [--threshold <percent>] [--significance <p-value>]
+          [--min-samples <n>] [-o <output>]
+      Compare the latency of spans, from creation to close, between two recordings, matching
+      callsites by target and name. A callsite regressed when its spans are significantly
+      slower in the candidate and its median grew by more than the threshold. Fails when any
+      callsites regressed. The defaults are 10%, 0.05, and 5 spans in each recording.
+  stats <recording> [--top <n>] [--bucket <time>] [-o <output>]
+      Report the hotspots of a recording: the callsites with the most spans and events, the
+      spans which were busy for the longest in total, and the rate of events of each target.
+      The default is the top 10, with events counted in buckets of 1s.
+  schema <recording> [--check] [-o <output>]
+      Print the fields of each callsite with the types of the values recorded for them, and how
+      many values and distinct values there were. Fields with values of more than one type are
+      flagged as inconsistent, with --check they fail the command.
+  json-schema [-o <output>]
+      Print the JSON Schema of a line of a recording, in the current version of the format, to
+      validate programs which write or read recordings in other languages.
+  size <recording> [--top <n>] [-o <output>]
+      Report where the bytes of a recording go, by kind of record and by callsite, and estimate
+      how much smaller it would be with deduplicated callsite metadata, compressed into a
+      container, or binary encoded. The default is the top 10 callsites.
+  open-spans <recording> --at <time> [-o <output>]
+      Print the spans which were open at a time on each thread, relative to the start of the
+      recording: those entered on the thread, outermost first, then those which it created that
+      weren't entered. Each span has how long it had been open and the values of its fields at
+      the time.
+  concat <recording>... [--gap <time>] [-o <output>]
+      Join recordings one after the other, shifting the timestamps of each one to start --gap
+      after the end of the one before, 0 by default. Threads keep their Ids, callsites and
+      spans are only given new Ids where they collide.
+  trim <recording> [--start <time>] [--end <time>] [-o <output>]
+      Cut a recording down to a time range, keeping the spans which are open at the start so
+      that the result can still be replayed.
+  gaps <recording> [--heartbeat <message> --heartbeat-interval <time>]
+       [--sequence-field <field>] [--record-sequence] [--dropped-field <field>]
+       [--max-silence <time>] [-o <output>]
+      Find where a recording is missing records: heartbeat events which are more than twice the
+      interval apart, skipped sequence numbers in a field or, with --record-sequence, in the
+      numbers of the records themselves, events with a field counting the dropped records, and
+      times when nothing was recorded for longer than the maximum silence.
+      Each gap is printed with its time range and the thread it affects.
+  repair <recording> [-o <output>]
+      Fix a recording which was cut short or is inconsistent, so that it can be replayed: spans
+      which are still entered or open at the end are exited and closed, and records which refer
+      to spans that aren't open are removed. What was repaired is printed to stderr.
+  replay <recording> [--speed <speed>] [--duration <duration>] [--deterministic]
+         [--thread-spans] [--span-skeleton] [--jitter <duration>] [--drop <probability>]
+         [--seed <seed>]
+      Replay a recording into a subscriber which prints the traces. --duration sets the speed
+      so that the replay takes the duration, keeping the relative spacing of the records.
+      --thread-spans wraps the records of each recorded thread in a span named after the
+      thread. --span-skeleton replays only the lifecycles of spans, without any events.
+      --jitter delays each record by a random time up to the duration and --drop drops
+      events and recorded values with the probability, to test how subscribers behave under
+      degraded telemetry.
+  view <recording> [--level <level>] [--target <prefix>]
+      Browse a recording in the terminal, as a tree of spans and events or the stream of
+      records. Spans can be folded, the records filtered by level and target, and the view
+      moved to a point in time. Press q to quit.
+  help
+      Print this message.
+";
+
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1);
+    let Some(command) = args.next() else {
+        eprint!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+    let args: Vec<String> = args.collect();
+
+    let result = match command.as_str() {
+        "inspect" => inspect::run(args),
+        "anonymize" => anonymize::run(args),
+        "cat" => cat::run(args),
+        "compare" => compare::run(args),
+        "concat" => concat::run(args),
+        "convert" => convert::run(args),
+        "diff" => diff::run(args),
+        "downsample" => downsample::run(args),
+        "filter" => filter::run(args),
+        "gaps" => gaps::run(args),
+        "index" => index::run(args),
+        "json-schema" => json_schema::run(args),
+        "merge" => merge::run(args),
+        "normalize" => normalize::run(args),
+        "open-spans" => open_spans::run(args),
+        "split" => split::run(args),
+        "schema" => schema::run(args),
+        "size" => size::run(args),
+        "stats" => stats::run(args),
+        "trim" => trim::run(args),
+        "repair" => repair::run(args),
+        "replay" => replay::run(args),
+        "view" => view::run(args),
+        "help" | "--help" | "-h" => {
+            print!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        _ => Err(format!("unknown command: {command}, run `cassette help` for usage").into()),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        // The output was closed early, such as by piping it into `head`.
+        Err(err)
+            if err
+                .downcast_ref::<io::Error>()
+                .is_some_and(|err| err.kind() == io::ErrorKind::BrokenPipe) =>
+        {
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+use tracing_cassette::{Metadata, Parent, SpanId, Trace, TraceRecord};
+
+use crate::{args::Args, recording, Result};
+
+const USAGE: &str = "usage: cassette merge <recording>... [-o <output>]";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(args, &["--output"], &[])?;
+    let paths = args.positional();
+    if paths.is_empty() {
+        return Err(USAGE.into());
+    }
+
+    let recordings = paths
+        .iter()
+        .map(|path| recording::read_records(path))
+        .collect::<Result<Vec<_>>>()?;
+    recording::write_records(args.output()?, &merge(recordings))
+}
+
+/// Merges recordings into one, in timestamp order.
+///
+/// The records of each recording stay in the same order. The threads, callsites, and spans of
+/// each recording are given new Ids, so that those from different recordings are kept apart even
+/// when they had the same Ids.
+fn merge(recordings: Vec<Vec<TraceRecord>>) -> Vec<TraceRecord> {
+    let mut ids = Ids::default();
+    let mut recordings: Vec<_> = recordings
+        .into_iter()
+        .map(|records| records.into_iter().peekable())
+        .collect();
+
+    let mut merged = Vec::new();
+    loop {
+        // The recording with the earliest next record, the earlier recording on a tie.
+        let next = recordings
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(idx, records)| Some((idx, records.peek()?.meta.timestamp())))
+            .min_by_key(|(_, ts)| *ts);
+        let Some((idx, _)) = next else {
+            break;
+        };
+        let mut record = recordings[idx].next().expect("the record was peeked");
+        ids.remap(idx, &mut record);
+        merged.push(record);
+    }
+
+    merged
+}
+
+/// The new Ids of the threads, callsites, and spans of each recording, keyed by the index of the
+/// recording and the Id in it.
+#[derive(Debug, Default)]
+struct Ids {
+    threads: HashMap<(usize, String), String>,
+    callsites: HashMap<(usize, u64), u64>,
+    spans: HashMap<(usize, SpanId), SpanId>,
+    next_span: u64,
+}
+
+impl Ids {
+    fn remap(&mut self, recording: usize, record: &mut TraceRecord) {
+        let next_thread = self.threads.len() + 1;
+        record.meta.thread_id = self
+            .threads
+            .entry((recording, record.meta.thread_id.clone()))
+            .or_insert_with(|| format!("ThreadId({next_thread})"))
+            .clone();
+
+        match &mut record.trace {
+            Trace::RegisterCallsite(metadata) => self.remap_callsite(recording, metadata),
+            Trace::NewSpan(new_span) => {
+                self.remap_callsite(recording, &mut new_span.metadata);
+                self.remap_parent(recording, &mut new_span.parent);
+                // Span Ids are reused once a span is closed, so each new span gets a new Id.
+                self.next_span += 1;
+                let id = SpanId::from(self.next_span);
+                self.spans.insert((recording, new_span.id), id);
+                new_span.id = id;
+            }
+            Trace::Event(event) => {
+                self.remap_callsite(recording, &mut event.metadata);
+                self.remap_parent(recording, &mut event.parent);
+            }
+            Trace::Record(record_values) => self.remap_span(recording, &mut record_values.id),
+            Trace::FollowsFrom(follows_from) => {
+                self.remap_span(recording, &mut follows_from.cause_id);
+                self.remap_span(recording, &mut follows_from.effect_id);
+            }
+            Trace::Enter(id) | Trace::Exit(id) | Trace::Close(id) => self.remap_span(recording, id),
+        }
+    }
+
+    fn remap_callsite(&mut self, recording: usize, metadata: &mut Metadata) {
+        let next_callsite = self.callsites.len() as u64 + 1;
+        metadata.id = *self
+            .callsites
+            .entry((recording, metadata.id))
+            .or_insert(next_callsite);
+    }
+
+    fn remap_parent(&mut self, recording: usize, parent: &mut Parent) {
+        if let Parent::Explicit(id) = parent {
+            self.remap_span(recording, id);
+        }
+    }
+
+    /// Remaps a span Id, Ids of spans from before the recording started are given a new Id too.
+    fn remap_span(&mut self, recording: usize, id: &mut SpanId) {
+        let next_span = &mut self.next_span;
+        *id = *self.spans.entry((recording, *id)).or_insert_with(|| {
+            *next_span += 1;
+            SpanId::from(*next_span)
+        });
+    }
+}
@@ -0,0 +1,13 @@
+use crate::{args::Args, recording, Result};
+
+const USAGE: &str = "usage: cassette normalize <recording> [-o <output>]";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(args, &["--output"], &[])?;
+    let [path] = args.positional() else {
+        return Err(USAGE.into());
+    };
+
+    let records = recording::read_records(path)?;
+    recording::write_records(args.output()?, &tracing_cassette::normalize(&records))
+}
@@ -0,0 +1,35 @@
+use std::io::Write;
+
+use tracing_cassette::SpanSnapshot;
+
+use crate::{args::Args, recording, Result};
+
+const USAGE: &str = "usage: cassette open-spans <recording> --at <time> [-o <output>]";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(args, &["--at", "--output"], &[])?;
+    let [path] = args.positional() else {
+        return Err(USAGE.into());
+    };
+    let Some(at) = args.duration("--at")? else {
+        return Err(USAGE.into());
+    };
+
+    let records = recording::read_records(path)?;
+    // The time is relative to the first record, as it is for `trim`.
+    let origin = records
+        .first()
+        .map(|record| record.meta.timestamp())
+        .unwrap_or_default();
+    let snapshot = SpanSnapshot::new(&records, origin + at);
+
+    let mut output = args.output()?;
+    if snapshot.threads.is_empty() {
+        writeln!(output, "no spans were open at {at:?}")?;
+    } else {
+        writeln!(output, "{snapshot}")?;
+    }
+    output.flush()?;
+
+    Ok(())
+}
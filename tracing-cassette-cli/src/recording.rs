@@ -0,0 +1,39 @@
+use std::{
+    fs,
+    io::{self, Read, Write},
+};
+
+use tracing_cassette::{ExportError, RecordReader, TraceRecord};
+
+use crate::Result;
+
+/// Reads the whole of the input at `path`, or stdin if `path` is `-`.
+pub(crate) fn read_input(path: &str) -> Result<Vec<u8>> {
+    if path == "-" {
+        let mut input = Vec::new();
+        io::stdin().lock().read_to_end(&mut input)?;
+        Ok(input)
+    } else {
+        fs::read(path).map_err(|err| format!("failed to read {path}: {err}").into())
+    }
+}
+
+/// Reads all the records of the recording at `path`, or stdin if `path` is `-`.
+pub(crate) fn read_records(path: &str) -> Result<Vec<TraceRecord>> {
+    let input = read_input(path)?;
+    read_records_from(input.as_slice())
+        .map_err(|err| format!("failed to read {path}: {err}").into())
+}
+
+/// Reads all the records of a recording.
+pub(crate) fn read_records_from(
+    recording: &[u8],
+) -> std::result::Result<Vec<TraceRecord>, ExportError> {
+    RecordReader::new(recording).collect()
+}
+
+/// Writes `records` as a recording in the current version of the format.
+pub(crate) fn write_records(mut writer: Box<dyn Write>, records: &[TraceRecord]) -> Result {
+    tracing_cassette::write_recording(&mut writer, records)?;
+    Ok(())
+}
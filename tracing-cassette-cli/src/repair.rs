@@ -0,0 +1,20 @@
+use crate::{args::Args, recording, Result};
+
+const USAGE: &str = "usage: cassette repair <recording> [-o <output>]";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(args, &["--output"], &[])?;
+    let [path] = args.positional() else {
+        return Err(USAGE.into());
+    };
+
+    let records = recording::read_records(path)?;
+    let (records, repairs) = tracing_cassette::repair(records);
+    // The repaired recording may be written to stdout, so the repairs are reported on stderr.
+    for repair in &repairs {
+        eprintln!("{repair}");
+    }
+    eprintln!("made {} repairs", repairs.len());
+
+    recording::write_records(args.output()?, &records)
+}
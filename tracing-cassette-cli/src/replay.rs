@@ -0,0 +1,128 @@
+use tracing_replay::{Chaos, Replay, ReplayMode};
+use tracing_subscriber::{fmt::format::FmtSpan, prelude::*};
+
+use crate::{args::Args, recording, Result};
+
+const USAGE: &str = "usage: cassette replay <recording> [--speed <speed>] \
+                     [--duration <duration>] [--deterministic] [--thread-spans] \
+                     [--span-skeleton] [--jitter <duration>] [--drop <probability>] [--seed <seed>]";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(
+        args,
+        &["--speed", "--duration", "--jitter", "--drop", "--seed"],
+        &["--deterministic", "--thread-spans", "--span-skeleton"],
+    )?;
+    let [path] = args.positional() else {
+        return Err(USAGE.into());
+    };
+    let speed = args
+        .option("--speed")
+        .map(|speed| {
+            speed
+                .parse::<f64>()
+                .map_err(|_| format!("invalid speed: {speed}"))
+        })
+        .transpose()?;
+    let target_duration = args.duration("--duration")?;
+    if target_duration.is_some_and(|target_duration| target_duration.is_zero()) {
+        return Err("--duration must be greater than zero".into());
+    }
+    let chaos = chaos(&args)?;
+
+    let layer = tracing_subscriber::fmt::Layer::default()
+        .with_file(true)
+        .with_line_number(true)
+        .with_thread_ids(true)
+        .with_thread_names(true)
+        .with_span_events(FmtSpan::FULL);
+    tracing_subscriber::registry().with(layer).init();
+
+    let mut replay = Replay::new();
+    if let Some(speed) = speed {
+        replay = replay.with_speed(speed);
+    }
+    if let Some(target_duration) = target_duration {
+        replay = replay.with_target_duration(target_duration);
+    }
+    if args.flag("--deterministic") {
+        replay = replay.with_mode(ReplayMode::Deterministic);
+    }
+    if args.flag("--thread-spans") {
+        replay = replay.with_thread_spans();
+    }
+    if args.flag("--span-skeleton") {
+        replay = replay.with_span_skeleton();
+    }
+    if let Some(chaos) = chaos {
+        replay = replay.with_chaos(chaos);
+    }
+    let summary_result = if path == "-" {
+        let recording = recording::read_input(path)?;
+        replay.replay_bytes(&recording)
+    } else {
+        replay.replay_file(path)
+    };
+    let summary_result = summary_result.map_err(|err| format!("failed to replay {path}: {err}"));
+    replay.close()?;
+
+    // The summary goes to stderr, so that it isn't mixed up with the replayed traces.
+    let summary = summary_result?;
+    eprintln!("replayed {} records", summary.record_count);
+    if summary.dropped_records > 0 {
+        eprintln!("dropped {} records", summary.dropped_records);
+    }
+    for restart in &summary.dispatcher_restarts {
+        eprintln!(
+            "restarted the dispatcher for {thread_id}, which panicked: {message}",
+            thread_id = restart.thread_id,
+            message = restart.message,
+        );
+    }
+    if let Some(line_index) = summary.truncated_final_record {
+        eprintln!("skipped truncated final record at line index {line_index}");
+    }
+    for (thread_id, thread_summary) in &summary.threads {
+        eprintln!(
+            "  {thread_id} ({thread_name}): {record_count} records, max dispatch lag: {lag:?}",
+            thread_name = thread_summary.thread_name.as_deref().unwrap_or("<unnamed>"),
+            record_count = thread_summary.record_count,
+            lag = thread_summary.max_dispatch_lag(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns how the replay is degraded, if any of the chaos options were given.
+fn chaos(args: &Args) -> Result<Option<Chaos>> {
+    let jitter = args.duration("--jitter")?;
+    let drop = args
+        .option("--drop")
+        .map(|drop| {
+            drop.parse::<f64>()
+                .ok()
+                .filter(|drop| (0.0..=1.0).contains(drop))
+                .ok_or_else(|| format!("invalid probability for --drop: {drop}"))
+        })
+        .transpose()?;
+    let seed = args
+        .option("--seed")
+        .map(|seed| {
+            seed.parse::<u64>()
+                .map_err(|_| format!("invalid number for --seed: {seed}"))
+        })
+        .transpose()?;
+    if jitter.is_none() && drop.is_none() {
+        return Ok(None);
+    }
+
+    let mut chaos = Chaos::new().with_seed(seed.unwrap_or_default());
+    if let Some(jitter) = jitter {
+        chaos = chaos.with_jitter(jitter);
+    }
+    if let Some(drop) = drop {
+        chaos = chaos.with_drop_probability(drop);
+    }
+    Ok(Some(chaos))
+}
@@ -0,0 +1,75 @@
+use std::io::Write;
+
+use tracing_cassette::{Kind, Schema};
+
+use crate::{args::Args, recording, stats::describe, Result};
+
+const USAGE: &str = "usage: cassette schema <recording> [--check] [-o <output>]";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(args, &["--output"], &["--check"])?;
+    let [path] = args.positional() else {
+        return Err(USAGE.into());
+    };
+
+    let input = recording::read_input(path)?;
+    let schema =
+        Schema::read(input.as_slice()).map_err(|err| format!("failed to read {path}: {err}"))?;
+
+    let mut output = args.output()?;
+    for callsite in &schema.callsites {
+        let kind = match callsite.metadata.kind {
+            Kind::Span => "spans",
+            Kind::Event => "events",
+        };
+        writeln!(
+            output,
+            "{callsite} ({count} {kind})",
+            callsite = describe(&callsite.metadata),
+            count = callsite.count,
+        )?;
+        let width = callsite
+            .fields
+            .iter()
+            .map(|field| field.name.len())
+            .max()
+            .unwrap_or_default();
+        for field in &callsite.fields {
+            let types = field
+                .types
+                .iter()
+                .map(|(field_type, count)| {
+                    if field.is_consistent() {
+                        field_type.to_string()
+                    } else {
+                        format!("{field_type} ({count})")
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            let more = if field.more_distinct_values { "+" } else { "" };
+            write!(
+                output,
+                "  {name:<width$}  {types}, {values} values, {distinct}{more} distinct",
+                name = field.name,
+                values = field.values,
+                distinct = field.distinct_values,
+            )?;
+            if !field.declared {
+                write!(output, ", undeclared")?;
+            }
+            if !field.is_consistent() {
+                write!(output, ", inconsistent types")?;
+            }
+            writeln!(output)?;
+        }
+    }
+    output.flush()?;
+
+    let inconsistencies = schema.inconsistencies().count();
+    if args.flag("--check") && inconsistencies > 0 {
+        return Err(format!("{inconsistencies} fields have values of more than one type").into());
+    }
+
+    Ok(())
+}
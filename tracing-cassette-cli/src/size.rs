@@ -0,0 +1,85 @@
+use std::io::Write;
+
+use tracing_cassette::{Kind, Sizes};
+
+use crate::{args::Args, recording, stats::describe, Result};
+
+const USAGE: &str = "usage: cassette size <recording> [--top <n>] [-o <output>]";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(args, &["--top", "--output"], &[])?;
+    let [path] = args.positional() else {
+        return Err(USAGE.into());
+    };
+    let mut sizes = Sizes::new();
+    if let Some(top) = args.option("--top") {
+        let top = top
+            .parse::<usize>()
+            .map_err(|_| format!("invalid number for --top: {top}"))?;
+        sizes = sizes.with_top(top);
+    }
+
+    let input = recording::read_input(path)?;
+    let report = sizes
+        .report(input.as_slice())
+        .map_err(|err| format!("failed to read {path}: {err}"))?;
+
+    let mut output = args.output()?;
+    writeln!(
+        output,
+        "{bytes} bytes in {records} records",
+        bytes = report.bytes,
+        records = report.records,
+    )?;
+    writeln!(output, "records by kind:")?;
+    for kind in &report.kinds {
+        writeln!(
+            output,
+            "  {bytes:>12}  {share:>5.1}%  {kind} ({records} records)",
+            bytes = kind.bytes,
+            share = share(kind.bytes, report.bytes),
+            kind = kind.kind,
+            records = kind.records,
+        )?;
+    }
+    writeln!(output, "callsites by size:")?;
+    for callsite in &report.callsites {
+        let kind = match callsite.metadata.kind {
+            Kind::Span => "span",
+            Kind::Event => "event",
+        };
+        writeln!(
+            output,
+            "  {bytes:>12}  {share:>5.1}%  {kind:<5}  {callsite} ({records} records)",
+            bytes = callsite.bytes,
+            share = share(callsite.bytes, report.bytes),
+            callsite = describe(&callsite.metadata),
+            records = callsite.records,
+        )?;
+    }
+    writeln!(output, "estimated size when stored:")?;
+    for (how, bytes) in [
+        ("deduplicated", report.deduplicated_bytes),
+        ("compressed container", report.compressed_bytes),
+        ("binary encoded", report.binary_bytes),
+    ] {
+        writeln!(
+            output,
+            "  {bytes:>12}  {how}, saving {savings:.1}%",
+            savings = report.savings(bytes) * 100.0,
+        )?;
+    }
+    output.flush()?;
+
+    Ok(())
+}
+
+/// Returns `bytes` as a percentage of `total`.
+#[allow(clippy::cast_precision_loss)]
+fn share(bytes: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        bytes as f64 / total as f64 * 100.0
+    }
+}
@@ -0,0 +1,48 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use tracing_cassette::SplitBy;
+
+use crate::{args::Args, recording, Result};
+
+const USAGE: &str = "usage: cassette split <recording> [--by <target|crate>] [--dir <directory>]";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(args, &["--by", "--dir"], &[])?;
+    let [path] = args.positional() else {
+        return Err(USAGE.into());
+    };
+    let by = match args.option("--by").unwrap_or("target") {
+        "target" => SplitBy::Target,
+        "crate" => SplitBy::Crate,
+        by => return Err(format!("unknown split: {by}").into()),
+    };
+    let dir = Path::new(args.option("--dir").unwrap_or("."));
+
+    let records = recording::read_records(path)?;
+    for (key, records) in tracing_cassette::split(records, by) {
+        let output = dir.join(format!("{}.tracing", file_stem(&key)));
+        let file = File::create(&output)
+            .map_err(|err| format!("failed to create {}: {err}", output.display()))?;
+        recording::write_records(Box::new(BufWriter::new(file)), &records)?;
+        eprintln!(
+            "wrote {} records of {key} to {}",
+            records.len(),
+            output.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns the name of the file for a target, with the path separators of the target replaced
+/// by dots, such as `my_app.db` for `my_app::db`.
+fn file_stem(target: &str) -> String {
+    target
+        .replace("::", ".")
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' | '.' => c,
+            _ => '_',
+        })
+        .collect()
+}
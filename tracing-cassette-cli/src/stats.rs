@@ -0,0 +1,103 @@
+use std::{io::Write, time::Duration};
+
+use tracing_cassette::{Kind, Metadata, Stats};
+
+use crate::{args::Args, recording, Result};
+
+const USAGE: &str = "usage: cassette stats <recording> [--top <n>] [--bucket <time>] [-o <output>]";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(args, &["--top", "--bucket", "--output"], &[])?;
+    let [path] = args.positional() else {
+        return Err(USAGE.into());
+    };
+    let mut stats = Stats::new();
+    if let Some(top) = args.option("--top") {
+        let top = top
+            .parse::<usize>()
+            .map_err(|_| format!("invalid number for --top: {top}"))?;
+        stats = stats.with_top(top);
+    }
+    if let Some(bucket) = args.duration("--bucket")? {
+        if bucket.is_zero() {
+            return Err("the time for --bucket must be more than zero".into());
+        }
+        stats = stats.with_bucket(bucket);
+    }
+
+    let input = recording::read_input(path)?;
+    let report = stats
+        .report(input.as_slice())
+        .map_err(|err| format!("failed to read {path}: {err}"))?;
+
+    let mut output = args.output()?;
+    writeln!(output, "callsites by count:")?;
+    for callsite in &report.callsites {
+        let kind = match callsite.metadata.kind {
+            Kind::Span => "span",
+            Kind::Event => "event",
+        };
+        writeln!(
+            output,
+            "  {count:>10}  {kind:<5}  {callsite}",
+            count = callsite.count,
+            callsite = describe(&callsite.metadata),
+        )?;
+    }
+    writeln!(output, "spans by busy time:")?;
+    for span in &report.spans {
+        writeln!(
+            output,
+            "  {busy:>10}  {callsite} ({spans} spans)",
+            busy = format!("{:?}", span.busy),
+            callsite = describe(&span.metadata),
+            spans = span.spans,
+        )?;
+    }
+    writeln!(output, "events per {:?} by target:", report.bucket)?;
+    for events in &report.events {
+        // The peak is the first of the busiest buckets, as a time since the start.
+        let (peak_idx, peak) =
+            events
+                .counts
+                .iter()
+                .enumerate()
+                .fold((0, 0), |(peak_idx, peak), (idx, count)| {
+                    if *count > peak {
+                        (idx, *count)
+                    } else {
+                        (peak_idx, peak)
+                    }
+                });
+        let peak_at = report
+            .bucket
+            .saturating_mul(u32::try_from(peak_idx).unwrap_or(u32::MAX));
+        let duration = report
+            .bucket
+            .saturating_mul(u32::try_from(events.counts.len()).unwrap_or(u32::MAX));
+        #[allow(clippy::cast_precision_loss)]
+        let mean = events.total() as f64 / duration.max(Duration::from_micros(1)).as_secs_f64();
+        writeln!(
+            output,
+            "  {target}: {total} events, {mean:.2}/s on average, peak of {peak} at {peak_at:?}",
+            target = events.target,
+            total = events.total(),
+        )?;
+    }
+    output.flush()?;
+
+    Ok(())
+}
+
+/// Returns the target and name of a callsite, with its location if it has one.
+pub(crate) fn describe(metadata: &Metadata) -> String {
+    let mut description = format!("{}::{}", metadata.target, metadata.name);
+    if let Some(file) = &metadata.file {
+        description.push_str(&format!(" ({file}"));
+        if let Some(line) = metadata.line {
+            description.push_str(&format!(":{line}"));
+        }
+        description.push(')');
+    }
+    description
+}
@@ -0,0 +1,16 @@
+use crate::{args::Args, recording, Result};
+
+const USAGE: &str =
+    "usage: cassette trim <recording> [--start <time>] [--end <time>] [-o <output>]";
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(args, &["--start", "--end", "--output"], &[])?;
+    let [path] = args.positional() else {
+        return Err(USAGE.into());
+    };
+    let start = args.duration("--start")?.unwrap_or_default();
+    let end = args.duration("--end")?;
+
+    let records = recording::read_records(path)?;
+    recording::write_records(args.output()?, &tracing_cassette::trim(records, start, end))
+}
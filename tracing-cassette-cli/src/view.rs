@@ -0,0 +1,554 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    time::Duration,
+};
+
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Layout},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, Borders, Paragraph, Wrap},
+    Frame, Terminal,
+};
+use tracing_cassette::{Level, Metadata, Parent, PrettyRecord, SpanId, Trace, TraceRecord};
+
+use crate::{
+    args::{self, Args},
+    recording, Result,
+};
+
+const USAGE: &str = "usage: cassette view <recording> [--level <level>] [--target <prefix>]";
+
+const HELP: &str = "q quit  ↑↓ pgup pgdn home end move  ←→ enter fold  t tree/stream  \
+                    l level  / target  : jump to time";
+
+/// The levels which `l` cycles through, `None` shows every level.
+const LEVELS: [Option<Level>; 5] = [
+    None,
+    Some(Level::Debug),
+    Some(Level::Info),
+    Some(Level::Warn),
+    Some(Level::Error),
+];
+
+pub(crate) fn run(args: Vec<String>) -> Result {
+    let args = Args::parse(args, &["--level", "--target"], &[])?;
+    let [path] = args.positional() else {
+        return Err(USAGE.into());
+    };
+    let level = args.option("--level").map(args::parse_level).transpose()?;
+    let target = args.option("--target").unwrap_or_default().to_owned();
+
+    let records = recording::read_records(path)?;
+    let mut viewer = Viewer::new(path.clone(), records);
+    viewer.level = level;
+    viewer.target = target;
+    viewer.rebuild();
+
+    // The terminal is restored when the guard is dropped, even if drawing fails or panics.
+    let _guard = TerminalGuard::enter()?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    loop {
+        terminal.draw(|frame| viewer.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press && !viewer.handle(key) {
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Puts the terminal into raw mode on the alternate screen, until it is dropped.
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        if let Err(err) = execute!(io::stdout(), EnterAlternateScreen) {
+            let _ = terminal::disable_raw_mode();
+            return Err(err);
+        }
+        Ok(Self)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// A span or event in the span tree of the recording.
+#[derive(Debug)]
+struct Node {
+    /// The record which created the span, or the event.
+    record: usize,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+/// A row of the list of records.
+#[derive(Clone, Copy, Debug)]
+struct Row {
+    record: usize,
+    /// The node of the row in the span tree, only set when viewing the tree.
+    node: Option<usize>,
+    depth: usize,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Prompt {
+    Target,
+    Time,
+}
+
+/// The state of the viewer.
+#[derive(Debug)]
+struct Viewer {
+    path: String,
+    records: Vec<TraceRecord>,
+    /// The timestamp of the first record.
+    start: Duration,
+    nodes: Vec<Node>,
+    /// The node that each record creates or is about, by record index.
+    record_nodes: Vec<Option<usize>>,
+    /// Whether the span tree is shown, or the stream of records.
+    tree: bool,
+    collapsed: HashSet<usize>,
+    level: Option<Level>,
+    target: String,
+    rows: Vec<Row>,
+    selected: usize,
+    /// The first row shown.
+    offset: usize,
+    /// The number of rows shown at once, as of the last time the list was drawn.
+    page: usize,
+    /// The prompt being answered, and the answer so far.
+    input: Option<(Prompt, String)>,
+    message: Option<String>,
+}
+
+impl Viewer {
+    fn new(path: String, records: Vec<TraceRecord>) -> Self {
+        let start = records
+            .iter()
+            .map(|record| record.meta.timestamp())
+            .min()
+            .unwrap_or_default();
+
+        let mut nodes: Vec<Node> = Vec::new();
+        let mut record_nodes = Vec::with_capacity(records.len());
+        let mut open: HashMap<SpanId, usize> = HashMap::new();
+        let mut entered: HashMap<&str, Vec<SpanId>> = HashMap::new();
+        for (idx, record) in records.iter().enumerate() {
+            let thread = record.meta.thread_id.as_str();
+            let parent_node = |parent: &Parent| match parent {
+                Parent::Current => entered
+                    .get(thread)
+                    .and_then(|stack| stack.last())
+                    .and_then(|id| open.get(id))
+                    .copied(),
+                Parent::Explicit(id) => open.get(id).copied(),
+                Parent::Root => None,
+            };
+            let node = match &record.trace {
+                Trace::RegisterCallsite(_) => None,
+                Trace::NewSpan(new_span) => {
+                    let node = nodes.len();
+                    let parent = parent_node(&new_span.parent);
+                    open.insert(new_span.id, node);
+                    Some((node, parent))
+                }
+                Trace::Event(event) => Some((nodes.len(), parent_node(&event.parent))),
+                Trace::Enter(id) => {
+                    entered.entry(thread).or_default().push(*id);
+                    record_nodes.push(open.get(id).copied());
+                    continue;
+                }
+                Trace::Exit(id) => {
+                    let stack = entered.entry(thread).or_default();
+                    if let Some(pos) = stack.iter().rposition(|entered| entered == id) {
+                        stack.remove(pos);
+                    }
+                    record_nodes.push(open.get(id).copied());
+                    continue;
+                }
+                Trace::Close(id) => {
+                    record_nodes.push(open.remove(id));
+                    continue;
+                }
+                Trace::Record(record_values) => {
+                    record_nodes.push(open.get(&record_values.id).copied());
+                    continue;
+                }
+                Trace::FollowsFrom(follows_from) => {
+                    record_nodes.push(open.get(&follows_from.effect_id).copied());
+                    continue;
+                }
+            };
+            record_nodes.push(node.map(|(node, _)| node));
+            if let Some((node, parent)) = node {
+                if let Some(parent) = parent {
+                    nodes[parent].children.push(node);
+                }
+                nodes.push(Node {
+                    record: idx,
+                    parent,
+                    children: Vec::new(),
+                });
+            }
+        }
+
+        Self {
+            path,
+            records,
+            start,
+            nodes,
+            record_nodes,
+            tree: true,
+            collapsed: HashSet::new(),
+            level: None,
+            target: String::new(),
+            rows: Vec::new(),
+            selected: 0,
+            offset: 0,
+            page: 1,
+            input: None,
+            message: None,
+        }
+    }
+
+    /// Returns the callsite of a record, or of the span it is about.
+    fn metadata(&self, record: usize) -> Option<&Metadata> {
+        let trace = match &self.records[record].trace {
+            Trace::RegisterCallsite(metadata) => return Some(metadata),
+            Trace::NewSpan(_) | Trace::Event(_) => &self.records[record].trace,
+            _ => &self.records[self.nodes[self.record_nodes[record]?].record].trace,
+        };
+        match trace {
+            Trace::NewSpan(new_span) => Some(&new_span.metadata),
+            Trace::Event(event) => Some(&event.metadata),
+            _ => None,
+        }
+    }
+
+    /// Returns whether a record passes the level and target filters. Records about spans which
+    /// were created before the recording started only pass when there aren't any filters.
+    fn matches(&self, record: usize) -> bool {
+        let Some(metadata) = self.metadata(record) else {
+            return self.level.is_none() && self.target.is_empty();
+        };
+        let below_level = self
+            .level
+            .as_ref()
+            .is_some_and(|level| args::severity(&metadata.level) < args::severity(level));
+        !below_level && metadata.target.starts_with(&self.target)
+    }
+
+    /// Rebuilds the rows after the view or filters change, keeping the selected record.
+    fn rebuild(&mut self) {
+        let selected = self.rows.get(self.selected).map(|row| row.record);
+
+        self.rows.clear();
+        if self.tree {
+            // A node is shown if it matches, or any of its descendants do, so that everything
+            // that matches keeps its place in the tree. Children always come after their parents.
+            let mut shown: Vec<bool> = self
+                .nodes
+                .iter()
+                .map(|node| self.matches(node.record))
+                .collect();
+            for idx in (0..self.nodes.len()).rev() {
+                if let Some(parent) = self.nodes[idx].parent {
+                    shown[parent] |= shown[idx];
+                }
+            }
+
+            let mut stack: Vec<(usize, usize)> = self
+                .nodes
+                .iter()
+                .enumerate()
+                .filter(|(idx, node)| node.parent.is_none() && shown[*idx])
+                .map(|(idx, _)| (idx, 0))
+                .rev()
+                .collect();
+            while let Some((idx, depth)) = stack.pop() {
+                let node = &self.nodes[idx];
+                self.rows.push(Row {
+                    record: node.record,
+                    node: Some(idx),
+                    depth,
+                });
+                if !self.collapsed.contains(&idx) {
+                    let children = node.children.iter().rev().filter(|child| shown[**child]);
+                    stack.extend(children.map(|child| (*child, depth + 1)));
+                }
+            }
+        } else {
+            self.rows = (0..self.records.len())
+                .filter(|record| self.matches(*record))
+                .map(|record| Row {
+                    record,
+                    node: None,
+                    depth: 0,
+                })
+                .collect();
+        }
+
+        self.selected = selected
+            .and_then(|selected| {
+                self.rows
+                    .iter()
+                    .position(|row| row.record == selected)
+                    .or_else(|| self.rows.iter().position(|row| row.record > selected))
+            })
+            .unwrap_or(0);
+    }
+
+    /// Handles a key press, returns `false` when the viewer should quit.
+    fn handle(&mut self, key: KeyEvent) -> bool {
+        if let Some((prompt, mut text)) = self.input.take() {
+            match key.code {
+                KeyCode::Enter => self.answer(prompt, text),
+                KeyCode::Esc => {}
+                KeyCode::Backspace => {
+                    text.pop();
+                    self.input = Some((prompt, text));
+                }
+                KeyCode::Char(c) => {
+                    text.push(c);
+                    self.input = Some((prompt, text));
+                }
+                _ => self.input = Some((prompt, text)),
+            }
+            return true;
+        }
+
+        self.message = None;
+        let last = self.rows.len().saturating_sub(1);
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => self.selected = (self.selected + 1).min(last),
+            KeyCode::PageUp => self.selected = self.selected.saturating_sub(self.page),
+            KeyCode::PageDown => self.selected = (self.selected + self.page).min(last),
+            KeyCode::Home => self.selected = 0,
+            KeyCode::End => self.selected = last,
+            KeyCode::Enter | KeyCode::Char(' ') => {
+                if let Some(node) = self.selected_node() {
+                    if !self.collapsed.remove(&node) {
+                        self.collapsed.insert(node);
+                    }
+                    self.rebuild();
+                }
+            }
+            KeyCode::Left => {
+                if let Some(node) = self.selected_node() {
+                    if self.nodes[node].children.is_empty() || self.collapsed.contains(&node) {
+                        // Move up to the parent instead.
+                        if let Some(parent) = self.nodes[node].parent {
+                            if let Some(row) =
+                                self.rows.iter().position(|row| row.node == Some(parent))
+                            {
+                                self.selected = row;
+                            }
+                        }
+                    } else {
+                        self.collapsed.insert(node);
+                        self.rebuild();
+                    }
+                }
+            }
+            KeyCode::Right => {
+                if let Some(node) = self.selected_node() {
+                    if self.collapsed.remove(&node) {
+                        self.rebuild();
+                    }
+                }
+            }
+            KeyCode::Char('t') => {
+                self.tree = !self.tree;
+                self.rebuild();
+            }
+            KeyCode::Char('l') => {
+                let current = LEVELS.iter().position(|level| *level == self.level);
+                self.level =
+                    LEVELS[current.map_or(0, |current| (current + 1) % LEVELS.len())].clone();
+                self.rebuild();
+            }
+            KeyCode::Char('/') => self.input = Some((Prompt::Target, self.target.clone())),
+            KeyCode::Char(':') => self.input = Some((Prompt::Time, String::new())),
+            _ => {}
+        }
+
+        true
+    }
+
+    fn answer(&mut self, prompt: Prompt, text: String) {
+        match prompt {
+            Prompt::Target => {
+                self.target = text;
+                self.rebuild();
+            }
+            Prompt::Time => {
+                let Some(time) = args::parse_duration(&text) else {
+                    self.message = Some(format!("invalid time: {text}"));
+                    return;
+                };
+                // The rows in the tree aren't in time order, so the earliest row at or after the
+                // time is selected.
+                let ts = self.start + time;
+                let row = self
+                    .rows
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, row)| self.records[row.record].meta.timestamp() >= ts)
+                    .min_by_key(|(_, row)| self.records[row.record].meta.timestamp())
+                    .map(|(idx, _)| idx);
+                match row {
+                    Some(row) => self.selected = row,
+                    None => self.message = Some(format!("nothing at or after {time:?}")),
+                }
+            }
+        }
+    }
+
+    fn selected_node(&self) -> Option<usize> {
+        self.rows.get(self.selected)?.node
+    }
+
+    fn draw(&mut self, frame: &mut Frame<'_>) {
+        let [list_area, detail_area, status_area] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(5),
+            Constraint::Length(1),
+        ])
+        .areas(frame.size());
+
+        self.page = usize::from(list_area.height.saturating_sub(2)).max(1);
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.selected >= self.offset + self.page {
+            self.offset = self.selected + 1 - self.page;
+        }
+
+        let lines: Vec<Line<'_>> = self
+            .rows
+            .iter()
+            .enumerate()
+            .skip(self.offset)
+            .take(self.page)
+            .map(|(idx, row)| {
+                let line = Line::raw(self.row_text(row));
+                if idx == self.selected {
+                    line.style(Style::new().add_modifier(Modifier::REVERSED))
+                } else {
+                    line
+                }
+            })
+            .collect();
+        let mut filters = Vec::new();
+        if let Some(level) = &self.level {
+            filters.push(format!("level >= {level:?}"));
+        }
+        if !self.target.is_empty() {
+            filters.push(format!("target {}*", self.target));
+        }
+        let title = format!(
+            " {path} | {view} | {shown} of {total} records{filters} ",
+            path = self.path,
+            view = if self.tree { "tree" } else { "stream" },
+            shown = self.rows.len(),
+            total = self.records.len(),
+            filters = if filters.is_empty() {
+                String::new()
+            } else {
+                format!(" | {}", filters.join(", "))
+            },
+        );
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::new().borders(Borders::ALL).title(title)),
+            list_area,
+        );
+
+        let detail = self
+            .rows
+            .get(self.selected)
+            .map_or_else(String::new, |row| {
+                let record = &self.records[row.record];
+                let mut detail = PrettyRecord::new(record).to_string();
+                if let Some(metadata) = self.metadata(row.record) {
+                    if let Some(file) = &metadata.file {
+                        detail.push_str(&format!("\nat {file}"));
+                        if let Some(line) = metadata.line {
+                            detail.push_str(&format!(":{line}"));
+                        }
+                    }
+                }
+                detail
+            });
+        frame.render_widget(
+            Paragraph::new(detail)
+                .wrap(Wrap { trim: false })
+                .block(Block::new().borders(Borders::TOP)),
+            detail_area,
+        );
+
+        let status = match &self.input {
+            Some((Prompt::Target, text)) => format!("target prefix: {text}"),
+            Some((Prompt::Time, text)) => format!("jump to time (such as 1.5s): {text}"),
+            None => self.message.clone().unwrap_or_else(|| HELP.to_owned()),
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+    }
+
+    /// Returns the text of a row: the time since the start, the thread, and the record without
+    /// its timestamp and thread.
+    fn row_text(&self, row: &Row) -> String {
+        let record = &self.records[row.record];
+        let meta = &record.meta;
+        let time = meta.timestamp().saturating_sub(self.start);
+        let thread = meta.thread_name.as_deref().unwrap_or(&meta.thread_id);
+        let marker = match row.node {
+            Some(node) if !self.nodes[node].children.is_empty() => {
+                if self.collapsed.contains(&node) {
+                    "▸ "
+                } else {
+                    "▾ "
+                }
+            }
+            Some(_) => "  ",
+            None => "",
+        };
+
+        // The pretty record starts with the timestamp and thread, which have their own columns.
+        let mut prefix = format!(
+            "{}.{:06} {}",
+            meta.timestamp_s, meta.timestamp_subsec_us, meta.thread_id
+        );
+        if let Some(thread_name) = &meta.thread_name {
+            prefix.push_str(&format!("[{thread_name}]"));
+        }
+        let pretty = PrettyRecord::new(record).to_string();
+        let trace = pretty
+            .strip_prefix(&prefix)
+            .map_or(pretty.as_str(), str::trim_start);
+
+        format!(
+            "{time:>12.6} {thread:<12.12} {indent}{marker}{trace}",
+            time = time.as_secs_f64(),
+            indent = "  ".repeat(row.depth),
+        )
+    }
+}
@@ -0,0 +1,295 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+use crate::{export, ExportError, Field, FieldValue, Header, Kind, Metadata, Trace, TraceRecord};
+
+/// What is done with the values of a field when a recording is anonymized.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum FieldAction {
+    /// The value is kept as it is.
+    Keep,
+    /// The value is replaced by a hash of the value, written as a hexadecimal string.
+    ///
+    /// The same value always has the same hash, even in different recordings, as long as they are
+    /// anonymized with the same salt. This isn't a cryptographic hash, values which could only be
+    /// one of a few possibilities can be found by hashing each of them, so a secret salt should be
+    /// used.
+    Hash,
+    /// The value is replaced by a token made up of the field name and a number, such as `user-1`.
+    ///
+    /// Each distinct value of a field gets its own token, in the order that the values appear.
+    /// The tokens don't reveal anything about the values, but they are only consistent within a
+    /// single anonymized recording.
+    Tokenize,
+    /// The field is removed, as if its value had never been recorded.
+    Remove,
+}
+
+/// Rewrites recordings so that they can be shared without revealing the data in them.
+///
+/// The values of fields are rewritten by the [`FieldAction`] of the first rule added with
+/// [`with_field`] whose pattern matches the field name, or by the default action if there is
+/// none. By default, all values are hashed. Patterns are matched against the whole field name,
+/// and may contain `*` to match any number of characters, such as `user.*`.
+///
+/// The structure of the recording is kept as it is: the callsites, spans, and events, their
+/// levels and names, and the names of their fields. The locations in the source code can also
+/// be removed with [`with_strip_paths`], and the targets replaced with tokens with
+/// [`with_strip_targets`].
+///
+/// The tokens given to values and targets are kept for the lifetime of the anonymizer, so every
+/// recording anonymized with the same anonymizer is consistent with the others.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_cassette::{Anonymizer, FieldAction, FieldValue, Trace, TraceRecord};
+///
+/// let mut record: TraceRecord = serde_json::from_str(
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543420,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"user logged in"}},{"name":"user.email","value":{"Str":"ferris@example.com"}},{"name":"password","value":{"Str":"hunter2"}}],"metadata":{"id":4403349608,"name":"event src/login.rs:12","target":"my_app::login","level":"Info","module_path":"my_app::login","file":"src/login.rs","line":12,"fields":["message","user.email","password"],"kind":"Event"},"parent":"Current"}}}"#,
+/// ).unwrap();
+///
+/// let mut anonymizer = Anonymizer::new()
+///     .with_field("message", FieldAction::Keep)
+///     .with_field("user.*", FieldAction::Tokenize)
+///     .with_field("password", FieldAction::Remove)
+///     .with_strip_paths(true)
+///     .with_strip_targets(true);
+/// anonymizer.anonymize(&mut record);
+///
+/// let Trace::Event(event) = &record.trace else { unreachable!() };
+/// assert_eq!(event.fields.len(), 2);
+/// assert_eq!(event.fields[0].value, FieldValue::Debug("user logged in".into()));
+/// assert_eq!(event.fields[1].value, FieldValue::Str("user.email-1".into()));
+/// assert_eq!(event.metadata.name, "event");
+/// assert_eq!(event.metadata.target, "target-1");
+/// assert_eq!(event.metadata.file, None);
+/// ```
+///
+/// [`with_field`]: fn@Self::with_field
+/// [`with_strip_paths`]: fn@Self::with_strip_paths
+/// [`with_strip_targets`]: fn@Self::with_strip_targets
+#[derive(Debug)]
+pub struct Anonymizer {
+    rules: Vec<(String, FieldAction)>,
+    default_action: FieldAction,
+    salt: String,
+    strip_paths: bool,
+    strip_targets: bool,
+    /// The tokens of the values of each field, by field name and serialized value.
+    value_tokens: HashMap<(String, String), usize>,
+    /// The number of tokens given out for each field.
+    value_token_counts: HashMap<String, usize>,
+    target_tokens: HashMap<String, usize>,
+}
+
+impl Default for Anonymizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Anonymizer {
+    /// Creates an anonymizer which hashes the values of all fields.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            default_action: FieldAction::Hash,
+            salt: String::new(),
+            strip_paths: false,
+            strip_targets: false,
+            value_tokens: HashMap::new(),
+            value_token_counts: HashMap::new(),
+            target_tokens: HashMap::new(),
+        }
+    }
+
+    /// Sets the action for the fields whose names match `pattern`.
+    ///
+    /// Rules are checked in the order they were added, the first one which matches is used.
+    #[must_use]
+    pub fn with_field(mut self, pattern: impl Into<String>, action: FieldAction) -> Self {
+        self.rules.push((pattern.into(), action));
+        self
+    }
+
+    /// Sets the action for the fields which don't match any rule, the default is
+    /// [`FieldAction::Hash`].
+    #[must_use]
+    pub fn with_default_action(mut self, action: FieldAction) -> Self {
+        self.default_action = action;
+        self
+    }
+
+    /// Sets the salt which is hashed together with each value by [`FieldAction::Hash`].
+    #[must_use]
+    pub fn with_salt(mut self, salt: impl Into<String>) -> Self {
+        self.salt = salt.into();
+        self
+    }
+
+    /// Sets whether the files and line numbers of callsites are removed, the default is `false`.
+    ///
+    /// The location is also removed from the names of events, which include it by default.
+    #[must_use]
+    pub fn with_strip_paths(mut self, strip_paths: bool) -> Self {
+        self.strip_paths = strip_paths;
+        self
+    }
+
+    /// Sets whether the targets of callsites are replaced with tokens, such as `target-1`, the
+    /// default is `false`.
+    ///
+    /// The module paths of callsites are removed as well, as they are usually the same as the
+    /// target.
+    #[must_use]
+    pub fn with_strip_targets(mut self, strip_targets: bool) -> Self {
+        self.strip_targets = strip_targets;
+        self
+    }
+
+    /// Anonymizes a single record.
+    pub fn anonymize(&mut self, record: &mut TraceRecord) {
+        match &mut record.trace {
+            Trace::RegisterCallsite(metadata) => self.anonymize_metadata(metadata),
+            Trace::NewSpan(new_span) => {
+                self.anonymize_metadata(&mut new_span.metadata);
+                self.anonymize_fields(&mut new_span.fields);
+            }
+            Trace::Event(event) => {
+                self.anonymize_metadata(&mut event.metadata);
+                self.anonymize_fields(&mut event.fields);
+            }
+            Trace::Record(record_values) => self.anonymize_fields(&mut record_values.fields),
+            Trace::Enter(_) | Trace::Exit(_) | Trace::Close(_) | Trace::FollowsFrom(_) => {}
+        }
+        if let Some(final_fields) = &mut record.final_fields {
+            self.anonymize_fields(final_fields);
+        }
+    }
+
+    /// Anonymizes the recording read from `reader`, which is written to `writer` in the current
+    /// version of the format.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the recording or writing the anonymized recording fails, if a
+    /// line of the recording can't be deserialized into a record, or if the recording was written
+    /// in a newer version of the format.
+    pub fn anonymize_recording<R, W>(&mut self, reader: R, mut writer: W) -> Result<(), ExportError>
+    where
+        R: BufRead,
+        W: Write,
+    {
+        writeln!(writer, "{}", Header::new().to_line())?;
+        export::for_each_record(reader, |mut record| {
+            self.anonymize(&mut record);
+            serde_json::to_writer(&mut writer, &record).map_err(io::Error::from)?;
+            writer.write_all(b"\n")?;
+            Ok(())
+        })?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    fn anonymize_metadata(&mut self, metadata: &mut Metadata) {
+        if self.strip_paths {
+            if let (Kind::Event, Some(file), Some(line)) =
+                (&metadata.kind, &metadata.file, metadata.line)
+            {
+                let location = format!(" {file}:{line}");
+                if let Some(name) = metadata.name.strip_suffix(&location) {
+                    metadata.name = name.to_owned();
+                }
+            }
+            metadata.file = None;
+            metadata.line = None;
+        }
+        if self.strip_targets {
+            let next_token = self.target_tokens.len() + 1;
+            let token = *self
+                .target_tokens
+                .entry(metadata.target.clone())
+                .or_insert(next_token);
+            metadata.target = format!("target-{token}");
+            metadata.module_path = None;
+        }
+    }
+
+    fn anonymize_fields(&mut self, fields: &mut Vec<Field>) {
+        fields.retain(|field| self.action(&field.name) != FieldAction::Remove);
+        for field in fields {
+            match self.action(&field.name) {
+                FieldAction::Keep | FieldAction::Remove => {}
+                FieldAction::Hash => {
+                    let hash = fnv1a(&[self.salt.as_bytes(), value_key(&field.value).as_bytes()]);
+                    field.value = FieldValue::Str(format!("{hash:016x}"));
+                }
+                FieldAction::Tokenize => {
+                    let key = (field.name.clone(), value_key(&field.value));
+                    let count = self
+                        .value_token_counts
+                        .entry(field.name.clone())
+                        .or_default();
+                    let token = *self.value_tokens.entry(key).or_insert_with(|| {
+                        *count += 1;
+                        *count
+                    });
+                    field.value = FieldValue::Str(format!("{}-{token}", field.name));
+                }
+            }
+        }
+    }
+
+    fn action(&self, field_name: &str) -> FieldAction {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| matches_pattern(pattern, field_name))
+            .map_or(self.default_action, |(_, action)| *action)
+    }
+}
+
+/// A key which is the same for equal values, and different for different values.
+fn value_key(value: &FieldValue) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+/// Hashes the concatenation of `parts` with 64-bit FNV-1a, which unlike the hasher in the
+/// standard library gives the same hashes in every version of Rust.
+fn fnv1a(parts: &[&[u8]]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    parts
+        .iter()
+        .flat_map(|part| part.iter())
+        .fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+        })
+}
+
+/// Returns whether `name` matches `pattern`, in which `*` matches any number of characters.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // There is no `*` in the pattern.
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
@@ -0,0 +1,546 @@
+use std::{
+    io::{self, Write},
+    ops::ControlFlow,
+    time::Duration,
+};
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::{
+    Event, Field, FieldValue, FollowsFrom, Kind, Level, Metadata, NewSpan, Parent, RecordMeta,
+    RecordValues, SpanId, Trace, TraceContext, TraceRecord, MAX_FIELDS,
+};
+
+/// The most records in an [`ArbitraryRecording`].
+const MAX_RECORDS: u32 = 256;
+/// The most threads in an [`ArbitraryRecording`].
+const MAX_THREADS: usize = 4;
+/// The most fields on a callsite in an [`ArbitraryRecording`].
+const MAX_RECORDING_FIELDS: usize = 8;
+/// The most time between two records in an [`ArbitraryRecording`], in microseconds.
+const MAX_STEP_US: u64 = 10_000;
+/// The deepest nesting of arrays and objects in a structured field value.
+const MAX_JSON_DEPTH: usize = 3;
+/// The most elements of an array, or entries of an object, in a structured field value.
+const MAX_JSON_LEN: usize = 4;
+
+impl<'a> Arbitrary<'a> for Level {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0..=4)? {
+            0 => Self::Trace,
+            1 => Self::Debug,
+            2 => Self::Info,
+            3 => Self::Warn,
+            _ => Self::Error,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Kind {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(if u.arbitrary()? {
+            Self::Span
+        } else {
+            Self::Event
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for SpanId {
+    /// Span Ids are never zero.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self::from(u.int_in_range(1..=u64::MAX)?))
+    }
+}
+
+impl<'a> Arbitrary<'a> for TraceContext {
+    /// Neither Id is ever zero.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            trace_id: u.int_in_range(1..=u128::MAX)?,
+            span_id: u.int_in_range(1..=u64::MAX)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Parent {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0..=2)? {
+            0 => Self::Root,
+            1 => Self::Current,
+            _ => Self::Explicit(u.arbitrary()?),
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for FieldValue {
+    /// Floating point values are always finite, as JSON has no way to represent the others.
+    /// Structured values are nested at most [`MAX_JSON_DEPTH`] deep.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0..=8)? {
+            0 => Self::Debug(u.arbitrary()?),
+            1 => {
+                let value: f64 = u.arbitrary()?;
+                Self::F64(if value.is_finite() { value } else { 0.0 })
+            }
+            2 => Self::I64(u.arbitrary()?),
+            3 => Self::U64(u.arbitrary()?),
+            4 => Self::I128(u.arbitrary()?),
+            5 => Self::U128(u.arbitrary()?),
+            6 => Self::Bool(u.arbitrary()?),
+            7 => Self::Str(u.arbitrary()?),
+            _ => Self::Json(arbitrary_json(u, MAX_JSON_DEPTH)?),
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Field {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            name: u.arbitrary()?,
+            value: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for RecordMeta {
+    /// The sub-second part of the timestamp is always less than a second.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            timestamp_s: u.arbitrary()?,
+            timestamp_subsec_us: u.int_in_range(0..=999_999)?,
+            thread_id: u.arbitrary()?,
+            thread_name: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Metadata {
+    /// Callsites have at most [`MAX_FIELDS`] fields.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let kind = u.arbitrary()?;
+        arbitrary_metadata(u, kind, MAX_FIELDS)
+    }
+}
+
+impl<'a> Arbitrary<'a> for Event {
+    /// The callsite is always an event, and the fields are always declared by it.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let metadata = arbitrary_metadata(u, Kind::Event, MAX_FIELDS)?;
+        Ok(Self {
+            fields: arbitrary_fields(u, &metadata.fields)?,
+            parent: u.arbitrary()?,
+            metadata,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for NewSpan {
+    /// The callsite is always a span, and the fields are always declared by it.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let metadata = arbitrary_metadata(u, Kind::Span, MAX_FIELDS)?;
+        Ok(Self {
+            id: u.arbitrary()?,
+            fields: arbitrary_fields(u, &metadata.fields)?,
+            parent: u.arbitrary()?,
+            metadata,
+            trace_context: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for RecordValues {
+    /// At most [`MAX_FIELDS`] values are recorded.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            id: u.arbitrary()?,
+            fields: arbitrary_vec(u, MAX_FIELDS)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for FollowsFrom {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            cause_id: u.arbitrary()?,
+            effect_id: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Trace {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0..=7)? {
+            0 => Self::RegisterCallsite(u.arbitrary()?),
+            1 => Self::Event(u.arbitrary()?),
+            2 => Self::NewSpan(u.arbitrary()?),
+            3 => Self::Enter(u.arbitrary()?),
+            4 => Self::Exit(u.arbitrary()?),
+            5 => Self::Close(u.arbitrary()?),
+            6 => Self::Record(u.arbitrary()?),
+            _ => Self::FollowsFrom(u.arbitrary()?),
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for TraceRecord {
+    /// Each record passes [`validate`] on its own, but the span Ids it refers to are arbitrary,
+    /// use [`ArbitraryRecording`] for a whole recording which refers only to spans that exist.
+    ///
+    /// [`validate`]: fn@crate::validate
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let meta = u.arbitrary()?;
+        let trace = u.arbitrary()?;
+        // Only the records of spans closing have final field values.
+        let final_fields = match trace {
+            Trace::Close(_) => u.arbitrary()?,
+            _ => None,
+        };
+        Ok(Self {
+            meta,
+            trace,
+            final_fields,
+            sequence: None,
+        })
+    }
+}
+
+/// A recording which is structurally valid, generated from unstructured data.
+///
+/// Where an arbitrary [`TraceRecord`] is only valid on its own, the records of an arbitrary
+/// recording are also valid together, as [`validate_stream`] checks them: callsites are
+/// registered before they are used and always with the same metadata, spans are only entered,
+/// exited, recorded, and closed while they are open, only fields declared by the callsite are
+/// recorded, and explicit parents are spans which have been created. The timestamps never go
+/// backwards, so they are also monotonic on each thread. Spans are exited on the thread they were
+/// entered on, and aren't closed while they are entered. Span Ids may be reused once the span
+/// they were given to is closed, as they are by `tracing-subscriber`'s registry.
+///
+/// This allows the replayer and the converters to be property-tested and fuzzed with realistic
+/// recordings, instead of ones which are rejected early on. Requires the `arbitrary` feature.
+///
+/// [`validate_stream`]: fn@crate::validate_stream
+///
+/// # Examples
+///
+/// ```
+/// use arbitrary::{Arbitrary, Unstructured};
+/// use tracing_cassette::{ArbitraryRecording, RecordReader};
+///
+/// // Any bytes will do, such as those from a fuzzer.
+/// let data: Vec<u8> = (0..4096_u32)
+///     .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+///     .collect();
+/// let recording = ArbitraryRecording::arbitrary(&mut Unstructured::new(&data)).unwrap();
+///
+/// let mut written = Vec::new();
+/// recording.write(&mut written).unwrap();
+///
+/// assert!(tracing_cassette::validate_stream(written.as_slice()).unwrap().is_empty());
+/// let read = RecordReader::new(written.as_slice())
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+/// assert_eq!(read.len(), recording.records.len());
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArbitraryRecording {
+    /// The records of the recording, in the order they were recorded.
+    pub records: Vec<TraceRecord>,
+}
+
+impl ArbitraryRecording {
+    /// Writes the recording in the current version of the format, starting with the header.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to `writer` fails.
+    pub fn write<W: Write>(&self, writer: W) -> io::Result<()> {
+        crate::write_recording(writer, &self.records)
+    }
+}
+
+impl<'a> Arbitrary<'a> for ArbitraryRecording {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut generator = Generator::new(u)?;
+        u.arbitrary_loop(None, Some(MAX_RECORDS), |u| {
+            generator.step(u)?;
+            Ok(ControlFlow::Continue(()))
+        })?;
+
+        Ok(Self {
+            records: generator.records,
+        })
+    }
+}
+
+/// A thread of an [`ArbitraryRecording`].
+struct Thread {
+    id: String,
+    name: Option<String>,
+    /// The spans entered on the thread, innermost last.
+    entered: Vec<SpanId>,
+}
+
+/// The state of an [`ArbitraryRecording`] as it is generated.
+struct Generator {
+    /// The timestamp of the last record.
+    timestamp: Duration,
+    threads: Vec<Thread>,
+    /// The callsites which have been registered.
+    callsites: Vec<Metadata>,
+    /// The open spans, with the index of their callsite.
+    open: Vec<(SpanId, usize)>,
+    /// The spans which have been closed, and can be used as explicit parents.
+    closed: Vec<SpanId>,
+    next_span_id: u64,
+    records: Vec<TraceRecord>,
+}
+
+impl Generator {
+    fn new(u: &mut Unstructured<'_>) -> Result<Self> {
+        let thread_count = u.int_in_range(1..=MAX_THREADS)?;
+        let threads = (0..thread_count)
+            .map(|idx| {
+                Ok(Thread {
+                    id: format!("ThreadId({})", idx + 1),
+                    name: if idx == 0 {
+                        Some("main".to_owned())
+                    } else {
+                        u.arbitrary()?
+                    },
+                    entered: Vec::new(),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            // Somewhere between 2017 and 2033.
+            timestamp: Duration::from_secs(u.int_in_range(1_500_000_000..=2_000_000_000)?)
+                + Duration::from_micros(u.int_in_range(0..=999_999)?),
+            threads,
+            callsites: Vec::new(),
+            open: Vec::new(),
+            closed: Vec::new(),
+            next_span_id: 1,
+            records: Vec::new(),
+        })
+    }
+
+    /// Adds the next record, along with the callsite it uses if that is new.
+    fn step(&mut self, u: &mut Unstructured<'_>) -> Result<()> {
+        let thread = u.choose_index(self.threads.len())?;
+        let trace = match u.int_in_range(0..=6)? {
+            0 => self.enter(u, thread)?,
+            1 => self.exit(thread),
+            2 => self.close(u)?,
+            3 => self.record(u)?,
+            4 => self.follows_from(u)?,
+            5 => Some(self.new_span(u, thread)?),
+            _ => None,
+        };
+        let trace = match trace {
+            Some(trace) => trace,
+            // Events can always be added.
+            None => self.event(u, thread)?,
+        };
+        self.push(u, thread, trace)
+    }
+
+    fn push(&mut self, u: &mut Unstructured<'_>, thread: usize, trace: Trace) -> Result<()> {
+        self.timestamp += Duration::from_micros(u.int_in_range(0..=MAX_STEP_US)?);
+        let thread = &self.threads[thread];
+        self.records.push(TraceRecord {
+            meta: RecordMeta {
+                timestamp_s: self.timestamp.as_secs(),
+                timestamp_subsec_us: self.timestamp.subsec_micros(),
+                thread_id: thread.id.clone(),
+                thread_name: thread.name.clone(),
+            },
+            trace,
+            final_fields: None,
+            sequence: None,
+        });
+        Ok(())
+    }
+
+    /// Returns the index of a callsite of `kind`, registering a new one if needed.
+    fn callsite(&mut self, u: &mut Unstructured<'_>, thread: usize, kind: Kind) -> Result<usize> {
+        let existing: Vec<usize> = (0..self.callsites.len())
+            .filter(|idx| self.callsites[*idx].kind == kind)
+            .collect();
+        if !existing.is_empty() && u.ratio(3, 4)? {
+            return Ok(*u.choose(&existing)?);
+        }
+
+        let mut metadata = arbitrary_metadata(u, kind, MAX_RECORDING_FIELDS)?;
+        // Callsite Ids are unique, they are usually the address of the callsite.
+        metadata.id =
+            self.callsites.last().map_or(0x1000, |last| last.id) + u.int_in_range(1..=0x1000)?;
+        self.callsites.push(metadata.clone());
+        self.push(u, thread, Trace::RegisterCallsite(metadata))?;
+        Ok(self.callsites.len() - 1)
+    }
+
+    fn parent(&self, u: &mut Unstructured<'_>) -> Result<Parent> {
+        let spans: Vec<SpanId> = self
+            .open
+            .iter()
+            .map(|(id, _)| *id)
+            .chain(self.closed.iter().copied())
+            .collect();
+        Ok(match u.int_in_range(0..=2)? {
+            0 => Parent::Current,
+            1 if !spans.is_empty() => Parent::Explicit(*u.choose(&spans)?),
+            _ => Parent::Root,
+        })
+    }
+
+    fn event(&mut self, u: &mut Unstructured<'_>, thread: usize) -> Result<Trace> {
+        let callsite = self.callsite(u, thread, Kind::Event)?;
+        let metadata = self.callsites[callsite].clone();
+        Ok(Trace::Event(Event {
+            fields: arbitrary_fields(u, &metadata.fields)?,
+            parent: self.parent(u)?,
+            metadata,
+        }))
+    }
+
+    fn new_span(&mut self, u: &mut Unstructured<'_>, thread: usize) -> Result<Trace> {
+        let callsite = self.callsite(u, thread, Kind::Span)?;
+        let metadata = self.callsites[callsite].clone();
+        let parent = self.parent(u)?;
+        let id = if !self.closed.is_empty() && u.ratio(1, 4)? {
+            let idx = u.choose_index(self.closed.len())?;
+            self.closed.swap_remove(idx)
+        } else {
+            self.next_span_id += 1;
+            SpanId::from(self.next_span_id - 1)
+        };
+        self.open.push((id, callsite));
+        Ok(Trace::NewSpan(NewSpan {
+            id,
+            fields: arbitrary_fields(u, &metadata.fields)?,
+            metadata,
+            parent,
+            trace_context: u.arbitrary()?,
+        }))
+    }
+
+    fn enter(&mut self, u: &mut Unstructured<'_>, thread: usize) -> Result<Option<Trace>> {
+        if self.open.is_empty() {
+            return Ok(None);
+        }
+        let (id, _) = *u.choose(&self.open)?;
+        self.threads[thread].entered.push(id);
+        Ok(Some(Trace::Enter(id)))
+    }
+
+    fn exit(&mut self, thread: usize) -> Option<Trace> {
+        self.threads[thread].entered.pop().map(Trace::Exit)
+    }
+
+    fn close(&mut self, u: &mut Unstructured<'_>) -> Result<Option<Trace>> {
+        let closable: Vec<usize> = (0..self.open.len())
+            .filter(|idx| {
+                let (id, _) = self.open[*idx];
+                !self
+                    .threads
+                    .iter()
+                    .any(|thread| thread.entered.contains(&id))
+            })
+            .collect();
+        if closable.is_empty() {
+            return Ok(None);
+        }
+        let (id, _) = self.open.remove(*u.choose(&closable)?);
+        self.closed.push(id);
+        Ok(Some(Trace::Close(id)))
+    }
+
+    fn record(&mut self, u: &mut Unstructured<'_>) -> Result<Option<Trace>> {
+        if self.open.is_empty() {
+            return Ok(None);
+        }
+        let (id, callsite) = *u.choose(&self.open)?;
+        Ok(Some(Trace::Record(RecordValues {
+            id,
+            fields: arbitrary_fields(u, &self.callsites[callsite].fields)?,
+        })))
+    }
+
+    fn follows_from(&mut self, u: &mut Unstructured<'_>) -> Result<Option<Trace>> {
+        if self.open.is_empty() {
+            return Ok(None);
+        }
+        let (cause_id, _) = *u.choose(&self.open)?;
+        let (effect_id, _) = *u.choose(&self.open)?;
+        Ok(Some(Trace::FollowsFrom(FollowsFrom {
+            cause_id,
+            effect_id,
+        })))
+    }
+}
+
+/// Returns callsite metadata of `kind` with at most `max_fields` fields.
+fn arbitrary_metadata(u: &mut Unstructured<'_>, kind: Kind, max_fields: usize) -> Result<Metadata> {
+    Ok(Metadata {
+        id: u.arbitrary()?,
+        name: u.arbitrary()?,
+        target: u.arbitrary()?,
+        level: u.arbitrary()?,
+        module_path: u.arbitrary()?,
+        file: u.arbitrary()?,
+        line: u.arbitrary()?,
+        fields: arbitrary_vec(u, max_fields)?,
+        kind,
+    })
+}
+
+/// Returns values for some of the fields in `names`.
+fn arbitrary_fields(u: &mut Unstructured<'_>, names: &[String]) -> Result<Vec<Field>> {
+    let mut fields = Vec::new();
+    for name in names {
+        if u.arbitrary()? {
+            fields.push(Field {
+                name: name.clone(),
+                value: u.arbitrary()?,
+            });
+        }
+    }
+    Ok(fields)
+}
+
+/// Returns at most `max` arbitrary values.
+fn arbitrary_vec<'a, T: Arbitrary<'a>>(u: &mut Unstructured<'a>, max: usize) -> Result<Vec<T>> {
+    let len = u.int_in_range(0..=max)?;
+    (0..len).map(|_| u.arbitrary()).collect()
+}
+
+/// Returns an arbitrary JSON value, with arrays and objects nested at most `depth` deep.
+fn arbitrary_json(u: &mut Unstructured<'_>, depth: usize) -> Result<serde_json::Value> {
+    use serde_json::Value;
+
+    let max = if depth == 0 { 4 } else { 6 };
+    Ok(match u.int_in_range(0..=max)? {
+        0 => Value::Null,
+        1 => Value::Bool(u.arbitrary()?),
+        2 => Value::from(u.arbitrary::<i64>()?),
+        3 => {
+            let value: f64 = u.arbitrary()?;
+            Value::from(if value.is_finite() { value } else { 0.0 })
+        }
+        4 => Value::String(u.arbitrary()?),
+        5 => {
+            let len = u.int_in_range(0..=MAX_JSON_LEN)?;
+            (0..len)
+                .map(|_| arbitrary_json(u, depth - 1))
+                .collect::<Result<_>>()?
+        }
+        _ => {
+            let len = u.int_in_range(0..=MAX_JSON_LEN)?;
+            (0..len)
+                .map(|_| Ok((u.arbitrary::<String>()?, arbitrary_json(u, depth - 1)?)))
+                .collect::<Result<_>>()?
+        }
+    })
+}
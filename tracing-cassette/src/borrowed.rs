@@ -0,0 +1,540 @@
+use std::{borrow::Cow, fmt, time::Duration};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    Event, Field, FieldValue, FollowsFrom, Kind, Level, Metadata, NewSpan, Parent, RecordMeta,
+    RecordValues, SpanId, Trace, TraceContext, TraceRecord,
+};
+
+/// A trace record which borrows its strings from the recording data where possible.
+///
+/// This is the borrowed form of [`TraceRecord`], with the same serialized format. Parsing records
+/// into this form means that records which are only inspected don't need to allocate at all, and
+/// a recorder can write records which borrow from the callsite metadata.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_cassette::{TraceRecord, TraceRecordRef};
+///
+/// let line = r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#;
+/// let record: TraceRecordRef<'_> = serde_json::from_str(line).unwrap();
+/// assert_eq!(record.meta.thread_id.as_str(), "ThreadId(1)");
+///
+/// // Both forms serialize to the same line.
+/// assert_eq!(serde_json::to_string(&record).unwrap(), line);
+/// assert_eq!(serde_json::to_string(&TraceRecord::from(record)).unwrap(), line);
+/// ```
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TraceRecordRef<'a> {
+    /// When and on which thread the trace was recorded.
+    #[serde(borrow)]
+    pub meta: RecordMetaRef<'a>,
+    /// The recorded trace.
+    #[serde(borrow)]
+    pub trace: TraceRef<'a>,
+    /// The borrowed form of [`TraceRecord::final_fields`].
+    #[serde(borrow, default, skip_serializing_if = "Option::is_none")]
+    pub final_fields: Option<Vec<FieldRef<'a>>>,
+    /// The same as [`TraceRecord::sequence`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+}
+
+/// The borrowed form of [`RecordMeta`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RecordMetaRef<'a> {
+    pub timestamp_s: u64,
+    pub timestamp_subsec_us: u32,
+    #[serde(borrow)]
+    pub thread_id: CowStr<'a>,
+    #[serde(borrow)]
+    pub thread_name: Option<CowStr<'a>>,
+}
+
+impl RecordMetaRef<'_> {
+    /// The time at which the trace was recorded, as a duration since the UNIX epoch.
+    #[must_use]
+    pub fn timestamp(&self) -> Duration {
+        Duration::from_secs(self.timestamp_s)
+            .saturating_add(Duration::from_micros(u64::from(self.timestamp_subsec_us)))
+    }
+}
+
+/// The borrowed form of [`Trace`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum TraceRef<'a> {
+    #[serde(borrow)]
+    RegisterCallsite(MetadataRef<'a>),
+    #[serde(borrow)]
+    Event(EventRef<'a>),
+    #[serde(borrow)]
+    NewSpan(NewSpanRef<'a>),
+    Enter(SpanId),
+    Exit(SpanId),
+    Close(SpanId),
+    #[serde(borrow)]
+    Record(RecordValuesRef<'a>),
+    FollowsFrom(FollowsFrom),
+}
+
+/// The borrowed form of [`Metadata`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MetadataRef<'a> {
+    pub id: u64,
+    #[serde(borrow)]
+    pub name: CowStr<'a>,
+    #[serde(borrow)]
+    pub target: CowStr<'a>,
+    pub level: Level,
+    #[serde(borrow)]
+    pub module_path: Option<CowStr<'a>>,
+    #[serde(borrow)]
+    pub file: Option<CowStr<'a>>,
+    pub line: Option<u32>,
+    #[serde(borrow)]
+    pub fields: Vec<CowStr<'a>>,
+    pub kind: Kind,
+}
+
+/// The borrowed form of [`Field`].
+#[derive(Clone, Debug, Serialize)]
+pub struct FieldRef<'a> {
+    pub name: CowStr<'a>,
+    pub value: FieldValueRef<'a>,
+}
+
+/// Accepts both the object and the pair encoding of a field, see [`Field`].
+impl<'de: 'a, 'a> Deserialize<'de> for FieldRef<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(field_identifier, rename_all = "lowercase")]
+        enum Key {
+            Name,
+            Value,
+            #[serde(other)]
+            Other,
+        }
+
+        struct FieldRefVisitor;
+
+        impl<'de> de::Visitor<'de> for FieldRefVisitor {
+            type Value = FieldRef<'de>;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a field object or a pair of field name and value")
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let name = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let EncodedFieldValue(value) = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                if seq.next_element::<de::IgnoredAny>()?.is_some() {
+                    return Err(de::Error::invalid_length(3, &self));
+                }
+
+                Ok(FieldRef { name, value })
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut name = None;
+                let mut value = None;
+                while let Some(key) = map.next_key()? {
+                    match key {
+                        Key::Name if name.is_some() => {
+                            return Err(de::Error::duplicate_field("name"))
+                        }
+                        Key::Name => name = Some(map.next_value()?),
+                        Key::Value if value.is_some() => {
+                            return Err(de::Error::duplicate_field("value"))
+                        }
+                        Key::Value => value = Some(map.next_value::<EncodedFieldValue<'de>>()?.0),
+                        Key::Other => {
+                            map.next_value::<de::IgnoredAny>()?;
+                        }
+                    }
+                }
+
+                Ok(FieldRef {
+                    name: name.ok_or_else(|| de::Error::missing_field("name"))?,
+                    value: value.ok_or_else(|| de::Error::missing_field("value"))?,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(FieldRefVisitor)
+    }
+}
+
+/// A field value which is either tagged with its type or given as a plain JSON value.
+struct EncodedFieldValue<'a>(FieldValueRef<'a>);
+
+impl<'a> EncodedFieldValue<'a> {
+    /// A plain string is the recorded `Debug` representation of the value.
+    fn debug(value: Cow<'a, str>) -> Self {
+        Self(FieldValueRef::Debug(CowStr(value)))
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for EncodedFieldValue<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct EncodedFieldValueVisitor;
+
+        impl<'de> de::Visitor<'de> for EncodedFieldValueVisitor {
+            type Value = EncodedFieldValue<'de>;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a field value")
+            }
+
+            fn visit_bool<E: de::Error>(self, v: bool) -> Result<Self::Value, E> {
+                Ok(EncodedFieldValue(FieldValueRef::Bool(v)))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                Ok(EncodedFieldValue(FieldValueRef::I64(v)))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                Ok(EncodedFieldValue(FieldValueRef::U64(v)))
+            }
+
+            fn visit_i128<E: de::Error>(self, v: i128) -> Result<Self::Value, E> {
+                Ok(EncodedFieldValue(FieldValueRef::I128(v)))
+            }
+
+            fn visit_u128<E: de::Error>(self, v: u128) -> Result<Self::Value, E> {
+                Ok(EncodedFieldValue(FieldValueRef::U128(v)))
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+                Ok(EncodedFieldValue(FieldValueRef::F64(v)))
+            }
+
+            fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> Result<Self::Value, E> {
+                Ok(EncodedFieldValue::debug(Cow::Borrowed(v)))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(EncodedFieldValue::debug(Cow::Owned(v.to_owned())))
+            }
+
+            fn visit_string<E: de::Error>(self, v: String) -> Result<Self::Value, E> {
+                Ok(EncodedFieldValue::debug(Cow::Owned(v)))
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                FieldValueRef::deserialize(de::value::MapAccessDeserializer::new(map))
+                    .map(EncodedFieldValue)
+            }
+        }
+
+        deserializer.deserialize_any(EncodedFieldValueVisitor)
+    }
+}
+
+/// The borrowed form of [`FieldValue`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum FieldValueRef<'a> {
+    #[serde(borrow)]
+    Debug(CowStr<'a>),
+    F64(f64),
+    I64(i64),
+    U64(u64),
+    I128(i128),
+    U128(u128),
+    Bool(bool),
+    #[serde(borrow)]
+    Str(CowStr<'a>),
+    Json(serde_json::Value),
+}
+
+/// The borrowed form of [`Event`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EventRef<'a> {
+    #[serde(borrow)]
+    pub fields: Vec<FieldRef<'a>>,
+    #[serde(borrow)]
+    pub metadata: MetadataRef<'a>,
+    pub parent: Parent,
+}
+
+/// The borrowed form of [`NewSpan`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NewSpanRef<'a> {
+    pub id: SpanId,
+    #[serde(borrow)]
+    pub fields: Vec<FieldRef<'a>>,
+    #[serde(borrow)]
+    pub metadata: MetadataRef<'a>,
+    pub parent: Parent,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_context: Option<TraceContext>,
+}
+
+/// The borrowed form of [`RecordValues`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RecordValuesRef<'a> {
+    pub id: SpanId,
+    #[serde(borrow)]
+    pub fields: Vec<FieldRef<'a>>,
+}
+
+/// A string which is borrowed from the recording data unless it contained escape sequences.
+///
+/// Unlike `Cow<'a, str>`, this type also borrows when it is nested inside an `Option` or a `Vec`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CowStr<'a>(Cow<'a, str>);
+
+impl CowStr<'_> {
+    /// Returns the string.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the string, allocating it if it is borrowed.
+    #[must_use]
+    pub fn into_owned(self) -> String {
+        self.0.into_owned()
+    }
+
+    /// Converts this string into one which doesn't borrow.
+    #[must_use]
+    pub fn into_static(self) -> CowStr<'static> {
+        CowStr(Cow::Owned(self.0.into_owned()))
+    }
+}
+
+impl From<String> for CowStr<'static> {
+    fn from(value: String) -> Self {
+        CowStr(Cow::Owned(value))
+    }
+}
+
+impl<'a> From<&'a str> for CowStr<'a> {
+    fn from(value: &'a str) -> Self {
+        CowStr(Cow::Borrowed(value))
+    }
+}
+
+impl Serialize for CowStr<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for CowStr<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct CowStrVisitor;
+
+        impl<'de> de::Visitor<'de> for CowStrVisitor {
+            type Value = CowStr<'de>;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a string")
+            }
+
+            fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> Result<Self::Value, E> {
+                Ok(CowStr(Cow::Borrowed(v)))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(CowStr(Cow::Owned(v.to_owned())))
+            }
+
+            fn visit_string<E: de::Error>(self, v: String) -> Result<Self::Value, E> {
+                Ok(CowStr(Cow::Owned(v)))
+            }
+        }
+
+        deserializer.deserialize_str(CowStrVisitor)
+    }
+}
+
+impl From<TraceRecordRef<'_>> for TraceRecord {
+    fn from(value: TraceRecordRef<'_>) -> Self {
+        Self {
+            meta: value.meta.into(),
+            trace: value.trace.into(),
+            final_fields: value.final_fields.map(owned_fields),
+            sequence: value.sequence,
+        }
+    }
+}
+
+impl From<RecordMetaRef<'_>> for RecordMeta {
+    fn from(value: RecordMetaRef<'_>) -> Self {
+        Self {
+            timestamp_s: value.timestamp_s,
+            timestamp_subsec_us: value.timestamp_subsec_us,
+            thread_id: value.thread_id.into_owned(),
+            thread_name: value.thread_name.map(CowStr::into_owned),
+        }
+    }
+}
+
+impl From<TraceRef<'_>> for Trace {
+    fn from(value: TraceRef<'_>) -> Self {
+        match value {
+            TraceRef::RegisterCallsite(metadata) => Self::RegisterCallsite(metadata.into()),
+            TraceRef::Event(event) => Self::Event(Event {
+                fields: owned_fields(event.fields),
+                metadata: event.metadata.into(),
+                parent: event.parent,
+            }),
+            TraceRef::NewSpan(new_span) => Self::NewSpan(NewSpan {
+                id: new_span.id,
+                fields: owned_fields(new_span.fields),
+                metadata: new_span.metadata.into(),
+                parent: new_span.parent,
+                trace_context: new_span.trace_context,
+            }),
+            TraceRef::Enter(span_id) => Self::Enter(span_id),
+            TraceRef::Exit(span_id) => Self::Exit(span_id),
+            TraceRef::Close(span_id) => Self::Close(span_id),
+            TraceRef::Record(record_values) => Self::Record(RecordValues {
+                id: record_values.id,
+                fields: owned_fields(record_values.fields),
+            }),
+            TraceRef::FollowsFrom(follows_from) => Self::FollowsFrom(follows_from),
+        }
+    }
+}
+
+impl From<MetadataRef<'_>> for Metadata {
+    fn from(value: MetadataRef<'_>) -> Self {
+        Self {
+            id: value.id,
+            name: value.name.into_owned(),
+            target: value.target.into_owned(),
+            level: value.level,
+            module_path: value.module_path.map(CowStr::into_owned),
+            file: value.file.map(CowStr::into_owned),
+            line: value.line,
+            fields: value.fields.into_iter().map(CowStr::into_owned).collect(),
+            kind: value.kind,
+        }
+    }
+}
+
+impl From<FieldRef<'_>> for Field {
+    fn from(value: FieldRef<'_>) -> Self {
+        Self {
+            name: value.name.into_owned(),
+            value: match value.value {
+                FieldValueRef::Debug(val) => FieldValue::Debug(val.into_owned()),
+                FieldValueRef::F64(val) => FieldValue::F64(val),
+                FieldValueRef::I64(val) => FieldValue::I64(val),
+                FieldValueRef::U64(val) => FieldValue::U64(val),
+                FieldValueRef::I128(val) => FieldValue::I128(val),
+                FieldValueRef::U128(val) => FieldValue::U128(val),
+                FieldValueRef::Bool(val) => FieldValue::Bool(val),
+                FieldValueRef::Str(val) => FieldValue::Str(val.into_owned()),
+                FieldValueRef::Json(val) => FieldValue::Json(val),
+            },
+        }
+    }
+}
+
+/// Converts borrowed fields into owned fields.
+#[must_use]
+pub fn owned_fields(fields: Vec<FieldRef<'_>>) -> Vec<Field> {
+    fields.into_iter().map(Field::from).collect()
+}
+
+impl TraceRecordRef<'_> {
+    /// Converts this record into one which doesn't borrow from the recording data.
+    #[must_use]
+    pub fn into_static(self) -> TraceRecordRef<'static> {
+        TraceRecordRef {
+            meta: RecordMetaRef {
+                timestamp_s: self.meta.timestamp_s,
+                timestamp_subsec_us: self.meta.timestamp_subsec_us,
+                thread_id: self.meta.thread_id.into_static(),
+                thread_name: self.meta.thread_name.map(CowStr::into_static),
+            },
+            trace: match self.trace {
+                TraceRef::RegisterCallsite(metadata) => {
+                    TraceRef::RegisterCallsite(metadata.into_static())
+                }
+                TraceRef::Event(event) => TraceRef::Event(EventRef {
+                    fields: static_fields(event.fields),
+                    metadata: event.metadata.into_static(),
+                    parent: event.parent,
+                }),
+                TraceRef::NewSpan(new_span) => TraceRef::NewSpan(NewSpanRef {
+                    id: new_span.id,
+                    fields: static_fields(new_span.fields),
+                    metadata: new_span.metadata.into_static(),
+                    parent: new_span.parent,
+                    trace_context: new_span.trace_context,
+                }),
+                TraceRef::Enter(span_id) => TraceRef::Enter(span_id),
+                TraceRef::Exit(span_id) => TraceRef::Exit(span_id),
+                TraceRef::Close(span_id) => TraceRef::Close(span_id),
+                TraceRef::Record(record_values) => TraceRef::Record(RecordValuesRef {
+                    id: record_values.id,
+                    fields: static_fields(record_values.fields),
+                }),
+                TraceRef::FollowsFrom(follows_from) => TraceRef::FollowsFrom(follows_from),
+            },
+            final_fields: self.final_fields.map(static_fields),
+            sequence: self.sequence,
+        }
+    }
+}
+
+impl MetadataRef<'_> {
+    /// Converts this metadata into metadata which doesn't borrow from the recording data.
+    #[must_use]
+    pub fn into_static(self) -> MetadataRef<'static> {
+        MetadataRef {
+            id: self.id,
+            name: self.name.into_static(),
+            target: self.target.into_static(),
+            level: self.level,
+            module_path: self.module_path.map(CowStr::into_static),
+            file: self.file.map(CowStr::into_static),
+            line: self.line,
+            fields: self.fields.into_iter().map(CowStr::into_static).collect(),
+            kind: self.kind,
+        }
+    }
+}
+
+fn static_fields(fields: Vec<FieldRef<'_>>) -> Vec<FieldRef<'static>> {
+    fields
+        .into_iter()
+        .map(|field| FieldRef {
+            name: field.name.into_static(),
+            value: match field.value {
+                FieldValueRef::Debug(val) => FieldValueRef::Debug(val.into_static()),
+                FieldValueRef::F64(val) => FieldValueRef::F64(val),
+                FieldValueRef::I64(val) => FieldValueRef::I64(val),
+                FieldValueRef::U64(val) => FieldValueRef::U64(val),
+                FieldValueRef::I128(val) => FieldValueRef::I128(val),
+                FieldValueRef::U128(val) => FieldValueRef::U128(val),
+                FieldValueRef::Bool(val) => FieldValueRef::Bool(val),
+                FieldValueRef::Str(val) => FieldValueRef::Str(val.into_static()),
+                FieldValueRef::Json(val) => FieldValueRef::Json(val),
+            },
+        })
+        .collect()
+}
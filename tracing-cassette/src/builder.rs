@@ -0,0 +1,526 @@
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+    time::Duration,
+};
+
+use crate::{
+    Event, Field, FieldValue, FollowsFrom, Kind, Level, Metadata, NewSpan, Parent, RecordMeta,
+    RecordValues, SpanId, Trace, TraceContext, TraceRecord,
+};
+
+/// The target of callsites which aren't given one.
+const DEFAULT_TARGET: &str = "synthetic";
+
+/// Builds a synthetic recording in code, without running an instrumented program.
+///
+/// Each thread of the recording has its own clock, which starts at the start of the recording
+/// and moves forward by the step, 1µs by default, with each record. It can be moved forward
+/// further with [`ThreadBuilder::advance`], and spans can be given a duration. Threads are built
+/// one after the other with [`thread`], and their records are interleaved by their timestamps
+/// when the recording is built, so that they run in parallel.
+///
+/// Spans are created, entered, exited, and closed around the closure which builds their
+/// contents, so that the spans and events within it are their children. The callsites are
+/// registered just before they are first used.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use tracing_cassette::{EventBuilder, Level, RecordingBuilder, SpanBuilder, Trace};
+///
+/// let mut recording = RecordingBuilder::new();
+/// recording.thread("main", |thread| {
+///     thread.span(SpanBuilder::new("request").with_field("user_id", 42), |thread| {
+///         thread.advance(Duration::from_millis(5));
+///         thread.event(EventBuilder::new("user not found").with_level(Level::Warn));
+///     });
+/// });
+/// recording.thread("worker", |thread| {
+///     thread.advance(Duration::from_millis(1));
+///     thread.span(
+///         SpanBuilder::new("cleanup").with_duration(Duration::from_millis(10)),
+///         |_| {},
+///     );
+/// });
+///
+/// let records = recording.build();
+/// // Three callsites, two spans which are created, entered, exited, and closed, and the event.
+/// assert_eq!(records.len(), 12);
+/// assert!(matches!(records[0].trace, Trace::RegisterCallsite(_)));
+/// assert_eq!(records.last().unwrap().meta.thread_name.as_deref(), Some("worker"));
+/// ```
+///
+/// [`thread`]: fn@Self::thread
+#[derive(Debug)]
+pub struct RecordingBuilder {
+    start: Duration,
+    step: Duration,
+    target: String,
+    threads: Vec<ThreadState>,
+    callsites: Vec<Metadata>,
+    next_span_id: u64,
+    records: Vec<TraceRecord>,
+}
+
+/// The state of a thread of a [`RecordingBuilder`].
+#[derive(Debug)]
+struct ThreadState {
+    thread_id: String,
+    thread_name: String,
+    /// The time on the thread, since the start of the recording.
+    now: Duration,
+    /// The spans which are entered, innermost last.
+    entered: Vec<SpanId>,
+}
+
+impl Default for RecordingBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RecordingBuilder {
+    /// Creates a builder of an empty recording.
+    ///
+    /// The recording starts at a fixed time, so that recordings are the same every time they
+    /// are built.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            start: Duration::from_secs(1_715_126_400),
+            step: Duration::from_micros(1),
+            target: DEFAULT_TARGET.to_owned(),
+            threads: Vec::new(),
+            callsites: Vec::new(),
+            next_span_id: 1,
+            records: Vec::new(),
+        }
+    }
+
+    /// Sets the time that the recording starts at, as a duration since the UNIX epoch.
+    #[must_use]
+    pub fn with_start(mut self, start: Duration) -> Self {
+        self.start = start;
+        self
+    }
+
+    /// Sets how far the clock of a thread moves forward with each record, the default is 1µs.
+    #[must_use]
+    pub fn with_step(mut self, step: Duration) -> Self {
+        self.step = step;
+        self
+    }
+
+    /// Sets the target of the spans and events which aren't given one, the default is
+    /// `synthetic`.
+    #[must_use]
+    pub fn with_target(mut self, target: impl Into<String>) -> Self {
+        self.target = target.into();
+        self
+    }
+
+    /// Adds records to the thread called `name`, with `f`.
+    ///
+    /// The first time a thread name is used, a new thread is added to the recording with the
+    /// next thread Id, such as `ThreadId(1)`, and its clock starts at the start of the recording.
+    /// Using the same name again continues the thread where it left off.
+    pub fn thread<F>(&mut self, name: &str, f: F) -> &mut Self
+    where
+        F: FnOnce(&mut ThreadBuilder<'_>),
+    {
+        let thread = match self
+            .threads
+            .iter()
+            .position(|thread| thread.thread_name == name)
+        {
+            Some(thread) => thread,
+            None => {
+                self.threads.push(ThreadState {
+                    thread_id: format!("ThreadId({})", self.threads.len() + 1),
+                    thread_name: name.to_owned(),
+                    now: Duration::ZERO,
+                    entered: Vec::new(),
+                });
+                self.threads.len() - 1
+            }
+        };
+        f(&mut ThreadBuilder {
+            recording: self,
+            thread,
+        });
+
+        self
+    }
+
+    /// Returns the records of the recording, in timestamp order.
+    #[must_use]
+    pub fn build(self) -> Vec<TraceRecord> {
+        let mut records = self.records;
+        // The sort is stable, so records at the same time stay in the order they were added.
+        records.sort_by_key(|record| record.meta.timestamp());
+
+        let mut registered = HashSet::new();
+        let mut built = Vec::with_capacity(records.len() + self.callsites.len());
+        for record in records {
+            let metadata = match &record.trace {
+                Trace::NewSpan(new_span) => Some(&new_span.metadata),
+                Trace::Event(event) => Some(&event.metadata),
+                _ => None,
+            };
+            if let Some(metadata) = metadata.filter(|metadata| registered.insert(metadata.id)) {
+                built.push(TraceRecord {
+                    meta: record.meta.clone(),
+                    trace: Trace::RegisterCallsite(metadata.clone()),
+                    final_fields: None,
+                    sequence: None,
+                });
+            }
+            built.push(record);
+        }
+
+        built
+    }
+
+    /// Writes the recording in the current version of the format, starting with the header.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to `writer` fails.
+    pub fn write<W: Write>(self, writer: W) -> io::Result<()> {
+        crate::write_recording(writer, &self.build())
+    }
+
+    /// Returns the metadata of the callsite, adding it if it is new.
+    fn callsite(
+        &mut self,
+        kind: Kind,
+        name: String,
+        target: Option<String>,
+        level: Level,
+        fields: Vec<String>,
+    ) -> Metadata {
+        let mut metadata = Metadata {
+            id: 0,
+            name,
+            target: target.unwrap_or_else(|| self.target.clone()),
+            level,
+            module_path: None,
+            file: None,
+            line: None,
+            fields,
+            kind,
+        };
+        let existing = self.callsites.iter().find(|callsite| {
+            callsite.kind == metadata.kind
+                && callsite.name == metadata.name
+                && callsite.target == metadata.target
+                && callsite.level == metadata.level
+                && callsite.fields == metadata.fields
+        });
+        match existing {
+            Some(callsite) => callsite.clone(),
+            None => {
+                metadata.id = self.callsites.len() as u64 + 1;
+                self.callsites.push(metadata.clone());
+                metadata
+            }
+        }
+    }
+}
+
+/// Adds records to a thread of a [`RecordingBuilder`].
+#[derive(Debug)]
+pub struct ThreadBuilder<'a> {
+    recording: &'a mut RecordingBuilder,
+    thread: usize,
+}
+
+impl ThreadBuilder<'_> {
+    /// Returns the time on this thread, since the start of the recording.
+    #[must_use]
+    pub fn now(&self) -> Duration {
+        self.state().now
+    }
+
+    /// Moves the clock of this thread forward by `duration`.
+    pub fn advance(&mut self, duration: Duration) -> &mut Self {
+        self.state_mut().now += duration;
+        self
+    }
+
+    /// Adds a span, with the contents added by `f`, and returns its Id.
+    ///
+    /// The span is created and entered, then `f` adds the spans and events within it, and then
+    /// the span is exited and closed. If the span has a duration, the clock is moved forward
+    /// before it is exited so that it lasts at least that long.
+    pub fn span<F>(&mut self, span: SpanBuilder, f: F) -> SpanId
+    where
+        F: FnOnce(&mut ThreadBuilder<'_>),
+    {
+        let id = SpanId::from(self.recording.next_span_id);
+        self.recording.next_span_id += 1;
+        let field_names = span
+            .fields
+            .iter()
+            .map(|field| field.name.clone())
+            .chain(span.empty_fields)
+            .collect();
+        let metadata =
+            self.recording
+                .callsite(Kind::Span, span.name, span.target, span.level, field_names);
+        self.push(Trace::NewSpan(NewSpan {
+            id,
+            fields: span.fields,
+            metadata,
+            parent: span.parent.map_or(Parent::Current, Parent::Explicit),
+            trace_context: span.trace_context,
+        }));
+        for cause_id in span.follows_from {
+            self.push(Trace::FollowsFrom(FollowsFrom {
+                cause_id,
+                effect_id: id,
+            }));
+        }
+
+        let entered_at = self.now();
+        self.push(Trace::Enter(id));
+        self.state_mut().entered.push(id);
+        f(self);
+        if let Some(duration) = span.duration {
+            let state = self.state_mut();
+            state.now = state.now.max(entered_at + duration);
+        }
+        self.state_mut().entered.pop();
+        self.push(Trace::Exit(id));
+        self.push(Trace::Close(id));
+
+        id
+    }
+
+    /// Adds an event, within the span which is entered.
+    pub fn event(&mut self, event: EventBuilder) -> &mut Self {
+        let mut fields = vec![Field {
+            name: "message".to_owned(),
+            value: FieldValue::Debug(event.message),
+        }];
+        fields.extend(event.fields);
+        let metadata = self.recording.callsite(
+            Kind::Event,
+            "event".to_owned(),
+            event.target,
+            event.level,
+            fields.iter().map(|field| field.name.clone()).collect(),
+        );
+        self.push(Trace::Event(Event {
+            fields,
+            metadata,
+            parent: event.parent.map_or(Parent::Current, Parent::Explicit),
+        }));
+        self
+    }
+
+    /// Records a value for the span which is entered.
+    ///
+    /// As with `tracing`, the field must be one of the fields of the span, which can be declared
+    /// without a value with [`SpanBuilder::with_empty_field`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if no span is entered on this thread.
+    pub fn record(&mut self, name: impl Into<String>, value: impl Into<FieldValue>) -> &mut Self {
+        let id = *self
+            .state()
+            .entered
+            .last()
+            .expect("values can only be recorded within a span");
+        self.push(Trace::Record(RecordValues {
+            id,
+            fields: vec![Field {
+                name: name.into(),
+                value: value.into(),
+            }],
+        }));
+        self
+    }
+
+    fn state(&self) -> &ThreadState {
+        &self.recording.threads[self.thread]
+    }
+
+    fn state_mut(&mut self) -> &mut ThreadState {
+        &mut self.recording.threads[self.thread]
+    }
+
+    /// Adds a record at the current time, and moves the clock forward by the step.
+    fn push(&mut self, trace: Trace) {
+        let step = self.recording.step;
+        let ts = self.recording.start + self.now();
+        let state = self.state_mut();
+        let meta = RecordMeta {
+            timestamp_s: ts.as_secs(),
+            timestamp_subsec_us: ts.subsec_micros(),
+            thread_id: state.thread_id.clone(),
+            thread_name: Some(state.thread_name.clone()),
+        };
+        state.now += step;
+        self.recording.records.push(TraceRecord {
+            meta,
+            trace,
+            final_fields: None,
+            sequence: None,
+        });
+    }
+}
+
+/// Describes a span to add with [`ThreadBuilder::span`].
+#[derive(Clone, Debug)]
+pub struct SpanBuilder {
+    name: String,
+    target: Option<String>,
+    level: Level,
+    fields: Vec<Field>,
+    /// The fields which are declared without a value.
+    empty_fields: Vec<String>,
+    duration: Option<Duration>,
+    parent: Option<SpanId>,
+    follows_from: Vec<SpanId>,
+    trace_context: Option<TraceContext>,
+}
+
+impl SpanBuilder {
+    /// Creates a span called `name`, at the `INFO` level.
+    #[must_use]
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            target: None,
+            level: Level::Info,
+            fields: Vec::new(),
+            empty_fields: Vec::new(),
+            duration: None,
+            parent: None,
+            follows_from: Vec::new(),
+            trace_context: None,
+        }
+    }
+
+    /// Sets the target of the span, the default is the target of the recording.
+    #[must_use]
+    pub fn with_target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    /// Sets the level of the span.
+    #[must_use]
+    pub fn with_level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Adds a field that the span is created with.
+    #[must_use]
+    pub fn with_field(mut self, name: impl Into<String>, value: impl Into<FieldValue>) -> Self {
+        self.fields.push(Field {
+            name: name.into(),
+            value: value.into(),
+        });
+        self
+    }
+
+    /// Declares a field of the span without a value, so that a value can be recorded for it
+    /// later with [`ThreadBuilder::record`].
+    #[must_use]
+    pub fn with_empty_field(mut self, name: impl Into<String>) -> Self {
+        self.empty_fields.push(name.into());
+        self
+    }
+
+    /// Sets how long the span is entered for, at least.
+    #[must_use]
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// Sets an explicit parent for the span, such as a span on another thread. By default, the
+    /// parent is the span which is entered.
+    #[must_use]
+    pub fn with_parent(mut self, parent: SpanId) -> Self {
+        self.parent = Some(parent);
+        self
+    }
+
+    /// Marks the span as following from `cause`.
+    #[must_use]
+    pub fn with_follows_from(mut self, cause: SpanId) -> Self {
+        self.follows_from.push(cause);
+        self
+    }
+
+    /// Sets the distributed trace context that the span was recorded with.
+    #[must_use]
+    pub fn with_trace_context(mut self, trace_context: TraceContext) -> Self {
+        self.trace_context = Some(trace_context);
+        self
+    }
+}
+
+/// Describes an event to add with [`ThreadBuilder::event`].
+#[derive(Clone, Debug)]
+pub struct EventBuilder {
+    message: String,
+    target: Option<String>,
+    level: Level,
+    fields: Vec<Field>,
+    parent: Option<SpanId>,
+}
+
+impl EventBuilder {
+    /// Creates an event with `message`, at the `INFO` level.
+    #[must_use]
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            target: None,
+            level: Level::Info,
+            fields: Vec::new(),
+            parent: None,
+        }
+    }
+
+    /// Sets the target of the event, the default is the target of the recording.
+    #[must_use]
+    pub fn with_target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    /// Sets the level of the event.
+    #[must_use]
+    pub fn with_level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Adds a field to the event, after the message.
+    #[must_use]
+    pub fn with_field(mut self, name: impl Into<String>, value: impl Into<FieldValue>) -> Self {
+        self.fields.push(Field {
+            name: name.into(),
+            value: value.into(),
+        });
+        self
+    }
+
+    /// Sets an explicit parent for the event. By default, the parent is the span which is
+    /// entered.
+    #[must_use]
+    pub fn with_parent(mut self, parent: SpanId) -> Self {
+        self.parent = Some(parent);
+        self
+    }
+}
@@ -0,0 +1,267 @@
+//! Export of recordings to the Chrome trace event format.
+
+use std::{
+    io::{self, BufRead, Write},
+    time::Duration,
+};
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    export::{self, Timeline, TimelineEvent},
+    ExportError, Field, FieldValue,
+};
+
+/// The process Id given to all trace events, a recording only ever contains a single process.
+const PID: u32 = 1;
+
+/// How the time that spans are entered is written in a Chrome trace.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ChromeSpanPhase {
+    /// A begin (`B`) event is written when a span is entered and an end (`E`) event when it is
+    /// exited.
+    #[default]
+    BeginEnd,
+    /// A single complete (`X`) event is written when a span is exited, with the duration that
+    /// the span was entered for.
+    Complete,
+}
+
+/// Exports the recording read from `reader` as a Chrome trace, which is written to `writer`.
+///
+/// The trace is written in Chrome's [trace event format], which can be opened in
+/// `chrome://tracing` or the [Perfetto UI]. Each recorded thread is shown in its own lane,
+/// named after the recorded thread. The time that spans are entered is shown as slices in the
+/// lane of the thread they were entered on, written as described by `span_phase`. Events are
+/// shown as instant events. The fields of spans and events are included as their arguments.
+///
+/// Timestamps are given relative to the first record in the recording. Spans which are still
+/// entered at the end of the recording are ended at the time of the last record.
+///
+/// # Errors
+///
+/// Returns an error if reading the recording or writing the trace fails, if a line of the
+/// recording can't be deserialized into a record, or if the recording was written in a newer
+/// version of the format.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_cassette::ChromeSpanPhase;
+///
+/// let recording = concat!(
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":1,"fields":[{"name":"answer","value":{"I64":42}}],"metadata":{"id":4403349456,"name":"span","target":"record_spans","level":"Info","module_path":"record_spans","file":"tracing-rec/examples/record-spans.rs","line":8,"fields":["answer"],"kind":"Span"},"parent":"Root"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543410,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543425,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Exit":1}}"#,
+///     "\n",
+/// );
+///
+/// let mut trace = Vec::new();
+/// tracing_cassette::to_chrome_trace(recording.as_bytes(), &mut trace, ChromeSpanPhase::Complete)
+///     .unwrap();
+///
+/// let trace: serde_json::Value = serde_json::from_slice(&trace).unwrap();
+/// let slice = &trace["traceEvents"][1];
+/// assert_eq!(slice["ph"], "X");
+/// assert_eq!(slice["name"], "span");
+/// assert_eq!(slice["ts"], 10);
+/// assert_eq!(slice["dur"], 15);
+/// assert_eq!(slice["args"]["answer"], 42);
+/// ```
+///
+/// [trace event format]: https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU
+/// [Perfetto UI]: https://ui.perfetto.dev
+pub fn to_chrome_trace<R, W>(
+    reader: R,
+    mut writer: W,
+    span_phase: ChromeSpanPhase,
+) -> Result<(), ExportError>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut timeline = Timeline::default();
+    let mut exporter = ChromeExporter {
+        span_phase,
+        written: false,
+    };
+
+    writer.write_all(b"{\"traceEvents\":[")?;
+    export::for_each_record(reader, |record| {
+        let events = timeline.push(record);
+        exporter.write_events(&mut writer, timeline.start(), events)
+    })?;
+    let events = timeline.finish();
+    exporter.write_events(&mut writer, timeline.start(), events)?;
+    writer.write_all(b"\n]}\n")?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// A single event in a Chrome trace.
+#[derive(Debug, Serialize)]
+struct TraceEvent {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cat: Option<String>,
+    ph: &'static str,
+    /// Microseconds since the start of the recording.
+    ts: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<u64>,
+    pid: u32,
+    tid: u64,
+    /// The scope of an instant event.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    s: Option<&'static str>,
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    args: Map<String, Value>,
+}
+
+/// Writes timeline events as trace events.
+#[derive(Debug)]
+struct ChromeExporter {
+    span_phase: ChromeSpanPhase,
+    /// Whether an event has been written yet, as events are separated by commas.
+    written: bool,
+}
+
+impl ChromeExporter {
+    fn write_events<W: Write>(
+        &mut self,
+        writer: &mut W,
+        start: Duration,
+        events: Vec<TimelineEvent>,
+    ) -> Result<(), ExportError> {
+        for event in events {
+            if let Some(event) = self.trace_event(start, event) {
+                if self.written {
+                    writer.write_all(b",")?;
+                }
+                writer.write_all(b"\n")?;
+                serde_json::to_writer(&mut *writer, &event).map_err(io::Error::from)?;
+                self.written = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Converts a timeline event into a trace event, timestamps are relative to `start`.
+    fn trace_event(&self, start: Duration, event: TimelineEvent) -> Option<TraceEvent> {
+        let micros =
+            |ts: Duration| u64::try_from(ts.saturating_sub(start).as_micros()).unwrap_or(u64::MAX);
+        let event = match event {
+            TimelineEvent::Thread {
+                lane,
+                thread_id,
+                thread_name,
+            } => {
+                let name = match thread_name {
+                    Some(name) => format!("{name} ({thread_id})"),
+                    None => thread_id,
+                };
+                let mut args = Map::new();
+                args.insert("name".into(), Value::String(name));
+                TraceEvent {
+                    name: "thread_name".into(),
+                    cat: None,
+                    ph: "M",
+                    ts: 0,
+                    dur: None,
+                    pid: PID,
+                    tid: lane,
+                    s: None,
+                    args,
+                }
+            }
+            TimelineEvent::SliceBegin {
+                lane, ts, slice, ..
+            } => {
+                if self.span_phase == ChromeSpanPhase::Complete {
+                    return None;
+                }
+                TraceEvent {
+                    name: slice.name,
+                    cat: Some(slice.target),
+                    ph: "B",
+                    ts: micros(ts),
+                    dur: None,
+                    pid: PID,
+                    tid: lane,
+                    s: None,
+                    args: field_args(slice.fields),
+                }
+            }
+            TimelineEvent::SliceEnd {
+                lane,
+                ts,
+                start,
+                slice,
+                ..
+            } => match self.span_phase {
+                ChromeSpanPhase::BeginEnd => TraceEvent {
+                    name: slice.name,
+                    cat: Some(slice.target),
+                    ph: "E",
+                    ts: micros(ts),
+                    dur: None,
+                    pid: PID,
+                    tid: lane,
+                    s: None,
+                    args: Map::new(),
+                },
+                ChromeSpanPhase::Complete => TraceEvent {
+                    name: slice.name,
+                    cat: Some(slice.target),
+                    ph: "X",
+                    ts: micros(start),
+                    dur: Some(micros(ts) - micros(start)),
+                    pid: PID,
+                    tid: lane,
+                    s: None,
+                    args: field_args(slice.fields),
+                },
+            },
+            TimelineEvent::Instant { lane, ts, slice } => TraceEvent {
+                name: slice.name,
+                cat: Some(slice.target),
+                ph: "i",
+                ts: micros(ts),
+                dur: None,
+                pid: PID,
+                tid: lane,
+                s: Some("t"),
+                args: field_args(slice.fields),
+            },
+        };
+
+        Some(event)
+    }
+}
+
+/// Converts recorded fields into the arguments of a trace event.
+fn field_args(fields: Vec<Field>) -> Map<String, Value> {
+    fields
+        .into_iter()
+        .map(|field| {
+            let value = match field.value {
+                FieldValue::Debug(value) | FieldValue::Str(value) => Value::String(value),
+                FieldValue::F64(value) => Value::from(value),
+                FieldValue::I64(value) => Value::from(value),
+                FieldValue::U64(value) => Value::from(value),
+                FieldValue::I128(value) => i64::try_from(value)
+                    .map_or_else(|_| Value::String(value.to_string()), Value::from),
+                FieldValue::U128(value) => u64::try_from(value)
+                    .map_or_else(|_| Value::String(value.to_string()), Value::from),
+                FieldValue::Bool(value) => Value::Bool(value),
+                FieldValue::Json(value) => value,
+            };
+            (field.name, value)
+        })
+        .collect()
+}
@@ -0,0 +1,177 @@
+//! Concatenating recordings one after the other.
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use crate::{Metadata, Parent, SpanId, Trace, TraceRecord};
+
+/// Concatenates recordings into one, each starting `gap` after the end of the one before.
+///
+/// This glues together the segments of a recording which was split up, such as when the file it
+/// was written to was rotated, or separate sessions of a program, so that they can be replayed
+/// or viewed as one. The timestamps of each recording after the first are shifted so that its
+/// earliest record is `gap` after the latest record of the recording before it. The records of
+/// each recording stay in the same order, and keep their times relative to each other.
+///
+/// Threads keep their Ids, so a thread which appears in more than one recording continues where
+/// it left off. Ids are only changed where they collide:
+///
+/// - A callsite keeps its Id unless a callsite with different metadata already has it, then
+///   it is given a new Id for the rest of its recording.
+/// - A span keeps its Id unless it is created while a span with that Id is open, then it is
+///   given a new Id. Spans which are still open at the end of one recording can be entered,
+///   exited, recorded, and closed in the next one, as when a recording is split up.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use tracing_cassette::{RecordingBuilder, SpanBuilder, Trace};
+///
+/// let mut first = RecordingBuilder::new();
+/// first.thread("main", |thread| {
+///     thread.span(SpanBuilder::new("first"), |_| {});
+/// });
+/// let mut second = RecordingBuilder::new().with_start(Duration::from_secs(1_800_000_000));
+/// second.thread("main", |thread| {
+///     thread.span(SpanBuilder::new("second"), |_| {});
+/// });
+/// let first = first.build();
+/// let second = second.build();
+/// let end = first.last().unwrap().meta.timestamp();
+///
+/// let concatenated =
+///     tracing_cassette::concat(vec![first, second], Duration::from_millis(10));
+/// assert_eq!(concatenated.len(), 10);
+/// // The second recording starts 10ms after the first one ends.
+/// assert_eq!(concatenated[5].meta.timestamp(), end + Duration::from_millis(10));
+/// // Both recordings gave their spans' callsites the same Id, the second one is given a new one.
+/// let Trace::NewSpan(first_span) = &concatenated[1].trace else { unreachable!() };
+/// let Trace::NewSpan(second_span) = &concatenated[6].trace else { unreachable!() };
+/// assert_eq!(second_span.metadata.name, "second");
+/// assert_ne!(second_span.metadata.id, first_span.metadata.id);
+/// ```
+#[must_use]
+pub fn concat(recordings: Vec<Vec<TraceRecord>>, gap: Duration) -> Vec<TraceRecord> {
+    let mut ids = ConcatIds::default();
+    let mut concatenated = Vec::new();
+    // The latest timestamp so far, after shifting.
+    let mut end: Option<Duration> = None;
+    for records in recordings {
+        let Some(start) = records.iter().map(|record| record.meta.timestamp()).min() else {
+            continue;
+        };
+        let to = end.map_or(start, |end| end + gap);
+
+        ids.callsites.clear();
+        for mut record in records {
+            let ts = record.meta.timestamp().saturating_sub(start) + to;
+            record.meta.timestamp_s = ts.as_secs();
+            record.meta.timestamp_subsec_us = ts.subsec_micros();
+            end = Some(end.map_or(ts, |end| end.max(ts)));
+
+            ids.remap(&mut record.trace);
+            concatenated.push(record);
+        }
+    }
+
+    concatenated
+}
+
+/// The Ids given to the callsites and spans of the concatenated recordings.
+#[derive(Debug, Default)]
+struct ConcatIds {
+    /// The metadata of each callsite in the concatenated recording, by Id.
+    registered: HashMap<u64, Metadata>,
+    /// The Ids given to the callsites of the current recording.
+    callsites: HashMap<u64, u64>,
+    /// The Ids given to spans, which carry over from one recording to the next.
+    spans: HashMap<SpanId, SpanId>,
+    /// The Ids of the spans which are open in the concatenated recording.
+    open: HashSet<SpanId>,
+    /// The highest span Id given out.
+    max_span_id: u64,
+}
+
+impl ConcatIds {
+    fn remap(&mut self, trace: &mut Trace) {
+        match trace {
+            Trace::RegisterCallsite(metadata) => self.remap_callsite(metadata),
+            Trace::NewSpan(new_span) => {
+                self.remap_callsite(&mut new_span.metadata);
+                self.remap_parent(&mut new_span.parent);
+                let id = if self.open.contains(&new_span.id) {
+                    self.max_span_id += 1;
+                    SpanId::from(self.max_span_id)
+                } else {
+                    new_span.id
+                };
+                self.max_span_id = self.max_span_id.max(u64::from(id));
+                self.open.insert(id);
+                self.spans.insert(new_span.id, id);
+                new_span.id = id;
+            }
+            Trace::Event(event) => {
+                self.remap_callsite(&mut event.metadata);
+                self.remap_parent(&mut event.parent);
+            }
+            Trace::Close(id) => {
+                *id = self.span(*id);
+                self.open.remove(id);
+            }
+            Trace::Enter(id) | Trace::Exit(id) => *id = self.span(*id),
+            Trace::Record(record_values) => record_values.id = self.span(record_values.id),
+            Trace::FollowsFrom(follows_from) => {
+                follows_from.cause_id = self.span(follows_from.cause_id);
+                follows_from.effect_id = self.span(follows_from.effect_id);
+            }
+        }
+    }
+
+    fn remap_callsite(&mut self, metadata: &mut Metadata) {
+        if let Some(id) = self.callsites.get(&metadata.id) {
+            metadata.id = *id;
+            return;
+        }
+
+        let original = metadata.id;
+        let collides = self
+            .registered
+            .get(&metadata.id)
+            .is_some_and(|registered| !same_callsite(registered, metadata));
+        if collides {
+            // Callsite Ids are usually addresses, so one past the highest is unlikely to be used.
+            metadata.id = self.registered.keys().max().map_or(1, |max| max + 1);
+        }
+        self.registered
+            .entry(metadata.id)
+            .or_insert_with(|| metadata.clone());
+        self.callsites.insert(original, metadata.id);
+    }
+
+    fn remap_parent(&mut self, parent: &mut Parent) {
+        if let Parent::Explicit(id) = parent {
+            *id = self.span(*id);
+        }
+    }
+
+    /// Returns the Id given to a span, spans which weren't created in any recording keep theirs.
+    fn span(&self, id: SpanId) -> SpanId {
+        self.spans.get(&id).copied().unwrap_or(id)
+    }
+}
+
+/// Returns whether two callsites are the same, apart from their Ids.
+fn same_callsite(left: &Metadata, right: &Metadata) -> bool {
+    left.name == right.name
+        && left.target == right.target
+        && left.level == right.level
+        && left.module_path == right.module_path
+        && left.file == right.file
+        && left.line == right.line
+        && left.fields == right.fields
+        && left.kind == right.kind
+}
@@ -0,0 +1,606 @@
+//! The container format, which stores the records of a recording in segments.
+//!
+//! All integers are little endian. A container starts with the [`CONTAINER_MAGIC`] bytes,
+//! followed by the version of the container format (`u32`) and the [`FORMAT_VERSION`] of the
+//! records (`u32`). From version 2 of the container format, these are followed by the length
+//! (`u32`) and the JSON object of the [`metadata`] of the header. Containers without metadata
+//! are written in version 1, so that they can be read by earlier versions of the crate. Then come
+//! any number of segments, each of which is:
+//!
+//! - the tag `S` (`u8`)
+//! - the compression of the segment (`u8`), 0 for none and 1 for deflate
+//! - the number of records in the segment (`u32`)
+//! - the length of the stored data (`u64`)
+//! - the length of the data once decompressed (`u64`)
+//! - the CRC-32 of the decompressed data (`u32`)
+//! - the stored data
+//!
+//! The decompressed data of a segment is the records, serialized as JSON lines, the same as in
+//! a recording without a container. The segments are followed by a footer, which is:
+//!
+//! - the tag `F` (`u8`)
+//! - the number of segments (`u64`)
+//! - the number of records (`u64`)
+//! - the CRC-32 of the decompressed data of all the segments, one after the other (`u32`)
+//!
+//! Nothing may come after the footer. A container which ends before the footer was not
+//! finished, such as when the program writing it crashed.
+//!
+//! [`metadata`]: structfield@Header::metadata
+
+use std::{
+    collections::BTreeMap,
+    error, fmt,
+    io::{self, BufRead, Read, Write},
+};
+
+use crate::{Header, TraceRecord, FORMAT_VERSION};
+
+/// The bytes that a container starts with.
+///
+/// The first byte isn't ASCII, so a container can't be mistaken for a recording of JSON lines,
+/// and the line endings catch containers which have been mangled by a transfer in text mode.
+pub const CONTAINER_MAGIC: [u8; 8] = *b"\x89CST\r\n\x1a\n";
+
+/// The version of the container format which is written by this version of the crate.
+pub const CONTAINER_VERSION: u32 = 2;
+
+/// The default number of records in each segment of a container.
+const DEFAULT_SEGMENT_RECORDS: u32 = 4096;
+
+const SEGMENT_TAG: u8 = b'S';
+const FOOTER_TAG: u8 = b'F';
+
+/// The compression of the segments of a container.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Compression {
+    /// The segments are stored as they are.
+    #[default]
+    None,
+    /// The segments are compressed with deflate.
+    Deflate,
+}
+
+impl Compression {
+    fn to_byte(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Deflate => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::None),
+            1 => Some(Self::Deflate),
+            _ => None,
+        }
+    }
+}
+
+/// A container couldn't be read.
+///
+/// These errors are returned by [`ContainerReader`] as [`io::Error`]s of the kind
+/// [`io::ErrorKind::InvalidData`], with the container error as the inner error.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ContainerError {
+    /// The container was written in a newer version of the container format than this version of
+    /// the crate knows about.
+    UnsupportedVersion {
+        /// The version the container was written in.
+        version: u32,
+    },
+    /// The metadata in the header of the container isn't a JSON object of strings.
+    CorruptHeader,
+    /// A block of the container starts with a tag which isn't a segment or the footer.
+    UnknownBlock {
+        /// The tag of the block.
+        tag: u8,
+    },
+    /// A segment is compressed in a way which this version of the crate doesn't know about.
+    UnsupportedCompression {
+        /// The index of the segment, starting from 0.
+        segment_index: u64,
+        /// The compression of the segment.
+        compression: u8,
+    },
+    /// The data of a segment doesn't match its length or checksum.
+    CorruptSegment {
+        /// The index of the segment, starting from 0.
+        segment_index: u64,
+    },
+    /// The counts or the checksum in the footer don't match the segments.
+    CorruptFooter,
+    /// The container ends before the footer.
+    Truncated,
+    /// There is more data after the footer.
+    TrailingData,
+}
+
+impl fmt::Display for ContainerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedVersion { version } => write!(
+                f,
+                "container too new: it was written in container version {version}, but the \
+                newest supported version is {CONTAINER_VERSION}"
+            ),
+            Self::CorruptHeader => f.write_str("the metadata in the header is corrupt"),
+            Self::UnknownBlock { tag } => write!(f, "unknown block with tag {tag:#04x}"),
+            Self::UnsupportedCompression {
+                segment_index,
+                compression,
+            } => write!(
+                f,
+                "segment {segment_index} has an unsupported compression: {compression}"
+            ),
+            Self::CorruptSegment { segment_index } => write!(
+                f,
+                "segment {segment_index} is corrupt: its data doesn't match its length or checksum"
+            ),
+            Self::CorruptFooter => f.write_str(
+                "the footer is corrupt: its counts or checksum don't match the segments",
+            ),
+            Self::Truncated => f.write_str("the container ends before the footer"),
+            Self::TrailingData => f.write_str("there is data after the footer"),
+        }
+    }
+}
+
+impl error::Error for ContainerError {}
+
+impl From<ContainerError> for io::Error {
+    fn from(value: ContainerError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, value)
+    }
+}
+
+/// Writes a recording as a container.
+///
+/// Records are collected into segments of [`with_segment_records`] records, each of which is
+/// written once it is full. The container must be completed with [`finish`], which writes the
+/// last segment and the footer, otherwise it is reported as truncated when it is read.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_cassette::{Compression, ContainerWriter, RecordReader, Trace, TraceRecord};
+///
+/// let record: TraceRecord = serde_json::from_str(
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543410,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+/// ).unwrap();
+///
+/// let mut writer = ContainerWriter::new(Vec::new())
+///     .with_compression(Compression::Deflate)
+///     .with_segment_records(2);
+/// for _ in 0..5 {
+///     writer.write_record(&record).unwrap();
+/// }
+/// let container = writer.finish().unwrap();
+///
+/// let records = RecordReader::new(container.as_slice())
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+/// assert_eq!(records.len(), 5);
+/// assert!(matches!(records[4].trace, Trace::Enter(_)));
+/// ```
+///
+/// [`with_segment_records`]: fn@Self::with_segment_records
+/// [`finish`]: fn@Self::finish
+#[derive(Debug)]
+pub struct ContainerWriter<W> {
+    writer: W,
+    compression: Compression,
+    segment_records: u32,
+    /// The metadata of the header, see [`Header::metadata`].
+    metadata: BTreeMap<String, String>,
+    /// Whether the magic bytes and the header have been written.
+    started: bool,
+    /// The records of the current segment, serialized as JSON lines.
+    segment: Vec<u8>,
+    segment_record_count: u32,
+    segment_count: u64,
+    record_count: u64,
+    checksum: crc32fast::Hasher,
+}
+
+impl<W: Write> ContainerWriter<W> {
+    /// Creates a writer of a container to `writer`, with uncompressed segments.
+    ///
+    /// Nothing is written until the first segment is full, or the container is finished.
+    #[must_use]
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            compression: Compression::None,
+            segment_records: DEFAULT_SEGMENT_RECORDS,
+            metadata: BTreeMap::new(),
+            started: false,
+            segment: Vec::new(),
+            segment_record_count: 0,
+            segment_count: 0,
+            record_count: 0,
+            checksum: crc32fast::Hasher::new(),
+        }
+    }
+
+    /// Sets the compression of the segments, the default is [`Compression::None`].
+    #[must_use]
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the number of records in each segment, the default is 4096.
+    ///
+    /// Larger segments compress better, but more records are lost if the container is never
+    /// finished. Segments have at least one record.
+    #[must_use]
+    pub fn with_segment_records(mut self, segment_records: u32) -> Self {
+        self.segment_records = segment_records.max(1);
+        self
+    }
+
+    /// Sets the metadata of the header of the recording, see [`Header::metadata`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::BTreeMap;
+    ///
+    /// use tracing_cassette::{ContainerWriter, RecordReader};
+    ///
+    /// let metadata = BTreeMap::from([("scenario".to_owned(), "checkout".to_owned())]);
+    /// let container = ContainerWriter::new(Vec::new())
+    ///     .with_metadata(metadata.clone())
+    ///     .finish()
+    ///     .unwrap();
+    ///
+    /// let mut reader = RecordReader::new(container.as_slice());
+    /// assert!(reader.next().is_none());
+    /// assert_eq!(reader.header().metadata, metadata);
+    /// ```
+    #[must_use]
+    pub fn with_metadata(mut self, metadata: BTreeMap<String, String>) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Writes a record, which is added to the current segment.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing a full segment to the underlying writer fails.
+    pub fn write_record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        serde_json::to_writer(&mut self.segment, record)?;
+        self.segment.push(b'\n');
+        self.segment_record_count += 1;
+        if self.segment_record_count >= self.segment_records {
+            self.write_segment()?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the last segment and the footer, and returns the underlying writer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the underlying writer fails.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_segment()?;
+        self.start()?;
+
+        self.writer.write_all(&[FOOTER_TAG])?;
+        self.writer.write_all(&self.segment_count.to_le_bytes())?;
+        self.writer.write_all(&self.record_count.to_le_bytes())?;
+        self.writer
+            .write_all(&self.checksum.clone().finalize().to_le_bytes())?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+
+    /// Writes the magic bytes and the header, if they haven't been written yet.
+    fn start(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.writer.write_all(&CONTAINER_MAGIC)?;
+            if self.metadata.is_empty() {
+                // Version 1 only differs by not having metadata, and earlier versions of the crate
+                // can read it.
+                self.writer.write_all(&1_u32.to_le_bytes())?;
+                self.writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+            } else {
+                let metadata = serde_json::to_vec(&self.metadata)?;
+                let len = u32::try_from(metadata.len()).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "the metadata is too long")
+                })?;
+                self.writer.write_all(&CONTAINER_VERSION.to_le_bytes())?;
+                self.writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+                self.writer.write_all(&len.to_le_bytes())?;
+                self.writer.write_all(&metadata)?;
+            }
+            self.started = true;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the current segment, if it has any records.
+    fn write_segment(&mut self) -> io::Result<()> {
+        if self.segment_record_count == 0 {
+            return Ok(());
+        }
+        self.start()?;
+
+        let checksum = crc32fast::hash(&self.segment);
+        self.checksum.update(&self.segment);
+        let compressed;
+        let stored = match self.compression {
+            Compression::None => &self.segment,
+            Compression::Deflate => {
+                let mut encoder =
+                    flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(&self.segment)?;
+                compressed = encoder.finish()?;
+                &compressed
+            }
+        };
+
+        self.writer
+            .write_all(&[SEGMENT_TAG, self.compression.to_byte()])?;
+        self.writer
+            .write_all(&self.segment_record_count.to_le_bytes())?;
+        self.writer
+            .write_all(&(stored.len() as u64).to_le_bytes())?;
+        self.writer
+            .write_all(&(self.segment.len() as u64).to_le_bytes())?;
+        self.writer.write_all(&checksum.to_le_bytes())?;
+        self.writer.write_all(stored)?;
+
+        self.segment_count += 1;
+        self.record_count += u64::from(self.segment_record_count);
+        self.segment.clear();
+        self.segment_record_count = 0;
+
+        Ok(())
+    }
+}
+
+/// Reads a recording which may be stored in a container, as JSON lines.
+///
+/// If the recording starts with the [`CONTAINER_MAGIC`] bytes, its segments are checked and
+/// decompressed, and read as a recording of JSON lines which starts with a [`Header`] for the
+/// version of the records. Otherwise, the recording is read as it is. Either way, the result can
+/// be read in the same way as any other recording.
+///
+/// [`RecordReader`] and the other readers in this crate read containers through this reader, so
+/// there is usually no need to use it directly.
+///
+/// Errors in the container are returned as [`io::Error`]s which wrap a [`ContainerError`].
+///
+/// [`RecordReader`]: struct@crate::RecordReader
+#[derive(Debug)]
+pub struct ContainerReader<R> {
+    reader: R,
+    state: State,
+    /// Data which has been decoded but not yet read.
+    buf: Vec<u8>,
+    pos: usize,
+    segment_count: u64,
+    record_count: u64,
+    checksum: crc32fast::Hasher,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum State {
+    /// Nothing has been read yet, so it isn't known whether there is a container.
+    Start,
+    /// The recording isn't in a container, it is read as it is.
+    JsonLines,
+    /// The segments of a container are being read.
+    Segments,
+    /// The footer of the container has been read.
+    Finished,
+}
+
+impl<R: BufRead> ContainerReader<R> {
+    /// Creates a reader of the recording in `reader`.
+    #[must_use]
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            state: State::Start,
+            buf: Vec::new(),
+            pos: 0,
+            segment_count: 0,
+            record_count: 0,
+            checksum: crc32fast::Hasher::new(),
+        }
+    }
+
+    /// Reads the start of the recording, to find out whether it is in a container.
+    fn read_start(&mut self) -> io::Result<()> {
+        let mut magic = Vec::with_capacity(CONTAINER_MAGIC.len());
+        (&mut self.reader)
+            .take(CONTAINER_MAGIC.len() as u64)
+            .read_to_end(&mut magic)?;
+        if magic != CONTAINER_MAGIC {
+            // The bytes which were read are the start of a recording of JSON lines.
+            self.buf = magic;
+            self.state = State::JsonLines;
+            return Ok(());
+        }
+
+        let version = read_u32(&mut self.reader)?;
+        if version > CONTAINER_VERSION {
+            return Err(ContainerError::UnsupportedVersion { version }.into());
+        }
+        let mut header = Header {
+            version: read_u32(&mut self.reader)?,
+            metadata: BTreeMap::new(),
+        };
+        if version >= 2 {
+            let len = read_u32(&mut self.reader)?;
+            let mut metadata = Vec::new();
+            read_all(
+                (&mut self.reader).take(u64::from(len)),
+                u64::from(len),
+                &mut metadata,
+            )?;
+            header.metadata =
+                serde_json::from_slice(&metadata).map_err(|_| ContainerError::CorruptHeader)?;
+        }
+        self.buf = format!("{}\n", header.to_line()).into_bytes();
+        self.state = State::Segments;
+
+        Ok(())
+    }
+
+    /// Reads the next block of the container, which is either a segment or the footer.
+    fn read_block(&mut self) -> io::Result<()> {
+        let tag = read_u8(&mut self.reader)?;
+        match tag {
+            SEGMENT_TAG => self.read_segment(),
+            FOOTER_TAG => self.read_footer(),
+            tag => Err(ContainerError::UnknownBlock { tag }.into()),
+        }
+    }
+
+    fn read_segment(&mut self) -> io::Result<()> {
+        let segment_index = self.segment_count;
+        let compression = read_u8(&mut self.reader)?;
+        let record_count = read_u32(&mut self.reader)?;
+        let stored_len = read_u64(&mut self.reader)?;
+        let len = read_u64(&mut self.reader)?;
+        let checksum = read_u32(&mut self.reader)?;
+
+        let stored = (&mut self.reader).take(stored_len);
+        self.buf.clear();
+        self.pos = 0;
+        match Compression::from_byte(compression) {
+            Some(Compression::None) => read_all(stored, stored_len, &mut self.buf)?,
+            Some(Compression::Deflate) => {
+                // The stored data is read in full first, so that a container which ends part way
+                // through a segment is reported as truncated rather than corrupt.
+                let mut compressed = Vec::new();
+                read_all(stored, stored_len, &mut compressed)?;
+                flate2::read::DeflateDecoder::new(compressed.as_slice())
+                    .read_to_end(&mut self.buf)
+                    .map_err(|_| ContainerError::CorruptSegment { segment_index })?;
+            }
+            None => {
+                return Err(ContainerError::UnsupportedCompression {
+                    segment_index,
+                    compression,
+                }
+                .into())
+            }
+        }
+        if self.buf.len() as u64 != len || crc32fast::hash(&self.buf) != checksum {
+            return Err(ContainerError::CorruptSegment { segment_index }.into());
+        }
+
+        self.checksum.update(&self.buf);
+        self.segment_count += 1;
+        self.record_count += u64::from(record_count);
+
+        Ok(())
+    }
+
+    fn read_footer(&mut self) -> io::Result<()> {
+        let segment_count = read_u64(&mut self.reader)?;
+        let record_count = read_u64(&mut self.reader)?;
+        let checksum = read_u32(&mut self.reader)?;
+        if segment_count != self.segment_count
+            || record_count != self.record_count
+            || checksum != self.checksum.clone().finalize()
+        {
+            return Err(ContainerError::CorruptFooter.into());
+        }
+        if !self.reader.fill_buf()?.is_empty() {
+            return Err(ContainerError::TrailingData.into());
+        }
+        self.state = State::Finished;
+
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for ContainerReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(out.len());
+        out[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for ContainerReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.state == State::Start {
+            self.read_start()?;
+        }
+        while self.pos == self.buf.len() && self.state == State::Segments {
+            self.read_block()?;
+        }
+
+        if self.pos < self.buf.len() {
+            Ok(&self.buf[self.pos..])
+        } else if self.state == State::JsonLines {
+            self.reader.fill_buf()
+        } else {
+            Ok(&[])
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if self.pos < self.buf.len() {
+            self.pos = (self.pos + amt).min(self.buf.len());
+        } else if self.state == State::JsonLines {
+            self.reader.consume(amt);
+        }
+    }
+}
+
+/// Reads exactly `len` bytes of stored data into `buf`.
+///
+/// The data is read without allocating `len` bytes up front, as a corrupt length could be huge.
+fn read_all<R: Read>(mut reader: R, len: u64, buf: &mut Vec<u8>) -> io::Result<()> {
+    let read = reader.read_to_end(buf)?;
+    if read as u64 == len {
+        Ok(())
+    } else {
+        Err(ContainerError::Truncated.into())
+    }
+}
+
+fn read_u8<R: Read>(reader: R) -> io::Result<u8> {
+    read_array(reader).map(u8::from_le_bytes)
+}
+
+fn read_u32<R: Read>(reader: R) -> io::Result<u32> {
+    read_array(reader).map(u32::from_le_bytes)
+}
+
+fn read_u64<R: Read>(reader: R) -> io::Result<u64> {
+    read_array(reader).map(u64::from_le_bytes)
+}
+
+/// Reads an array of bytes from a container, which ends part way through if there aren't enough.
+fn read_array<R: Read, const N: usize>(mut reader: R) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes).map_err(|err| {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            ContainerError::Truncated.into()
+        } else {
+            err
+        }
+    })?;
+
+    Ok(bytes)
+}
@@ -0,0 +1,112 @@
+//! Conversions between the recorded types and the types from `tracing-core`.
+
+use tracing_core::{span, Event};
+
+use crate::{CowStr, Kind, Level, MetadataRef, Parent, SpanId};
+
+impl From<&tracing_core::Level> for Level {
+    fn from(value: &tracing_core::Level) -> Self {
+        match *value {
+            tracing_core::Level::TRACE => Level::Trace,
+            tracing_core::Level::DEBUG => Level::Debug,
+            tracing_core::Level::INFO => Level::Info,
+            tracing_core::Level::WARN => Level::Warn,
+            tracing_core::Level::ERROR => Level::Error,
+        }
+    }
+}
+
+impl From<Level> for tracing_core::Level {
+    fn from(value: Level) -> Self {
+        match value {
+            Level::Trace => Self::TRACE,
+            Level::Debug => Self::DEBUG,
+            Level::Info => Self::INFO,
+            Level::Warn => Self::WARN,
+            Level::Error => Self::ERROR,
+        }
+    }
+}
+
+impl From<&tracing_core::Metadata<'_>> for Kind {
+    fn from(value: &tracing_core::Metadata<'_>) -> Self {
+        if value.is_event() {
+            Self::Event
+        } else {
+            debug_assert!(
+                value.is_span(),
+                "either is_event() or is_span() should be true",
+            );
+            Self::Span
+        }
+    }
+}
+
+impl From<Kind> for tracing_core::metadata::Kind {
+    fn from(value: Kind) -> Self {
+        match value {
+            Kind::Event => Self::EVENT,
+            Kind::Span => Self::SPAN,
+        }
+    }
+}
+
+/// Records the metadata of a callsite, borrowing its strings.
+///
+/// The address of the metadata identifies the callsite, as it doesn't change for the lifetime of
+/// the program.
+impl From<&'static tracing_core::Metadata<'static>> for MetadataRef<'static> {
+    fn from(value: &'static tracing_core::Metadata<'static>) -> Self {
+        Self {
+            id: std::ptr::from_ref(value) as u64,
+            name: value.name().into(),
+            target: value.target().into(),
+            level: value.level().into(),
+            module_path: value.module_path().map(CowStr::from),
+            file: value.file().map(CowStr::from),
+            line: value.line(),
+            fields: value.fields().iter().map(|f| f.name().into()).collect(),
+            kind: Kind::from(value),
+        }
+    }
+}
+
+impl From<&span::Id> for SpanId {
+    fn from(value: &span::Id) -> Self {
+        Self::from(value.into_u64())
+    }
+}
+
+impl From<&Event<'_>> for Parent {
+    fn from(value: &Event<'_>) -> Self {
+        if value.is_root() {
+            Self::Root
+        } else if value.is_contextual() {
+            Self::Current
+        } else {
+            Self::Explicit(
+                value
+                    .parent()
+                    .expect("a span that isn't root or contextual should have an explicit Id")
+                    .into(),
+            )
+        }
+    }
+}
+
+impl From<&span::Attributes<'_>> for Parent {
+    fn from(value: &span::Attributes<'_>) -> Self {
+        if value.is_root() {
+            Self::Root
+        } else if value.is_contextual() {
+            Self::Current
+        } else {
+            Self::Explicit(
+                value
+                    .parent()
+                    .expect("a span that isn't root or contextual should have an explicit Id")
+                    .into(),
+            )
+        }
+    }
+}
@@ -0,0 +1,590 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    pretty::{self, PrettyValue},
+    Field, FieldValue, Kind, Level, Metadata, Parent, SpanId, Trace, TraceRecord,
+};
+
+/// A difference between two recordings found by [`diff`].
+///
+/// Spans and events are identified by their path, which is made up of the spans that they are
+/// within, outermost first, separated by ` > `. Spans are written as their target and name, such
+/// as `my_crate::request`, and events as their target and message, such as
+/// `my_crate: request handled`. When a span has more than one child with the same target
+/// and name, or message, the later ones have their index among them appended, such as
+/// `my_crate::query[1]`.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Difference {
+    /// A callsite is only in the right recording.
+    CallsiteAdded {
+        /// The metadata of the callsite.
+        callsite: Metadata,
+    },
+    /// A callsite is only in the left recording.
+    CallsiteRemoved {
+        /// The metadata of the callsite.
+        callsite: Metadata,
+    },
+    /// A callsite with the same kind, target, and name has different metadata, such as a
+    /// different level or fields.
+    ///
+    /// The callsite Ids aren't compared, as they are assigned when the program runs.
+    CallsiteChanged {
+        /// The metadata of the callsite in the left recording.
+        left: Metadata,
+        /// The metadata of the callsite in the right recording.
+        right: Metadata,
+    },
+    /// A span is only in the right recording.
+    SpanAdded {
+        /// The path of the span.
+        path: String,
+    },
+    /// A span is only in the left recording.
+    SpanRemoved {
+        /// The path of the span.
+        path: String,
+    },
+    /// An event is only in the right recording.
+    EventAdded {
+        /// The path of the event.
+        path: String,
+    },
+    /// An event is only in the left recording.
+    EventRemoved {
+        /// The path of the event.
+        path: String,
+    },
+    /// A span or event has a different level.
+    LevelChanged {
+        /// The path of the span or event.
+        path: String,
+        /// The level in the left recording.
+        left: Level,
+        /// The level in the right recording.
+        right: Level,
+    },
+    /// A field of a span or event has a different value, or is only recorded in one of the
+    /// recordings.
+    ///
+    /// The fields of spans are compared after all the values recorded for them have been applied.
+    FieldChanged {
+        /// The path of the span or event.
+        path: String,
+        /// The name of the field.
+        name: String,
+        /// The value in the left recording, if it was recorded.
+        left: Option<FieldValue>,
+        /// The value in the right recording, if it was recorded.
+        right: Option<FieldValue>,
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CallsiteAdded { callsite } => {
+                write!(f, "callsite added: {}", CallsiteName(callsite))
+            }
+            Self::CallsiteRemoved { callsite } => {
+                write!(f, "callsite removed: {}", CallsiteName(callsite))
+            }
+            Self::CallsiteChanged { left, right } => {
+                write!(f, "callsite changed: {}:", CallsiteName(left))?;
+                if left.level != right.level {
+                    write!(
+                        f,
+                        " level {} -> {}",
+                        pretty::level(&left.level),
+                        pretty::level(&right.level),
+                    )?;
+                }
+                if left.module_path != right.module_path {
+                    write!(
+                        f,
+                        " module_path {:?} -> {:?}",
+                        left.module_path, right.module_path,
+                    )?;
+                }
+                if left.file != right.file || left.line != right.line {
+                    write!(f, " location {} -> {}", Location(left), Location(right))?;
+                }
+                if left.fields != right.fields {
+                    write!(f, " fields {:?} -> {:?}", left.fields, right.fields)?;
+                }
+                Ok(())
+            }
+            Self::SpanAdded { path } => write!(f, "span added: {path}"),
+            Self::SpanRemoved { path } => write!(f, "span removed: {path}"),
+            Self::EventAdded { path } => write!(f, "event added: {path}"),
+            Self::EventRemoved { path } => write!(f, "event removed: {path}"),
+            Self::LevelChanged { path, left, right } => write!(
+                f,
+                "level changed: {path}: {} -> {}",
+                pretty::level(left),
+                pretty::level(right),
+            ),
+            Self::FieldChanged {
+                path,
+                name,
+                left,
+                right,
+            } => write!(
+                f,
+                "field changed: {path}: {name}: {} -> {}",
+                OptionalValue(left.as_ref()),
+                OptionalValue(right.as_ref()),
+            ),
+        }
+    }
+}
+
+struct CallsiteName<'a>(&'a Metadata);
+
+impl fmt::Display for CallsiteName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.0.kind {
+            Kind::Span => "span",
+            Kind::Event => "event",
+        };
+        write!(f, "{kind} {}::{}", self.0.target, self.0.name)
+    }
+}
+
+struct Location<'a>(&'a Metadata);
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.0.file, self.0.line) {
+            (Some(file), Some(line)) => write!(f, "{file}:{line}"),
+            (Some(file), None) => write!(f, "{file}"),
+            (None, _) => write!(f, "<unknown>"),
+        }
+    }
+}
+
+struct OptionalValue<'a>(Option<&'a FieldValue>);
+
+impl fmt::Display for OptionalValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(value) => PrettyValue {
+                value,
+                quote_strings: true,
+            }
+            .fmt(f),
+            None => f.write_str("<none>"),
+        }
+    }
+}
+
+/// Compares the structure of two recordings.
+///
+/// The recordings are compared without their timestamps and the Ids that were assigned when
+/// they were recorded, so two recordings of the same program doing the same work are the same,
+/// even though they were recorded at different times. This makes it possible to check the traces
+/// of a program against a recording that is known to be good.
+///
+/// Callsites are matched by their kind, target, and name, and then their level, location,
+/// and field names are compared. Spans and events are arranged in a tree by their parents, with
+/// contextual parents resolved to the span that was entered on the recorded thread. The spans and
+/// events with the same parent are matched by their target and name, or message for events, in
+/// the order that they were recorded, and then their levels and field values are compared.
+/// Matching then continues with the children of each matching span. The recorded threads aren't
+/// compared, nor are the times that spans are entered and exited.
+///
+/// The differences are returned in the order of the left recording, followed by what is only in
+/// the right recording. Recordings in any supported version of the format can be read for
+/// comparison with [`RecordReader`].
+///
+/// # Examples
+///
+/// ```
+/// use tracing_cassette::{Difference, FieldValue, RecordReader, TraceRecord};
+///
+/// let left = concat!(
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":1,"fields":[{"name":"answer","value":{"I64":42}}],"metadata":{"id":4403349456,"name":"span","target":"record_spans","level":"Info","module_path":"record_spans","file":"tracing-rec/examples/record-spans.rs","line":8,"fields":["answer"],"kind":"Span"},"parent":"Root"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543410,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543420,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":4403349608,"name":"event","target":"record_spans","level":"Info","module_path":null,"file":null,"line":null,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#,
+///     "\n",
+/// );
+/// // The same recording, at a different time, with different Ids, and a different answer.
+/// let right = concat!(
+///     r#"{"meta":{"timestamp_s":1715180000,"timestamp_subsec_us":100,"thread_id":"ThreadId(7)","thread_name":"main"},"trace":{"NewSpan":{"id":9,"fields":[{"name":"answer","value":{"I64":43}}],"metadata":{"id":5503349456,"name":"span","target":"record_spans","level":"Info","module_path":"record_spans","file":"tracing-rec/examples/record-spans.rs","line":8,"fields":["answer"],"kind":"Span"},"parent":"Root"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715180000,"timestamp_subsec_us":200,"thread_id":"ThreadId(7)","thread_name":"main"},"trace":{"Enter":9}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715180000,"timestamp_subsec_us":300,"thread_id":"ThreadId(7)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":5503349608,"name":"event","target":"record_spans","level":"Info","module_path":null,"file":null,"line":null,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#,
+///     "\n",
+/// );
+///
+/// let read = |recording: &str| -> Vec<TraceRecord> {
+///     RecordReader::new(recording.as_bytes()).collect::<Result<_, _>>().unwrap()
+/// };
+/// let differences = tracing_cassette::diff(&read(left), &read(right));
+/// assert_eq!(
+///     differences,
+///     vec![Difference::FieldChanged {
+///         path: "record_spans::span".into(),
+///         name: "answer".into(),
+///         left: Some(FieldValue::I64(42)),
+///         right: Some(FieldValue::I64(43)),
+///     }],
+/// );
+/// assert_eq!(
+///     differences[0].to_string(),
+///     "field changed: record_spans::span: answer: 42 -> 43",
+/// );
+/// ```
+///
+/// [`RecordReader`]: struct@crate::RecordReader
+#[must_use]
+pub fn diff(left: &[TraceRecord], right: &[TraceRecord]) -> Vec<Difference> {
+    let left = Tree::new(left);
+    let right = Tree::new(right);
+
+    let mut differences = Vec::new();
+    diff_callsites(&left.callsites, &right.callsites, &mut differences);
+    diff_children(
+        &left,
+        &left.roots,
+        &right,
+        &right.roots,
+        "",
+        &mut differences,
+    );
+    differences
+}
+
+/// A span or event in a recording.
+#[derive(Debug)]
+struct Node {
+    kind: Kind,
+    level: Level,
+    /// The target and name of a span, or the target and message of an event.
+    label: String,
+    fields: Vec<Field>,
+    children: Vec<usize>,
+}
+
+/// The spans and events of a recording, arranged by their parents.
+#[derive(Debug, Default)]
+struct Tree {
+    callsites: Vec<Metadata>,
+    nodes: Vec<Node>,
+    roots: Vec<usize>,
+}
+
+impl Tree {
+    fn new(records: &[TraceRecord]) -> Self {
+        let mut tree = Self::default();
+        let mut callsite_ids = Vec::new();
+        // The node of each span Id, span Ids are reused once spans close, so this is the latest.
+        let mut spans: HashMap<SpanId, usize> = HashMap::new();
+        let mut entered: HashMap<&str, Vec<SpanId>> = HashMap::new();
+
+        for record in records {
+            let thread_id = record.meta.thread_id.as_str();
+            let mut add_callsite = |metadata: &Metadata| {
+                if !callsite_ids.contains(&metadata.id) {
+                    callsite_ids.push(metadata.id);
+                    tree.callsites.push(metadata.clone());
+                }
+            };
+            let resolve_parent = |parent: &Parent| match parent {
+                Parent::Root => None,
+                Parent::Current => entered
+                    .get(thread_id)
+                    .and_then(|stack| stack.last())
+                    .and_then(|id| spans.get(id).copied()),
+                Parent::Explicit(id) => spans.get(id).copied(),
+            };
+
+            let (node, parent) = match &record.trace {
+                Trace::RegisterCallsite(metadata) => {
+                    add_callsite(metadata);
+                    continue;
+                }
+                Trace::NewSpan(new_span) => {
+                    add_callsite(&new_span.metadata);
+                    let metadata = &new_span.metadata;
+                    let node = Node {
+                        kind: Kind::Span,
+                        level: metadata.level.clone(),
+                        label: format!("{}::{}", metadata.target, metadata.name),
+                        fields: new_span.fields.clone(),
+                        children: Vec::new(),
+                    };
+                    let parent = resolve_parent(&new_span.parent);
+                    spans.insert(new_span.id, tree.nodes.len());
+                    (node, parent)
+                }
+                Trace::Event(event) => {
+                    add_callsite(&event.metadata);
+                    let metadata = &event.metadata;
+                    let message = event
+                        .fields
+                        .iter()
+                        .find(|field| field.name == "message")
+                        .map(|field| {
+                            PrettyValue {
+                                value: &field.value,
+                                quote_strings: false,
+                            }
+                            .to_string()
+                        });
+                    let label = match message {
+                        Some(message) => format!("{}: {message}", metadata.target),
+                        None => format!("{}::{}", metadata.target, metadata.name),
+                    };
+                    let node = Node {
+                        kind: Kind::Event,
+                        level: metadata.level.clone(),
+                        label,
+                        fields: event
+                            .fields
+                            .iter()
+                            .filter(|field| field.name != "message")
+                            .cloned()
+                            .collect(),
+                        children: Vec::new(),
+                    };
+                    (node, resolve_parent(&event.parent))
+                }
+                Trace::Record(record_values) => {
+                    if let Some(&idx) = spans.get(&record_values.id) {
+                        let fields = &mut tree.nodes[idx].fields;
+                        for field in &record_values.fields {
+                            match fields.iter_mut().find(|f| f.name == field.name) {
+                                Some(existing) => existing.value = field.value.clone(),
+                                None => fields.push(field.clone()),
+                            }
+                        }
+                    }
+                    continue;
+                }
+                Trace::Enter(id) => {
+                    entered.entry(thread_id).or_default().push(*id);
+                    continue;
+                }
+                Trace::Exit(id) => {
+                    let stack = entered.entry(thread_id).or_default();
+                    if let Some(idx) = stack.iter().rposition(|entered| entered == id) {
+                        stack.remove(idx);
+                    }
+                    continue;
+                }
+                Trace::Close(_) | Trace::FollowsFrom(_) => continue,
+            };
+
+            let idx = tree.nodes.len();
+            tree.nodes.push(node);
+            match parent {
+                Some(parent) => tree.nodes[parent].children.push(idx),
+                None => tree.roots.push(idx),
+            }
+        }
+
+        tree
+    }
+}
+
+/// Items paired up by [`match_by_key`].
+#[derive(Debug)]
+struct Matches {
+    /// For each item on the left, its index among the items with the same key, and the index of
+    /// the matching item on the right, if there is one.
+    left: Vec<(usize, Option<usize>)>,
+    /// For each item which is only on the right, its index among the items with the same key,
+    /// and its index.
+    right_only: Vec<(usize, usize)>,
+}
+
+/// Pairs up items with the same key, in the order that they appear.
+fn match_by_key<K, T>(left: &[T], right: &[T], key: impl Fn(&T) -> K) -> Matches
+where
+    K: Eq + std::hash::Hash,
+{
+    let mut right_by_key: HashMap<K, Vec<usize>> = HashMap::new();
+    for (idx, item) in right.iter().enumerate() {
+        right_by_key.entry(key(item)).or_default().push(idx);
+    }
+
+    let mut occurrences: HashMap<K, usize> = HashMap::new();
+    let mut matched = vec![false; right.len()];
+    let left = left
+        .iter()
+        .map(|item| {
+            let key = key(item);
+            let right_idx = right_by_key
+                .get(&key)
+                .and_then(|indices| indices.get(*occurrences.get(&key).unwrap_or(&0)))
+                .copied();
+            let occurrence = occurrences.entry(key).or_default();
+            let result = (*occurrence, right_idx);
+            *occurrence += 1;
+            if let Some(right_idx) = right_idx {
+                matched[right_idx] = true;
+            }
+            result
+        })
+        .collect();
+
+    // The occurrences of the items which are only on the right.
+    let mut occurrences: HashMap<K, usize> = HashMap::new();
+    let right_only = right
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, item)| {
+            let occurrence = occurrences.entry(key(item)).or_default();
+            let result = (*occurrence, idx);
+            *occurrence += 1;
+            (!matched[idx]).then_some(result)
+        })
+        .collect();
+
+    Matches { left, right_only }
+}
+
+fn diff_callsites(left: &[Metadata], right: &[Metadata], differences: &mut Vec<Difference>) {
+    let key = |metadata: &Metadata| {
+        (
+            metadata.kind.clone(),
+            metadata.target.clone(),
+            metadata.name.clone(),
+        )
+    };
+    let matches = match_by_key(left, right, key);
+
+    for (left_callsite, (_, right_idx)) in left.iter().zip(matches.left) {
+        match right_idx {
+            Some(right_idx) => {
+                let right_callsite = &right[right_idx];
+                if left_callsite.level != right_callsite.level
+                    || left_callsite.module_path != right_callsite.module_path
+                    || left_callsite.file != right_callsite.file
+                    || left_callsite.line != right_callsite.line
+                    || left_callsite.fields != right_callsite.fields
+                {
+                    differences.push(Difference::CallsiteChanged {
+                        left: left_callsite.clone(),
+                        right: right_callsite.clone(),
+                    });
+                }
+            }
+            None => differences.push(Difference::CallsiteRemoved {
+                callsite: left_callsite.clone(),
+            }),
+        }
+    }
+    for (_, right_idx) in matches.right_only {
+        differences.push(Difference::CallsiteAdded {
+            callsite: right[right_idx].clone(),
+        });
+    }
+}
+
+fn diff_children(
+    left: &Tree,
+    left_children: &[usize],
+    right: &Tree,
+    right_children: &[usize],
+    parent_path: &str,
+    differences: &mut Vec<Difference>,
+) {
+    let left_nodes: Vec<&Node> = left_children.iter().map(|&idx| &left.nodes[idx]).collect();
+    let right_nodes: Vec<&Node> = right_children
+        .iter()
+        .map(|&idx| &right.nodes[idx])
+        .collect();
+    let matches = match_by_key(&left_nodes, &right_nodes, |node| {
+        (node.kind.clone(), node.label.clone())
+    });
+
+    for ((&left_idx, left_node), (occurrence, right_idx)) in
+        left_children.iter().zip(&left_nodes).zip(matches.left)
+    {
+        let path = path(parent_path, left_node, occurrence);
+        let Some(right_idx) = right_idx else {
+            differences.push(removed(left_node, path));
+            continue;
+        };
+        let right_node = right_nodes[right_idx];
+
+        if left_node.level != right_node.level {
+            differences.push(Difference::LevelChanged {
+                path: path.clone(),
+                left: left_node.level.clone(),
+                right: right_node.level.clone(),
+            });
+        }
+        diff_fields(&left_node.fields, &right_node.fields, &path, differences);
+        diff_children(
+            left,
+            &left.nodes[left_idx].children,
+            right,
+            &right.nodes[right_children[right_idx]].children,
+            &path,
+            differences,
+        );
+    }
+    for (occurrence, right_idx) in matches.right_only {
+        let right_node = right_nodes[right_idx];
+        let path = path(parent_path, right_node, occurrence);
+        differences.push(match right_node.kind {
+            Kind::Span => Difference::SpanAdded { path },
+            Kind::Event => Difference::EventAdded { path },
+        });
+    }
+}
+
+fn removed(node: &Node, path: String) -> Difference {
+    match node.kind {
+        Kind::Span => Difference::SpanRemoved { path },
+        Kind::Event => Difference::EventRemoved { path },
+    }
+}
+
+fn path(parent_path: &str, node: &Node, occurrence: usize) -> String {
+    let mut path = String::from(parent_path);
+    if !path.is_empty() {
+        path.push_str(" > ");
+    }
+    path.push_str(&node.label);
+    if occurrence > 0 {
+        path.push_str(&format!("[{occurrence}]"));
+    }
+    path
+}
+
+fn diff_fields(left: &[Field], right: &[Field], path: &str, differences: &mut Vec<Difference>) {
+    let changed = |name: &str, left: Option<&FieldValue>, right: Option<&FieldValue>| {
+        Difference::FieldChanged {
+            path: path.to_owned(),
+            name: name.to_owned(),
+            left: left.cloned(),
+            right: right.cloned(),
+        }
+    };
+    for left_field in left {
+        let right_value = right
+            .iter()
+            .find(|field| field.name == left_field.name)
+            .map(|field| &field.value);
+        if right_value != Some(&left_field.value) {
+            differences.push(changed(
+                &left_field.name,
+                Some(&left_field.value),
+                right_value,
+            ));
+        }
+    }
+    for right_field in right {
+        if !left.iter().any(|field| field.name == right_field.name) {
+            differences.push(changed(&right_field.name, None, Some(&right_field.value)));
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+use crate::{export, ExportError, Header, Trace, TraceRecord};
+
+/// Makes recordings smaller by dropping some of their events, while keeping their structure.
+///
+/// Every record other than an event is kept: the callsites, and the whole lifecycle of each
+/// span, so the downsampled recording has the same trace tree and can still be replayed. Of the
+/// events, the fraction given to [`new`] is dropped.
+///
+/// By default, each event is dropped at random, so callsites with few events may lose all of
+/// them. With [`with_stratified`], events are instead sampled per callsite: the first event of
+/// each callsite is kept, and then every event which brings the callsite up to its share of the
+/// events kept, so each callsite keeps the same fraction of its events, and at least one.
+///
+/// Downsampling is deterministic, the same recording is downsampled the same way each time,
+/// unless the seed is changed with [`with_seed`].
+///
+/// # Examples
+///
+/// ```
+/// use tracing_cassette::{Downsampler, EventBuilder, Level, RecordingBuilder, SpanBuilder, Trace};
+///
+/// let mut recording = RecordingBuilder::new();
+/// recording.thread("main", |thread| {
+///     thread.span(SpanBuilder::new("request"), |thread| {
+///         for _ in 0..8 {
+///             thread.event(EventBuilder::new("frequent"));
+///         }
+///         thread.event(EventBuilder::new("rare").with_level(Level::Warn));
+///     });
+/// });
+///
+/// let mut downsampler = Downsampler::new(0.75).with_stratified(true);
+/// let records: Vec<_> = recording
+///     .build()
+///     .into_iter()
+///     .filter(|record| downsampler.keep(record))
+///     .collect();
+///
+/// let events = records
+///     .iter()
+///     .filter(|record| matches!(record.trace, Trace::Event(_)))
+///     .count();
+/// // A quarter of the frequent events are kept, as is the only rare one.
+/// assert_eq!(events, 3);
+/// // The three callsites are kept, as the span is created, entered, exited, and closed.
+/// assert_eq!(records.len(), 3 + 4 + events);
+/// ```
+///
+/// [`new`]: fn@Self::new
+/// [`with_stratified`]: fn@Self::with_stratified
+/// [`with_seed`]: fn@Self::with_seed
+#[derive(Debug)]
+pub struct Downsampler {
+    /// The fraction of events which are kept.
+    keep_fraction: f64,
+    stratified: bool,
+    /// The state of the random number generator, which starts at the seed.
+    state: u64,
+    /// The number of events seen and kept of each callsite, by callsite Id, when sampling per
+    /// callsite.
+    callsites: HashMap<u64, (u64, u64)>,
+}
+
+impl Downsampler {
+    /// Creates a downsampler which drops `drop_fraction` of the events, from 0 for none of them
+    /// to 1 for all of them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `drop_fraction` isn't between 0 and 1.
+    #[must_use]
+    pub fn new(drop_fraction: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&drop_fraction),
+            "the fraction of events to drop must be between 0 and 1, not {drop_fraction}"
+        );
+        Self {
+            keep_fraction: 1.0 - drop_fraction,
+            stratified: false,
+            state: 0,
+            callsites: HashMap::new(),
+        }
+    }
+
+    /// Sets whether events are sampled per callsite, the default is `false`.
+    #[must_use]
+    pub fn with_stratified(mut self, stratified: bool) -> Self {
+        self.stratified = stratified;
+        self
+    }
+
+    /// Sets the seed of the random choice of events to drop, the default is 0.
+    ///
+    /// The seed isn't used when events are sampled per callsite.
+    #[must_use]
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.state = seed;
+        self
+    }
+
+    /// Returns whether a record is kept, which is always the case for records other than events.
+    pub fn keep(&mut self, record: &TraceRecord) -> bool {
+        let Trace::Event(event) = &record.trace else {
+            return true;
+        };
+
+        if self.stratified {
+            let (seen, kept) = self.callsites.entry(event.metadata.id).or_default();
+            *seen += 1;
+            // The first event of a callsite is always kept.
+            #[allow(
+                clippy::cast_possible_truncation,
+                clippy::cast_precision_loss,
+                clippy::cast_sign_loss
+            )]
+            let share = ((*seen as f64 * self.keep_fraction).ceil() as u64).max(1);
+            let keep = *kept < share;
+            if keep {
+                *kept += 1;
+            }
+            keep
+        } else {
+            self.next_fraction() < self.keep_fraction
+        }
+    }
+
+    /// Downsamples the recording read from `reader`, which is written to `writer` in the current
+    /// version of the format.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the recording or writing the downsampled recording fails, if
+    /// a line of the recording can't be deserialized into a record, or if the recording was
+    /// written in a newer version of the format.
+    pub fn downsample_recording<R, W>(
+        &mut self,
+        reader: R,
+        mut writer: W,
+    ) -> Result<(), ExportError>
+    where
+        R: BufRead,
+        W: Write,
+    {
+        writeln!(writer, "{}", Header::new().to_line())?;
+        export::for_each_record(reader, |record| {
+            if self.keep(&record) {
+                serde_json::to_writer(&mut writer, &record).map_err(io::Error::from)?;
+                writer.write_all(b"\n")?;
+            }
+            Ok(())
+        })?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Returns a random number from 0 up to, but not including, 1.
+    fn next_fraction(&mut self) -> f64 {
+        // SplitMix64.
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+
+        // The top 53 bits fit in the mantissa of an `f64` exactly.
+        #[allow(clippy::cast_precision_loss)]
+        {
+            (z >> 11) as f64 / (1_u64 << 53) as f64
+        }
+    }
+}
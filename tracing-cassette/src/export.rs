@@ -0,0 +1,289 @@
+//! The parts of exporting recordings to other formats which are shared between the formats.
+
+use std::{
+    collections::HashMap,
+    error, fmt,
+    io::{self, BufRead},
+    time::Duration,
+};
+
+use crate::{Field, FieldValue, FormatVersionError, RecordReader, SpanId, Trace, TraceRecord};
+
+/// An error reading a recording or exporting it to another format.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ExportError {
+    /// Reading the recording or writing the export failed.
+    Io(io::Error),
+    /// A line of the recording couldn't be deserialized into a record.
+    InvalidRecord {
+        /// The index of the line, starting from 0.
+        line_index: usize,
+        /// The error from deserializing the line.
+        inner: serde_json::Error,
+    },
+    /// The recording was written in a version of the format which can't be read.
+    Version(FormatVersionError),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "i/o error: {err}"),
+            Self::InvalidRecord { line_index, inner } => {
+                write!(f, "invalid record on line {line_index}: {inner}")
+            }
+            Self::Version(err) => err.fmt(f),
+        }
+    }
+}
+
+impl error::Error for ExportError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::InvalidRecord { inner, .. } => Some(inner),
+            Self::Version(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for ExportError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// Reads the records of the recording in `reader`, passing each one to `f`.
+pub(crate) fn for_each_record<R, F>(reader: R, mut f: F) -> Result<(), ExportError>
+where
+    R: BufRead,
+    F: FnMut(TraceRecord) -> Result<(), ExportError>,
+{
+    for record in RecordReader::new(reader) {
+        f(record?)?;
+    }
+
+    Ok(())
+}
+
+/// Something to be shown on the timeline of a recording.
+///
+/// Timestamps are durations since the UNIX epoch.
+#[derive(Debug)]
+pub(crate) enum TimelineEvent {
+    /// A recorded thread was seen for the first time and has been given a lane.
+    Thread {
+        lane: u64,
+        thread_id: String,
+        thread_name: Option<String>,
+    },
+    /// A span was entered.
+    SliceBegin {
+        lane: u64,
+        id: SpanId,
+        ts: Duration,
+        slice: Slice,
+    },
+    /// A span was exited, after being entered at `start`.
+    SliceEnd {
+        lane: u64,
+        id: SpanId,
+        ts: Duration,
+        start: Duration,
+        slice: Slice,
+    },
+    /// An event was recorded.
+    Instant {
+        lane: u64,
+        ts: Duration,
+        slice: Slice,
+    },
+}
+
+/// The description of a span or an event on the timeline.
+#[derive(Clone, Debug)]
+pub(crate) struct Slice {
+    /// The name of the span, or the message of the event.
+    pub(crate) name: String,
+    pub(crate) target: String,
+    pub(crate) file: Option<String>,
+    pub(crate) line: Option<u32>,
+    /// The fields, without the message of an event.
+    pub(crate) fields: Vec<Field>,
+}
+
+/// A span which has been entered on a lane.
+#[derive(Debug)]
+struct EnteredSpan {
+    id: SpanId,
+    start: Duration,
+    /// The span as it was when it was entered, in case it is closed while it is entered.
+    slice: Slice,
+}
+
+/// Turns the records of a recording into the events on its timeline.
+///
+/// Each recorded thread is given its own lane, numbered from 1 in the order that the threads
+/// first appear in the recording. The time that spans are entered is shown as slices on the lane
+/// of the thread they were entered on.
+#[derive(Debug, Default)]
+pub(crate) struct Timeline {
+    /// The timestamp of the first record.
+    start: Option<Duration>,
+    /// The timestamp of the latest record.
+    latest: Duration,
+    /// The lane of each recorded thread, by recorded thread Id.
+    lanes: HashMap<String, u64>,
+    open_spans: HashMap<SpanId, Slice>,
+    /// The spans entered on each lane, innermost last.
+    entered: HashMap<u64, Vec<EnteredSpan>>,
+}
+
+impl Timeline {
+    /// The timestamp of the first record, or zero if there haven't been any.
+    pub(crate) fn start(&self) -> Duration {
+        self.start.unwrap_or_default()
+    }
+
+    /// Returns the timeline events for `record`.
+    pub(crate) fn push(&mut self, record: TraceRecord) -> Vec<TimelineEvent> {
+        let ts = record.meta.timestamp();
+        self.start.get_or_insert(ts);
+        self.latest = self.latest.max(ts);
+
+        let mut events = Vec::new();
+        let next_lane = self.lanes.len() as u64 + 1;
+        let lane = *self
+            .lanes
+            .entry(record.meta.thread_id.clone())
+            .or_insert_with(|| {
+                events.push(TimelineEvent::Thread {
+                    lane: next_lane,
+                    thread_id: record.meta.thread_id,
+                    thread_name: record.meta.thread_name,
+                });
+                next_lane
+            });
+
+        match record.trace {
+            Trace::RegisterCallsite(_) | Trace::FollowsFrom(_) => {}
+            Trace::Event(event) => {
+                let mut fields = event.fields;
+                let name = match fields.iter().position(|field| field.name == "message") {
+                    Some(idx) => value_to_string(fields.remove(idx).value),
+                    None => event.metadata.name,
+                };
+                events.push(TimelineEvent::Instant {
+                    lane,
+                    ts,
+                    slice: Slice {
+                        name,
+                        target: event.metadata.target,
+                        file: event.metadata.file,
+                        line: event.metadata.line,
+                        fields,
+                    },
+                });
+            }
+            Trace::NewSpan(new_span) => {
+                self.open_spans.insert(
+                    new_span.id,
+                    Slice {
+                        name: new_span.metadata.name,
+                        target: new_span.metadata.target,
+                        file: new_span.metadata.file,
+                        line: new_span.metadata.line,
+                        fields: new_span.fields,
+                    },
+                );
+            }
+            Trace::Record(record_values) => {
+                if let Some(slice) = self.open_spans.get_mut(&record_values.id) {
+                    for field in record_values.fields {
+                        match slice.fields.iter_mut().find(|f| f.name == field.name) {
+                            Some(existing) => existing.value = field.value,
+                            None => slice.fields.push(field),
+                        }
+                    }
+                }
+            }
+            Trace::Enter(id) => {
+                // Spans which were created before the recording started can't be shown, as
+                // their names aren't known.
+                if let Some(slice) = self.open_spans.get(&id) {
+                    events.push(TimelineEvent::SliceBegin {
+                        lane,
+                        id,
+                        ts,
+                        slice: slice.clone(),
+                    });
+                    self.entered.entry(lane).or_default().push(EnteredSpan {
+                        id,
+                        start: ts,
+                        slice: slice.clone(),
+                    });
+                }
+            }
+            Trace::Exit(id) => {
+                let entered = self.entered.entry(lane).or_default();
+                if let Some(idx) = entered.iter().rposition(|entered| entered.id == id) {
+                    let entered = entered.remove(idx);
+                    events.push(self.end_slice(lane, ts, entered));
+                }
+            }
+            Trace::Close(id) => {
+                self.open_spans.remove(&id);
+            }
+        }
+
+        events
+    }
+
+    /// Returns the timeline events which end the spans that are still entered, at the time of
+    /// the last record.
+    pub(crate) fn finish(&mut self) -> Vec<TimelineEvent> {
+        let mut entered: Vec<_> = self.entered.drain().collect();
+        entered.sort_by_key(|(lane, _)| *lane);
+
+        let mut events = Vec::new();
+        for (lane, spans) in entered {
+            for span in spans.into_iter().rev() {
+                events.push(self.end_slice(lane, self.latest, span));
+            }
+        }
+
+        events
+    }
+
+    fn end_slice(&self, lane: u64, ts: Duration, entered: EnteredSpan) -> TimelineEvent {
+        // Use the latest fields if the span is still open, as they may have been recorded while
+        // the span was entered.
+        let slice = self
+            .open_spans
+            .get(&entered.id)
+            .cloned()
+            .unwrap_or(entered.slice);
+        TimelineEvent::SliceEnd {
+            lane,
+            id: entered.id,
+            ts,
+            start: entered.start,
+            slice,
+        }
+    }
+}
+
+/// Formats a field value as it would be displayed, without saying what type it is.
+pub(crate) fn value_to_string(value: FieldValue) -> String {
+    match value {
+        FieldValue::Debug(value) | FieldValue::Str(value) => value,
+        FieldValue::F64(value) => value.to_string(),
+        FieldValue::I64(value) => value.to_string(),
+        FieldValue::U64(value) => value.to_string(),
+        FieldValue::I128(value) => value.to_string(),
+        FieldValue::U128(value) => value.to_string(),
+        FieldValue::Bool(value) => value.to_string(),
+        FieldValue::Json(value) => value.to_string(),
+    }
+}
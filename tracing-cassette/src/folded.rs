@@ -0,0 +1,173 @@
+//! Export of recordings as folded stacks, for rendering flamegraphs.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{BufRead, Write},
+    time::Duration,
+};
+
+use crate::{
+    export::{self, Slice, Timeline, TimelineEvent},
+    ExportError, SpanId,
+};
+
+/// Exports the recording read from `reader` as folded stacks, which are written to `writer`.
+///
+/// Each line of the output is a stack of spans, outermost first and separated by semicolons,
+/// followed by the busy time of the innermost span in that stack in microseconds. The busy time
+/// is the time that the span was entered for, without the time that its children were entered
+/// for. Stacks which are entered more than once are written on a single line, with the busy
+/// times added together. This is the format which [`inferno`] and the original `flamegraph.pl`
+/// read, so a flamegraph of a recording can be rendered with:
+///
+/// ```sh
+/// inferno-flamegraph < recording.folded > recording.svg
+/// ```
+///
+/// Spans are named by their target and name, such as `my_crate::request`. Stacks are built per
+/// recorded thread, but the threads aren't included in the stacks, so the same stack entered on
+/// different threads is written as a single line. Events aren't included, as they don't take up
+/// any time.
+///
+/// Spans which are still entered at the end of the recording are ended at the time of the last
+/// record.
+///
+/// # Errors
+///
+/// Returns an error if reading the recording or writing the stacks fails, if a line of the
+/// recording can't be deserialized into a record, or if the recording was written in a newer
+/// version of the format.
+///
+/// # Examples
+///
+/// ```
+/// let recording = concat!(
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":1,"fields":[],"metadata":{"id":4403349456,"name":"outer","target":"app","level":"Info","module_path":"app","file":"src/main.rs","line":8,"fields":[],"kind":"Span"},"parent":"Root"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543410,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":2,"fields":[],"metadata":{"id":4403349608,"name":"inner","target":"app","level":"Info","module_path":"app","file":"src/main.rs","line":9,"fields":[],"kind":"Span"},"parent":"Current"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543500,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543600,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":2}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543900,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Exit":2}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":544000,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Exit":1}}"#,
+///     "\n",
+/// );
+///
+/// let mut folded = Vec::new();
+/// tracing_cassette::to_folded_stacks(recording.as_bytes(), &mut folded).unwrap();
+///
+/// assert_eq!(
+///     String::from_utf8(folded).unwrap(),
+///     "app::outer 200\napp::outer;app::inner 300\n",
+/// );
+/// ```
+///
+/// [`inferno`]: https://github.com/jonhoo/inferno
+pub fn to_folded_stacks<R, W>(reader: R, mut writer: W) -> Result<(), ExportError>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut timeline = Timeline::default();
+    let mut stacks = FoldedStacks::default();
+
+    export::for_each_record(reader, |record| {
+        for event in timeline.push(record) {
+            stacks.push(event);
+        }
+        Ok(())
+    })?;
+    for event in timeline.finish() {
+        stacks.push(event);
+    }
+
+    for (stack, busy) in stacks.busy {
+        let micros = u64::try_from(busy.as_micros()).unwrap_or(u64::MAX);
+        // Stacks which were never busy for a whole microsecond would be dropped by most tools
+        // anyway.
+        if micros > 0 {
+            writeln!(writer, "{stack} {micros}")?;
+        }
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// A span which has been entered on a lane.
+#[derive(Debug)]
+struct EnteredFrame {
+    id: SpanId,
+    frame: String,
+    /// The time that the children of the span were entered for while it was entered.
+    children: Duration,
+}
+
+/// Adds up the busy time of each stack of spans.
+#[derive(Debug, Default)]
+struct FoldedStacks {
+    /// The spans entered on each lane, innermost last.
+    entered: HashMap<u64, Vec<EnteredFrame>>,
+    /// The busy time of each stack, sorted so that the output doesn't depend on hashing.
+    busy: BTreeMap<String, Duration>,
+}
+
+impl FoldedStacks {
+    fn push(&mut self, event: TimelineEvent) {
+        match event {
+            TimelineEvent::SliceBegin {
+                lane, id, slice, ..
+            } => {
+                self.entered.entry(lane).or_default().push(EnteredFrame {
+                    id,
+                    frame: frame(&slice),
+                    children: Duration::ZERO,
+                });
+            }
+            TimelineEvent::SliceEnd {
+                lane,
+                id,
+                ts,
+                start,
+                ..
+            } => {
+                let entered = self.entered.entry(lane).or_default();
+                // The timeline only ends slices which it has begun, in the same way as spans
+                // are exited here, so the span is always found.
+                let Some(idx) = entered.iter().rposition(|frame| frame.id == id) else {
+                    return;
+                };
+                let duration = ts.saturating_sub(start);
+                let stack = entered[..=idx]
+                    .iter()
+                    .map(|frame| frame.frame.as_str())
+                    .collect::<Vec<_>>()
+                    .join(";");
+                let frame = entered.remove(idx);
+                if let Some(parent) = idx.checked_sub(1) {
+                    entered[parent].children += duration;
+                }
+                *self.busy.entry(stack).or_default() += duration.saturating_sub(frame.children);
+            }
+            TimelineEvent::Thread { .. } | TimelineEvent::Instant { .. } => {}
+        }
+    }
+}
+
+/// Returns the name of the frame for a span.
+///
+/// Semicolons separate frames and newlines separate stacks, so neither can be used within a
+/// frame.
+fn frame(slice: &Slice) -> String {
+    format!("{}::{}", slice.target, slice.name)
+        .chars()
+        .map(|c| match c {
+            ';' => ':',
+            '\n' | '\r' => ' ',
+            c => c,
+        })
+        .collect()
+}
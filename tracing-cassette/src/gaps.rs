@@ -0,0 +1,462 @@
+//! Finding the parts of a recording which are missing records.
+
+use std::{collections::HashMap, fmt, io::BufRead, time::Duration};
+
+use crate::{
+    export::{self, ExportError},
+    Field, FieldValue, Trace,
+};
+
+/// Finds the gaps in a recording, where records are missing, so that the parts of a replay
+/// which can't be trusted are known.
+///
+/// A recording doesn't say when records are missing from it, such as when a writer fell behind
+/// and dropped records, or the program was suspended. The gaps are found from records which the
+/// recorded program writes for this purpose, each of which has to be enabled:
+///
+/// - [`with_heartbeat`]: events with a message which are recorded at a regular interval. A gap is
+///   found where a heartbeat on a thread is more than twice the interval after the last one, or
+///   where the recording goes on for more than twice the interval after the last heartbeat.
+/// - [`with_sequence_field`]: a field of spans and events which numbers them, counting up by one
+///   on each thread. A gap is found where a number is skipped.
+/// - [`with_record_sequence`]: the [`sequence`] numbers of records, which count up by one across
+///   the whole recording. A gap is found where a number is skipped.
+/// - [`with_dropped_field`]: a field of events which marks that records were dropped, with the
+///   number of records. A gap is found before each marker on its thread.
+/// - [`with_max_silence`]: a gap is found wherever no records at all were recorded for longer
+///   than a duration.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use tracing_cassette::{EventBuilder, GapDetection, GapKind, RecordingBuilder};
+///
+/// let mut recording = RecordingBuilder::new();
+/// recording.thread("main", |thread| {
+///     for seq in [1_u64, 2, 5, 6] {
+///         thread.event(EventBuilder::new("request").with_field("seq", seq));
+///     }
+///     thread.event(EventBuilder::new("writer fell behind").with_field("dropped", 12_u64));
+/// });
+/// let mut written = Vec::new();
+/// recording.write(&mut written).unwrap();
+///
+/// let report = GapDetection::new()
+///     .with_sequence_field("seq")
+///     .with_dropped_field("dropped")
+///     .detect(written.as_slice())
+///     .unwrap();
+///
+/// let kinds: Vec<_> = report.gaps.iter().map(|gap| &gap.kind).collect();
+/// assert_eq!(
+///     kinds,
+///     [
+///         &GapKind::SequenceJump { expected: 3, found: 5 },
+///         &GapKind::DroppedRecords { count: 12 },
+///     ],
+/// );
+/// let jump = &report.gaps[0];
+/// assert_eq!(jump.thread_id.as_deref(), Some("ThreadId(1)"));
+/// assert!(report.affects("ThreadId(1)", jump.end));
+/// assert_eq!(report.watermark(), Some(jump.start));
+/// ```
+///
+/// [`with_heartbeat`]: fn@Self::with_heartbeat
+/// [`with_sequence_field`]: fn@Self::with_sequence_field
+/// [`with_record_sequence`]: fn@Self::with_record_sequence
+/// [`sequence`]: structfield@crate::TraceRecord::sequence
+/// [`with_dropped_field`]: fn@Self::with_dropped_field
+/// [`with_max_silence`]: fn@Self::with_max_silence
+#[derive(Clone, Debug, Default)]
+pub struct GapDetection {
+    heartbeat: Option<(String, Duration)>,
+    sequence_field: Option<String>,
+    record_sequence: bool,
+    dropped_field: Option<String>,
+    max_silence: Option<Duration>,
+}
+
+impl GapDetection {
+    /// Creates a detection which finds nothing until the records it uses are enabled.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Finds gaps from heartbeat events, which have `message` and are recorded every
+    /// `interval`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero.
+    #[must_use]
+    pub fn with_heartbeat(mut self, message: impl Into<String>, interval: Duration) -> Self {
+        assert!(
+            !interval.is_zero(),
+            "the heartbeat interval must be greater than zero"
+        );
+        self.heartbeat = Some((message.into(), interval));
+        self
+    }
+
+    /// Finds gaps from sequence numbers, the unsigned integer values of the field `name`.
+    #[must_use]
+    pub fn with_sequence_field(mut self, name: impl Into<String>) -> Self {
+        self.sequence_field = Some(name.into());
+        self
+    }
+
+    /// Finds gaps from the sequence numbers of records, see [`TraceRecord::sequence`].
+    ///
+    /// A skipped number means that records are missing, but not from which thread, so these
+    /// gaps affect all threads.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tracing_cassette::{GapDetection, GapKind};
+    ///
+    /// let recording = [
+    ///     r#"{"meta":{"timestamp_s":1,"timestamp_subsec_us":0,"thread_id":"ThreadId(1)","thread_name":null},"trace":{"Enter":1},"sequence":1}"#,
+    ///     r#"{"meta":{"timestamp_s":2,"timestamp_subsec_us":0,"thread_id":"ThreadId(2)","thread_name":null},"trace":{"Enter":2},"sequence":2}"#,
+    ///     r#"{"meta":{"timestamp_s":4,"timestamp_subsec_us":0,"thread_id":"ThreadId(1)","thread_name":null},"trace":{"Exit":1},"sequence":5}"#,
+    /// ]
+    /// .join("\n");
+    ///
+    /// let report = GapDetection::new()
+    ///     .with_record_sequence()
+    ///     .detect(recording.as_bytes())
+    ///     .unwrap();
+    ///
+    /// assert_eq!(report.gaps.len(), 1);
+    /// let gap = &report.gaps[0];
+    /// assert_eq!(gap.kind, GapKind::SequenceJump { expected: 3, found: 5 });
+    /// assert_eq!(gap.thread_id, None);
+    /// assert_eq!(gap.duration(), std::time::Duration::from_secs(2));
+    /// ```
+    ///
+    /// [`TraceRecord::sequence`]: structfield@crate::TraceRecord::sequence
+    #[must_use]
+    pub fn with_record_sequence(mut self) -> Self {
+        self.record_sequence = true;
+        self
+    }
+
+    /// Finds gaps from dropped record markers, events with the number of dropped records as the
+    /// value of the field `name`.
+    #[must_use]
+    pub fn with_dropped_field(mut self, name: impl Into<String>) -> Self {
+        self.dropped_field = Some(name.into());
+        self
+    }
+
+    /// Finds gaps wherever no records were recorded, on any thread, for longer than
+    /// `max_silence`.
+    #[must_use]
+    pub fn with_max_silence(mut self, max_silence: Duration) -> Self {
+        self.max_silence = Some(max_silence);
+        self
+    }
+
+    /// Reads a recording and finds its gaps.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the recording fails, if a line of the recording can't be
+    /// deserialized into a record, or if the recording was written in a newer version of the
+    /// format.
+    pub fn detect<R: BufRead>(&self, reader: R) -> Result<GapReport, ExportError> {
+        let mut detector = Detector {
+            detection: self,
+            report: GapReport::default(),
+            latest: None,
+            sequence: None,
+            threads: HashMap::new(),
+        };
+        export::for_each_record(reader, |record| {
+            let ts = record.meta.timestamp();
+            let fields = match &record.trace {
+                Trace::Event(event) => Some(&event.fields),
+                Trace::NewSpan(new_span) => Some(&new_span.fields),
+                _ => None,
+            };
+            let is_event = matches!(record.trace, Trace::Event(_));
+            detector.push_sequence(ts, record.sequence);
+            detector.push(ts, record.meta.thread_id, fields, is_event);
+            Ok(())
+        })?;
+
+        Ok(detector.finish())
+    }
+}
+
+/// A part of a recording which is missing records.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gap {
+    /// The time of the last record before the gap, as a duration since the UNIX epoch.
+    pub start: Duration,
+    /// The time of the first record after the gap, or of the end of the recording.
+    pub end: Duration,
+    /// The recorded thread which is missing records, or `None` if the whole recording is.
+    pub thread_id: Option<String>,
+    /// How the gap was found.
+    pub kind: GapKind,
+}
+
+impl Gap {
+    /// Returns how long the gap lasted.
+    #[must_use]
+    pub fn duration(&self) -> Duration {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+/// How a [`Gap`] was found.
+#[non_exhaustive]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum GapKind {
+    /// Heartbeats were missing.
+    MissedHeartbeats {
+        /// The number of heartbeats which were expected but not recorded.
+        missed: u64,
+    },
+    /// Sequence numbers were skipped.
+    SequenceJump {
+        /// The sequence number which was expected.
+        expected: u64,
+        /// The sequence number which was recorded.
+        found: u64,
+    },
+    /// A marker recorded that records were dropped.
+    DroppedRecords {
+        /// The number of records which were dropped.
+        count: u64,
+    },
+    /// Nothing was recorded for longer than the maximum silence.
+    Silence,
+}
+
+impl fmt::Display for GapKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissedHeartbeats { missed } => write!(f, "missed {missed} heartbeats"),
+            Self::SequenceJump { expected, found } => write!(
+                f,
+                "expected sequence number {expected} but found {found}, missing {} records",
+                found - expected
+            ),
+            Self::DroppedRecords { count } => write!(f, "{count} records dropped"),
+            Self::Silence => f.write_str("nothing recorded"),
+        }
+    }
+}
+
+/// The gaps found in a recording by [`GapDetection`].
+#[non_exhaustive]
+#[derive(Clone, Debug, Default)]
+pub struct GapReport {
+    /// The time of the first record, as a duration since the UNIX epoch.
+    pub start: Duration,
+    /// The time of the last record.
+    pub end: Duration,
+    /// The gaps, ordered by when they started.
+    pub gaps: Vec<Gap>,
+}
+
+impl GapReport {
+    /// Returns the time up to which the recording has no gaps, the start of the first gap, or
+    /// `None` if it has none.
+    #[must_use]
+    pub fn watermark(&self) -> Option<Duration> {
+        self.gaps.first().map(|gap| gap.start)
+    }
+
+    /// Returns whether the records of a thread at `timestamp` may be missing records, because
+    /// the time is within a gap of the thread or of the whole recording.
+    #[must_use]
+    pub fn affects(&self, thread_id: &str, timestamp: Duration) -> bool {
+        self.gaps.iter().any(|gap| {
+            let thread_matches = match &gap.thread_id {
+                Some(gap_thread_id) => gap_thread_id == thread_id,
+                None => true,
+            };
+            thread_matches && gap.start <= timestamp && timestamp <= gap.end
+        })
+    }
+}
+
+/// Finds the gaps one record at a time.
+struct Detector<'a> {
+    detection: &'a GapDetection,
+    report: GapReport,
+    latest: Option<Duration>,
+    /// The sequence number of the last numbered record.
+    sequence: Option<u64>,
+    threads: HashMap<String, ThreadState>,
+}
+
+/// What is known about a recorded thread so far.
+#[derive(Default)]
+struct ThreadState {
+    latest: Option<Duration>,
+    heartbeat: Option<Duration>,
+    sequence: Option<u64>,
+}
+
+impl Detector<'_> {
+    /// Checks the sequence number of a record, before it is pushed.
+    fn push_sequence(&mut self, ts: Duration, sequence: Option<u64>) {
+        if !self.detection.record_sequence {
+            return;
+        }
+        let Some(found) = sequence else {
+            return;
+        };
+        if let Some(expected) = self.sequence.map(|sequence| sequence + 1) {
+            if found > expected {
+                let previous = self.latest.unwrap_or(ts);
+                self.gap(
+                    previous,
+                    ts,
+                    None,
+                    GapKind::SequenceJump { expected, found },
+                );
+            }
+        }
+        self.sequence = Some(found);
+    }
+
+    fn push(&mut self, ts: Duration, thread_id: String, fields: Option<&Vec<Field>>, event: bool) {
+        let detection = self.detection;
+        match self.latest {
+            None => self.report.start = ts,
+            Some(latest) => {
+                let silent = detection
+                    .max_silence
+                    .is_some_and(|max_silence| ts.saturating_sub(latest) > max_silence);
+                if silent {
+                    self.gap(latest, ts, None, GapKind::Silence);
+                }
+            }
+        }
+        self.latest = Some(self.latest.map_or(ts, |latest| latest.max(ts)));
+
+        let mut thread = self.threads.remove(&thread_id).unwrap_or_default();
+        let previous = thread.latest.unwrap_or(ts);
+        let fields = fields.map_or(&[][..], Vec::as_slice);
+
+        if let Some((message, interval)) = &detection.heartbeat {
+            let is_heartbeat = event
+                && field(fields, "message").is_some_and(|value| match value {
+                    FieldValue::Debug(value) | FieldValue::Str(value) => value == message,
+                    _ => false,
+                });
+            if is_heartbeat {
+                if let Some(heartbeat) = thread.heartbeat {
+                    let elapsed = ts.saturating_sub(heartbeat);
+                    if elapsed > *interval * 2 {
+                        let missed = (elapsed.as_secs_f64() / interval.as_secs_f64()).round() - 1.0;
+                        let kind = GapKind::MissedHeartbeats {
+                            missed: float_count(missed),
+                        };
+                        self.gap(heartbeat, ts, Some(thread_id.clone()), kind);
+                    }
+                }
+                thread.heartbeat = Some(ts);
+            }
+        }
+
+        if let Some(name) = &detection.sequence_field {
+            if let Some(found) = field(fields, name).and_then(unsigned) {
+                if let Some(expected) = thread.sequence.map(|sequence| sequence + 1) {
+                    if found > expected {
+                        let kind = GapKind::SequenceJump { expected, found };
+                        self.gap(previous, ts, Some(thread_id.clone()), kind);
+                    }
+                }
+                thread.sequence = Some(found);
+            }
+        }
+
+        if let Some(name) = &detection.dropped_field {
+            let count = field(fields, name).and_then(unsigned).unwrap_or_default();
+            if event && count > 0 {
+                let kind = GapKind::DroppedRecords { count };
+                self.gap(previous, ts, Some(thread_id.clone()), kind);
+            }
+        }
+
+        thread.latest = Some(ts);
+        self.threads.insert(thread_id, thread);
+    }
+
+    fn finish(mut self) -> GapReport {
+        let end = self.latest.unwrap_or_default();
+        self.report.end = end;
+
+        // Heartbeats which stopped before the end of the recording.
+        if let Some((_, interval)) = &self.detection.heartbeat {
+            let mut threads: Vec<_> = self.threads.iter().collect();
+            threads.sort_by_key(|(thread_id, _)| *thread_id);
+            let mut stopped = Vec::new();
+            for (thread_id, thread) in threads {
+                let Some(heartbeat) = thread.heartbeat else {
+                    continue;
+                };
+                let elapsed = end.saturating_sub(heartbeat);
+                if elapsed > *interval * 2 {
+                    let missed = (elapsed.as_secs_f64() / interval.as_secs_f64()).floor();
+                    stopped.push(Gap {
+                        start: heartbeat,
+                        end,
+                        thread_id: Some(thread_id.clone()),
+                        kind: GapKind::MissedHeartbeats {
+                            missed: float_count(missed),
+                        },
+                    });
+                }
+            }
+            self.report.gaps.extend(stopped);
+        }
+
+        let mut report = self.report;
+        report.gaps.sort_by_key(|gap| (gap.start, gap.end));
+        report
+    }
+
+    fn gap(&mut self, start: Duration, end: Duration, thread_id: Option<String>, kind: GapKind) {
+        self.report.gaps.push(Gap {
+            start,
+            end,
+            thread_id,
+            kind,
+        });
+    }
+}
+
+fn field<'f>(fields: &'f [Field], name: &str) -> Option<&'f FieldValue> {
+    fields
+        .iter()
+        .find(|field| field.name == name)
+        .map(|field| &field.value)
+}
+
+/// Returns the value of an integer field, if it isn't negative.
+fn unsigned(value: &FieldValue) -> Option<u64> {
+    match value {
+        FieldValue::U64(value) => Some(*value),
+        FieldValue::I64(value) => u64::try_from(*value).ok(),
+        FieldValue::U128(value) => u64::try_from(*value).ok(),
+        FieldValue::I128(value) => u64::try_from(*value).ok(),
+        _ => None,
+    }
+}
+
+/// Converts a whole, non-negative number of heartbeats into a count.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn float_count(count: f64) -> u64 {
+    count.max(0.0) as u64
+}
@@ -0,0 +1,451 @@
+//! Export of recordings as a self-contained HTML report.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::{
+    export::{self, value_to_string, Slice, Timeline, TimelineEvent},
+    ExportError, Field, Level, SpanId, Trace,
+};
+
+/// Exports the recording read from `reader` as an HTML report, which is written to `writer`.
+///
+/// The report is a single page which can be opened in any browser, without a network
+/// connection, so it can be attached to a bug report or sent to someone who doesn't have any
+/// tooling installed. It shows:
+///
+/// - A timeline with a lane for each recorded thread, with the time that spans were entered
+///   shown as slices, nested by how deep they were entered, and events as ticks below them. The
+///   timeline can be zoomed in, and scrolled.
+/// - The stream of events, in the order they were recorded, with their thread, level, target,
+///   and message, which can be filtered by text.
+/// - An inspector which shows the location and all the fields of a span or event when it is
+///   clicked on.
+///
+/// Timestamps are shown relative to the first record in the recording. Spans which are still
+/// entered at the end of the recording are ended at the time of the last record. The page
+/// contains all of the recording's spans and events, so very large recordings are better
+/// filtered or trimmed first.
+///
+/// # Errors
+///
+/// Returns an error if reading the recording or writing the report fails, if a line of the
+/// recording can't be deserialized into a record, or if the recording was written in a newer
+/// version of the format.
+///
+/// # Examples
+///
+/// ```
+/// let recording = concat!(
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":1,"fields":[{"name":"user_id","value":{"I64":7}}],"metadata":{"id":4403349456,"name":"request","target":"app","level":"Info","module_path":"app","file":"src/main.rs","line":8,"fields":["user_id"],"kind":"Span"},"parent":"Root"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543410,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543420,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"</script> in a message"}}],"metadata":{"id":4403349608,"name":"event","target":"app","level":"Warn","module_path":"app","file":"src/main.rs","line":9,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543430,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Exit":1}}"#,
+///     "\n",
+/// );
+///
+/// let mut html = Vec::new();
+/// tracing_cassette::to_html(recording.as_bytes(), &mut html).unwrap();
+///
+/// let html = String::from_utf8(html).unwrap();
+/// assert!(html.starts_with("<!DOCTYPE html>"));
+/// assert!(html.contains(r#""name":"request""#));
+/// // The recording can't end the script which it is embedded in.
+/// assert!(html.contains(r#""name":"\u003c/script> in a message""#));
+/// assert!(!html.contains("</script> in a message"));
+/// ```
+pub fn to_html<R, W>(reader: R, mut writer: W) -> Result<(), ExportError>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut timeline = Timeline::default();
+    let mut report = HtmlReport::default();
+    export::for_each_record(reader, |record| {
+        // Events don't have a level on the timeline.
+        let level = match &record.trace {
+            Trace::Event(event) => Some(level(&event.metadata.level)),
+            _ => None,
+        };
+        let events = timeline.push(record);
+        let start = timeline.start();
+        for event in events {
+            report.push(start, event, level);
+        }
+        Ok(())
+    })?;
+    let start = timeline.start();
+    for event in timeline.finish() {
+        report.push(start, event, None);
+    }
+
+    // `<` is only ever in strings, where it can be escaped so that the data can't close the
+    // script that it is in.
+    let data = serde_json::to_string(&report.data())
+        .map_err(std::io::Error::from)?
+        .replace('<', "\\u003c");
+    writer.write_all(HEAD.as_bytes())?;
+    writer.write_all(data.as_bytes())?;
+    writer.write_all(TAIL.as_bytes())?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// The spans and events of the report, with times in microseconds since the first record.
+#[derive(Debug, Default)]
+struct HtmlReport {
+    threads: Vec<HtmlThread>,
+    slices: Vec<HtmlSlice>,
+    events: Vec<HtmlEvent>,
+    /// The spans entered on each lane, innermost last.
+    entered: HashMap<u64, Vec<SpanId>>,
+    /// The index of each lane in `threads`.
+    lanes: HashMap<u64, usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct HtmlThread {
+    id: String,
+    name: Option<String>,
+    /// The most spans entered at once on the thread.
+    depth: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct HtmlSlice {
+    thread: usize,
+    /// How many spans were entered on the thread when this one was.
+    depth: usize,
+    start: u64,
+    end: u64,
+    name: String,
+    target: String,
+    file: Option<String>,
+    line: Option<u32>,
+    fields: Vec<(String, String)>,
+}
+
+#[derive(Debug, Serialize)]
+struct HtmlEvent {
+    thread: usize,
+    ts: u64,
+    level: Option<&'static str>,
+    /// The message of the event, or its name if it doesn't have one.
+    name: String,
+    target: String,
+    file: Option<String>,
+    line: Option<u32>,
+    fields: Vec<(String, String)>,
+}
+
+/// The data which is embedded in the report.
+#[derive(Debug, Serialize)]
+struct HtmlData<'a> {
+    end: u64,
+    threads: &'a [HtmlThread],
+    slices: &'a [HtmlSlice],
+    events: &'a [HtmlEvent],
+}
+
+impl HtmlReport {
+    fn push(&mut self, start: Duration, event: TimelineEvent, level: Option<&'static str>) {
+        match event {
+            TimelineEvent::Thread {
+                lane,
+                thread_id,
+                thread_name,
+            } => {
+                self.lanes.insert(lane, self.threads.len());
+                self.threads.push(HtmlThread {
+                    id: thread_id,
+                    name: thread_name,
+                    depth: 0,
+                });
+            }
+            TimelineEvent::SliceBegin { lane, id, .. } => {
+                let entered = self.entered.entry(lane).or_default();
+                entered.push(id);
+                let depth = entered.len();
+                if let Some(thread) = self.thread_mut(lane) {
+                    thread.depth = thread.depth.max(depth);
+                }
+            }
+            TimelineEvent::SliceEnd {
+                lane,
+                id,
+                ts,
+                start: slice_start,
+                slice,
+            } => {
+                let entered = self.entered.entry(lane).or_default();
+                let depth = match entered.iter().rposition(|entered| *entered == id) {
+                    Some(idx) => {
+                        entered.remove(idx);
+                        idx
+                    }
+                    None => 0,
+                };
+                let Some(thread) = self.lanes.get(&lane).copied() else {
+                    return;
+                };
+                let (name, target, file, line, fields) = slice_parts(slice);
+                self.slices.push(HtmlSlice {
+                    thread,
+                    depth,
+                    start: micros(start, slice_start),
+                    end: micros(start, ts),
+                    name,
+                    target,
+                    file,
+                    line,
+                    fields,
+                });
+            }
+            TimelineEvent::Instant { lane, ts, slice } => {
+                let Some(thread) = self.lanes.get(&lane).copied() else {
+                    return;
+                };
+                let (name, target, file, line, fields) = slice_parts(slice);
+                self.events.push(HtmlEvent {
+                    thread,
+                    ts: micros(start, ts),
+                    level,
+                    name,
+                    target,
+                    file,
+                    line,
+                    fields,
+                });
+            }
+        }
+    }
+
+    fn thread_mut(&mut self, lane: u64) -> Option<&mut HtmlThread> {
+        let idx = *self.lanes.get(&lane)?;
+        self.threads.get_mut(idx)
+    }
+
+    /// Returns the time of the last slice or event.
+    fn end(&self) -> u64 {
+        let slices = self.slices.iter().map(|slice| slice.end);
+        let events = self.events.iter().map(|event| event.ts);
+        slices.chain(events).max().unwrap_or(0)
+    }
+
+    fn data(&self) -> HtmlData<'_> {
+        HtmlData {
+            end: self.end(),
+            threads: &self.threads,
+            slices: &self.slices,
+            events: &self.events,
+        }
+    }
+}
+
+/// Returns the parts of a slice, with the field values formatted.
+#[allow(clippy::type_complexity)]
+fn slice_parts(
+    slice: Slice,
+) -> (
+    String,
+    String,
+    Option<String>,
+    Option<u32>,
+    Vec<(String, String)>,
+) {
+    let fields = slice.fields.into_iter().map(field_parts).collect();
+    (slice.name, slice.target, slice.file, slice.line, fields)
+}
+
+fn field_parts(field: Field) -> (String, String) {
+    (field.name, value_to_string(field.value))
+}
+
+/// Returns the microseconds from `start` to `ts`.
+fn micros(start: Duration, ts: Duration) -> u64 {
+    u64::try_from(ts.saturating_sub(start).as_micros()).unwrap_or(u64::MAX)
+}
+
+fn level(level: &Level) -> &'static str {
+    match level {
+        Level::Trace => "TRACE",
+        Level::Debug => "DEBUG",
+        Level::Info => "INFO",
+        Level::Warn => "WARN",
+        Level::Error => "ERROR",
+    }
+}
+
+/// The page up to the data of the recording.
+const HEAD: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Recording</title>
+<style>
+body { font: 13px/1.4 system-ui, sans-serif; margin: 0; color: #222; }
+header { padding: 8px 12px; background: #f4f4f4; border-bottom: 1px solid #ddd; display: flex; gap: 16px; align-items: center; }
+h1 { font-size: 15px; margin: 0; }
+#timeline { overflow-x: auto; border-bottom: 1px solid #ddd; }
+.lane { display: flex; border-bottom: 1px solid #eee; }
+.label { position: sticky; left: 0; z-index: 2; width: 160px; min-width: 160px; padding: 4px 8px; background: #fafafa; border-right: 1px solid #ddd; overflow: hidden; text-overflow: ellipsis; white-space: nowrap; }
+.track { position: relative; flex: none; }
+.slice { position: absolute; height: 18px; min-width: 1px; box-sizing: border-box; padding: 0 3px; overflow: hidden; white-space: nowrap; text-overflow: ellipsis; font-size: 11px; line-height: 18px; background: #7aa6da; border: 1px solid #5b88bf; border-radius: 2px; color: #fff; cursor: pointer; }
+.tick { position: absolute; width: 2px; height: 12px; cursor: pointer; }
+.TRACE { background: #999; } .DEBUG { background: #3a8; } .INFO { background: #38c; } .WARN { background: #e90; } .ERROR { background: #d33; }
+.selected { outline: 2px solid #222; z-index: 1; }
+main { display: flex; height: 45vh; }
+#events { flex: 3; overflow: auto; border-right: 1px solid #ddd; }
+#events table { border-collapse: collapse; width: 100%; }
+#events td { padding: 2px 8px; border-bottom: 1px solid #f0f0f0; white-space: nowrap; }
+#events tr { cursor: pointer; }
+#events tr:hover { background: #f4f8fc; }
+#events .level { color: #fff; font-size: 11px; border-radius: 2px; padding: 0 4px; }
+#inspector { flex: 2; overflow: auto; padding: 8px 12px; }
+#inspector table { border-collapse: collapse; }
+#inspector td { padding: 2px 8px 2px 0; vertical-align: top; }
+#inspector td:first-child { color: #666; }
+</style>
+</head>
+<body>
+<header>
+<h1>Recording</h1>
+<span id="summary"></span>
+<label>Zoom <input id="zoom" type="range" min="1" max="200" value="1"></label>
+<label>Filter events <input id="filter" type="search"></label>
+</header>
+<div id="timeline"></div>
+<main>
+<div id="events"><table><tbody id="event-rows"></tbody></table></div>
+<div id="inspector">Click on a span or an event to inspect it.</div>
+</main>
+<script id="recording" type="application/json">"#;
+
+/// The page after the data of the recording.
+const TAIL: &str = r#"</script>
+<script>
+"use strict";
+const data = JSON.parse(document.getElementById("recording").textContent);
+const ROW = 20;
+const el = (tag, className, text) => {
+  const node = document.createElement(tag);
+  if (className) node.className = className;
+  if (text !== undefined) node.textContent = text;
+  return node;
+};
+const time = (us) => {
+  if (us >= 1e6) return (us / 1e6).toFixed(3) + " s";
+  if (us >= 1e3) return (us / 1e3).toFixed(3) + " ms";
+  return us + " µs";
+};
+const threadName = (thread) => thread.name ? `${thread.name} (${thread.id})` : thread.id;
+const span = Math.max(data.end, 1);
+
+document.getElementById("summary").textContent =
+  `${time(data.end)}, ${data.threads.length} threads, ${data.slices.length} span slices, ${data.events.length} events`;
+
+let selected = null;
+const inspect = (node, kind, item) => {
+  if (selected) selected.classList.remove("selected");
+  selected = node;
+  if (node) node.classList.add("selected");
+  const inspector = document.getElementById("inspector");
+  inspector.replaceChildren();
+  inspector.append(el("h3", null, item.name));
+  const table = el("table");
+  const row = (name, value) => {
+    const tr = el("tr");
+    tr.append(el("td", null, name), el("td", null, value));
+    table.append(tr);
+  };
+  row("kind", kind);
+  row("thread", threadName(data.threads[item.thread]));
+  if (item.level) row("level", item.level);
+  row("target", item.target);
+  if (item.file) row("location", item.line ? `${item.file}:${item.line}` : item.file);
+  if (kind === "span") {
+    row("entered", time(item.start));
+    row("duration", time(item.end - item.start));
+  } else {
+    row("time", time(item.ts));
+  }
+  for (const [name, value] of item.fields) row(name, value);
+  inspector.append(table);
+};
+
+const timeline = document.getElementById("timeline");
+const tracks = data.threads.map((thread) => {
+  const lane = el("div", "lane");
+  const label = el("div", "label", threadName(thread));
+  const track = el("div", "track");
+  track.style.height = `${(thread.depth + 1) * ROW + 4}px`;
+  lane.append(label, track);
+  timeline.append(lane);
+  return track;
+});
+for (const slice of data.slices) {
+  const node = el("div", "slice", slice.name);
+  node.title = `${slice.name} (${time(slice.end - slice.start)})`;
+  node.style.left = `${(slice.start / span) * 100}%`;
+  node.style.width = `${((slice.end - slice.start) / span) * 100}%`;
+  node.style.top = `${slice.depth * ROW + 2}px`;
+  node.onclick = () => inspect(node, "span", slice);
+  tracks[slice.thread].append(node);
+}
+const rows = document.getElementById("event-rows");
+data.events.forEach((event) => {
+  const tick = el("div", `tick ${event.level || "INFO"}`);
+  tick.title = event.name;
+  tick.style.left = `${(event.ts / span) * 100}%`;
+  tick.style.top = `${data.threads[event.thread].depth * ROW + 4}px`;
+  tracks[event.thread].append(tick);
+
+  const tr = el("tr");
+  tr.append(
+    el("td", null, time(event.ts)),
+    el("td", null, threadName(data.threads[event.thread])),
+  );
+  const level = el("td");
+  level.append(el("span", `level ${event.level || "INFO"}`, event.level || ""));
+  tr.append(level, el("td", null, event.target), el("td", null, event.name));
+  tr.dataset.text = [event.level, event.target, event.name, ...event.fields.flat()]
+    .join(" ")
+    .toLowerCase();
+  const select = () => {
+    inspect(tick, "event", event);
+    tick.scrollIntoView({ block: "nearest", inline: "center" });
+  };
+  tick.onclick = () => {
+    inspect(tick, "event", event);
+    tr.scrollIntoView({ block: "nearest" });
+  };
+  tr.onclick = select;
+  rows.append(tr);
+});
+
+const zoom = document.getElementById("zoom");
+const resize = () => {
+  const width = (timeline.clientWidth - 160) * Number(zoom.value);
+  for (const track of tracks) track.style.width = `${Math.max(width, 100)}px`;
+};
+zoom.oninput = resize;
+window.onresize = resize;
+resize();
+
+document.getElementById("filter").oninput = (e) => {
+  const text = e.target.value.toLowerCase();
+  for (const tr of rows.children) tr.hidden = text !== "" && !tr.dataset.text.includes(text);
+};
+</script>
+</body>
+</html>
+"#;
@@ -0,0 +1,454 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{self, BufRead, BufWriter, Read, Write},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    reader, ContainerReader, ExportError, Header, Metadata, SpanId, Trace, TraceRecord,
+    UNVERSIONED_FORMAT_VERSION,
+};
+
+/// The current version of the index format.
+pub const INDEX_VERSION: u32 = 1;
+
+/// An index over a recording, which allows readers to seek to a point in time without reading
+/// the whole recording up to that point.
+///
+/// The index is stored in a sidecar file next to the recording, see [`sidecar_path`]. It is
+/// made up of:
+///
+/// - checkpoints, which map offsets in time from the start of the recording to positions in the
+///   recording;
+/// - the positions of each recorded thread's records at the same intervals, so that a single
+///   thread can be loaded lazily;
+/// - a table of all the callsites in the recording, with the position they first appear at.
+///
+/// Each checkpoint also stores a prologue: the positions of the records which are needed to
+/// reconstruct the state at the checkpoint. This includes all the callsite registrations seen so
+/// far as well as the creation, recorded values, and entering of all the spans that are still
+/// open. `tracing-replay` uses the prologue to replay a time range of a recording from the
+/// closest checkpoint before the start of the range.
+///
+/// Positions are given as byte offsets into the recording, together with the index of the line.
+/// The header of the recording counts as a line.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use tracing_cassette::RecordingIndex;
+///
+/// let recording = concat!(
+///     r#"{"header":{"version":2}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":0,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"RegisterCallsite":{"id":4403349456,"name":"span","target":"record_spans","level":"Info","module_path":"record_spans","file":"tracing-rec/examples/record-spans.rs","line":8,"fields":[],"kind":"Span"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":10,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":1,"fields":[],"metadata":{"id":4403349456,"name":"span","target":"record_spans","level":"Info","module_path":"record_spans","file":"tracing-rec/examples/record-spans.rs","line":8,"fields":[],"kind":"Span"},"parent":"Root"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177341,"timestamp_subsec_us":500000,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+///     "\n",
+/// );
+///
+/// let index = RecordingIndex::build(recording.as_bytes(), Duration::from_secs(1)).unwrap();
+/// assert_eq!(index.callsites().len(), 1);
+/// assert_eq!(index.threads()[0].record_count, 3);
+///
+/// // The checkpoint for 1.5 seconds in is at the `Enter` record, and the prologue contains the
+/// // callsite and the creation of the span.
+/// let checkpoint = index.checkpoint_before(Duration::from_millis(1_500)).unwrap();
+/// assert_eq!(checkpoint.offset_us, 1_500_000);
+/// assert_eq!(checkpoint.position.line_index, 3);
+/// assert_eq!(checkpoint.prologue.len(), 2);
+/// ```
+///
+/// [`sidecar_path`]: fn@Self::sidecar_path
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RecordingIndex {
+    version: u32,
+    start_timestamp_s: u64,
+    start_timestamp_subsec_us: u32,
+    checkpoints: Vec<IndexCheckpoint>,
+    // Indexes written before the threads and callsites were added don't have them.
+    #[serde(default)]
+    threads: Vec<ThreadIndex>,
+    #[serde(default)]
+    callsites: Vec<IndexedCallsite>,
+}
+
+/// The position of a record in a recording.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct IndexPosition {
+    /// The offset of the start of the line in bytes.
+    pub byte_offset: u64,
+    /// The index of the line, starting from 0.
+    pub line_index: usize,
+}
+
+/// A point in a recording that readers can seek to.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct IndexCheckpoint {
+    /// Offset from the start of the recording in microseconds.
+    pub offset_us: u64,
+    /// The position of the first record at or after the offset.
+    pub position: IndexPosition,
+    /// The positions of the records needed to reconstruct the state at the checkpoint, in
+    /// recording order.
+    pub prologue: Vec<IndexPosition>,
+}
+
+/// The records of a single recorded thread.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ThreadIndex {
+    /// The Id of the recorded thread, such as `ThreadId(1)`.
+    pub thread_id: String,
+    /// The name of the recorded thread, if it had one.
+    pub thread_name: Option<String>,
+    /// The number of records on the thread.
+    pub record_count: u64,
+    /// The first record on the thread at or after each interval.
+    pub checkpoints: Vec<ThreadCheckpoint>,
+}
+
+/// The position of the first record on a thread at or after an offset in time.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ThreadCheckpoint {
+    /// Offset from the start of the recording in microseconds.
+    pub offset_us: u64,
+    /// The position of the record.
+    pub position: IndexPosition,
+}
+
+/// A callsite in a recording.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct IndexedCallsite {
+    /// The metadata of the callsite.
+    pub metadata: Metadata,
+    /// The position of the first record with the callsite.
+    pub position: IndexPosition,
+}
+
+impl RecordingIndex {
+    /// Builds an index for the recording read from `reader`.
+    ///
+    /// A checkpoint is placed at the first record after every `interval` of recorded time, both
+    /// for the whole recording and for each thread.
+    ///
+    /// Recordings stored in a container are indexed once they have been decoded, so the positions
+    /// in the index are positions in the decoded recording, see [`ContainerReader`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the recording fails, if a line of the recording can't be
+    /// deserialized into a record, or if the recording was written in a newer version of the
+    /// format.
+    ///
+    /// [`ContainerReader`]: struct@crate::ContainerReader
+    pub fn build<R: BufRead>(reader: R, interval: Duration) -> Result<Self, ExportError> {
+        let mut reader = ContainerReader::new(reader);
+        let mut builder = IndexBuilder::new(interval);
+        let mut version = UNVERSIONED_FORMAT_VERSION;
+        let mut position = IndexPosition {
+            byte_offset: 0,
+            line_index: 0,
+        };
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            let len = reader.read_until(b'\n', &mut buf)?;
+            if len == 0 {
+                break;
+            }
+            let line_position = position;
+            position = IndexPosition {
+                byte_offset: position.byte_offset + len as u64,
+                line_index: position.line_index + 1,
+            };
+
+            let line = buf.strip_suffix(b"\n").unwrap_or(&buf);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            if line_position.line_index == 0 {
+                if let Some(header) = Header::from_line(line) {
+                    header.check().map_err(ExportError::Version)?;
+                    version = header.version;
+                    continue;
+                }
+            }
+
+            let record = reader::parse_record(version, line).map_err(|inner| {
+                ExportError::InvalidRecord {
+                    line_index: line_position.line_index,
+                    inner,
+                }
+            })?;
+            builder.push(&record, line_position);
+        }
+
+        Ok(builder.finish())
+    }
+
+    /// Reads an index which was written with [`write`].
+    ///
+    /// The version of the index isn't checked, see [`version`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the index fails or if it can't be deserialized.
+    ///
+    /// [`write`]: fn@Self::write
+    /// [`version`]: fn@Self::version
+    pub fn read<R: Read>(reader: R) -> Result<Self, serde_json::Error> {
+        serde_json::from_reader(reader)
+    }
+
+    /// Writes the index to `writer`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to `writer` fails.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()
+    }
+
+    /// Returns the path of the sidecar index for the recording at `recording_path`.
+    #[must_use]
+    pub fn sidecar_path(recording_path: &str) -> String {
+        format!("{recording_path}.idx")
+    }
+
+    /// Writes this index to the sidecar file for the recording at `recording_path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sidecar file can't be written.
+    pub fn write_sidecar(&self, recording_path: &str) -> io::Result<()> {
+        let file = File::create(Self::sidecar_path(recording_path))?;
+        self.write(BufWriter::new(file))
+    }
+
+    /// The version of the index format that the index was written in, indexes in any version
+    /// other than [`INDEX_VERSION`] can't be relied upon.
+    #[must_use]
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The timestamp of the first record in the recording, as a duration since the UNIX epoch.
+    #[must_use]
+    pub fn start(&self) -> Duration {
+        Duration::from_secs(self.start_timestamp_s).saturating_add(Duration::from_micros(
+            u64::from(self.start_timestamp_subsec_us),
+        ))
+    }
+
+    /// The checkpoints of the recording, in order.
+    #[must_use]
+    pub fn checkpoints(&self) -> &[IndexCheckpoint] {
+        &self.checkpoints
+    }
+
+    /// Returns the last checkpoint at or before `offset` from the start of the recording.
+    #[must_use]
+    pub fn checkpoint_before(&self, offset: Duration) -> Option<&IndexCheckpoint> {
+        let offset_us = u64::try_from(offset.as_micros()).unwrap_or(u64::MAX);
+        self.checkpoints
+            .iter()
+            .take_while(|checkpoint| checkpoint.offset_us <= offset_us)
+            .last()
+    }
+
+    /// The recorded threads, in the order they first appear in the recording.
+    #[must_use]
+    pub fn threads(&self) -> &[ThreadIndex] {
+        &self.threads
+    }
+
+    /// The callsites, in the order they first appear in the recording.
+    #[must_use]
+    pub fn callsites(&self) -> &[IndexedCallsite] {
+        &self.callsites
+    }
+}
+
+/// Builds an index from the records of a recording.
+#[derive(Debug)]
+struct IndexBuilder {
+    interval: Duration,
+    start: Option<Duration>,
+    next_checkpoint: Duration,
+    checkpoints: Vec<IndexCheckpoint>,
+    /// The threads, together with when their next checkpoint is due.
+    threads: Vec<(ThreadIndex, Duration)>,
+    callsites: Vec<IndexedCallsite>,
+    callsite_ids: HashSet<u64>,
+    tracker: PrologueTracker,
+}
+
+impl IndexBuilder {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            start: None,
+            next_checkpoint: Duration::ZERO,
+            checkpoints: Vec::new(),
+            threads: Vec::new(),
+            callsites: Vec::new(),
+            callsite_ids: HashSet::new(),
+            tracker: PrologueTracker::default(),
+        }
+    }
+
+    fn push(&mut self, record: &TraceRecord, position: IndexPosition) {
+        let timestamp = record.meta.timestamp();
+        let offset = timestamp.saturating_sub(*self.start.get_or_insert(timestamp));
+        let offset_us = u64::try_from(offset.as_micros()).unwrap_or(u64::MAX);
+        if offset >= self.next_checkpoint {
+            self.checkpoints.push(IndexCheckpoint {
+                offset_us,
+                position,
+                prologue: self.tracker.prologue(),
+            });
+            self.next_checkpoint = next_checkpoint(self.next_checkpoint, offset, self.interval);
+        }
+
+        let thread_idx = match self
+            .threads
+            .iter()
+            .position(|(thread, _)| thread.thread_id == record.meta.thread_id)
+        {
+            Some(thread_idx) => thread_idx,
+            None => {
+                let thread = ThreadIndex {
+                    thread_id: record.meta.thread_id.clone(),
+                    thread_name: record.meta.thread_name.clone(),
+                    record_count: 0,
+                    checkpoints: Vec::new(),
+                };
+                self.threads.push((thread, Duration::ZERO));
+                self.threads.len() - 1
+            }
+        };
+        let (thread, thread_next_checkpoint) = &mut self.threads[thread_idx];
+        thread.record_count += 1;
+        if offset >= *thread_next_checkpoint {
+            thread.checkpoints.push(ThreadCheckpoint {
+                offset_us,
+                position,
+            });
+            *thread_next_checkpoint =
+                next_checkpoint(*thread_next_checkpoint, offset, self.interval);
+        }
+
+        let metadata = match &record.trace {
+            Trace::RegisterCallsite(metadata) => Some(metadata),
+            Trace::NewSpan(new_span) => Some(&new_span.metadata),
+            Trace::Event(event) => Some(&event.metadata),
+            _ => None,
+        };
+        if let Some(metadata) = metadata {
+            if self.callsite_ids.insert(metadata.id) {
+                self.callsites.push(IndexedCallsite {
+                    metadata: metadata.clone(),
+                    position,
+                });
+            }
+        }
+
+        self.tracker.track(&record.trace, position);
+    }
+
+    fn finish(self) -> RecordingIndex {
+        let start = self.start.unwrap_or_default();
+        RecordingIndex {
+            version: INDEX_VERSION,
+            start_timestamp_s: start.as_secs(),
+            start_timestamp_subsec_us: start.subsec_micros(),
+            checkpoints: self.checkpoints,
+            threads: self.threads.into_iter().map(|(thread, _)| thread).collect(),
+            callsites: self.callsites,
+        }
+    }
+}
+
+/// Returns when the checkpoint after one placed at `offset` is due.
+fn next_checkpoint(mut next: Duration, offset: Duration, interval: Duration) -> Duration {
+    if interval.is_zero() {
+        return offset + Duration::from_micros(1);
+    }
+    while next <= offset {
+        next += interval;
+    }
+    next
+}
+
+/// Tracks the records needed to reconstruct the state at a position in a recording.
+#[derive(Debug, Default)]
+struct PrologueTracker {
+    callsites: Vec<IndexPosition>,
+    open_spans: HashMap<SpanId, OpenSpan>,
+}
+
+#[derive(Debug)]
+struct OpenSpan {
+    new_span: IndexPosition,
+    /// Positions of records which set field values on the span.
+    records: Vec<IndexPosition>,
+    /// Positions of enters which haven't been exited yet.
+    enters: Vec<IndexPosition>,
+}
+
+impl PrologueTracker {
+    fn track(&mut self, trace: &Trace, position: IndexPosition) {
+        match trace {
+            Trace::RegisterCallsite(_) => self.callsites.push(position),
+            Trace::NewSpan(new_span) => {
+                self.open_spans.insert(
+                    new_span.id,
+                    OpenSpan {
+                        new_span: position,
+                        records: Vec::new(),
+                        enters: Vec::new(),
+                    },
+                );
+            }
+            Trace::Record(record_values) => {
+                if let Some(open_span) = self.open_spans.get_mut(&record_values.id) {
+                    open_span.records.push(position);
+                }
+            }
+            Trace::Enter(span_id) => {
+                if let Some(open_span) = self.open_spans.get_mut(span_id) {
+                    open_span.enters.push(position);
+                }
+            }
+            Trace::Exit(span_id) => {
+                if let Some(open_span) = self.open_spans.get_mut(span_id) {
+                    open_span.enters.pop();
+                }
+            }
+            Trace::Close(span_id) => {
+                self.open_spans.remove(span_id);
+            }
+            Trace::Event(_) | Trace::FollowsFrom(_) => {}
+        }
+    }
+
+    /// The positions of all the records in the prologue, in recording order.
+    fn prologue(&self) -> Vec<IndexPosition> {
+        let mut prologue = self.callsites.clone();
+        for open_span in self.open_spans.values() {
+            prologue.push(open_span.new_span);
+            prologue.extend_from_slice(&open_span.records);
+            prologue.extend_from_slice(&open_span.enters);
+        }
+        prologue.sort_unstable_by_key(|position| position.byte_offset);
+        prologue
+    }
+}
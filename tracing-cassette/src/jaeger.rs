@@ -0,0 +1,224 @@
+//! Export of recordings to Jaeger's trace JSON format.
+//!
+//! Jaeger's model of spans is the same as OpenTelemetry's, so recordings are first converted
+//! into OTLP spans and log records, which are then rewritten into Jaeger's format.
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+use serde_json::{json, Value};
+
+use crate::{otlp, ExportError};
+
+/// The Id of the process that all spans belong to, a recording only ever contains a single
+/// process.
+const PROCESS_ID: &str = "p1";
+
+/// Exports the recording read from `reader` as Jaeger traces, which are written to `writer`.
+///
+/// The traces are written in the JSON format returned by Jaeger's query API, which can be
+/// loaded into the Jaeger UI with its upload feature. Spans become Jaeger spans in the same way
+/// as they are converted into OpenTelemetry spans by [`to_otlp`], so a span starts when it was
+/// created and ends when it was closed. The fields of a span and the location of its callsite
+/// are added as tags, and all spans belong to a single process named `service_name`.
+///
+/// Events become logs on their parent span, with the message of the event as the `event` field.
+/// Events without a parent span can't be shown in Jaeger, so they aren't included.
+///
+/// # Errors
+///
+/// Returns an error if reading the recording or writing the traces fails, if a line of the
+/// recording can't be deserialized into a record, or if the recording was written in a newer
+/// version of the format.
+///
+/// # Examples
+///
+/// ```
+/// let recording = concat!(
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":1,"fields":[{"name":"answer","value":{"I64":42}}],"metadata":{"id":4403349456,"name":"span","target":"record_spans","level":"Info","module_path":"record_spans","file":"tracing-rec/examples/record-spans.rs","line":8,"fields":["answer"],"kind":"Span"},"parent":"Root"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543410,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543420,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":4403349608,"name":"event","target":"record_spans","level":"Info","module_path":null,"file":null,"line":null,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543430,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Exit":1}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543440,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Close":1}}"#,
+///     "\n",
+/// );
+///
+/// let mut traces = Vec::new();
+/// tracing_cassette::to_jaeger(recording.as_bytes(), &mut traces, "example").unwrap();
+///
+/// let traces: serde_json::Value = serde_json::from_slice(&traces).unwrap();
+/// let trace = &traces["data"][0];
+/// assert_eq!(trace["processes"]["p1"]["serviceName"], "example");
+///
+/// let span = &trace["spans"][0];
+/// assert_eq!(span["operationName"], "span");
+/// assert_eq!(span["startTime"], 1715177340543400_u64);
+/// assert_eq!(span["duration"], 40);
+/// assert_eq!(span["tags"][0]["key"], "answer");
+/// assert_eq!(span["tags"][0]["value"], 42);
+///
+/// let log = &span["logs"][0];
+/// assert_eq!(log["timestamp"], 1715177340543420_u64);
+/// assert_eq!(log["fields"][0]["key"], "event");
+/// assert_eq!(log["fields"][0]["value"], "I am an info event!");
+/// ```
+///
+/// [`to_otlp`]: fn@crate::to_otlp
+pub fn to_jaeger<R, W>(reader: R, mut writer: W, service_name: &str) -> Result<(), ExportError>
+where
+    R: BufRead,
+    W: Write,
+{
+    let (spans, logs) = otlp::otlp_spans_and_logs(reader)?;
+
+    // Traces are written in the order that their first span was created.
+    let mut traces: Vec<(String, Vec<Value>)> = Vec::new();
+    let mut spans: Vec<(&Value, Value)> = spans
+        .iter()
+        .map(|otlp_span| (otlp_span, jaeger_span(otlp_span)))
+        .collect();
+    spans.sort_by_key(|(otlp_span, _)| micros(&otlp_span["startTimeUnixNano"]));
+    let span_indices: HashMap<(&str, &str), usize> = spans
+        .iter()
+        .enumerate()
+        .map(|(idx, (otlp_span, _))| {
+            let trace_id = otlp_span["traceId"].as_str().unwrap_or_default();
+            let span_id = otlp_span["spanId"].as_str().unwrap_or_default();
+            ((trace_id, span_id), idx)
+        })
+        .collect();
+    for log in &logs {
+        let trace_id = log.get("traceId").and_then(Value::as_str);
+        let span_id = log.get("spanId").and_then(Value::as_str);
+        if let Some(&idx) = trace_id
+            .zip(span_id)
+            .and_then(|context| span_indices.get(&context))
+        {
+            spans[idx].1["logs"]
+                .as_array_mut()
+                .expect("logs is an array")
+                .push(jaeger_log(log));
+        }
+    }
+    let mut trace_indices: HashMap<String, usize> = HashMap::new();
+    for (_, span) in spans {
+        let trace_id = span["traceID"].as_str().unwrap_or_default().to_owned();
+        let idx = *trace_indices.entry(trace_id.clone()).or_insert_with(|| {
+            traces.push((trace_id, Vec::new()));
+            traces.len() - 1
+        });
+        traces[idx].1.push(span);
+    }
+
+    let data: Vec<Value> = traces
+        .into_iter()
+        .map(|(trace_id, spans)| {
+            json!({
+                "traceID": trace_id,
+                "spans": spans,
+                "processes": {
+                    PROCESS_ID: { "serviceName": service_name, "tags": [] },
+                },
+                "warnings": null,
+            })
+        })
+        .collect();
+    serde_json::to_writer(&mut writer, &json!({ "data": data })).map_err(io::Error::from)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Rewrites an OTLP span as a Jaeger span, without any logs.
+fn jaeger_span(otlp_span: &Value) -> Value {
+    let trace_id = &otlp_span["traceId"];
+    let references = match otlp_span.get("parentSpanId") {
+        Some(parent_span_id) => json!([{
+            "refType": "CHILD_OF",
+            "traceID": trace_id,
+            "spanID": parent_span_id,
+        }]),
+        None => json!([]),
+    };
+    let start = micros(&otlp_span["startTimeUnixNano"]);
+    let end = micros(&otlp_span["endTimeUnixNano"]);
+
+    json!({
+        "traceID": trace_id,
+        "spanID": otlp_span["spanId"],
+        "operationName": otlp_span["name"],
+        "references": references,
+        "startTime": start,
+        "duration": end.saturating_sub(start),
+        "tags": tags(&otlp_span["attributes"]),
+        "logs": [],
+        "processID": PROCESS_ID,
+        "warnings": null,
+    })
+}
+
+/// Rewrites an OTLP log record as a log on a Jaeger span.
+fn jaeger_log(otlp_log: &Value) -> Value {
+    let mut fields = vec![
+        tag("event", &otlp_log["body"]),
+        json!({ "key": "level", "type": "string", "value": otlp_log["severityText"] }),
+    ];
+    fields.extend(tags(&otlp_log["attributes"]));
+
+    json!({
+        "timestamp": micros(&otlp_log["timeUnixNano"]),
+        "fields": fields,
+    })
+}
+
+fn tags(attributes: &Value) -> Vec<Value> {
+    attributes
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|attribute| {
+            tag(
+                attribute["key"].as_str().unwrap_or_default(),
+                &attribute["value"],
+            )
+        })
+        .collect()
+}
+
+/// Rewrites an OTLP attribute value as a Jaeger tag.
+fn tag(key: &str, any_value: &Value) -> Value {
+    let (tag_type, value) = if let Some(value) = any_value.get("stringValue") {
+        ("string", value.clone())
+    } else if let Some(value) = any_value.get("intValue") {
+        // OTLP JSON writes 64-bit integers as strings, Jaeger writes them as numbers.
+        let value = value
+            .as_str()
+            .and_then(|value| value.parse::<i64>().ok())
+            .map_or(Value::Null, Value::from);
+        ("int64", value)
+    } else if let Some(value) = any_value.get("doubleValue") {
+        ("float64", value.clone())
+    } else if let Some(value) = any_value.get("boolValue") {
+        ("bool", value.clone())
+    } else {
+        ("string", Value::String(any_value.to_string()))
+    };
+
+    json!({ "key": key, "type": tag_type, "value": value })
+}
+
+/// Converts a timestamp in nanoseconds, written as a string, into microseconds.
+fn micros(nanos: &Value) -> u64 {
+    let nanos: u128 = nanos
+        .as_str()
+        .and_then(|nanos| nanos.parse().ok())
+        .unwrap_or_default();
+    u64::try_from(nanos / 1_000).unwrap_or(u64::MAX)
+}
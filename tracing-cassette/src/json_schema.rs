@@ -0,0 +1,93 @@
+use std::borrow::Cow;
+
+use schemars::{
+    generate::SchemaSettings, json_schema, transform::RecursiveTransform, JsonSchema, Schema,
+    SchemaGenerator,
+};
+
+use crate::{version::HeaderLine, TraceContext, TraceRecord};
+
+/// A single line of a recording, which is either the header or a record.
+#[derive(JsonSchema)]
+#[schemars(
+    title = "tracing-cassette recording line",
+    description = "A line of a tracing recording, written as JSON lines. The first line is the \
+                   header, each of the others is a record."
+)]
+#[serde(untagged)]
+// Only the schema of the enum is used, it is never constructed.
+#[allow(dead_code, clippy::large_enum_variant)]
+enum RecordingLine {
+    Header(HeaderLine),
+    Record(TraceRecord),
+}
+
+/// Returns the JSON Schema of a line of a recording, in the current [`FORMAT_VERSION`].
+///
+/// A recording is written as JSON lines: the first line is the [`Header`], and each line after it
+/// is a [`TraceRecord`]. The schema matches either of them, and is generated from the types, so it
+/// is the canonical description of the format. Programs which write or read recordings without
+/// this crate, in other languages, can validate each line they write or read against it.
+///
+/// The schema follows JSON Schema draft 2020-12. Readers of the format accept some older
+/// encodings which aren't written any more, such as fields written as a pair of their name and
+/// value, which the schema doesn't describe.
+///
+/// # Examples
+///
+/// ```
+/// let schema = tracing_cassette::json_schema();
+///
+/// assert_eq!(schema["$schema"], "https://json-schema.org/draft/2020-12/schema");
+/// assert_eq!(schema["title"], "tracing-cassette recording line");
+/// assert!(schema["$defs"]["TraceRecord"]["properties"]["trace"].is_object());
+/// ```
+///
+/// [`FORMAT_VERSION`]: crate::FORMAT_VERSION
+/// [`Header`]: crate::Header
+#[must_use]
+pub fn json_schema() -> serde_json::Value {
+    SchemaSettings::draft2020_12()
+        .with_transform(RecursiveTransform(without_examples))
+        .into_generator()
+        .into_root_schema_for::<RecordingLine>()
+        .to_value()
+}
+
+impl JsonSchema for TraceContext {
+    fn schema_name() -> Cow<'static, str> {
+        "TraceContext".into()
+    }
+
+    fn json_schema(_generator: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "description": "The distributed trace context of a span, as identified in the W3C \
+                            Trace Context, with the Ids as hex strings which aren't all zero.",
+            "type": "object",
+            "properties": {
+                "trace_id": {
+                    "type": "string",
+                    "pattern": "^[0-9a-fA-F]{32}$",
+                },
+                "span_id": {
+                    "type": "string",
+                    "pattern": "^[0-9a-fA-F]{16}$",
+                },
+            },
+            "required": ["trace_id", "span_id"],
+        })
+    }
+}
+
+/// Removes the sections after the summary of the description of `schema`, which are taken from
+/// the documentation of the types, as their examples are Rust code.
+fn without_examples(schema: &mut Schema) {
+    let description = schema
+        .as_object_mut()
+        .and_then(|schema| schema.get_mut("description"));
+    if let Some(serde_json::Value::String(description)) = description {
+        if let Some(end) = description.find("\n\n# ") {
+            description.truncate(end);
+        }
+    }
+}
@@ -0,0 +1,372 @@
+//! Comparing how long spans last between two recordings.
+
+use std::{cmp::Ordering, collections::HashMap, io::BufRead, time::Duration};
+
+use crate::{
+    export::{self, ExportError},
+    SpanId, Trace,
+};
+
+/// Compares the latency of spans between a baseline recording and a candidate, such as from
+/// the last release and the next one, to find the span callsites which got slower.
+///
+/// The latency of a span is the time from when it was created until it was closed, spans which
+/// aren't closed in the recording aren't counted. Callsites are matched between the recordings
+/// by their target and name, as their Ids are assigned when the program runs. Callsites which
+/// have the same target and name, such as spans created in more than one place, are counted
+/// together.
+///
+/// For each callsite with at least [`with_min_samples`] spans in both recordings, the latencies
+/// are compared with a one-sided Mann-Whitney U test, which doesn't assume that latencies are
+/// normally distributed. A callsite has regressed when the test finds that its spans are slower
+/// in the candidate, with a p-value below [`with_significance`], and its median latency grew by
+/// more than [`with_threshold`], so that tiny but consistent slowdowns don't fail a
+/// performance gate.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use tracing_cassette::{LatencyComparison, RecordingBuilder, SpanBuilder};
+///
+/// let recording = |millis: u64| {
+///     let mut recording = RecordingBuilder::new();
+///     recording.thread("main", |thread| {
+///         for idx in 0..20 {
+///             let request = SpanBuilder::new("request")
+///                 .with_duration(Duration::from_millis(millis + idx % 5));
+///             thread.span(request, |_| {});
+///             thread.span(SpanBuilder::new("health_check"), |_| {});
+///         }
+///     });
+///     let mut written = Vec::new();
+///     recording.write(&mut written).unwrap();
+///     written
+/// };
+/// let baseline = recording(10);
+/// let candidate = recording(15);
+///
+/// let report = LatencyComparison::new()
+///     .compare(baseline.as_slice(), candidate.as_slice())
+///     .unwrap();
+///
+/// let regressions: Vec<_> = report.regressions().map(|latency| &latency.name).collect();
+/// assert_eq!(regressions, ["request"]);
+/// let request = &report.callsites[0];
+/// assert!(request.baseline.median < request.candidate.median);
+/// assert!(request.p_value.unwrap() < 0.05);
+/// ```
+///
+/// [`with_min_samples`]: fn@Self::with_min_samples
+/// [`with_significance`]: fn@Self::with_significance
+/// [`with_threshold`]: fn@Self::with_threshold
+#[derive(Clone, Debug)]
+pub struct LatencyComparison {
+    threshold: f64,
+    significance: f64,
+    min_samples: usize,
+}
+
+impl Default for LatencyComparison {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LatencyComparison {
+    /// Creates a comparison which finds the callsites whose median latency grew by more than
+    /// 10%, with a p-value below 0.05, from at least 5 spans in each recording.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            threshold: 0.1,
+            significance: 0.05,
+            min_samples: 5,
+        }
+    }
+
+    /// Sets how much the median latency must grow by to be a regression, as a fraction of the
+    /// baseline median, the default is 0.1 for 10%.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threshold` is negative.
+    #[must_use]
+    pub fn with_threshold(mut self, threshold: f64) -> Self {
+        assert!(
+            threshold >= 0.0,
+            "the regression threshold must not be negative, not {threshold}"
+        );
+        self.threshold = threshold;
+        self
+    }
+
+    /// Sets the p-value which a slowdown must be below to be a regression, the default is 0.05.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `significance` isn't between 0 and 1.
+    #[must_use]
+    pub fn with_significance(mut self, significance: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&significance),
+            "the significance level must be between 0 and 1, not {significance}"
+        );
+        self.significance = significance;
+        self
+    }
+
+    /// Sets the number of spans that a callsite needs in each recording to be tested, the
+    /// default is 5.
+    #[must_use]
+    pub fn with_min_samples(mut self, min_samples: usize) -> Self {
+        self.min_samples = min_samples;
+        self
+    }
+
+    /// Reads the baseline and candidate recordings and compares the latency of their spans.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading either recording fails, if a line of a recording can't be
+    /// deserialized into a record, or if a recording was written in a newer version of the
+    /// format.
+    pub fn compare<B, C>(&self, baseline: B, candidate: C) -> Result<LatencyReport, ExportError>
+    where
+        B: BufRead,
+        C: BufRead,
+    {
+        let mut baseline = latencies(baseline)?;
+        let candidate = latencies(candidate)?;
+
+        let mut callsites: Vec<CallsiteLatency> = candidate
+            .into_iter()
+            .filter_map(|(key, mut candidate)| {
+                let mut baseline = baseline.remove(&key)?;
+                baseline.sort_unstable();
+                candidate.sort_unstable();
+                let p_value = (baseline.len() >= self.min_samples.max(1)
+                    && candidate.len() >= self.min_samples.max(1))
+                .then(|| mann_whitney_p_value(&baseline, &candidate));
+                let baseline = LatencySummary::new(&baseline);
+                let candidate = LatencySummary::new(&candidate);
+                let slower = candidate.median.as_secs_f64()
+                    > baseline.median.as_secs_f64() * (1.0 + self.threshold);
+                let regression =
+                    slower && p_value.is_some_and(|p_value| p_value < self.significance);
+                let (target, name) = key;
+                Some(CallsiteLatency {
+                    target,
+                    name,
+                    baseline,
+                    candidate,
+                    p_value,
+                    regression,
+                })
+            })
+            .collect();
+        // Regressions first, then the biggest slowdowns.
+        callsites.sort_by(|left, right| {
+            right
+                .regression
+                .cmp(&left.regression)
+                .then_with(|| {
+                    right
+                        .median_change()
+                        .partial_cmp(&left.median_change())
+                        .unwrap_or(Ordering::Equal)
+                })
+                .then_with(|| (&left.target, &left.name).cmp(&(&right.target, &right.name)))
+        });
+
+        Ok(LatencyReport { callsites })
+    }
+}
+
+/// The latency of the span callsites in two recordings, see [`LatencyComparison`].
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct LatencyReport {
+    /// The span callsites which are in both recordings, the regressions first, then by how much
+    /// their median latency grew, most first.
+    pub callsites: Vec<CallsiteLatency>,
+}
+
+impl LatencyReport {
+    /// Returns the callsites which regressed.
+    pub fn regressions(&self) -> impl Iterator<Item = &CallsiteLatency> {
+        self.callsites.iter().filter(|latency| latency.regression)
+    }
+}
+
+/// The latency of the spans of a callsite in both recordings, see [`LatencyReport::callsites`].
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallsiteLatency {
+    /// The target of the callsite.
+    pub target: String,
+    /// The name of the callsite.
+    pub name: String,
+    /// The latency of the spans in the baseline recording.
+    pub baseline: LatencySummary,
+    /// The latency of the spans in the candidate recording.
+    pub candidate: LatencySummary,
+    /// The probability of the candidate's spans being at least this much slower by chance, or
+    /// `None` if there weren't enough spans in one of the recordings to test.
+    pub p_value: Option<f64>,
+    /// Whether the callsite regressed.
+    pub regression: bool,
+}
+
+impl CallsiteLatency {
+    /// Returns how much the median latency changed, as a fraction of the baseline median, such
+    /// as 0.5 when it is 50% slower in the candidate.
+    #[must_use]
+    pub fn median_change(&self) -> f64 {
+        let baseline = self.baseline.median.as_secs_f64();
+        let candidate = self.candidate.median.as_secs_f64();
+        if baseline == 0.0 {
+            if candidate == 0.0 {
+                0.0
+            } else {
+                f64::INFINITY
+            }
+        } else {
+            candidate / baseline - 1.0
+        }
+    }
+}
+
+/// The distribution of the latencies of the spans of a callsite in one recording.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct LatencySummary {
+    /// The number of spans.
+    pub count: usize,
+    /// The median latency.
+    pub median: Duration,
+    /// The 95th percentile latency.
+    pub p95: Duration,
+    /// The longest latency.
+    pub max: Duration,
+}
+
+impl LatencySummary {
+    /// Summarizes latencies, which are sorted.
+    fn new(sorted: &[Duration]) -> Self {
+        Self {
+            count: sorted.len(),
+            median: percentile(sorted, 0.5),
+            p95: percentile(sorted, 0.95),
+            max: sorted.last().copied().unwrap_or_default(),
+        }
+    }
+}
+
+/// Returns the latencies of the closed spans in a recording, by target and name.
+fn latencies<R: BufRead>(
+    reader: R,
+) -> Result<HashMap<(String, String), Vec<Duration>>, ExportError> {
+    let mut latencies: HashMap<(String, String), Vec<Duration>> = HashMap::new();
+    // When each open span was created, and its target and name.
+    let mut open: HashMap<SpanId, (Duration, (String, String))> = HashMap::new();
+    export::for_each_record(reader, |record| {
+        let ts = record.meta.timestamp();
+        match record.trace {
+            Trace::NewSpan(new_span) => {
+                let key = (new_span.metadata.target, new_span.metadata.name);
+                open.insert(new_span.id, (ts, key));
+            }
+            Trace::Close(id) => {
+                if let Some((created, key)) = open.remove(&id) {
+                    latencies
+                        .entry(key)
+                        .or_default()
+                        .push(ts.saturating_sub(created));
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    })?;
+
+    Ok(latencies)
+}
+
+/// Returns the nearest-rank percentile of sorted latencies.
+fn percentile(sorted: &[Duration], fraction: f64) -> Duration {
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    let rank = (sorted.len() as f64 * fraction).ceil() as usize;
+    sorted
+        .get(rank.saturating_sub(1))
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Returns the p-value of a one-sided Mann-Whitney U test of whether the candidate latencies
+/// tend to be longer than the baseline ones, both sorted.
+///
+/// The p-value comes from the normal approximation, with a continuity correction and the
+/// variance corrected for ties, which is close enough for the sample sizes of recordings.
+#[allow(clippy::cast_precision_loss)]
+fn mann_whitney_p_value(baseline: &[Duration], candidate: &[Duration]) -> f64 {
+    // Merge the sorted samples, ranking each run of ties with the mean of their ranks.
+    let mut candidate_rank_sum = 0.0;
+    let mut tie_correction = 0.0;
+    let (mut b, mut c) = (0, 0);
+    let mut rank = 1.0;
+    while b < baseline.len() || c < candidate.len() {
+        let value = match (baseline.get(b), candidate.get(c)) {
+            (Some(left), Some(right)) => *left.min(right),
+            (Some(value), None) | (None, Some(value)) => *value,
+            (None, None) => unreachable!(),
+        };
+        let ties_b = baseline[b..].iter().take_while(|v| **v == value).count();
+        let ties_c = candidate[c..].iter().take_while(|v| **v == value).count();
+        let ties = (ties_b + ties_c) as f64;
+        let mean_rank = rank + (ties - 1.0) / 2.0;
+        candidate_rank_sum += mean_rank * ties_c as f64;
+        tie_correction += ties * ties * ties - ties;
+        rank += ties;
+        b += ties_b;
+        c += ties_c;
+    }
+
+    let n_b = baseline.len() as f64;
+    let n_c = candidate.len() as f64;
+    let n = n_b + n_c;
+    let u = candidate_rank_sum - n_c * (n_c + 1.0) / 2.0;
+    let mean = n_b * n_c / 2.0;
+    let variance = n_b * n_c / 12.0 * ((n + 1.0) - tie_correction / (n * (n - 1.0)));
+    if variance <= 0.0 {
+        // Every latency is the same.
+        return 1.0;
+    }
+    let z = (u - mean - 0.5) / variance.sqrt();
+    0.5 * erfc(z / std::f64::consts::SQRT_2)
+}
+
+/// The complementary error function, with a relative error below 1.2e-7.
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = -z * z - 1.265_512_23
+        + t * (1.000_023_68
+            + t * (0.374_091_96
+                + t * (0.096_784_18
+                    + t * (-0.186_288_06
+                        + t * (0.278_868_07
+                            + t * (-1.135_203_98
+                                + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77))))))));
+    let erfc = t * poly.exp();
+    if x >= 0.0 {
+        erfc
+    } else {
+        2.0 - erfc
+    }
+}
@@ -1 +1,365 @@
+//! The format of `tracing` recordings.
+//!
+//! # Overview
+//!
+//! The `tracing-cassette` crate defines the records which make up a recording. It is shared by
+//! `tracing-rec`, which writes recordings, and `tracing-replay`, which reads them, so that both
+//! sides agree on the format.
+//!
+//! A recording is a sequence of lines, each of which is a single [`TraceRecord`] serialized as
+//! JSON. A record contains a recorded call to the subscriber, the [`Trace`], together with the
+//! time and thread it was recorded on.
+//!
+//! Each type has a borrowed form, such as [`TraceRecordRef`] for [`TraceRecord`], which borrows
+//! its strings instead of owning them. Both forms have the same serialized format.
+//!
+//! Recordings can also be built in code with a [`RecordingBuilder`], which is useful for fixtures
+//! in tests and for load generators, without running an instrumented program.
+//!
+//! # Versions
+//!
+//! The first line of a recording is a [`Header`] which contains the version of the format that
+//! the recording was written in. Recordings written in an earlier version can still be read: the
+//! records are migrated to the current [`FORMAT_VERSION`] with [`migrate`] as they are read.
+//! Recordings written in a newer version are rejected with [`FormatVersionError::TooNew`], as
+//! there is no way to know what has changed.
+//!
+//! Recordings written before the header was added don't have one, these are in version 1.
+//!
+//! Adding a new kind of [`Trace`] doesn't change the version, as the existing records are
+//! unchanged. Readers which don't know about a kind of trace may skip those records, as
+//! `tracing-replay` does.
+//!
+//! Recordings in any supported version can be read with [`RecordReader`], which migrates the
+//! records as they are read.
+//!
+//! # Containers
+//!
+//! Recordings can also be stored in a container, written with [`ContainerWriter`]. A container
+//! starts with the [`CONTAINER_MAGIC`] bytes and a header, then holds the records in segments,
+//! which can be compressed, and ends with a footer. Each segment, and the container as a whole,
+//! has a checksum, so corrupt recordings are found when they are read, as is a container which
+//! was never finished.
+//!
+//! Containers are read by [`RecordReader`], [`validate_stream`], [`RecordingIndex::build`],
+//! and the exporters in the same way as recordings of JSON lines, by way of a
+//! [`ContainerReader`], so both can be used wherever a recording is read.
+//!
+//! Large recordings can be indexed with a [`RecordingIndex`], which is stored next to the
+//! recording and allows readers to seek to a point in time, or to load the records of a single
+//! thread, without reading the whole recording.
+//!
+//! Records can be rendered on a single line for people to read with [`PrettyRecord`], and whole
+//! recordings with [`to_pretty`].
+//!
+//! # Validation
+//!
+//! Recordings which are written by other implementations of the format can be checked with
+//! [`validate_stream`], which reports every [`Violation`] of the format along with the line it
+//! was found on. Single records can be checked with [`validate`].
+//!
+//! Recordings which were cut short, such as when the program crashed, can be made consistent
+//! with [`repair`], which closes the spans that were left open and removes records that refer to
+//! spans which don't exist, and reports each [`Repair`] that it made.
+//!
+//! Records which are missing from a recording, such as those dropped by a writer which fell
+//! behind, can be found with [`GapDetection`], from heartbeats, sequence numbers, and dropped
+//! record markers which the recorded program writes. It reports each [`Gap`] and the threads it
+//! affects, so that the parts of a replay which can't be trusted are known.
+//!
+//! The fields of each callsite can be checked with a [`Schema`], which is inferred from the
+//! values recorded. It has the types and the number of distinct values of each field, and flags
+//! the fields which were recorded with values of more than one type, which usually points to a
+//! bug in the instrumentation.
+//!
+//! # Querying
+//!
+//! The spans and events of a recording can be selected with a [`Query`], such as
+//! `level >= WARN && target starts_with "my_app"`. [`Query::filter`] keeps only the matching
+//! spans and events, together with their callsites and ancestor spans, so that the result can
+//! still be replayed.
+//!
+//! Analysis which needs the tree of spans and events, rather than the flat stream of records,
+//! can load a recording into a [`TraceTree`]. It resolves the parents of spans and events,
+//! applies the values recorded for spans, computes how long spans lasted and were busy for, and
+//! indexes spans and events by name, target, and time.
+//!
+//! What a program was doing at a point in time, such as when an incident started, can be found
+//! with a [`SpanSnapshot`]. It has the spans which were open at that instant on each recorded
+//! thread, with the values of their fields at the time and how long they had been open for.
+//!
+//! Recordings can be cut down to a time range with [`trim`], which carries forward the callsites
+//! and the spans which are open at the start of the range, so that the result can still be
+//! replayed. Recordings which were split up, or of separate sessions, can be joined into one
+//! with [`concat`], which shifts their timestamps so that they follow on from each other. A
+//! recording can also be split up with [`split`], into one recording for each target or crate,
+//! which each keep the spans that their traces were recorded in.
+//!
+//! # Comparing
+//!
+//! Two recordings can be compared with [`diff`], which finds the callsites, spans, events, and
+//! field values that differ between them, while ignoring when they were recorded and the Ids
+//! they were given. This can be used to check the traces of a program against a recording which
+//! is known to be good.
+//!
+//! A recording can be rewritten into a canonical form with [`normalize`], which numbers its Ids
+//! in order, makes its timestamps start at 0, and sorts the fields of each trace, so that
+//! recordings of the same traces are the same. Whether two recordings are the same trace is
+//! checked with [`structurally_equal`].
+//!
+//! # Statistics
+//!
+//! The hotspots of a recording can be found with [`Stats`], which reports the callsites with the
+//! most spans and events, the spans which were busy for the longest in total, and the number of
+//! events of each target over time.
+//!
+//! The latency of spans can be compared between a baseline recording and a candidate with
+//! [`LatencyComparison`], which reports the span callsites that got significantly slower, so
+//! that recordings can back performance gates in a release process.
+//!
+//! Where the bytes of a recording go can be found with [`Sizes`], which reports the size of each
+//! kind of record and of the largest callsites, and estimates how much would be saved by
+//! deduplicating callsite metadata, compressing the recording into a container, or encoding it
+//! in binary.
+//!
+//! # Anonymizing
+//!
+//! Recordings can contain data which shouldn't be shared. An [`Anonymizer`] rewrites a recording
+//! with the values of its fields hashed, replaced with tokens, or removed, depending on the name
+//! of the field, and can also remove the locations and targets of the callsites. The anonymized
+//! recording has the same structure, so it can still be replayed, and attached to a public bug
+//! report.
+//!
+//! Recordings can be made smaller before they are shared or archived with a [`Downsampler`],
+//! which drops a fraction of the events, at random or evenly across callsites, while keeping the
+//! callsites and the whole lifecycle of every span.
+//!
+//! # Exporting
+//!
+//! Recordings can be exported to Chrome's trace event format with [`to_chrome_trace`], so that
+//! they can be viewed in `chrome://tracing` or the Perfetto UI. Large recordings are better
+//! exported to Perfetto's protobuf format with [`to_perfetto_trace`].
+//!
+//! The time spent in each span can be rendered as a flamegraph by exporting a recording as
+//! folded stacks with [`to_folded_stacks`], or explored in speedscope by exporting it with
+//! [`to_speedscope`].
+//!
+//! Recordings can also be converted into OpenTelemetry spans and logs with [`to_otlp`], which
+//! can then be sent to an OTLP collector with `send_otlp`, so that they can be analyzed in
+//! tracing backends such as Jaeger. Recordings can also be exported to Jaeger's own JSON
+//! format with [`to_jaeger`], which can be uploaded into the Jaeger UI directly.
+//!
+//! A recording which is known to be good can be turned into `tracing-mock` expectations with
+//! [`to_tracing_mock`], so that tests can check that the code still produces the same traces.
+//!
+//! What happened in a recording can be shared with people who don't have any tooling installed
+//! by rendering it into a self-contained HTML page with [`to_html`], which shows a timeline of
+//! the spans on each thread, the stream of events, and their fields.
+//!
+//! Large recordings can be analyzed with SQL or data frames, in DuckDB or Polars, by exporting
+//! them to Parquet with `to_parquet`, which writes one table of the events and one of the span
+//! lifecycle.
+//!
+//! # Importing
+//!
+//! OpenTelemetry spans and logs exported over OTLP, as JSON or protobuf, can be turned into a
+//! recording with [`OtlpImport`]. The recording can then be replayed into any `tracing`
+//! subscriber, such as one which is being developed.
+//!
+//! # Crate Features
+//!
+//! - `arbitrary`: Implements `arbitrary::Arbitrary` for the records, and adds
+//!   `ArbitraryRecording`, which generates whole recordings that are structurally valid, for
+//!   property tests and fuzzing.
+//! - `otlp`: Adds `send_otlp`, which sends recordings converted with [`to_otlp`] to an
+//!   OTLP/HTTP collector.
+//! - `parquet`: Adds `to_parquet`, which exports recordings to Parquet files for analysis with
+//!   SQL or data frames.
+//! - `schemars`: Implements `schemars::JsonSchema` for the records, and adds `json_schema`,
+//!   which returns the JSON Schema of the format, for validating programs which write or read
+//!   recordings in other languages.
+//!
+//! # Usage
+//!
+//! Records can be written and read back with any `serde` data format, although recordings are
+//! written as JSON.
+//!
+//! ```
+//! use tracing_cassette::{RecordMeta, SpanId, Trace, TraceRecord};
+//!
+//! let record = TraceRecord {
+//!     meta: RecordMeta {
+//!         timestamp_s: 1715177340,
+//!         timestamp_subsec_us: 543400,
+//!         thread_id: "ThreadId(1)".into(),
+//!         thread_name: Some("main".into()),
+//!     },
+//!     trace: Trace::Enter(SpanId::from(1)),
+//!     final_fields: None,
+//!     sequence: None,
+//! };
+//!
+//! let line = serde_json::to_string(&record).unwrap();
+//! assert_eq!(
+//!     line,
+//!     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+//! );
+//! assert_eq!(serde_json::from_str::<TraceRecord>(&line).unwrap(), record);
+//! ```
+//!
+//! Every kind of record survives a round trip, in both forms:
+//!
+//! ```
+//! use tracing_cassette::{TraceRecord, TraceRecordRef};
+//!
+//! let recording = concat!(
+//!     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"RegisterCallsite":{"id":4403349456,"name":"span","target":"record_spans","level":"Info","module_path":"record_spans","file":"tracing-rec/examples/record-spans.rs","line":8,"fields":["answer"],"kind":"Span"}}}"#,
+//!     "\n",
+//!     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543410,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":1,"fields":[{"name":"answer","value":{"I64":42}}],"metadata":{"id":4403349456,"name":"span","target":"record_spans","level":"Info","module_path":"record_spans","file":"tracing-rec/examples/record-spans.rs","line":8,"fields":["answer"],"kind":"Span"},"parent":"Root"}}}"#,
+//!     "\n",
+//!     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543420,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+//!     "\n",
+//!     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543430,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Record":{"id":1,"fields":[{"name":"answer","value":{"U128":43}}]}}}"#,
+//!     "\n",
+//!     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543440,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an \"info\" event!"}},{"name":"ratio","value":{"F64":0.5}},{"name":"ok","value":{"Bool":true}},{"name":"text","value":{"Str":"text"}}],"metadata":{"id":4403349608,"name":"event","target":"record_spans","level":"Warn","module_path":null,"file":null,"line":null,"fields":["message","ratio","ok","text"],"kind":"Event"},"parent":{"Explicit":1}}}}"#,
+//!     "\n",
+//!     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543450,"thread_id":"ThreadId(2)","thread_name":null},"trace":{"FollowsFrom":{"cause_id":2,"effect_id":1}}}"#,
+//!     "\n",
+//!     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543460,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Exit":1}}"#,
+//!     "\n",
+//!     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543470,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Close":1}}"#,
+//!     "\n",
+//! );
+//!
+//! for line in recording.lines() {
+//!     let record: TraceRecord = serde_json::from_str(line).unwrap();
+//!     assert_eq!(serde_json::to_string(&record).unwrap(), line);
+//!
+//!     let record_ref: TraceRecordRef<'_> = serde_json::from_str(line).unwrap();
+//!     assert_eq!(serde_json::to_string(&record_ref).unwrap(), line);
+//!     assert_eq!(TraceRecord::from(record_ref), record);
+//! }
+//! ```
+//!
+//! # Supported Rust Versions
+//!
+//! `tracing-cassette` is built against the latest stable release. The minimum supported version
+//! is 1.76. The current version of `tracing-cassette` is not guaranteed to build on Rust versions
+//! earlier than the minimum supported version.
+//!
+//! # License
+//!
+//! This project is licensed under the [MIT license].
+//!
+//! [MIT license]: https://github.com/hds/tracing-rec-replay/blob/main/LICENSE
+//!
+//! # Contribution
+//!
+//! Unless you explicitly state otherwise, any contribution intentionally submitted for inclusion
+//! in `tracing-cassette` by you, shall be licensed as MIT, without any additional terms or
+//! conditions.
 
+mod anonymize;
+#[cfg(feature = "arbitrary")]
+mod arbitrary;
+mod borrowed;
+mod builder;
+mod chrome;
+mod concat;
+mod container;
+mod convert;
+mod diff;
+mod downsample;
+mod export;
+mod folded;
+mod gaps;
+mod html;
+mod index;
+mod jaeger;
+#[cfg(feature = "schemars")]
+mod json_schema;
+mod latency;
+mod mock;
+mod normalize;
+mod otlp;
+mod otlp_import;
+#[cfg(feature = "parquet")]
+mod parquet;
+mod perfetto;
+mod pretty;
+mod protobuf;
+mod query;
+mod reader;
+mod record;
+mod repair;
+mod schema;
+mod size;
+mod snapshot;
+mod speedscope;
+mod split;
+mod stats;
+mod tree;
+mod trim;
+mod validate;
+mod version;
+
+#[cfg(feature = "arbitrary")]
+pub use crate::arbitrary::ArbitraryRecording;
+#[cfg(feature = "schemars")]
+pub use crate::json_schema::json_schema;
+#[cfg(feature = "otlp")]
+pub use crate::otlp::send_otlp;
+#[cfg(feature = "parquet")]
+pub use crate::parquet::to_parquet;
+pub use crate::{
+    anonymize::{Anonymizer, FieldAction},
+    borrowed::{
+        owned_fields, CowStr, EventRef, FieldRef, FieldValueRef, MetadataRef, NewSpanRef,
+        RecordMetaRef, RecordValuesRef, TraceRecordRef, TraceRef,
+    },
+    builder::{EventBuilder, RecordingBuilder, SpanBuilder, ThreadBuilder},
+    chrome::{to_chrome_trace, ChromeSpanPhase},
+    concat::concat,
+    container::{
+        Compression, ContainerError, ContainerReader, ContainerWriter, CONTAINER_MAGIC,
+        CONTAINER_VERSION,
+    },
+    diff::{diff, Difference},
+    downsample::Downsampler,
+    export::ExportError,
+    folded::to_folded_stacks,
+    gaps::{Gap, GapDetection, GapKind, GapReport},
+    html::to_html,
+    index::{
+        IndexCheckpoint, IndexPosition, IndexedCallsite, RecordingIndex, ThreadCheckpoint,
+        ThreadIndex, INDEX_VERSION,
+    },
+    jaeger::to_jaeger,
+    latency::{CallsiteLatency, LatencyComparison, LatencyReport, LatencySummary},
+    mock::to_tracing_mock,
+    normalize::{normalize, structurally_equal},
+    otlp::{to_otlp, OtlpRequests},
+    otlp_import::{ImportError, OtlpImport},
+    perfetto::to_perfetto_trace,
+    pretty::{to_pretty, PrettyRecord},
+    query::{Literal, Operator, Query, QueryError, Subject},
+    reader::RecordReader,
+    record::{
+        Event, Field, FieldValue, FollowsFrom, Kind, Level, Metadata, NewSpan, Parent, RecordMeta,
+        RecordValues, SpanId, Trace, TraceContext, TraceRecord,
+    },
+    repair::{repair, Repair},
+    schema::{CallsiteSchema, FieldSchema, FieldType, Schema, MAX_DISTINCT_VALUES},
+    size::{CallsiteSize, KindSize, SizeReport, Sizes},
+    snapshot::{OpenSpan, SpanSnapshot, ThreadSnapshot},
+    speedscope::to_speedscope,
+    split::{split, SplitBy},
+    stats::{CallsiteCount, SpanBusyTime, Stats, StatsReport, TargetEvents},
+    tree::{EventNode, NodeRef, SpanNode, TraceTree},
+    trim::trim,
+    validate::{validate, validate_stream, LineViolation, Violation, MAX_FIELDS},
+    version::{
+        check_version, migrate, needs_migration, write_recording, FormatVersionError, Header,
+        FORMAT_VERSION, UNVERSIONED_FORMAT_VERSION,
+    },
+};
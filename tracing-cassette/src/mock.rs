@@ -0,0 +1,263 @@
+//! Generation of `tracing-mock` expectations from recordings.
+
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{BufRead, Write},
+};
+
+use crate::{
+    export::{self, ExportError},
+    Field, FieldValue, Level, Metadata, SpanId, Trace,
+};
+
+/// Writes `tracing-mock` expectations for the recording read from `reader` to `writer`.
+///
+/// The expectations are written as Rust code, a function named `expected_traces` which returns a
+/// mock subscriber that expects the spans and events of the recording, in the order that they
+/// were recorded, and nothing else, together with its handle. This allows a trace which is known
+/// to be good to be recorded once, and then checked in a test, so that changes to the code which
+/// alter the traces that it produces are found:
+///
+/// ```text
+/// let (subscriber, handle) = expected_traces();
+/// tracing::subscriber::with_default(subscriber, || handle_request());
+/// handle.assert_finished();
+/// ```
+///
+/// Each span is bound to a variable, `span_1`, `span_2`, and so on, in the order that they were
+/// created, which is expected to be created, entered, exited, recorded, and closed (`drop_span`),
+/// as it was in the recording. Spans and events are expected with their name, level, and target,
+/// and all of the field values they were recorded with. Values recorded with `Debug` are expected
+/// to be formatted the same way, which `tracing::field::display` of the formatted value does.
+/// Parents aren't expected, nor are callsites, as `tracing-mock` doesn't check when they are
+/// registered. Records which refer to spans that aren't in the recording are left out.
+///
+/// The records of all threads are expected in the order they were recorded, so recordings of
+/// more than one thread only match code which runs the threads in the same order. The recording
+/// can be limited to a single thread with [`Query`] first.
+///
+/// The code is written for version 0.1 of `tracing-mock`. It isn't formatted beyond one
+/// expectation per line, so it may be run through `rustfmt`.
+///
+/// # Errors
+///
+/// Returns an error if reading the recording or writing the expectations fails, if a line of the
+/// recording can't be deserialized into a record, or if the recording was written in a newer
+/// version of the format.
+///
+/// [`Query`]: enum@crate::Query
+///
+/// # Examples
+///
+/// ```
+/// let recording = concat!(
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":1,"fields":[{"name":"user_id","value":{"I64":7}}],"metadata":{"id":4403349456,"name":"request","target":"app","level":"Info","module_path":"app","file":"src/main.rs","line":8,"fields":["user_id"],"kind":"Span"},"parent":"Root"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543410,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543420,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"slow response"}}],"metadata":{"id":4403349608,"name":"event","target":"app","level":"Warn","module_path":"app","file":"src/main.rs","line":9,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543430,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Exit":1}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543440,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Close":1}}"#,
+///     "\n",
+/// );
+///
+/// let mut code = Vec::new();
+/// tracing_cassette::to_tracing_mock(recording.as_bytes(), &mut code).unwrap();
+///
+/// assert_eq!(
+///     String::from_utf8(code).unwrap(),
+///     r#"use tracing::Level;
+/// use tracing_mock::{expect, subscriber};
+///
+/// /// Returns a mock subscriber which expects the traces of the recording, and its handle.
+/// pub fn expected_traces() -> (impl tracing::Subscriber, subscriber::MockHandle) {
+///     let span_1 = expect::span()
+///         .named("request")
+///         .at_level(Level::INFO)
+///         .with_target("app");
+///
+///     subscriber::mock()
+///         .new_span(span_1.clone().with_fields(expect::field("user_id").with_value(&7_i64).only()))
+///         .enter(span_1.clone())
+///         .event(expect::event().at_level(Level::WARN).with_target("app").with_fields(expect::field("message").with_value(&tracing::field::display("slow response")).only()))
+///         .exit(span_1.clone())
+///         .drop_span(span_1.clone())
+///         .only()
+///         .run_with_handle()
+/// }
+/// "#,
+/// );
+/// ```
+pub fn to_tracing_mock<R, W>(reader: R, mut writer: W) -> Result<(), ExportError>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut expectations = MockExpectations::default();
+    export::for_each_record(reader, |record| {
+        expectations.push(record.trace);
+        Ok(())
+    })?;
+
+    writeln!(writer, "use tracing::Level;")?;
+    writeln!(writer, "use tracing_mock::{{expect, subscriber}};")?;
+    writeln!(writer)?;
+    writeln!(
+        writer,
+        "/// Returns a mock subscriber which expects the traces of the recording, and its handle."
+    )?;
+    writeln!(
+        writer,
+        "pub fn expected_traces() -> (impl tracing::Subscriber, subscriber::MockHandle) {{"
+    )?;
+    for binding in &expectations.bindings {
+        writeln!(writer, "{binding}")?;
+        writeln!(writer)?;
+    }
+    writeln!(writer, "    subscriber::mock()")?;
+    for expectation in &expectations.expectations {
+        writeln!(writer, "        {expectation}")?;
+    }
+    writeln!(writer, "        .only()")?;
+    writeln!(writer, "        .run_with_handle()")?;
+    writeln!(writer, "}}")?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// The expectations for a recording, built up one record at a time.
+#[derive(Debug, Default)]
+struct MockExpectations {
+    /// The `let` statements which bind each span.
+    bindings: Vec<String>,
+    /// The calls on the mock subscriber, in the order they are expected.
+    expectations: Vec<String>,
+    /// The variable bound to each span which is open.
+    spans: HashMap<SpanId, String>,
+}
+
+impl MockExpectations {
+    fn push(&mut self, trace: Trace) {
+        let expectation = match trace {
+            Trace::RegisterCallsite(_) => return,
+            Trace::NewSpan(new_span) => {
+                let variable = format!("span_{}", self.bindings.len() + 1);
+                self.bindings.push(format!(
+                    "    let {variable} = expect::span()\n        .named({:?})\n        \
+                     .at_level({})\n        .with_target({:?});",
+                    new_span.metadata.name,
+                    level(&new_span.metadata.level),
+                    new_span.metadata.target,
+                ));
+                let expectation = if new_span.fields.is_empty() {
+                    format!(".new_span({variable}.clone())")
+                } else {
+                    format!(
+                        ".new_span({variable}.clone().with_fields({}))",
+                        fields(&new_span.fields),
+                    )
+                };
+                // A span Id may be reused once the span is closed, so this replaces that span.
+                self.spans.insert(new_span.id, variable);
+                expectation
+            }
+            Trace::Event(event) => {
+                format!(".event({})", expected_event(&event.metadata, &event.fields))
+            }
+            Trace::Enter(id) => match self.spans.get(&id) {
+                Some(variable) => format!(".enter({variable}.clone())"),
+                None => return,
+            },
+            Trace::Exit(id) => match self.spans.get(&id) {
+                Some(variable) => format!(".exit({variable}.clone())"),
+                None => return,
+            },
+            Trace::Close(id) => match self.spans.remove(&id) {
+                Some(variable) => format!(".drop_span({variable}.clone())"),
+                None => return,
+            },
+            Trace::Record(record_values) => match self.spans.get(&record_values.id) {
+                Some(variable) if !record_values.fields.is_empty() => format!(
+                    ".record({variable}.clone(), {})",
+                    fields(&record_values.fields),
+                ),
+                _ => return,
+            },
+            Trace::FollowsFrom(follows_from) => match (
+                self.spans.get(&follows_from.effect_id),
+                self.spans.get(&follows_from.cause_id),
+            ) {
+                (Some(effect), Some(cause)) => {
+                    format!(".follows_from({effect}.clone(), {cause}.clone())")
+                }
+                _ => return,
+            },
+        };
+        self.expectations.push(expectation);
+    }
+}
+
+/// Returns the expression for an event with `metadata` and `fields`.
+fn expected_event(metadata: &Metadata, fields: &[Field]) -> String {
+    let mut event = format!(
+        "expect::event().at_level({}).with_target({:?})",
+        level(&metadata.level),
+        metadata.target,
+    );
+    if !fields.is_empty() {
+        let _ = write!(event, ".with_fields({})", self::fields(fields));
+    }
+    event
+}
+
+/// Returns the expression for exactly `fields`.
+fn fields(fields: &[Field]) -> String {
+    let mut expected = String::new();
+    for (idx, field) in fields.iter().enumerate() {
+        if idx > 0 {
+            expected.push_str(".and(");
+        }
+        let _ = write!(
+            expected,
+            "expect::field({:?}).with_value(&{})",
+            field.name,
+            value(&field.value),
+        );
+        if idx > 0 {
+            expected.push(')');
+        }
+    }
+    expected.push_str(".only()");
+    expected
+}
+
+/// Returns the expression for `value`, with its type.
+fn value(value: &FieldValue) -> String {
+    match value {
+        // Displaying the formatted value records it as `Debug`, with the same text.
+        FieldValue::Debug(value) => format!("tracing::field::display({value:?})"),
+        FieldValue::F64(value) => format!("{value:?}_f64"),
+        FieldValue::I64(value) => format!("{value}_i64"),
+        FieldValue::U64(value) => format!("{value}_u64"),
+        FieldValue::I128(value) => format!("{value}_i128"),
+        FieldValue::U128(value) => format!("{value}_u128"),
+        FieldValue::Bool(value) => value.to_string(),
+        FieldValue::Str(value) => format!("{value:?}"),
+        // Structured values are replayed as their JSON text, which is recorded as `Debug`.
+        FieldValue::Json(value) => format!("tracing::field::display({:?})", value.to_string()),
+    }
+}
+
+fn level(level: &Level) -> &'static str {
+    match level {
+        Level::Trace => "Level::TRACE",
+        Level::Debug => "Level::DEBUG",
+        Level::Info => "Level::INFO",
+        Level::Warn => "Level::WARN",
+        Level::Error => "Level::ERROR",
+    }
+}
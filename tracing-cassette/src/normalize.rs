@@ -0,0 +1,227 @@
+use std::{collections::HashMap, time::Duration};
+
+use crate::{Field, Metadata, Parent, SpanId, Trace, TraceContext, TraceRecord};
+
+/// Rewrites a recording into a canonical form, which is the same for every recording of the same
+/// traces.
+///
+/// The parts of a recording which depend on when and where it was recorded, rather than on the
+/// traces themselves, are rewritten:
+///
+/// - span Ids are numbered from 1 in the order that the spans are created, so a span Id which is
+///   reused once its span closes is given a new number.
+/// - callsite Ids are numbered from 1 in the order that the callsites first appear.
+/// - thread Ids are numbered from 1 in the order that the threads first appear, as
+///   `ThreadId(1)` and so on. Thread names are kept.
+/// - trace Ids and span Ids in the distributed trace context of spans are numbered from 1 in the
+///   order that they first appear.
+/// - timestamps are made relative to the earliest timestamp in the recording, which becomes 0.
+/// - the fields of each callsite, span, event, recorded values, and final values of a span are
+///   sorted by name.
+///
+/// The records themselves are kept in the same order. Two recordings are the same trace if
+/// their normalized records are the same, apart from their timestamps, which is what
+/// [`structurally_equal`] checks.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use tracing_cassette::{RecordingBuilder, SpanBuilder, Trace};
+///
+/// let mut recording = RecordingBuilder::new().with_start(Duration::from_secs(1_715_177_340));
+/// recording.thread("main", |thread| {
+///     thread.span(SpanBuilder::new("request"), |_| {});
+/// });
+///
+/// let normalized = tracing_cassette::normalize(&recording.build());
+/// assert_eq!(normalized[0].meta.timestamp(), Duration::ZERO);
+/// assert_eq!(normalized[0].meta.thread_id, "ThreadId(1)");
+/// let Some(Trace::NewSpan(new_span)) = normalized.iter().map(|r| &r.trace).find(|trace| {
+///     matches!(trace, Trace::NewSpan(_))
+/// }) else {
+///     panic!("the span was recorded");
+/// };
+/// assert_eq!(new_span.id, 1.into());
+/// assert_eq!(new_span.metadata.id, 1);
+/// ```
+#[must_use]
+pub fn normalize(records: &[TraceRecord]) -> Vec<TraceRecord> {
+    let mut normalizer = Normalizer {
+        start: records
+            .iter()
+            .map(|record| record.meta.timestamp())
+            .min()
+            .unwrap_or_default(),
+        ..Normalizer::default()
+    };
+    records
+        .iter()
+        .map(|record| normalizer.normalize(record.clone()))
+        .collect()
+}
+
+/// Returns whether two recordings are structurally the same trace.
+///
+/// The recordings are the same if they have the same records in the same order once they are
+/// [`normalize`]d, ignoring their timestamps. So the traces must have been recorded in the same
+/// order, on the same number of threads, but may have been recorded at different times, with
+/// different Ids.
+///
+/// This is stricter than comparing recordings with [`diff`], which also ignores the threads, the
+/// order of the traces on different threads, and when spans are entered and exited. It is also
+/// cheaper, so it can be checked first, with [`diff`] only needed to explain the differences.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use tracing_cassette::{EventBuilder, RecordingBuilder, SpanBuilder};
+///
+/// let record = |start: u64, fields: &[(&str, i64)]| {
+///     let mut recording = RecordingBuilder::new().with_start(Duration::from_secs(start));
+///     recording.thread("main", |thread| {
+///         thread.span(SpanBuilder::new("request"), |thread| {
+///             let event = fields
+///                 .iter()
+///                 .fold(EventBuilder::new("handled"), |event, (name, value)| {
+///                     event.with_field(*name, *value)
+///                 });
+///             thread.event(event);
+///         });
+///     });
+///     recording.build()
+/// };
+///
+/// let left = record(1_715_177_340, &[("status", 200), ("bytes", 512)]);
+/// let right = record(1_715_180_000, &[("bytes", 512), ("status", 200)]);
+/// assert!(tracing_cassette::structurally_equal(&left, &right));
+///
+/// let different = record(1_715_180_000, &[("bytes", 512), ("status", 404)]);
+/// assert!(!tracing_cassette::structurally_equal(&left, &different));
+/// ```
+///
+/// [`diff`]: fn@crate::diff
+#[must_use]
+pub fn structurally_equal(left: &[TraceRecord], right: &[TraceRecord]) -> bool {
+    if left.len() != right.len() {
+        return false;
+    }
+
+    normalize(left)
+        .into_iter()
+        .zip(normalize(right))
+        .all(|(left, right)| {
+            left.trace == right.trace
+                && left.final_fields == right.final_fields
+                && left.meta.thread_id == right.meta.thread_id
+                && left.meta.thread_name == right.meta.thread_name
+        })
+}
+
+/// The numbers given to the Ids in a recording while it is normalized.
+#[derive(Default)]
+struct Normalizer {
+    start: Duration,
+    threads: HashMap<String, usize>,
+    callsites: HashMap<u64, u64>,
+    /// The number of each span Id, span Ids are reused once spans close, so this is the latest.
+    spans: HashMap<SpanId, SpanId>,
+    next_span: u64,
+    trace_ids: HashMap<u128, u128>,
+    trace_span_ids: HashMap<u64, u64>,
+}
+
+impl Normalizer {
+    fn normalize(&mut self, mut record: TraceRecord) -> TraceRecord {
+        let timestamp = record.meta.timestamp().saturating_sub(self.start);
+        record.meta.timestamp_s = timestamp.as_secs();
+        record.meta.timestamp_subsec_us = timestamp.subsec_micros();
+        let threads = self.threads.len();
+        let thread = *self
+            .threads
+            .entry(record.meta.thread_id)
+            .or_insert(threads + 1);
+        record.meta.thread_id = format!("ThreadId({thread})");
+
+        match &mut record.trace {
+            Trace::RegisterCallsite(metadata) => self.metadata(metadata),
+            Trace::Event(event) => {
+                self.metadata(&mut event.metadata);
+                self.parent(&mut event.parent);
+                sort_fields(&mut event.fields);
+            }
+            Trace::NewSpan(new_span) => {
+                self.metadata(&mut new_span.metadata);
+                self.parent(&mut new_span.parent);
+                sort_fields(&mut new_span.fields);
+                new_span.id = self.new_span(new_span.id);
+                if let Some(trace_context) = &mut new_span.trace_context {
+                    self.trace_context(trace_context);
+                }
+            }
+            Trace::Enter(id) | Trace::Exit(id) | Trace::Close(id) => *id = self.span(*id),
+            Trace::Record(record_values) => {
+                record_values.id = self.span(record_values.id);
+                sort_fields(&mut record_values.fields);
+            }
+            Trace::FollowsFrom(follows_from) => {
+                follows_from.cause_id = self.span(follows_from.cause_id);
+                follows_from.effect_id = self.span(follows_from.effect_id);
+            }
+        }
+        if let Some(final_fields) = &mut record.final_fields {
+            sort_fields(final_fields);
+        }
+
+        record
+    }
+
+    fn metadata(&mut self, metadata: &mut Metadata) {
+        let callsites = self.callsites.len() as u64;
+        metadata.id = *self.callsites.entry(metadata.id).or_insert(callsites + 1);
+        metadata.fields.sort();
+    }
+
+    fn parent(&mut self, parent: &mut Parent) {
+        if let Parent::Explicit(id) = parent {
+            *id = self.span(*id);
+        }
+    }
+
+    fn new_span(&mut self, id: SpanId) -> SpanId {
+        self.next_span += 1;
+        let number = SpanId::from(self.next_span);
+        self.spans.insert(id, number);
+        number
+    }
+
+    /// Returns the number of the span `id`, which is only given a number here if its creation
+    /// wasn't recorded.
+    fn span(&mut self, id: SpanId) -> SpanId {
+        match self.spans.get(&id) {
+            Some(number) => *number,
+            None => self.new_span(id),
+        }
+    }
+
+    fn trace_context(&mut self, trace_context: &mut TraceContext) {
+        let trace_ids = self.trace_ids.len() as u128;
+        trace_context.trace_id = *self
+            .trace_ids
+            .entry(trace_context.trace_id)
+            .or_insert(trace_ids + 1);
+        let span_ids = self.trace_span_ids.len() as u64;
+        trace_context.span_id = *self
+            .trace_span_ids
+            .entry(trace_context.span_id)
+            .or_insert(span_ids + 1);
+    }
+}
+
+/// Sorts `fields` by name, fields with the same name are kept in the order they were recorded.
+fn sort_fields(fields: &mut [Field]) {
+    fields.sort_by(|left, right| left.name.cmp(&right.name));
+}
@@ -0,0 +1,455 @@
+//! Export of recordings to OpenTelemetry, encoded as OTLP JSON.
+
+#[cfg(feature = "otlp")]
+use std::io;
+use std::{collections::HashMap, io::BufRead, time::Duration};
+
+use serde_json::{json, Value};
+
+use crate::{
+    export::{self, value_to_string},
+    ExportError, Field, FieldValue, Level, Metadata, Parent, SpanId, Trace, TraceRecord,
+};
+
+/// The name of the instrumentation scope that exported spans and logs are given.
+const SCOPE_NAME: &str = "tracing-cassette";
+
+/// A recording converted into OTLP export requests.
+///
+/// Each request is the JSON encoding of an OTLP export request, which can be sent to the
+/// `/v1/traces` and `/v1/logs` endpoints of an OTLP/HTTP collector respectively.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OtlpRequests {
+    /// An `ExportTraceServiceRequest` containing the spans of the recording.
+    pub traces: Value,
+    /// An `ExportLogsServiceRequest` containing the events of the recording.
+    pub logs: Value,
+}
+
+/// Converts the recording read from `reader` into OpenTelemetry spans and logs.
+///
+/// Each recorded span becomes an OpenTelemetry span, which starts when the span was created and
+/// ends when it was closed. Spans which are still open at the end of the recording end at the
+/// time of the last record. The parent of a span is taken from the recording, contextual parents
+/// are the span which was entered on the same thread. Spans without a parent start a new trace.
+/// Recorded events become log records, with the trace and span Ids of their parent span.
+///
+/// The fields of spans and events are added as attributes, together with the location of the
+/// callsite. The resource of the spans and logs is given `service_name` as its `service.name`.
+///
+/// Trace and span Ids are generated from the time of the first record and the order of the
+/// spans in the recording, so exporting the same recording again gives the same Ids.
+///
+/// # Errors
+///
+/// Returns an error if reading the recording fails, if a line of the recording can't be
+/// deserialized into a record, or if the recording was written in a newer version of the
+/// format.
+///
+/// # Examples
+///
+/// ```
+/// let recording = concat!(
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":1,"fields":[{"name":"answer","value":{"I64":42}}],"metadata":{"id":4403349456,"name":"span","target":"record_spans","level":"Info","module_path":"record_spans","file":"tracing-rec/examples/record-spans.rs","line":8,"fields":["answer"],"kind":"Span"},"parent":"Root"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543410,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543420,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":4403349608,"name":"event","target":"record_spans","level":"Info","module_path":null,"file":null,"line":null,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543430,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Exit":1}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543440,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Close":1}}"#,
+///     "\n",
+/// );
+///
+/// let requests = tracing_cassette::to_otlp(recording.as_bytes(), "example").unwrap();
+///
+/// let span = &requests.traces["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+/// assert_eq!(span["name"], "span");
+/// assert_eq!(span["startTimeUnixNano"], "1715177340543400000");
+/// assert_eq!(span["endTimeUnixNano"], "1715177340543440000");
+///
+/// let log = &requests.logs["resourceLogs"][0]["scopeLogs"][0]["logRecords"][0];
+/// assert_eq!(log["body"]["stringValue"], "I am an info event!");
+/// assert_eq!(log["severityText"], "INFO");
+/// assert_eq!(log["spanId"], span["spanId"]);
+/// ```
+pub fn to_otlp<R: BufRead>(reader: R, service_name: &str) -> Result<OtlpRequests, ExportError> {
+    let (spans, logs) = otlp_spans_and_logs(reader)?;
+    let resource = json!({
+        "attributes": [attribute("service.name", string_value(service_name))],
+    });
+    let scope = json!({
+        "name": SCOPE_NAME,
+        "version": env!("CARGO_PKG_VERSION"),
+    });
+    Ok(OtlpRequests {
+        traces: json!({
+            "resourceSpans": [{
+                "resource": resource,
+                "scopeSpans": [{ "scope": scope, "spans": spans }],
+            }],
+        }),
+        logs: json!({
+            "resourceLogs": [{
+                "resource": resource,
+                "scopeLogs": [{ "scope": scope, "logRecords": logs }],
+            }],
+        }),
+    })
+}
+
+/// Converts the recording read from `reader` into OTLP spans and log records, without the
+/// resource and scope that they are exported in.
+pub(crate) fn otlp_spans_and_logs<R: BufRead>(
+    reader: R,
+) -> Result<(Vec<Value>, Vec<Value>), ExportError> {
+    let mut converter = OtlpConverter::default();
+    export::for_each_record(reader, |record| {
+        converter.push(record);
+        Ok(())
+    })?;
+    converter.finish();
+
+    Ok((converter.spans, converter.logs))
+}
+
+/// Sends OTLP export requests to the OTLP/HTTP collector at `endpoint`.
+///
+/// The spans are sent to `{endpoint}/v1/traces` and the logs to `{endpoint}/v1/logs`, with the
+/// provided `headers`, which can be used for authentication. Requests which don't contain any
+/// spans or logs aren't sent.
+///
+/// # Errors
+///
+/// Returns an error if either request fails or is rejected by the collector.
+///
+/// # Examples
+///
+/// ```no_run
+/// let recording = std::fs::read("recording.tracing").unwrap();
+/// let requests = tracing_cassette::to_otlp(recording.as_slice(), "my-service").unwrap();
+/// tracing_cassette::send_otlp(&requests, "http://localhost:4318", &[]).unwrap();
+/// ```
+#[cfg(feature = "otlp")]
+pub fn send_otlp(
+    requests: &OtlpRequests,
+    endpoint: &str,
+    headers: &[(&str, &str)],
+) -> Result<(), ExportError> {
+    let endpoint = endpoint.trim_end_matches('/');
+    let bodies = [
+        (
+            "traces",
+            &requests.traces,
+            "/resourceSpans/0/scopeSpans/0/spans",
+        ),
+        (
+            "logs",
+            &requests.logs,
+            "/resourceLogs/0/scopeLogs/0/logRecords",
+        ),
+    ];
+    for (signal, body, items) in bodies {
+        let has_items = body
+            .pointer(items)
+            .and_then(Value::as_array)
+            .is_some_and(|items| !items.is_empty());
+        if !has_items {
+            continue;
+        }
+
+        let mut request =
+            ureq::post(&format!("{endpoint}/v1/{signal}")).set("Content-Type", "application/json");
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+        request
+            .send_bytes(&serde_json::to_vec(body).map_err(io::Error::from)?)
+            .map_err(io::Error::other)?;
+    }
+
+    Ok(())
+}
+
+/// The trace and span Id of a span.
+#[derive(Clone, Copy, Debug)]
+struct SpanContext {
+    trace_id: u128,
+    span_id: u64,
+}
+
+/// A span which has been created and not yet closed.
+#[derive(Debug)]
+struct OpenSpan {
+    context: SpanContext,
+    parent_span_id: Option<u64>,
+    name: String,
+    start: Duration,
+    /// The attributes, by key, in the order they were first recorded.
+    attributes: Vec<(String, Value)>,
+}
+
+/// Converts records into OpenTelemetry spans and log records.
+#[derive(Debug, Default)]
+struct OtlpConverter {
+    /// The timestamp of the first record, used to generate trace Ids.
+    start: Option<Duration>,
+    /// The timestamp of the latest record.
+    latest: Duration,
+    traces: u64,
+    /// The spans which have been created so far.
+    span_count: u64,
+    open_spans: HashMap<SpanId, OpenSpan>,
+    /// The context of the latest span created with each span Id, which may have been closed
+    /// already, as it can still be used as an explicit parent.
+    contexts: HashMap<SpanId, SpanContext>,
+    /// The spans entered on each recorded thread, innermost last.
+    entered: HashMap<String, Vec<SpanId>>,
+    spans: Vec<Value>,
+    logs: Vec<Value>,
+}
+
+impl OtlpConverter {
+    fn push(&mut self, record: TraceRecord) {
+        let ts = record.meta.timestamp();
+        self.start.get_or_insert(ts);
+        self.latest = self.latest.max(ts);
+        let thread_id = record.meta.thread_id;
+        let thread_name = record.meta.thread_name;
+
+        match record.trace {
+            Trace::RegisterCallsite(_) | Trace::FollowsFrom(_) => {}
+            Trace::Event(event) => {
+                let parent = self.parent_context(&event.parent, &thread_id);
+                let mut fields = event.fields;
+                let body = match fields.iter().position(|field| field.name == "message") {
+                    Some(idx) => value_to_string(fields.remove(idx).value),
+                    None => event.metadata.name.clone(),
+                };
+                let mut attributes = field_attributes(fields);
+                attributes.extend(metadata_attributes(&event.metadata));
+                attributes.extend(thread_attributes(thread_name));
+
+                let mut log = json!({
+                    "timeUnixNano": nanos(ts),
+                    "observedTimeUnixNano": nanos(ts),
+                    "severityNumber": severity_number(&event.metadata.level),
+                    "severityText": severity_text(&event.metadata.level),
+                    "body": string_value(&body),
+                    "attributes": attributes
+                        .into_iter()
+                        .map(|(key, value)| attribute(&key, value))
+                        .collect::<Vec<_>>(),
+                });
+                if let Some(parent) = parent {
+                    log["traceId"] = Value::String(format!("{:032x}", parent.trace_id));
+                    log["spanId"] = Value::String(format!("{:016x}", parent.span_id));
+                }
+                self.logs.push(log);
+            }
+            Trace::NewSpan(new_span) => {
+                let parent = self.parent_context(&new_span.parent, &thread_id);
+                self.span_count += 1;
+                let context = SpanContext {
+                    trace_id: parent.map_or_else(|| self.new_trace_id(), |parent| parent.trace_id),
+                    span_id: self.span_count,
+                };
+                let mut attributes = field_attributes(new_span.fields);
+                attributes.extend(metadata_attributes(&new_span.metadata));
+                attributes.push((
+                    "level".into(),
+                    string_value(severity_text(&new_span.metadata.level)),
+                ));
+                attributes.extend(thread_attributes(thread_name));
+
+                self.contexts.insert(new_span.id, context);
+                self.open_spans.insert(
+                    new_span.id,
+                    OpenSpan {
+                        context,
+                        parent_span_id: parent.map(|parent| parent.span_id),
+                        name: new_span.metadata.name,
+                        start: ts,
+                        attributes,
+                    },
+                );
+            }
+            Trace::Record(record_values) => {
+                if let Some(open_span) = self.open_spans.get_mut(&record_values.id) {
+                    for (key, value) in field_attributes(record_values.fields) {
+                        match open_span.attributes.iter_mut().find(|(k, _)| *k == key) {
+                            Some((_, existing)) => *existing = value,
+                            None => open_span.attributes.push((key, value)),
+                        }
+                    }
+                }
+            }
+            Trace::Enter(id) => self.entered.entry(thread_id).or_default().push(id),
+            Trace::Exit(id) => {
+                let entered = self.entered.entry(thread_id).or_default();
+                if let Some(idx) = entered.iter().rposition(|entered| *entered == id) {
+                    entered.remove(idx);
+                }
+            }
+            Trace::Close(id) => {
+                if let Some(open_span) = self.open_spans.remove(&id) {
+                    self.spans.push(span(open_span, ts));
+                }
+            }
+        }
+    }
+
+    /// Ends the spans which are still open.
+    fn finish(&mut self) {
+        let mut open_spans: Vec<_> = self.open_spans.drain().map(|(_, span)| span).collect();
+        open_spans.sort_by_key(|open_span| open_span.context.span_id);
+        for open_span in open_spans {
+            self.spans.push(span(open_span, self.latest));
+        }
+    }
+
+    fn parent_context(&self, parent: &Parent, thread_id: &str) -> Option<SpanContext> {
+        let parent_id = match parent {
+            Parent::Root => return None,
+            Parent::Current => self.entered.get(thread_id)?.last()?,
+            Parent::Explicit(id) => id,
+        };
+        self.contexts.get(parent_id).copied()
+    }
+
+    fn new_trace_id(&mut self) -> u128 {
+        self.traces += 1;
+        let start = u64::try_from(self.start.unwrap_or_default().as_nanos()).unwrap_or(u64::MAX);
+        u128::from(start) << 64 | u128::from(self.traces)
+    }
+}
+
+/// Returns the OTLP span for `open_span`, which ended at `end`.
+fn span(open_span: OpenSpan, end: Duration) -> Value {
+    let mut span = json!({
+        "traceId": format!("{:032x}", open_span.context.trace_id),
+        "spanId": format!("{:016x}", open_span.context.span_id),
+        "name": open_span.name,
+        // SPAN_KIND_INTERNAL
+        "kind": 1,
+        "startTimeUnixNano": nanos(open_span.start),
+        "endTimeUnixNano": nanos(end),
+        "attributes": open_span
+            .attributes
+            .into_iter()
+            .map(|(key, value)| attribute(&key, value))
+            .collect::<Vec<_>>(),
+    });
+    if let Some(parent_span_id) = open_span.parent_span_id {
+        span["parentSpanId"] = Value::String(format!("{parent_span_id:016x}"));
+    }
+    span
+}
+
+/// Formats a timestamp as nanoseconds since the UNIX epoch, 64-bit integers are written as
+/// strings in OTLP JSON.
+fn nanos(ts: Duration) -> String {
+    ts.as_nanos().to_string()
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    json!({ "key": key, "value": value })
+}
+
+fn string_value(value: &str) -> Value {
+    json!({ "stringValue": value })
+}
+
+fn int_value(value: i64) -> Value {
+    json!({ "intValue": value.to_string() })
+}
+
+fn field_attributes(fields: Vec<Field>) -> Vec<(String, Value)> {
+    fields
+        .into_iter()
+        .map(|field| {
+            let value = match field.value {
+                FieldValue::Debug(value) | FieldValue::Str(value) => string_value(&value),
+                FieldValue::F64(value) => json!({ "doubleValue": value }),
+                FieldValue::I64(value) => int_value(value),
+                FieldValue::U64(value) => i64::try_from(value)
+                    .map_or_else(|_| string_value(&value.to_string()), int_value),
+                FieldValue::I128(value) => i64::try_from(value)
+                    .map_or_else(|_| string_value(&value.to_string()), int_value),
+                FieldValue::U128(value) => i64::try_from(value)
+                    .map_or_else(|_| string_value(&value.to_string()), int_value),
+                FieldValue::Bool(value) => json!({ "boolValue": value }),
+                FieldValue::Json(value) => any_value(value),
+            };
+            (field.name, value)
+        })
+        .collect()
+}
+
+/// Converts a structured field value into an `AnyValue`, keeping objects and arrays as key value
+/// lists and arrays.
+fn any_value(value: Value) -> Value {
+    match value {
+        Value::Null => string_value("null"),
+        Value::Bool(value) => json!({ "boolValue": value }),
+        Value::Number(number) => match number.as_i64() {
+            Some(value) => int_value(value),
+            None => match number.as_f64() {
+                Some(value) if number.is_f64() => json!({ "doubleValue": value }),
+                _ => string_value(&number.to_string()),
+            },
+        },
+        Value::String(value) => string_value(&value),
+        Value::Array(values) => {
+            let values: Vec<Value> = values.into_iter().map(any_value).collect();
+            json!({ "arrayValue": { "values": values } })
+        }
+        Value::Object(entries) => {
+            let values: Vec<Value> = entries
+                .into_iter()
+                .map(|(key, value)| attribute(&key, any_value(value)))
+                .collect();
+            json!({ "kvlistValue": { "values": values } })
+        }
+    }
+}
+
+/// The attributes describing the location of a callsite.
+fn metadata_attributes(metadata: &Metadata) -> Vec<(String, Value)> {
+    let mut attributes = vec![("target".to_owned(), string_value(&metadata.target))];
+    if let Some(module_path) = &metadata.module_path {
+        attributes.push(("code.namespace".into(), string_value(module_path)));
+    }
+    if let Some(file) = &metadata.file {
+        attributes.push(("code.filepath".into(), string_value(file)));
+    }
+    if let Some(line) = metadata.line {
+        attributes.push(("code.lineno".into(), int_value(i64::from(line))));
+    }
+    attributes
+}
+
+fn thread_attributes(thread_name: Option<String>) -> Option<(String, Value)> {
+    thread_name.map(|thread_name| ("thread.name".into(), string_value(&thread_name)))
+}
+
+fn severity_number(level: &Level) -> u8 {
+    match level {
+        Level::Trace => 1,
+        Level::Debug => 5,
+        Level::Info => 9,
+        Level::Warn => 13,
+        Level::Error => 17,
+    }
+}
+
+fn severity_text(level: &Level) -> &'static str {
+    match level {
+        Level::Trace => "TRACE",
+        Level::Debug => "DEBUG",
+        Level::Info => "INFO",
+        Level::Warn => "WARN",
+        Level::Error => "ERROR",
+    }
+}
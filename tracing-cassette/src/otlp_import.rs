@@ -0,0 +1,1185 @@
+//! Import of OpenTelemetry spans and logs exported over OTLP into recordings.
+
+use std::{collections::HashMap, error, fmt};
+
+use serde_json::{Map, Value};
+
+use crate::{
+    protobuf::{DecodeError, FieldData, Fields},
+    Event, Field, FieldValue, Kind, Level, Metadata, NewSpan, Parent, RecordMeta, SpanId, Trace,
+    TraceContext, TraceRecord,
+};
+
+/// `TracesData.resource_spans` and `LogsData.resource_logs`
+const RESOURCE_DATA: u32 = 1;
+/// `ResourceSpans.resource` and `ResourceLogs.resource`
+const RESOURCE: u32 = 1;
+/// `ResourceSpans.scope_spans` and `ResourceLogs.scope_logs`
+const SCOPE_DATA: u32 = 2;
+/// `Resource.attributes`
+const RESOURCE_ATTRIBUTES: u32 = 1;
+/// `ScopeSpans.scope` and `ScopeLogs.scope`
+const SCOPE: u32 = 1;
+/// `ScopeSpans.spans` and `ScopeLogs.log_records`
+const SCOPE_ITEMS: u32 = 2;
+/// `InstrumentationScope.name`
+const SCOPE_NAME: u32 = 1;
+
+/// `Span.trace_id`
+const SPAN_TRACE_ID: u32 = 1;
+/// `Span.span_id`
+const SPAN_SPAN_ID: u32 = 2;
+/// `Span.parent_span_id`
+const SPAN_PARENT_SPAN_ID: u32 = 4;
+/// `Span.name`
+const SPAN_NAME: u32 = 5;
+/// `Span.start_time_unix_nano`
+const SPAN_START_TIME: u32 = 7;
+/// `Span.end_time_unix_nano`
+const SPAN_END_TIME: u32 = 8;
+/// `Span.attributes`
+const SPAN_ATTRIBUTES: u32 = 9;
+/// `Span.events`
+const SPAN_EVENTS: u32 = 11;
+
+/// `Span.Event.time_unix_nano`
+const EVENT_TIME: u32 = 1;
+/// `Span.Event.name`
+const EVENT_NAME: u32 = 2;
+/// `Span.Event.attributes`
+const EVENT_ATTRIBUTES: u32 = 3;
+
+/// `LogRecord.time_unix_nano`
+const LOG_TIME: u32 = 1;
+/// `LogRecord.severity_number`
+const LOG_SEVERITY_NUMBER: u32 = 2;
+/// `LogRecord.body`
+const LOG_BODY: u32 = 5;
+/// `LogRecord.attributes`
+const LOG_ATTRIBUTES: u32 = 6;
+/// `LogRecord.trace_id`
+const LOG_TRACE_ID: u32 = 9;
+/// `LogRecord.span_id`
+const LOG_SPAN_ID: u32 = 10;
+/// `LogRecord.observed_time_unix_nano`
+const LOG_OBSERVED_TIME: u32 = 11;
+
+/// `KeyValue.key`
+const KEY_VALUE_KEY: u32 = 1;
+/// `KeyValue.value`
+const KEY_VALUE_VALUE: u32 = 2;
+
+/// `AnyValue.string_value`
+const ANY_STRING: u32 = 1;
+/// `AnyValue.bool_value`
+const ANY_BOOL: u32 = 2;
+/// `AnyValue.int_value`
+const ANY_INT: u32 = 3;
+/// `AnyValue.double_value`
+const ANY_DOUBLE: u32 = 4;
+
+/// The thread name used when the service of a span isn't known.
+const DEFAULT_SERVICE_NAME: &str = "otlp";
+
+/// An error importing OpenTelemetry data.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ImportError {
+    /// The data isn't valid JSON.
+    Json(serde_json::Error),
+    /// The data isn't a valid protobuf message.
+    Protobuf {
+        /// Describes what is wrong with the message.
+        message: String,
+    },
+    /// The data is valid, but isn't an OTLP export.
+    InvalidOtlp {
+        /// Describes what is wrong with the export.
+        message: String,
+    },
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Json(err) => write!(f, "invalid JSON: {err}"),
+            Self::Protobuf { message } => write!(f, "invalid protobuf: {message}"),
+            Self::InvalidOtlp { message } => write!(f, "invalid OTLP: {message}"),
+        }
+    }
+}
+
+impl error::Error for ImportError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Json(err) => Some(err),
+            Self::Protobuf { .. } | Self::InvalidOtlp { .. } => None,
+        }
+    }
+}
+
+impl From<DecodeError> for ImportError {
+    fn from(value: DecodeError) -> Self {
+        Self::Protobuf {
+            message: value.to_string(),
+        }
+    }
+}
+
+fn invalid(message: impl Into<String>) -> ImportError {
+    ImportError::InvalidOtlp {
+        message: message.into(),
+    }
+}
+
+/// Builds a recording from OpenTelemetry spans and logs exported over OTLP.
+///
+/// OTLP exports are added with [`add_json`], [`add_protobuf_traces`] and [`add_protobuf_logs`].
+/// Once all the exports have been added, [`into_records`] synthesizes the records of a recording
+/// in which the spans were created, entered, exited and closed, and the logs were recorded as
+/// events. The records can be written to a recording with [`write_recording`] and then replayed
+/// into any `tracing` subscriber.
+///
+/// Each span is created and entered at its start time and exited at its end time, with its trace
+/// and span Id as its [`TraceContext`]. OpenTelemetry spans don't belong to threads, so the spans
+/// are spread over as few recorded threads as possible while keeping the spans entered on each
+/// thread nested. A span is closed once it has ended and all its children have been closed, as a
+/// `tracing` span stays open while it has children.
+///
+/// The attributes of spans and logs become fields, except for the attributes which describe the
+/// location of the callsite, such as `code.filepath`, which are used for the callsite metadata.
+/// Span events are recorded as events within the span, with the name of the span event as the
+/// message. Logs are recorded as events within the span they refer to, if it has been imported,
+/// with the body as the message.
+///
+/// Spans and logs exported with [`to_otlp`] can be imported back in, which gives a recording with
+/// the same spans and events.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_cassette::{OtlpImport, Trace};
+///
+/// let traces = r#"{"resourceSpans":[{"resource":{"attributes":[{"key":"service.name","value":{"stringValue":"example"}}]},"scopeSpans":[{"scope":{"name":"example"},"spans":[{"traceId":"5b8efff798038103d269b633813fc60c","spanId":"eee19b7ec3c1b174","name":"request","kind":2,"startTimeUnixNano":"1715177340543400000","endTimeUnixNano":"1715177340643400000","attributes":[{"key":"http.method","value":{"stringValue":"GET"}}],"events":[{"timeUnixNano":"1715177340593400000","name":"cache miss"}]}]}]}]}"#;
+///
+/// let mut import = OtlpImport::new();
+/// import.add_json(traces.as_bytes()).unwrap();
+/// let records = import.into_records();
+///
+/// let traces: Vec<_> = records.iter().map(|record| &record.trace).collect();
+/// assert!(matches!(
+///     traces.as_slice(),
+///     [
+///         Trace::RegisterCallsite(_),
+///         Trace::NewSpan(_),
+///         Trace::Enter(_),
+///         Trace::RegisterCallsite(_),
+///         Trace::Event(_),
+///         Trace::Exit(_),
+///         Trace::Close(_),
+///     ],
+/// ));
+///
+/// let mut recording = Vec::new();
+/// tracing_cassette::write_recording(&mut recording, &records).unwrap();
+/// assert!(tracing_cassette::validate_stream(recording.as_slice()).unwrap().is_empty());
+/// ```
+///
+/// [`add_json`]: fn@Self::add_json
+/// [`add_protobuf_traces`]: fn@Self::add_protobuf_traces
+/// [`add_protobuf_logs`]: fn@Self::add_protobuf_logs
+/// [`into_records`]: fn@Self::into_records
+/// [`write_recording`]: fn@crate::write_recording
+/// [`to_otlp`]: fn@crate::to_otlp
+#[derive(Debug, Default)]
+pub struct OtlpImport {
+    spans: Vec<ImportedSpan>,
+    logs: Vec<ImportedLog>,
+}
+
+/// The resource and instrumentation scope that spans and logs were exported from.
+#[derive(Clone, Debug, Default)]
+struct Source {
+    service_name: Option<String>,
+    scope_name: Option<String>,
+}
+
+#[derive(Debug)]
+struct ImportedSpan {
+    trace_id: u128,
+    span_id: u64,
+    parent_span_id: Option<u64>,
+    name: String,
+    start: u64,
+    end: u64,
+    attributes: Vec<Field>,
+    events: Vec<ImportedEvent>,
+    source: Source,
+}
+
+#[derive(Debug)]
+struct ImportedEvent {
+    time: u64,
+    name: String,
+    attributes: Vec<Field>,
+}
+
+#[derive(Debug)]
+struct ImportedLog {
+    time: u64,
+    level: Level,
+    body: Option<FieldValue>,
+    attributes: Vec<Field>,
+    /// The trace and span Ids of the span the log was recorded in.
+    span: Option<(u128, u64)>,
+    source: Source,
+}
+
+impl OtlpImport {
+    /// Creates an import without any spans or logs.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the spans and logs from OTLP exports encoded as JSON.
+    ///
+    /// The data may contain any number of `ExportTraceServiceRequest` and
+    /// `ExportLogsServiceRequest` messages (or `TracesData` and `LogsData`, which are encoded in
+    /// the same way), one after the other. This is the format written by the file exporter of
+    /// the OpenTelemetry collector.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data isn't valid JSON, or if it doesn't contain OTLP exports.
+    pub fn add_json(&mut self, data: &[u8]) -> Result<(), ImportError> {
+        for export in serde_json::Deserializer::from_slice(data).into_iter::<Value>() {
+            let export = export.map_err(ImportError::Json)?;
+            let resource_spans = get(&export, "resourceSpans", "resource_spans");
+            let resource_logs = get(&export, "resourceLogs", "resource_logs");
+            if resource_spans.is_none() && resource_logs.is_none() {
+                return Err(invalid("expected resourceSpans or resourceLogs"));
+            }
+
+            for resource_data in array(resource_spans) {
+                for (source, span) in json_items(resource_data, "scopeSpans", "spans")? {
+                    self.spans.push(json_span(span, source)?);
+                }
+            }
+            for resource_data in array(resource_logs) {
+                for (source, log) in json_items(resource_data, "scopeLogs", "logRecords")? {
+                    self.logs.push(json_log(log, source)?);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds the spans from an OTLP export encoded as protobuf.
+    ///
+    /// The data must be an `ExportTraceServiceRequest` or a `TracesData` message, as sent to the
+    /// `/v1/traces` endpoint of an OTLP/HTTP collector.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data can't be decoded.
+    pub fn add_protobuf_traces(&mut self, data: &[u8]) -> Result<(), ImportError> {
+        for (source, span) in protobuf_items(data)? {
+            self.spans.push(protobuf_span(span, source)?);
+        }
+
+        Ok(())
+    }
+
+    /// Adds the logs from an OTLP export encoded as protobuf.
+    ///
+    /// The data must be an `ExportLogsServiceRequest` or a `LogsData` message, as sent to the
+    /// `/v1/logs` endpoint of an OTLP/HTTP collector.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data can't be decoded.
+    pub fn add_protobuf_logs(&mut self, data: &[u8]) -> Result<(), ImportError> {
+        for (source, log) in protobuf_items(data)? {
+            self.logs.push(protobuf_log(log, source)?);
+        }
+
+        Ok(())
+    }
+
+    /// Synthesizes the records of a recording of the spans and logs which have been added.
+    ///
+    /// The records are ordered by their timestamps, which are truncated to microseconds.
+    #[must_use]
+    pub fn into_records(self) -> Vec<TraceRecord> {
+        RecordBuilder::new(self).build()
+    }
+}
+
+/// A record waiting to be put in order, records are sorted by time and then by `order`.
+struct PendingRecord {
+    time: u64,
+    order: (u8, usize, usize),
+    lane: usize,
+    trace: Trace,
+}
+
+/// A callsite which records have been synthesized for.
+#[derive(PartialEq)]
+struct CallsiteKey {
+    name: String,
+    target: String,
+    level: Level,
+    kind: Kind,
+    fields: Vec<String>,
+    location: Location,
+}
+
+/// The location of a callsite, taken from the `code.*` attributes.
+#[derive(Clone, Default, PartialEq)]
+struct Location {
+    module_path: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+}
+
+/// Synthesizes records from imported spans and logs.
+struct RecordBuilder {
+    spans: Vec<ImportedSpan>,
+    logs: Vec<ImportedLog>,
+    callsites: Vec<CallsiteKey>,
+    /// The name of each recorded thread, by lane.
+    lanes: Vec<String>,
+    records: Vec<PendingRecord>,
+}
+
+impl RecordBuilder {
+    fn new(import: OtlpImport) -> Self {
+        let mut spans = import.spans;
+        spans.sort_by_key(|span| span.start);
+        Self {
+            spans,
+            logs: import.logs,
+            callsites: Vec::new(),
+            lanes: Vec::new(),
+            records: Vec::new(),
+        }
+    }
+
+    fn build(mut self) -> Vec<TraceRecord> {
+        // The index of each span, by trace and span Id.
+        let index: HashMap<(u128, u64), usize> = self
+            .spans
+            .iter()
+            .enumerate()
+            .map(|(idx, span)| ((span.trace_id, span.span_id), idx))
+            .collect();
+        let parents: Vec<Option<usize>> = self
+            .spans
+            .iter()
+            .map(|span| {
+                span.parent_span_id
+                    .and_then(|parent_span_id| index.get(&(span.trace_id, parent_span_id)))
+                    .copied()
+            })
+            .collect();
+        let depths = depths(&parents);
+        let close_times = close_times(&self.spans, &parents);
+        let span_lanes = self.assign_lanes(&parents);
+
+        let spans = std::mem::take(&mut self.spans);
+        for (idx, span) in spans.into_iter().enumerate() {
+            let id = span_record_id(idx);
+            let lane = span_lanes[idx];
+            let depth = depths[idx];
+            let (location, target, level, fields) = split_attributes(span.attributes, &span.source);
+            let metadata = self.callsite(
+                CallsiteKey {
+                    name: span.name,
+                    target,
+                    level,
+                    kind: Kind::Span,
+                    fields: fields.iter().map(|field| field.name.clone()).collect(),
+                    location,
+                },
+                span.start,
+                (0, depth, idx),
+                lane,
+            );
+            let parent = match parents[idx] {
+                Some(parent_idx) => Parent::Explicit(span_record_id(parent_idx)),
+                None => Parent::Root,
+            };
+            self.push(
+                span.start,
+                (1, depth, idx),
+                lane,
+                Trace::NewSpan(NewSpan {
+                    id,
+                    fields,
+                    metadata,
+                    parent,
+                    trace_context: trace_context(span.trace_id, span.span_id),
+                }),
+            );
+            self.push(span.start, (2, depth, idx), lane, Trace::Enter(id));
+
+            for event in span.events {
+                let mut fields = vec![Field {
+                    name: "message".into(),
+                    value: FieldValue::Debug(event.name),
+                }];
+                let (location, target, level, attributes) =
+                    split_attributes(event.attributes, &span.source);
+                fields.extend(attributes);
+                self.event(
+                    event.time,
+                    (3, depth, idx),
+                    lane,
+                    Parent::Explicit(id),
+                    target,
+                    level,
+                    location,
+                    fields,
+                );
+            }
+
+            // Spans are exited with the innermost first and closed with the outermost first, as
+            // a parent can only be closed after its children.
+            self.push(
+                span.end,
+                (4, usize::MAX - depth, idx),
+                lane,
+                Trace::Exit(id),
+            );
+            self.push(close_times[idx], (5, depth, idx), lane, Trace::Close(id));
+        }
+
+        let logs = std::mem::take(&mut self.logs);
+        for (log_idx, log) in logs.into_iter().enumerate() {
+            let span_idx = log.span.and_then(|span| index.get(&span)).copied();
+            let (lane, parent) = match span_idx {
+                Some(span_idx) => (
+                    span_lanes[span_idx],
+                    Parent::Explicit(span_record_id(span_idx)),
+                ),
+                None => (0, Parent::Root),
+            };
+            let mut fields = Vec::new();
+            if let Some(body) = log.body {
+                fields.push(Field {
+                    name: "message".into(),
+                    value: body,
+                });
+            }
+            let (location, target, _, attributes) = split_attributes(log.attributes, &log.source);
+            fields.extend(attributes);
+            self.event(
+                log.time,
+                (3, span_idx.map_or(0, |span_idx| depths[span_idx]), log_idx),
+                lane,
+                parent,
+                target,
+                log.level,
+                location,
+                fields,
+            );
+        }
+
+        let mut records = self.records;
+        records.sort_by_key(|record| (record.time, record.order));
+        let lanes = self.lanes;
+        records
+            .into_iter()
+            .map(|record| TraceRecord {
+                meta: RecordMeta {
+                    timestamp_s: record.time / 1_000_000_000,
+                    timestamp_subsec_us: (record.time % 1_000_000_000 / 1_000) as u32,
+                    thread_id: format!("ThreadId({})", record.lane + 1),
+                    thread_name: lanes.get(record.lane).cloned(),
+                },
+                trace: record.trace,
+                final_fields: None,
+                sequence: None,
+            })
+            .collect()
+    }
+
+    /// Assigns each span to a lane, which becomes a recorded thread.
+    ///
+    /// A span is put on the lane where its parent is the innermost entered span, if there is
+    /// one, so that it is entered within its parent. Otherwise it is put on the first lane
+    /// without any entered spans, or a new lane if there isn't one.
+    fn assign_lanes(&mut self, parents: &[Option<usize>]) -> Vec<usize> {
+        // The spans entered on each lane, innermost last.
+        let mut entered: Vec<Vec<usize>> = Vec::new();
+        let mut span_lanes = Vec::with_capacity(self.spans.len());
+        for (idx, span) in self.spans.iter().enumerate() {
+            for stack in &mut entered {
+                // A span which ends at the same time as this one starts is still entered, as
+                // exits are ordered after enters with the same timestamp.
+                while stack
+                    .last()
+                    .is_some_and(|&top| self.spans[top].end < span.start)
+                {
+                    stack.pop();
+                }
+            }
+
+            let fits = |stack: &Vec<usize>| match stack.last() {
+                Some(&top) => Some(top) == parents[idx] && span.end <= self.spans[top].end,
+                None => false,
+            };
+            let lane = match entered.iter().position(fits) {
+                Some(lane) => lane,
+                None => match entered.iter().position(Vec::is_empty) {
+                    Some(lane) => lane,
+                    None => {
+                        entered.push(Vec::new());
+                        entered.len() - 1
+                    }
+                },
+            };
+            entered[lane].push(idx);
+            if self.lanes.len() <= lane {
+                let service_name = span
+                    .source
+                    .service_name
+                    .as_deref()
+                    .unwrap_or(DEFAULT_SERVICE_NAME);
+                self.lanes.push(format!("{service_name}-{}", lane + 1));
+            }
+            span_lanes.push(lane);
+        }
+
+        if self.lanes.is_empty() && !self.logs.is_empty() {
+            let service_name = self.logs[0]
+                .source
+                .service_name
+                .as_deref()
+                .unwrap_or(DEFAULT_SERVICE_NAME);
+            self.lanes.push(format!("{service_name}-1"));
+        }
+
+        span_lanes
+    }
+
+    fn push(&mut self, time: u64, order: (u8, usize, usize), lane: usize, trace: Trace) {
+        self.records.push(PendingRecord {
+            time,
+            order,
+            lane,
+            trace,
+        });
+    }
+
+    /// Returns the metadata for the callsite, registering it if it is new.
+    fn callsite(
+        &mut self,
+        key: CallsiteKey,
+        time: u64,
+        order: (u8, usize, usize),
+        lane: usize,
+    ) -> Metadata {
+        let (idx, register) = match self.callsites.iter().position(|callsite| *callsite == key) {
+            Some(idx) => (idx, false),
+            None => {
+                self.callsites.push(key);
+                (self.callsites.len() - 1, true)
+            }
+        };
+        let key = &self.callsites[idx];
+        let metadata = Metadata {
+            id: idx as u64 + 1,
+            name: key.name.clone(),
+            target: key.target.clone(),
+            level: key.level.clone(),
+            module_path: key.location.module_path.clone(),
+            file: key.location.file.clone(),
+            line: key.location.line,
+            fields: key.fields.clone(),
+            kind: key.kind.clone(),
+        };
+        if register {
+            self.push(time, order, lane, Trace::RegisterCallsite(metadata.clone()));
+        }
+        metadata
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn event(
+        &mut self,
+        time: u64,
+        order: (u8, usize, usize),
+        lane: usize,
+        parent: Parent,
+        target: String,
+        level: Level,
+        location: Location,
+        fields: Vec<Field>,
+    ) {
+        // Events are named after their location, in the same way as `tracing` names them.
+        let name = match (&location.file, location.line) {
+            (Some(file), Some(line)) => format!("event {file}:{line}"),
+            _ => "event".to_owned(),
+        };
+        let metadata = self.callsite(
+            CallsiteKey {
+                name,
+                target,
+                level,
+                kind: Kind::Event,
+                fields: fields.iter().map(|field| field.name.clone()).collect(),
+                location,
+            },
+            time,
+            order,
+            lane,
+        );
+        self.push(
+            time,
+            order,
+            lane,
+            Trace::Event(Event {
+                fields,
+                metadata,
+                parent,
+            }),
+        );
+    }
+}
+
+/// The recorded span Id given to the span at `idx`, span Ids must be non-zero.
+fn span_record_id(idx: usize) -> SpanId {
+    SpanId::from(idx as u64 + 1)
+}
+
+/// Returns the number of ancestors of each span.
+fn depths(parents: &[Option<usize>]) -> Vec<usize> {
+    let mut depths: Vec<usize> = Vec::with_capacity(parents.len());
+    for parent in parents {
+        // Spans are sorted by start time, so a parent which started earlier already has its
+        // depth. A parent which started later can only come from an invalid export.
+        let depth = match parent {
+            Some(parent) => depths.get(*parent).map_or(0, |depth| depth + 1),
+            None => 0,
+        };
+        depths.push(depth);
+    }
+    depths
+}
+
+/// Returns the time at which each span is closed, which is once it has ended and all its
+/// children have been closed.
+fn close_times(spans: &[ImportedSpan], parents: &[Option<usize>]) -> Vec<u64> {
+    let mut close_times: Vec<u64> = spans.iter().map(|span| span.end).collect();
+    // Children start after their parents, so visiting the spans in reverse handles the children
+    // before their parents.
+    for idx in (0..spans.len()).rev() {
+        if let Some(parent) = parents[idx] {
+            if parent < idx {
+                close_times[parent] = close_times[parent].max(close_times[idx]);
+            }
+        }
+    }
+    close_times
+}
+
+/// Splits the attributes which describe a callsite from the rest, which become fields.
+///
+/// Returns the location, target, level, and the remaining fields.
+fn split_attributes(
+    attributes: Vec<Field>,
+    source: &Source,
+) -> (Location, String, Level, Vec<Field>) {
+    let mut location = Location::default();
+    let mut target = None;
+    let mut level = Level::Info;
+    let mut fields = Vec::new();
+    for attribute in attributes {
+        match (attribute.name.as_str(), attribute.value) {
+            ("code.namespace", FieldValue::Str(value)) => location.module_path = Some(value),
+            ("code.filepath" | "code.file.path", FieldValue::Str(value)) => {
+                location.file = Some(value);
+            }
+            ("code.lineno" | "code.line.number", FieldValue::I64(value)) => {
+                location.line = u32::try_from(value).ok();
+            }
+            ("target", FieldValue::Str(value)) => target = Some(value),
+            ("level", FieldValue::Str(value)) => level = parse_level(&value).unwrap_or(level),
+            // The thread name is only used by the exporter, the threads are assigned again.
+            ("thread.name", FieldValue::Str(_)) => {}
+            (_, value) => fields.push(Field {
+                name: attribute.name,
+                value,
+            }),
+        }
+    }
+    let target = target
+        .or_else(|| source.scope_name.clone())
+        .or_else(|| source.service_name.clone())
+        .unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_owned());
+
+    (location, target, level, fields)
+}
+
+fn parse_level(level: &str) -> Option<Level> {
+    match level.to_ascii_uppercase().as_str() {
+        "TRACE" => Some(Level::Trace),
+        "DEBUG" => Some(Level::Debug),
+        "INFO" => Some(Level::Info),
+        "WARN" => Some(Level::Warn),
+        "ERROR" => Some(Level::Error),
+        _ => None,
+    }
+}
+
+/// Converts an OpenTelemetry severity number into a level.
+fn severity_level(severity_number: u64) -> Level {
+    match severity_number {
+        1..=4 => Level::Trace,
+        5..=8 => Level::Debug,
+        13..=16 => Level::Warn,
+        17.. => Level::Error,
+        // Unspecified severities are taken as info.
+        _ => Level::Info,
+    }
+}
+
+/// Returns the field `camel_case` of `value`, or `snake_case` if it isn't there.
+///
+/// OTLP JSON uses the camel case names, but the snake case names from the protobuf definitions
+/// are accepted by most parsers.
+fn get<'a>(value: &'a Value, camel_case: &str, snake_case: &str) -> Option<&'a Value> {
+    value.get(camel_case).or_else(|| value.get(snake_case))
+}
+
+/// Returns the elements of an array, or nothing if `value` isn't an array.
+fn array(value: Option<&Value>) -> impl Iterator<Item = &Value> {
+    value
+        .and_then(Value::as_array)
+        .into_iter()
+        .flat_map(|items| items.iter())
+}
+
+/// Returns the spans or logs of a `ResourceSpans` or `ResourceLogs`, with where they came from.
+fn json_items<'a>(
+    resource_data: &'a Value,
+    scope_data: &str,
+    items: &str,
+) -> Result<Vec<(Source, &'a Value)>, ImportError> {
+    let service_name = array(
+        resource_data
+            .get("resource")
+            .and_then(|resource| resource.get("attributes")),
+    )
+    .map(json_attribute)
+    .collect::<Result<Vec<_>, _>>()?
+    .into_iter()
+    .find_map(|attribute| match attribute {
+        Field {
+            name,
+            value: FieldValue::Str(value),
+        } if name == "service.name" => Some(value),
+        _ => None,
+    });
+
+    let mut result = Vec::new();
+    let snake_scope_data = to_snake_case(scope_data);
+    let snake_items = to_snake_case(items);
+    for scope_data in array(get(resource_data, scope_data, &snake_scope_data)) {
+        let source = Source {
+            service_name: service_name.clone(),
+            scope_name: scope_data
+                .get("scope")
+                .and_then(|scope| scope.get("name"))
+                .and_then(Value::as_str)
+                .filter(|name| !name.is_empty())
+                .map(str::to_owned),
+        };
+        for item in array(get(scope_data, items, &snake_items)) {
+            result.push((source.clone(), item));
+        }
+    }
+
+    Ok(result)
+}
+
+fn to_snake_case(camel_case: &str) -> String {
+    let mut snake_case = String::with_capacity(camel_case.len() + 2);
+    for c in camel_case.chars() {
+        if c.is_ascii_uppercase() {
+            snake_case.push('_');
+            snake_case.push(c.to_ascii_lowercase());
+        } else {
+            snake_case.push(c);
+        }
+    }
+    snake_case
+}
+
+fn json_span(span: &Value, source: Source) -> Result<ImportedSpan, ImportError> {
+    let parent_span_id = match get(span, "parentSpanId", "parent_span_id").and_then(Value::as_str) {
+        Some(parent_span_id) if !parent_span_id.is_empty() => Some(parse_span_id(parent_span_id)?),
+        _ => None,
+    };
+    let events = array(span.get("events"))
+        .map(|event| {
+            Ok(ImportedEvent {
+                time: json_time(get(event, "timeUnixNano", "time_unix_nano"))?,
+                name: event
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_owned(),
+                attributes: json_attributes(event)?,
+            })
+        })
+        .collect::<Result<_, ImportError>>()?;
+
+    Ok(ImportedSpan {
+        trace_id: parse_trace_id(json_str(get(span, "traceId", "trace_id"), "traceId")?)?,
+        span_id: parse_span_id(json_str(get(span, "spanId", "span_id"), "spanId")?)?,
+        parent_span_id,
+        name: json_str(span.get("name"), "name")?.to_owned(),
+        start: json_time(get(span, "startTimeUnixNano", "start_time_unix_nano"))?,
+        end: json_time(get(span, "endTimeUnixNano", "end_time_unix_nano"))?,
+        attributes: json_attributes(span)?,
+        events,
+        source,
+    })
+}
+
+fn json_log(log: &Value, source: Source) -> Result<ImportedLog, ImportError> {
+    let time = match get(log, "timeUnixNano", "time_unix_nano") {
+        Some(time) if json_time(Some(time))? != 0 => json_time(Some(time))?,
+        _ => json_time(get(log, "observedTimeUnixNano", "observed_time_unix_nano"))?,
+    };
+    let trace_id = get(log, "traceId", "trace_id").and_then(Value::as_str);
+    let span_id = get(log, "spanId", "span_id").and_then(Value::as_str);
+    let span = match (trace_id, span_id) {
+        (Some(trace_id), Some(span_id)) if !trace_id.is_empty() && !span_id.is_empty() => {
+            Some((parse_trace_id(trace_id)?, parse_span_id(span_id)?))
+        }
+        _ => None,
+    };
+    let severity_number = get(log, "severityNumber", "severity_number")
+        .and_then(Value::as_u64)
+        .unwrap_or_default();
+
+    Ok(ImportedLog {
+        time,
+        level: severity_level(severity_number),
+        body: log.get("body").map(json_value).transpose()?,
+        attributes: json_attributes(log)?,
+        span,
+        source,
+    })
+}
+
+fn json_str<'a>(value: Option<&'a Value>, name: &str) -> Result<&'a str, ImportError> {
+    value
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid(format!("expected {name} to be a string")))
+}
+
+/// Parses a timestamp, which may be written as a string or a number.
+fn json_time(value: Option<&Value>) -> Result<u64, ImportError> {
+    match value {
+        None => Ok(0),
+        Some(Value::String(time)) => time
+            .parse()
+            .map_err(|_| invalid(format!("invalid timestamp: {time:?}"))),
+        Some(time) => time
+            .as_u64()
+            .ok_or_else(|| invalid(format!("invalid timestamp: {time}"))),
+    }
+}
+
+fn json_attributes(value: &Value) -> Result<Vec<Field>, ImportError> {
+    array(value.get("attributes")).map(json_attribute).collect()
+}
+
+fn json_attribute(attribute: &Value) -> Result<Field, ImportError> {
+    Ok(Field {
+        name: json_str(attribute.get("key"), "key")?.to_owned(),
+        value: attribute
+            .get("value")
+            .map_or(Ok(FieldValue::Debug(String::new())), json_value)?,
+    })
+}
+
+/// Converts an `AnyValue` into a field value, arrays and key value lists are recorded as
+/// structured values, and other values which `tracing` can't record directly are recorded as
+/// their JSON encoding.
+fn json_value(value: &Value) -> Result<FieldValue, ImportError> {
+    if let Some(string) = get(value, "stringValue", "string_value").and_then(Value::as_str) {
+        return Ok(FieldValue::Str(string.to_owned()));
+    }
+    if let Some(boolean) = get(value, "boolValue", "bool_value").and_then(Value::as_bool) {
+        return Ok(FieldValue::Bool(boolean));
+    }
+    if let Some(int) = get(value, "intValue", "int_value") {
+        let parsed = match int {
+            Value::String(int) => int.parse().ok(),
+            int => int.as_i64(),
+        };
+        return parsed
+            .map(FieldValue::I64)
+            .ok_or_else(|| invalid(format!("invalid intValue: {int}")));
+    }
+    if let Some(double) = get(value, "doubleValue", "double_value").and_then(Value::as_f64) {
+        return Ok(FieldValue::F64(double));
+    }
+    if get(value, "arrayValue", "array_value").is_some()
+        || get(value, "kvlistValue", "kvlist_value").is_some()
+    {
+        return plain_json(value).map(FieldValue::Json);
+    }
+    Ok(FieldValue::Debug(value.to_string()))
+}
+
+/// Converts an `AnyValue` into the plain JSON value that it describes.
+fn plain_json(value: &Value) -> Result<Value, ImportError> {
+    if let Some(values) = get(value, "arrayValue", "array_value") {
+        return array(values.get("values"))
+            .map(plain_json)
+            .collect::<Result<_, _>>()
+            .map(Value::Array);
+    }
+    if let Some(list) = get(value, "kvlistValue", "kvlist_value") {
+        return array(list.get("values"))
+            .map(|entry| {
+                let key = json_str(entry.get("key"), "key")?.to_owned();
+                let value = entry.get("value").map_or(Ok(Value::Null), plain_json)?;
+                Ok((key, value))
+            })
+            .collect::<Result<Map<_, _>, _>>()
+            .map(Value::Object);
+    }
+    Ok(match json_value(value)? {
+        FieldValue::Str(value) => value.into(),
+        FieldValue::Bool(value) => value.into(),
+        FieldValue::I64(value) => value.into(),
+        FieldValue::F64(value) => value.into(),
+        _ => value.clone(),
+    })
+}
+
+fn parse_trace_id(trace_id: &str) -> Result<u128, ImportError> {
+    u128::from_str_radix(trace_id, 16)
+        .ok()
+        .filter(|_| trace_id.len() == 32)
+        .ok_or_else(|| invalid(format!("invalid trace Id: {trace_id:?}")))
+}
+
+fn parse_span_id(span_id: &str) -> Result<u64, ImportError> {
+    u64::from_str_radix(span_id, 16)
+        .ok()
+        .filter(|_| span_id.len() == 16)
+        .ok_or_else(|| invalid(format!("invalid span Id: {span_id:?}")))
+}
+
+/// Returns the trace context of a span, unless either of its Ids is invalid, which is all zeros.
+fn trace_context(trace_id: u128, span_id: u64) -> Option<TraceContext> {
+    (trace_id != 0 && span_id != 0).then_some(TraceContext { trace_id, span_id })
+}
+
+/// Returns the spans or logs of a protobuf encoded `TracesData` or `LogsData`, with where they
+/// came from.
+fn protobuf_items(data: &[u8]) -> Result<Vec<(Source, &[u8])>, ImportError> {
+    let mut result = Vec::new();
+    for field in Fields::new(data) {
+        let (RESOURCE_DATA, resource_data) = field? else {
+            continue;
+        };
+
+        let mut service_name = None;
+        let mut scopes = Vec::new();
+        for field in Fields::new(resource_data.bytes()?) {
+            match field? {
+                (RESOURCE, resource) => {
+                    for field in Fields::new(resource.bytes()?) {
+                        if let (RESOURCE_ATTRIBUTES, attribute) = field? {
+                            if let Field {
+                                name,
+                                value: FieldValue::Str(value),
+                            } = protobuf_attribute(attribute)?
+                            {
+                                if name == "service.name" {
+                                    service_name = Some(value);
+                                }
+                            }
+                        }
+                    }
+                }
+                (SCOPE_DATA, scope_data) => scopes.push(scope_data.bytes()?),
+                _ => {}
+            }
+        }
+
+        for scope_data in scopes {
+            let mut source = Source {
+                service_name: service_name.clone(),
+                scope_name: None,
+            };
+            let mut items = Vec::new();
+            for field in Fields::new(scope_data) {
+                match field? {
+                    (SCOPE, scope) => {
+                        for field in Fields::new(scope.bytes()?) {
+                            if let (SCOPE_NAME, name) = field? {
+                                source.scope_name =
+                                    Some(name.string()?.to_owned()).filter(|name| !name.is_empty());
+                            }
+                        }
+                    }
+                    (SCOPE_ITEMS, item) => items.push(item.bytes()?),
+                    _ => {}
+                }
+            }
+            result.extend(items.into_iter().map(|item| (source.clone(), item)));
+        }
+    }
+
+    Ok(result)
+}
+
+fn protobuf_span(span: &[u8], source: Source) -> Result<ImportedSpan, ImportError> {
+    let mut imported = ImportedSpan {
+        trace_id: 0,
+        span_id: 0,
+        parent_span_id: None,
+        name: String::new(),
+        start: 0,
+        end: 0,
+        attributes: Vec::new(),
+        events: Vec::new(),
+        source,
+    };
+    for field in Fields::new(span) {
+        match field? {
+            (SPAN_TRACE_ID, trace_id) => imported.trace_id = protobuf_trace_id(trace_id)?,
+            (SPAN_SPAN_ID, span_id) => imported.span_id = protobuf_span_id(span_id)?,
+            (SPAN_PARENT_SPAN_ID, parent_span_id) if !parent_span_id.bytes()?.is_empty() => {
+                imported.parent_span_id = Some(protobuf_span_id(parent_span_id)?);
+            }
+            (SPAN_NAME, name) => name.string()?.clone_into(&mut imported.name),
+            (SPAN_START_TIME, start) => imported.start = start.fixed64()?,
+            (SPAN_END_TIME, end) => imported.end = end.fixed64()?,
+            (SPAN_ATTRIBUTES, attribute) => {
+                imported.attributes.push(protobuf_attribute(attribute)?);
+            }
+            (SPAN_EVENTS, event) => {
+                let mut imported_event = ImportedEvent {
+                    time: 0,
+                    name: String::new(),
+                    attributes: Vec::new(),
+                };
+                for field in Fields::new(event.bytes()?) {
+                    match field? {
+                        (EVENT_TIME, time) => imported_event.time = time.fixed64()?,
+                        (EVENT_NAME, name) => name.string()?.clone_into(&mut imported_event.name),
+                        (EVENT_ATTRIBUTES, attribute) => {
+                            imported_event
+                                .attributes
+                                .push(protobuf_attribute(attribute)?);
+                        }
+                        _ => {}
+                    }
+                }
+                imported.events.push(imported_event);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(imported)
+}
+
+fn protobuf_log(log: &[u8], source: Source) -> Result<ImportedLog, ImportError> {
+    let mut time = 0;
+    let mut observed_time = 0;
+    let mut imported = ImportedLog {
+        time: 0,
+        level: Level::Info,
+        body: None,
+        attributes: Vec::new(),
+        span: None,
+        source,
+    };
+    let mut trace_id = None;
+    let mut span_id = None;
+    for field in Fields::new(log) {
+        match field? {
+            (LOG_TIME, value) => time = value.fixed64()?,
+            (LOG_OBSERVED_TIME, value) => observed_time = value.fixed64()?,
+            (LOG_SEVERITY_NUMBER, severity_number) => {
+                imported.level = severity_level(severity_number.varint()?);
+            }
+            (LOG_BODY, body) => imported.body = Some(protobuf_value(body.bytes()?)?),
+            (LOG_ATTRIBUTES, attribute) => imported.attributes.push(protobuf_attribute(attribute)?),
+            (LOG_TRACE_ID, value) if !value.bytes()?.is_empty() => {
+                trace_id = Some(protobuf_trace_id(value)?);
+            }
+            (LOG_SPAN_ID, value) if !value.bytes()?.is_empty() => {
+                span_id = Some(protobuf_span_id(value)?);
+            }
+            _ => {}
+        }
+    }
+    imported.time = if time == 0 { observed_time } else { time };
+    imported.span = trace_id.zip(span_id);
+
+    Ok(imported)
+}
+
+fn protobuf_trace_id(trace_id: FieldData<'_>) -> Result<u128, ImportError> {
+    let bytes: [u8; 16] = trace_id
+        .bytes()?
+        .try_into()
+        .map_err(|_| invalid("trace Id must be 16 bytes"))?;
+    Ok(u128::from_be_bytes(bytes))
+}
+
+fn protobuf_span_id(span_id: FieldData<'_>) -> Result<u64, ImportError> {
+    let bytes: [u8; 8] = span_id
+        .bytes()?
+        .try_into()
+        .map_err(|_| invalid("span Id must be 8 bytes"))?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+fn protobuf_attribute(attribute: FieldData<'_>) -> Result<Field, ImportError> {
+    let mut name = String::new();
+    let mut value = FieldValue::Debug(String::new());
+    for field in Fields::new(attribute.bytes()?) {
+        match field? {
+            (KEY_VALUE_KEY, key) => key.string()?.clone_into(&mut name),
+            (KEY_VALUE_VALUE, any_value) => value = protobuf_value(any_value.bytes()?)?,
+            _ => {}
+        }
+    }
+
+    Ok(Field { name, value })
+}
+
+/// Converts an `AnyValue` into a field value, values which `tracing` can't record directly are
+/// recorded as a placeholder.
+fn protobuf_value(any_value: &[u8]) -> Result<FieldValue, ImportError> {
+    let mut value = FieldValue::Debug(String::new());
+    for field in Fields::new(any_value) {
+        value = match field? {
+            (ANY_STRING, string) => FieldValue::Str(string.string()?.to_owned()),
+            (ANY_BOOL, boolean) => FieldValue::Bool(boolean.varint()? != 0),
+            // Negative values are encoded in two's complement.
+            (ANY_INT, int) => FieldValue::I64(int.varint()? as i64),
+            (ANY_DOUBLE, double) => FieldValue::F64(double.double()?),
+            (_, FieldData::Bytes(_)) => FieldValue::Debug("<unsupported value>".to_owned()),
+            _ => continue,
+        };
+    }
+
+    Ok(value)
+}
@@ -0,0 +1,589 @@
+//! Export of recordings to Parquet.
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    sync::Arc,
+};
+
+use parquet::{
+    data_type::{ByteArray, ByteArrayType, DataType, Int32Type, Int64Type},
+    errors::ParquetError,
+    file::{
+        properties::WriterProperties,
+        writer::{SerializedFileWriter, SerializedRowGroupWriter},
+    },
+    schema::parser::parse_message_type,
+};
+use serde_json::{Map, Value};
+
+use crate::{
+    export::{self, value_to_string, ExportError},
+    Field, FieldValue, Level, Metadata, Parent, RecordMeta, SpanId, Trace,
+};
+
+/// The most rows in a row group, so that large recordings aren't held in memory.
+const ROW_GROUP_ROWS: usize = 64 * 1024;
+
+const EVENTS_SCHEMA: &str = "
+message events {
+    REQUIRED INT64 timestamp (TIMESTAMP(MICROS, true));
+    REQUIRED BYTE_ARRAY thread_id (STRING);
+    OPTIONAL BYTE_ARRAY thread_name (STRING);
+    OPTIONAL INT64 span_id (INTEGER(64, false));
+    REQUIRED BYTE_ARRAY level (STRING);
+    REQUIRED BYTE_ARRAY target (STRING);
+    REQUIRED BYTE_ARRAY name (STRING);
+    OPTIONAL BYTE_ARRAY module_path (STRING);
+    OPTIONAL BYTE_ARRAY file (STRING);
+    OPTIONAL INT32 line (INTEGER(32, false));
+    OPTIONAL BYTE_ARRAY message (STRING);
+    REQUIRED BYTE_ARRAY fields (JSON);
+}
+";
+
+const SPANS_SCHEMA: &str = "
+message spans {
+    REQUIRED INT64 timestamp (TIMESTAMP(MICROS, true));
+    REQUIRED BYTE_ARRAY thread_id (STRING);
+    OPTIONAL BYTE_ARRAY thread_name (STRING);
+    REQUIRED BYTE_ARRAY kind (STRING);
+    REQUIRED INT64 span_id (INTEGER(64, false));
+    OPTIONAL BYTE_ARRAY level (STRING);
+    OPTIONAL BYTE_ARRAY target (STRING);
+    OPTIONAL BYTE_ARRAY name (STRING);
+    OPTIONAL INT64 parent_id (INTEGER(64, false));
+    OPTIONAL INT64 follows_from_id (INTEGER(64, false));
+    OPTIONAL BYTE_ARRAY fields (JSON);
+}
+";
+
+/// Exports the recording read from `reader` to two Parquet files, one of the events written to
+/// `events`, and one of the span lifecycle written to `spans`.
+///
+/// Each event is a row of the events table, with the time it was recorded, the thread it was
+/// recorded on, the span it was recorded in (`span_id`), its callsite, its message, and its other
+/// fields as a JSON object. The span that an event was recorded in is its explicit parent, or
+/// the innermost span entered on its thread when it has a contextual parent.
+///
+/// Each record of a span is a row of the spans table, with its `kind` being one of `new_span`,
+/// `enter`, `exit`, `record`, `follows_from`, or `close`. Each row has the name, target, and
+/// level of the span, when the span was created in the recording. When a span is created, the
+/// row has its parent, resolved in the same way as for events, and its fields. Recorded values
+/// have their fields, and `follows_from` rows have the span that the span follows from in
+/// `follows_from_id`.
+///
+/// Timestamps are in microseconds since the UNIX epoch, and field values are JSON numbers,
+/// booleans, or strings, with 128-bit integers which don't fit in 64 bits written as strings.
+/// This allows large recordings to be analyzed with SQL or data frames, for example with DuckDB:
+///
+/// ```sql
+/// SELECT name, count(*), max(epoch_us(timestamp) - epoch_us(entered)) AS longest_us
+/// FROM (
+///     SELECT name, kind, timestamp,
+///         lag(timestamp) OVER (PARTITION BY span_id, thread_id ORDER BY timestamp) AS entered
+///     FROM 'recording.spans.parquet'
+///     WHERE kind IN ('enter', 'exit')
+/// )
+/// WHERE kind = 'exit'
+/// GROUP BY name;
+/// ```
+///
+/// The files are written uncompressed. Requires the `parquet` feature.
+///
+/// # Errors
+///
+/// Returns an error if reading the recording or writing either file fails, if a line of the
+/// recording can't be deserialized into a record, or if the recording was written in a newer
+/// version of the format.
+///
+/// # Examples
+///
+/// ```
+/// use parquet::file::reader::{FileReader, SerializedFileReader};
+///
+/// let recording = concat!(
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":1,"fields":[{"name":"user_id","value":{"I64":7}}],"metadata":{"id":4403349456,"name":"request","target":"app","level":"Info","module_path":"app","file":"src/main.rs","line":8,"fields":["user_id"],"kind":"Span"},"parent":"Root"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543410,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543420,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"slow response"}}],"metadata":{"id":4403349608,"name":"event","target":"app","level":"Warn","module_path":"app","file":"src/main.rs","line":9,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543430,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Exit":1}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543440,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Close":1}}"#,
+///     "\n",
+/// );
+///
+/// let dir = std::env::temp_dir();
+/// let events_path = dir.join("tracing-cassette-doctest.events.parquet");
+/// let spans_path = dir.join("tracing-cassette-doctest.spans.parquet");
+/// tracing_cassette::to_parquet(
+///     recording.as_bytes(),
+///     std::fs::File::create(&events_path).unwrap(),
+///     std::fs::File::create(&spans_path).unwrap(),
+/// )
+/// .unwrap();
+///
+/// let events = SerializedFileReader::new(std::fs::File::open(&events_path).unwrap()).unwrap();
+/// assert_eq!(events.metadata().file_metadata().num_rows(), 1);
+/// let spans = SerializedFileReader::new(std::fs::File::open(&spans_path).unwrap()).unwrap();
+/// assert_eq!(spans.metadata().file_metadata().num_rows(), 4);
+/// ```
+pub fn to_parquet<R, E, S>(reader: R, events: E, spans: S) -> Result<(), ExportError>
+where
+    R: BufRead,
+    E: Write + Send,
+    S: Write + Send,
+{
+    let mut exporter = ParquetExporter::new(events, spans).map_err(io::Error::other)?;
+    export::for_each_record(reader, |record| {
+        exporter
+            .push(record.meta, record.trace)
+            .map_err(|err| io::Error::other(err).into())
+    })?;
+    exporter.finish().map_err(io::Error::other)?;
+
+    Ok(())
+}
+
+/// A span which has been created in the recording.
+#[derive(Debug)]
+struct SpanInfo {
+    level: ByteArray,
+    target: ByteArray,
+    name: ByteArray,
+}
+
+/// The tables of the recording, which are written a row group at a time.
+struct ParquetExporter<E: Write + Send, S: Write + Send> {
+    events_writer: SerializedFileWriter<E>,
+    events: EventsTable,
+    spans_writer: SerializedFileWriter<S>,
+    spans: SpansTable,
+    /// The spans which are open, by Id.
+    open_spans: HashMap<SpanId, SpanInfo>,
+    /// The spans entered on each thread, innermost last.
+    entered: HashMap<String, Vec<SpanId>>,
+}
+
+impl<E: Write + Send, S: Write + Send> ParquetExporter<E, S> {
+    fn new(events: E, spans: S) -> Result<Self, ParquetError> {
+        let properties = Arc::new(WriterProperties::builder().build());
+        Ok(Self {
+            events_writer: SerializedFileWriter::new(
+                events,
+                Arc::new(parse_message_type(EVENTS_SCHEMA)?),
+                Arc::clone(&properties),
+            )?,
+            events: EventsTable::default(),
+            spans_writer: SerializedFileWriter::new(
+                spans,
+                Arc::new(parse_message_type(SPANS_SCHEMA)?),
+                properties,
+            )?,
+            spans: SpansTable::default(),
+            open_spans: HashMap::new(),
+            entered: HashMap::new(),
+        })
+    }
+
+    fn push(&mut self, meta: RecordMeta, trace: Trace) -> Result<(), ParquetError> {
+        match trace {
+            Trace::RegisterCallsite(_) => {}
+            Trace::Event(event) => {
+                let span_id = self.parent(&meta.thread_id, &event.parent);
+                let (message, fields): (Vec<Field>, Vec<Field>) = event
+                    .fields
+                    .into_iter()
+                    .partition(|field| field.name == "message");
+                self.events.push(
+                    meta,
+                    span_id,
+                    &event.metadata,
+                    message
+                        .into_iter()
+                        .last()
+                        .map(|field| value_to_string(field.value)),
+                    fields,
+                );
+                if self.events.len() >= ROW_GROUP_ROWS {
+                    self.events.write(&mut self.events_writer)?;
+                }
+            }
+            Trace::NewSpan(new_span) => {
+                let parent_id = self.parent(&meta.thread_id, &new_span.parent);
+                let info = SpanInfo::new(&new_span.metadata);
+                self.push_span(
+                    meta,
+                    "new_span",
+                    new_span.id,
+                    Some(&info),
+                    SpanRow {
+                        parent_id,
+                        follows_from_id: None,
+                        fields: Some(new_span.fields),
+                    },
+                )?;
+                // A span Id may be reused once the span is closed, so this replaces that span.
+                self.open_spans.insert(new_span.id, info);
+            }
+            Trace::Enter(id) => {
+                self.entered
+                    .entry(meta.thread_id.clone())
+                    .or_default()
+                    .push(id);
+                self.push_span(meta, "enter", id, None, SpanRow::default())?;
+            }
+            Trace::Exit(id) => {
+                if let Some(entered) = self.entered.get_mut(&meta.thread_id) {
+                    if let Some(idx) = entered.iter().rposition(|entered| *entered == id) {
+                        entered.remove(idx);
+                    }
+                }
+                self.push_span(meta, "exit", id, None, SpanRow::default())?;
+            }
+            Trace::Record(record_values) => {
+                self.push_span(
+                    meta,
+                    "record",
+                    record_values.id,
+                    None,
+                    SpanRow {
+                        fields: Some(record_values.fields),
+                        ..SpanRow::default()
+                    },
+                )?;
+            }
+            Trace::FollowsFrom(follows_from) => {
+                self.push_span(
+                    meta,
+                    "follows_from",
+                    follows_from.effect_id,
+                    None,
+                    SpanRow {
+                        follows_from_id: Some(follows_from.cause_id),
+                        ..SpanRow::default()
+                    },
+                )?;
+            }
+            Trace::Close(id) => {
+                let info = self.open_spans.remove(&id);
+                self.push_span(meta, "close", id, info.as_ref(), SpanRow::default())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds a row to the spans table, for the span `id` unless its `info` is provided.
+    fn push_span(
+        &mut self,
+        meta: RecordMeta,
+        kind: &str,
+        id: SpanId,
+        info: Option<&SpanInfo>,
+        row: SpanRow,
+    ) -> Result<(), ParquetError> {
+        let info = info.or_else(|| self.open_spans.get(&id));
+        self.spans.push(meta, kind, id, info, row);
+        if self.spans.len() >= ROW_GROUP_ROWS {
+            self.spans.write(&mut self.spans_writer)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the span which is the parent of a span or event recorded on `thread_id`.
+    fn parent(&self, thread_id: &str, parent: &Parent) -> Option<SpanId> {
+        match parent {
+            Parent::Root => None,
+            Parent::Current => self
+                .entered
+                .get(thread_id)
+                .and_then(|entered| entered.last())
+                .copied(),
+            Parent::Explicit(id) => Some(*id),
+        }
+    }
+
+    fn finish(mut self) -> Result<(), ParquetError> {
+        // Empty row groups aren't written, but the files are, so that they can always be read.
+        if self.events.len() > 0 {
+            self.events.write(&mut self.events_writer)?;
+        }
+        self.events_writer.close()?;
+        if self.spans.len() > 0 {
+            self.spans.write(&mut self.spans_writer)?;
+        }
+        self.spans_writer.close()?;
+
+        Ok(())
+    }
+}
+
+impl SpanInfo {
+    fn new(metadata: &Metadata) -> Self {
+        Self {
+            level: level(&metadata.level).into(),
+            target: metadata.target.as_str().into(),
+            name: metadata.name.as_str().into(),
+        }
+    }
+}
+
+/// The columns of a row of the spans table which only some kinds of rows have.
+#[derive(Debug, Default)]
+struct SpanRow {
+    parent_id: Option<SpanId>,
+    follows_from_id: Option<SpanId>,
+    fields: Option<Vec<Field>>,
+}
+
+/// The rows of the events table which haven't been written yet.
+#[derive(Debug, Default)]
+struct EventsTable {
+    timestamp: Column<i64>,
+    thread_id: Column<ByteArray>,
+    thread_name: Column<ByteArray>,
+    span_id: Column<i64>,
+    level: Column<ByteArray>,
+    target: Column<ByteArray>,
+    name: Column<ByteArray>,
+    module_path: Column<ByteArray>,
+    file: Column<ByteArray>,
+    line: Column<i32>,
+    message: Column<ByteArray>,
+    fields: Column<ByteArray>,
+}
+
+impl EventsTable {
+    fn len(&self) -> usize {
+        self.timestamp.values.len()
+    }
+
+    fn push(
+        &mut self,
+        meta: RecordMeta,
+        span_id: Option<SpanId>,
+        metadata: &Metadata,
+        message: Option<String>,
+        fields: Vec<Field>,
+    ) {
+        self.timestamp.push(timestamp(&meta));
+        self.thread_id.push(string(meta.thread_id));
+        self.thread_name.push_option(meta.thread_name.map(string));
+        self.span_id.push_option(span_id.map(span_id_value));
+        self.level.push(level(&metadata.level).into());
+        self.target.push(metadata.target.as_str().into());
+        self.name.push(metadata.name.as_str().into());
+        self.module_path
+            .push_option(metadata.module_path.as_deref().map(Into::into));
+        self.file
+            .push_option(metadata.file.as_deref().map(Into::into));
+        // Lines are written as unsigned 32-bit integers, so their bits are kept as they are.
+        #[allow(clippy::cast_possible_wrap)]
+        self.line.push_option(metadata.line.map(|line| line as i32));
+        self.message.push_option(message.map(string));
+        self.fields.push(fields_json(fields));
+    }
+
+    fn write<W: Write + Send>(
+        &mut self,
+        writer: &mut SerializedFileWriter<W>,
+    ) -> Result<(), ParquetError> {
+        let mut row_group = writer.next_row_group()?;
+        write_column::<Int64Type, _>(&mut row_group, &mut self.timestamp)?;
+        write_column::<ByteArrayType, _>(&mut row_group, &mut self.thread_id)?;
+        write_column::<ByteArrayType, _>(&mut row_group, &mut self.thread_name)?;
+        write_column::<Int64Type, _>(&mut row_group, &mut self.span_id)?;
+        write_column::<ByteArrayType, _>(&mut row_group, &mut self.level)?;
+        write_column::<ByteArrayType, _>(&mut row_group, &mut self.target)?;
+        write_column::<ByteArrayType, _>(&mut row_group, &mut self.name)?;
+        write_column::<ByteArrayType, _>(&mut row_group, &mut self.module_path)?;
+        write_column::<ByteArrayType, _>(&mut row_group, &mut self.file)?;
+        write_column::<Int32Type, _>(&mut row_group, &mut self.line)?;
+        write_column::<ByteArrayType, _>(&mut row_group, &mut self.message)?;
+        write_column::<ByteArrayType, _>(&mut row_group, &mut self.fields)?;
+        row_group.close()?;
+
+        Ok(())
+    }
+}
+
+/// The rows of the spans table which haven't been written yet.
+#[derive(Debug, Default)]
+struct SpansTable {
+    timestamp: Column<i64>,
+    thread_id: Column<ByteArray>,
+    thread_name: Column<ByteArray>,
+    kind: Column<ByteArray>,
+    span_id: Column<i64>,
+    level: Column<ByteArray>,
+    target: Column<ByteArray>,
+    name: Column<ByteArray>,
+    parent_id: Column<i64>,
+    follows_from_id: Column<i64>,
+    fields: Column<ByteArray>,
+}
+
+impl SpansTable {
+    fn len(&self) -> usize {
+        self.timestamp.values.len()
+    }
+
+    fn push(
+        &mut self,
+        meta: RecordMeta,
+        kind: &str,
+        id: SpanId,
+        info: Option<&SpanInfo>,
+        row: SpanRow,
+    ) {
+        self.timestamp.push(timestamp(&meta));
+        self.thread_id.push(string(meta.thread_id));
+        self.thread_name.push_option(meta.thread_name.map(string));
+        self.kind.push(kind.into());
+        self.span_id.push(span_id_value(id));
+        self.level.push_option(info.map(|info| info.level.clone()));
+        self.target
+            .push_option(info.map(|info| info.target.clone()));
+        self.name.push_option(info.map(|info| info.name.clone()));
+        self.parent_id.push_option(row.parent_id.map(span_id_value));
+        self.follows_from_id
+            .push_option(row.follows_from_id.map(span_id_value));
+        self.fields.push_option(row.fields.map(fields_json));
+    }
+
+    fn write<W: Write + Send>(
+        &mut self,
+        writer: &mut SerializedFileWriter<W>,
+    ) -> Result<(), ParquetError> {
+        let mut row_group = writer.next_row_group()?;
+        write_column::<Int64Type, _>(&mut row_group, &mut self.timestamp)?;
+        write_column::<ByteArrayType, _>(&mut row_group, &mut self.thread_id)?;
+        write_column::<ByteArrayType, _>(&mut row_group, &mut self.thread_name)?;
+        write_column::<ByteArrayType, _>(&mut row_group, &mut self.kind)?;
+        write_column::<Int64Type, _>(&mut row_group, &mut self.span_id)?;
+        write_column::<ByteArrayType, _>(&mut row_group, &mut self.level)?;
+        write_column::<ByteArrayType, _>(&mut row_group, &mut self.target)?;
+        write_column::<ByteArrayType, _>(&mut row_group, &mut self.name)?;
+        write_column::<Int64Type, _>(&mut row_group, &mut self.parent_id)?;
+        write_column::<Int64Type, _>(&mut row_group, &mut self.follows_from_id)?;
+        write_column::<ByteArrayType, _>(&mut row_group, &mut self.fields)?;
+        row_group.close()?;
+
+        Ok(())
+    }
+}
+
+/// The values of a column which haven't been written yet.
+#[derive(Debug)]
+struct Column<T> {
+    values: Vec<T>,
+    /// Whether each row has a value, for optional columns, 1 if it does and 0 if it doesn't.
+    definition_levels: Vec<i16>,
+}
+
+impl<T> Default for Column<T> {
+    fn default() -> Self {
+        Self {
+            values: Vec::new(),
+            definition_levels: Vec::new(),
+        }
+    }
+}
+
+impl<T> Column<T> {
+    /// Adds the value of a required column.
+    fn push(&mut self, value: T) {
+        self.values.push(value);
+    }
+
+    /// Adds the value of an optional column.
+    fn push_option(&mut self, value: Option<T>) {
+        match value {
+            Some(value) => {
+                self.values.push(value);
+                self.definition_levels.push(1);
+            }
+            None => self.definition_levels.push(0),
+        }
+    }
+}
+
+/// Writes the next column of `row_group` from `column`, which is then empty.
+fn write_column<D, W>(
+    row_group: &mut SerializedRowGroupWriter<'_, W>,
+    column: &mut Column<D::T>,
+) -> Result<(), ParquetError>
+where
+    D: DataType,
+    W: Write + Send,
+{
+    let Some(mut writer) = row_group.next_column()? else {
+        return Err(ParquetError::General(
+            "more columns than in the schema".into(),
+        ));
+    };
+    // Required columns have no definition levels.
+    let definition_levels =
+        (!column.definition_levels.is_empty()).then_some(column.definition_levels.as_slice());
+    writer
+        .typed::<D>()
+        .write_batch(&column.values, definition_levels, None)?;
+    writer.close()?;
+    column.values.clear();
+    column.definition_levels.clear();
+
+    Ok(())
+}
+
+fn string(value: String) -> ByteArray {
+    value.into_bytes().into()
+}
+
+fn timestamp(meta: &RecordMeta) -> i64 {
+    i64::try_from(meta.timestamp().as_micros()).unwrap_or(i64::MAX)
+}
+
+/// Returns the bits of a span Id, which is written as an unsigned 64-bit integer.
+#[allow(clippy::cast_possible_wrap)]
+fn span_id_value(id: SpanId) -> i64 {
+    u64::from(id) as i64
+}
+
+/// Returns the fields as a JSON object, the last value of a field which is recorded twice wins.
+fn fields_json(fields: Vec<Field>) -> ByteArray {
+    let object: Map<String, Value> = fields
+        .into_iter()
+        .map(|field| (field.name, json_value(field.value)))
+        .collect();
+    string(Value::Object(object).to_string())
+}
+
+fn json_value(value: FieldValue) -> Value {
+    match value {
+        FieldValue::Debug(value) | FieldValue::Str(value) => Value::String(value),
+        FieldValue::F64(value) => value.into(),
+        FieldValue::I64(value) => value.into(),
+        FieldValue::U64(value) => value.into(),
+        FieldValue::I128(value) => {
+            i64::try_from(value).map_or_else(|_| Value::String(value.to_string()), Value::from)
+        }
+        FieldValue::U128(value) => {
+            u64::try_from(value).map_or_else(|_| Value::String(value.to_string()), Value::from)
+        }
+        FieldValue::Bool(value) => value.into(),
+        FieldValue::Json(value) => value,
+    }
+}
+
+fn level(level: &Level) -> &'static str {
+    match level {
+        Level::Trace => "TRACE",
+        Level::Debug => "DEBUG",
+        Level::Info => "INFO",
+        Level::Warn => "WARN",
+        Level::Error => "ERROR",
+    }
+}
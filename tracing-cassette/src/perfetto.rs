@@ -0,0 +1,339 @@
+//! Export of recordings to the Perfetto protobuf trace format.
+//!
+//! Only the few fields of the `TracePacket` messages which are needed are written, with the
+//! encoder in [`crate::protobuf`]. The field numbers are taken from the Perfetto protos, see
+//! `protos/perfetto/trace/trace_packet.proto` in the Perfetto repository.
+
+use std::{
+    io::{BufRead, Write},
+    time::Duration,
+};
+
+use crate::{
+    export::{self, Timeline, TimelineEvent},
+    protobuf::Message,
+    ExportError, Field, FieldValue,
+};
+
+/// The Id of the sequence that all packets are written on.
+const SEQUENCE_ID: u64 = 1;
+/// The pid given to the process that the recording was made in.
+const PID: u64 = 1;
+/// The uuid of the track of the process, the track of each thread is a child of this one.
+const PROCESS_TRACK_UUID: u64 = 1;
+
+/// `Trace.packet`
+const TRACE_PACKET: u32 = 1;
+
+/// `TracePacket.timestamp`
+const PACKET_TIMESTAMP: u32 = 8;
+/// `TracePacket.trusted_packet_sequence_id`
+const PACKET_SEQUENCE_ID: u32 = 10;
+/// `TracePacket.track_event`
+const PACKET_TRACK_EVENT: u32 = 11;
+/// `TracePacket.sequence_flags`
+const PACKET_SEQUENCE_FLAGS: u32 = 13;
+/// `TracePacket.track_descriptor`
+const PACKET_TRACK_DESCRIPTOR: u32 = 60;
+/// `TracePacket.SequenceFlags.SEQ_INCREMENTAL_STATE_CLEARED`
+const SEQ_INCREMENTAL_STATE_CLEARED: u64 = 1;
+
+/// `TrackDescriptor.uuid`
+const TRACK_UUID: u32 = 1;
+/// `TrackDescriptor.name`
+const TRACK_NAME: u32 = 2;
+/// `TrackDescriptor.process`
+const TRACK_PROCESS: u32 = 3;
+/// `TrackDescriptor.thread`
+const TRACK_THREAD: u32 = 4;
+/// `TrackDescriptor.parent_uuid`
+const TRACK_PARENT_UUID: u32 = 5;
+
+/// `ProcessDescriptor.pid`
+const PROCESS_PID: u32 = 1;
+/// `ProcessDescriptor.process_name`
+const PROCESS_NAME: u32 = 6;
+
+/// `ThreadDescriptor.pid`
+const THREAD_PID: u32 = 1;
+/// `ThreadDescriptor.tid`
+const THREAD_TID: u32 = 2;
+/// `ThreadDescriptor.thread_name`
+const THREAD_NAME: u32 = 5;
+
+/// `TrackEvent.debug_annotations`
+const EVENT_DEBUG_ANNOTATIONS: u32 = 4;
+/// `TrackEvent.type`
+const EVENT_TYPE: u32 = 9;
+/// `TrackEvent.track_uuid`
+const EVENT_TRACK_UUID: u32 = 11;
+/// `TrackEvent.categories`
+const EVENT_CATEGORIES: u32 = 22;
+/// `TrackEvent.name`
+const EVENT_NAME: u32 = 23;
+/// `TrackEvent.Type.TYPE_SLICE_BEGIN`
+const TYPE_SLICE_BEGIN: u64 = 1;
+/// `TrackEvent.Type.TYPE_SLICE_END`
+const TYPE_SLICE_END: u64 = 2;
+/// `TrackEvent.Type.TYPE_INSTANT`
+const TYPE_INSTANT: u64 = 3;
+
+/// `DebugAnnotation.bool_value`
+const ANNOTATION_BOOL: u32 = 2;
+/// `DebugAnnotation.uint_value`
+const ANNOTATION_UINT: u32 = 3;
+/// `DebugAnnotation.int_value`
+const ANNOTATION_INT: u32 = 4;
+/// `DebugAnnotation.double_value`
+const ANNOTATION_DOUBLE: u32 = 5;
+/// `DebugAnnotation.string_value`
+const ANNOTATION_STRING: u32 = 6;
+/// `DebugAnnotation.name`
+const ANNOTATION_NAME: u32 = 10;
+/// `DebugAnnotation.dict_entries`
+const ANNOTATION_DICT_ENTRIES: u32 = 11;
+/// `DebugAnnotation.array_values`
+const ANNOTATION_ARRAY_VALUES: u32 = 12;
+
+/// Exports the recording read from `reader` as a Perfetto trace, which is written to `writer`.
+///
+/// The trace is written as a protobuf encoded `Trace` message, which can be opened in the
+/// [Perfetto UI] and queried with Perfetto's trace processor. Unlike the JSON based
+/// [`to_chrome_trace`], the Perfetto UI can load traces which are far larger.
+///
+/// The trace contains a track for each recorded thread, named after the recorded thread. The
+/// time that spans are entered is shown as slices on the track of the thread they were entered
+/// on, events are shown as instant events. The fields of spans and events are included as debug
+/// annotations.
+///
+/// Spans which are still entered at the end of the recording are ended at the time of the last
+/// record.
+///
+/// # Errors
+///
+/// Returns an error if reading the recording or writing the trace fails, if a line of the
+/// recording can't be deserialized into a record, or if the recording was written in a newer
+/// version of the format.
+///
+/// # Examples
+///
+/// ```
+/// let recording = concat!(
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":1,"fields":[{"name":"answer","value":{"I64":42}}],"metadata":{"id":4403349456,"name":"span","target":"record_spans","level":"Info","module_path":"record_spans","file":"tracing-rec/examples/record-spans.rs","line":8,"fields":["answer"],"kind":"Span"},"parent":"Root"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543410,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543425,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Exit":1}}"#,
+///     "\n",
+/// );
+///
+/// let mut trace = Vec::new();
+/// tracing_cassette::to_perfetto_trace(recording.as_bytes(), &mut trace).unwrap();
+///
+/// // Every packet is a length delimited `Trace.packet` field.
+/// assert_eq!(trace[0], 0x0a);
+/// ```
+///
+/// [Perfetto UI]: https://ui.perfetto.dev
+/// [`to_chrome_trace`]: fn@crate::to_chrome_trace
+pub fn to_perfetto_trace<R, W>(reader: R, mut writer: W) -> Result<(), ExportError>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut timeline = Timeline::default();
+    let mut wrote_process = false;
+
+    export::for_each_record(reader, |record| {
+        let ts = record.meta.timestamp();
+        if !wrote_process {
+            write_packet(&mut writer, process_packet(ts))?;
+            wrote_process = true;
+        }
+        for event in timeline.push(record) {
+            write_packet(&mut writer, event_packet(ts, event))?;
+        }
+        Ok(())
+    })?;
+    for event in timeline.finish() {
+        write_packet(&mut writer, event_packet(Duration::ZERO, event))?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Writes `packet` as a `Trace.packet` field.
+fn write_packet<W: Write>(writer: &mut W, packet: Message) -> Result<(), ExportError> {
+    let mut trace = Message::default();
+    trace.message(TRACE_PACKET, &packet);
+    writer.write_all(trace.as_bytes())?;
+
+    Ok(())
+}
+
+/// Returns the packet which describes the track of the recorded process.
+///
+/// This is the first packet in the trace, so it also clears the incremental state of the
+/// sequence.
+fn process_packet(ts: Duration) -> Message {
+    let mut process = Message::default();
+    process.varint(PROCESS_PID, PID);
+    process.string(PROCESS_NAME, "recording");
+
+    let mut track = Message::default();
+    track.varint(TRACK_UUID, PROCESS_TRACK_UUID);
+    track.message(TRACK_PROCESS, &process);
+
+    let mut packet = packet(ts);
+    packet.varint(PACKET_SEQUENCE_FLAGS, SEQ_INCREMENTAL_STATE_CLEARED);
+    packet.message(PACKET_TRACK_DESCRIPTOR, &track);
+    packet
+}
+
+/// Returns the packet for a timeline event.
+///
+/// The timestamp of the event is used for the packet, except for threads which don't have
+/// one, where `record_ts` is used instead.
+fn event_packet(record_ts: Duration, event: TimelineEvent) -> Message {
+    match event {
+        TimelineEvent::Thread {
+            lane,
+            thread_id,
+            thread_name,
+        } => {
+            let name = match thread_name {
+                Some(name) => format!("{name} ({thread_id})"),
+                None => thread_id,
+            };
+            let mut thread = Message::default();
+            thread.varint(THREAD_PID, PID);
+            thread.varint(THREAD_TID, lane);
+            thread.string(THREAD_NAME, &name);
+
+            let mut track = Message::default();
+            track.varint(TRACK_UUID, thread_track_uuid(lane));
+            track.string(TRACK_NAME, &name);
+            track.varint(TRACK_PARENT_UUID, PROCESS_TRACK_UUID);
+            track.message(TRACK_THREAD, &thread);
+
+            let mut packet = packet(record_ts);
+            packet.message(PACKET_TRACK_DESCRIPTOR, &track);
+            packet
+        }
+        TimelineEvent::SliceBegin {
+            lane, ts, slice, ..
+        } => {
+            let mut event = track_event(TYPE_SLICE_BEGIN, lane);
+            event.string(EVENT_NAME, &slice.name);
+            event.string(EVENT_CATEGORIES, &slice.target);
+            annotate(&mut event, slice.fields);
+
+            let mut packet = packet(ts);
+            packet.message(PACKET_TRACK_EVENT, &event);
+            packet
+        }
+        TimelineEvent::SliceEnd { lane, ts, .. } => {
+            let mut packet = packet(ts);
+            packet.message(PACKET_TRACK_EVENT, &track_event(TYPE_SLICE_END, lane));
+            packet
+        }
+        TimelineEvent::Instant { lane, ts, slice } => {
+            let mut event = track_event(TYPE_INSTANT, lane);
+            event.string(EVENT_NAME, &slice.name);
+            event.string(EVENT_CATEGORIES, &slice.target);
+            annotate(&mut event, slice.fields);
+
+            let mut packet = packet(ts);
+            packet.message(PACKET_TRACK_EVENT, &event);
+            packet
+        }
+    }
+}
+
+/// The uuid of the track of the thread in `lane`.
+fn thread_track_uuid(lane: u64) -> u64 {
+    PROCESS_TRACK_UUID + lane
+}
+
+/// Returns a packet with the timestamp and sequence Id set.
+fn packet(ts: Duration) -> Message {
+    let mut packet = Message::default();
+    packet.varint(
+        PACKET_TIMESTAMP,
+        u64::try_from(ts.as_nanos()).unwrap_or(u64::MAX),
+    );
+    packet.varint(PACKET_SEQUENCE_ID, SEQUENCE_ID);
+    packet
+}
+
+/// Returns a track event of `event_type` on the track of the thread in `lane`.
+fn track_event(event_type: u64, lane: u64) -> Message {
+    let mut event = Message::default();
+    event.varint(EVENT_TYPE, event_type);
+    event.varint(EVENT_TRACK_UUID, thread_track_uuid(lane));
+    event
+}
+
+/// Adds `fields` to a track event as debug annotations.
+fn annotate(event: &mut Message, fields: Vec<Field>) {
+    for field in fields {
+        let mut annotation = Message::default();
+        annotation.string(ANNOTATION_NAME, &field.name);
+        match field.value {
+            FieldValue::Debug(value) | FieldValue::Str(value) => {
+                annotation.string(ANNOTATION_STRING, &value);
+            }
+            FieldValue::F64(value) => annotation.double(ANNOTATION_DOUBLE, value),
+            FieldValue::I64(value) => annotation.int(ANNOTATION_INT, value),
+            FieldValue::U64(value) => annotation.varint(ANNOTATION_UINT, value),
+            FieldValue::I128(value) => match i64::try_from(value) {
+                Ok(value) => annotation.int(ANNOTATION_INT, value),
+                Err(_) => annotation.string(ANNOTATION_STRING, &value.to_string()),
+            },
+            FieldValue::U128(value) => match u64::try_from(value) {
+                Ok(value) => annotation.varint(ANNOTATION_UINT, value),
+                Err(_) => annotation.string(ANNOTATION_STRING, &value.to_string()),
+            },
+            FieldValue::Bool(value) => annotation.varint(ANNOTATION_BOOL, u64::from(value)),
+            FieldValue::Json(value) => annotate_json(&mut annotation, &value),
+        }
+        event.message(EVENT_DEBUG_ANNOTATIONS, &annotation);
+    }
+}
+
+/// Sets the value of a debug annotation to a JSON value, objects and arrays are nested
+/// annotations.
+fn annotate_json(annotation: &mut Message, value: &serde_json::Value) {
+    use serde_json::Value;
+
+    match value {
+        Value::Null => annotation.string(ANNOTATION_STRING, "null"),
+        Value::Bool(value) => annotation.varint(ANNOTATION_BOOL, u64::from(*value)),
+        Value::Number(number) => {
+            if let Some(value) = number.as_i64() {
+                annotation.int(ANNOTATION_INT, value);
+            } else if let Some(value) = number.as_u64() {
+                annotation.varint(ANNOTATION_UINT, value);
+            } else {
+                annotation.double(ANNOTATION_DOUBLE, number.as_f64().unwrap_or_default());
+            }
+        }
+        Value::String(value) => annotation.string(ANNOTATION_STRING, value),
+        Value::Array(values) => {
+            for value in values {
+                let mut entry = Message::default();
+                annotate_json(&mut entry, value);
+                annotation.message(ANNOTATION_ARRAY_VALUES, &entry);
+            }
+        }
+        Value::Object(entries) => {
+            for (name, value) in entries {
+                let mut entry = Message::default();
+                entry.string(ANNOTATION_NAME, name);
+                annotate_json(&mut entry, value);
+                annotation.message(ANNOTATION_DICT_ENTRIES, &entry);
+            }
+        }
+    }
+}
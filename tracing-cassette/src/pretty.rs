@@ -0,0 +1,232 @@
+//! Rendering of records in a compact human-readable format.
+
+use std::{
+    fmt,
+    io::{BufRead, Write},
+};
+
+use crate::{
+    export, ExportError, Field, FieldValue, Kind, Level, Metadata, Parent, Trace, TraceRecord,
+};
+
+/// A record rendered on a single line for people to read.
+///
+/// The line starts with the timestamp of the record in seconds since the UNIX epoch, the thread
+/// it was recorded on, and the kind of trace. It is followed by what the trace is about:
+///
+/// - callsites show their level, target and name, kind, and Id;
+/// - new spans show their Id, level, target and name, fields, and parent;
+/// - events show their level, target, message, other fields, and parent;
+/// - entering, exiting, and closing a span show the span's Id;
+/// - recorded values show the span's Id and the fields;
+/// - follows from shows the span's Id and the Id of the span it follows from.
+///
+/// Span Ids are written with a leading `#`, such as `#1`. Parents are only shown when they
+/// aren't the current span. Fields are written as `name=value`, with strings quoted and debug
+/// values written as they are. Line breaks in values are escaped, so that a record always takes
+/// up a single line.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_cassette::{PrettyRecord, TraceRecord};
+///
+/// let record: TraceRecord = serde_json::from_str(
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543410,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":1,"fields":[{"name":"answer","value":{"I64":42}}],"metadata":{"id":4403349456,"name":"span","target":"record_spans","level":"Info","module_path":"record_spans","file":"tracing-rec/examples/record-spans.rs","line":8,"fields":["answer"],"kind":"Span"},"parent":"Root"}}}"#,
+/// ).unwrap();
+///
+/// assert_eq!(
+///     PrettyRecord::new(&record).to_string(),
+///     "1715177340.543410 ThreadId(1)[main] NewSpan #1 INFO record_spans::span answer=42 parent=root",
+/// );
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct PrettyRecord<'a> {
+    record: &'a TraceRecord,
+}
+
+impl<'a> PrettyRecord<'a> {
+    /// Wraps a record, so that it is rendered in the human-readable format when displayed.
+    #[must_use]
+    pub fn new(record: &'a TraceRecord) -> Self {
+        Self { record }
+    }
+}
+
+impl fmt::Display for PrettyRecord<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let meta = &self.record.meta;
+        write!(
+            f,
+            "{}.{:06} {}",
+            meta.timestamp_s, meta.timestamp_subsec_us, meta.thread_id,
+        )?;
+        if let Some(thread_name) = &meta.thread_name {
+            write!(f, "[{thread_name}]")?;
+        }
+
+        match &self.record.trace {
+            Trace::RegisterCallsite(metadata) => {
+                write!(f, " RegisterCallsite ")?;
+                write_callsite(f, metadata)?;
+                let kind = match metadata.kind {
+                    Kind::Span => "span",
+                    Kind::Event => "event",
+                };
+                write!(f, " kind={kind} id={}", metadata.id)
+            }
+            Trace::NewSpan(new_span) => {
+                write!(f, " NewSpan #{} ", u64::from(new_span.id))?;
+                write_callsite(f, &new_span.metadata)?;
+                write_fields(f, &new_span.fields)?;
+                write_parent(f, &new_span.parent)
+            }
+            Trace::Event(event) => {
+                let metadata = &event.metadata;
+                write!(f, " Event {} {}", level(&metadata.level), metadata.target)?;
+                if let Some(message) = event.fields.iter().find(|field| field.name == "message") {
+                    f.write_str(" ")?;
+                    write_value(f, &message.value, false)?;
+                }
+                write_fields(
+                    f,
+                    event.fields.iter().filter(|field| field.name != "message"),
+                )?;
+                write_parent(f, &event.parent)
+            }
+            Trace::Enter(id) => write!(f, " Enter #{}", u64::from(*id)),
+            Trace::Exit(id) => write!(f, " Exit #{}", u64::from(*id)),
+            Trace::Close(id) => write!(f, " Close #{}", u64::from(*id)),
+            Trace::Record(record_values) => {
+                write!(f, " Record #{}", u64::from(record_values.id))?;
+                write_fields(f, &record_values.fields)
+            }
+            Trace::FollowsFrom(follows_from) => write!(
+                f,
+                " FollowsFrom #{} follows_from=#{}",
+                u64::from(follows_from.effect_id),
+                u64::from(follows_from.cause_id),
+            ),
+        }
+    }
+}
+
+/// Renders the recording read from `reader` in the human-readable format of [`PrettyRecord`],
+/// one record per line, which is written to `writer`.
+///
+/// # Errors
+///
+/// Returns an error if reading the recording or writing the output fails, if a line of the
+/// recording can't be deserialized into a record, or if the recording was written in a newer
+/// version of the format.
+///
+/// # Examples
+///
+/// ```
+/// let recording = concat!(
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543410,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543420,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}},{"name":"user","value":{"Str":"ferris"}}],"metadata":{"id":4403349608,"name":"event","target":"record_spans","level":"Info","module_path":null,"file":null,"line":null,"fields":["message","user"],"kind":"Event"},"parent":"Current"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543425,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Exit":1}}"#,
+///     "\n",
+/// );
+///
+/// let mut pretty = Vec::new();
+/// tracing_cassette::to_pretty(recording.as_bytes(), &mut pretty).unwrap();
+///
+/// assert_eq!(
+///     String::from_utf8(pretty).unwrap(),
+///     concat!(
+///         "1715177340.543410 ThreadId(1)[main] Enter #1\n",
+///         "1715177340.543420 ThreadId(1)[main] Event INFO record_spans I am an info event! user=\"ferris\"\n",
+///         "1715177340.543425 ThreadId(1)[main] Exit #1\n",
+///     ),
+/// );
+/// ```
+pub fn to_pretty<R, W>(reader: R, mut writer: W) -> Result<(), ExportError>
+where
+    R: BufRead,
+    W: Write,
+{
+    export::for_each_record(reader, |record| {
+        writeln!(writer, "{}", PrettyRecord::new(&record))?;
+        Ok(())
+    })?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+pub(crate) fn level(level: &Level) -> &'static str {
+    match level {
+        Level::Trace => "TRACE",
+        Level::Debug => "DEBUG",
+        Level::Info => "INFO",
+        Level::Warn => "WARN",
+        Level::Error => "ERROR",
+    }
+}
+
+pub(crate) fn write_callsite(f: &mut fmt::Formatter<'_>, metadata: &Metadata) -> fmt::Result {
+    write!(
+        f,
+        "{} {}::{}",
+        level(&metadata.level),
+        metadata.target,
+        metadata.name,
+    )
+}
+
+pub(crate) fn write_fields<'a>(
+    f: &mut fmt::Formatter<'_>,
+    fields: impl IntoIterator<Item = &'a Field>,
+) -> fmt::Result {
+    for field in fields {
+        write!(f, " {}=", field.name)?;
+        write_value(f, &field.value, true)?;
+    }
+    Ok(())
+}
+
+/// A field value rendered for people to read, strings are only quoted when `quote_strings` is
+/// set.
+#[derive(Debug)]
+pub(crate) struct PrettyValue<'a> {
+    pub(crate) value: &'a FieldValue,
+    pub(crate) quote_strings: bool,
+}
+
+impl fmt::Display for PrettyValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_value(f, self.value, self.quote_strings)
+    }
+}
+
+/// Writes a field value, strings are only quoted when `quote_strings` is set.
+fn write_value(f: &mut fmt::Formatter<'_>, value: &FieldValue, quote_strings: bool) -> fmt::Result {
+    match value {
+        FieldValue::Debug(value) => f.write_str(&escape_line_breaks(value)),
+        FieldValue::Str(value) if quote_strings => write!(f, "{value:?}"),
+        FieldValue::Str(value) => f.write_str(&escape_line_breaks(value)),
+        FieldValue::F64(value) => write!(f, "{value}"),
+        FieldValue::I64(value) => write!(f, "{value}"),
+        FieldValue::U64(value) => write!(f, "{value}"),
+        FieldValue::I128(value) => write!(f, "{value}"),
+        FieldValue::U128(value) => write!(f, "{value}"),
+        FieldValue::Bool(value) => write!(f, "{value}"),
+        FieldValue::Json(value) => write!(f, "{value}"),
+    }
+}
+
+fn write_parent(f: &mut fmt::Formatter<'_>, parent: &Parent) -> fmt::Result {
+    match parent {
+        Parent::Current => Ok(()),
+        Parent::Root => f.write_str(" parent=root"),
+        Parent::Explicit(id) => write!(f, " parent=#{}", u64::from(*id)),
+    }
+}
+
+fn escape_line_breaks(value: &str) -> String {
+    value.replace('\r', "\\r").replace('\n', "\\n")
+}
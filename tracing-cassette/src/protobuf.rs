@@ -0,0 +1,208 @@
+//! A minimal protobuf encoder and decoder.
+//!
+//! Only the few messages of the formats that recordings are exported to and imported from are
+//! needed, so they are encoded and decoded by hand instead of with generated code.
+
+use std::fmt;
+
+/// A protobuf encoded message.
+#[derive(Debug, Default)]
+pub(crate) struct Message {
+    buf: Vec<u8>,
+}
+
+impl Message {
+    /// Wire type of varint encoded fields.
+    const VARINT: u8 = 0;
+    /// Wire type of 64-bit fields.
+    const I64: u8 = 1;
+    /// Wire type of length delimited fields.
+    const LEN: u8 = 2;
+
+    /// The encoded message.
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        self.raw_varint(u64::from(field) << 3 | u64::from(wire_type));
+    }
+
+    fn raw_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push((value & 0x7f) as u8 | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+
+    pub(crate) fn varint(&mut self, field: u32, value: u64) {
+        self.key(field, Self::VARINT);
+        self.raw_varint(value);
+    }
+
+    /// Writes an `int64` field, negative values are written in two's complement.
+    pub(crate) fn int(&mut self, field: u32, value: i64) {
+        self.varint(field, value as u64);
+    }
+
+    pub(crate) fn double(&mut self, field: u32, value: f64) {
+        self.key(field, Self::I64);
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn string(&mut self, field: u32, value: &str) {
+        self.bytes(field, value.as_bytes());
+    }
+
+    pub(crate) fn message(&mut self, field: u32, message: &Message) {
+        self.bytes(field, &message.buf);
+    }
+
+    pub(crate) fn bytes(&mut self, field: u32, value: &[u8]) {
+        self.key(field, Self::LEN);
+        self.raw_varint(value.len() as u64);
+        self.buf.extend_from_slice(value);
+    }
+}
+
+/// An error decoding a protobuf message.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct DecodeError {
+    message: &'static str,
+}
+
+impl DecodeError {
+    fn new(message: &'static str) -> Self {
+        Self { message }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message)
+    }
+}
+
+/// The value of a single field in a protobuf message.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum FieldData<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    /// A 32-bit field, which is skipped as none of the decoded fields have that wire type.
+    Fixed32,
+}
+
+impl<'a> FieldData<'a> {
+    /// Returns the value of a varint field, such as a `uint64`, `int64`, `bool` or enum.
+    pub(crate) fn varint(self) -> Result<u64, DecodeError> {
+        match self {
+            Self::Varint(value) => Ok(value),
+            _ => Err(DecodeError::new("expected a varint field")),
+        }
+    }
+
+    /// Returns the value of a `fixed64` field.
+    pub(crate) fn fixed64(self) -> Result<u64, DecodeError> {
+        match self {
+            Self::Fixed64(value) => Ok(value),
+            _ => Err(DecodeError::new("expected a 64-bit field")),
+        }
+    }
+
+    /// Returns the value of a `double` field.
+    pub(crate) fn double(self) -> Result<f64, DecodeError> {
+        self.fixed64().map(f64::from_bits)
+    }
+
+    /// Returns the value of a length delimited field, such as `bytes` or a nested message.
+    pub(crate) fn bytes(self) -> Result<&'a [u8], DecodeError> {
+        match self {
+            Self::Bytes(value) => Ok(value),
+            _ => Err(DecodeError::new("expected a length delimited field")),
+        }
+    }
+
+    /// Returns the value of a `string` field.
+    pub(crate) fn string(self) -> Result<&'a str, DecodeError> {
+        std::str::from_utf8(self.bytes()?).map_err(|_| DecodeError::new("invalid UTF-8 in string"))
+    }
+}
+
+/// Iterates over the fields of a protobuf encoded message, as field numbers and their values.
+#[derive(Clone, Debug)]
+pub(crate) struct Fields<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn raw_varint(&mut self) -> Result<u64, DecodeError> {
+        let mut value = 0_u64;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self
+                .buf
+                .split_first()
+                .ok_or_else(|| DecodeError::new("truncated varint"))?;
+            self.buf = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::new("varint is too long"))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if self.buf.len() < len {
+            return Err(DecodeError::new("truncated field"));
+        }
+        let (value, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(value)
+    }
+
+    fn field(&mut self) -> Result<(u32, FieldData<'a>), DecodeError> {
+        let key = self.raw_varint()?;
+        let field =
+            u32::try_from(key >> 3).map_err(|_| DecodeError::new("invalid field number"))?;
+        let data = match key & 0x7 {
+            0 => FieldData::Varint(self.raw_varint()?),
+            1 => FieldData::Fixed64(u64::from_le_bytes(
+                self.take(8)?.try_into().expect("took 8 bytes"),
+            )),
+            2 => {
+                let len = usize::try_from(self.raw_varint()?)
+                    .map_err(|_| DecodeError::new("field is too long"))?;
+                FieldData::Bytes(self.take(len)?)
+            }
+            5 => {
+                self.take(4)?;
+                FieldData::Fixed32
+            }
+            _ => return Err(DecodeError::new("unsupported wire type")),
+        };
+
+        Ok((field, data))
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u32, FieldData<'a>), DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.is_empty() {
+            return None;
+        }
+        let field = self.field();
+        if field.is_err() {
+            // Stop after the first error, the rest of the message can't be decoded.
+            self.buf = &[];
+        }
+        Some(field)
+    }
+}
@@ -0,0 +1,859 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    error, fmt,
+    io::{BufRead, Write},
+    iter::Peekable,
+    ops,
+    str::{CharIndices, FromStr},
+};
+
+use crate::{
+    ExportError, Field, FieldValue, Kind, Level, Metadata, Parent, RecordMeta, RecordReader,
+    SpanId, Trace, TraceRecord,
+};
+
+/// A query which selects spans and events from a recording.
+///
+/// Queries are usually parsed from an expression, such as:
+///
+/// ```text
+/// level >= WARN && target starts_with "my_app" && field("user_id") == "42"
+/// ```
+///
+/// An expression compares a subject to a value, and expressions can be combined with `&&`, `||`,
+/// `!`, and parentheses. The subjects are:
+///
+/// - `level`: the level of the callsite, compared to a level such as `WARN` or `info`. More
+///   severe levels are greater, so `level >= WARN` matches warnings and errors.
+/// - `target`, `name`: the target and name of the callsite.
+/// - `kind`: `span` or `event`.
+/// - `thread`, `thread_id`: the name and the Id of the thread that the span or event was
+///   recorded on.
+/// - `field("name")`: the value of a field. Spans and events without the field never match.
+///   Numbers are compared to numeric values, and strings to the text of any value.
+///
+/// The operators are `==`, `!=`, `<`, `<=`, `>`, `>=`, and, for text, `starts_with`,
+/// `ends_with`, and `contains`. Strings are written in double quotes, levels and other single
+/// words can be written without them.
+///
+/// Queries can also be built up programmatically with [`Query::compare`], [`and`], [`or`], and
+/// `!`.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_cassette::{Level, Literal, Operator, Query, Subject};
+///
+/// let parsed: Query = r#"level >= WARN && target starts_with "my_app""#.parse().unwrap();
+/// let built = Query::compare(Subject::Level, Operator::Ge, Level::Warn).and(Query::compare(
+///     Subject::Target,
+///     Operator::StartsWith,
+///     "my_app",
+/// ));
+/// assert_eq!(parsed, built);
+///
+/// let err = "level >= LOUD".parse::<Query>().unwrap_err();
+/// assert_eq!(err.position(), 9);
+/// ```
+///
+/// [`and`]: fn@Self::and
+/// [`or`]: fn@Self::or
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Query {
+    /// Matches when both queries match.
+    And(Box<Query>, Box<Query>),
+    /// Matches when either query matches.
+    Or(Box<Query>, Box<Query>),
+    /// Matches when the query doesn't match.
+    Not(Box<Query>),
+    /// Matches when the subject compares to the value with the operator.
+    Compare {
+        /// What is compared.
+        subject: Subject,
+        /// How it is compared.
+        operator: Operator,
+        /// What it is compared to.
+        value: Literal,
+    },
+}
+
+/// The part of a span or event which a [`Query`] compares.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Subject {
+    /// The level of the callsite.
+    Level,
+    /// The target of the callsite.
+    Target,
+    /// The name of the callsite.
+    Name,
+    /// Whether the callsite is a span or an event, as `span` or `event`.
+    Kind,
+    /// The name of the thread, or an empty string if the thread wasn't named.
+    Thread,
+    /// The Id of the thread, such as `ThreadId(1)`.
+    ThreadId,
+    /// The value of the field with this name.
+    Field(String),
+}
+
+/// How a [`Query`] compares a subject to a value.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Operator {
+    /// `==`
+    Eq,
+    /// `!=`
+    Ne,
+    /// `<`
+    Lt,
+    /// `<=`
+    Le,
+    /// `>`
+    Gt,
+    /// `>=`
+    Ge,
+    /// `starts_with`
+    StartsWith,
+    /// `ends_with`
+    EndsWith,
+    /// `contains`
+    Contains,
+}
+
+/// A value that a [`Query`] compares a subject to.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Literal {
+    /// A string, such as `"my_app"`.
+    Str(String),
+    /// A whole number, such as `42`.
+    Int(i128),
+    /// A number with a fraction, such as `0.5`.
+    Float(f64),
+    /// `true` or `false`.
+    Bool(bool),
+    /// A level, such as `WARN`.
+    Level(Level),
+}
+
+impl From<&str> for Literal {
+    fn from(value: &str) -> Self {
+        Self::Str(value.to_owned())
+    }
+}
+
+impl From<String> for Literal {
+    fn from(value: String) -> Self {
+        Self::Str(value)
+    }
+}
+
+impl From<i64> for Literal {
+    fn from(value: i64) -> Self {
+        Self::Int(value.into())
+    }
+}
+
+impl From<f64> for Literal {
+    fn from(value: f64) -> Self {
+        Self::Float(value)
+    }
+}
+
+impl From<bool> for Literal {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<Level> for Literal {
+    fn from(value: Level) -> Self {
+        Self::Level(value)
+    }
+}
+
+/// A query expression couldn't be parsed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct QueryError {
+    position: usize,
+    message: String,
+}
+
+impl QueryError {
+    /// The byte offset in the expression at which the error was found.
+    #[must_use]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid query at position {}: {}",
+            self.position, self.message
+        )
+    }
+}
+
+impl error::Error for QueryError {}
+
+impl Query {
+    /// Parses a query expression, see [`Query`] for the syntax.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the expression isn't a valid query.
+    pub fn parse(expression: &str) -> Result<Self, QueryError> {
+        let tokens = tokenize(expression)?;
+        let mut parser = Parser {
+            tokens,
+            next: 0,
+            end: expression.len(),
+        };
+        let query = parser.parse_or()?;
+        match parser.peek() {
+            None => Ok(query),
+            Some((position, _)) => Err(error(position, "expected `&&`, `||`, or the end")),
+        }
+    }
+
+    /// Creates a query which compares `subject` to `value` with `operator`.
+    #[must_use]
+    pub fn compare(subject: Subject, operator: Operator, value: impl Into<Literal>) -> Self {
+        Self::Compare {
+            subject,
+            operator,
+            value: value.into(),
+        }
+    }
+
+    /// Creates a query which matches when both this query and `other` match.
+    #[must_use]
+    pub fn and(self, other: Query) -> Self {
+        Self::And(Box::new(self), Box::new(other))
+    }
+
+    /// Creates a query which matches when either this query or `other` matches.
+    #[must_use]
+    pub fn or(self, other: Query) -> Self {
+        Self::Or(Box::new(self), Box::new(other))
+    }
+
+    /// Returns whether a span or event matches the query.
+    ///
+    /// The span or event was recorded with `meta`, at the callsite described by `metadata`, and
+    /// has the values in `fields`.
+    #[must_use]
+    pub fn matches(&self, meta: &RecordMeta, metadata: &Metadata, fields: &[Field]) -> bool {
+        match self {
+            Self::And(left, right) => {
+                left.matches(meta, metadata, fields) && right.matches(meta, metadata, fields)
+            }
+            Self::Or(left, right) => {
+                left.matches(meta, metadata, fields) || right.matches(meta, metadata, fields)
+            }
+            Self::Not(query) => !query.matches(meta, metadata, fields),
+            Self::Compare {
+                subject,
+                operator,
+                value,
+            } => {
+                let text = match subject {
+                    Subject::Level => {
+                        return level_literal(value).is_some_and(|level| {
+                            compare_ord(*operator, severity(&metadata.level).cmp(&severity(&level)))
+                        })
+                    }
+                    Subject::Field(name) => {
+                        return fields
+                            .iter()
+                            .find(|field| field.name == *name)
+                            .is_some_and(|field| compare_field(*operator, &field.value, value))
+                    }
+                    Subject::Target => metadata.target.as_str(),
+                    Subject::Name => metadata.name.as_str(),
+                    Subject::Kind => match metadata.kind {
+                        Kind::Span => "span",
+                        Kind::Event => "event",
+                    },
+                    Subject::Thread => meta.thread_name.as_deref().unwrap_or_default(),
+                    Subject::ThreadId => meta.thread_id.as_str(),
+                };
+                match value {
+                    Literal::Str(expected) => compare_str(*operator, text, expected),
+                    _ => false,
+                }
+            }
+        }
+    }
+
+    /// Keeps only the spans and events which match the query, together with the context they
+    /// need to be replayed.
+    ///
+    /// Spans are matched with the values recorded for them at any point, not only those they
+    /// were created with. The context which is kept is the callsites of the matching spans and
+    /// events, and all their ancestor spans, so that they keep their place in the trace tree.
+    /// All the records of the spans which are kept are kept, any other record is removed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tracing_cassette::{Query, RecordReader, Trace};
+    ///
+    /// let recording = concat!(
+    ///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":1,"fields":[{"name":"user_id","value":{"I64":42}}],"metadata":{"id":1,"name":"request","target":"my_app","level":"Info","module_path":null,"file":null,"line":null,"fields":["user_id"],"kind":"Span"},"parent":"Root"}}}"#,
+    ///     "\n",
+    ///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543410,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+    ///     "\n",
+    ///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543420,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"loading"}}],"metadata":{"id":2,"name":"event","target":"my_app","level":"Debug","module_path":null,"file":null,"line":null,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#,
+    ///     "\n",
+    ///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543430,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"not found"}}],"metadata":{"id":3,"name":"event","target":"my_app","level":"Warn","module_path":null,"file":null,"line":null,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#,
+    ///     "\n",
+    ///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543440,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Exit":1}}"#,
+    ///     "\n",
+    ///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543450,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Close":1}}"#,
+    ///     "\n",
+    /// );
+    /// let records = RecordReader::new(recording.as_bytes())
+    ///     .collect::<Result<Vec<_>, _>>()
+    ///     .unwrap();
+    ///
+    /// // The warning is kept, along with the span it was recorded in.
+    /// let query = Query::parse("level >= WARN").unwrap();
+    /// let filtered = query.filter(records.clone());
+    /// assert_eq!(filtered.len(), 5);
+    /// assert!(filtered.iter().all(|record| !matches!(
+    ///     &record.trace,
+    ///     Trace::Event(event) if event.metadata.id == 2,
+    /// )));
+    ///
+    /// // The span matches, but none of the events within it do.
+    /// let query = Query::parse(r#"field("user_id") == 42"#).unwrap();
+    /// assert_eq!(query.filter(records).len(), 4);
+    /// ```
+    #[must_use]
+    pub fn filter(&self, records: Vec<TraceRecord>) -> Vec<TraceRecord> {
+        let mut spans: Vec<SpanInstance> = Vec::new();
+        // The instance of each span Id, which is replaced when the Id is reused.
+        let mut instances: HashMap<SpanId, usize> = HashMap::new();
+        let mut entered: HashMap<&str, Vec<usize>> = HashMap::new();
+        let mut matched_events = Vec::new();
+        let mut involved = Vec::with_capacity(records.len());
+        for (idx, record) in records.iter().enumerate() {
+            let resolve = |parent: &Parent, entered: &HashMap<&str, Vec<usize>>| match parent {
+                Parent::Root => None,
+                Parent::Current => entered
+                    .get(record.meta.thread_id.as_str())
+                    .and_then(|stack| stack.last().copied()),
+                Parent::Explicit(id) => instances.get(id).copied(),
+            };
+            involved.push(match &record.trace {
+                Trace::RegisterCallsite(metadata) => Involves::Callsite(metadata.id),
+                Trace::NewSpan(new_span) => {
+                    let instance = spans.len();
+                    spans.push(SpanInstance {
+                        record: idx,
+                        parent: resolve(&new_span.parent, &entered),
+                        fields: new_span.fields.clone(),
+                    });
+                    instances.insert(new_span.id, instance);
+                    Involves::Span(Some(instance))
+                }
+                Trace::Event(event) => {
+                    let matched = self.matches(&record.meta, &event.metadata, &event.fields);
+                    if matched {
+                        matched_events.push(resolve(&event.parent, &entered));
+                    }
+                    Involves::Event(matched)
+                }
+                Trace::Record(record_values) => {
+                    let instance = instances.get(&record_values.id).copied();
+                    if let Some(instance) = instance {
+                        let fields = &mut spans[instance].fields;
+                        for field in &record_values.fields {
+                            match fields.iter_mut().find(|f| f.name == field.name) {
+                                Some(existing) => existing.value = field.value.clone(),
+                                None => fields.push(field.clone()),
+                            }
+                        }
+                    }
+                    Involves::Span(instance)
+                }
+                Trace::Enter(id) => {
+                    let instance = instances.get(id).copied();
+                    if let Some(instance) = instance {
+                        entered
+                            .entry(record.meta.thread_id.as_str())
+                            .or_default()
+                            .push(instance);
+                    }
+                    Involves::Span(instance)
+                }
+                Trace::Exit(id) => {
+                    let instance = instances.get(id).copied();
+                    let stack = entered.entry(record.meta.thread_id.as_str()).or_default();
+                    if let Some(pos) = stack.iter().rposition(|entered| Some(*entered) == instance)
+                    {
+                        stack.remove(pos);
+                    }
+                    Involves::Span(instance)
+                }
+                Trace::Close(id) => Involves::Span(instances.get(id).copied()),
+                Trace::FollowsFrom(follows_from) => Involves::Spans(
+                    instances.get(&follows_from.cause_id).copied(),
+                    instances.get(&follows_from.effect_id).copied(),
+                ),
+            });
+        }
+
+        // Mark the matching spans and the ancestors of everything which matched as kept.
+        let mut kept = vec![false; spans.len()];
+        let mut keep_with_ancestors = |mut instance: Option<usize>| {
+            // Parents are always created before their children, so this can't loop forever.
+            while let Some(idx) = instance {
+                if kept[idx] {
+                    break;
+                }
+                kept[idx] = true;
+                instance = spans[idx].parent;
+            }
+        };
+        for (idx, span) in spans.iter().enumerate() {
+            if let Trace::NewSpan(new_span) = &records[span.record].trace {
+                if self.matches(&records[span.record].meta, &new_span.metadata, &span.fields) {
+                    keep_with_ancestors(Some(idx));
+                }
+            }
+        }
+        for parent in matched_events {
+            keep_with_ancestors(parent);
+        }
+
+        let mut callsites = HashSet::new();
+        for (record, involves) in records.iter().zip(&involved) {
+            let keep = match involves {
+                Involves::Span(Some(instance)) => kept[*instance],
+                Involves::Event(matched) => *matched,
+                _ => false,
+            };
+            if keep {
+                match &record.trace {
+                    Trace::NewSpan(new_span) => callsites.insert(new_span.metadata.id),
+                    Trace::Event(event) => callsites.insert(event.metadata.id),
+                    _ => false,
+                };
+            }
+        }
+
+        let is_kept = |instance: &Option<usize>| instance.is_some_and(|instance| kept[instance]);
+        records
+            .into_iter()
+            .zip(involved)
+            .filter(|(_, involves)| match involves {
+                Involves::Callsite(id) => callsites.contains(id),
+                Involves::Span(instance) => is_kept(instance),
+                Involves::Spans(cause, effect) => is_kept(cause) && is_kept(effect),
+                Involves::Event(matched) => *matched,
+            })
+            .map(|(record, _)| record)
+            .collect()
+    }
+
+    /// Filters the recording read from `reader` with [`filter`], and writes the result to
+    /// `writer` in the current version of the format.
+    ///
+    /// The whole recording is read into memory, as whether a record is needed may depend on
+    /// records which come after it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the recording or writing the filtered recording fails, if a
+    /// line of the recording can't be deserialized into a record, or if the recording was
+    /// written in a newer version of the format.
+    ///
+    /// [`filter`]: fn@Self::filter
+    pub fn filter_recording<R, W>(&self, reader: R, writer: W) -> Result<(), ExportError>
+    where
+        R: BufRead,
+        W: Write,
+    {
+        let records = RecordReader::new(reader).collect::<Result<Vec<_>, _>>()?;
+        crate::write_recording(writer, &self.filter(records))?;
+
+        Ok(())
+    }
+}
+
+impl ops::Not for Query {
+    type Output = Self;
+
+    fn not(self) -> Self::Output {
+        Self::Not(Box::new(self))
+    }
+}
+
+impl FromStr for Query {
+    type Err = QueryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// A span in a recording, from its creation until its Id is reused.
+#[derive(Debug)]
+struct SpanInstance {
+    /// The index of the `NewSpan` record.
+    record: usize,
+    /// The parent instance, with contextual parents resolved.
+    parent: Option<usize>,
+    /// The values the span was created with, updated with the values recorded since.
+    fields: Vec<Field>,
+}
+
+/// What a record refers to, which decides whether it is kept.
+#[derive(Debug)]
+enum Involves {
+    Callsite(u64),
+    /// The instance of the span, `None` for spans which weren't created in the recording.
+    Span(Option<usize>),
+    /// The cause and the effect of a follows from relationship.
+    Spans(Option<usize>, Option<usize>),
+    /// Whether the event matched.
+    Event(bool),
+}
+
+/// Orders levels from the least to the most severe.
+fn severity(level: &Level) -> u8 {
+    match level {
+        Level::Trace => 0,
+        Level::Debug => 1,
+        Level::Info => 2,
+        Level::Warn => 3,
+        Level::Error => 4,
+    }
+}
+
+/// Returns the level that a literal names, ignoring case.
+fn level_literal(value: &Literal) -> Option<Level> {
+    match value {
+        Literal::Level(level) => Some(level.clone()),
+        Literal::Str(name) => match name.to_ascii_lowercase().as_str() {
+            "trace" => Some(Level::Trace),
+            "debug" => Some(Level::Debug),
+            "info" => Some(Level::Info),
+            "warn" => Some(Level::Warn),
+            "error" => Some(Level::Error),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn compare_ord(operator: Operator, ordering: Ordering) -> bool {
+    match operator {
+        Operator::Eq => ordering.is_eq(),
+        Operator::Ne => ordering.is_ne(),
+        Operator::Lt => ordering.is_lt(),
+        Operator::Le => ordering.is_le(),
+        Operator::Gt => ordering.is_gt(),
+        Operator::Ge => ordering.is_ge(),
+        Operator::StartsWith | Operator::EndsWith | Operator::Contains => false,
+    }
+}
+
+fn compare_str(operator: Operator, actual: &str, expected: &str) -> bool {
+    match operator {
+        Operator::StartsWith => actual.starts_with(expected),
+        Operator::EndsWith => actual.ends_with(expected),
+        Operator::Contains => actual.contains(expected),
+        operator => compare_ord(operator, actual.cmp(expected)),
+    }
+}
+
+fn compare_field(operator: Operator, value: &FieldValue, expected: &Literal) -> bool {
+    match expected {
+        Literal::Str(expected) => compare_str(operator, &field_text(value), expected),
+        Literal::Int(expected) => compare_number(operator, value, &Number::Int(*expected)),
+        Literal::Float(expected) => compare_number(operator, value, &Number::Float(*expected)),
+        Literal::Bool(expected) => match value {
+            FieldValue::Bool(actual) => compare_ord(operator, actual.cmp(expected)),
+            _ => false,
+        },
+        Literal::Level(_) => false,
+    }
+}
+
+/// The text of a field value, strings are taken without quotes.
+fn field_text(value: &FieldValue) -> String {
+    match value {
+        FieldValue::Debug(s) | FieldValue::Str(s) => s.clone(),
+        FieldValue::F64(v) => v.to_string(),
+        FieldValue::I64(v) => v.to_string(),
+        FieldValue::U64(v) => v.to_string(),
+        FieldValue::I128(v) => v.to_string(),
+        FieldValue::U128(v) => v.to_string(),
+        FieldValue::Bool(v) => v.to_string(),
+        FieldValue::Json(v) => v.to_string(),
+    }
+}
+
+/// A number, which is compared exactly when both sides are whole numbers.
+enum Number {
+    Int(i128),
+    Float(f64),
+}
+
+impl Number {
+    fn to_f64(&self) -> f64 {
+        match self {
+            Self::Int(value) => *value as f64,
+            Self::Float(value) => *value,
+        }
+    }
+}
+
+fn compare_number(operator: Operator, value: &FieldValue, expected: &Number) -> bool {
+    let actual = match value {
+        FieldValue::I64(v) => Number::Int((*v).into()),
+        FieldValue::U64(v) => Number::Int((*v).into()),
+        FieldValue::I128(v) => Number::Int(*v),
+        FieldValue::U128(v) => i128::try_from(*v).map_or(Number::Float(*v as f64), Number::Int),
+        FieldValue::F64(v) => Number::Float(*v),
+        FieldValue::Debug(_) | FieldValue::Str(_) | FieldValue::Bool(_) | FieldValue::Json(_) => {
+            return false
+        }
+    };
+    let ordering = match (actual, expected) {
+        (Number::Int(actual), Number::Int(expected)) => Some(actual.cmp(expected)),
+        (actual, expected) => actual.to_f64().partial_cmp(&expected.to_f64()),
+    };
+    ordering.is_some_and(|ordering| compare_ord(operator, ordering))
+}
+
+fn error(position: usize, message: impl Into<String>) -> QueryError {
+    QueryError {
+        position,
+        message: message.into(),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// A word, such as a subject, a word operator, or a value without quotes.
+    Word(String),
+    Str(String),
+    Number(String),
+    Operator(Operator),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+/// Splits an expression into tokens, each with the byte offset it starts at.
+fn tokenize(expression: &str) -> Result<Vec<(usize, Token)>, QueryError> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '&' if next_is(&mut chars, '&') => Token::And,
+            '|' if next_is(&mut chars, '|') => Token::Or,
+            '=' if next_is(&mut chars, '=') => Token::Operator(Operator::Eq),
+            '!' if next_is(&mut chars, '=') => Token::Operator(Operator::Ne),
+            '!' => Token::Not,
+            '<' if next_is(&mut chars, '=') => Token::Operator(Operator::Le),
+            '<' => Token::Operator(Operator::Lt),
+            '>' if next_is(&mut chars, '=') => Token::Operator(Operator::Ge),
+            '>' => Token::Operator(Operator::Gt),
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c @ ('"' | '\\'))) => value.push(c),
+                            Some((pos, _)) => return Err(error(pos, "unknown escape")),
+                            None => return Err(error(start, "unterminated string")),
+                        },
+                        Some((_, c)) => value.push(c),
+                        None => return Err(error(start, "unterminated string")),
+                    }
+                }
+                Token::Str(value)
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let mut value = String::from(c);
+                while let Some((_, c)) =
+                    chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || matches!(c, '.' | '_'))
+                {
+                    value.push(c);
+                }
+                Token::Number(value)
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut value = String::from(c);
+                while let Some((_, c)) =
+                    chars.next_if(|(_, c)| c.is_alphanumeric() || matches!(c, '_' | '.' | ':'))
+                {
+                    value.push(c);
+                }
+                Token::Word(value)
+            }
+            c => return Err(error(start, format!("unexpected `{c}`"))),
+        };
+        tokens.push((start, token));
+    }
+
+    Ok(tokens)
+}
+
+/// Consumes the next character if it is `expected`.
+fn next_is(chars: &mut Peekable<CharIndices<'_>>, expected: char) -> bool {
+    chars.next_if(|(_, c)| *c == expected).is_some()
+}
+
+/// A recursive descent parser over the tokens of an expression.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    /// The length of the expression, which is where errors at the end are reported.
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<(usize, &Token)> {
+        self.tokens
+            .get(self.next)
+            .map(|(position, token)| (*position, token))
+    }
+
+    fn advance(&mut self) -> Result<(usize, Token), QueryError> {
+        let token = self
+            .tokens
+            .get(self.next)
+            .cloned()
+            .ok_or_else(|| error(self.end, "unexpected end of the query"))?;
+        self.next += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, expected: &Token) -> bool {
+        let found = matches!(self.peek(), Some((_, token)) if token == expected);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn parse_or(&mut self) -> Result<Query, QueryError> {
+        let mut query = self.parse_and()?;
+        while self.eat(&Token::Or) {
+            query = query.or(self.parse_and()?);
+        }
+        Ok(query)
+    }
+
+    fn parse_and(&mut self) -> Result<Query, QueryError> {
+        let mut query = self.parse_unary()?;
+        while self.eat(&Token::And) {
+            query = query.and(self.parse_unary()?);
+        }
+        Ok(query)
+    }
+
+    fn parse_unary(&mut self) -> Result<Query, QueryError> {
+        if self.eat(&Token::Not) {
+            return Ok(!self.parse_unary()?);
+        }
+        if self.eat(&Token::Open) {
+            let query = self.parse_or()?;
+            return match self.advance()? {
+                (_, Token::Close) => Ok(query),
+                (position, _) => Err(error(position, "expected `)`")),
+            };
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Query, QueryError> {
+        let (position, token) = self.advance()?;
+        let subject = match token {
+            Token::Word(word) => match word.as_str() {
+                "level" => Subject::Level,
+                "target" => Subject::Target,
+                "name" => Subject::Name,
+                "kind" => Subject::Kind,
+                "thread" => Subject::Thread,
+                "thread_id" => Subject::ThreadId,
+                "field" => Subject::Field(self.parse_field_name()?),
+                _ => return Err(error(position, format!("unknown subject `{word}`"))),
+            },
+            _ => return Err(error(position, "expected a subject, such as `level`")),
+        };
+
+        let (position, token) = self.advance()?;
+        let operator = match token {
+            Token::Operator(operator) => operator,
+            Token::Word(word) if word == "starts_with" => Operator::StartsWith,
+            Token::Word(word) if word == "ends_with" => Operator::EndsWith,
+            Token::Word(word) if word == "contains" => Operator::Contains,
+            _ => return Err(error(position, "expected an operator, such as `==`")),
+        };
+
+        let (position, token) = self.advance()?;
+        let value = match token {
+            Token::Str(value) => Literal::Str(value),
+            Token::Number(number) => match (number.parse(), number.parse()) {
+                (Ok(value), _) => Literal::Int(value),
+                (_, Ok(value)) => Literal::Float(value),
+                _ => return Err(error(position, format!("invalid number `{number}`"))),
+            },
+            Token::Word(word) if word == "true" => Literal::Bool(true),
+            Token::Word(word) if word == "false" => Literal::Bool(false),
+            Token::Word(word) => Literal::Str(word),
+            _ => return Err(error(position, "expected a value")),
+        };
+
+        if subject == Subject::Level {
+            let Some(level) = level_literal(&value) else {
+                return Err(error(position, "expected a level, such as `WARN`"));
+            };
+            if matches!(
+                operator,
+                Operator::StartsWith | Operator::EndsWith | Operator::Contains
+            ) {
+                return Err(error(
+                    position,
+                    "levels can only be compared with `==`, `<` and the like",
+                ));
+            }
+            return Ok(Query::compare(subject, operator, level));
+        }
+
+        Ok(Query::compare(subject, operator, value))
+    }
+
+    /// Parses the `("name")` after `field`.
+    fn parse_field_name(&mut self) -> Result<String, QueryError> {
+        let (position, open) = self.advance()?;
+        let (_, name) = self.advance()?;
+        let (_, close) = self.advance()?;
+        match (open, name, close) {
+            (Token::Open, Token::Str(name) | Token::Word(name), Token::Close) => Ok(name),
+            _ => Err(error(
+                position,
+                "expected a field name, such as `field(\"user_id\")`",
+            )),
+        }
+    }
+}
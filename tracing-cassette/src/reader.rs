@@ -0,0 +1,134 @@
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, Lines},
+};
+
+use serde_json::Value;
+
+use crate::{ContainerReader, ExportError, Header, TraceRecord, UNVERSIONED_FORMAT_VERSION};
+
+/// Reads the records of a recording, in any supported version of the format.
+///
+/// The header is read to find the version of the format that the recording was written in,
+/// records written in an earlier version are migrated to the current version as they are read.
+/// Empty lines are skipped. Recordings stored in a container are read in the same way, see
+/// [`ContainerReader`].
+///
+/// Each item is the next record, or the error which stopped the recording from being read. The
+/// errors are the same as those returned by the exporters, as reading is the first step of every
+/// export.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_cassette::{RecordReader, Trace};
+///
+/// let recording = concat!(
+///     r#"{"header":{"version":2}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543410,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543425,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Exit":1}}"#,
+///     "\n",
+/// );
+///
+/// let mut reader = RecordReader::new(recording.as_bytes());
+/// assert!(matches!(reader.next().unwrap().unwrap().trace, Trace::Enter(_)));
+/// assert!(matches!(reader.next().unwrap().unwrap().trace, Trace::Exit(_)));
+/// assert!(reader.next().is_none());
+/// assert_eq!(reader.version(), 2);
+/// ```
+#[derive(Debug)]
+pub struct RecordReader<R> {
+    lines: Lines<ContainerReader<R>>,
+    line_index: usize,
+    header: Header,
+    /// Set once an error has been returned, after which no more records are read.
+    failed: bool,
+}
+
+impl<R: BufRead> RecordReader<R> {
+    /// Creates a reader of the recording in `reader`.
+    #[must_use]
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: ContainerReader::new(reader).lines(),
+            line_index: 0,
+            header: Header {
+                version: UNVERSIONED_FORMAT_VERSION,
+                metadata: BTreeMap::new(),
+            },
+            failed: false,
+        }
+    }
+}
+
+impl<R> RecordReader<R> {
+    /// The version of the format that the recording was written in.
+    ///
+    /// This is only known once the first line has been read, until then the version of
+    /// recordings without a header is returned.
+    #[must_use]
+    pub fn version(&self) -> u32 {
+        self.header.version
+    }
+
+    /// The header of the recording, which has the version and the metadata of the recording.
+    ///
+    /// As with [`version`], this is only known once the first line has been read. Recordings
+    /// without a header have no metadata.
+    ///
+    /// [`version`]: fn@Self::version
+    #[must_use]
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+}
+
+impl<R: BufRead> Iterator for RecordReader<R> {
+    type Item = Result<TraceRecord, ExportError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let result = loop {
+            let line_index = self.line_index;
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(err) => break Err(ExportError::Io(err)),
+            };
+            self.line_index += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            if line_index == 0 {
+                if let Some(header) = Header::from_line(line.as_bytes()) {
+                    if let Err(err) = header.check() {
+                        break Err(ExportError::Version(err));
+                    }
+                    self.header = header;
+                    continue;
+                }
+            }
+
+            break parse_record(self.header.version, line.as_bytes())
+                .map_err(|inner| ExportError::InvalidRecord { line_index, inner });
+        };
+        self.failed = result.is_err();
+
+        Some(result)
+    }
+}
+
+/// Deserializes a line of a recording written in `version`, migrating it if necessary.
+pub(crate) fn parse_record(version: u32, line: &[u8]) -> Result<TraceRecord, serde_json::Error> {
+    if crate::needs_migration(version) {
+        let mut value: Value = serde_json::from_slice(line)?;
+        crate::migrate(version, &mut value).map_err(serde::de::Error::custom)?;
+        serde_json::from_value(value)
+    } else {
+        serde_json::from_slice(line)
+    }
+}
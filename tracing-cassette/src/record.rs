@@ -0,0 +1,408 @@
+use std::time::Duration;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::FieldRef;
+
+/// A single recorded trace together with the context it was recorded in.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct TraceRecord {
+    /// When and on which thread the trace was recorded.
+    pub meta: RecordMeta,
+    /// The recorded trace.
+    pub trace: Trace,
+    /// The values of the fields of a span when it was closed, on the [`Trace::Close`] record of
+    /// the span.
+    ///
+    /// These are the values the span was created with, with the values recorded for it
+    /// afterwards merged in, so that the end state of the span is known without following all of
+    /// its [`Trace::Record`] records. They are only recorded when asked for, such as with
+    /// `tracing-rec`'s `Rec::with_final_fields`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub final_fields: Option<Vec<Field>>,
+    /// The number of the record in the recording, counting up by one from 1.
+    ///
+    /// Records are only numbered when asked for, such as by the layers of `tracing-rec`'s
+    /// `RecSink`, which number the records of all of their layers in the order they are written.
+    /// Gaps are found from skipped numbers with [`GapDetection::with_record_sequence`].
+    ///
+    /// [`GapDetection::with_record_sequence`]: fn@crate::GapDetection::with_record_sequence
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+}
+
+/// The context in which a trace was recorded.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct RecordMeta {
+    /// The whole seconds of the timestamp, since the UNIX epoch.
+    pub timestamp_s: u64,
+    /// The microseconds of the timestamp within the second.
+    pub timestamp_subsec_us: u32,
+    /// The Id of the recorded thread, such as `ThreadId(1)`.
+    pub thread_id: String,
+    /// The name of the recorded thread, if it had one.
+    pub thread_name: Option<String>,
+}
+
+impl RecordMeta {
+    /// The time at which the trace was recorded, as a duration since the UNIX epoch.
+    ///
+    /// Microseconds which add up to a second or more, which a valid recording never has, carry
+    /// over into the seconds rather than overflowing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use tracing_cassette::RecordMeta;
+    ///
+    /// let meta = RecordMeta {
+    ///     timestamp_s: 1715177340,
+    ///     timestamp_subsec_us: 4_000_500_000,
+    ///     thread_id: "ThreadId(1)".into(),
+    ///     thread_name: None,
+    /// };
+    /// assert_eq!(meta.timestamp(), Duration::new(1715181340, 500_000_000));
+    /// ```
+    #[must_use]
+    pub fn timestamp(&self) -> Duration {
+        Duration::from_secs(self.timestamp_s)
+            .saturating_add(Duration::from_micros(u64::from(self.timestamp_subsec_us)))
+    }
+}
+
+/// A recorded call to the subscriber.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum Trace {
+    /// A callsite was registered.
+    RegisterCallsite(Metadata),
+    /// An event was recorded.
+    Event(Event),
+    /// A new span was created.
+    NewSpan(NewSpan),
+    /// A span was entered.
+    Enter(SpanId),
+    /// A span was exited.
+    Exit(SpanId),
+    /// A span was closed.
+    Close(SpanId),
+    /// Values were recorded for a span.
+    Record(RecordValues),
+    /// A span was marked as following from another one.
+    FollowsFrom(FollowsFrom),
+}
+
+/// The verbosity level of a span or event.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Hash, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+/// Whether a callsite is a span or an event.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Hash, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum Kind {
+    Span,
+    Event,
+}
+
+/// The recorded metadata of a callsite.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Metadata {
+    pub id: u64,
+    pub name: String,
+    pub target: String,
+    pub level: Level,
+    pub module_path: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub fields: Vec<String>,
+    pub kind: Kind,
+}
+
+/// The parent of a span or event.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum Parent {
+    /// The new span will be a root span.
+    Root,
+    /// The new span will be rooted in the current span.
+    Current,
+    /// The new span has an explicitly-specified parent.
+    Explicit(SpanId),
+}
+
+/// A recorded field and its value.
+///
+/// Fields are recorded as an object with the field name and the field value, such as
+/// `{"name":"answer","value":{"I64":42}}`. Fields may also be given as a pair of the name and the
+/// value, such as `["answer",{"I64":42}]`. In either encoding, the value may be given as a plain
+/// JSON value instead: strings are taken to be recorded `Debug` values, while numbers and
+/// booleans are taken as they are.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_cassette::{Field, FieldValue};
+///
+/// let object: Field = serde_json::from_str(r#"{"name":"answer","value":{"I64":42}}"#).unwrap();
+/// let pair: Field = serde_json::from_str(r#"["answer",{"I64":42}]"#).unwrap();
+/// assert_eq!(object, pair);
+///
+/// let plain: Field = serde_json::from_str(r#"["answer",42]"#).unwrap();
+/// assert_eq!(plain.value, FieldValue::U64(42));
+///
+/// // Fields are always written in the object encoding.
+/// assert_eq!(
+///     serde_json::to_string(&pair).unwrap(),
+///     r#"{"name":"answer","value":{"I64":42}}"#,
+/// );
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Field {
+    pub name: String,
+    pub value: FieldValue,
+}
+
+impl<'de> Deserialize<'de> for Field {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        FieldRef::deserialize(deserializer).map(Self::from)
+    }
+}
+
+/// A recorded field value.
+///
+/// Values which have a structure of their own, such as those recorded with [`valuable`], are
+/// recorded as `Json`, so that converters and replaying can keep the structure rather than
+/// flattening it into a string.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_cassette::{Field, FieldValue};
+///
+/// let field: Field =
+///     serde_json::from_str(r#"{"name":"user","value":{"Json":{"id":42,"roles":["admin"]}}}"#)
+///         .unwrap();
+/// assert_eq!(
+///     field.value,
+///     FieldValue::Json(serde_json::json!({"id": 42, "roles": ["admin"]})),
+/// );
+/// ```
+///
+/// [`valuable`]: https://docs.rs/valuable
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum FieldValue {
+    Debug(String),
+    F64(f64),
+    I64(i64),
+    U64(u64),
+    I128(i128),
+    U128(u128),
+    Bool(bool),
+    Str(String),
+    Json(serde_json::Value),
+}
+
+impl From<&str> for FieldValue {
+    fn from(value: &str) -> Self {
+        Self::Str(value.to_owned())
+    }
+}
+
+impl From<String> for FieldValue {
+    fn from(value: String) -> Self {
+        Self::Str(value)
+    }
+}
+
+impl From<i32> for FieldValue {
+    fn from(value: i32) -> Self {
+        Self::I64(value.into())
+    }
+}
+
+impl From<u32> for FieldValue {
+    fn from(value: u32) -> Self {
+        Self::U64(value.into())
+    }
+}
+
+impl From<i64> for FieldValue {
+    fn from(value: i64) -> Self {
+        Self::I64(value)
+    }
+}
+
+impl From<u64> for FieldValue {
+    fn from(value: u64) -> Self {
+        Self::U64(value)
+    }
+}
+
+impl From<f64> for FieldValue {
+    fn from(value: f64) -> Self {
+        Self::F64(value)
+    }
+}
+
+impl From<bool> for FieldValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<serde_json::Value> for FieldValue {
+    fn from(value: serde_json::Value) -> Self {
+        Self::Json(value)
+    }
+}
+
+/// A recorded event.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Event {
+    pub fields: Vec<Field>,
+    pub metadata: Metadata,
+    pub parent: Parent,
+}
+
+/// A recorded new span.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct NewSpan {
+    pub id: SpanId,
+    pub fields: Vec<Field>,
+    pub metadata: Metadata,
+    pub parent: Parent,
+    /// The distributed trace that the span was part of, if it was known when it was recorded.
+    ///
+    /// This is left out of the record when there is no trace context, so recordings without one
+    /// are written as they were before it was added.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_context: Option<TraceContext>,
+}
+
+/// The distributed trace context of a span, as identified in the W3C Trace Context.
+///
+/// This allows a recorded span to be correlated with the spans of the same distributed trace
+/// which were collected by other systems, such as with OpenTelemetry. The Ids are written as
+/// lowercase hex strings, as they are in a `traceparent` header.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_cassette::TraceContext;
+///
+/// let context = TraceContext {
+///     trace_id: 0x4bf9_2f35_77b3_4da6_a3ce_929d_0e0e_4736,
+///     span_id: 0x00f0_67aa_0ba9_02b7,
+/// };
+/// let json = serde_json::to_string(&context).unwrap();
+/// assert_eq!(
+///     json,
+///     r#"{"trace_id":"4bf92f3577b34da6a3ce929d0e0e4736","span_id":"00f067aa0ba902b7"}"#,
+/// );
+/// assert_eq!(serde_json::from_str::<TraceContext>(&json).unwrap(), context);
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct TraceContext {
+    /// The Id of the distributed trace, which is never zero.
+    pub trace_id: u128,
+    /// The Id of the span within the distributed trace, which is never zero.
+    pub span_id: u64,
+}
+
+/// The serialized form of a [`TraceContext`], with the Ids as hex strings.
+#[derive(Deserialize, Serialize)]
+struct HexTraceContext<'a> {
+    #[serde(borrow)]
+    trace_id: std::borrow::Cow<'a, str>,
+    #[serde(borrow)]
+    span_id: std::borrow::Cow<'a, str>,
+}
+
+impl Serialize for TraceContext {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        HexTraceContext {
+            trace_id: format!("{:032x}", self.trace_id).into(),
+            span_id: format!("{:016x}", self.span_id).into(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TraceContext {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let hex = HexTraceContext::deserialize(deserializer)?;
+        let trace_id = parse_hex_id(&hex.trace_id, 32)
+            .ok_or_else(|| de::Error::custom("trace_id must be 32 hex digits and not zero"))?;
+        let span_id = parse_hex_id(&hex.span_id, 16)
+            .and_then(|id| u64::try_from(id).ok())
+            .ok_or_else(|| de::Error::custom("span_id must be 16 hex digits and not zero"))?;
+
+        Ok(Self { trace_id, span_id })
+    }
+}
+
+/// Parses an Id of exactly `digits` hex digits, which is invalid if all of them are zero.
+fn parse_hex_id(hex: &str, digits: usize) -> Option<u128> {
+    if hex.len() != digits || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    u128::from_str_radix(hex, 16).ok().filter(|id| *id != 0)
+}
+
+/// The span Id assigned by the subscriber during the recording.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Hash, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct SpanId(u64);
+
+impl From<u64> for SpanId {
+    fn from(value: u64) -> Self {
+        Self(value)
+    }
+}
+
+impl From<SpanId> for u64 {
+    fn from(value: SpanId) -> Self {
+        value.0
+    }
+}
+
+/// Values recorded for an existing span.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct RecordValues {
+    pub id: SpanId,
+    pub fields: Vec<Field>,
+}
+
+/// A recorded follows from relationship between two spans.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct FollowsFrom {
+    pub cause_id: SpanId,
+    pub effect_id: SpanId,
+}
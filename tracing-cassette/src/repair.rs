@@ -0,0 +1,375 @@
+//! Repairing recordings which aren't consistent, such as those cut short by a crash.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    time::Duration,
+};
+
+use crate::{Parent, RecordMeta, SpanId, Trace, TraceRecord};
+
+/// A defect in a recording which was fixed by [`repair`].
+///
+/// Records are identified by their index in the recording which was repaired, starting from 0,
+/// not counting the header.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Repair {
+    /// A record which refers to a span that isn't open was removed.
+    UnknownSpan {
+        /// The index of the record.
+        index: usize,
+        /// The span Id.
+        id: SpanId,
+    },
+    /// A span was exited on a thread that it wasn't entered on, the exit was removed.
+    ExitWithoutEnter {
+        /// The index of the record.
+        index: usize,
+        /// The span Id.
+        id: SpanId,
+    },
+    /// A span follows from a span which isn't open, or isn't open itself, the record was
+    /// removed.
+    DanglingFollowsFrom {
+        /// The index of the record.
+        index: usize,
+        /// The Id of the span which caused the other.
+        cause_id: SpanId,
+        /// The Id of the span which follows from the other.
+        effect_id: SpanId,
+    },
+    /// The explicit parent of a span or event was never created, it was given no parent
+    /// instead.
+    UnknownParent {
+        /// The index of the record.
+        index: usize,
+        /// The Id of the parent.
+        id: SpanId,
+    },
+    /// A new span reused the Id of a span which is still open, the open span was exited and
+    /// closed before it.
+    DuplicateSpan {
+        /// The index of the new span.
+        index: usize,
+        /// The span Id.
+        id: SpanId,
+    },
+    /// A span was still entered on a thread at the end of the recording, an exit was added.
+    MissingExit {
+        /// The recorded thread Id.
+        thread_id: String,
+        /// The span Id.
+        id: SpanId,
+    },
+    /// A span was still open at the end of the recording, a close was added.
+    MissingClose {
+        /// The span Id.
+        id: SpanId,
+    },
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownSpan { index, id } => write!(
+                f,
+                "record {index}: removed, span {} isn't open",
+                u64::from(*id)
+            ),
+            Self::ExitWithoutEnter { index, id } => write!(
+                f,
+                "record {index}: removed exit, span {} wasn't entered on the thread",
+                u64::from(*id)
+            ),
+            Self::DanglingFollowsFrom {
+                index,
+                cause_id,
+                effect_id,
+            } => write!(
+                f,
+                "record {index}: removed follows from, span {} or span {} isn't open",
+                u64::from(*effect_id),
+                u64::from(*cause_id)
+            ),
+            Self::UnknownParent { index, id } => write!(
+                f,
+                "record {index}: removed unknown parent, span {} was never created",
+                u64::from(*id)
+            ),
+            Self::DuplicateSpan { index, id } => write!(
+                f,
+                "record {index}: closed span {} before it was created again",
+                u64::from(*id)
+            ),
+            Self::MissingExit { thread_id, id } => write!(
+                f,
+                "added exit of span {} on {thread_id} at the end",
+                u64::from(*id)
+            ),
+            Self::MissingClose { id } => {
+                write!(f, "added close of span {} at the end", u64::from(*id))
+            }
+        }
+    }
+}
+
+/// Fixes the common defects of recordings, so that they are consistent and can be replayed.
+///
+/// Recordings which were cut short, such as when the program crashed, have spans which were
+/// never exited or closed. Recordings which were cut up, or written by other tools, can have
+/// records which refer to spans that don't exist. `repair` returns the repaired records,
+/// together with what was repaired, in the order it was found:
+///
+/// - Records which refer to a span which isn't open are removed: entering, exiting, recording
+///   values for, and closing it.
+/// - Exits from a span on a thread that the span isn't entered on are removed.
+/// - Follows from records where either span isn't open are removed.
+/// - The explicit parent of a span or event which was never created is removed, so that it
+///   has no parent. Parents which were closed earlier are kept.
+/// - A new span which reuses the Id of an open span closes the open span first.
+/// - Spans which are still entered at the end of the recording are exited, innermost first, and
+///   spans which are still open are closed, most recently created first. These records are added
+///   at the time of the latest record in the recording.
+///
+/// A recording without any of these defects is returned as it is, with no repairs.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_cassette::{Repair, RecordingBuilder, SpanBuilder, SpanId, Trace};
+///
+/// let mut recording = RecordingBuilder::new();
+/// recording.thread("main", |thread| {
+///     thread.span(SpanBuilder::new("request"), |thread| {
+///         thread.span(SpanBuilder::new("query"), |_| {});
+///     });
+/// });
+/// let mut records = recording.build();
+/// // The recording was cut short before the request finished.
+/// let len = records.len();
+/// records.truncate(len - 2);
+///
+/// let (repaired, repairs) = tracing_cassette::repair(records);
+/// assert_eq!(
+///     repairs,
+///     vec![
+///         Repair::MissingExit { thread_id: "ThreadId(1)".into(), id: SpanId::from(1) },
+///         Repair::MissingClose { id: SpanId::from(1) },
+///     ],
+/// );
+/// assert_eq!(repaired.len(), len);
+/// assert!(matches!(repaired[len - 1].trace, Trace::Close(id) if id == SpanId::from(1)));
+/// ```
+#[must_use]
+pub fn repair(records: Vec<TraceRecord>) -> (Vec<TraceRecord>, Vec<Repair>) {
+    let mut state = RepairState::default();
+    let mut repaired = Vec::with_capacity(records.len());
+    for (index, record) in records.into_iter().enumerate() {
+        state.push(index, record, &mut repaired);
+    }
+    state.finish(&mut repaired);
+
+    (repaired, state.repairs)
+}
+
+/// An open span.
+#[derive(Debug)]
+struct OpenSpan {
+    /// The order in which the span was created.
+    seq: u64,
+    /// The recorded thread that the span was created on, and its name.
+    thread: (String, Option<String>),
+}
+
+/// The spans of the recording so far, with the repairs made.
+#[derive(Debug, Default)]
+struct RepairState {
+    open: HashMap<SpanId, OpenSpan>,
+    closed: HashSet<SpanId>,
+    /// The spans entered on each recorded thread, innermost last.
+    entered: HashMap<String, Vec<SpanId>>,
+    /// The recorded threads, in the order they first appear, and their names.
+    threads: Vec<(String, Option<String>)>,
+    next_seq: u64,
+    latest: Duration,
+    repairs: Vec<Repair>,
+}
+
+impl RepairState {
+    fn push(&mut self, index: usize, mut record: TraceRecord, repaired: &mut Vec<TraceRecord>) {
+        self.latest = self.latest.max(record.meta.timestamp());
+        let thread_id = &record.meta.thread_id;
+        if !self.entered.contains_key(thread_id) {
+            self.entered.insert(thread_id.clone(), Vec::new());
+            self.threads
+                .push((thread_id.clone(), record.meta.thread_name.clone()));
+        }
+
+        let keep = match &mut record.trace {
+            Trace::RegisterCallsite(_) => true,
+            Trace::NewSpan(new_span) => {
+                self.repair_parent(index, &mut new_span.parent);
+                if self.open.contains_key(&new_span.id) {
+                    self.repairs.push(Repair::DuplicateSpan {
+                        index,
+                        id: new_span.id,
+                    });
+                    let ts = record.meta.timestamp();
+                    self.close(new_span.id, ts, repaired);
+                }
+                self.closed.remove(&new_span.id);
+                self.open.insert(
+                    new_span.id,
+                    OpenSpan {
+                        seq: self.next_seq,
+                        thread: (
+                            record.meta.thread_id.clone(),
+                            record.meta.thread_name.clone(),
+                        ),
+                    },
+                );
+                self.next_seq += 1;
+                true
+            }
+            Trace::Event(event) => {
+                self.repair_parent(index, &mut event.parent);
+                true
+            }
+            Trace::Enter(id) => {
+                let open = self.is_open(index, *id);
+                if open {
+                    self.entered.entry(thread_id.clone()).or_default().push(*id);
+                }
+                open
+            }
+            Trace::Exit(id) => {
+                let open = self.is_open(index, *id);
+                let stack = self.entered.entry(thread_id.clone()).or_default();
+                match stack.iter().rposition(|entered| entered == id) {
+                    Some(pos) if open => {
+                        stack.remove(pos);
+                        true
+                    }
+                    _ => {
+                        if open {
+                            self.repairs
+                                .push(Repair::ExitWithoutEnter { index, id: *id });
+                        }
+                        false
+                    }
+                }
+            }
+            Trace::Close(id) => {
+                let open = self.is_open(index, *id);
+                if open {
+                    self.open.remove(id);
+                    self.closed.insert(*id);
+                    for stack in self.entered.values_mut() {
+                        stack.retain(|entered| entered != id);
+                    }
+                }
+                open
+            }
+            Trace::Record(record_values) => self.is_open(index, record_values.id),
+            Trace::FollowsFrom(follows_from) => {
+                let dangling = !self.open.contains_key(&follows_from.cause_id)
+                    || !self.open.contains_key(&follows_from.effect_id);
+                if dangling {
+                    self.repairs.push(Repair::DanglingFollowsFrom {
+                        index,
+                        cause_id: follows_from.cause_id,
+                        effect_id: follows_from.effect_id,
+                    });
+                }
+                !dangling
+            }
+        };
+
+        if keep {
+            repaired.push(record);
+        }
+    }
+
+    /// Exits the spans which are still entered and closes the spans which are still open.
+    fn finish(&mut self, repaired: &mut Vec<TraceRecord>) {
+        let threads = std::mem::take(&mut self.threads);
+        for (thread_id, thread_name) in &threads {
+            let Some(stack) = self.entered.get_mut(thread_id) else {
+                continue;
+            };
+            for id in std::mem::take(stack).into_iter().rev() {
+                self.repairs.push(Repair::MissingExit {
+                    thread_id: thread_id.clone(),
+                    id,
+                });
+                repaired.push(record(
+                    self.latest,
+                    (thread_id.clone(), thread_name.clone()),
+                    Trace::Exit(id),
+                ));
+            }
+        }
+
+        let mut open: Vec<(SpanId, OpenSpan)> = self.open.drain().collect();
+        open.sort_by_key(|(_, span)| std::cmp::Reverse(span.seq));
+        for (id, span) in open {
+            self.repairs.push(Repair::MissingClose { id });
+            repaired.push(record(self.latest, span.thread, Trace::Close(id)));
+        }
+    }
+
+    /// Exits an open span wherever it is entered, and closes it, at `ts`.
+    fn close(&mut self, id: SpanId, ts: Duration, repaired: &mut Vec<TraceRecord>) {
+        for (thread_id, thread_name) in &self.threads {
+            let Some(stack) = self.entered.get_mut(thread_id) else {
+                continue;
+            };
+            while let Some(pos) = stack.iter().rposition(|entered| *entered == id) {
+                stack.remove(pos);
+                repaired.push(record(
+                    ts,
+                    (thread_id.clone(), thread_name.clone()),
+                    Trace::Exit(id),
+                ));
+            }
+        }
+        if let Some(span) = self.open.remove(&id) {
+            repaired.push(record(ts, span.thread, Trace::Close(id)));
+        }
+    }
+
+    /// Returns whether a span is open, recording a repair for the record at `index` if it isn't.
+    fn is_open(&mut self, index: usize, id: SpanId) -> bool {
+        let open = self.open.contains_key(&id);
+        if !open {
+            self.repairs.push(Repair::UnknownSpan { index, id });
+        }
+        open
+    }
+
+    fn repair_parent(&mut self, index: usize, parent: &mut Parent) {
+        if let Parent::Explicit(id) = parent {
+            if !self.open.contains_key(id) && !self.closed.contains(id) {
+                self.repairs.push(Repair::UnknownParent { index, id: *id });
+                *parent = Parent::Root;
+            }
+        }
+    }
+}
+
+/// Returns a record added to the recording, at `ts` on a recorded thread.
+fn record(ts: Duration, thread: (String, Option<String>), trace: Trace) -> TraceRecord {
+    let (thread_id, thread_name) = thread;
+    TraceRecord {
+        meta: RecordMeta {
+            timestamp_s: ts.as_secs(),
+            timestamp_subsec_us: ts.subsec_micros(),
+            thread_id,
+            thread_name,
+        },
+        trace,
+        final_fields: None,
+        sequence: None,
+    }
+}
@@ -0,0 +1,356 @@
+//! The types and cardinalities of the fields of each callsite in a recording.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    io::BufRead,
+};
+
+use crate::{
+    export::{self, ExportError},
+    Field, FieldValue, Metadata, SpanId, Trace, TraceRecord,
+};
+
+/// The number of distinct values of a field which are counted, see
+/// [`FieldSchema::distinct_values`].
+pub const MAX_DISTINCT_VALUES: usize = 1024;
+
+/// The schema of the fields of each callsite in a recording, inferred from the values recorded.
+///
+/// For each field of each callsite which was used, the schema has the types of the values that
+/// were recorded, how many values were recorded, and how many of them were distinct. Fields
+/// which have been recorded with values of more than one type are flagged as inconsistent, which
+/// usually means that the instrumentation has a bug, such as a field which is recorded as a
+/// number in one place and as a string in another. The values of spans include the values
+/// recorded after they were created.
+///
+/// The schema also documents the fields of a program's instrumentation, and the cardinality of
+/// each field shows which fields are suitable as metric labels or index keys.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_cassette::{EventBuilder, FieldType, RecordingBuilder, Schema};
+///
+/// let mut recording = RecordingBuilder::new().with_target("app");
+/// recording.thread("main", |thread| {
+///     thread.event(EventBuilder::new("request").with_field("status", 200_u32));
+///     thread.event(EventBuilder::new("request").with_field("status", 404_u32));
+///     thread.event(EventBuilder::new("request").with_field("status", "timeout"));
+/// });
+/// let schema = Schema::new(&recording.build());
+///
+/// let callsite = &schema.callsites[0];
+/// assert_eq!(callsite.count, 3);
+/// let [message, status] = &callsite.fields[..] else { unreachable!() };
+/// assert_eq!(message.name, "message");
+/// assert_eq!(message.distinct_values, 1);
+/// assert!(message.is_consistent());
+/// assert_eq!(status.name, "status");
+/// assert_eq!(status.types, [(FieldType::U64, 2), (FieldType::Str, 1)]);
+/// assert_eq!(status.distinct_values, 3);
+/// assert!(!status.is_consistent());
+/// assert_eq!(schema.inconsistencies().count(), 1);
+/// ```
+#[non_exhaustive]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Schema {
+    /// The callsites which were used, in the order they were first used.
+    pub callsites: Vec<CallsiteSchema>,
+}
+
+/// The schema of the fields of a callsite, see [`Schema`].
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallsiteSchema {
+    /// The metadata of the callsite.
+    pub metadata: Metadata,
+    /// The number of spans created, or events recorded, at the callsite.
+    pub count: u64,
+    /// The fields of the callsite, in the order of the callsite's fields, followed by fields
+    /// which the callsite doesn't declare in the order they were first recorded.
+    ///
+    /// Declared fields which never had a value recorded aren't included.
+    pub fields: Vec<FieldSchema>,
+}
+
+/// The schema of a field of a callsite, see [`CallsiteSchema::fields`].
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldSchema {
+    /// The name of the field.
+    pub name: String,
+    /// Whether the field is one of the fields of the callsite's metadata.
+    ///
+    /// Recordings made by `tracing-rec` only have declared fields, undeclared fields come from
+    /// recordings which were edited or imported.
+    pub declared: bool,
+    /// The types of the values recorded, and how many values had each type, most first.
+    pub types: Vec<(FieldType, u64)>,
+    /// The number of values recorded.
+    pub values: u64,
+    /// The number of distinct values recorded, up to [`MAX_DISTINCT_VALUES`].
+    pub distinct_values: u64,
+    /// Whether there were more distinct values than [`MAX_DISTINCT_VALUES`], in which case
+    /// [`distinct_values`] is the maximum.
+    ///
+    /// [`distinct_values`]: structfield@Self::distinct_values
+    pub more_distinct_values: bool,
+}
+
+impl FieldSchema {
+    /// Returns whether all the values of the field had the same type.
+    #[must_use]
+    pub fn is_consistent(&self) -> bool {
+        self.types.len() <= 1
+    }
+}
+
+/// The type of a recorded field value, the variant of its [`FieldValue`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum FieldType {
+    /// A value recorded with its `Debug` or `Display` implementation.
+    Debug,
+    /// A string.
+    Str,
+    /// A 64-bit float.
+    F64,
+    /// A signed 64-bit integer.
+    I64,
+    /// An unsigned 64-bit integer.
+    U64,
+    /// A signed 128-bit integer.
+    I128,
+    /// An unsigned 128-bit integer.
+    U128,
+    /// A boolean.
+    Bool,
+    /// A structured value.
+    Json,
+}
+
+impl FieldType {
+    /// Returns the type of `value`.
+    #[must_use]
+    pub fn of(value: &FieldValue) -> Self {
+        match value {
+            FieldValue::Debug(_) => Self::Debug,
+            FieldValue::Str(_) => Self::Str,
+            FieldValue::F64(_) => Self::F64,
+            FieldValue::I64(_) => Self::I64,
+            FieldValue::U64(_) => Self::U64,
+            FieldValue::I128(_) => Self::I128,
+            FieldValue::U128(_) => Self::U128,
+            FieldValue::Bool(_) => Self::Bool,
+            FieldValue::Json(_) => Self::Json,
+        }
+    }
+}
+
+impl fmt::Display for FieldType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Debug => "debug",
+            Self::Str => "str",
+            Self::F64 => "f64",
+            Self::I64 => "i64",
+            Self::U64 => "u64",
+            Self::I128 => "i128",
+            Self::U128 => "u128",
+            Self::Bool => "bool",
+            Self::Json => "json",
+        })
+    }
+}
+
+impl Schema {
+    /// Infers the schema of the fields of the callsites used in `records`.
+    #[must_use]
+    pub fn new(records: &[TraceRecord]) -> Self {
+        let mut builder = SchemaBuilder::default();
+        for record in records {
+            builder.push(record.clone());
+        }
+        builder.finish()
+    }
+
+    /// Reads a recording from `reader` and infers the schema of the fields of its callsites.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the recording fails, if a line of the recording can't be
+    /// deserialized into a record, or if the recording was written in a newer version of the
+    /// format.
+    pub fn read<R: BufRead>(reader: R) -> Result<Self, ExportError> {
+        let mut builder = SchemaBuilder::default();
+        export::for_each_record(reader, |record| {
+            builder.push(record);
+            Ok(())
+        })?;
+
+        Ok(builder.finish())
+    }
+
+    /// Returns the fields which were recorded with values of more than one type, together with
+    /// their callsites.
+    pub fn inconsistencies(&self) -> impl Iterator<Item = (&CallsiteSchema, &FieldSchema)> {
+        self.callsites.iter().flat_map(|callsite| {
+            callsite
+                .fields
+                .iter()
+                .filter(|field| !field.is_consistent())
+                .map(move |field| (callsite, field))
+        })
+    }
+}
+
+/// The values of a field recorded so far.
+#[derive(Debug, Default)]
+struct FieldValues {
+    types: BTreeMap<FieldType, u64>,
+    values: u64,
+    /// The distinct values, serialized, up to the maximum.
+    distinct: HashSet<String>,
+    more_distinct: bool,
+    /// The order in which the field was first recorded.
+    order: usize,
+}
+
+/// The fields of a callsite recorded so far.
+#[derive(Debug)]
+struct CallsiteFields {
+    metadata: Metadata,
+    count: u64,
+    fields: HashMap<String, FieldValues>,
+}
+
+impl CallsiteFields {
+    fn push(&mut self, fields: Vec<Field>) {
+        for field in fields {
+            let order = self.fields.len();
+            let values = self
+                .fields
+                .entry(field.name)
+                .or_insert_with(|| FieldValues {
+                    order,
+                    ..FieldValues::default()
+                });
+            *values.types.entry(FieldType::of(&field.value)).or_default() += 1;
+            values.values += 1;
+            if !values.more_distinct {
+                let value = serde_json::to_string(&field.value).unwrap_or_default();
+                if values.distinct.len() < MAX_DISTINCT_VALUES {
+                    values.distinct.insert(value);
+                } else if !values.distinct.contains(&value) {
+                    values.more_distinct = true;
+                }
+            }
+        }
+    }
+
+    fn finish(self) -> CallsiteSchema {
+        let declared = &self.metadata.fields;
+        let mut fields: Vec<(String, FieldValues)> = self.fields.into_iter().collect();
+        // Declared fields come first in the order of the metadata, then undeclared fields in the
+        // order they were first recorded.
+        fields.sort_by_key(|(name, values)| {
+            match declared.iter().position(|declared| declared == name) {
+                Some(position) => (0, position),
+                None => (1, values.order),
+            }
+        });
+        let fields = fields
+            .into_iter()
+            .map(|(name, values)| {
+                let mut types: Vec<(FieldType, u64)> = values.types.into_iter().collect();
+                // The types are in order, which breaks ties.
+                types.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+                FieldSchema {
+                    declared: declared.contains(&name),
+                    name,
+                    types,
+                    values: values.values,
+                    distinct_values: values.distinct.len() as u64,
+                    more_distinct_values: values.more_distinct,
+                }
+            })
+            .collect();
+
+        CallsiteSchema {
+            metadata: self.metadata,
+            count: self.count,
+            fields,
+        }
+    }
+}
+
+/// Builds a [`Schema`] one record at a time.
+#[derive(Debug, Default)]
+struct SchemaBuilder {
+    /// The fields of each callsite, by callsite Id.
+    callsites: HashMap<u64, CallsiteFields>,
+    /// The callsite Ids in the order that the callsites were first used.
+    order: Vec<u64>,
+    /// The callsite of each open span.
+    spans: HashMap<SpanId, u64>,
+}
+
+impl SchemaBuilder {
+    fn push(&mut self, record: TraceRecord) {
+        match record.trace {
+            Trace::NewSpan(new_span) => {
+                // A span Id may be reused once the span is closed, so this replaces that span.
+                self.spans.insert(new_span.id, new_span.metadata.id);
+                let callsite = self.callsite(new_span.metadata);
+                callsite.count += 1;
+                callsite.push(new_span.fields);
+            }
+            Trace::Event(event) => {
+                let callsite = self.callsite(event.metadata);
+                callsite.count += 1;
+                callsite.push(event.fields);
+            }
+            Trace::Record(record_values) => {
+                if let Some(callsite) = self
+                    .spans
+                    .get(&record_values.id)
+                    .and_then(|id| self.callsites.get_mut(id))
+                {
+                    callsite.push(record_values.fields);
+                }
+            }
+            Trace::Close(id) => {
+                self.spans.remove(&id);
+            }
+            Trace::RegisterCallsite(_)
+            | Trace::Enter(_)
+            | Trace::Exit(_)
+            | Trace::FollowsFrom(_) => {}
+        }
+    }
+
+    /// Returns the fields of the callsite with `metadata`, adding it if it is new.
+    fn callsite(&mut self, metadata: Metadata) -> &mut CallsiteFields {
+        let order = &mut self.order;
+        self.callsites.entry(metadata.id).or_insert_with(|| {
+            order.push(metadata.id);
+            CallsiteFields {
+                metadata,
+                count: 0,
+                fields: HashMap::new(),
+            }
+        })
+    }
+
+    fn finish(mut self) -> Schema {
+        let callsites = self
+            .order
+            .iter()
+            .filter_map(|id| self.callsites.remove(id))
+            .map(CallsiteFields::finish)
+            .collect();
+
+        Schema { callsites }
+    }
+}
@@ -0,0 +1,495 @@
+//! Where the bytes of a recording go, and how much smaller it could be stored.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{self, BufRead, Write},
+};
+
+use crate::{
+    export::{self, ExportError},
+    protobuf::Message,
+    Compression, ContainerWriter, Field, FieldValue, Metadata, Parent, SpanId, Trace, TraceRecord,
+};
+
+/// Reports the size of a recording, and estimates how much smaller it could be stored.
+///
+/// The report which [`report`] returns breaks the size of the recording down by the kind of
+/// record and by callsite, and estimates the size of the recording if it was stored in other
+/// ways:
+///
+/// - deduplicated, with the metadata of each callsite and the name of each thread only written
+///   the first time, and referred to by Id after that.
+/// - compressed, in a container with deflate compressed segments, see [`ContainerWriter`].
+/// - binary encoded, with callsites, threads, and field names referred to by index, and
+///   timestamps stored as the time since the previous record.
+///
+/// These guide the choice of how to record: a recording which is mostly a few chatty callsites
+/// can be filtered, while one which compresses well can be recorded into a container.
+///
+/// Sizes are of the records serialized as JSON lines in the current version of the format, the
+/// way that `tracing-rec` writes them, so a recording in an older version of the format or in a
+/// container is measured as if it was written again. By default, the 10 callsites with the most
+/// bytes are reported, this can be changed with [`with_top`].
+///
+/// # Examples
+///
+/// ```
+/// let recording = concat!(
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":0,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":1,"fields":[],"metadata":{"id":4403349456,"name":"request","target":"app","level":"Info","module_path":"app","file":"src/main.rs","line":8,"fields":[],"kind":"Span"},"parent":"Root"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":100000,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":200000,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"slow response"}}],"metadata":{"id":4403349608,"name":"event","target":"app::db","level":"Warn","module_path":"app::db","file":"src/db.rs","line":9,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177341,"timestamp_subsec_us":500000,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"slow response"}}],"metadata":{"id":4403349608,"name":"event","target":"app::db","level":"Warn","module_path":"app::db","file":"src/db.rs","line":9,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177341,"timestamp_subsec_us":600000,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Exit":1}}"#,
+///     "\n",
+/// );
+///
+/// let report = tracing_cassette::Sizes::new()
+///     .with_top(5)
+///     .report(recording.as_bytes())
+///     .unwrap();
+///
+/// assert_eq!(report.records, 5);
+/// assert_eq!(report.kinds[0].kind, "Event");
+/// assert_eq!(report.kinds[0].records, 2);
+/// assert_eq!(report.callsites[0].metadata.target, "app::db");
+/// assert!(report.deduplicated_bytes < report.bytes);
+/// assert!(report.binary_bytes < report.deduplicated_bytes);
+/// ```
+///
+/// [`report`]: fn@Self::report
+/// [`with_top`]: fn@Self::with_top
+#[derive(Clone, Debug)]
+pub struct Sizes {
+    top: usize,
+}
+
+impl Default for Sizes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sizes {
+    /// Creates a size report of the top 10 callsites.
+    #[must_use]
+    pub fn new() -> Self {
+        Self { top: 10 }
+    }
+
+    /// Sets how many of the callsites with the most bytes are reported, the default is 10.
+    #[must_use]
+    pub fn with_top(mut self, top: usize) -> Self {
+        self.top = top;
+        self
+    }
+
+    /// Reads the recording from `reader` and reports its size.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the recording fails, if a line of the recording can't be
+    /// deserialized into a record, or if the recording was written in a newer version of the
+    /// format.
+    pub fn report<R: BufRead>(&self, reader: R) -> Result<SizeReport, ExportError> {
+        let mut counter = SizeCounter::new();
+        export::for_each_record(reader, |record| counter.push(&record))?;
+
+        counter.finish(self.top)
+    }
+}
+
+/// The size of a recording, see [`Sizes`].
+///
+/// All sizes are in bytes.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SizeReport {
+    /// The number of records.
+    pub records: u64,
+    /// The size of the records as JSON lines.
+    pub bytes: u64,
+    /// The size of each kind of record, most bytes first.
+    pub kinds: Vec<KindSize>,
+    /// The callsites with the most bytes, most first.
+    pub callsites: Vec<CallsiteSize>,
+    /// The estimated size with the metadata of each callsite and the name of each thread only
+    /// written the first time.
+    pub deduplicated_bytes: u64,
+    /// The size as a container with deflate compressed segments.
+    pub compressed_bytes: u64,
+    /// The estimated size with a binary encoding of the deduplicated records.
+    pub binary_bytes: u64,
+}
+
+impl SizeReport {
+    /// Returns the fraction of the size of the recording which would be saved by storing it in
+    /// `bytes`, such as [`compressed_bytes`], which is negative if `bytes` is larger.
+    ///
+    /// [`compressed_bytes`]: structfield@Self::compressed_bytes
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn savings(&self, bytes: u64) -> f64 {
+        if self.bytes == 0 {
+            return 0.0;
+        }
+        1.0 - bytes as f64 / self.bytes as f64
+    }
+}
+
+/// The size of the records of one kind, see [`SizeReport::kinds`].
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct KindSize {
+    /// The kind of record, the name of its [`Trace`] variant, such as `NewSpan`.
+    pub kind: &'static str,
+    /// The number of records of the kind.
+    pub records: u64,
+    /// The size of the records of the kind.
+    pub bytes: u64,
+}
+
+/// The size of the records of a callsite, see [`SizeReport::callsites`].
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallsiteSize {
+    /// The metadata of the callsite.
+    pub metadata: Metadata,
+    /// The number of records of the callsite: its registration, its events, and its spans being
+    /// created, entered, exited, closed, and recording values.
+    pub records: u64,
+    /// The size of the records of the callsite.
+    pub bytes: u64,
+}
+
+/// A writer which only counts the bytes written to it.
+#[derive(Debug, Default)]
+struct CountingWriter {
+    bytes: u64,
+}
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Measures the records of a recording, one at a time.
+#[derive(Debug)]
+struct SizeCounter {
+    records: u64,
+    bytes: u64,
+    deduplicated_bytes: u64,
+    binary_bytes: u64,
+    container: ContainerWriter<CountingWriter>,
+    /// The records and bytes of each kind of record.
+    kinds: BTreeMap<&'static str, (u64, u64)>,
+    /// The metadata, records, and bytes of each callsite, by callsite Id.
+    callsites: HashMap<u64, CallsiteSize>,
+    /// The callsite of each open span.
+    spans: HashMap<SpanId, u64>,
+    /// The index of each thread, by thread Id.
+    threads: HashMap<String, u64>,
+    /// The callsites whose metadata has been written.
+    written_callsites: HashSet<u64>,
+    /// The timestamp of the previous record, in microseconds since the UNIX epoch.
+    previous_us: i64,
+}
+
+impl SizeCounter {
+    fn new() -> Self {
+        Self {
+            records: 0,
+            bytes: 0,
+            deduplicated_bytes: 0,
+            binary_bytes: 0,
+            container: ContainerWriter::new(CountingWriter::default())
+                .with_compression(Compression::Deflate),
+            kinds: BTreeMap::new(),
+            callsites: HashMap::new(),
+            spans: HashMap::new(),
+            threads: HashMap::new(),
+            written_callsites: HashSet::new(),
+            previous_us: 0,
+        }
+    }
+
+    fn push(&mut self, record: &TraceRecord) -> Result<(), ExportError> {
+        let line = serde_json::to_vec(record).map_err(io::Error::from)?;
+        // Each record is followed by a line ending.
+        let bytes = line.len() as u64 + 1;
+        self.records += 1;
+        self.bytes += bytes;
+        self.container.write_record(record)?;
+
+        let kind = match &record.trace {
+            Trace::RegisterCallsite(_) => "RegisterCallsite",
+            Trace::Event(_) => "Event",
+            Trace::NewSpan(_) => "NewSpan",
+            Trace::Enter(_) => "Enter",
+            Trace::Exit(_) => "Exit",
+            Trace::Close(_) => "Close",
+            Trace::Record(_) => "Record",
+            Trace::FollowsFrom(_) => "FollowsFrom",
+        };
+        let (kind_records, kind_bytes) = self.kinds.entry(kind).or_default();
+        *kind_records += 1;
+        *kind_bytes += bytes;
+
+        let callsite_id = match &record.trace {
+            Trace::RegisterCallsite(metadata) => Some(self.callsite(metadata)),
+            Trace::Event(event) => Some(self.callsite(&event.metadata)),
+            Trace::NewSpan(new_span) => {
+                let callsite_id = self.callsite(&new_span.metadata);
+                // A span Id may be reused once the span is closed, so this replaces that span.
+                self.spans.insert(new_span.id, callsite_id);
+                Some(callsite_id)
+            }
+            Trace::Enter(id) | Trace::Exit(id) => self.spans.get(id).copied(),
+            Trace::Close(id) => self.spans.remove(id),
+            Trace::Record(values) => self.spans.get(&values.id).copied(),
+            Trace::FollowsFrom(follows_from) => self.spans.get(&follows_from.effect_id).copied(),
+        };
+        if let Some(callsite) = callsite_id.and_then(|id| self.callsites.get_mut(&id)) {
+            callsite.records += 1;
+            callsite.bytes += bytes;
+        }
+
+        self.deduplicated_bytes += bytes - self.duplicated_bytes(record)?;
+        self.binary_bytes += self.binary_encode(record);
+
+        Ok(())
+    }
+
+    /// Returns the callsite Id of `metadata`, adding the callsite if it is new.
+    fn callsite(&mut self, metadata: &Metadata) -> u64 {
+        self.callsites
+            .entry(metadata.id)
+            .or_insert_with(|| CallsiteSize {
+                metadata: metadata.clone(),
+                records: 0,
+                bytes: 0,
+            });
+        metadata.id
+    }
+
+    /// Returns the bytes of `record` which repeat a callsite's metadata or a thread's name that
+    /// were written before, less what is needed to refer to them.
+    fn duplicated_bytes(&mut self, record: &TraceRecord) -> Result<u64, ExportError> {
+        let mut duplicated = 0;
+        if let Some(thread_name) = &record.meta.thread_name {
+            if self.threads.contains_key(&record.meta.thread_id) {
+                // The name would be left out, as `null`.
+                let name_len = serde_json::to_vec(thread_name)
+                    .map_err(io::Error::from)?
+                    .len();
+                duplicated += (name_len as u64).saturating_sub("null".len() as u64);
+            }
+        }
+
+        let metadata = match &record.trace {
+            Trace::RegisterCallsite(metadata) => metadata,
+            Trace::Event(event) => &event.metadata,
+            Trace::NewSpan(new_span) => &new_span.metadata,
+            _ => return Ok(duplicated),
+        };
+        if !self.written_callsites.insert(metadata.id) {
+            // The metadata would be replaced by its Id.
+            let metadata_len = serde_json::to_vec(metadata).map_err(io::Error::from)?.len();
+            let id_len = metadata.id.to_string().len();
+            duplicated += metadata_len.saturating_sub(id_len) as u64;
+        }
+
+        Ok(duplicated)
+    }
+
+    /// Returns the size of `record` in a binary encoding, with a length prefix.
+    ///
+    /// This isn't a format which recordings can be written in, it's an estimate of how small a
+    /// binary encoding of the records could be.
+    fn binary_encode(&mut self, record: &TraceRecord) -> u64 {
+        let mut message = Message::default();
+
+        let timestamp_us = i64::try_from(record.meta.timestamp().as_micros()).unwrap_or(i64::MAX);
+        message.varint(1, zigzag(timestamp_us.wrapping_sub(self.previous_us)));
+        self.previous_us = timestamp_us;
+
+        let next_index = self.threads.len() as u64;
+        let thread_index = match self.threads.get(&record.meta.thread_id) {
+            Some(index) => *index,
+            None => {
+                message.string(2, &record.meta.thread_id);
+                if let Some(thread_name) = &record.meta.thread_name {
+                    message.string(3, thread_name);
+                }
+                self.threads
+                    .insert(record.meta.thread_id.clone(), next_index);
+                next_index
+            }
+        };
+        message.varint(4, thread_index);
+
+        let span_id = |message: &mut Message, field, id: &SpanId| {
+            message.varint(field, u64::from(*id));
+        };
+        match &record.trace {
+            Trace::RegisterCallsite(metadata) => {
+                message.message(5, &encode_metadata(metadata));
+            }
+            Trace::Event(event) => {
+                let mut trace = Message::default();
+                trace.varint(1, event.metadata.id);
+                encode_parent(&mut trace, &event.parent);
+                encode_fields(&mut trace, &event.metadata, &event.fields);
+                message.message(6, &trace);
+            }
+            Trace::NewSpan(new_span) => {
+                let mut trace = Message::default();
+                trace.varint(1, new_span.metadata.id);
+                encode_parent(&mut trace, &new_span.parent);
+                encode_fields(&mut trace, &new_span.metadata, &new_span.fields);
+                span_id(&mut trace, 4, &new_span.id);
+                message.message(7, &trace);
+            }
+            Trace::Enter(id) => span_id(&mut message, 8, id),
+            Trace::Exit(id) => span_id(&mut message, 9, id),
+            Trace::Close(id) => span_id(&mut message, 10, id),
+            Trace::Record(values) => {
+                let mut trace = Message::default();
+                span_id(&mut trace, 1, &values.id);
+                let metadata = self
+                    .spans
+                    .get(&values.id)
+                    .and_then(|callsite_id| self.callsites.get(callsite_id))
+                    .map(|callsite| &callsite.metadata);
+                match metadata {
+                    Some(metadata) => encode_fields(&mut trace, metadata, &values.fields),
+                    None => {
+                        for field in &values.fields {
+                            encode_field(&mut trace, None, field);
+                        }
+                    }
+                }
+                message.message(11, &trace);
+            }
+            Trace::FollowsFrom(follows_from) => {
+                let mut trace = Message::default();
+                span_id(&mut trace, 1, &follows_from.cause_id);
+                span_id(&mut trace, 2, &follows_from.effect_id);
+                message.message(12, &trace);
+            }
+        }
+
+        let len = message.as_bytes().len() as u64;
+        len + varint_len(len)
+    }
+
+    fn finish(self, top: usize) -> Result<SizeReport, ExportError> {
+        let compressed_bytes = self.container.finish()?.bytes;
+
+        let mut kinds: Vec<KindSize> = self
+            .kinds
+            .into_iter()
+            .map(|(kind, (records, bytes))| KindSize {
+                kind,
+                records,
+                bytes,
+            })
+            .collect();
+        // The kinds are in order of their names, which breaks ties.
+        kinds.sort_by_key(|kind| Reverse(kind.bytes));
+
+        let mut callsites: Vec<CallsiteSize> = self.callsites.into_values().collect();
+        // Ties are broken by the callsite Id, so that the report doesn't depend on hashing.
+        callsites.sort_by_key(|callsite| (Reverse(callsite.bytes), callsite.metadata.id));
+        callsites.truncate(top);
+
+        Ok(SizeReport {
+            records: self.records,
+            bytes: self.bytes,
+            kinds,
+            callsites,
+            deduplicated_bytes: self.deduplicated_bytes,
+            compressed_bytes,
+            binary_bytes: self.binary_bytes,
+        })
+    }
+}
+
+fn encode_metadata(metadata: &Metadata) -> Message {
+    let mut message = Message::default();
+    message.varint(1, metadata.id);
+    message.string(2, &metadata.name);
+    message.string(3, &metadata.target);
+    message.varint(4, metadata.level.clone() as u64);
+    if let Some(module_path) = &metadata.module_path {
+        message.string(5, module_path);
+    }
+    if let Some(file) = &metadata.file {
+        message.string(6, file);
+    }
+    if let Some(line) = metadata.line {
+        message.varint(7, line.into());
+    }
+    for field in &metadata.fields {
+        message.string(8, field);
+    }
+    message.varint(9, metadata.kind.clone() as u64);
+    message
+}
+
+fn encode_parent(message: &mut Message, parent: &Parent) {
+    match parent {
+        // The current span is the usual parent, so it is left out.
+        Parent::Current => {}
+        Parent::Root => message.varint(2, 0),
+        Parent::Explicit(id) => message.varint(2, u64::from(*id) + 1),
+    }
+}
+
+fn encode_fields(message: &mut Message, metadata: &Metadata, fields: &[Field]) {
+    for field in fields {
+        let index = metadata.fields.iter().position(|name| *name == field.name);
+        encode_field(message, index, field);
+    }
+}
+
+/// Encodes a field, referring to its name by the index in the callsite's fields if it has one.
+fn encode_field(message: &mut Message, index: Option<usize>, field: &Field) {
+    let mut encoded = Message::default();
+    match index {
+        Some(index) => encoded.varint(1, index as u64),
+        None => encoded.string(2, &field.name),
+    }
+    match &field.value {
+        FieldValue::Debug(value) => encoded.string(3, value),
+        FieldValue::Str(value) => encoded.string(4, value),
+        FieldValue::F64(value) => encoded.double(5, *value),
+        FieldValue::I64(value) => encoded.varint(6, zigzag(*value)),
+        FieldValue::U64(value) => encoded.varint(7, *value),
+        FieldValue::I128(value) => encoded.bytes(8, &value.to_le_bytes()),
+        FieldValue::U128(value) => encoded.bytes(9, &value.to_le_bytes()),
+        FieldValue::Bool(value) => encoded.varint(10, u64::from(*value)),
+        FieldValue::Json(value) => encoded.string(11, &value.to_string()),
+    }
+    message.message(3, &encoded);
+}
+
+/// Maps signed integers to unsigned ones, so that small negative numbers stay small.
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Returns the number of bytes in the varint encoding of `value`.
+fn varint_len(value: u64) -> u64 {
+    u64::from((64 - value.leading_zeros()).max(1).div_ceil(7))
+}
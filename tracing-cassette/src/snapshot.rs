@@ -0,0 +1,310 @@
+//! The spans which were open at an instant of a recording.
+
+use std::{collections::HashMap, fmt, io::BufRead, time::Duration};
+
+use crate::{
+    export::{self, ExportError},
+    pretty::{write_callsite, write_fields},
+    Field, Metadata, Parent, SpanId, Trace, TraceRecord,
+};
+
+/// The spans which were open at an instant of a recording, on each recorded thread.
+///
+/// This answers what a program was doing at a point in time, such as when an incident started.
+/// Each thread has the spans which were entered on it at that instant, outermost first, followed
+/// by the spans which it created that were open but not entered anywhere, in the order that they
+/// were created. A span which was entered on several threads at once is on each of them.
+///
+/// The fields of each span have the values which were recorded up to that instant, values
+/// recorded later aren't applied. The snapshot is displayed with a line for each thread, followed
+/// by a line for each of its spans.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use tracing_cassette::{FieldValue, SpanSnapshot};
+///
+/// let recording = concat!(
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":0,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":1,"fields":[{"name":"user_id","value":{"U64":42}}],"metadata":{"id":4403349456,"name":"request","target":"app","level":"Info","module_path":"app","file":"src/main.rs","line":8,"fields":["user_id"],"kind":"Span"},"parent":"Root"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":100000,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":200000,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":2,"fields":[],"metadata":{"id":4403349608,"name":"query","target":"app::db","level":"Debug","module_path":"app::db","file":"src/db.rs","line":9,"fields":[],"kind":"Span"},"parent":"Current"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177341,"timestamp_subsec_us":0,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Close":2}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177341,"timestamp_subsec_us":500000,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Exit":1}}"#,
+///     "\n",
+/// );
+///
+/// let at = Duration::new(1715177340, 500_000_000);
+/// let snapshot = SpanSnapshot::read(recording.as_bytes(), at).unwrap();
+///
+/// let main = &snapshot.threads[0];
+/// assert_eq!(main.thread_name.as_deref(), Some("main"));
+/// assert_eq!(main.spans.len(), 2);
+/// assert_eq!(main.spans[0].metadata.name, "request");
+/// assert!(main.spans[0].entered);
+/// assert_eq!(main.spans[0].fields[0].value, FieldValue::U64(42));
+/// assert_eq!(main.spans[0].open_for, Duration::from_millis(500));
+/// assert_eq!(main.spans[1].metadata.name, "query");
+/// assert_eq!(main.spans[1].parent, Some(main.spans[0].id));
+/// assert!(!main.spans[1].entered);
+///
+/// assert_eq!(
+///     snapshot.to_string(),
+///     "ThreadId(1) [main]\n  \
+///        entered        500ms INFO app::request user_id=42\n  \
+///        idle           300ms DEBUG app::db::query",
+/// );
+/// ```
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpanSnapshot {
+    /// The instant of the snapshot, as a duration since the UNIX epoch.
+    pub at: Duration,
+    /// The threads which had spans open, in the order they were first recorded.
+    pub threads: Vec<ThreadSnapshot>,
+}
+
+/// The spans which were open on a recorded thread, see [`SpanSnapshot`].
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ThreadSnapshot {
+    /// The Id of the recorded thread, such as `ThreadId(1)`.
+    pub thread_id: String,
+    /// The name of the recorded thread, if it had one.
+    pub thread_name: Option<String>,
+    /// The spans entered on the thread, outermost first, then the spans which the thread created
+    /// that weren't entered anywhere.
+    pub spans: Vec<OpenSpan>,
+}
+
+/// A span which was open at the instant of a [`SpanSnapshot`].
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpenSpan {
+    /// The Id that the span was recorded with.
+    pub id: SpanId,
+    /// The metadata of the span's callsite.
+    pub metadata: Metadata,
+    /// The values of the span's fields, with the values recorded up to the snapshot applied.
+    pub fields: Vec<Field>,
+    /// The Id of the span's parent, if it had one.
+    pub parent: Option<SpanId>,
+    /// When the span was created, as a duration since the UNIX epoch.
+    pub created: Duration,
+    /// How long the span had been open for.
+    pub open_for: Duration,
+    /// Whether the span was entered on the thread.
+    pub entered: bool,
+}
+
+impl SpanSnapshot {
+    /// Returns the spans which were open at `at` in `records`, a duration since the UNIX epoch.
+    #[must_use]
+    pub fn new(records: &[TraceRecord], at: Duration) -> Self {
+        let mut builder = SnapshotBuilder::new(at);
+        for record in records {
+            builder.push(record.clone());
+        }
+        builder.finish()
+    }
+
+    /// Reads a recording from `reader` and returns the spans which were open at `at`, a
+    /// duration since the UNIX epoch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the recording fails, if a line of the recording can't be
+    /// deserialized into a record, or if the recording was written in a newer version of the
+    /// format.
+    pub fn read<R: BufRead>(reader: R, at: Duration) -> Result<Self, ExportError> {
+        let mut builder = SnapshotBuilder::new(at);
+        export::for_each_record(reader, |record| {
+            builder.push(record);
+            Ok(())
+        })?;
+
+        Ok(builder.finish())
+    }
+}
+
+impl fmt::Display for SpanSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, thread) in self.threads.iter().enumerate() {
+            if idx > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", thread.thread_id)?;
+            if let Some(thread_name) = &thread.thread_name {
+                write!(f, " [{thread_name}]")?;
+            }
+            for span in &thread.spans {
+                let state = if span.entered { "entered" } else { "idle" };
+                write!(f, "\n  {state:<7} {:>12} ", format!("{:?}", span.open_for))?;
+                write_callsite(f, &span.metadata)?;
+                write_fields(f, &span.fields)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A span which is open so far.
+#[derive(Debug)]
+struct SpanState {
+    metadata: Metadata,
+    fields: Vec<Field>,
+    parent: Option<SpanId>,
+    created: Duration,
+    /// The index of the thread which created the span.
+    thread: usize,
+    /// The order in which the span was created.
+    order: u64,
+}
+
+/// Builds a [`SpanSnapshot`] one record at a time.
+#[derive(Debug)]
+struct SnapshotBuilder {
+    at: Duration,
+    /// The Id and name of each thread, in the order they were first recorded.
+    threads: Vec<(String, Option<String>)>,
+    thread_indices: HashMap<String, usize>,
+    open: HashMap<SpanId, SpanState>,
+    /// The spans entered on each thread, by thread index, innermost last.
+    entered: Vec<Vec<SpanId>>,
+    created: u64,
+}
+
+impl SnapshotBuilder {
+    fn new(at: Duration) -> Self {
+        Self {
+            at,
+            threads: Vec::new(),
+            thread_indices: HashMap::new(),
+            open: HashMap::new(),
+            entered: Vec::new(),
+            created: 0,
+        }
+    }
+
+    fn push(&mut self, record: TraceRecord) {
+        let ts = record.meta.timestamp();
+        // Records from different threads may be slightly out of order, so the whole recording is
+        // read rather than stopping at the first record after the snapshot.
+        if ts > self.at {
+            return;
+        }
+        let thread = self.thread(record.meta.thread_id, record.meta.thread_name);
+
+        match record.trace {
+            Trace::NewSpan(new_span) => {
+                let parent = match new_span.parent {
+                    Parent::Root => None,
+                    Parent::Current => self.entered[thread].last().copied(),
+                    Parent::Explicit(id) => Some(id),
+                };
+                self.created += 1;
+                // A span Id may be reused once the span is closed, so this replaces that span.
+                self.open.insert(
+                    new_span.id,
+                    SpanState {
+                        metadata: new_span.metadata,
+                        fields: new_span.fields,
+                        parent,
+                        created: ts,
+                        thread,
+                        order: self.created,
+                    },
+                );
+            }
+            Trace::Record(record_values) => {
+                let Some(span) = self.open.get_mut(&record_values.id) else {
+                    return;
+                };
+                for field in record_values.fields {
+                    match span.fields.iter_mut().find(|f| f.name == field.name) {
+                        Some(existing) => existing.value = field.value,
+                        None => span.fields.push(field),
+                    }
+                }
+            }
+            Trace::Enter(id) => self.entered[thread].push(id),
+            Trace::Exit(id) => {
+                let stack = &mut self.entered[thread];
+                if let Some(pos) = stack.iter().rposition(|entered| *entered == id) {
+                    stack.remove(pos);
+                }
+            }
+            Trace::Close(id) => {
+                self.open.remove(&id);
+            }
+            Trace::RegisterCallsite(_) | Trace::Event(_) | Trace::FollowsFrom(_) => {}
+        }
+    }
+
+    /// Returns the index of a thread, adding it if it is new.
+    fn thread(&mut self, thread_id: String, thread_name: Option<String>) -> usize {
+        if let Some(&idx) = self.thread_indices.get(&thread_id) {
+            return idx;
+        }
+        let idx = self.threads.len();
+        self.thread_indices.insert(thread_id.clone(), idx);
+        self.threads.push((thread_id, thread_name));
+        self.entered.push(Vec::new());
+        idx
+    }
+
+    fn finish(self) -> SpanSnapshot {
+        let at = self.at;
+        let open_span = |id: SpanId, span: &SpanState, entered: bool| OpenSpan {
+            id,
+            metadata: span.metadata.clone(),
+            fields: span.fields.clone(),
+            parent: span.parent,
+            created: span.created,
+            open_for: at.saturating_sub(span.created),
+            entered,
+        };
+
+        let mut spans: Vec<Vec<OpenSpan>> = vec![Vec::new(); self.threads.len()];
+        for (thread, stack) in self.entered.iter().enumerate() {
+            for id in stack {
+                // A span which is entered again while it is entered is only listed once.
+                if spans[thread].iter().any(|span| span.id == *id) {
+                    continue;
+                }
+                if let Some(span) = self.open.get(id) {
+                    spans[thread].push(open_span(*id, span, true));
+                }
+            }
+        }
+
+        let mut idle: Vec<(&SpanId, &SpanState)> = self
+            .open
+            .iter()
+            .filter(|(id, _)| !self.entered.iter().any(|stack| stack.contains(id)))
+            .collect();
+        idle.sort_by_key(|(_, span)| span.order);
+        for (id, span) in idle {
+            spans[span.thread].push(open_span(*id, span, false));
+        }
+
+        let threads = self
+            .threads
+            .into_iter()
+            .zip(spans)
+            .filter(|(_, spans)| !spans.is_empty())
+            .map(|((thread_id, thread_name), spans)| ThreadSnapshot {
+                thread_id,
+                thread_name,
+                spans,
+            })
+            .collect();
+
+        SpanSnapshot { at, threads }
+    }
+}
@@ -0,0 +1,271 @@
+//! Export of recordings to the speedscope profile format.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, BufRead, Write},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::{
+    export::{self, Slice, Timeline, TimelineEvent},
+    ExportError, SpanId,
+};
+
+/// The URL of the schema that speedscope files declare.
+const SCHEMA: &str = "https://www.speedscope.app/file-format-schema.json";
+
+/// Exports the recording read from `reader` as a speedscope profile, which is written to
+/// `writer`.
+///
+/// The profile is written in speedscope's [file format], which can be opened in the
+/// [speedscope] web app, or run locally without any other infrastructure. The file contains an
+/// evented profile for each recorded thread, named after the recorded thread. Each span is a
+/// frame, named by its target and name, which is opened when the span is entered and closed when
+/// it is exited. Events aren't included, as speedscope has no way of showing them.
+///
+/// Speedscope requires the frames to be closed in the reverse order that they were opened. When
+/// a span is exited while spans entered after it are still entered, those spans are closed and
+/// opened again at the same time.
+///
+/// Timestamps are given in microseconds, relative to the first record in the recording. Spans
+/// which are still entered at the end of the recording are ended at the time of the last record.
+///
+/// # Errors
+///
+/// Returns an error if reading the recording or writing the profile fails, if a line of the
+/// recording can't be deserialized into a record, or if the recording was written in a newer
+/// version of the format.
+///
+/// # Examples
+///
+/// ```
+/// let recording = concat!(
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":1,"fields":[{"name":"answer","value":{"I64":42}}],"metadata":{"id":4403349456,"name":"span","target":"record_spans","level":"Info","module_path":"record_spans","file":"tracing-rec/examples/record-spans.rs","line":8,"fields":["answer"],"kind":"Span"},"parent":"Root"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543410,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543425,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Exit":1}}"#,
+///     "\n",
+/// );
+///
+/// let mut profile = Vec::new();
+/// tracing_cassette::to_speedscope(recording.as_bytes(), &mut profile).unwrap();
+///
+/// let profile: serde_json::Value = serde_json::from_slice(&profile).unwrap();
+/// assert_eq!(profile["shared"]["frames"][0]["name"], "record_spans::span");
+/// let thread = &profile["profiles"][0];
+/// assert_eq!(thread["name"], "main (ThreadId(1))");
+/// assert_eq!(thread["events"][0]["type"], "O");
+/// assert_eq!(thread["events"][0]["at"], 10);
+/// assert_eq!(thread["events"][1]["type"], "C");
+/// assert_eq!(thread["events"][1]["at"], 25);
+/// ```
+///
+/// [file format]: https://github.com/jlfwong/speedscope/wiki/Importing-from-custom-sources
+/// [speedscope]: https://www.speedscope.app
+pub fn to_speedscope<R, W>(reader: R, mut writer: W) -> Result<(), ExportError>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut timeline = Timeline::default();
+    let mut exporter = SpeedscopeExporter::default();
+
+    export::for_each_record(reader, |record| {
+        let events = timeline.push(record);
+        exporter.push_events(timeline.start(), events);
+        Ok(())
+    })?;
+    let events = timeline.finish();
+    exporter.push_events(timeline.start(), events);
+
+    let file = File {
+        schema: SCHEMA,
+        exporter: concat!("tracing-cassette@", env!("CARGO_PKG_VERSION")),
+        shared: Shared {
+            frames: exporter.frames,
+        },
+        profiles: exporter
+            .profiles
+            .into_values()
+            .map(|profile| profile.profile)
+            .collect(),
+    };
+    serde_json::to_writer(&mut writer, &file).map_err(io::Error::from)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// A speedscope file.
+#[derive(Debug, Serialize)]
+struct File {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    exporter: &'static str,
+    shared: Shared,
+    profiles: Vec<Profile>,
+}
+
+/// The parts of a speedscope file which are shared between its profiles.
+#[derive(Debug, Serialize)]
+struct Shared {
+    frames: Vec<Frame>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Frame {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<u32>,
+}
+
+/// An evented profile of a single recorded thread.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Profile {
+    #[serde(rename = "type")]
+    profile_type: &'static str,
+    name: String,
+    unit: &'static str,
+    start_value: u64,
+    end_value: u64,
+    events: Vec<ProfileEvent>,
+}
+
+/// A frame being opened (`O`) or closed (`C`).
+#[derive(Debug, Serialize)]
+struct ProfileEvent {
+    #[serde(rename = "type")]
+    event_type: &'static str,
+    /// The index of the frame in the shared frames.
+    frame: usize,
+    /// Microseconds since the start of the recording.
+    at: u64,
+}
+
+/// A profile together with the frames which are open in it.
+#[derive(Debug)]
+struct OpenProfile {
+    profile: Profile,
+    /// The spans which are open, with their frames, innermost last.
+    open: Vec<(SpanId, usize)>,
+}
+
+/// Turns timeline events into the frames and profiles of a speedscope file.
+#[derive(Debug, Default)]
+struct SpeedscopeExporter {
+    frames: Vec<Frame>,
+    /// The index of each frame, by target, name, file and line.
+    frame_indices: HashMap<(String, String, Option<String>, Option<u32>), usize>,
+    /// The profile of each lane, ordered by lane.
+    profiles: BTreeMap<u64, OpenProfile>,
+}
+
+impl SpeedscopeExporter {
+    /// Adds the timeline events to the profiles, timestamps are relative to `start`.
+    fn push_events(&mut self, start: Duration, events: Vec<TimelineEvent>) {
+        let micros =
+            |ts: Duration| u64::try_from(ts.saturating_sub(start).as_micros()).unwrap_or(u64::MAX);
+        for event in events {
+            match event {
+                TimelineEvent::Thread {
+                    lane,
+                    thread_id,
+                    thread_name,
+                } => {
+                    let name = match thread_name {
+                        Some(name) => format!("{name} ({thread_id})"),
+                        None => thread_id,
+                    };
+                    self.profiles.insert(
+                        lane,
+                        OpenProfile {
+                            profile: Profile {
+                                profile_type: "evented",
+                                name,
+                                unit: "microseconds",
+                                start_value: 0,
+                                end_value: 0,
+                                events: Vec::new(),
+                            },
+                            open: Vec::new(),
+                        },
+                    );
+                }
+                TimelineEvent::SliceBegin {
+                    lane,
+                    id,
+                    ts,
+                    slice,
+                } => {
+                    let frame = self.frame(slice);
+                    if let Some(profile) = self.profiles.get_mut(&lane) {
+                        profile.push_event("O", frame, micros(ts));
+                        profile.open.push((id, frame));
+                    }
+                }
+                TimelineEvent::SliceEnd { lane, id, ts, .. } => {
+                    if let Some(profile) = self.profiles.get_mut(&lane) {
+                        profile.close(id, micros(ts));
+                    }
+                }
+                TimelineEvent::Instant { .. } => {}
+            }
+        }
+    }
+
+    /// Returns the index of the frame for a span, adding the frame if it is new.
+    fn frame(&mut self, slice: Slice) -> usize {
+        let name = format!("{}::{}", slice.target, slice.name);
+        let key = (slice.target, slice.name, slice.file, slice.line);
+        if let Some(idx) = self.frame_indices.get(&key) {
+            return *idx;
+        }
+
+        let idx = self.frames.len();
+        self.frames.push(Frame {
+            name,
+            file: key.2.clone(),
+            line: key.3,
+        });
+        self.frame_indices.insert(key, idx);
+        idx
+    }
+}
+
+impl OpenProfile {
+    fn push_event(&mut self, event_type: &'static str, frame: usize, at: u64) {
+        if self.profile.events.is_empty() {
+            self.profile.start_value = at;
+        }
+        self.profile.end_value = self.profile.end_value.max(at);
+        self.profile.events.push(ProfileEvent {
+            event_type,
+            frame,
+            at,
+        });
+    }
+
+    /// Closes the frame of the span `id`, closing and opening again the frames opened after it.
+    fn close(&mut self, id: SpanId, at: u64) {
+        let Some(idx) = self.open.iter().rposition(|(open_id, _)| *open_id == id) else {
+            return;
+        };
+        let reopen: Vec<_> = self.open.drain(idx + 1..).collect();
+        for (_, frame) in reopen.iter().rev() {
+            self.push_event("C", *frame, at);
+        }
+        let (_, frame) = self.open.remove(idx);
+        self.push_event("C", frame, at);
+        for (_, frame) in &reopen {
+            self.push_event("O", *frame, at);
+        }
+        self.open.extend(reopen);
+    }
+}
@@ -0,0 +1,90 @@
+//! Splitting a recording up by target.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{Operator, Query, Subject, Trace, TraceRecord};
+
+/// How [`split`] partitions a recording.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum SplitBy {
+    /// Each target gets its own recording, such as `my_app::db`.
+    Target,
+    /// Each crate gets its own recording, with all the targets which start with its name, such
+    /// as `my_app` for `my_app` and `my_app::db`.
+    Crate,
+}
+
+/// Splits a recording into one recording for each target or crate, keyed by the target or crate.
+///
+/// Each recording has the spans and events of its target or crate, as [`Query::filter`] keeps
+/// them: together with their callsites and all of their ancestor spans, even those from other
+/// targets, so that each recording keeps its place in the trace tree and can still be replayed.
+/// This allows teams to archive and analyze only the traces of the code they own. A span from
+/// one target which has children from others is in the recording of each of them.
+///
+/// Only targets which have spans or events in the recording get a recording of their own, not
+/// those with callsites that were registered but never used.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_cassette::{EventBuilder, RecordingBuilder, SpanBuilder, SplitBy, Trace};
+///
+/// let mut recording = RecordingBuilder::new().with_target("my_app");
+/// recording.thread("main", |thread| {
+///     thread.span(SpanBuilder::new("request"), |thread| {
+///         thread.event(EventBuilder::new("query").with_target("my_app::db"));
+///         thread.event(EventBuilder::new("connect").with_target("hyper::client"));
+///     });
+/// });
+///
+/// let by_target = tracing_cassette::split(recording.build(), SplitBy::Target);
+/// let targets: Vec<_> = by_target.keys().map(String::as_str).collect();
+/// assert_eq!(targets, ["hyper::client", "my_app", "my_app::db"]);
+///
+/// // The `hyper` events keep the span they were recorded in.
+/// let hyper = &by_target["hyper::client"];
+/// assert!(hyper
+///     .iter()
+///     .any(|record| matches!(&record.trace, Trace::NewSpan(span) if span.metadata.target == "my_app")));
+/// assert!(!hyper
+///     .iter()
+///     .any(|record| matches!(&record.trace, Trace::Event(event) if event.metadata.target == "my_app::db")));
+/// ```
+#[must_use]
+pub fn split(records: Vec<TraceRecord>, by: SplitBy) -> BTreeMap<String, Vec<TraceRecord>> {
+    let mut keys = BTreeSet::new();
+    for record in &records {
+        let metadata = match &record.trace {
+            Trace::NewSpan(new_span) => &new_span.metadata,
+            Trace::Event(event) => &event.metadata,
+            _ => continue,
+        };
+        let key = match by {
+            SplitBy::Target => metadata.target.as_str(),
+            SplitBy::Crate => crate_name(&metadata.target),
+        };
+        if !keys.contains(key) {
+            keys.insert(key.to_owned());
+        }
+    }
+
+    keys.into_iter()
+        .map(|key| {
+            let query = match by {
+                SplitBy::Target => Query::compare(Subject::Target, Operator::Eq, key.as_str()),
+                SplitBy::Crate => Query::compare(Subject::Target, Operator::Eq, key.as_str()).or(
+                    Query::compare(Subject::Target, Operator::StartsWith, format!("{key}::")),
+                ),
+            };
+            let records = query.filter(records.clone());
+            (key, records)
+        })
+        .collect()
+}
+
+/// Returns the name of the crate of a target, which is the target up to the first `::`.
+fn crate_name(target: &str) -> &str {
+    target.split("::").next().unwrap_or(target)
+}
@@ -0,0 +1,341 @@
+//! Statistics about where a recording spends its records and its time.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    io::BufRead,
+    time::Duration,
+};
+
+use crate::{
+    export::{self, ExportError},
+    Metadata, SpanId, Trace, TraceRecord,
+};
+
+/// Finds the hotspots of a recording: the busiest callsites and spans, and the rate of events.
+///
+/// The report which [`report`] returns has the callsites with the most spans and events, the
+/// span callsites whose spans were entered for the most time in total, and the number of events
+/// of each target in each time bucket of the recording. By default, the top 10 callsites and
+/// spans are reported, and events are counted in buckets of 1 second. This can be changed with
+/// [`with_top`] and [`with_bucket`].
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// let recording = concat!(
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":0,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":1,"fields":[],"metadata":{"id":4403349456,"name":"request","target":"app","level":"Info","module_path":"app","file":"src/main.rs","line":8,"fields":[],"kind":"Span"},"parent":"Root"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":100000,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":200000,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"slow response"}}],"metadata":{"id":4403349608,"name":"event","target":"app::db","level":"Warn","module_path":"app::db","file":"src/db.rs","line":9,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177341,"timestamp_subsec_us":500000,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"slow response"}}],"metadata":{"id":4403349608,"name":"event","target":"app::db","level":"Warn","module_path":"app::db","file":"src/db.rs","line":9,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177341,"timestamp_subsec_us":600000,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Exit":1}}"#,
+///     "\n",
+/// );
+///
+/// let report = tracing_cassette::Stats::new()
+///     .with_top(5)
+///     .with_bucket(Duration::from_secs(1))
+///     .report(recording.as_bytes())
+///     .unwrap();
+///
+/// assert_eq!(report.callsites[0].metadata.target, "app::db");
+/// assert_eq!(report.callsites[0].count, 2);
+/// assert_eq!(report.spans[0].metadata.name, "request");
+/// assert_eq!(report.spans[0].busy, Duration::from_millis(1500));
+/// assert_eq!(report.events[0].target, "app::db");
+/// assert_eq!(report.events[0].counts, vec![1, 1]);
+/// ```
+///
+/// [`report`]: fn@Self::report
+/// [`with_top`]: fn@Self::with_top
+/// [`with_bucket`]: fn@Self::with_bucket
+#[derive(Clone, Debug)]
+pub struct Stats {
+    top: usize,
+    bucket: Duration,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stats {
+    /// Creates statistics which report the top 10 callsites and spans, and count events in
+    /// buckets of 1 second.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            top: 10,
+            bucket: Duration::from_secs(1),
+        }
+    }
+
+    /// Sets how many of the busiest callsites and spans are reported, the default is 10.
+    #[must_use]
+    pub fn with_top(mut self, top: usize) -> Self {
+        self.top = top;
+        self
+    }
+
+    /// Sets the length of the time buckets that events are counted in, the default is 1 second.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket` is zero.
+    #[must_use]
+    pub fn with_bucket(mut self, bucket: Duration) -> Self {
+        assert!(!bucket.is_zero(), "the time buckets must not be empty");
+        self.bucket = bucket;
+        self
+    }
+
+    /// Reads the recording from `reader` and reports its hotspots.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the recording fails, if a line of the recording can't be
+    /// deserialized into a record, or if the recording was written in a newer version of the
+    /// format.
+    pub fn report<R: BufRead>(&self, reader: R) -> Result<StatsReport, ExportError> {
+        let mut counter = StatsCounter::default();
+        export::for_each_record(reader, |record| {
+            counter.push(record, self.bucket);
+            Ok(())
+        })?;
+
+        Ok(counter.finish(self.top, self.bucket))
+    }
+}
+
+/// The hotspots of a recording, see [`Stats`].
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatsReport {
+    /// The timestamp of the first record, as a duration since the UNIX epoch, which is the
+    /// start of the first time bucket.
+    pub start: Duration,
+    /// The length of each time bucket.
+    pub bucket: Duration,
+    /// The callsites with the most spans and events, most first.
+    pub callsites: Vec<CallsiteCount>,
+    /// The span callsites whose spans were busy for the longest in total, longest first.
+    pub spans: Vec<SpanBusyTime>,
+    /// The events of each target in each time bucket, sorted by target.
+    pub events: Vec<TargetEvents>,
+}
+
+/// The number of times that a callsite was used, see [`StatsReport::callsites`].
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallsiteCount {
+    /// The metadata of the callsite.
+    pub metadata: Metadata,
+    /// The number of spans created, or events recorded, at the callsite.
+    pub count: u64,
+}
+
+/// The time that the spans of a callsite were busy, see [`StatsReport::spans`].
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpanBusyTime {
+    /// The metadata of the span callsite.
+    pub metadata: Metadata,
+    /// The number of spans created at the callsite.
+    pub spans: u64,
+    /// The total time that the spans were entered for, including the time that their children
+    /// were entered for.
+    ///
+    /// A span which is entered on several threads at once is busy on each of them. Spans which
+    /// are still entered at the end of the recording are busy until the last record.
+    pub busy: Duration,
+}
+
+/// The number of events of a target in each time bucket, see [`StatsReport::events`].
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct TargetEvents {
+    /// The target of the events.
+    pub target: String,
+    /// The number of events in each time bucket, there is a bucket for all of the recording.
+    ///
+    /// Events on one thread may be recorded before the first record of the recording on
+    /// another, those are counted in the first bucket.
+    pub counts: Vec<u64>,
+}
+
+impl TargetEvents {
+    /// Returns the total number of events of the target.
+    #[must_use]
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+/// The uses of a callsite, counted so far.
+#[derive(Debug)]
+struct CallsiteUse {
+    metadata: Metadata,
+    count: u64,
+    busy: Duration,
+}
+
+/// Counts the records of a recording, one at a time.
+#[derive(Debug, Default)]
+struct StatsCounter {
+    start: Option<Duration>,
+    latest: Duration,
+    /// The uses of each callsite, by callsite Id.
+    callsites: HashMap<u64, CallsiteUse>,
+    /// The callsite of each open span.
+    spans: HashMap<SpanId, u64>,
+    /// When each span was entered on each thread, and how many times it is entered there.
+    entered: HashMap<(String, SpanId), (Duration, usize)>,
+    /// The events of each target in each time bucket.
+    events: BTreeMap<String, Vec<u64>>,
+}
+
+impl StatsCounter {
+    fn push(&mut self, record: TraceRecord, bucket: Duration) {
+        let ts = record.meta.timestamp();
+        let start = *self.start.get_or_insert(ts);
+        self.latest = self.latest.max(ts);
+
+        match record.trace {
+            Trace::RegisterCallsite(metadata) => {
+                self.callsite(metadata);
+            }
+            Trace::NewSpan(new_span) => {
+                let callsite_id = new_span.metadata.id;
+                self.callsite(new_span.metadata).count += 1;
+                // A span Id may be reused once the span is closed, so this replaces that span.
+                self.spans.insert(new_span.id, callsite_id);
+            }
+            Trace::Event(event) => {
+                let counts = self
+                    .events
+                    .entry(event.metadata.target.clone())
+                    .or_default();
+                let idx = bucket_index(ts.saturating_sub(start), bucket);
+                if counts.len() <= idx {
+                    counts.resize(idx + 1, 0);
+                }
+                counts[idx] += 1;
+                self.callsite(event.metadata).count += 1;
+            }
+            Trace::Enter(id) => {
+                self.entered
+                    .entry((record.meta.thread_id, id))
+                    .and_modify(|(_, depth)| *depth += 1)
+                    .or_insert((ts, 1));
+            }
+            Trace::Exit(id) => {
+                let key = (record.meta.thread_id, id);
+                let Some((entered_at, depth)) = self.entered.get_mut(&key) else {
+                    return;
+                };
+                *depth -= 1;
+                // A span which is entered again while it is entered is only busy once.
+                if *depth == 0 {
+                    let busy = ts.saturating_sub(*entered_at);
+                    self.entered.remove(&key);
+                    self.add_busy(id, busy);
+                }
+            }
+            Trace::Close(id) => {
+                self.spans.remove(&id);
+            }
+            Trace::Record(_) | Trace::FollowsFrom(_) => {}
+        }
+    }
+
+    /// Returns the uses of the callsite with `metadata`, adding it if it is new.
+    fn callsite(&mut self, metadata: Metadata) -> &mut CallsiteUse {
+        self.callsites
+            .entry(metadata.id)
+            .or_insert_with(|| CallsiteUse {
+                metadata,
+                count: 0,
+                busy: Duration::ZERO,
+            })
+    }
+
+    fn add_busy(&mut self, id: SpanId, busy: Duration) {
+        if let Some(callsite) = self
+            .spans
+            .get(&id)
+            .and_then(|callsite_id| self.callsites.get_mut(callsite_id))
+        {
+            callsite.busy += busy;
+        }
+    }
+
+    fn finish(mut self, top: usize, bucket: Duration) -> StatsReport {
+        for ((_, id), (entered_at, _)) in std::mem::take(&mut self.entered) {
+            self.add_busy(id, self.latest.saturating_sub(entered_at));
+        }
+
+        let start = self.start.unwrap_or_default();
+        let buckets = match self.start {
+            Some(start) => bucket_index(self.latest.saturating_sub(start), bucket) + 1,
+            None => 0,
+        };
+
+        let mut callsites: Vec<&CallsiteUse> = self
+            .callsites
+            .values()
+            .filter(|callsite| callsite.count > 0)
+            .collect();
+        // Ties are broken by the callsite Id, so that the report doesn't depend on hashing.
+        callsites.sort_by_key(|callsite| (Reverse(callsite.count), callsite.metadata.id));
+        let top_callsites = callsites
+            .iter()
+            .take(top)
+            .map(|callsite| CallsiteCount {
+                metadata: callsite.metadata.clone(),
+                count: callsite.count,
+            })
+            .collect();
+
+        callsites.retain(|callsite| !callsite.busy.is_zero());
+        callsites.sort_by_key(|callsite| (Reverse(callsite.busy), callsite.metadata.id));
+        let top_spans = callsites
+            .iter()
+            .take(top)
+            .map(|callsite| SpanBusyTime {
+                metadata: callsite.metadata.clone(),
+                spans: callsite.count,
+                busy: callsite.busy,
+            })
+            .collect();
+
+        let events = self
+            .events
+            .into_iter()
+            .map(|(target, mut counts)| {
+                counts.resize(buckets, 0);
+                TargetEvents { target, counts }
+            })
+            .collect();
+
+        StatsReport {
+            start,
+            bucket,
+            callsites: top_callsites,
+            spans: top_spans,
+            events,
+        }
+    }
+}
+
+/// Returns the index of the time bucket that a time since the start of the recording is in.
+fn bucket_index(since_start: Duration, bucket: Duration) -> usize {
+    usize::try_from(since_start.as_nanos() / bucket.as_nanos()).unwrap_or(usize::MAX)
+}
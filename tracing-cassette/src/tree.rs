@@ -0,0 +1,467 @@
+//! An in-memory model of a recording as a tree of spans and events.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::BufRead,
+    ops::Bound,
+    time::Duration,
+};
+
+use crate::{
+    export::{self, ExportError},
+    Field, Metadata, Parent, Query, RecordMeta, SpanId, Trace, TraceRecord,
+};
+
+/// A span or event in a [`TraceTree`], by its index in [`TraceTree::spans`] or
+/// [`TraceTree::events`].
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum NodeRef {
+    /// A span, by its index in [`TraceTree::spans`].
+    Span(usize),
+    /// An event, by its index in [`TraceTree::events`].
+    Event(usize),
+}
+
+/// A span in a [`TraceTree`].
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpanNode {
+    /// The Id that the span was recorded with, which may be reused by later spans once this one
+    /// is closed.
+    pub id: SpanId,
+    /// When, and on which thread, the span was created.
+    pub meta: RecordMeta,
+    /// The metadata of the span's callsite.
+    pub metadata: Metadata,
+    /// The values of the span's fields, with the values recorded after it was created applied.
+    pub fields: Vec<Field>,
+    /// The index of the span's parent, if it has one.
+    pub parent: Option<usize>,
+    /// The spans and events within the span, in the order they were recorded.
+    pub children: Vec<NodeRef>,
+    /// The indexes of the spans which this span follows from.
+    pub follows_from: Vec<usize>,
+    /// When the span was closed, or `None` if it wasn't closed in the recording.
+    pub closed: Option<Duration>,
+    /// The total time that the span was entered for, on all threads.
+    ///
+    /// Spans which are still entered at the end of the recording are busy until the last record.
+    pub busy: Duration,
+}
+
+impl SpanNode {
+    /// Returns when the span was created, as a duration since the UNIX epoch.
+    #[must_use]
+    pub fn created(&self) -> Duration {
+        self.meta.timestamp()
+    }
+
+    /// Returns the time from when the span was created until it was closed, or `None` if it
+    /// wasn't closed in the recording.
+    #[must_use]
+    pub fn duration(&self) -> Option<Duration> {
+        self.closed
+            .map(|closed| closed.saturating_sub(self.created()))
+    }
+}
+
+/// An event in a [`TraceTree`].
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventNode {
+    /// When, and on which thread, the event was recorded.
+    pub meta: RecordMeta,
+    /// The metadata of the event's callsite.
+    pub metadata: Metadata,
+    /// The values of the event's fields.
+    pub fields: Vec<Field>,
+    /// The index of the span that the event was recorded within, if there was one.
+    pub parent: Option<usize>,
+}
+
+/// A recording loaded into memory as a tree of spans and events.
+///
+/// Analysing a recording usually needs the tree of spans and events which the flat stream of
+/// records describes. A `TraceTree` is built from the records once, with the parents of spans and
+/// events resolved, contextual parents to the span which was entered on the recorded thread,
+/// the values recorded for spans applied, and how long each span lasted and was busy for. Spans
+/// are indexed by name, spans and events by target and time, and both can be selected with a
+/// [`Query`].
+///
+/// Spans and events are referred to by their index in [`spans`] and [`events`], which are in
+/// the order they were recorded, with a [`NodeRef`] where it can be either.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use tracing_cassette::{
+///     EventBuilder, NodeRef, Query, RecordingBuilder, SpanBuilder, TraceTree,
+/// };
+///
+/// let mut recording = RecordingBuilder::new().with_target("my_app");
+/// recording.thread("main", |thread| {
+///     thread.span(SpanBuilder::new("request").with_field("user_id", 42), |thread| {
+///         thread.span(
+///             SpanBuilder::new("query")
+///                 .with_target("my_app::db")
+///                 .with_duration(Duration::from_millis(5)),
+///             |_| {},
+///         );
+///         thread.event(EventBuilder::new("responded"));
+///     });
+/// });
+/// let tree = TraceTree::new(&recording.build());
+///
+/// let request = &tree.spans()[0];
+/// assert_eq!(request.metadata.name, "request");
+/// assert_eq!(request.children, [NodeRef::Span(1), NodeRef::Event(0)]);
+/// assert_eq!(tree.events()[0].parent, Some(0));
+///
+/// let query = tree.spans_named("query").next().unwrap();
+/// assert!(query.duration().unwrap() >= Duration::from_millis(5));
+/// assert_eq!(tree.with_target("my_app::db").collect::<Vec<_>>(), [NodeRef::Span(1)]);
+///
+/// // Only the request was open when it was created.
+/// let created = request.created();
+/// assert_eq!(tree.between(created, created), [NodeRef::Span(0)]);
+///
+/// let selected = tree.select(&Query::parse(r#"field("user_id") == 42"#).unwrap());
+/// assert_eq!(selected, [NodeRef::Span(0)]);
+/// ```
+///
+/// [`spans`]: fn@Self::spans
+/// [`events`]: fn@Self::events
+#[derive(Clone, Debug, Default)]
+pub struct TraceTree {
+    spans: Vec<SpanNode>,
+    events: Vec<EventNode>,
+    roots: Vec<NodeRef>,
+    /// The spans and events in the order they were recorded.
+    order: Vec<NodeRef>,
+    /// The spans and events, ordered by when they were created or recorded.
+    by_time: Vec<NodeRef>,
+    /// The spans with each name.
+    by_name: HashMap<String, Vec<usize>>,
+    /// The spans and events of each target.
+    by_target: BTreeMap<String, Vec<NodeRef>>,
+}
+
+impl TraceTree {
+    /// Builds the tree of a recording's records.
+    #[must_use]
+    pub fn new(records: &[TraceRecord]) -> Self {
+        let mut builder = TreeBuilder::default();
+        for record in records {
+            builder.push(record.clone());
+        }
+        builder.finish()
+    }
+
+    /// Reads a recording from `reader` and builds its tree.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the recording fails, if a line of the recording can't be
+    /// deserialized into a record, or if the recording was written in a newer version of the
+    /// format.
+    pub fn read<R: BufRead>(reader: R) -> Result<Self, ExportError> {
+        let mut builder = TreeBuilder::default();
+        export::for_each_record(reader, |record| {
+            builder.push(record);
+            Ok(())
+        })?;
+
+        Ok(builder.finish())
+    }
+
+    /// Returns the spans, in the order they were created.
+    #[must_use]
+    pub fn spans(&self) -> &[SpanNode] {
+        &self.spans
+    }
+
+    /// Returns the events, in the order they were recorded.
+    #[must_use]
+    pub fn events(&self) -> &[EventNode] {
+        &self.events
+    }
+
+    /// Returns the spans and events without a parent, in the order they were recorded.
+    #[must_use]
+    pub fn roots(&self) -> &[NodeRef] {
+        &self.roots
+    }
+
+    /// Returns all the spans and events, in the order they were recorded.
+    #[must_use]
+    pub fn nodes(&self) -> &[NodeRef] {
+        &self.order
+    }
+
+    /// Returns the metadata of a span or event.
+    #[must_use]
+    pub fn metadata(&self, node: NodeRef) -> &Metadata {
+        match node {
+            NodeRef::Span(idx) => &self.spans[idx].metadata,
+            NodeRef::Event(idx) => &self.events[idx].metadata,
+        }
+    }
+
+    /// Returns the index of the parent of a span or event, if it has one.
+    #[must_use]
+    pub fn parent(&self, node: NodeRef) -> Option<usize> {
+        match node {
+            NodeRef::Span(idx) => self.spans[idx].parent,
+            NodeRef::Event(idx) => self.events[idx].parent,
+        }
+    }
+
+    /// Returns the indexes of the ancestors of a span or event, from its parent to its root.
+    pub fn ancestors(&self, node: NodeRef) -> impl Iterator<Item = usize> + '_ {
+        std::iter::successors(self.parent(node), |idx| self.spans[*idx].parent)
+    }
+
+    /// Returns the spans and events within a span, and within those, depth first in the order
+    /// they were recorded.
+    #[must_use]
+    pub fn descendants(&self, span: usize) -> Vec<NodeRef> {
+        let mut descendants = Vec::new();
+        let mut stack: Vec<NodeRef> = self.spans[span].children.iter().rev().copied().collect();
+        while let Some(node) = stack.pop() {
+            descendants.push(node);
+            if let NodeRef::Span(idx) = node {
+                stack.extend(self.spans[idx].children.iter().rev());
+            }
+        }
+        descendants
+    }
+
+    /// Returns the spans with a name, in the order they were created.
+    pub fn spans_named<'a>(&'a self, name: &str) -> impl Iterator<Item = &'a SpanNode> + 'a {
+        self.by_name
+            .get(name)
+            .into_iter()
+            .flatten()
+            .map(|idx| &self.spans[*idx])
+    }
+
+    /// Returns the spans and events with a target which starts with `prefix`, such as
+    /// `my_app::db` for `my_app`, by target and then in the order they were recorded.
+    pub fn with_target<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = NodeRef> + 'a {
+        self.by_target
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(move |(target, _)| target.starts_with(prefix))
+            .flat_map(|(_, nodes)| nodes.iter().copied())
+    }
+
+    /// Returns the spans which were open, and the events recorded, at any time from `start` to
+    /// `end`, ordered by when they were created or recorded.
+    ///
+    /// The times are durations since the UNIX epoch, as timestamps are. Spans which weren't
+    /// closed are open until the end of the recording.
+    #[must_use]
+    pub fn between(&self, start: Duration, end: Duration) -> Vec<NodeRef> {
+        let created_by_end = self
+            .by_time
+            .partition_point(|node| self.timestamp(*node) <= end);
+        self.by_time[..created_by_end]
+            .iter()
+            .copied()
+            .filter(|node| match node {
+                NodeRef::Span(idx) => match self.spans[*idx].closed {
+                    Some(closed) => closed >= start,
+                    None => true,
+                },
+                NodeRef::Event(idx) => self.events[*idx].meta.timestamp() >= start,
+            })
+            .collect()
+    }
+
+    /// Returns the spans and events which match `query`, in the order they were recorded.
+    ///
+    /// Spans are matched with their fields after all of their recorded values were applied.
+    #[must_use]
+    pub fn select(&self, query: &Query) -> Vec<NodeRef> {
+        self.order
+            .iter()
+            .copied()
+            .filter(|node| match node {
+                NodeRef::Span(idx) => {
+                    let span = &self.spans[*idx];
+                    query.matches(&span.meta, &span.metadata, &span.fields)
+                }
+                NodeRef::Event(idx) => {
+                    let event = &self.events[*idx];
+                    query.matches(&event.meta, &event.metadata, &event.fields)
+                }
+            })
+            .collect()
+    }
+
+    /// Returns when a span was created, or an event was recorded.
+    fn timestamp(&self, node: NodeRef) -> Duration {
+        match node {
+            NodeRef::Span(idx) => self.spans[idx].created(),
+            NodeRef::Event(idx) => self.events[idx].meta.timestamp(),
+        }
+    }
+}
+
+/// Builds a [`TraceTree`] one record at a time.
+#[derive(Debug, Default)]
+struct TreeBuilder {
+    tree: TraceTree,
+    /// The index of each open span, span Ids are reused once spans close.
+    open: HashMap<SpanId, usize>,
+    /// The index of each span Id which was created, the latest one for reused Ids.
+    created: HashMap<SpanId, usize>,
+    /// The spans entered on each recorded thread, innermost last.
+    entered: HashMap<String, Vec<SpanId>>,
+    /// When each span was entered on each thread, and how many times it is entered there.
+    entered_at: HashMap<(String, usize), (Duration, usize)>,
+    latest: Duration,
+}
+
+impl TreeBuilder {
+    fn push(&mut self, record: TraceRecord) {
+        let ts = record.meta.timestamp();
+        self.latest = self.latest.max(ts);
+        let thread_id = &record.meta.thread_id;
+
+        match record.trace {
+            Trace::RegisterCallsite(_) => {}
+            Trace::NewSpan(new_span) => {
+                let idx = self.tree.spans.len();
+                let parent = self.resolve_parent(thread_id, &new_span.parent);
+                self.open.insert(new_span.id, idx);
+                self.created.insert(new_span.id, idx);
+                self.add(NodeRef::Span(idx), parent, &new_span.metadata);
+                self.tree.spans.push(SpanNode {
+                    id: new_span.id,
+                    meta: record.meta,
+                    metadata: new_span.metadata,
+                    fields: new_span.fields,
+                    parent,
+                    children: Vec::new(),
+                    follows_from: Vec::new(),
+                    closed: None,
+                    busy: Duration::ZERO,
+                });
+            }
+            Trace::Event(event) => {
+                let idx = self.tree.events.len();
+                let parent = self.resolve_parent(thread_id, &event.parent);
+                self.add(NodeRef::Event(idx), parent, &event.metadata);
+                self.tree.events.push(EventNode {
+                    meta: record.meta,
+                    metadata: event.metadata,
+                    fields: event.fields,
+                    parent,
+                });
+            }
+            Trace::Record(record_values) => {
+                let Some(&idx) = self.open.get(&record_values.id) else {
+                    return;
+                };
+                let fields = &mut self.tree.spans[idx].fields;
+                for field in record_values.fields {
+                    match fields.iter_mut().find(|f| f.name == field.name) {
+                        Some(existing) => existing.value = field.value,
+                        None => fields.push(field),
+                    }
+                }
+            }
+            Trace::FollowsFrom(follows_from) => {
+                let effect = self.open.get(&follows_from.effect_id);
+                let cause = self.created.get(&follows_from.cause_id);
+                if let (Some(&effect), Some(&cause)) = (effect, cause) {
+                    self.tree.spans[effect].follows_from.push(cause);
+                }
+            }
+            Trace::Enter(id) => {
+                let Some(&idx) = self.open.get(&id) else {
+                    return;
+                };
+                self.entered.entry(thread_id.clone()).or_default().push(id);
+                self.entered_at
+                    .entry((record.meta.thread_id, idx))
+                    .and_modify(|(_, depth)| *depth += 1)
+                    .or_insert((ts, 1));
+            }
+            Trace::Exit(id) => {
+                let stack = self.entered.entry(thread_id.clone()).or_default();
+                if let Some(pos) = stack.iter().rposition(|entered| *entered == id) {
+                    stack.remove(pos);
+                }
+                let Some(&idx) = self.open.get(&id) else {
+                    return;
+                };
+                let key = (record.meta.thread_id, idx);
+                let Some((entered_at, depth)) = self.entered_at.get_mut(&key) else {
+                    return;
+                };
+                *depth -= 1;
+                // A span which is entered again while it is entered is only busy once.
+                if *depth == 0 {
+                    let busy = ts.saturating_sub(*entered_at);
+                    self.entered_at.remove(&key);
+                    self.tree.spans[idx].busy += busy;
+                }
+            }
+            Trace::Close(id) => {
+                if let Some(idx) = self.open.remove(&id) {
+                    self.tree.spans[idx].closed = Some(ts);
+                }
+            }
+        }
+    }
+
+    fn finish(mut self) -> TraceTree {
+        for ((_, idx), (entered_at, _)) in self.entered_at.drain() {
+            self.tree.spans[idx].busy += self.latest.saturating_sub(entered_at);
+        }
+
+        let mut tree = self.tree;
+        let mut by_time = tree.order.clone();
+        by_time.sort_by_key(|node| tree.timestamp(*node));
+        tree.by_time = by_time;
+        tree
+    }
+
+    /// Returns the index of the parent span, contextual parents are the span which is entered
+    /// on the recorded thread.
+    fn resolve_parent(&self, thread_id: &str, parent: &Parent) -> Option<usize> {
+        match parent {
+            Parent::Root => None,
+            Parent::Current => self
+                .entered
+                .get(thread_id)
+                .and_then(|stack| stack.last())
+                .and_then(|id| self.open.get(id).copied()),
+            Parent::Explicit(id) => self.created.get(id).copied(),
+        }
+    }
+
+    /// Adds a span or event to the tree, under its parent, and to the indexes.
+    fn add(&mut self, node: NodeRef, parent: Option<usize>, metadata: &Metadata) {
+        match parent {
+            Some(parent) => self.tree.spans[parent].children.push(node),
+            None => self.tree.roots.push(node),
+        }
+        self.tree.order.push(node);
+        if let NodeRef::Span(idx) = node {
+            self.tree
+                .by_name
+                .entry(metadata.name.clone())
+                .or_default()
+                .push(idx);
+        }
+        self.tree
+            .by_target
+            .entry(metadata.target.clone())
+            .or_default()
+            .push(node);
+    }
+}
@@ -0,0 +1,289 @@
+//! Trimming recordings to a time range.
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use crate::{Parent, RecordMeta, SpanId, Trace, TraceRecord};
+
+/// Cuts a recording down to the time range from `start` to `end`, which are relative to the
+/// first record.
+///
+/// The callsites which were registered before the start, and the spans which are open and
+/// entered at the start, are carried forward to the start so that the records within the time
+/// range can still be replayed. Spans which are still entered and open at the end are exited
+/// and closed at the end. Without an end, the recording is kept until its last record.
+///
+/// Spans which were created before the start and closed before it aren't kept, so spans and
+/// events which have them as an explicit parent are given their closest ancestor which is kept
+/// instead, or no parent at all. Records which refer to spans which aren't kept are removed.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use tracing_cassette::{EventBuilder, FieldValue, RecordingBuilder, SpanBuilder, Trace};
+///
+/// let mut recording = RecordingBuilder::new();
+/// recording.thread("main", |thread| {
+///     thread.span(SpanBuilder::new("request"), |thread| {
+///         thread.advance(Duration::from_millis(10));
+///         thread.event(EventBuilder::new("too early"));
+///         thread.advance(Duration::from_millis(10));
+///         thread.event(EventBuilder::new("just right"));
+///     });
+/// });
+/// let records = recording.build();
+/// let start = records[0].meta.timestamp();
+///
+/// let trimmed = tracing_cassette::trim(records, Duration::from_millis(15), None);
+/// // The callsites, and the span which is open and entered, are carried forward to the start.
+/// assert!(matches!(trimmed[2].trace, Trace::NewSpan(_)));
+/// assert!(matches!(trimmed[3].trace, Trace::Enter(_)));
+/// assert_eq!(trimmed[3].meta.timestamp(), start + Duration::from_millis(15));
+/// // Only the second event is left, and the span is exited and closed as it was.
+/// let Trace::Event(event) = &trimmed[4].trace else { unreachable!() };
+/// assert_eq!(event.fields[0].value, FieldValue::Debug("just right".into()));
+/// assert!(matches!(trimmed[5].trace, Trace::Exit(_)));
+/// assert!(matches!(trimmed[6].trace, Trace::Close(_)));
+/// assert_eq!(trimmed.len(), 7);
+/// ```
+#[must_use]
+pub fn trim(records: Vec<TraceRecord>, start: Duration, end: Option<Duration>) -> Vec<TraceRecord> {
+    let Some(first) = records.first() else {
+        return records;
+    };
+    let origin = first.meta.timestamp();
+    let start = origin + start;
+    let end = end.map(|end| origin + end);
+
+    let mut state = State::default();
+    // The spans which are in the trimmed recording.
+    let mut known = HashSet::new();
+    let mut started = false;
+    let mut cut_off = false;
+    let mut last = origin;
+    let mut trimmed = Vec::new();
+    for mut record in records {
+        let ts = record.meta.timestamp();
+        if end.is_some_and(|end| ts > end) {
+            cut_off = true;
+            break;
+        }
+        last = ts;
+        if !started {
+            if ts < start {
+                state.push(&record);
+                continue;
+            }
+            trimmed.extend(state.carry_forward(start, &mut known));
+            started = true;
+        }
+
+        let keep = match &mut record.trace {
+            Trace::NewSpan(new_span) => {
+                if let Parent::Explicit(id) = new_span.parent {
+                    if !known.contains(&id) && !state.is_open(id) {
+                        new_span.parent = state.known_ancestor(Some(id), &known);
+                    }
+                }
+                known.insert(new_span.id);
+                true
+            }
+            Trace::Event(event) => {
+                if let Parent::Explicit(id) = event.parent {
+                    if !known.contains(&id) {
+                        event.parent = state.known_ancestor(Some(id), &known);
+                    }
+                }
+                true
+            }
+            Trace::Enter(id) | Trace::Exit(id) | Trace::Close(id) => known.contains(id),
+            Trace::Record(record_values) => known.contains(&record_values.id),
+            Trace::FollowsFrom(follows_from) => {
+                known.contains(&follows_from.cause_id) && known.contains(&follows_from.effect_id)
+            }
+            Trace::RegisterCallsite(_) => true,
+        };
+        state.push(&record);
+        if keep {
+            trimmed.push(record);
+        }
+    }
+
+    if !started {
+        // The whole recording is before the start, so only what was open at the end is left.
+        trimmed.extend(state.carry_forward(last, &mut known));
+    }
+    if let (true, Some(end)) = (cut_off, end) {
+        trimmed.extend(state.finish(end, &known));
+    }
+
+    trimmed
+}
+
+/// What is open at a point in a recording.
+#[derive(Debug, Default)]
+struct State {
+    callsites: Vec<TraceRecord>,
+    /// The `NewSpan` records of the open spans, in the order they were created, with the values
+    /// recorded since applied.
+    open: Vec<TraceRecord>,
+    /// The parent of each span, with contextual parents resolved.
+    parents: HashMap<SpanId, Option<SpanId>>,
+    /// The thread name and entered spans of each thread, innermost last, in the order that the
+    /// threads first appear.
+    entered: Vec<(String, Option<String>, Vec<SpanId>)>,
+}
+
+impl State {
+    fn push(&mut self, record: &TraceRecord) {
+        let thread = match self
+            .entered
+            .iter()
+            .position(|(thread_id, _, _)| *thread_id == record.meta.thread_id)
+        {
+            Some(thread) => thread,
+            None => {
+                self.entered.push((
+                    record.meta.thread_id.clone(),
+                    record.meta.thread_name.clone(),
+                    Vec::new(),
+                ));
+                self.entered.len() - 1
+            }
+        };
+
+        match &record.trace {
+            Trace::RegisterCallsite(_) => self.callsites.push(record.clone()),
+            Trace::NewSpan(new_span) => {
+                let parent = match new_span.parent {
+                    Parent::Root => None,
+                    Parent::Current => self.entered[thread].2.last().copied(),
+                    Parent::Explicit(id) => Some(id),
+                };
+                self.parents.insert(new_span.id, parent);
+                self.open.push(record.clone());
+            }
+            Trace::Record(record_values) => {
+                if let Some(Trace::NewSpan(new_span)) = self
+                    .open
+                    .iter_mut()
+                    .map(|open| &mut open.trace)
+                    .find(|trace| matches!(trace, Trace::NewSpan(new_span) if new_span.id == record_values.id))
+                {
+                    for field in &record_values.fields {
+                        match new_span.fields.iter_mut().find(|f| f.name == field.name) {
+                            Some(existing) => existing.value = field.value.clone(),
+                            None => new_span.fields.push(field.clone()),
+                        }
+                    }
+                }
+            }
+            Trace::Enter(id) => self.entered[thread].2.push(*id),
+            Trace::Exit(id) => {
+                let stack = &mut self.entered[thread].2;
+                if let Some(idx) = stack.iter().rposition(|entered| entered == id) {
+                    stack.remove(idx);
+                }
+            }
+            Trace::Close(id) => {
+                self.open.retain(
+                    |open| !matches!(&open.trace, Trace::NewSpan(new_span) if new_span.id == *id),
+                );
+            }
+            Trace::Event(_) | Trace::FollowsFrom(_) => {}
+        }
+    }
+
+    fn is_open(&self, id: SpanId) -> bool {
+        self.open
+            .iter()
+            .any(|open| matches!(&open.trace, Trace::NewSpan(new_span) if new_span.id == id))
+    }
+
+    /// Returns the closest ancestor of a span, starting with `parent`, which is in `known`.
+    fn known_ancestor(&self, mut parent: Option<SpanId>, known: &HashSet<SpanId>) -> Parent {
+        // Guard against cycles, which a reused span Id could create.
+        for _ in 0..=self.parents.len() {
+            match parent {
+                None => return Parent::Root,
+                Some(id) if known.contains(&id) => return Parent::Explicit(id),
+                Some(id) => parent = self.parents.get(&id).copied().flatten(),
+            }
+        }
+        Parent::Root
+    }
+
+    /// Returns the records which recreate this state at `ts`.
+    fn carry_forward(&self, ts: Duration, known: &mut HashSet<SpanId>) -> Vec<TraceRecord> {
+        let mut records = self.callsites.clone();
+        for open in &self.open {
+            let mut open = open.clone();
+            if let Trace::NewSpan(new_span) = &mut open.trace {
+                // Contextual parents can't be used, as the spans aren't entered yet.
+                new_span.parent = self.known_ancestor(self.parents[&new_span.id], known);
+                known.insert(new_span.id);
+            }
+            records.push(open);
+        }
+        for (thread_id, thread_name, stack) in &self.entered {
+            for id in stack.iter().filter(|id| known.contains(id)) {
+                records.push(thread_record(thread_id, thread_name, Trace::Enter(*id)));
+            }
+        }
+
+        for record in &mut records {
+            set_timestamp(&mut record.meta, ts);
+        }
+        records
+    }
+
+    /// Returns the records which exit the entered spans and close the open spans, at `ts`.
+    fn finish(&self, ts: Duration, known: &HashSet<SpanId>) -> Vec<TraceRecord> {
+        let mut records = Vec::new();
+        for (thread_id, thread_name, stack) in &self.entered {
+            for id in stack.iter().rev().filter(|id| known.contains(id)) {
+                records.push(thread_record(thread_id, thread_name, Trace::Exit(*id)));
+            }
+        }
+        // Children are closed before their parents.
+        for open in self.open.iter().rev() {
+            if let Trace::NewSpan(new_span) = &open.trace {
+                if known.contains(&new_span.id) {
+                    let mut close = open.clone();
+                    close.trace = Trace::Close(new_span.id);
+                    records.push(close);
+                }
+            }
+        }
+
+        for record in &mut records {
+            set_timestamp(&mut record.meta, ts);
+        }
+        records
+    }
+}
+
+/// Returns a record on a thread, with its timestamp to be set.
+fn thread_record(thread_id: &str, thread_name: &Option<String>, trace: Trace) -> TraceRecord {
+    TraceRecord {
+        meta: RecordMeta {
+            timestamp_s: 0,
+            timestamp_subsec_us: 0,
+            thread_id: thread_id.to_owned(),
+            thread_name: thread_name.clone(),
+        },
+        trace,
+        final_fields: None,
+        sequence: None,
+    }
+}
+
+/// Sets the timestamp of a record.
+fn set_timestamp(meta: &mut RecordMeta, ts: Duration) {
+    meta.timestamp_s = ts.as_secs();
+    meta.timestamp_subsec_us = ts.subsec_micros();
+}
@@ -0,0 +1,428 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    io::{self, BufRead},
+};
+
+use crate::{
+    ContainerReader, Field, FormatVersionError, Header, Kind, Metadata, Parent, SpanId, Trace,
+    TraceRecord, FORMAT_VERSION,
+};
+
+/// The maximum number of fields that a callsite can have.
+///
+/// At most this many field values are passed to the subscriber when a span or event is replayed,
+/// so callsites with more fields than this can't be replayed in full.
+pub const MAX_FIELDS: usize = 32;
+
+/// The names of the levels, as they are serialized.
+const LEVELS: &[&str] = &["Trace", "Debug", "Info", "Warn", "Error"];
+
+/// A way in which a record doesn't conform to the recording format.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Violation {
+    /// The line can't be deserialized into a record.
+    InvalidRecord {
+        /// The error from deserializing the line.
+        message: String,
+    },
+    /// A level isn't one of the levels that `tracing` has.
+    InvalidLevel {
+        /// The level as it was recorded.
+        level: String,
+    },
+    /// The recording was written in a newer version of the format.
+    UnsupportedVersion {
+        /// The version in the header of the recording.
+        version: u32,
+    },
+    /// A header was found somewhere other than on the first line.
+    MisplacedHeader,
+    /// The microseconds of the timestamp are not within a second.
+    InvalidTimestamp {
+        /// The recorded microseconds.
+        timestamp_subsec_us: u32,
+    },
+    /// A callsite or a record has more than [`MAX_FIELDS`] fields.
+    TooManyFields {
+        /// The number of fields.
+        count: usize,
+    },
+    /// A field value was recorded for a field that the callsite doesn't have.
+    UnknownField {
+        /// The name of the field.
+        name: String,
+    },
+    /// The kind of the callsite doesn't match the record, such as an event with the metadata of
+    /// a span.
+    KindMismatch {
+        /// The kind that the callsite should have.
+        expected: Kind,
+    },
+    /// A span Id of zero was recorded, `tracing` span Ids are always non-zero.
+    ZeroSpanId,
+    /// A callsite Id was recorded with different metadata than it was first recorded with.
+    CallsiteMismatch {
+        /// The callsite Id.
+        id: u64,
+    },
+    /// A record refers to a span which isn't open.
+    ///
+    /// The explicit parent of a span or event may have been closed already, as long as it was
+    /// created earlier in the recording.
+    UnknownSpan {
+        /// The recorded span Id.
+        id: SpanId,
+    },
+    /// A new span reuses the span Id of a span which is still open.
+    DuplicateSpan {
+        /// The recorded span Id.
+        id: SpanId,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidRecord { message } => write!(f, "invalid record: {message}"),
+            Self::InvalidLevel { level } => write!(f, "invalid level: {level:?}"),
+            Self::UnsupportedVersion { version } => write!(
+                f,
+                "unsupported version: {version}, the newest supported version is {FORMAT_VERSION}"
+            ),
+            Self::MisplacedHeader => write!(f, "header found after the first line"),
+            Self::InvalidTimestamp {
+                timestamp_subsec_us,
+            } => write!(
+                f,
+                "invalid timestamp: {timestamp_subsec_us} microseconds is more than a second"
+            ),
+            Self::TooManyFields { count } => {
+                write!(f, "too many fields: {count}, the maximum is {MAX_FIELDS}")
+            }
+            Self::UnknownField { name } => write!(f, "unknown field: {name:?}"),
+            Self::KindMismatch { expected } => {
+                write!(f, "kind mismatch: the callsite should be {expected:?}")
+            }
+            Self::ZeroSpanId => write!(f, "span Id of zero"),
+            Self::CallsiteMismatch { id } => {
+                write!(
+                    f,
+                    "callsite mismatch: {id} was recorded with different metadata"
+                )
+            }
+            Self::UnknownSpan { id } => write!(f, "unknown span: {}", u64::from(*id)),
+            Self::DuplicateSpan { id } => {
+                write!(f, "duplicate span: {} is still open", u64::from(*id))
+            }
+        }
+    }
+}
+
+/// A violation found on a line of a recording by [`validate_stream`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LineViolation {
+    /// The index of the line, starting from 0.
+    pub line_index: usize,
+    /// The violation.
+    pub violation: Violation,
+}
+
+impl fmt::Display for LineViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line_index, self.violation)
+    }
+}
+
+/// Checks the structure of a single record.
+///
+/// Only constraints which can be checked without the rest of the recording are checked, use
+/// [`validate_stream`] to check that records refer to spans and callsites correctly as well.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_cassette::{TraceRecord, Violation};
+///
+/// let line = r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Record":{"id":0,"fields":[{"name":"answer","value":{"I64":42}}]}}}"#;
+/// let record: TraceRecord = serde_json::from_str(line).unwrap();
+///
+/// assert_eq!(tracing_cassette::validate(&record), vec![Violation::ZeroSpanId]);
+/// ```
+#[must_use]
+pub fn validate(record: &TraceRecord) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    if record.meta.timestamp_subsec_us >= 1_000_000 {
+        violations.push(Violation::InvalidTimestamp {
+            timestamp_subsec_us: record.meta.timestamp_subsec_us,
+        });
+    }
+
+    match &record.trace {
+        Trace::RegisterCallsite(metadata) => validate_metadata(metadata, &mut violations),
+        Trace::Event(event) => {
+            validate_metadata(&event.metadata, &mut violations);
+            validate_kind(&event.metadata, Kind::Event, &mut violations);
+            validate_fields(&event.fields, &event.metadata.fields, &mut violations);
+            validate_parent(&event.parent, &mut violations);
+        }
+        Trace::NewSpan(new_span) => {
+            validate_span_id(new_span.id, &mut violations);
+            validate_metadata(&new_span.metadata, &mut violations);
+            validate_kind(&new_span.metadata, Kind::Span, &mut violations);
+            validate_fields(&new_span.fields, &new_span.metadata.fields, &mut violations);
+            validate_parent(&new_span.parent, &mut violations);
+        }
+        Trace::Enter(id) | Trace::Exit(id) | Trace::Close(id) => {
+            validate_span_id(*id, &mut violations);
+        }
+        Trace::Record(record_values) => {
+            validate_span_id(record_values.id, &mut violations);
+            if record_values.fields.len() > MAX_FIELDS {
+                violations.push(Violation::TooManyFields {
+                    count: record_values.fields.len(),
+                });
+            }
+        }
+        Trace::FollowsFrom(follows_from) => {
+            validate_span_id(follows_from.cause_id, &mut violations);
+            validate_span_id(follows_from.effect_id, &mut violations);
+        }
+    }
+
+    violations
+}
+
+fn validate_metadata(metadata: &Metadata, violations: &mut Vec<Violation>) {
+    if metadata.fields.len() > MAX_FIELDS {
+        violations.push(Violation::TooManyFields {
+            count: metadata.fields.len(),
+        });
+    }
+}
+
+fn validate_kind(metadata: &Metadata, expected: Kind, violations: &mut Vec<Violation>) {
+    if metadata.kind != expected {
+        violations.push(Violation::KindMismatch { expected });
+    }
+}
+
+fn validate_fields(fields: &[Field], names: &[String], violations: &mut Vec<Violation>) {
+    for field in fields {
+        if !names.contains(&field.name) {
+            violations.push(Violation::UnknownField {
+                name: field.name.clone(),
+            });
+        }
+    }
+}
+
+fn validate_parent(parent: &Parent, violations: &mut Vec<Violation>) {
+    if let Parent::Explicit(id) = parent {
+        validate_span_id(*id, violations);
+    }
+}
+
+fn validate_span_id(id: SpanId, violations: &mut Vec<Violation>) {
+    if u64::from(id) == 0 {
+        violations.push(Violation::ZeroSpanId);
+    }
+}
+
+/// Checks the structure of every record in a recording read from `reader`.
+///
+/// Each record is checked with [`validate`]. In addition, the records are checked against each
+/// other: records must only refer to spans which are open, new spans must not reuse the Id of an
+/// open span, and a callsite Id must always be recorded with the same metadata. Lines which can't
+/// be deserialized into a record are reported as violations too, as is a header with a version
+/// newer than [`FORMAT_VERSION`].
+///
+/// This allows recordings written by other implementations of the format to be checked against
+/// this one.
+///
+/// Recordings stored in a container are checked once they have been decoded, so the line
+/// indices are those of the decoded records, see [`ContainerReader`].
+///
+/// # Errors
+///
+/// Returns an error if reading from `reader` fails, including if the recording is stored in a
+/// container which is corrupt.
+///
+/// [`ContainerReader`]: struct@crate::ContainerReader
+///
+/// # Examples
+///
+/// ```
+/// use tracing_cassette::{LineViolation, SpanId, Violation};
+///
+/// let recording = concat!(
+///     r#"{"header":{"version":2}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"RegisterCallsite":{"id":4403349456,"name":"span","target":"record_spans","level":"Fatal","module_path":"record_spans","file":"tracing-rec/examples/record-spans.rs","line":8,"fields":["answer"],"kind":"Span"}}}"#,
+///     "\n",
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543420,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+///     "\n",
+/// );
+///
+/// let violations = tracing_cassette::validate_stream(recording.as_bytes()).unwrap();
+/// assert_eq!(
+///     violations,
+///     vec![
+///         LineViolation {
+///             line_index: 1,
+///             violation: Violation::InvalidLevel {
+///                 level: "Fatal".into()
+///             },
+///         },
+///         LineViolation {
+///             line_index: 2,
+///             violation: Violation::UnknownSpan { id: SpanId::from(1) },
+///         },
+///     ],
+/// );
+/// ```
+pub fn validate_stream<R: BufRead>(reader: R) -> io::Result<Vec<LineViolation>> {
+    let mut validator = StreamValidator::default();
+    let mut violations = Vec::new();
+    for (line_index, line) in ContainerReader::new(reader).lines().enumerate() {
+        let line = line?;
+        violations.extend(validator.validate_line(line_index, &line).into_iter().map(
+            |violation| LineViolation {
+                line_index,
+                violation,
+            },
+        ));
+    }
+
+    Ok(violations)
+}
+
+/// The state needed to check records against the records which came before them.
+#[derive(Default)]
+struct StreamValidator {
+    /// The metadata that each callsite Id was first recorded with.
+    callsites: HashMap<u64, Metadata>,
+    /// The field names of the callsite of each open span.
+    open_spans: HashMap<SpanId, Vec<String>>,
+    /// The spans which have been closed, these can still be used as explicit parents.
+    closed_spans: HashSet<SpanId>,
+}
+
+impl StreamValidator {
+    fn validate_line(&mut self, line_index: usize, line: &str) -> Vec<Violation> {
+        if line.trim().is_empty() {
+            return Vec::new();
+        }
+        if let Some(header) = Header::from_line(line.as_bytes()) {
+            return if line_index != 0 {
+                vec![Violation::MisplacedHeader]
+            } else if let Err(FormatVersionError::TooNew { version }) = header.check() {
+                vec![Violation::UnsupportedVersion { version }]
+            } else {
+                Vec::new()
+            };
+        }
+
+        let record = match serde_json::from_str::<TraceRecord>(line) {
+            Ok(record) => record,
+            Err(err) => return vec![invalid_record(line, &err)],
+        };
+        let mut violations = validate(&record);
+        self.validate_references(&record.trace, &mut violations);
+
+        violations
+    }
+
+    fn validate_references(&mut self, trace: &Trace, violations: &mut Vec<Violation>) {
+        match trace {
+            Trace::RegisterCallsite(metadata) => self.validate_callsite(metadata, violations),
+            Trace::Event(event) => {
+                self.validate_callsite(&event.metadata, violations);
+                self.validate_parent(&event.parent, violations);
+            }
+            Trace::NewSpan(new_span) => {
+                self.validate_callsite(&new_span.metadata, violations);
+                self.validate_parent(&new_span.parent, violations);
+                self.closed_spans.remove(&new_span.id);
+                let previous = self
+                    .open_spans
+                    .insert(new_span.id, new_span.metadata.fields.clone());
+                if previous.is_some() {
+                    violations.push(Violation::DuplicateSpan { id: new_span.id });
+                }
+            }
+            Trace::Enter(id) | Trace::Exit(id) => self.validate_open_span(*id, violations),
+            Trace::Close(id) => {
+                if self.open_spans.remove(id).is_some() {
+                    self.closed_spans.insert(*id);
+                } else {
+                    violations.push(Violation::UnknownSpan { id: *id });
+                }
+            }
+            Trace::Record(record_values) => match self.open_spans.get(&record_values.id) {
+                Some(names) => validate_fields(&record_values.fields, names, violations),
+                None => violations.push(Violation::UnknownSpan {
+                    id: record_values.id,
+                }),
+            },
+            Trace::FollowsFrom(follows_from) => {
+                self.validate_open_span(follows_from.cause_id, violations);
+                self.validate_open_span(follows_from.effect_id, violations);
+            }
+        }
+    }
+
+    fn validate_callsite(&mut self, metadata: &Metadata, violations: &mut Vec<Violation>) {
+        let first = self
+            .callsites
+            .entry(metadata.id)
+            .or_insert_with(|| metadata.clone());
+        if first != metadata {
+            violations.push(Violation::CallsiteMismatch { id: metadata.id });
+        }
+    }
+
+    fn validate_parent(&self, parent: &Parent, violations: &mut Vec<Violation>) {
+        if let Parent::Explicit(id) = parent {
+            if !self.closed_spans.contains(id) {
+                self.validate_open_span(*id, violations);
+            }
+        }
+    }
+
+    fn validate_open_span(&self, id: SpanId, violations: &mut Vec<Violation>) {
+        if !self.open_spans.contains_key(&id) {
+            violations.push(Violation::UnknownSpan { id });
+        }
+    }
+}
+
+/// Describes why `line` couldn't be deserialized into a record.
+///
+/// Levels are checked separately, so that a level which doesn't exist is reported as such
+/// instead of as an unknown variant somewhere in the record.
+fn invalid_record(line: &str, err: &serde_json::Error) -> Violation {
+    let value: Option<serde_json::Value> = serde_json::from_str(line).ok();
+    let level = value.as_ref().and_then(|value| {
+        [
+            "/trace/RegisterCallsite/level",
+            "/trace/Event/metadata/level",
+            "/trace/NewSpan/metadata/level",
+        ]
+        .iter()
+        .find_map(|pointer| value.pointer(pointer))
+    });
+    match level {
+        Some(level) if !level.as_str().is_some_and(|level| LEVELS.contains(&level)) => {
+            Violation::InvalidLevel {
+                level: level
+                    .as_str()
+                    .map_or_else(|| level.to_string(), str::to_owned),
+            }
+        }
+        _ => Violation::InvalidRecord {
+            message: err.to_string(),
+        },
+    }
+}
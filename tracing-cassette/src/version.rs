@@ -0,0 +1,233 @@
+use std::{
+    collections::BTreeMap,
+    error, fmt,
+    io::{self, Write},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::TraceRecord;
+
+/// The version of the recording format which is written by this version of the crate.
+///
+/// Recordings written in any earlier version can still be read, their records are migrated to
+/// the current version with [`migrate`].
+pub const FORMAT_VERSION: u32 = 3;
+
+/// The version of recordings which don't start with a [`Header`].
+///
+/// The header was added in version 2, so recordings without one were written in version 1.
+pub const UNVERSIONED_FORMAT_VERSION: u32 = 1;
+
+/// The first line of a recording, which describes the recording as a whole.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_cassette::{Header, FORMAT_VERSION};
+///
+/// let line = Header::new().to_line();
+/// assert_eq!(line, format!(r#"{{"header":{{"version":{FORMAT_VERSION}}}}}"#));
+/// assert_eq!(Header::from_line(line.as_bytes()), Some(Header::new()));
+///
+/// let header = Header::new().with_metadata("build", "4f2a9c1");
+/// let read = Header::from_line(header.to_line().as_bytes()).unwrap();
+/// assert_eq!(read.metadata["build"], "4f2a9c1");
+///
+/// let record = r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#;
+/// assert_eq!(Header::from_line(record.as_bytes()), None);
+/// ```
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Header {
+    /// The version of the format the recording was written in.
+    pub version: u32,
+    /// Key/value pairs which describe the recording, set by the application which recorded it.
+    ///
+    /// This is for anything which helps to make sense of the recording later, such as the
+    /// commit the application was built from, the environment it was deployed to, or the name
+    /// of the test scenario which was recorded. Readers which don't know about the metadata
+    /// ignore it, so it doesn't change the version of the format.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// The header is written as an object with a single `header` key, which no record has.
+#[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub(crate) enum HeaderLine {
+    #[serde(rename = "header")]
+    Header(Header),
+}
+
+impl Header {
+    /// Creates a header for a recording written in the current [`FORMAT_VERSION`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the key/value pair `key` and `value` to the [`metadata`], replacing any earlier value
+    /// for `key`.
+    ///
+    /// [`metadata`]: structfield@Self::metadata
+    #[must_use]
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// Serializes the header into a line of a recording, without the trailing newline.
+    #[must_use]
+    pub fn to_line(&self) -> String {
+        serde_json::to_string(&HeaderLine::Header(self.clone()))
+            .expect("serializing a header can't fail")
+    }
+
+    /// Parses a line of a recording as a header.
+    ///
+    /// Returns `None` if the line isn't a header, in which case it is expected to be a record.
+    #[must_use]
+    pub fn from_line(line: &[u8]) -> Option<Self> {
+        match serde_json::from_slice(line) {
+            Ok(HeaderLine::Header(header)) => Some(header),
+            Err(_) => None,
+        }
+    }
+
+    /// Checks that the recording can be read by this version of the crate.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the recording was written in a newer version of the format.
+    pub fn check(&self) -> Result<(), FormatVersionError> {
+        check_version(self.version)
+    }
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A recording was written in a version of the format which can't be read.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum FormatVersionError {
+    /// The recording was written in a newer version of the format than this version of the
+    /// crate knows about.
+    TooNew {
+        /// The version the recording was written in.
+        version: u32,
+    },
+}
+
+impl fmt::Display for FormatVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooNew { version } => write!(
+                f,
+                "recording too new: it was written in format version {version}, but the newest \
+                supported version is {FORMAT_VERSION}"
+            ),
+        }
+    }
+}
+
+impl error::Error for FormatVersionError {}
+
+/// Writes `records` as a recording in the current [`FORMAT_VERSION`], starting with the header.
+///
+/// # Errors
+///
+/// Returns an error if writing to `writer` fails.
+pub fn write_recording<W: Write>(mut writer: W, records: &[TraceRecord]) -> io::Result<()> {
+    writeln!(writer, "{}", Header::new().to_line())?;
+    for record in records {
+        serde_json::to_writer(&mut writer, record)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+/// A change to the records of the format from one version to the next.
+struct Migration {
+    /// The version which records are migrated from.
+    from: u32,
+    /// Rewrites a record into the next version, or `None` if the records didn't change.
+    migrate_record: Option<fn(&mut serde_json::Value)>,
+}
+
+/// The migrations between consecutive versions, in order.
+const MIGRATIONS: &[Migration] = &[
+    // Version 2 added the header, the records themselves are unchanged.
+    Migration {
+        from: 1,
+        migrate_record: None,
+    },
+    // Version 3 added structured `Json` field values, earlier records are still valid.
+    Migration {
+        from: 2,
+        migrate_record: None,
+    },
+];
+
+/// Checks that recordings written in `version` can be read by this version of the crate.
+///
+/// # Errors
+///
+/// Returns an error if `version` is newer than [`FORMAT_VERSION`].
+pub fn check_version(version: u32) -> Result<(), FormatVersionError> {
+    if version > FORMAT_VERSION {
+        Err(FormatVersionError::TooNew { version })
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns whether records written in `version` need to be migrated before they can be read.
+///
+/// Records which don't need to be migrated can be deserialized directly, which avoids parsing
+/// them into a [`serde_json::Value`] first.
+#[must_use]
+pub fn needs_migration(version: u32) -> bool {
+    MIGRATIONS
+        .iter()
+        .any(|migration| migration.from >= version && migration.migrate_record.is_some())
+}
+
+/// Migrates a record written in `version` to the current [`FORMAT_VERSION`].
+///
+/// # Errors
+///
+/// Returns an error if `version` is newer than [`FORMAT_VERSION`].
+///
+/// # Examples
+///
+/// ```
+/// use tracing_cassette::TraceRecord;
+///
+/// let line = r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#;
+/// let mut value: serde_json::Value = serde_json::from_str(line).unwrap();
+/// tracing_cassette::migrate(1, &mut value).unwrap();
+/// let _record: TraceRecord = serde_json::from_value(value).unwrap();
+///
+/// assert!(tracing_cassette::migrate(4, &mut serde_json::Value::Null).is_err());
+/// ```
+pub fn migrate(version: u32, record: &mut serde_json::Value) -> Result<(), FormatVersionError> {
+    check_version(version)?;
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| migration.from >= version)
+    {
+        if let Some(migrate_record) = migration.migrate_record {
+            migrate_record(record);
+        }
+    }
+
+    Ok(())
+}
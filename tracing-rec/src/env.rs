@@ -0,0 +1,211 @@
+use std::{
+    error, fmt,
+    fs::File,
+    io::{self, stdout, Write},
+};
+
+use tracing_cassette::{Compression, ContainerWriter};
+use tracing_subscriber::filter::{ParseError, Targets};
+
+use crate::{rec_container_layer, rec_file_layer, rec_layer, Rec};
+
+/// The environment variable which sets the path that the recording is written to.
+const PATH_VAR: &str = "TRACING_REC_PATH";
+/// The environment variable which sets the format of the recording.
+const FORMAT_VAR: &str = "TRACING_REC_FORMAT";
+/// The environment variable which sets the filter of spans and events to record.
+const FILTER_VAR: &str = "TRACING_REC_FILTER";
+/// The environment variable which sets the fraction of traces to record.
+const SAMPLE_VAR: &str = "TRACING_REC_SAMPLE";
+
+/// An environment variable read by [`rec_layer_from_env`] has a value which isn't valid, or the
+/// recording couldn't be created.
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum RecEnvError {
+    /// The file at `TRACING_REC_PATH` couldn't be created.
+    CannotCreateFile {
+        /// The value of the variable.
+        path: String,
+        /// Why the file couldn't be created.
+        inner: io::Error,
+    },
+    /// `TRACING_REC_FORMAT` isn't one of the recording formats.
+    InvalidFormat {
+        /// The value of the variable.
+        value: String,
+    },
+    /// `TRACING_REC_FILTER` isn't a valid filter.
+    InvalidFilter {
+        /// The value of the variable.
+        value: String,
+        /// Why the filter couldn't be parsed.
+        inner: ParseError,
+    },
+    /// `TRACING_REC_SAMPLE` isn't a number greater than zero and at most one.
+    InvalidSampleRate {
+        /// The value of the variable.
+        value: String,
+    },
+}
+
+impl fmt::Display for RecEnvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CannotCreateFile { path, inner } => {
+                write!(
+                    f,
+                    "cannot create the recording {path:?} ({PATH_VAR}): {inner}"
+                )
+            }
+            Self::InvalidFormat { value } => write!(
+                f,
+                "{FORMAT_VAR} must be one of json or container, but is {value:?}"
+            ),
+            Self::InvalidFilter { value, inner } => {
+                write!(f, "{FILTER_VAR} is not a valid filter ({inner}): {value:?}")
+            }
+            Self::InvalidSampleRate { value } => write!(
+                f,
+                "{SAMPLE_VAR} must be a number greater than zero and at most one, but is {value:?}"
+            ),
+        }
+    }
+}
+
+impl error::Error for RecEnvError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::CannotCreateFile { inner, .. } => Some(inner),
+            Self::InvalidFilter { inner, .. } => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+/// The format that a recording is written in.
+enum Format {
+    Json,
+    Container,
+}
+
+/// Returns a recording layer configured from environment variables, or `None` if recording
+/// isn't switched on.
+///
+/// This allows recording to be switched on in a deployed binary without changing its code.
+/// Recording is switched on by setting `TRACING_REC_PATH`, the other variables are optional:
+///
+/// - `TRACING_REC_PATH`: the file to write the recording to, which is created or truncated. The
+///   recording is written to stdout if this is `-`.
+/// - `TRACING_REC_FORMAT`: `json` (the default) to write a recording of JSON lines, or
+///   `container` to write a compressed container.
+/// - `TRACING_REC_FILTER`: only record the spans and events that this filter enables, in the
+///   same syntax as `RUST_LOG`, such as `my_crate=debug,warn`. See [`Rec::with_filter`].
+/// - `TRACING_REC_SAMPLE`: only record this fraction of the traces, such as `0.1`. See
+///   [`Rec::with_sample_rate`].
+///
+/// Variables which are set to an empty value are treated as unset.
+///
+/// A container is only complete once the layer has been dropped, which is when the subscriber
+/// it's part of is dropped. A global default subscriber is never dropped, so the `json` format
+/// should be used with one.
+///
+/// # Errors
+///
+/// Returns an error if one of the variables has a value which isn't valid, or if the recording
+/// file can't be created.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_rec::recording::{RecordReader, Trace};
+/// use tracing_subscriber::layer::SubscriberExt;
+///
+/// let path = std::env::temp_dir().join(format!("rec-from-env-{}.tracing", std::process::id()));
+/// std::env::set_var("TRACING_REC_PATH", &path);
+/// std::env::set_var("TRACING_REC_FILTER", "warn");
+///
+/// let layer = tracing_rec::rec_layer_from_env()
+///     .expect("the environment is valid")
+///     .expect("recording is switched on");
+/// let subscriber = tracing_subscriber::registry().with(layer);
+/// tracing::subscriber::with_default(subscriber, || {
+///     tracing::info!("not recorded");
+///     tracing::warn!("recorded");
+/// });
+///
+/// let recording = std::fs::read(&path).unwrap();
+/// let events = RecordReader::new(&recording[..])
+///     .filter_map(Result::ok)
+///     .filter(|record| matches!(record.trace, Trace::Event(_)))
+///     .count();
+/// assert_eq!(events, 1);
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub fn rec_layer_from_env() -> Result<Option<Rec>, RecEnvError> {
+    // Variables which are set to an empty value are treated as unset.
+    let var = |name| {
+        std::env::var(name)
+            .ok()
+            .filter(|value: &String| !value.trim().is_empty())
+    };
+
+    let Some(path) = var(PATH_VAR) else {
+        return Ok(None);
+    };
+
+    let format = match var(FORMAT_VAR) {
+        None => Format::Json,
+        Some(value) => match value.trim().to_ascii_lowercase().as_str() {
+            "json" => Format::Json,
+            "container" => Format::Container,
+            _ => return Err(RecEnvError::InvalidFormat { value }),
+        },
+    };
+
+    let filter = match var(FILTER_VAR) {
+        None => None,
+        Some(value) => match value.parse::<Targets>() {
+            Ok(filter) => Some(filter),
+            Err(inner) => return Err(RecEnvError::InvalidFilter { value, inner }),
+        },
+    };
+
+    let sample_rate = match var(SAMPLE_VAR) {
+        None => None,
+        Some(value) => Some(
+            value
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|rate| *rate > 0.0 && *rate <= 1.0)
+                .ok_or(RecEnvError::InvalidSampleRate { value })?,
+        ),
+    };
+
+    let mut rec = match (format, path.as_str()) {
+        (Format::Json, "-") => rec_layer(),
+        (Format::Json, _) => File::create(&path)
+            .map(rec_file_layer)
+            .map_err(|inner| RecEnvError::CannotCreateFile { path, inner })?,
+        (Format::Container, _) => {
+            let writer: Box<dyn Write + Send> = if path == "-" {
+                Box::new(stdout())
+            } else {
+                let file = File::create(&path)
+                    .map_err(|inner| RecEnvError::CannotCreateFile { path, inner })?;
+                Box::new(io::BufWriter::new(file))
+            };
+            rec_container_layer(ContainerWriter::new(writer).with_compression(Compression::Deflate))
+        }
+    };
+
+    if let Some(filter) = filter {
+        rec = rec.with_filter(filter);
+    }
+    if let Some(sample_rate) = sample_rate {
+        rec = rec.with_sample_rate(sample_rate);
+    }
+
+    Ok(Some(rec))
+}
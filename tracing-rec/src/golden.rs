@@ -0,0 +1,133 @@
+//! Snapshot testing of traces against golden recordings.
+
+use std::{env, ffi::OsStr, fmt::Write as _, fs, path::Path};
+
+use tracing_cassette::{RecordReader, TraceRecord};
+
+use crate::record_scope;
+
+/// The environment variable which makes [`assert_traces_match`] write the golden recordings,
+/// instead of checking against them.
+pub const BLESS_ENV_VAR: &str = "TRACING_REC_BLESS";
+
+/// Asserts that the traces of the code in a closure match a golden recording, a snapshot of its
+/// traces which is checked in next to the tests.
+///
+/// This is [`assert_traces_match`], with a relative path to the golden recording resolved from the
+/// directory of the crate which is being tested, rather than the current directory.
+///
+/// # Examples
+///
+/// ```
+/// # let dir = std::env::temp_dir().join(format!("tracing-rec-golden-{}", std::process::id()));
+/// # let fixture = dir.join("handle.tracing");
+/// # let fixture = fixture.to_str().unwrap();
+/// fn handle(request: u64) {
+///     let span = tracing::info_span!("handle", request);
+///     let _guard = span.enter();
+///     tracing::info!(response = request * 2, "handled");
+/// }
+///
+/// // The first run writes the golden recording, as with `TRACING_REC_BLESS=1 cargo test`.
+/// std::env::set_var("TRACING_REC_BLESS", "1");
+/// tracing_rec::assert_traces_match!(fixture, || handle(21));
+/// std::env::remove_var("TRACING_REC_BLESS");
+///
+/// // Later runs check the traces against it.
+/// tracing_rec::assert_traces_match!(fixture, || handle(21));
+///
+/// // Different traces fail the test.
+/// let result = std::panic::catch_unwind(|| {
+///     tracing_rec::assert_traces_match!(fixture, || handle(20));
+/// });
+/// assert!(result.is_err());
+/// # std::fs::remove_dir_all(dir).unwrap();
+/// ```
+#[macro_export]
+macro_rules! assert_traces_match {
+    ($golden:expr, $f:expr $(,)?) => {
+        $crate::assert_traces_match(
+            ::std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join($golden),
+            $f,
+        )
+    };
+}
+
+/// Asserts that the traces of the code in `f` match the golden recording at `path`.
+///
+/// The closure is run with a subscriber which records it into memory, which is then compared
+/// with the golden recording with [`tracing_cassette::structurally_equal`], and then, if they
+/// aren't the same, with [`tracing_cassette::diff`]. The comparison ignores the
+/// timestamps, the threads, and the Ids assigned when the traces were recorded, so only the
+/// structure of the traces is checked: the callsites, including their locations, the tree of
+/// spans and events, their levels, and their field values.
+///
+/// When the environment variable `TRACING_REC_BLESS` is set, to anything other than `0`, the
+/// recording is written to `path` instead, creating the directories it is in if they don't
+/// exist. This is how golden recordings are created, and updated when the traces change on
+/// purpose.
+///
+/// The subscriber is only the default for the thread that the closure runs on, spans and events
+/// on other threads aren't recorded.
+///
+/// # Panics
+///
+/// Panics if the traces don't match the golden recording, listing the differences, or if the
+/// golden recording doesn't exist or can't be read or written.
+pub fn assert_traces_match<P, F>(path: P, f: F)
+where
+    P: AsRef<Path>,
+    F: FnOnce(),
+{
+    let path = path.as_ref();
+    let recording = record_scope(f);
+
+    if is_blessing() {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .unwrap_or_else(|err| panic!("failed to create {}: {err}", dir.display()));
+        }
+        fs::write(path, recording.to_bytes())
+            .unwrap_or_else(|err| panic!("failed to write {}: {err}", path.display()));
+        return;
+    }
+
+    let golden = match fs::read(path) {
+        Ok(golden) => golden,
+        Err(err) => panic!(
+            "failed to read the golden recording {}: {err}\n\
+             run the test with {BLESS_ENV_VAR}=1 to write it",
+            path.display(),
+        ),
+    };
+    let golden: Vec<TraceRecord> = RecordReader::new(golden.as_slice())
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|err| panic!("failed to read {}: {err}", path.display()));
+
+    let records = recording.records();
+    // Recordings which are structurally the same trace can't differ, this avoids matching up
+    // the traces of the two recordings in the common case.
+    if tracing_cassette::structurally_equal(&golden, &records) {
+        return;
+    }
+    let differences = tracing_cassette::diff(&golden, &records);
+    if !differences.is_empty() {
+        let mut message = format!(
+            "the traces don't match the golden recording {}:\n",
+            path.display()
+        );
+        for difference in &differences {
+            let _ = writeln!(message, "  {difference}");
+        }
+        let _ = write!(
+            message,
+            "run the test with {BLESS_ENV_VAR}=1 to update the golden recording"
+        );
+        panic!("{message}");
+    }
+}
+
+/// Returns whether golden recordings are written, rather than checked.
+fn is_blessing() -> bool {
+    env::var_os(BLESS_ENV_VAR).is_some_and(|value| !value.is_empty() && value != OsStr::new("0"))
+}
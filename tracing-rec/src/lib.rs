@@ -1,361 +1,638 @@
 use std::{
-    io::{stdout, Stdout, Write},
+    backtrace::Backtrace,
+    fs::File,
+    io::{stdout, Write},
+    sync::{Arc, Mutex},
     time::{SystemTime, UNIX_EPOCH},
 };
 
-use serde::Serialize;
 use tracing::{field::Visit, span, subscriber::Interest, Subscriber};
+use tracing_cassette::{
+    ContainerWriter, EventRef, FieldRef, FieldValueRef, FollowsFrom, MetadataRef, NewSpanRef,
+    Parent, RecordMetaRef, RecordReader, RecordValuesRef, SpanId, TraceContext, TraceRecord,
+    TraceRecordRef, TraceRef,
+};
+use tracing_subscriber::{filter::Targets, layer::SubscriberExt, registry::LookupSpan};
+
+pub use crate::env::{rec_layer_from_env, RecEnvError};
+pub use crate::golden::{assert_traces_match, BLESS_ENV_VAR};
+pub use crate::noop::NoopRec;
+pub use crate::sink::RecSink;
+/// The records which are written to a recording, see [`tracing_cassette`].
+pub use tracing_cassette as recording;
+
+use crate::{
+    selection::Selection,
+    sink::{RecWriter, Sink, SpanIds},
+};
+
+mod env;
+mod golden;
+mod noop;
+#[cfg(feature = "opentelemetry")]
+mod otel;
+mod selection;
+mod sink;
+#[cfg(all(tracing_unstable, feature = "valuable"))]
+mod structured;
+
+/// The name of the field which the backtrace of an error event is recorded in, see
+/// [`Rec::with_backtraces`].
+pub const BACKTRACE_FIELD: &str = "backtrace";
 
 pub struct Rec {
-    writer: Stdout,
+    sink: Arc<Sink>,
+    /// The span Ids given to the spans this layer records, if it is one of the layers of a
+    /// [`RecSink`].
+    span_ids: Option<SpanIds>,
+    /// Which spans and events are recorded, if not all of them.
+    selection: Option<Selection>,
+    /// Whether a backtrace is recorded with each error event.
+    backtraces: bool,
+    /// Whether the final values of the fields of spans are recorded when they close.
+    final_fields: bool,
+    #[cfg(feature = "opentelemetry")]
+    otel: otel::OtelContext,
 }
 
 #[must_use]
 pub fn rec_layer() -> Rec {
-    Rec { writer: stdout() }
-}
-
-#[derive(Debug, Serialize)]
-struct TraceRecord {
-    meta: RecordMeta,
-    trace: Trace,
-}
-
-impl TraceRecord {
-    fn implicit(trace: Trace) -> Self {
-        Self {
-            meta: RecordMeta::new(),
-            trace,
-        }
+    Rec::new(RecWriter::Stdout(stdout()))
+}
+
+/// Returns a layer which records into `file`, as JSON lines.
+fn rec_file_layer(file: File) -> Rec {
+    Rec::new(RecWriter::File(Mutex::new(file)))
+}
+
+/// Returns a layer which records into a container written to `writer`.
+fn rec_container_layer(writer: ContainerWriter<Box<dyn Write + Send>>) -> Rec {
+    Rec::new(RecWriter::Container(Mutex::new(Some(writer))))
+}
+
+/// Returns a layer which records into memory, together with the recording it writes to.
+///
+/// This is useful in tests, where the recording can be checked, or replayed, once the code under
+/// test has run.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_rec::recording::Trace;
+/// use tracing_subscriber::layer::SubscriberExt;
+///
+/// let (layer, recording) = tracing_rec::rec_memory_layer();
+/// let subscriber = tracing_subscriber::registry().with(layer);
+/// tracing::subscriber::with_default(subscriber, || {
+///     tracing::info!(answer = 42, "I am an info event!");
+/// });
+///
+/// let events = recording
+///     .records()
+///     .into_iter()
+///     .filter(|record| matches!(record.trace, Trace::Event(_)))
+///     .count();
+/// assert_eq!(events, 1);
+/// ```
+#[must_use]
+pub fn rec_memory_layer() -> (Rec, MemoryRecording) {
+    let recording = MemoryRecording::default();
+    let rec = Rec::new(RecWriter::Memory(recording.clone()));
+    (rec, recording)
+}
+
+/// Records the traces of the code in `f` into memory, and returns the recording.
+///
+/// The closure is run with a subscriber which only has the layer from [`rec_memory_layer`] as
+/// the default for the current thread. Spans and events on other threads aren't recorded, unless
+/// `f` sets the default on the threads that it spawns too. This makes it simple to create a
+/// recording as a fixture in a test, the recording can be written to a file with
+/// [`MemoryRecording::to_bytes`].
+///
+/// # Examples
+///
+/// ```
+/// use tracing_rec::recording::Trace;
+///
+/// let recording = tracing_rec::record_scope(|| {
+///     let span = tracing::info_span!("request", user_id = 7);
+///     span.in_scope(|| tracing::warn!(retries = 2, "slow response"));
+/// });
+///
+/// let spans = recording
+///     .records()
+///     .into_iter()
+///     .filter(|record| matches!(record.trace, Trace::NewSpan(_)))
+///     .count();
+/// assert_eq!(spans, 1);
+/// ```
+#[must_use]
+pub fn record_scope<F: FnOnce()>(f: F) -> MemoryRecording {
+    let (layer, recording) = rec_memory_layer();
+    tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), f);
+    recording
+}
+
+/// A recording held in memory, written by the layer from [`rec_memory_layer`].
+///
+/// Clones share the same recording, so the recording can be read while the layer is still
+/// writing to it.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryRecording {
+    buffer: Arc<Mutex<Vec<u8>>>,
+}
+
+impl MemoryRecording {
+    /// Returns the recording written so far, starting with the header.
+    ///
+    /// The header is written together with the first record, or when the layer is dropped if
+    /// nothing was recorded.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.buffer.lock().expect("lock poisoned").clone()
     }
-}
 
-#[derive(Debug, Serialize)]
-struct RecordMeta {
-    timestamp_s: u64,
-    timestamp_subsec_us: u32,
-    thread_id: String,
-    thread_name: Option<String>,
-}
-
-impl RecordMeta {
-    fn new() -> Self {
-        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-        let thread = std::thread::current();
-
-        Self {
-            timestamp_s: timestamp.as_secs(),
-            timestamp_subsec_us: timestamp.subsec_micros(),
-            thread_id: format!("{:?}", thread.id()),
-            thread_name: thread.name().map(Into::into),
-        }
+    /// Returns the records written so far.
+    #[must_use]
+    pub fn records(&self) -> Vec<TraceRecord> {
+        RecordReader::new(self.to_bytes().as_slice())
+            .collect::<Result<_, _>>()
+            .expect("recorded records are always valid")
     }
-}
 
-#[derive(Debug, Serialize)]
-enum Trace {
-    RegisterCallsite(Metadata),
-    Event(Event),
-    NewSpan(NewSpan),
-    Enter(SpanId),
-    Exit(SpanId),
-    Close(SpanId),
-    Record(RecordValues),
-    FollowsFrom(FollowsFrom),
-}
-
-#[derive(Debug, Serialize)]
-enum Level {
-    Trace,
-    Debug,
-    Info,
-    Warn,
-    Error,
-}
-
-impl From<&tracing::Level> for Level {
-    fn from(value: &tracing::Level) -> Self {
-        match *value {
-            tracing::Level::TRACE => Level::Trace,
-            tracing::Level::DEBUG => Level::Debug,
-            tracing::Level::INFO => Level::Info,
-            tracing::Level::WARN => Level::Warn,
-            tracing::Level::ERROR => Level::Error,
-        }
+    fn write_line(&self, line: &[u8]) {
+        let mut buffer = self.buffer.lock().expect("lock poisoned");
+        buffer.extend_from_slice(line);
+        buffer.push(b'\n');
     }
 }
 
-#[derive(Debug, Serialize)]
-enum Kind {
-    Span,
-    Event,
-}
-
-impl From<&'static tracing::Metadata<'static>> for Kind {
-    fn from(value: &'static tracing::Metadata<'static>) -> Self {
-        if value.is_event() {
-            Self::Event
-        } else {
-            debug_assert!(
-                value.is_span(),
-                "either is_event() or is_span() should be true",
-            );
-            Self::Span
-        }
+fn implicit_record(trace: TraceRef<'_>) -> TraceRecordRef<'_> {
+    TraceRecordRef {
+        meta: record_meta(),
+        trace,
+        final_fields: None,
+        sequence: None,
     }
 }
 
-#[derive(Debug, Serialize)]
-struct Metadata {
-    id: u64,
-    name: &'static str,
-    target: &'static str,
-    level: Level,
-    module_path: Option<&'static str>,
-    file: Option<&'static str>,
-    line: Option<u32>,
-    fields: Vec<&'static str>,
-    kind: Kind,
-}
+fn record_meta() -> RecordMetaRef<'static> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let thread = std::thread::current();
 
-impl From<&'static tracing::Metadata<'static>> for Metadata {
-    fn from(value: &'static tracing::Metadata<'static>) -> Self {
-        Self {
-            id: std::ptr::from_ref(value) as u64,
-            name: value.name(),
-            target: value.target(),
-            level: value.level().into(),
-            module_path: value.module_path(),
-            file: value.file(),
-            line: value.line(),
-            fields: value.fields().iter().map(|f| f.name()).collect(),
-            kind: Kind::from(value),
-        }
+    RecordMetaRef {
+        timestamp_s: timestamp.as_secs(),
+        timestamp_subsec_us: timestamp.subsec_micros(),
+        thread_id: format!("{:?}", thread.id()).into(),
+        thread_name: thread.name().map(|name| name.to_owned().into()),
     }
 }
 
-#[derive(Debug, Serialize)]
-enum Parent {
-    /// The new span will be a root span.
-    Root,
-    /// The new span will be rooted in the current span.
-    Current,
-    /// The new span has an explicitly-specified parent.
-    Explicit(u64),
+struct Fields {
+    inner: Vec<FieldRef<'static>>,
 }
 
-impl From<&tracing::Event<'_>> for Parent {
-    fn from(value: &tracing::Event<'_>) -> Self {
-        if value.is_root() {
-            Self::Root
-        } else if value.is_contextual() {
-            Self::Current
-        } else {
-            Self::Explicit(
-                value
-                    .parent()
-                    .expect("a span that isn't root or contextual should have an explicit Id")
-                    .into_u64(),
-            )
-        }
+impl Fields {
+    fn new() -> Self {
+        Self { inner: Vec::new() }
     }
-}
 
-impl From<&span::Attributes<'_>> for Parent {
-    fn from(value: &span::Attributes<'_>) -> Self {
-        if value.is_root() {
-            Self::Root
-        } else if value.is_contextual() {
-            Self::Current
-        } else {
-            Self::Explicit(
-                value
-                    .parent()
-                    .expect("a span that isn't root or contextual should have an explicit Id")
-                    .into_u64(),
-            )
-        }
+    fn push(&mut self, field: &tracing::field::Field, value: FieldValueRef<'static>) {
+        self.inner.push(FieldRef {
+            name: field.name().into(),
+            value,
+        });
     }
 }
 
-#[derive(Debug, Serialize)]
-struct Fields {
-    inner: Vec<Field>,
-}
-
-impl Fields {
-    fn new() -> Self {
-        Self { inner: Vec::new() }
+/// The values of the fields of a span, with the values recorded since it was created merged in,
+/// which is kept in the extensions of the span, see [`Rec::with_final_fields`].
+struct FinalFields(Vec<FieldRef<'static>>);
+
+impl FinalFields {
+    /// Replaces the values of the fields in `recorded`, adding the fields which had no value.
+    fn merge(&mut self, recorded: &[FieldRef<'static>]) {
+        for field in recorded {
+            match self
+                .0
+                .iter_mut()
+                .find(|final_field| final_field.name == field.name)
+            {
+                Some(final_field) => final_field.value = field.value.clone(),
+                None => self.0.push(field.clone()),
+            }
+        }
     }
 }
 
 impl Visit for Fields {
     fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
-        self.inner.push(Field::new(
-            field.name(),
-            FieldValue::Debug(format!("{value:?}")),
-        ));
+        self.push(field, FieldValueRef::Debug(format!("{value:?}").into()));
     }
 
     fn record_f64(&mut self, field: &tracing::field::Field, value: f64) {
-        self.inner
-            .push(Field::new(field.name(), FieldValue::F64(value)));
+        self.push(field, FieldValueRef::F64(value));
     }
 
     fn record_i64(&mut self, field: &tracing::field::Field, value: i64) {
-        self.inner
-            .push(Field::new(field.name(), FieldValue::I64(value)));
+        self.push(field, FieldValueRef::I64(value));
     }
 
     fn record_u64(&mut self, field: &tracing::field::Field, value: u64) {
-        self.inner
-            .push(Field::new(field.name(), FieldValue::U64(value)));
+        self.push(field, FieldValueRef::U64(value));
     }
 
     fn record_i128(&mut self, field: &tracing::field::Field, value: i128) {
-        self.inner
-            .push(Field::new(field.name(), FieldValue::I128(value)));
+        self.push(field, FieldValueRef::I128(value));
     }
 
     fn record_u128(&mut self, field: &tracing::field::Field, value: u128) {
-        self.inner
-            .push(Field::new(field.name(), FieldValue::U128(value)));
+        self.push(field, FieldValueRef::U128(value));
     }
 
     fn record_bool(&mut self, field: &tracing::field::Field, value: bool) {
-        self.inner
-            .push(Field::new(field.name(), FieldValue::Bool(value)));
+        self.push(field, FieldValueRef::Bool(value));
     }
 
     fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
-        self.inner
-            .push(Field::new(field.name(), FieldValue::Str(value.into())));
+        self.push(field, FieldValueRef::Str(value.to_owned().into()));
     }
-}
 
-#[derive(Debug, Serialize)]
-struct Field {
-    name: &'static str,
-    value: FieldValue,
+    #[cfg(all(tracing_unstable, feature = "valuable"))]
+    fn record_value(&mut self, field: &tracing::field::Field, value: valuable::Value<'_>) {
+        self.push(field, structured::field_value(value));
+    }
 }
 
-impl Field {
-    fn new(name: &'static str, value: FieldValue) -> Self {
-        Self { name, value }
+fn event(value: &tracing::Event<'_>) -> EventRef<'static> {
+    let mut fields = Fields::new();
+    value.record(&mut fields);
+
+    EventRef {
+        fields: fields.inner,
+        metadata: value.metadata().into(),
+        parent: Parent::from(value),
     }
 }
 
-#[derive(Debug, Serialize)]
-enum FieldValue {
-    Debug(String),
-    F64(f64),
-    I64(i64),
-    U64(u64),
-    I128(i128),
-    U128(u128),
-    Bool(bool),
-    Str(String),
-    // TODO(hds): add variants for Value and Error
+fn new_span(
+    attrs: &span::Attributes<'_>,
+    id: SpanId,
+    trace_context: Option<TraceContext>,
+) -> NewSpanRef<'static> {
+    let mut fields = Fields::new();
+    attrs.record(&mut fields);
+
+    NewSpanRef {
+        id,
+        fields: fields.inner,
+        metadata: attrs.metadata().into(),
+        parent: Parent::from(attrs),
+        trace_context,
+    }
 }
 
-#[derive(Debug, Serialize)]
-struct Event {
-    fields: Vec<Field>,
-    metadata: Metadata,
-    parent: Parent,
+fn record_values(id: SpanId, values: &span::Record<'_>) -> RecordValuesRef<'static> {
+    let mut fields = Fields::new();
+    values.record(&mut fields);
+
+    RecordValuesRef {
+        id,
+        fields: fields.inner,
+    }
 }
 
-impl From<&tracing::Event<'_>> for Event {
-    fn from(value: &tracing::Event<'_>) -> Self {
-        let mut fields = Fields::new();
-        value.record(&mut fields);
+impl Rec {
+    fn new(writer: RecWriter) -> Self {
+        Self::with_sink(Arc::new(Sink::new(writer)), None)
+    }
 
+    fn with_sink(sink: Arc<Sink>, span_ids: Option<SpanIds>) -> Self {
         Self {
-            fields: fields.inner,
-            metadata: value.metadata().into(),
-            parent: Parent::from(value),
+            sink,
+            span_ids,
+            selection: None,
+            backtraces: false,
+            final_fields: false,
+            #[cfg(feature = "opentelemetry")]
+            otel: otel::OtelContext::default(),
         }
     }
-}
 
-#[derive(Debug, Serialize)]
-struct NewSpan {
-    id: SpanId,
-    fields: Vec<Field>,
-    metadata: Metadata,
-    parent: Parent,
-}
+    /// Records only the spans and events which `filter` enables, by their target and level.
+    ///
+    /// Unlike filtering the whole subscriber, this leaves the spans and events which aren't
+    /// recorded for the other layers. The spans and events within a span which isn't recorded
+    /// are still recorded if the filter enables them, and are replayed without the span.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tracing_rec::recording::Trace;
+    /// use tracing_subscriber::{filter::Targets, layer::SubscriberExt};
+    ///
+    /// let (layer, recording) = tracing_rec::rec_memory_layer();
+    /// let filter: Targets = "warn".parse().unwrap();
+    /// let subscriber = tracing_subscriber::registry().with(layer.with_filter(filter));
+    /// tracing::subscriber::with_default(subscriber, || {
+    ///     tracing::info!("not recorded");
+    ///     tracing::warn!("recorded");
+    /// });
+    ///
+    /// let events = recording
+    ///     .records()
+    ///     .into_iter()
+    ///     .filter(|record| matches!(record.trace, Trace::Event(_)))
+    ///     .count();
+    /// assert_eq!(events, 1);
+    /// ```
+    #[must_use]
+    pub fn with_filter(mut self, filter: Targets) -> Self {
+        self.selection
+            .get_or_insert_with(Selection::default)
+            .set_filter(filter);
+        self
+    }
 
-impl From<(&span::Attributes<'_>, &span::Id)> for NewSpan {
-    fn from((attrs, id): (&span::Attributes<'_>, &span::Id)) -> Self {
-        let mut fields = Fields::new();
-        attrs.record(&mut fields);
+    /// Records only a fraction of the traces, given by `sample_rate`.
+    ///
+    /// Traces are sampled as a whole: a root span, which has no parent, is recorded together with
+    /// all the spans and events within it, or not at all. Events without a parent are sampled in
+    /// the same way. The roots are sampled evenly, so a rate of `0.1` records the first root and
+    /// every tenth root after it.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `sample_rate` is not greater than `0.0` and at most `1.0`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tracing_rec::recording::Trace;
+    /// use tracing_subscriber::layer::SubscriberExt;
+    ///
+    /// let (layer, recording) = tracing_rec::rec_memory_layer();
+    /// let subscriber = tracing_subscriber::registry().with(layer.with_sample_rate(0.25));
+    /// tracing::subscriber::with_default(subscriber, || {
+    ///     for request in 0..8 {
+    ///         let span = tracing::info_span!("request", request);
+    ///         span.in_scope(|| tracing::info!("handled"));
+    ///     }
+    /// });
+    ///
+    /// let records = recording.records();
+    /// let count = |is: fn(&Trace) -> bool| records.iter().filter(|r| is(&r.trace)).count();
+    /// assert_eq!(count(|trace| matches!(trace, Trace::NewSpan(_))), 2);
+    /// assert_eq!(count(|trace| matches!(trace, Trace::Event(_))), 2);
+    /// ```
+    #[must_use]
+    pub fn with_sample_rate(mut self, sample_rate: f64) -> Self {
+        assert!(
+            sample_rate > 0.0 && sample_rate <= 1.0,
+            "the sample rate must be greater than 0 and at most 1, but got {sample_rate}"
+        );
+        self.selection
+            .get_or_insert_with(Selection::default)
+            .set_sample_rate(sample_rate);
+        self
+    }
 
-        Self {
-            id: id.into(),
-            fields: fields.inner,
-            metadata: attrs.metadata().into(),
-            parent: Parent::from(attrs),
-        }
+    /// Records a backtrace with each event at the `ERROR` level.
+    ///
+    /// The backtrace of the thread which recorded the event is captured and resolved when the
+    /// event is recorded, and is recorded as an extra string field named [`BACKTRACE_FIELD`]. The
+    /// field is added to the recorded fields of the callsites of error events too, so the
+    /// backtrace is dispatched with the event when it is replayed. Events which already have a
+    /// field with that name are recorded without a backtrace.
+    ///
+    /// Capturing and resolving a backtrace is slow, so this is best kept for applications which
+    /// log few errors.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tracing_rec::recording::{FieldValue, Trace};
+    /// use tracing_subscriber::layer::SubscriberExt;
+    ///
+    /// let (layer, recording) = tracing_rec::rec_memory_layer();
+    /// let subscriber = tracing_subscriber::registry().with(layer.with_backtraces());
+    /// tracing::subscriber::with_default(subscriber, || {
+    ///     tracing::warn!("recorded without a backtrace");
+    ///     tracing::error!("recorded with a backtrace");
+    /// });
+    ///
+    /// let backtraces = recording
+    ///     .records()
+    ///     .into_iter()
+    ///     .filter_map(|record| match record.trace {
+    ///         Trace::Event(event) => Some(event),
+    ///         _ => None,
+    ///     })
+    ///     .map(|event| {
+    ///         event
+    ///             .fields
+    ///             .into_iter()
+    ///             .any(|field| field.name == tracing_rec::BACKTRACE_FIELD)
+    ///     })
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(backtraces, [false, true]);
+    /// ```
+    #[must_use]
+    pub fn with_backtraces(mut self) -> Self {
+        self.backtraces = true;
+        self
     }
-}
 
-#[derive(Debug, Serialize)]
-struct SpanId(u64);
+    /// Records the final values of the fields of each span in the record of it closing.
+    ///
+    /// The values a span is created with, and the values recorded for it afterwards, are merged
+    /// together in the span's extensions as they are recorded. When the span closes, the merged
+    /// values are recorded in the [`TraceRecord::final_fields`] of its `Close` record, so that
+    /// tools which analyze the recording have the end state of each span without following all
+    /// of its `Record` records. The `Record` records are still recorded as well.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tracing_rec::recording::{FieldValue, Trace};
+    /// use tracing_subscriber::layer::SubscriberExt;
+    ///
+    /// let (layer, recording) = tracing_rec::rec_memory_layer();
+    /// let subscriber = tracing_subscriber::registry().with(layer.with_final_fields());
+    /// tracing::subscriber::with_default(subscriber, || {
+    ///     let span = tracing::info_span!("request", status = 0, bytes = tracing::field::Empty);
+    ///     span.record("status", 200);
+    ///     span.record("bytes", 512);
+    /// });
+    ///
+    /// let close = recording
+    ///     .records()
+    ///     .into_iter()
+    ///     .find(|record| matches!(record.trace, Trace::Close(_)))
+    ///     .unwrap();
+    /// let final_fields = close.final_fields.unwrap();
+    /// assert_eq!(final_fields[0].name, "status");
+    /// assert_eq!(final_fields[0].value, FieldValue::I64(200));
+    /// assert_eq!(final_fields[1].name, "bytes");
+    /// assert_eq!(final_fields[1].value, FieldValue::I64(512));
+    /// ```
+    ///
+    /// [`TraceRecord::final_fields`]: structfield@tracing_cassette::TraceRecord::final_fields
+    #[must_use]
+    pub fn with_final_fields(mut self) -> Self {
+        self.final_fields = true;
+        self
+    }
 
-impl From<&span::Id> for SpanId {
-    fn from(value: &span::Id) -> Self {
-        Self(value.into_u64())
+    /// Adds the key/value pair `key` and `value` to the metadata in the header of the recording.
+    ///
+    /// The metadata describes the recording as a whole, such as the commit the application was
+    /// built from, the environment it was deployed to, or the name of the test scenario which
+    /// was recorded. It is available when the recording is replayed, see
+    /// [`Header::metadata`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tracing_rec::recording::RecordReader;
+    /// use tracing_subscriber::layer::SubscriberExt;
+    ///
+    /// let (layer, recording) = tracing_rec::rec_memory_layer();
+    /// let layer = layer
+    ///     .with_metadata("build", "4f2a9c1")
+    ///     .with_metadata("environment", "staging");
+    /// let subscriber = tracing_subscriber::registry().with(layer);
+    /// tracing::subscriber::with_default(subscriber, || {
+    ///     tracing::info!("recorded");
+    /// });
+    ///
+    /// let recording = recording.to_bytes();
+    /// let mut reader = RecordReader::new(recording.as_slice());
+    /// reader.next();
+    /// assert_eq!(reader.header().metadata["build"], "4f2a9c1");
+    /// assert_eq!(reader.header().metadata["environment"], "staging");
+    /// ```
+    ///
+    /// [`Header::metadata`]: structfield@tracing_cassette::Header::metadata
+    #[must_use]
+    pub fn with_metadata(self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.sink.add_metadata(key.into(), value.into());
+        self
     }
-}
 
-#[derive(Debug, Serialize)]
-struct RecordValues {
-    id: SpanId,
-    fields: Vec<Field>,
-}
+    /// Turns this layer into a sink, which several layers can record into, see [`RecSink`].
+    ///
+    /// The metadata added to this layer is kept, its other configuration, such as its filter,
+    /// isn't: each layer of the sink is configured separately.
+    #[must_use]
+    pub fn into_sink(self) -> RecSink {
+        RecSink::new(self.sink)
+    }
 
-impl From<(&span::Id, &span::Record<'_>)> for RecordValues {
-    fn from((id, values): (&span::Id, &span::Record<'_>)) -> Self {
-        let mut fields = Fields::new();
-        values.record(&mut fields);
+    fn write_trace(&self, trace_record: TraceRecordRef<'_>) {
+        self.sink.write_trace(trace_record);
+    }
 
-        Self {
-            id: id.into(),
-            fields: fields.inner,
+    /// Returns the recorded span Id of a new span `id`.
+    fn new_span_id(&self, id: &span::Id) -> SpanId {
+        match &self.span_ids {
+            Some(span_ids) => span_ids.new_span(id, &self.sink),
+            None => id.into(),
         }
     }
-}
 
-#[derive(Debug, Serialize)]
-struct FollowsFrom {
-    cause_id: SpanId,
-    effect_id: SpanId,
-}
+    /// Returns the recorded span Id of the span `id`.
+    fn span_id(&self, id: &span::Id) -> SpanId {
+        match &self.span_ids {
+            Some(span_ids) => span_ids.get(id, &self.sink),
+            None => id.into(),
+        }
+    }
 
-impl FollowsFrom {
-    fn new(cause_id: SpanId, effect_id: SpanId) -> Self {
-        Self {
-            cause_id,
-            effect_id,
+    /// Returns the recorded span Id of the span `id`, which is closing.
+    fn close_span_id(&self, id: &span::Id) -> SpanId {
+        match &self.span_ids {
+            Some(span_ids) => span_ids.close(id, &self.sink),
+            None => id.into(),
+        }
+    }
+
+    /// Replaces an explicit `parent` with its recorded span Id.
+    fn map_parent(&self, parent: &mut Parent) {
+        if let Some(span_ids) = &self.span_ids {
+            span_ids.map_parent(parent, &self.sink);
+        }
+    }
+
+    /// Returns the distributed trace context of the span `id`, if it has one.
+    #[cfg(feature = "opentelemetry")]
+    fn trace_context(&self, id: &span::Id) -> Option<TraceContext> {
+        self.otel.trace_context(id)
+    }
+
+    /// Returns the distributed trace context of the span `id`, which is never known without the
+    /// `opentelemetry` feature.
+    #[cfg(not(feature = "opentelemetry"))]
+    fn trace_context(&self, _id: &span::Id) -> Option<TraceContext> {
+        None
+    }
+
+    /// Returns whether a backtrace is recorded with the events of the callsite `metadata`.
+    fn records_backtrace(&self, metadata: &tracing::Metadata<'_>) -> bool {
+        self.backtraces
+            && metadata.is_event()
+            && *metadata.level() == tracing::Level::ERROR
+            && metadata.fields().field(BACKTRACE_FIELD).is_none()
+    }
+
+    /// Returns the recorded metadata of the callsite `metadata`.
+    fn metadata(&self, metadata: &'static tracing::Metadata<'static>) -> MetadataRef<'static> {
+        let mut rec_metadata = MetadataRef::from(metadata);
+        if self.records_backtrace(metadata) {
+            rec_metadata.fields.push(BACKTRACE_FIELD.into());
+        }
+        rec_metadata
+    }
+
+    /// Returns whether the records of the span `id` are recorded.
+    fn records_span(&self, id: &span::Id) -> bool {
+        match &self.selection {
+            Some(selection) => selection.span(id),
+            None => true,
         }
     }
 }
 
-impl Rec {
-    fn write_trace(&self, trace_record: &TraceRecord) {
-        serde_json::to_writer(&self.writer, &trace_record).expect("writing failed");
-        writeln!(&self.writer).expect("writing failed");
+/// Returns the parent of a span or event, which is either explicit or the current span.
+fn parent_id<S: Subscriber>(
+    explicit: Option<&span::Id>,
+    is_contextual: bool,
+    ctx: &tracing_subscriber::layer::Context<'_, S>,
+) -> Option<span::Id> {
+    match explicit {
+        Some(id) => Some(id.clone()),
+        None if is_contextual => ctx.current_span().id().cloned(),
+        None => None,
     }
 }
 
 impl<S> tracing_subscriber::Layer<S> for Rec
 where
-    S: Subscriber,
+    S: Subscriber + for<'a> LookupSpan<'a>,
 {
+    #[cfg(feature = "opentelemetry")]
+    fn on_register_dispatch(&self, dispatch: &tracing::Dispatch) {
+        self.otel.register(dispatch);
+    }
+
     fn register_callsite(&self, metadata: &'static tracing::Metadata<'static>) -> Interest {
-        let trace = Trace::RegisterCallsite(metadata.into());
-        self.write_trace(&TraceRecord::implicit(trace));
+        if let Some(selection) = &self.selection {
+            if !selection.callsite(metadata) {
+                return Interest::always();
+            }
+        }
+        let trace = TraceRef::RegisterCallsite(self.metadata(metadata));
+        self.write_trace(implicit_record(trace));
 
         Interest::always()
     }
@@ -364,20 +641,46 @@ where
         &self,
         attrs: &span::Attributes<'_>,
         id: &span::Id,
-        _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
     ) {
-        let trace = Trace::NewSpan((attrs, id).into());
-        self.write_trace(&TraceRecord::implicit(trace));
+        if let Some(selection) = &self.selection {
+            let parent = parent_id(attrs.parent(), attrs.is_contextual(), &ctx);
+            if !selection.new_span(id, attrs.metadata(), parent.as_ref()) {
+                return;
+            }
+        }
+        let mut new_span = new_span(attrs, self.new_span_id(id), self.trace_context(id));
+        self.map_parent(&mut new_span.parent);
+        if self.final_fields {
+            if let Some(span) = ctx.span(id) {
+                // Another layer recording the same subscriber may have added the same values.
+                span.extensions_mut()
+                    .replace(FinalFields(new_span.fields.clone()));
+            }
+        }
+        let trace = TraceRef::NewSpan(new_span);
+        self.write_trace(implicit_record(trace));
     }
 
     fn on_record(
         &self,
         span: &span::Id,
         values: &span::Record<'_>,
-        _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
     ) {
-        let trace = Trace::Record((span, values).into());
-        self.write_trace(&TraceRecord::implicit(trace));
+        if !self.records_span(span) {
+            return;
+        }
+        let record_values = record_values(self.span_id(span), values);
+        if self.final_fields {
+            if let Some(span) = ctx.span(span) {
+                if let Some(final_fields) = span.extensions_mut().get_mut::<FinalFields>() {
+                    final_fields.merge(&record_values.fields);
+                }
+            }
+        }
+        let trace = TraceRef::Record(record_values);
+        self.write_trace(implicit_record(trace));
     }
 
     fn on_follows_from(
@@ -386,31 +689,66 @@ where
         follows: &span::Id,
         _ctx: tracing_subscriber::layer::Context<'_, S>,
     ) {
-        let trace = Trace::FollowsFrom(FollowsFrom::new(follows.into(), span.into()));
-        self.write_trace(&TraceRecord::implicit(trace));
+        if !self.records_span(span) || !self.records_span(follows) {
+            return;
+        }
+        let trace = TraceRef::FollowsFrom(FollowsFrom {
+            cause_id: self.span_id(follows),
+            effect_id: self.span_id(span),
+        });
+        self.write_trace(implicit_record(trace));
     }
 
-    fn on_event(
-        &self,
-        event: &tracing::Event<'_>,
-        _ctx: tracing_subscriber::layer::Context<'_, S>,
-    ) {
-        let trace = Trace::Event(event.into());
-        self.write_trace(&TraceRecord::implicit(trace));
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        if let Some(selection) = &self.selection {
+            let parent = parent_id(event.parent(), event.is_contextual(), &ctx);
+            if !selection.event(event.metadata(), parent.as_ref()) {
+                return;
+            }
+        }
+        let mut rec_event = self::event(event);
+        if self.records_backtrace(event.metadata()) {
+            rec_event.metadata = self.metadata(event.metadata());
+            rec_event.fields.push(FieldRef {
+                name: BACKTRACE_FIELD.into(),
+                value: FieldValueRef::Str(Backtrace::force_capture().to_string().into()),
+            });
+        }
+        self.map_parent(&mut rec_event.parent);
+        let trace = TraceRef::Event(rec_event);
+        self.write_trace(implicit_record(trace));
     }
 
     fn on_enter(&self, id: &span::Id, _ctx: tracing_subscriber::layer::Context<'_, S>) {
-        let trace = Trace::Enter(id.into());
-        self.write_trace(&TraceRecord::implicit(trace));
+        if !self.records_span(id) {
+            return;
+        }
+        let trace = TraceRef::Enter(self.span_id(id));
+        self.write_trace(implicit_record(trace));
     }
 
     fn on_exit(&self, id: &span::Id, _ctx: tracing_subscriber::layer::Context<'_, S>) {
-        let trace = Trace::Exit(id.into());
-        self.write_trace(&TraceRecord::implicit(trace));
+        if !self.records_span(id) {
+            return;
+        }
+        let trace = TraceRef::Exit(self.span_id(id));
+        self.write_trace(implicit_record(trace));
     }
 
-    fn on_close(&self, id: span::Id, _ctx: tracing_subscriber::layer::Context<'_, S>) {
-        let trace = Trace::Close((&id).into());
-        self.write_trace(&TraceRecord::implicit(trace));
+    fn on_close(&self, id: span::Id, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        if let Some(selection) = &self.selection {
+            if !selection.close(&id) {
+                return;
+            }
+        }
+        let final_fields = self
+            .final_fields
+            .then(|| ctx.span(&id))
+            .flatten()
+            .and_then(|span| span.extensions_mut().remove::<FinalFields>());
+        let trace = TraceRef::Close(self.close_span_id(&id));
+        let mut record = implicit_record(trace);
+        record.final_fields = final_fields.map(|final_fields| final_fields.0);
+        self.write_trace(record);
     }
 }
@@ -0,0 +1,85 @@
+use tracing::Subscriber;
+use tracing_subscriber::filter::Targets;
+
+/// A layer which records nothing, as a drop-in replacement for [`Rec`].
+///
+/// `NoopRec` has the same configuration methods as [`Rec`], which ignore their arguments, and
+/// implements none of the layer's callbacks. It has no size and compiles down to nothing, so the
+/// wiring which sets up recording can be kept in an application permanently, and switched to a
+/// real [`Rec`] only in the builds which record, such as with a feature of the application:
+///
+/// ```
+/// # #![allow(unexpected_cfgs)]
+/// use tracing_subscriber::layer::SubscriberExt;
+///
+/// #[cfg(feature = "record")]
+/// let layer = tracing_rec::rec_layer();
+/// #[cfg(not(feature = "record"))]
+/// let layer = tracing_rec::NoopRec::new();
+///
+/// let subscriber = tracing_subscriber::registry().with(layer.with_final_fields());
+/// tracing::subscriber::with_default(subscriber, || {
+///     tracing::info!("only recorded with the `record` feature");
+/// });
+/// ```
+///
+/// [`Rec`]: struct@crate::Rec
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopRec {
+    _priv: (),
+}
+
+impl NoopRec {
+    /// Returns a layer which records nothing.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self { _priv: () }
+    }
+
+    /// Does nothing, see [`Rec::with_filter`].
+    ///
+    /// [`Rec::with_filter`]: fn@crate::Rec::with_filter
+    #[must_use]
+    #[inline]
+    pub fn with_filter(self, _filter: Targets) -> Self {
+        self
+    }
+
+    /// Does nothing, see [`Rec::with_sample_rate`].
+    ///
+    /// [`Rec::with_sample_rate`]: fn@crate::Rec::with_sample_rate
+    #[must_use]
+    #[inline]
+    pub fn with_sample_rate(self, _sample_rate: f64) -> Self {
+        self
+    }
+
+    /// Does nothing, see [`Rec::with_backtraces`].
+    ///
+    /// [`Rec::with_backtraces`]: fn@crate::Rec::with_backtraces
+    #[must_use]
+    #[inline]
+    pub fn with_backtraces(self) -> Self {
+        self
+    }
+
+    /// Does nothing, see [`Rec::with_final_fields`].
+    ///
+    /// [`Rec::with_final_fields`]: fn@crate::Rec::with_final_fields
+    #[must_use]
+    #[inline]
+    pub fn with_final_fields(self) -> Self {
+        self
+    }
+
+    /// Does nothing, see [`Rec::with_metadata`].
+    ///
+    /// [`Rec::with_metadata`]: fn@crate::Rec::with_metadata
+    #[must_use]
+    #[inline]
+    pub fn with_metadata(self, _key: impl Into<String>, _value: impl Into<String>) -> Self {
+        self
+    }
+}
+
+impl<S> tracing_subscriber::Layer<S> for NoopRec where S: Subscriber {}
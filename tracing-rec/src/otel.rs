@@ -0,0 +1,55 @@
+//! Recording of the OpenTelemetry trace context of spans, which is kept by
+//! [`tracing-opentelemetry`].
+//!
+//! [`tracing-opentelemetry`]: https://docs.rs/tracing-opentelemetry
+
+use std::sync::OnceLock;
+
+use opentelemetry::trace::{TraceContextExt, TraceId};
+use tracing::{dispatcher::WeakDispatch, span, Dispatch};
+use tracing_cassette::TraceContext;
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::{registry::LookupSpan, Registry};
+
+/// Reads the OpenTelemetry trace context of spans from the dispatcher the layer is part of.
+///
+/// The `tracing-opentelemetry` layer keeps the context in the extensions of each span, which are
+/// reached through the [`Registry`] of the dispatcher, so that the rec layer doesn't need to
+/// require a subscriber which can look up spans. The current dispatcher can't be used for this,
+/// as it isn't available while the dispatcher is being called.
+#[derive(Debug, Default)]
+pub(crate) struct OtelContext {
+    dispatch: OnceLock<WeakDispatch>,
+}
+
+impl OtelContext {
+    /// Sets the dispatcher that the layer is part of.
+    pub(crate) fn register(&self, dispatch: &Dispatch) {
+        // A layer is only ever part of a single dispatcher.
+        let _ = self.dispatch.set(dispatch.downgrade());
+    }
+
+    /// Returns the OpenTelemetry trace context of the span `id`, if it has one.
+    ///
+    /// The context is only there if the `tracing-opentelemetry` layer has already been called
+    /// for the new span, which is the case when it is added to the registry before the rec
+    /// layer.
+    pub(crate) fn trace_context(&self, id: &span::Id) -> Option<TraceContext> {
+        let dispatch = self.dispatch.get()?.upgrade()?;
+        let registry = dispatch.downcast_ref::<Registry>()?;
+        let span = registry.span(id)?;
+        let extensions = span.extensions();
+        let otel_data = extensions.get::<OtelData>()?;
+
+        // The trace Id is only generated for root spans, the others are in their parent's trace.
+        let trace_id = otel_data
+            .builder
+            .trace_id
+            .unwrap_or_else(|| otel_data.parent_cx.span().span_context().trace_id());
+        let span_id = otel_data.builder.span_id?;
+        (trace_id != TraceId::INVALID).then(|| TraceContext {
+            trace_id: u128::from_be_bytes(trace_id.to_bytes()),
+            span_id: u64::from_be_bytes(span_id.to_bytes()),
+        })
+    }
+}
@@ -0,0 +1,118 @@
+//! Selecting which spans and events are recorded, by filtering and sampling.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use tracing::{span, Metadata};
+use tracing_subscriber::filter::Targets;
+
+/// Which spans and events a [`Rec`] layer records.
+///
+/// [`Rec`]: crate::Rec
+#[derive(Debug)]
+pub(crate) struct Selection {
+    filter: Option<Targets>,
+    sample_rate: f64,
+    state: Mutex<SelectionState>,
+}
+
+#[derive(Debug)]
+struct SelectionState {
+    /// The open spans which aren't recorded, and whether they were sampled. The descendants of
+    /// spans which weren't sampled aren't recorded either.
+    skipped: HashMap<span::Id, bool>,
+    /// The fraction of a root which is owed to the sample, a root is sampled when it reaches 1.
+    sample_credit: f64,
+}
+
+impl Default for Selection {
+    fn default() -> Self {
+        Self {
+            filter: None,
+            sample_rate: 1.0,
+            state: Mutex::new(SelectionState {
+                skipped: HashMap::new(),
+                sample_credit: 0.0,
+            }),
+        }
+    }
+}
+
+impl Selection {
+    pub(crate) fn set_filter(&mut self, filter: Targets) {
+        self.filter = Some(filter);
+    }
+
+    pub(crate) fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        // The first root is always sampled.
+        self.lock().sample_credit = 1.0 - sample_rate;
+    }
+
+    /// Returns whether the callsite with `metadata` is recorded.
+    pub(crate) fn callsite(&self, metadata: &Metadata<'_>) -> bool {
+        self.filter_enables(metadata)
+    }
+
+    /// Returns whether a new span with the parent `parent` is recorded.
+    pub(crate) fn new_span(
+        &self,
+        id: &span::Id,
+        metadata: &Metadata<'_>,
+        parent: Option<&span::Id>,
+    ) -> bool {
+        let mut state = self.lock();
+        let sampled = self.sampled(&mut state, parent);
+        let recorded = sampled && self.filter_enables(metadata);
+        if !recorded {
+            state.skipped.insert(id.clone(), sampled);
+        }
+        recorded
+    }
+
+    /// Returns whether an event with the parent `parent` is recorded.
+    pub(crate) fn event(&self, metadata: &Metadata<'_>, parent: Option<&span::Id>) -> bool {
+        let mut state = self.lock();
+        self.sampled(&mut state, parent) && self.filter_enables(metadata)
+    }
+
+    /// Returns whether the records of an open span are recorded.
+    pub(crate) fn span(&self, id: &span::Id) -> bool {
+        !self.lock().skipped.contains_key(id)
+    }
+
+    /// Returns whether the closing of a span is recorded, and forgets the span.
+    pub(crate) fn close(&self, id: &span::Id) -> bool {
+        self.lock().skipped.remove(id).is_none()
+    }
+
+    /// Returns whether a span or event with the parent `parent` is sampled.
+    ///
+    /// The descendants of a span are sampled if it was, roots are sampled evenly at the sample
+    /// rate.
+    fn sampled(&self, state: &mut SelectionState, parent: Option<&span::Id>) -> bool {
+        match parent {
+            Some(parent) => state.skipped.get(parent).copied().unwrap_or(true),
+            None => {
+                state.sample_credit += self.sample_rate;
+                // Allow for rounding errors, so that a rate of 0.1 samples exactly 1 in 10.
+                if state.sample_credit >= 1.0 - f64::EPSILON * 16.0 {
+                    state.sample_credit = (state.sample_credit - 1.0).max(0.0);
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    fn filter_enables(&self, metadata: &Metadata<'_>) -> bool {
+        match &self.filter {
+            Some(filter) => filter.would_enable(metadata.target(), metadata.level()),
+            None => true,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, SelectionState> {
+        self.state.lock().expect("lock poisoned")
+    }
+}
@@ -0,0 +1,269 @@
+//! The recording that layers write to, which may be shared between several layers.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Stdout, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, Once,
+    },
+};
+
+use tracing::span;
+use tracing_cassette::{ContainerWriter, Header, Parent, SpanId, TraceRecord, TraceRecordRef};
+
+use crate::{MemoryRecording, Rec};
+
+pub(crate) enum RecWriter {
+    Stdout(Stdout),
+    Memory(MemoryRecording),
+    File(Mutex<File>),
+    /// The container is taken to be finished when the sink is dropped.
+    Container(Mutex<Option<ContainerWriter<Box<dyn Write + Send>>>>),
+}
+
+/// A recording which one or more [`Rec`] layers write to.
+///
+/// The header is written before the first record, and a container is finished once the last
+/// layer writing to it has been dropped.
+pub(crate) struct Sink {
+    writer: RecWriter,
+    /// The header of the recording, which is written before the first record.
+    header: Mutex<Header>,
+    header_written: Once,
+    /// The span Id given to the next span recorded by any layer, when the sink is shared.
+    next_span_id: AtomicU64,
+    /// The number of the last record written, if records are numbered.
+    ///
+    /// The lock is held while each record is written, so that the records are written in the
+    /// order of their numbers.
+    sequence: Mutex<Option<u64>>,
+}
+
+impl Sink {
+    pub(crate) fn new(writer: RecWriter) -> Self {
+        Self {
+            writer,
+            header: Mutex::new(Header::new()),
+            header_written: Once::new(),
+            next_span_id: AtomicU64::new(1),
+            sequence: Mutex::new(None),
+        }
+    }
+
+    /// Numbers the records written from now on, see [`TraceRecord::sequence`].
+    ///
+    /// [`TraceRecord::sequence`]: structfield@TraceRecord::sequence
+    pub(crate) fn number_records(&self) {
+        self.sequence
+            .lock()
+            .expect("lock poisoned")
+            .get_or_insert(0);
+    }
+
+    pub(crate) fn add_metadata(&self, key: String, value: String) {
+        self.header
+            .lock()
+            .expect("lock poisoned")
+            .metadata
+            .insert(key, value);
+    }
+
+    /// Writes the header of the recording, unless it has already been written.
+    ///
+    /// The header isn't written when the layer is created, so that metadata can be added to it
+    /// first.
+    fn write_header(&self) {
+        self.header_written.call_once(|| {
+            let header = self.header.lock().expect("lock poisoned");
+            match &self.writer {
+                RecWriter::Stdout(writer) => {
+                    writeln!(&*writer, "{}", header.to_line()).expect("writing failed");
+                }
+                RecWriter::Memory(recording) => recording.write_line(header.to_line().as_bytes()),
+                RecWriter::File(file) => {
+                    writeln!(file.lock().expect("lock poisoned"), "{}", header.to_line())
+                        .expect("writing failed");
+                }
+                RecWriter::Container(container) => {
+                    // The container writes the header itself, once it writes the first segment.
+                    let mut container = container.lock().expect("lock poisoned");
+                    *container = container
+                        .take()
+                        .map(|container| container.with_metadata(header.metadata.clone()));
+                }
+            }
+        });
+    }
+
+    pub(crate) fn write_trace(&self, mut trace_record: TraceRecordRef<'_>) {
+        self.write_header();
+        let mut sequence = self.sequence.lock().expect("lock poisoned");
+        if let Some(sequence) = &mut *sequence {
+            *sequence += 1;
+            trace_record.sequence = Some(*sequence);
+        }
+        match &self.writer {
+            RecWriter::Stdout(writer) => {
+                serde_json::to_writer(writer, &trace_record).expect("writing failed");
+                writeln!(&*writer).expect("writing failed");
+            }
+            RecWriter::Memory(recording) => {
+                // The record is serialized first, so that records written from different
+                // threads aren't interleaved.
+                let line = serde_json::to_vec(&trace_record).expect("serializing failed");
+                recording.write_line(&line);
+            }
+            RecWriter::File(file) => {
+                let mut line = serde_json::to_vec(&trace_record).expect("serializing failed");
+                line.push(b'\n');
+                file.lock()
+                    .expect("lock poisoned")
+                    .write_all(&line)
+                    .expect("writing failed");
+            }
+            RecWriter::Container(container) => {
+                if let Some(container) = &mut *container.lock().expect("lock poisoned") {
+                    container
+                        .write_record(&TraceRecord::from(trace_record))
+                        .expect("writing failed");
+                }
+            }
+        }
+    }
+
+    fn next_span_id(&self) -> SpanId {
+        SpanId::from(self.next_span_id.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Drop for Sink {
+    fn drop(&mut self) {
+        // A recording without any records still has a header.
+        self.write_header();
+        if let RecWriter::Container(container) = &self.writer {
+            let container = container.lock().map(|mut container| container.take());
+            if let Ok(Some(container)) = container {
+                // Errors can't be reported from here, the container is left truncated.
+                let _ = container.finish();
+            }
+        }
+    }
+}
+
+/// A handle to a recording which several [`Rec`] layers write to.
+///
+/// Some applications have more than one subscriber, such as one for each async runtime. A sink
+/// is created from a layer with [`Rec::into_sink`], and then each subscriber is given its own
+/// layer from [`layer`], all of which record into the same recording, which has a single header.
+/// A container is finished once the sink and all of its layers have been dropped.
+///
+/// Each subscriber assigns span Ids independently, so the Ids of spans from different
+/// subscribers may be the same. The layers of a sink give the spans they record new span Ids
+/// instead, numbered in sequence across all of the layers, so that the Ids in the recording are
+/// unique.
+///
+/// The records themselves are numbered as well, in the order they are written by any of the
+/// layers, in their [`sequence`], so that records missing from the recording can be found with
+/// [`GapDetection::with_record_sequence`].
+///
+/// # Examples
+///
+/// ```
+/// use tracing_rec::recording::Trace;
+/// use tracing_subscriber::layer::SubscriberExt;
+///
+/// let (layer, recording) = tracing_rec::rec_memory_layer();
+/// let sink = layer.into_sink();
+///
+/// for runtime in ["io", "compute"] {
+///     let subscriber = tracing_subscriber::registry().with(sink.layer());
+///     tracing::subscriber::with_default(subscriber, || {
+///         tracing::info_span!("runtime", runtime).in_scope(|| tracing::info!("started"));
+///     });
+/// }
+///
+/// let span_ids = recording
+///     .records()
+///     .into_iter()
+///     .filter_map(|record| match record.trace {
+///         Trace::NewSpan(new_span) => Some(new_span.id),
+///         _ => None,
+///     })
+///     .collect::<Vec<_>>();
+/// assert_eq!(span_ids, [1.into(), 2.into()]);
+///
+/// let sequence = recording
+///     .records()
+///     .into_iter()
+///     .map(|record| record.sequence.unwrap())
+///     .collect::<Vec<_>>();
+/// assert_eq!(sequence, (1..=sequence.len() as u64).collect::<Vec<_>>());
+/// ```
+///
+/// [`layer`]: fn@Self::layer
+/// [`sequence`]: structfield@tracing_cassette::TraceRecord::sequence
+/// [`GapDetection::with_record_sequence`]: fn@tracing_cassette::GapDetection::with_record_sequence
+#[derive(Clone)]
+pub struct RecSink {
+    sink: Arc<Sink>,
+}
+
+impl RecSink {
+    pub(crate) fn new(sink: Arc<Sink>) -> Self {
+        sink.number_records();
+        Self { sink }
+    }
+
+    /// Returns a new layer which records into this sink.
+    ///
+    /// The layer records all spans and events, it can be configured with the same methods as
+    /// any other [`Rec`] layer, such as [`Rec::with_filter`].
+    #[must_use]
+    pub fn layer(&self) -> Rec {
+        Rec::with_sink(Arc::clone(&self.sink), Some(SpanIds::default()))
+    }
+}
+
+/// The span Ids given to the spans recorded by one of the layers of a [`RecSink`].
+#[derive(Default)]
+pub(crate) struct SpanIds {
+    /// The recorded span Id of each open span, by the span Id the subscriber assigned.
+    ids: Mutex<HashMap<span::Id, SpanId>>,
+}
+
+impl SpanIds {
+    /// Gives the new span `id` the next span Id of the `sink`.
+    pub(crate) fn new_span(&self, id: &span::Id, sink: &Sink) -> SpanId {
+        let span_id = sink.next_span_id();
+        self.lock().insert(id.clone(), span_id);
+        span_id
+    }
+
+    /// Returns the recorded span Id of the span `id`.
+    ///
+    /// A span which wasn't recorded, such as the explicit parent of an event which was filtered
+    /// out, is given an Id of its own which no recorded span has.
+    pub(crate) fn get(&self, id: &span::Id, sink: &Sink) -> SpanId {
+        let span_id = self.lock().get(id).copied();
+        span_id.unwrap_or_else(|| sink.next_span_id())
+    }
+
+    /// Returns the recorded span Id of the span `id`, and forgets the span.
+    pub(crate) fn close(&self, id: &span::Id, sink: &Sink) -> SpanId {
+        let span_id = self.lock().remove(id);
+        span_id.unwrap_or_else(|| sink.next_span_id())
+    }
+
+    /// Replaces an explicit `parent` with its recorded span Id.
+    pub(crate) fn map_parent(&self, parent: &mut Parent, sink: &Sink) {
+        if let Parent::Explicit(id) = parent {
+            *id = self.get(&span::Id::from_u64(u64::from(*id)), sink);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<span::Id, SpanId>> {
+        self.ids.lock().expect("lock poisoned")
+    }
+}
@@ -0,0 +1,161 @@
+//! Recording of structured values, which are recorded with [`valuable`].
+
+use serde_json::{Map, Value as Json};
+use tracing_cassette::FieldValueRef;
+use valuable::{Enumerable, Fields, NamedValues, Structable, Value, Visit};
+
+/// Converts a value recorded with `valuable` into a field value.
+///
+/// Values which `tracing` can record directly are recorded as they would be, all others are
+/// recorded as JSON, keeping their structure.
+pub(crate) fn field_value(value: Value<'_>) -> FieldValueRef<'static> {
+    match value {
+        Value::Bool(value) => FieldValueRef::Bool(value),
+        Value::F32(value) => FieldValueRef::F64(value.into()),
+        Value::F64(value) => FieldValueRef::F64(value),
+        Value::I8(value) => FieldValueRef::I64(value.into()),
+        Value::I16(value) => FieldValueRef::I64(value.into()),
+        Value::I32(value) => FieldValueRef::I64(value.into()),
+        Value::I64(value) => FieldValueRef::I64(value),
+        Value::I128(value) => FieldValueRef::I128(value),
+        Value::U8(value) => FieldValueRef::U64(value.into()),
+        Value::U16(value) => FieldValueRef::U64(value.into()),
+        Value::U32(value) => FieldValueRef::U64(value.into()),
+        Value::U64(value) => FieldValueRef::U64(value),
+        Value::U128(value) => FieldValueRef::U128(value),
+        Value::String(value) => FieldValueRef::Str(value.to_owned().into()),
+        value => FieldValueRef::Json(to_json(value)),
+    }
+}
+
+/// Converts a value into JSON.
+///
+/// Structs with named fields and maps become objects, lists, tuples, and structs with unnamed
+/// fields become arrays. Enum variants become an object with the variant name as its only key,
+/// as `serde` encodes them, except for unit variants which are just the variant name. Numbers
+/// which JSON can't represent are recorded as strings.
+fn to_json(value: Value<'_>) -> Json {
+    match value {
+        Value::Bool(value) => value.into(),
+        Value::Char(value) => value.to_string().into(),
+        Value::F32(value) => f64::from(value).into(),
+        Value::F64(value) => value.into(),
+        Value::I8(value) => value.into(),
+        Value::I16(value) => value.into(),
+        Value::I32(value) => value.into(),
+        Value::I64(value) => value.into(),
+        Value::I128(value) => {
+            i64::try_from(value).map_or_else(|_| value.to_string().into(), Json::from)
+        }
+        Value::Isize(value) => value.into(),
+        Value::U8(value) => value.into(),
+        Value::U16(value) => value.into(),
+        Value::U32(value) => value.into(),
+        Value::U64(value) => value.into(),
+        Value::U128(value) => {
+            u64::try_from(value).map_or_else(|_| value.to_string().into(), Json::from)
+        }
+        Value::Usize(value) => value.into(),
+        Value::String(value) => value.into(),
+        Value::Path(value) => value.display().to_string().into(),
+        Value::Error(value) => value.to_string().into(),
+        Value::Listable(value) => {
+            let mut collector = Collector::new(false);
+            value.visit(&mut collector);
+            collector.finish()
+        }
+        Value::Mappable(value) => {
+            let mut collector = Collector::new(true);
+            value.visit(&mut collector);
+            collector.finish()
+        }
+        Value::Tuplable(value) => {
+            let mut collector = Collector::new(false);
+            value.visit(&mut collector);
+            collector.finish()
+        }
+        Value::Structable(value) => struct_json(value),
+        Value::Enumerable(value) => enum_json(value),
+        Value::Unit => Json::Null,
+        // `Value` is non-exhaustive, values added later are kept as their `Debug` text.
+        value => format!("{value:?}").into(),
+    }
+}
+
+fn struct_json(value: &dyn Structable) -> Json {
+    let named = matches!(value.definition().fields(), Fields::Named(_));
+    let mut collector = Collector::new(named);
+    value.visit(&mut collector);
+    collector.finish()
+}
+
+fn enum_json(value: &dyn Enumerable) -> Json {
+    let variant = value.variant();
+    let mut collector = match variant.fields() {
+        Fields::Named(_) => Collector::new(true),
+        Fields::Unnamed(0) => return variant.name().into(),
+        Fields::Unnamed(_) => Collector::new(false),
+    };
+    value.visit(&mut collector);
+    let fields = match collector.finish() {
+        // Like `serde`, a variant with a single unnamed field holds just that field.
+        Json::Array(mut values) if values.len() == 1 => values.remove(0),
+        fields => fields,
+    };
+
+    let mut object = Map::new();
+    object.insert(variant.name().to_owned(), fields);
+    Json::Object(object)
+}
+
+/// Collects the values visited into an array, or the named fields and map entries into an object.
+struct Collector {
+    named: bool,
+    values: Vec<Json>,
+    entries: Map<String, Json>,
+}
+
+impl Collector {
+    fn new(named: bool) -> Self {
+        Self {
+            named,
+            values: Vec::new(),
+            entries: Map::new(),
+        }
+    }
+
+    fn finish(self) -> Json {
+        if self.named || !self.entries.is_empty() {
+            Json::Object(self.entries)
+        } else {
+            Json::Array(self.values)
+        }
+    }
+}
+
+impl Visit for Collector {
+    fn visit_value(&mut self, value: Value<'_>) {
+        self.values.push(to_json(value));
+    }
+
+    fn visit_named_fields(&mut self, named_values: &NamedValues<'_>) {
+        for (field, value) in named_values {
+            self.entries
+                .insert(field.name().to_owned(), to_json(*value));
+        }
+    }
+
+    fn visit_unnamed_fields(&mut self, values: &[Value<'_>]) {
+        self.values
+            .extend(values.iter().map(|value| to_json(*value)));
+    }
+
+    fn visit_entry(&mut self, key: Value<'_>, value: Value<'_>) {
+        // JSON object keys are strings, other keys are written as their JSON text.
+        let key = match to_json(key) {
+            Json::String(key) => key,
+            key => key.to_string(),
+        };
+        self.entries.insert(key, to_json(value));
+    }
+}
@@ -0,0 +1,20 @@
+//! The C API of [`tracing-replay`], built as a C dynamic library.
+//!
+//! Building this crate produces `libtracing_replay_ffi.so` (`.dylib` on macOS,
+//! `tracing_replay_ffi.dll` on Windows), which exports the functions of the
+//! [`tracing_replay::ffi`] module:
+//!
+//! ```sh
+//! cargo build -p tracing-replay-ffi --release
+//! ```
+//!
+//! The declarations for C are in `tracing-replay/include/tracing_replay.h`.
+//!
+//! Replayed traces are dispatched to the global default subscriber of the library. To replay
+//! into Rust components, build them into a C dynamic library of their own which depends on
+//! `tracing-replay` with the `ffi` feature and re-exports [`tracing_replay::ffi`] in the same way
+//! as this crate does.
+//!
+//! [`tracing-replay`]: https://docs.rs/tracing-replay
+
+pub use tracing_replay::ffi::*;
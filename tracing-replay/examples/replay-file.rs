@@ -17,7 +17,8 @@ fn main() -> Result<(), Box<dyn error::Error>> {
         );
     };
 
-    let mut replay = tracing_replay::Replay::new();
+    // The replay can be tuned with the `TRACING_REPLAY_*` environment variables.
+    let mut replay = tracing_replay::Replay::from_env()?;
     let summary_result = replay
         .replay_file(&path)
         .map_err(|err| format!("failed to replay file: {path}, error: {err}."));
@@ -28,6 +29,18 @@ fn main() -> Result<(), Box<dyn error::Error>> {
         "Successully replayed, record count: {record_count}.",
         record_count = summary.record_count
     );
+    if let Some(line_index) = summary.truncated_final_record {
+        println!("Skipped truncated final record at line index {line_index}.");
+    }
+    for (thread_id, thread_summary) in &summary.threads {
+        println!(
+            "  {thread_id} ({thread_name}) replayed on {replay_thread_id:?}: {record_count} records, max dispatch lag: {lag:?}",
+            thread_name = thread_summary.thread_name.as_deref().unwrap_or("<unnamed>"),
+            replay_thread_id = thread_summary.replay_thread_id,
+            record_count = thread_summary.record_count,
+            lag = thread_summary.max_dispatch_lag(),
+        );
+    }
 
     Ok(())
 }
@@ -0,0 +1,63 @@
+/*
+ * The C API of tracing-replay, built as a C dynamic library by the tracing-replay-ffi crate:
+ *
+ *     cargo build -p tracing-replay-ffi --release
+ *
+ * See the documentation of the `tracing_replay::ffi` module for details.
+ */
+#ifndef TRACING_REPLAY_H
+#define TRACING_REPLAY_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define TRACING_REPLAY_NOT_STARTED 0
+#define TRACING_REPLAY_RUNNING 1
+#define TRACING_REPLAY_FINISHED 2
+#define TRACING_REPLAY_FAILED 3
+
+#define TRACING_REPLAY_MODE_REALTIME 0
+#define TRACING_REPLAY_MODE_DETERMINISTIC 1
+
+/* A replay which is driven through the C API. */
+typedef struct TracingReplay TracingReplay;
+
+/* The progress of a replay, see `tracing_replay_poll`. */
+typedef struct TracingReplayProgress {
+    /* One of the TRACING_REPLAY_* states. */
+    int state;
+    /* The number of records which have been dispatched so far. */
+    uint64_t records_dispatched;
+    /* The number of records which were replayed, once the replay has finished. */
+    uint64_t record_count;
+} TracingReplayProgress;
+
+/* Opens the recording at `path` to replay, returns NULL if it doesn't exist. */
+TracingReplay *tracing_replay_open(const char *path);
+
+/* Sets the speed of the replay, which must be greater than zero. */
+int tracing_replay_set_speed(TracingReplay *replay, double speed);
+
+/* Sets the mode of the replay, before it is started. */
+int tracing_replay_set_mode(TracingReplay *replay, int mode);
+
+/* Starts replaying the recording, on a thread of its own. */
+int tracing_replay_start(TracingReplay *replay);
+
+/* Writes the progress of the replay to `progress`. */
+int tracing_replay_poll(TracingReplay *replay, TracingReplayProgress *progress);
+
+/* Returns the reason for the last failure, or NULL. Valid until the next call with `replay`. */
+const char *tracing_replay_last_error(const TracingReplay *replay);
+
+/* Waits for the replay to finish, if it was started, and frees it. */
+int tracing_replay_close(TracingReplay *replay);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* TRACING_REPLAY_H */
@@ -0,0 +1,221 @@
+use std::{collections::HashMap, time::Duration};
+
+use crate::{
+    reader::Lines,
+    recording::{Parent, SpanId, TraceRecordRef, TraceRef},
+    ReplayFileError,
+};
+
+/// How a recording is multiplied, see [`Replay::with_amplification`].
+///
+/// [`Replay::with_amplification`]: fn@crate::Replay::with_amplification
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Amplification {
+    pub(crate) copies: usize,
+    pub(crate) max_jitter: Duration,
+}
+
+impl Amplification {
+    /// Returns the records of each copy of the recording in `lines`, merged by their timestamps.
+    pub(crate) fn records<'a>(&self, lines: &Lines<'a>) -> AmplifiedRecords<'a> {
+        let mut jitter = Jitter::default();
+        let copies = (0..self.copies)
+            .map(|copy| AmplifiedCopy {
+                lines: lines.clone(),
+                // The first copy is the recording itself, so that the replay starts when it does.
+                offset: if copy == 0 {
+                    Duration::ZERO
+                } else {
+                    jitter.up_to(self.max_jitter)
+                },
+                thread_suffix: (copy > 0).then(|| format!("#{copy}")),
+                span_ids: HashMap::new(),
+                next: None,
+            })
+            .collect();
+
+        AmplifiedRecords {
+            copies,
+            next_span_id: 1,
+            unknown_records: 0,
+            error: None,
+        }
+    }
+}
+
+/// The records of several copies of a recording, in timestamp order.
+///
+/// Each copy is read from the recording separately, so only the records which are about to be
+/// replayed are held in memory, however many copies there are.
+pub(crate) struct AmplifiedRecords<'a> {
+    copies: Vec<AmplifiedCopy<'a>>,
+    /// The span Id given to the next span in any copy, so that span Ids are unique across copies.
+    next_span_id: u64,
+    /// The number of records with an unknown kind of trace which were skipped in any copy and
+    /// haven't been returned yet.
+    unknown_records: usize,
+    /// The first error from reading a copy, which is returned once the other copies are done.
+    error: Option<ReplayFileError>,
+}
+
+/// A copy of a recording, which is shifted in time and has its own threads and span Ids.
+struct AmplifiedCopy<'a> {
+    lines: Lines<'a>,
+    offset: Duration,
+    /// Appended to the recorded thread Ids, so that each copy is replayed on its own threads.
+    thread_suffix: Option<String>,
+    /// The span Id given to each recorded span Id in this copy.
+    span_ids: HashMap<SpanId, SpanId>,
+    /// The next record of this copy, which has already been read.
+    next: Option<TraceRecordRef<'a>>,
+}
+
+impl<'a> Iterator for AmplifiedRecords<'a> {
+    type Item = Result<Option<TraceRecordRef<'a>>, ReplayFileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut idx = 0;
+        while idx < self.copies.len() {
+            let copy = &mut self.copies[idx];
+            if copy.next.is_none() {
+                match copy.lines.next().map(|line| line.parse()) {
+                    Some(Ok(Some(record))) => {
+                        copy.next = Some(copy.amplify(record, &mut self.next_span_id));
+                    }
+                    Some(Ok(None)) => {
+                        // Read the next record of this copy instead.
+                        self.unknown_records += 1;
+                        continue;
+                    }
+                    Some(Err(err)) => {
+                        // The other copies will find the same error when they reach this line.
+                        self.error.get_or_insert(err);
+                        self.copies.remove(idx);
+                        continue;
+                    }
+                    None => {
+                        self.copies.remove(idx);
+                        continue;
+                    }
+                }
+            }
+            idx += 1;
+        }
+
+        // Skipped records are only counted, so they are returned as soon as they are found.
+        if self.unknown_records > 0 {
+            self.unknown_records -= 1;
+            return Some(Ok(None));
+        }
+
+        // Each copy is in recorded order, so the copies are merged without reordering them.
+        let earliest = self
+            .copies
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, copy)| Some((copy.next.as_ref()?.meta.timestamp(), idx)))
+            .min()
+            .and_then(|(_, idx)| self.copies[idx].next.take());
+        match earliest {
+            Some(record) => Some(Ok(Some(record))),
+            None => self.error.take().map(Err),
+        }
+    }
+}
+
+impl<'a> AmplifiedCopy<'a> {
+    fn amplify(
+        &mut self,
+        mut record: TraceRecordRef<'a>,
+        next_span_id: &mut u64,
+    ) -> TraceRecordRef<'a> {
+        let timestamp = record.meta.timestamp() + self.offset;
+        record.meta.timestamp_s = timestamp.as_secs();
+        record.meta.timestamp_subsec_us = timestamp.subsec_micros();
+        if let Some(suffix) = &self.thread_suffix {
+            record.meta.thread_id = format!("{}{suffix}", record.meta.thread_id.as_str()).into();
+        }
+
+        match &mut record.trace {
+            TraceRef::RegisterCallsite(_) => {}
+            TraceRef::NewSpan(new_span) => {
+                self.map_parent(&mut new_span.parent, next_span_id);
+                // A recorded span Id may be reused once its span is closed, the new span is given
+                // a new Id too.
+                let id = SpanId::from(*next_span_id);
+                *next_span_id += 1;
+                self.span_ids.insert(new_span.id, id);
+                new_span.id = id;
+            }
+            TraceRef::Event(event) => self.map_parent(&mut event.parent, next_span_id),
+            TraceRef::Enter(id) | TraceRef::Exit(id) | TraceRef::Close(id) => {
+                *id = self.map_span_id(*id, next_span_id);
+            }
+            TraceRef::Record(record_values) => {
+                record_values.id = self.map_span_id(record_values.id, next_span_id);
+            }
+            TraceRef::FollowsFrom(follows_from) => {
+                follows_from.cause_id = self.map_span_id(follows_from.cause_id, next_span_id);
+                follows_from.effect_id = self.map_span_id(follows_from.effect_id, next_span_id);
+            }
+        }
+
+        record
+    }
+
+    fn map_parent(&mut self, parent: &mut Parent, next_span_id: &mut u64) {
+        if let Parent::Explicit(id) = parent {
+            *id = self.map_span_id(*id, next_span_id);
+        }
+    }
+
+    /// Returns the span Id given to the recorded span Id `id` in this copy.
+    ///
+    /// Spans which were created before the recording started are given an Id when they are first
+    /// referred to.
+    fn map_span_id(&mut self, id: SpanId, next_span_id: &mut u64) -> SpanId {
+        *self.span_ids.entry(id).or_insert_with(|| {
+            *next_span_id += 1;
+            SpanId::from(*next_span_id - 1)
+        })
+    }
+}
+
+/// A deterministic source of jitter, so that amplified and chaotic replays are reproducible.
+#[derive(Debug, Default)]
+pub(crate) struct Jitter {
+    state: u64,
+}
+
+impl Jitter {
+    /// Creates a source of jitter which starts from `seed`.
+    pub(crate) fn seeded(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Returns a duration between zero and `max`, inclusive.
+    pub(crate) fn up_to(&mut self, max: Duration) -> Duration {
+        let z = self.next_u64();
+        let max_us = u64::try_from(max.as_micros()).unwrap_or(u64::MAX);
+        match max_us.checked_add(1) {
+            Some(range) => Duration::from_micros(z % range),
+            None => Duration::from_micros(z),
+        }
+    }
+
+    /// Returns `true` with the given `probability`, between `0.0` and `1.0`.
+    pub(crate) fn chance(&mut self, probability: f64) -> bool {
+        // The top 53 bits are as many as an `f64` in `[0, 1)` can hold.
+        let sample = (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64;
+        sample < probability
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        // SplitMix64.
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
@@ -0,0 +1,143 @@
+use std::fmt;
+
+use crate::{
+    clock::ReplayClock,
+    recording::{FieldValueRef, TraceRecord, TraceRef},
+};
+
+/// A condition which pauses the replay when it is met.
+///
+/// See [`Replay::with_breakpoint`] for details.
+///
+/// [`Replay::with_breakpoint`]: fn@crate::Replay::with_breakpoint
+#[non_exhaustive]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Breakpoint {
+    /// Breaks when a span with the given name is entered.
+    SpanEntered {
+        /// The name of the span.
+        name: String,
+    },
+    /// Breaks when an event with the given name is dispatched.
+    Event {
+        /// The name of the event.
+        name: String,
+    },
+    /// Breaks when an event with the given field value is dispatched.
+    ///
+    /// The value is compared with the text representation of the recorded value, so `"true"`
+    /// matches both a `bool` field which was `true` and a field which was recorded with its
+    /// `Debug` implementation as `true`.
+    EventField {
+        /// The name of the field.
+        name: String,
+        /// The text representation of the field value.
+        value: String,
+    },
+}
+
+impl Breakpoint {
+    /// Breaks when a span with the given name is entered.
+    pub fn span_entered(name: impl Into<String>) -> Self {
+        Self::SpanEntered { name: name.into() }
+    }
+
+    /// Breaks when an event with the given name is dispatched.
+    pub fn event(name: impl Into<String>) -> Self {
+        Self::Event { name: name.into() }
+    }
+
+    /// Breaks when an event with the given field value is dispatched.
+    pub fn event_field(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self::EventField {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+
+    /// Returns whether the trace hits this breakpoint.
+    ///
+    /// The name of the span being entered is passed in `entered_span_name`, as it isn't part of
+    /// the recorded trace.
+    fn is_hit(&self, trace: &TraceRef<'_>, entered_span_name: Option<&str>) -> bool {
+        match (self, trace) {
+            (Self::SpanEntered { name }, TraceRef::Enter(_)) => {
+                entered_span_name == Some(name.as_str())
+            }
+            (Self::Event { name }, TraceRef::Event(event)) => event.metadata.name.as_str() == name,
+            (Self::EventField { name, value }, TraceRef::Event(event)) => {
+                event.fields.iter().any(|field| {
+                    field.name.as_str() == name && field_value_matches(&field.value, value)
+                })
+            }
+            _ => false,
+        }
+    }
+}
+
+fn field_value_matches(field_value: &FieldValueRef<'_>, value: &str) -> bool {
+    match field_value {
+        FieldValueRef::Debug(val) | FieldValueRef::Str(val) => val.as_str() == value,
+        FieldValueRef::F64(val) => val.to_string() == value,
+        FieldValueRef::I64(val) => val.to_string() == value,
+        FieldValueRef::U64(val) => val.to_string() == value,
+        FieldValueRef::I128(val) => val.to_string() == value,
+        FieldValueRef::U128(val) => val.to_string() == value,
+        FieldValueRef::Bool(val) => val.to_string() == value,
+        FieldValueRef::Json(val) => serde_json::to_string(val).is_ok_and(|json| json == value),
+    }
+}
+
+type BreakpointCallback = Box<dyn FnMut(&TraceRecord) + Send>;
+
+/// The breakpoints registered on a replay, together with their callbacks.
+#[derive(Default)]
+pub(crate) struct Breakpoints {
+    breakpoints: Vec<(Breakpoint, BreakpointCallback)>,
+}
+
+impl Breakpoints {
+    pub(crate) fn push(&mut self, breakpoint: Breakpoint, callback: BreakpointCallback) {
+        self.breakpoints.push((breakpoint, callback));
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+    }
+
+    /// Calls the callback of each breakpoint which `trace` hits.
+    ///
+    /// The clock is paused while the callbacks run, so that the replay continues on the same
+    /// schedule afterwards.
+    pub(crate) fn hit(
+        &mut self,
+        clock: &ReplayClock,
+        trace: &TraceRef<'_>,
+        entered_span_name: Option<&str>,
+        record: impl FnOnce() -> TraceRecord,
+    ) {
+        let mut hits = self
+            .breakpoints
+            .iter_mut()
+            .filter(|(breakpoint, _)| breakpoint.is_hit(trace, entered_span_name))
+            .peekable();
+        if hits.peek().is_none() {
+            return;
+        }
+
+        let record = record();
+        clock.pause();
+        for (_, callback) in hits {
+            callback(&record);
+        }
+        clock.resume();
+    }
+}
+
+impl fmt::Debug for Breakpoints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.breakpoints.iter().map(|(breakpoint, _)| breakpoint))
+            .finish()
+    }
+}
@@ -0,0 +1,130 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use tracing::Metadata;
+
+use crate::{
+    recording::{Kind, Level, MetadataRef},
+    replay_metadata,
+};
+
+/// A cache of the callsite metadata created by replays.
+///
+/// The metadata for replayed callsites must live for the rest of the program, so it is leaked
+/// when a callsite is first replayed. Each [`Replay`] has its own cache by default, which means
+/// that replaying the same recording again leaks the same metadata again. This adds up when a
+/// recording is replayed many times, for example in a load test.
+///
+/// A `MetadataCache` can be created once and shared between many replays with
+/// [`Replay::with_metadata_cache`]. Metadata is interned by its contents: the name, target,
+/// level, module path, location, field names, and kind of the callsite. Replays which share a
+/// cache reuse the metadata created by any of them, so each distinct callsite is only leaked
+/// once. The recorded callsite Id isn't part of the contents, as it changes between runs of the
+/// recorded program.
+///
+/// Cloning a `MetadataCache` returns a handle to the same cache.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_replay::{MetadataCache, Replay};
+///
+/// let recording = concat!(
+///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543496,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#,
+///     "\n",
+/// );
+///
+/// let cache = MetadataCache::new();
+/// for _ in 0..3 {
+///     let mut replay = Replay::new().with_metadata_cache(cache.clone());
+///     replay.replay_str(recording).unwrap();
+///     replay.close().unwrap();
+/// }
+/// assert_eq!(cache.len(), 1);
+/// ```
+///
+/// [`Replay`]: struct@crate::Replay
+/// [`Replay::with_metadata_cache`]: fn@crate::Replay::with_metadata_cache
+#[derive(Clone, Default)]
+pub struct MetadataCache {
+    metadata: Arc<Mutex<HashMap<MetadataKey, &'static Metadata<'static>>>>,
+}
+
+/// The contents of callsite metadata which it is interned by.
+#[derive(Debug, Eq, Hash, PartialEq)]
+struct MetadataKey {
+    name: String,
+    target: String,
+    level: Level,
+    module_path: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+    fields: Vec<String>,
+    kind: Kind,
+}
+
+impl From<&MetadataRef<'_>> for MetadataKey {
+    fn from(value: &MetadataRef<'_>) -> Self {
+        Self {
+            name: value.name.as_str().to_owned(),
+            target: value.target.as_str().to_owned(),
+            level: value.level.clone(),
+            module_path: value.module_path.as_ref().map(|s| s.as_str().to_owned()),
+            file: value.file.as_ref().map(|s| s.as_str().to_owned()),
+            line: value.line,
+            fields: value
+                .fields
+                .iter()
+                .map(|field| field.as_str().to_owned())
+                .collect(),
+            kind: value.kind.clone(),
+        }
+    }
+}
+
+impl MetadataCache {
+    /// Creates a new, empty cache.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of distinct callsites in the cache.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Returns `true` if the cache doesn't contain any callsites.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Returns the metadata for the recorded callsite, creating it if it isn't in the cache.
+    pub(crate) fn get_or_create(
+        &self,
+        rec_metadata: MetadataRef<'_>,
+    ) -> &'static Metadata<'static> {
+        self.lock()
+            .entry(MetadataKey::from(&rec_metadata))
+            .or_insert_with(|| Box::leak(Box::new(replay_metadata(rec_metadata.into()))))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<MetadataKey, &'static Metadata<'static>>> {
+        self.metadata
+            .lock()
+            .expect("metadata cache has become corrupted.")
+    }
+}
+
+impl fmt::Debug for MetadataCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetadataCache")
+            .field("len", &self.len())
+            .finish()
+    }
+}
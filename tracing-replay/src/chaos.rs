@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use crate::{amplify::Jitter, recording::TraceRef};
+
+/// Degrades a replay on purpose, see [`Replay::with_chaos`].
+///
+/// By default, nothing is degraded. Random latency is added with [`with_jitter`], and records
+/// are dropped with [`with_drop_probability`]. The random choices are made from a seed, so that
+/// replaying the same recording with the same seed degrades it in the same way.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use tracing_replay::{Chaos, Replay};
+///
+/// let replay = Replay::new().with_chaos(
+///     Chaos::new()
+///         .with_jitter(Duration::from_millis(50))
+///         .with_drop_probability(0.01)
+///         .with_seed(42),
+/// );
+/// ```
+///
+/// [`Replay::with_chaos`]: fn@crate::Replay::with_chaos
+/// [`with_jitter`]: fn@Self::with_jitter
+/// [`with_drop_probability`]: fn@Self::with_drop_probability
+#[derive(Clone, Debug, Default)]
+pub struct Chaos {
+    max_jitter: Duration,
+    drop_probability: f64,
+    seed: u64,
+}
+
+impl Chaos {
+    /// Creates a chaos configuration which doesn't degrade anything.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delays the dispatch of each record by a random time up to `max_jitter`.
+    ///
+    /// Records are still dispatched in order on each replay thread, so a record which is delayed
+    /// holds up the records after it on the same thread, as a slow exporter would. Jitter only
+    /// applies in [`ReplayMode::Realtime`], as the other modes don't wait for records to be due.
+    ///
+    /// [`ReplayMode::Realtime`]: crate::ReplayMode::Realtime
+    #[must_use]
+    pub fn with_jitter(mut self, max_jitter: Duration) -> Self {
+        self.max_jitter = max_jitter;
+        self
+    }
+
+    /// Drops events and recorded span values at random, each with `probability`.
+    ///
+    /// Only records which the rest of the recording doesn't depend on are dropped, spans are
+    /// always created, entered, exited, and closed, so that the span tree stays intact. Dropped
+    /// records are counted in [`ReplaySummary::dropped_records`] instead of being replayed.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `probability` is not between `0.0` and `1.0`.
+    ///
+    /// [`ReplaySummary::dropped_records`]: crate::ReplaySummary::dropped_records
+    #[must_use]
+    pub fn with_drop_probability(mut self, probability: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&probability),
+            "chaos drop probability must be between 0 and 1, but got {probability}"
+        );
+        self.drop_probability = probability;
+        self
+    }
+
+    /// Sets the seed that the random jitter and drops are chosen from, `0` by default.
+    #[must_use]
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+/// Applies a [`Chaos`] configuration to the records of a replay.
+#[derive(Debug)]
+pub(crate) struct ChaosInjector {
+    chaos: Chaos,
+    jitter: Jitter,
+}
+
+impl ChaosInjector {
+    pub(crate) fn new(chaos: Chaos) -> Self {
+        let jitter = Jitter::seeded(chaos.seed);
+        Self { chaos, jitter }
+    }
+
+    /// Returns whether `trace` should be dropped from the replay.
+    pub(crate) fn drops(&mut self, trace: &TraceRef<'_>) -> bool {
+        let droppable = matches!(trace, TraceRef::Event(_) | TraceRef::Record(_));
+        droppable
+            && self.chaos.drop_probability > 0.0
+            && self.jitter.chance(self.chaos.drop_probability)
+    }
+
+    /// Returns how much later than recorded the next record is dispatched.
+    pub(crate) fn delay(&mut self) -> Duration {
+        if self.chaos.max_jitter.is_zero() {
+            Duration::ZERO
+        } else {
+            self.jitter.up_to(self.chaos.max_jitter)
+        }
+    }
+}
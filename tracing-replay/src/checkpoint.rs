@@ -0,0 +1,178 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    index::{Checkpoint, Position, PrologueTracker},
+    recording::TraceRecordRef,
+    ReplayFileError,
+};
+
+/// The current version of the checkpoint format.
+const CHECKPOINT_VERSION: u32 = 1;
+
+/// The progress of a replay, from which an interrupted replay can be resumed.
+///
+/// Checkpoints are written to a sidecar file next to the recording, see [`sidecar_path`], while
+/// a [`Replay`] configured with [`Replay::with_checkpoints`] replays a file. An interrupted replay
+/// can then be continued with [`Replay::resume_from_checkpoint`].
+///
+/// A checkpoint stores the byte position of the next record to replay together with a prologue:
+/// the records which are needed to reconstruct the state of the replay at that position. This
+/// includes all the callsite registrations seen so far as well as the creation, recorded values,
+/// and entering of all the spans that are still open. Spans are given new span Ids by the
+/// subscriber when they are created again, so the mapping from recorded span Ids is rebuilt when
+/// the prologue is replayed.
+///
+/// [`Replay`]: struct@crate::Replay
+/// [`Replay::with_checkpoints`]: fn@crate::Replay::with_checkpoints
+/// [`Replay::resume_from_checkpoint`]: fn@crate::Replay::resume_from_checkpoint
+/// [`sidecar_path`]: fn@Self::sidecar_path
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ReplayCheckpoint {
+    version: u32,
+    start_timestamp_s: u64,
+    start_timestamp_subsec_us: u32,
+    checkpoint: Checkpoint,
+}
+
+impl ReplayCheckpoint {
+    /// Returns the path of the sidecar checkpoint for the recording at `recording_path`.
+    #[must_use]
+    pub fn sidecar_path(recording_path: &str) -> String {
+        format!("{recording_path}.ckpt")
+    }
+
+    /// Reads the sidecar checkpoint for the recording at `recording_path`.
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if the sidecar file cannot be read or deserialized.
+    pub fn read_sidecar(recording_path: &str) -> Result<Self, ReplayFileError> {
+        let file = File::open(Self::sidecar_path(recording_path))
+            .map_err(|io_err| ReplayFileError::CannotReadCheckpoint { inner: io_err })?;
+        let checkpoint: Self = serde_json::from_reader(BufReader::new(file))
+            .map_err(|err| ReplayFileError::CannotDeserializeCheckpoint { inner: err })?;
+
+        if checkpoint.version == CHECKPOINT_VERSION {
+            Ok(checkpoint)
+        } else {
+            Err(ReplayFileError::UnsupportedCheckpointVersion {
+                version: checkpoint.version,
+            })
+        }
+    }
+
+    /// The offset from the start of the recording at which the replay will be resumed.
+    #[must_use]
+    pub fn offset(&self) -> Duration {
+        Duration::from_micros(self.checkpoint.offset_us)
+    }
+
+    /// The timestamp of the first record in the recording.
+    pub(crate) fn start(&self) -> Duration {
+        Duration::from_secs(self.start_timestamp_s).saturating_add(Duration::from_micros(
+            u64::from(self.start_timestamp_subsec_us),
+        ))
+    }
+
+    pub(crate) fn checkpoint(&self) -> &Checkpoint {
+        &self.checkpoint
+    }
+
+    /// Writes the checkpoint to the sidecar file for the recording at `recording_path`.
+    ///
+    /// The checkpoint is written to a temporary file first and then moved into place, so that an
+    /// interruption while writing doesn't leave a corrupt checkpoint behind.
+    fn write_sidecar(&self, recording_path: &str) -> Result<(), io::Error> {
+        let path = Self::sidecar_path(recording_path);
+        let tmp_path = format!("{path}.tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.into_inner()?.sync_all()?;
+        fs::rename(tmp_path, path)
+    }
+}
+
+/// Writes checkpoints for a replay of the recording at `recording_path`.
+#[derive(Debug)]
+pub(crate) struct Checkpointer<'p> {
+    recording_path: &'p str,
+    interval: Duration,
+    tracker: PrologueTracker,
+    start: Option<Duration>,
+    next_checkpoint: Duration,
+}
+
+impl<'p> Checkpointer<'p> {
+    pub(crate) fn new(recording_path: &'p str, interval: Duration) -> Self {
+        Self {
+            recording_path,
+            interval,
+            tracker: PrologueTracker::default(),
+            start: None,
+            next_checkpoint: Duration::ZERO,
+        }
+    }
+
+    /// Continues checkpointing a replay of the recording in `data` which starts at `checkpoint`.
+    pub(crate) fn resume(&mut self, start: Duration, checkpoint: &Checkpoint, data: &[u8]) {
+        self.start = Some(start);
+        self.next_checkpoint = Duration::from_micros(checkpoint.offset_us) + self.interval;
+        self.tracker = PrologueTracker::from_prologue(data, &checkpoint.prologue);
+    }
+
+    /// Returns the offset of `record` from the start of the recording if a checkpoint is due
+    /// before it is replayed.
+    pub(crate) fn due(&mut self, record: &TraceRecordRef<'_>) -> Option<Duration> {
+        let timestamp = record.meta.timestamp();
+        let offset = timestamp.saturating_sub(*self.start.get_or_insert(timestamp));
+        (offset >= self.next_checkpoint).then_some(offset)
+    }
+
+    /// Writes a checkpoint at `position`, which is `offset` from the start of the recording.
+    pub(crate) fn write(
+        &mut self,
+        offset: Duration,
+        position: Position,
+    ) -> Result<(), ReplayFileError> {
+        let start = self.start.unwrap_or_default();
+        let checkpoint = ReplayCheckpoint {
+            version: CHECKPOINT_VERSION,
+            start_timestamp_s: start.as_secs(),
+            start_timestamp_subsec_us: start.subsec_micros(),
+            checkpoint: Checkpoint {
+                offset_us: u64::try_from(offset.as_micros()).unwrap_or(u64::MAX),
+                position,
+                prologue: self.tracker.prologue(),
+            },
+        };
+        checkpoint
+            .write_sidecar(self.recording_path)
+            .map_err(|io_err| ReplayFileError::CannotWriteCheckpoint { inner: io_err })?;
+        while self.next_checkpoint <= offset {
+            self.next_checkpoint += self.interval;
+        }
+
+        Ok(())
+    }
+
+    /// Tracks the record at `position`, which is about to be replayed.
+    pub(crate) fn track(&mut self, record: &TraceRecordRef<'_>, position: Position) {
+        self.tracker.track(&record.trace, position);
+    }
+
+    /// Removes the checkpoint once the replay has completed.
+    pub(crate) fn complete(self) -> Result<(), ReplayFileError> {
+        match fs::remove_file(ReplayCheckpoint::sidecar_path(self.recording_path)) {
+            Err(io_err) if io_err.kind() != io::ErrorKind::NotFound => {
+                Err(ReplayFileError::CannotWriteCheckpoint { inner: io_err })
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    sync::{Condvar, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use crate::time::Instant;
+
+/// The clock that paces a replay.
+///
+/// Recorded timestamps are mapped onto the replay's timeline relative to an anchor: the recorded
+/// timestamp and the [`Instant`] at which that timestamp was replayed. The speed scales the time
+/// between the anchor and any later recorded timestamp.
+///
+/// Whenever the speed changes or the clock is resumed after being paused, the clock is
+/// re-anchored at its current position and any dispatcher threads waiting on it are woken so
+/// they can recalculate their deadlines.
+#[derive(Debug)]
+pub(crate) struct ReplayClock {
+    state: Mutex<ClockState>,
+    changed: Condvar,
+}
+
+#[derive(Debug)]
+struct ClockState {
+    speed: f64,
+    anchor_instant: Instant,
+    anchor_recorded: Duration,
+    /// The latest recorded timestamp that has been waited for. Used to determine the clock's
+    /// position when replaying at infinite speed.
+    latest_recorded: Duration,
+    /// The instant at which the clock was paused, if it is paused.
+    paused_at: Option<Instant>,
+}
+
+impl ReplayClock {
+    pub(crate) fn new(speed: f64) -> Self {
+        assert_valid_speed(speed);
+
+        Self {
+            state: Mutex::new(ClockState {
+                speed,
+                anchor_instant: Instant::now(),
+                anchor_recorded: Duration::ZERO,
+                latest_recorded: Duration::ZERO,
+                paused_at: None,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Anchors the recorded timestamp to the current instant.
+    ///
+    /// This is done at the beginning of each recording so that traces are replayed on the same
+    /// schedule as they were recorded.
+    pub(crate) fn anchor(&self, recorded: Duration) {
+        let mut state = self.lock();
+        state.anchor_instant = Instant::now();
+        state.anchor_recorded = recorded;
+        state.latest_recorded = recorded;
+        self.changed.notify_all();
+    }
+
+    pub(crate) fn speed(&self) -> f64 {
+        self.lock().speed
+    }
+
+    pub(crate) fn set_speed(&self, speed: f64) {
+        assert_valid_speed(speed);
+
+        let mut state = self.lock();
+        let now = Instant::now();
+        state.anchor_recorded = state.position(now);
+        state.anchor_instant = now;
+        state.speed = speed;
+        self.changed.notify_all();
+    }
+
+    /// Stops the clock at its current position until it is resumed.
+    pub(crate) fn pause(&self) {
+        let mut state = self.lock();
+        if state.paused_at.is_none() {
+            state.paused_at = Some(Instant::now());
+        }
+    }
+
+    /// Restarts the clock from the position at which it was paused.
+    pub(crate) fn resume(&self) {
+        let mut state = self.lock();
+        if let Some(paused_at) = state.paused_at.take() {
+            state.anchor_recorded = state.position(paused_at);
+            state.anchor_instant = Instant::now();
+            self.changed.notify_all();
+        }
+    }
+
+    /// Blocks the current thread until the recorded timestamp is reached.
+    ///
+    /// Returns how late the recorded timestamp was reached compared to the schedule. When
+    /// replaying at infinite speed there is no schedule, so the lag is always zero.
+    pub(crate) fn wait_until(&self, recorded: Duration) -> Duration {
+        let mut state = self.lock();
+        let lag = loop {
+            let now = Instant::now();
+            match state.deadline(recorded) {
+                Some(_) if state.speed.is_infinite() => break Duration::ZERO,
+                Some(deadline) if deadline <= now => break now - deadline,
+                Some(deadline) => {
+                    state = self
+                        .changed
+                        .wait_timeout(state, deadline - now)
+                        .expect("replay internal state (clock) has become corrupted.")
+                        .0;
+                }
+                None => {
+                    state = self
+                        .changed
+                        .wait(state)
+                        .expect("replay internal state (clock) has become corrupted.");
+                }
+            }
+        };
+        state.latest_recorded = state.latest_recorded.max(recorded);
+
+        lag
+    }
+
+    /// Returns how long to wait until the recorded timestamp is reached, without blocking.
+    ///
+    /// Returns zero once the timestamp has been reached, or `None` while the clock is paused.
+    pub(crate) fn time_until(&self, recorded: Duration) -> Option<Duration> {
+        let state = self.lock();
+        let deadline = state.deadline(recorded)?;
+        if state.speed.is_infinite() {
+            Some(Duration::ZERO)
+        } else {
+            Some(deadline.saturating_duration_since(Instant::now()))
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ClockState> {
+        self.state
+            .lock()
+            .expect("replay internal state (clock) has become corrupted.")
+    }
+}
+
+impl ClockState {
+    /// The instant at which the recorded timestamp should be replayed.
+    ///
+    /// Returns `None` if the clock is paused or if the deadline is too far in the future to be
+    /// represented.
+    fn deadline(&self, recorded: Duration) -> Option<Instant> {
+        if self.paused_at.is_some() {
+            return None;
+        }
+        let since_anchor = recorded.saturating_sub(self.anchor_recorded);
+        self.anchor_instant
+            .checked_add(scale(since_anchor, 1.0 / self.speed))
+    }
+
+    /// The recorded timestamp which corresponds to the instant `now`.
+    fn position(&self, now: Instant) -> Duration {
+        let now = self.paused_at.map_or(now, |paused_at| paused_at.min(now));
+        if self.speed.is_infinite() {
+            self.latest_recorded.max(self.anchor_recorded)
+        } else {
+            let elapsed = now.saturating_duration_since(self.anchor_instant);
+            self.anchor_recorded
+                .saturating_add(scale(elapsed, self.speed))
+        }
+    }
+}
+
+fn scale(duration: Duration, factor: f64) -> Duration {
+    Duration::try_from_secs_f64(duration.as_secs_f64() * factor).unwrap_or(Duration::MAX)
+}
+
+fn assert_valid_speed(speed: f64) {
+    assert!(
+        speed > 0.0,
+        "replay speed must be greater than zero, but got {speed}"
+    );
+}
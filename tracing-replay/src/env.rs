@@ -0,0 +1,105 @@
+use std::{error, fmt};
+
+use tracing_subscriber::filter::{ParseError, Targets};
+
+use crate::{Replay, ReplayMode};
+
+/// The environment variable which sets the replay speed.
+pub(crate) const SPEED_VAR: &str = "TRACING_REPLAY_SPEED";
+/// The environment variable which sets the replay mode.
+pub(crate) const MODE_VAR: &str = "TRACING_REPLAY_MODE";
+/// The environment variable which sets the filter of spans and events to replay.
+pub(crate) const FILTER_VAR: &str = "TRACING_REPLAY_FILTER";
+
+/// An environment variable read by [`Replay::from_env`] has a value which isn't valid.
+///
+/// [`Replay::from_env`]: fn@crate::Replay::from_env
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum ReplayEnvError {
+    /// `TRACING_REPLAY_SPEED` isn't a number greater than zero.
+    InvalidSpeed {
+        /// The value of the variable.
+        value: String,
+    },
+    /// `TRACING_REPLAY_MODE` isn't one of the replay modes.
+    InvalidMode {
+        /// The value of the variable.
+        value: String,
+    },
+    /// `TRACING_REPLAY_FILTER` isn't a valid filter.
+    InvalidFilter {
+        /// The value of the variable.
+        value: String,
+        /// Why the filter couldn't be parsed.
+        inner: ParseError,
+    },
+}
+
+impl fmt::Display for ReplayEnvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSpeed { value } => write!(
+                f,
+                "{SPEED_VAR} must be a number greater than zero, but is {value:?}"
+            ),
+            Self::InvalidMode { value } => write!(
+                f,
+                "{MODE_VAR} must be one of realtime, deterministic, or instant, but is {value:?}"
+            ),
+            Self::InvalidFilter { value, inner } => {
+                write!(f, "{FILTER_VAR} is not a valid filter ({inner}): {value:?}")
+            }
+        }
+    }
+}
+
+impl error::Error for ReplayEnvError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::InvalidFilter { inner, .. } => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+/// Configures `replay` from the variables returned by `var`, which are unset if it returns
+/// `None`.
+pub(crate) fn configure(
+    mut replay: Replay,
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<Replay, ReplayEnvError> {
+    // Variables which are set to an empty value are treated as unset.
+    let var = |name| var(name).filter(|value: &String| !value.trim().is_empty());
+
+    if let Some(value) = var(SPEED_VAR) {
+        let speed = value
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|speed| *speed > 0.0)
+            .ok_or(ReplayEnvError::InvalidSpeed { value })?;
+        replay = replay.with_speed(speed);
+    }
+
+    if let Some(value) = var(MODE_VAR) {
+        replay = match value.trim().to_ascii_lowercase().as_str() {
+            "realtime" => replay.with_mode(ReplayMode::Realtime),
+            "deterministic" => replay.with_mode(ReplayMode::Deterministic),
+            "instant" => replay
+                .with_mode(ReplayMode::Realtime)
+                .with_speed(f64::INFINITY),
+            _ => return Err(ReplayEnvError::InvalidMode { value }),
+        };
+    }
+
+    if let Some(value) = var(FILTER_VAR) {
+        let filter = match value.parse::<Targets>() {
+            Ok(filter) => filter,
+            Err(inner) => return Err(ReplayEnvError::InvalidFilter { value, inner }),
+        };
+        replay = replay.with_filter(filter);
+    }
+
+    Ok(replay)
+}
@@ -0,0 +1,229 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
+};
+
+use tracing::{
+    span::{self, Attributes},
+    subscriber::Interest,
+    Dispatch, Event, Metadata, Subscriber,
+};
+use tracing_core::span::Current;
+
+use crate::proxy::reparent_event;
+
+/// A subscriber which dispatches everything it receives to several dispatch targets.
+///
+/// Each target decides independently whether it is interested in a span or an event. Spans are
+/// given their own span Ids, which are mapped to the span Ids assigned by each of the targets
+/// which enabled the span.
+#[derive(Debug)]
+pub(crate) struct FanOut {
+    targets: Vec<Dispatch>,
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, FanOutSpan>>,
+}
+
+#[derive(Debug)]
+struct FanOutSpan {
+    /// The span Id assigned by each target, or `None` if the target didn't enable the span.
+    target_ids: Vec<Option<span::Id>>,
+    ref_count: usize,
+}
+
+impl FanOut {
+    pub(crate) fn new(targets: Vec<Dispatch>) -> Self {
+        Self {
+            targets,
+            next_id: AtomicU64::new(1),
+            spans: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn lock_spans(&self) -> MutexGuard<'_, HashMap<u64, FanOutSpan>> {
+        self.spans
+            .lock()
+            .expect("fan out span state has become corrupted.")
+    }
+
+    /// Returns the span Ids assigned by each target to the span `id`.
+    fn target_ids(&self, id: &span::Id) -> Vec<Option<span::Id>> {
+        self.lock_spans().get(&id.into_u64()).map_or_else(
+            || vec![None; self.targets.len()],
+            |span| span.target_ids.clone(),
+        )
+    }
+
+    /// Calls `f` for each target which enabled the span `id`, with the target's span Id.
+    fn for_each_span(&self, id: &span::Id, mut f: impl FnMut(&Dispatch, &span::Id)) {
+        for (target, target_id) in self.targets.iter().zip(self.target_ids(id)) {
+            if let Some(target_id) = target_id {
+                f(target, &target_id);
+            }
+        }
+    }
+}
+
+impl Subscriber for FanOut {
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        let mut interests = self
+            .targets
+            .iter()
+            .map(|target| target.register_callsite(metadata));
+        let Some(first) = interests.next() else {
+            return Interest::never();
+        };
+        interests.fold(first, |combined, interest| {
+            if combined.is_always() && interest.is_always() {
+                Interest::always()
+            } else if combined.is_never() && interest.is_never() {
+                Interest::never()
+            } else {
+                Interest::sometimes()
+            }
+        })
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.targets.iter().any(|target| target.enabled(metadata))
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> span::Id {
+        let parent_ids = span.parent().map(|parent| self.target_ids(parent));
+        let target_ids =
+            self.targets
+                .iter()
+                .enumerate()
+                .map(|(idx, target)| {
+                    if !target.enabled(span.metadata()) {
+                        return None;
+                    }
+                    let target_id = match &parent_ids {
+                        // A parent which the target didn't enable doesn't exist for the target, so
+                        // the span is a root, just as it would be if the parent had been disabled
+                        // when the span was created.
+                        Some(parent_ids) => match &parent_ids[idx] {
+                            Some(parent_id) => target.new_span(&Attributes::child_of(
+                                parent_id.clone(),
+                                span.metadata(),
+                                span.values(),
+                            )),
+                            None => target
+                                .new_span(&Attributes::new_root(span.metadata(), span.values())),
+                        },
+                        None => target.new_span(span),
+                    };
+                    Some(target_id)
+                })
+                .collect();
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.lock_spans().insert(
+            id,
+            FanOutSpan {
+                target_ids,
+                ref_count: 1,
+            },
+        );
+
+        span::Id::from_u64(id)
+    }
+
+    fn record(&self, span: &span::Id, values: &span::Record<'_>) {
+        self.for_each_span(span, |target, target_id| target.record(target_id, values));
+    }
+
+    fn record_follows_from(&self, span: &span::Id, follows: &span::Id) {
+        let follows_ids = self.target_ids(follows);
+        for ((target, target_id), follows_id) in self
+            .targets
+            .iter()
+            .zip(self.target_ids(span))
+            .zip(follows_ids)
+        {
+            if let (Some(target_id), Some(follows_id)) = (target_id, follows_id) {
+                target.record_follows_from(&target_id, &follows_id);
+            }
+        }
+    }
+
+    fn event(&self, event: &Event<'_>) {
+        let parent_ids = event.parent().map(|parent| self.target_ids(parent));
+        for (idx, target) in self.targets.iter().enumerate() {
+            if !target.enabled(event.metadata()) {
+                continue;
+            }
+            match &parent_ids {
+                Some(parent_ids) => {
+                    reparent_event(event, parent_ids[idx].clone(), |event| target.event(event));
+                }
+                None => target.event(event),
+            }
+        }
+    }
+
+    fn enter(&self, span: &span::Id) {
+        self.for_each_span(span, Dispatch::enter);
+    }
+
+    fn exit(&self, span: &span::Id) {
+        // A registry closes spans through the default dispatcher when they are exited, so the
+        // target has to be the default, otherwise the span Id is passed back to the fan out.
+        self.for_each_span(span, |target, target_id| {
+            tracing::dispatcher::with_default(target, || target.exit(target_id));
+        });
+    }
+
+    fn clone_span(&self, id: &span::Id) -> span::Id {
+        if let Some(span) = self.lock_spans().get_mut(&id.into_u64()) {
+            span.ref_count += 1;
+        }
+        self.for_each_span(id, |target, target_id| {
+            target.clone_span(target_id);
+        });
+
+        id.clone()
+    }
+
+    fn try_close(&self, id: span::Id) -> bool {
+        // A registry closes the parent of a span through the default dispatcher, as above.
+        self.for_each_span(&id, |target, target_id| {
+            tracing::dispatcher::with_default(target, || target.try_close(target_id.clone()));
+        });
+
+        let mut spans = self.lock_spans();
+        let Some(span) = spans.get_mut(&id.into_u64()) else {
+            return false;
+        };
+        span.ref_count -= 1;
+        if span.ref_count > 0 {
+            return false;
+        }
+        spans.remove(&id.into_u64());
+
+        true
+    }
+
+    fn current_span(&self) -> Current {
+        // Targets only know about the spans they enabled, so the first target with a current
+        // span decides.
+        for (idx, target) in self.targets.iter().enumerate() {
+            let current = target.current_span();
+            let (Some(target_id), Some(metadata)) = (current.id(), current.metadata()) else {
+                continue;
+            };
+            let spans = self.lock_spans();
+            let id = spans.iter().find_map(|(id, span)| {
+                (span.target_ids[idx].as_ref() == Some(target_id)).then_some(*id)
+            });
+            if let Some(id) = id {
+                return Current::new(span::Id::from_u64(id), metadata);
+            }
+        }
+
+        Current::none()
+    }
+}
@@ -0,0 +1,355 @@
+//! A C API for driving a replay from other languages.
+//!
+//! This lets test harnesses written in languages such as Python or Go replay a recording into
+//! Rust components which are built into the same library. The traces are dispatched to the
+//! global default subscriber of the library, which the Rust components set up.
+//!
+//! A replay is opened with [`tracing_replay_open`], configured with [`tracing_replay_set_speed`]
+//! and [`tracing_replay_set_mode`], and started with [`tracing_replay_start`], which replays the
+//! recording on a thread of its own. Its progress is polled with [`tracing_replay_poll`], and it
+//! is closed with [`tracing_replay_close`], which waits for it to finish and frees it.
+//!
+//! Functions which can fail return `0` on success and `-1` on failure, the reason for the last
+//! failure is returned by [`tracing_replay_last_error`]. The declarations for C are in
+//! `include/tracing_replay.h`.
+//!
+//! The `tracing-replay-ffi` crate builds the C API as a C dynamic library,
+//! `libtracing_replay_ffi.so` (`.dylib` on macOS, `tracing_replay_ffi.dll` on Windows):
+//!
+//! ```sh
+//! cargo build -p tracing-replay-ffi --release
+//! ```
+//!
+//! To replay into Rust components, build them into a C dynamic library of their own which
+//! depends on `tracing-replay` with the `ffi` feature and re-exports this module with
+//! `pub use tracing_replay::ffi::*;`.
+//!
+//! # Examples
+//!
+//! Driving a replay through the C API, here from Rust:
+//!
+//! ```
+//! use std::{ffi::CString, thread, time::Duration};
+//!
+//! use tracing_replay::ffi::*;
+//!
+//! let path = CString::new("../sample-data/threads.tracing").unwrap();
+//! unsafe {
+//!     let replay = tracing_replay_open(path.as_ptr());
+//!     assert!(!replay.is_null());
+//!     assert_eq!(tracing_replay_set_mode(replay, TRACING_REPLAY_MODE_DETERMINISTIC), 0);
+//!     assert_eq!(tracing_replay_start(replay), 0);
+//!
+//!     let mut progress = TracingReplayProgress::default();
+//!     loop {
+//!         assert_eq!(tracing_replay_poll(replay, &mut progress), 0);
+//!         if progress.state != TRACING_REPLAY_RUNNING {
+//!             break;
+//!         }
+//!         thread::sleep(Duration::from_millis(1));
+//!     }
+//!     assert_eq!(progress.state, TRACING_REPLAY_FINISHED);
+//!     assert_eq!(progress.record_count, 14);
+//!     assert!(progress.records_dispatched > 0);
+//!     assert_eq!(tracing_replay_close(replay), 0);
+//! }
+//! ```
+
+use std::{
+    ffi::{c_char, c_int, CStr, CString},
+    fmt, ptr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::{Replay, ReplayHandle, ReplayMode};
+
+/// The replay hasn't been started yet.
+pub const TRACING_REPLAY_NOT_STARTED: c_int = 0;
+/// The replay is in progress.
+pub const TRACING_REPLAY_RUNNING: c_int = 1;
+/// The whole recording has been replayed.
+pub const TRACING_REPLAY_FINISHED: c_int = 2;
+/// The replay stopped because of an error, see [`tracing_replay_last_error`].
+pub const TRACING_REPLAY_FAILED: c_int = 3;
+
+/// Replays recorded traces in real time, see [`ReplayMode::Realtime`].
+pub const TRACING_REPLAY_MODE_REALTIME: c_int = 0;
+/// Replays recorded traces in order without delays, see [`ReplayMode::Deterministic`].
+pub const TRACING_REPLAY_MODE_DETERMINISTIC: c_int = 1;
+
+/// A replay which is driven through the C API.
+pub struct TracingReplay {
+    path: String,
+    state: ReplayState,
+    handle: ReplayHandle,
+    /// The number of records which have been dispatched so far.
+    dispatched: Arc<AtomicU64>,
+    last_error: Option<CString>,
+}
+
+enum ReplayState {
+    NotStarted(Box<Replay>),
+    Running(JoinHandle<Result<u64, String>>),
+    Finished(u64),
+    Failed,
+}
+
+impl fmt::Debug for TracingReplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TracingReplay")
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TracingReplay {
+    fn fail(&mut self, message: impl Into<String>) -> c_int {
+        // Interior nul bytes can't be passed to C, they are unlikely in an error message.
+        let message = message.into().replace('\0', " ");
+        self.last_error = Some(CString::new(message).expect("nul bytes were removed"));
+        -1
+    }
+
+    /// Moves a replay which has stopped running into its final state.
+    fn join(&mut self) {
+        let ReplayState::Running(join_handle) = &self.state else {
+            return;
+        };
+        if !join_handle.is_finished() {
+            return;
+        }
+        let ReplayState::Running(join_handle) =
+            std::mem::replace(&mut self.state, ReplayState::Failed)
+        else {
+            unreachable!("the replay is running");
+        };
+        match join_handle.join() {
+            Ok(Ok(record_count)) => self.state = ReplayState::Finished(record_count),
+            Ok(Err(message)) => {
+                self.fail(message);
+            }
+            Err(_) => {
+                self.fail("the replay thread panicked");
+            }
+        }
+    }
+}
+
+/// The progress of a replay, see [`tracing_replay_poll`].
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TracingReplayProgress {
+    /// One of `TRACING_REPLAY_NOT_STARTED`, `TRACING_REPLAY_RUNNING`, `TRACING_REPLAY_FINISHED`,
+    /// or `TRACING_REPLAY_FAILED`.
+    pub state: c_int,
+    /// The number of records which have been dispatched so far.
+    pub records_dispatched: u64,
+    /// The number of records which were replayed, once the replay has finished.
+    pub record_count: u64,
+}
+
+/// Opens the recording at `path` to replay.
+///
+/// Returns null if `path` isn't valid UTF-8 or the recording doesn't exist. The replay must be
+/// closed with [`tracing_replay_close`].
+///
+/// # Safety
+///
+/// `path` must be a valid pointer to a nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tracing_replay_open(path: *const c_char) -> *mut TracingReplay {
+    if path.is_null() {
+        return ptr::null_mut();
+    }
+    // SAFETY: the caller guarantees that `path` is a nul terminated string.
+    let Ok(path) = unsafe { CStr::from_ptr(path) }.to_str() else {
+        return ptr::null_mut();
+    };
+    if std::fs::metadata(path).is_err() {
+        return ptr::null_mut();
+    }
+
+    let dispatched = Arc::new(AtomicU64::new(0));
+    let hook_dispatched = Arc::clone(&dispatched);
+    let replay = Replay::new().on_dispatched(move |_record| {
+        hook_dispatched.fetch_add(1, Ordering::Relaxed);
+    });
+    Box::into_raw(Box::new(TracingReplay {
+        path: path.to_owned(),
+        handle: replay.handle(),
+        state: ReplayState::NotStarted(Box::new(replay)),
+        dispatched,
+        last_error: None,
+    }))
+}
+
+/// Sets the speed of the replay, which must be greater than zero.
+///
+/// The speed can be changed before the replay is started and while it is running.
+///
+/// # Safety
+///
+/// `replay` must have been returned by [`tracing_replay_open`] and not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn tracing_replay_set_speed(replay: *mut TracingReplay, speed: f64) -> c_int {
+    // SAFETY: the caller guarantees that `replay` is an open replay.
+    let Some(replay) = (unsafe { replay.as_mut() }) else {
+        return -1;
+    };
+    if speed.is_nan() || speed <= 0.0 {
+        return replay.fail(format!(
+            "the speed must be greater than zero, but is {speed}"
+        ));
+    }
+    replay.handle.set_speed(speed);
+    0
+}
+
+/// Sets the mode of the replay, `TRACING_REPLAY_MODE_REALTIME` or
+/// `TRACING_REPLAY_MODE_DETERMINISTIC`.
+///
+/// The mode can only be set before the replay is started.
+///
+/// # Safety
+///
+/// `replay` must have been returned by [`tracing_replay_open`] and not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn tracing_replay_set_mode(replay: *mut TracingReplay, mode: c_int) -> c_int {
+    // SAFETY: the caller guarantees that `replay` is an open replay.
+    let Some(replay) = (unsafe { replay.as_mut() }) else {
+        return -1;
+    };
+    let mode = match mode {
+        TRACING_REPLAY_MODE_REALTIME => ReplayMode::Realtime,
+        TRACING_REPLAY_MODE_DETERMINISTIC => ReplayMode::Deterministic,
+        mode => return replay.fail(format!("unknown replay mode: {mode}")),
+    };
+    let state = std::mem::replace(&mut replay.state, ReplayState::Failed);
+    let ReplayState::NotStarted(inner) = state else {
+        replay.state = state;
+        return replay.fail("the mode can't be set once the replay has started");
+    };
+    replay.state = ReplayState::NotStarted(Box::new(inner.with_mode(mode)));
+    0
+}
+
+/// Starts replaying the recording, on a thread of its own.
+///
+/// # Safety
+///
+/// `replay` must have been returned by [`tracing_replay_open`] and not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn tracing_replay_start(replay: *mut TracingReplay) -> c_int {
+    // SAFETY: the caller guarantees that `replay` is an open replay.
+    let Some(replay) = (unsafe { replay.as_mut() }) else {
+        return -1;
+    };
+    let state = std::mem::replace(&mut replay.state, ReplayState::Failed);
+    let ReplayState::NotStarted(mut inner) = state else {
+        replay.state = state;
+        return replay.fail("the replay has already been started");
+    };
+    let path = replay.path.clone();
+    let join_handle = thread::Builder::new()
+        .name("tracing-replay-ffi".into())
+        .spawn(move || {
+            let summary = inner
+                .replay_file(&path)
+                .map_err(|err| format!("failed to replay {path}: {err}"));
+            inner
+                .close()
+                .map_err(|err| format!("failed to close the replay: {err}"))?;
+            Ok(summary?.record_count as u64)
+        });
+    match join_handle {
+        Ok(join_handle) => {
+            replay.state = ReplayState::Running(join_handle);
+            0
+        }
+        Err(err) => replay.fail(format!("failed to start the replay thread: {err}")),
+    }
+}
+
+/// Writes the progress of the replay to `progress`.
+///
+/// # Safety
+///
+/// `replay` must have been returned by [`tracing_replay_open`] and not yet closed, and `progress`
+/// must be a valid pointer to a `TracingReplayProgress`.
+#[no_mangle]
+pub unsafe extern "C" fn tracing_replay_poll(
+    replay: *mut TracingReplay,
+    progress: *mut TracingReplayProgress,
+) -> c_int {
+    // SAFETY: the caller guarantees that `replay` is an open replay.
+    let Some(replay) = (unsafe { replay.as_mut() }) else {
+        return -1;
+    };
+    if progress.is_null() {
+        return replay.fail("progress is null");
+    }
+    replay.join();
+    let (state, record_count) = match &replay.state {
+        ReplayState::NotStarted(_) => (TRACING_REPLAY_NOT_STARTED, 0),
+        ReplayState::Running(_) => (TRACING_REPLAY_RUNNING, 0),
+        ReplayState::Finished(record_count) => (TRACING_REPLAY_FINISHED, *record_count),
+        ReplayState::Failed => (TRACING_REPLAY_FAILED, 0),
+    };
+    let value = TracingReplayProgress {
+        state,
+        records_dispatched: replay.dispatched.load(Ordering::Relaxed),
+        record_count,
+    };
+    // SAFETY: the caller guarantees that `progress` is valid, and it isn't null.
+    unsafe { progress.write(value) };
+    0
+}
+
+/// Returns the reason for the last failure of a function called with `replay`, or null if
+/// nothing has failed.
+///
+/// The string belongs to the replay, it is valid until the next call with `replay`.
+///
+/// # Safety
+///
+/// `replay` must have been returned by [`tracing_replay_open`] and not yet closed.
+#[no_mangle]
+pub unsafe extern "C" fn tracing_replay_last_error(replay: *const TracingReplay) -> *const c_char {
+    // SAFETY: the caller guarantees that `replay` is an open replay.
+    let Some(replay) = (unsafe { replay.as_ref() }) else {
+        return ptr::null();
+    };
+    replay
+        .last_error
+        .as_ref()
+        .map_or(ptr::null(), |last_error| last_error.as_ptr())
+}
+
+/// Waits for the replay to finish, if it was started, and frees it.
+///
+/// Returns `-1` if the replay failed, the reason can't be retrieved as the replay has been freed.
+///
+/// # Safety
+///
+/// `replay` must have been returned by [`tracing_replay_open`] and not yet closed. It must not be
+/// used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn tracing_replay_close(replay: *mut TracingReplay) -> c_int {
+    if replay.is_null() {
+        return -1;
+    }
+    // SAFETY: the caller guarantees that `replay` is an open replay, which is given back here.
+    let replay = unsafe { Box::from_raw(replay) };
+    match replay.state {
+        ReplayState::NotStarted(_) | ReplayState::Finished(_) => 0,
+        ReplayState::Running(join_handle) => match join_handle.join() {
+            Ok(Ok(_)) => 0,
+            Ok(Err(_)) | Err(_) => -1,
+        },
+        ReplayState::Failed => -1,
+    }
+}
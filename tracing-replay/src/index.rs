@@ -0,0 +1,224 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing_cassette::{ExportError, FormatVersionError, INDEX_VERSION};
+
+use crate::{
+    reader::{self, Lines},
+    recording::{self, TraceRef},
+    ReplayFileError,
+};
+
+pub(crate) use tracing_cassette::{IndexCheckpoint as Checkpoint, IndexPosition as Position};
+
+/// An index over a recording file which allows a replay to seek to a point in time.
+///
+/// The index is stored in a sidecar file next to the recording, see [`sidecar_path`]. When a
+/// [`Replay`] is configured with a time range that starts after the beginning of the recording
+/// and a sidecar index is present, the replay will seek directly to the closest checkpoint
+/// before the start of the range instead of reading the whole recording up to that point.
+///
+/// Each checkpoint stores the byte position in the recording together with a prologue: the
+/// records which are needed to reconstruct the state at that position. This includes all the
+/// callsite registrations seen so far as well as the creation and entering of all the spans that
+/// are still open.
+///
+/// The index is a [`tracing_cassette::RecordingIndex`], so sidecar indexes written by other
+/// tools, such as `cassette index`, are used by the replay as well.
+///
+/// [`Replay`]: struct@crate::Replay
+/// [`sidecar_path`]: fn@Self::sidecar_path
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct RecordingIndex {
+    index: tracing_cassette::RecordingIndex,
+}
+
+impl RecordingIndex {
+    /// Builds an index for the recording file at `path`.
+    ///
+    /// A checkpoint is placed at the first record after every `interval` of recorded time.
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if the file at the provided path cannot be read or if
+    /// individual records cannot be read or deserialized.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let temp_dir = tempfile::tempdir().unwrap();
+    /// # let path_buf = temp_dir.path().join("recording.tracing");
+    /// # let recording_path = path_buf.to_str().unwrap();
+    /// # {
+    /// #    use std::io::Write;
+    /// #    let mut file = std::fs::File::create(recording_path).unwrap();
+    /// #    writeln!(file, "{}", r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"RegisterCallsite":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"}}}"#);
+    /// #    writeln!(file, "{}", r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543496,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#);
+    /// # }
+    /// use std::time::Duration;
+    /// use tracing_replay::RecordingIndex;
+    ///
+    /// let index = RecordingIndex::build(recording_path, Duration::from_secs(1)).unwrap();
+    /// index.write_sidecar(recording_path).unwrap();
+    ///
+    /// let mut replay = tracing_replay::Replay::new().with_time_range(Duration::from_secs(37)..);
+    /// let result = replay.replay_file(recording_path);
+    /// assert!(result.is_ok());
+    /// # temp_dir.close().unwrap();
+    /// ```
+    pub fn build(path: &str, interval: Duration) -> Result<Self, ReplayFileError> {
+        let data = reader::map_file(path)?;
+        let data = reader::decode_container(&data)?;
+        let index = tracing_cassette::RecordingIndex::build(&data[..], interval).map_err(
+            |err| match err {
+                ExportError::InvalidRecord { line_index, inner } => {
+                    ReplayFileError::CannotDeserializeRecord {
+                        inner,
+                        line_index,
+                        line: Lines::new(&data)
+                            .find(|line| line.position.line_index == line_index)
+                            .map(|line| String::from_utf8_lossy(line.bytes).into_owned())
+                            .unwrap_or_default(),
+                    }
+                }
+                ExportError::Version(FormatVersionError::TooNew { version }) => {
+                    ReplayFileError::RecordingTooNew { version }
+                }
+                ExportError::Io(inner) => ReplayFileError::CannotOpenFile { inner },
+                err => ReplayFileError::CannotOpenFile {
+                    inner: io::Error::other(err),
+                },
+            },
+        )?;
+
+        Ok(Self { index })
+    }
+
+    /// Returns the path of the sidecar index for the recording at `recording_path`.
+    #[must_use]
+    pub fn sidecar_path(recording_path: &str) -> String {
+        tracing_cassette::RecordingIndex::sidecar_path(recording_path)
+    }
+
+    /// Reads the sidecar index for the recording at `recording_path`.
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if the sidecar file cannot be read or deserialized.
+    pub fn read_sidecar(recording_path: &str) -> Result<Self, ReplayFileError> {
+        let file = File::open(Self::sidecar_path(recording_path))
+            .map_err(|io_err| ReplayFileError::CannotReadIndex { inner: io_err })?;
+        let index = tracing_cassette::RecordingIndex::read(BufReader::new(file))
+            .map_err(|err| ReplayFileError::CannotDeserializeIndex { inner: err })?;
+
+        if index.version() == INDEX_VERSION {
+            Ok(Self { index })
+        } else {
+            Err(ReplayFileError::UnsupportedIndexVersion {
+                version: index.version(),
+            })
+        }
+    }
+
+    /// Writes this index to the sidecar file for the recording at `recording_path`.
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if the sidecar file cannot be written.
+    pub fn write_sidecar(&self, recording_path: &str) -> Result<(), io::Error> {
+        self.index.write_sidecar(recording_path)
+    }
+
+    /// The timestamp of the first record in the recording.
+    pub(crate) fn start(&self) -> Duration {
+        self.index.start()
+    }
+
+    /// Returns the last checkpoint at or before `offset` from the start of the recording.
+    pub(crate) fn checkpoint_before(&self, offset: Duration) -> Option<&Checkpoint> {
+        self.index.checkpoint_before(offset)
+    }
+}
+
+/// Tracks the records needed to reconstruct the replay state at a position in a recording.
+#[derive(Debug, Default)]
+pub(crate) struct PrologueTracker {
+    callsites: Vec<Position>,
+    open_spans: HashMap<recording::SpanId, OpenSpan>,
+}
+
+#[derive(Debug)]
+struct OpenSpan {
+    new_span: Position,
+    /// Positions of records which set field values on the span.
+    records: Vec<Position>,
+    /// Positions of enters which haven't been exited yet.
+    enters: Vec<Position>,
+}
+
+impl PrologueTracker {
+    /// Rebuilds the tracker from the records in a prologue of the recording in `data`.
+    pub(crate) fn from_prologue(data: &[u8], prologue: &[Position]) -> Self {
+        let mut tracker = Self::default();
+        for position in prologue {
+            if let Some(Ok(Some(record))) = Lines::line_at(data, *position).map(|line| line.parse())
+            {
+                tracker.track(&record.trace, *position);
+            }
+        }
+        tracker
+    }
+
+    pub(crate) fn track(&mut self, trace: &TraceRef<'_>, position: Position) {
+        match trace {
+            TraceRef::RegisterCallsite(_) => self.callsites.push(position),
+            TraceRef::NewSpan(new_span) => {
+                self.open_spans.insert(
+                    new_span.id,
+                    OpenSpan {
+                        new_span: position,
+                        records: Vec::new(),
+                        enters: Vec::new(),
+                    },
+                );
+            }
+            TraceRef::Record(record_values) => {
+                if let Some(open_span) = self.open_spans.get_mut(&record_values.id) {
+                    open_span.records.push(position);
+                }
+            }
+            TraceRef::Enter(span_id) => {
+                if let Some(open_span) = self.open_spans.get_mut(span_id) {
+                    open_span.enters.push(position);
+                }
+            }
+            TraceRef::Exit(span_id) => {
+                if let Some(open_span) = self.open_spans.get_mut(span_id) {
+                    open_span.enters.pop();
+                }
+            }
+            TraceRef::Close(span_id) => {
+                self.open_spans.remove(span_id);
+            }
+            TraceRef::Event(_) | TraceRef::FollowsFrom(_) => {}
+        }
+    }
+
+    /// The positions of all the records in the prologue, in recording order.
+    pub(crate) fn prologue(&self) -> Vec<Position> {
+        let mut prologue = self.callsites.clone();
+        for open_span in self.open_spans.values() {
+            prologue.push(open_span.new_span);
+            prologue.extend_from_slice(&open_span.records);
+            prologue.extend_from_slice(&open_span.enters);
+        }
+        prologue.sort_unstable_by_key(|position| position.byte_offset);
+        prologue
+    }
+}
@@ -0,0 +1,562 @@
+use std::{collections::HashMap, time::Duration};
+
+use serde::de::Error as _;
+use serde_json::{Map, Value};
+
+use crate::{
+    recording::{
+        CowStr, EventRef, FieldRef, FieldValueRef, Kind, Level, MetadataRef, NewSpanRef, Parent,
+        RecordMetaRef, SpanId, TraceRecordRef, TraceRef,
+    },
+    ReplayFileError,
+};
+
+/// The thread which log lines are assigned to when they don't say which thread they came from.
+const DEFAULT_THREAD_ID: &str = "ThreadId(1)";
+
+/// A structured JSON log format which can be replayed with [`Replay::replay_json_logs`].
+///
+/// [`Replay::replay_json_logs`]: fn@crate::Replay::replay_json_logs
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum JsonLogFormat {
+    /// The JSON format of the `tracing-subscriber` fmt layer, which serializes with
+    /// `tracing-serde`.
+    ///
+    /// Spans are reconstructed from the list of spans in each log line, so the `spans` field must
+    /// not have been turned off with `with_span_list(false)`. Lines for span lifecycle events,
+    /// which are enabled with `with_span_events`, are used to reconstruct the spans but are not
+    /// replayed as events.
+    FmtJson,
+    /// The format written by `tracing-bunyan-formatter`.
+    ///
+    /// Spans are reconstructed from the `[NAME - START]` and `[NAME - END]` log lines.
+    Bunyan,
+}
+
+/// Converts the JSON log lines in `logs` into trace records.
+pub(crate) fn convert(
+    logs: &str,
+    format: JsonLogFormat,
+) -> Result<Vec<TraceRecordRef<'static>>, ReplayFileError> {
+    let mut converter = Converter::new();
+    for (line_index, line) in logs.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let deserialize_error = |inner| ReplayFileError::CannotDeserializeRecord {
+            inner,
+            line_index,
+            line: line.to_owned(),
+        };
+        let log = serde_json::from_str::<Map<String, Value>>(line).map_err(deserialize_error)?;
+        match format {
+            JsonLogFormat::FmtJson => converter.convert_fmt_json(log),
+            JsonLogFormat::Bunyan => converter.convert_bunyan(log),
+        }
+        .map_err(|message| deserialize_error(serde_json::Error::custom(message)))?;
+    }
+    converter.close_all();
+
+    Ok(converter.records)
+}
+
+/// A span which has been reconstructed from the logs and is still open.
+#[derive(Debug)]
+struct OpenSpan {
+    id: SpanId,
+    name: String,
+    /// The fields of the span as logged, used to tell apart spans with the same name.
+    fields: Map<String, Value>,
+}
+
+/// The thread which a log line was written from.
+#[derive(Debug)]
+struct LogThread {
+    id: String,
+    name: Option<String>,
+}
+
+/// Describes a callsite, callsites with the same description are given the same Id.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct CallsiteKey {
+    name: String,
+    target: String,
+    level: Level,
+    file: Option<String>,
+    line: Option<u32>,
+    fields: Vec<String>,
+    kind: Kind,
+}
+
+#[derive(Debug)]
+struct Converter {
+    callsite_ids: HashMap<CallsiteKey, u64>,
+    next_span_id: u64,
+    /// The open spans on each thread, from the root to the leaf.
+    open_spans: HashMap<String, Vec<OpenSpan>>,
+    /// The last timestamp seen on each thread, used to close the spans which are still open at
+    /// the end of the logs.
+    last_timestamps: HashMap<String, Duration>,
+    last_timestamp: Duration,
+    records: Vec<TraceRecordRef<'static>>,
+}
+
+impl Converter {
+    fn new() -> Self {
+        Self {
+            callsite_ids: HashMap::new(),
+            next_span_id: 1,
+            open_spans: HashMap::new(),
+            last_timestamps: HashMap::new(),
+            last_timestamp: Duration::ZERO,
+            records: Vec::new(),
+        }
+    }
+
+    fn convert_fmt_json(&mut self, mut log: Map<String, Value>) -> Result<(), String> {
+        let timestamp = self.timestamp(log.remove("timestamp"))?;
+        let level = match log.remove("level") {
+            Some(Value::String(level)) => parse_level_name(&level)?,
+            _ => return Err("missing field `level`".to_owned()),
+        };
+        let target = take_string(&mut log, "target").unwrap_or_default();
+        let file = take_string(&mut log, "filename");
+        let line = take_u32(&mut log, "line_number");
+        let thread = LogThread {
+            id: take_string(&mut log, "threadId").unwrap_or_else(|| DEFAULT_THREAD_ID.to_owned()),
+            name: take_string(&mut log, "threadName"),
+        };
+        log.remove("span");
+        let spans = match log.remove("spans") {
+            Some(Value::Array(spans)) => spans
+                .into_iter()
+                .map(|span| match span {
+                    Value::Object(mut fields) => {
+                        Ok((take_string(&mut fields, "name").unwrap_or_default(), fields))
+                    }
+                    _ => Err("expected `spans` to contain objects".to_owned()),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            _ => Vec::new(),
+        };
+        // Events are either nested under `fields`, or flattened into the log line itself.
+        let fields = match log.remove("fields") {
+            Some(Value::Object(fields)) => fields,
+            _ => log,
+        };
+
+        // Span lifecycle events are only used to reconstruct the spans. A closed span is still
+        // in the list of spans when its close event is written.
+        let lifecycle = match fields.get("message") {
+            Some(Value::String(message)) if fields.len() <= 3 => match message.as_str() {
+                "new" | "enter" | "exit" => Some(spans.len()),
+                "close" => Some(spans.len().saturating_sub(1)),
+                _ => None,
+            },
+            _ => None,
+        };
+        let current_spans = &spans[..lifecycle.unwrap_or(spans.len())];
+        self.update_spans(&thread, timestamp, &target, current_spans);
+
+        if lifecycle.is_none() {
+            self.push_event(&thread, timestamp, level, target, file, line, fields);
+        }
+
+        Ok(())
+    }
+
+    fn convert_bunyan(&mut self, mut log: Map<String, Value>) -> Result<(), String> {
+        let timestamp = self.timestamp(log.remove("time"))?;
+        let level = match log.remove("level") {
+            Some(Value::Number(level)) => parse_level_number(level.as_u64().unwrap_or_default())?,
+            Some(Value::String(level)) => parse_level_name(&level)?,
+            _ => return Err("missing field `level`".to_owned()),
+        };
+        let Some(msg) = take_string(&mut log, "msg") else {
+            return Err("missing field `msg`".to_owned());
+        };
+        let target = take_string(&mut log, "target").unwrap_or_default();
+        let file = take_string(&mut log, "file");
+        let line = take_u32(&mut log, "line");
+        for key in ["v", "name", "hostname", "pid"] {
+            log.remove(key);
+        }
+        let thread = LogThread {
+            id: DEFAULT_THREAD_ID.to_owned(),
+            name: None,
+        };
+
+        if let Some(name) = span_marker(&msg, "START") {
+            log.remove("elapsed_milliseconds");
+            let open_spans = self.open_spans.entry(thread.id.clone()).or_default();
+            let parent = open_spans.last().map(|span| span.id);
+            let span = OpenSpan {
+                id: self.next_span_id(),
+                name: name.to_owned(),
+                fields: log,
+            };
+            self.push_new_span(&thread, timestamp, level, &target, parent, &span);
+            self.push_trace(&thread, timestamp, TraceRef::Enter(span.id));
+            self.open_spans
+                .entry(thread.id.clone())
+                .or_default()
+                .push(span);
+        } else if let Some(name) = span_marker(&msg, "END") {
+            let open_spans = self.open_spans.entry(thread.id.clone()).or_default();
+            if let Some(position) = open_spans.iter().rposition(|span| span.name == name) {
+                let span = open_spans.remove(position);
+                self.push_trace(&thread, timestamp, TraceRef::Exit(span.id));
+                self.push_trace(&thread, timestamp, TraceRef::Close(span.id));
+            }
+        } else {
+            // Events inside a span are prefixed with the span name, and carry the fields of the
+            // spans they are in, which aren't part of the event.
+            let message = match msg.split_once(" - EVENT] ") {
+                Some((prefix, message)) if prefix.starts_with('[') => message.to_owned(),
+                _ => msg,
+            };
+            if let Some(open_spans) = self.open_spans.get(&thread.id) {
+                log.retain(|key, value| {
+                    !open_spans
+                        .iter()
+                        .any(|span| span.fields.get(key) == Some(value))
+                });
+            }
+            let mut fields = Map::new();
+            fields.insert("message".to_owned(), Value::String(message));
+            fields.extend(log);
+            self.push_event(&thread, timestamp, level, target, file, line, fields);
+        }
+
+        Ok(())
+    }
+
+    /// Parses the timestamp of a log line, log lines without one are given the timestamp of the
+    /// previous line.
+    fn timestamp(&mut self, timestamp: Option<Value>) -> Result<Duration, String> {
+        match timestamp {
+            Some(Value::String(timestamp)) => {
+                let timestamp = parse_rfc3339(&timestamp)
+                    .ok_or_else(|| format!("invalid timestamp `{timestamp}`"))?;
+                self.last_timestamp = timestamp;
+                Ok(timestamp)
+            }
+            Some(_) => Err("expected timestamp to be a string".to_owned()),
+            None => Ok(self.last_timestamp),
+        }
+    }
+
+    fn next_span_id(&mut self) -> SpanId {
+        let id = self.next_span_id;
+        self.next_span_id += 1;
+        SpanId::from(id)
+    }
+
+    /// Closes and opens spans on `thread` so that the open spans match `spans`, which are listed
+    /// from the root to the leaf.
+    fn update_spans(
+        &mut self,
+        thread: &LogThread,
+        timestamp: Duration,
+        target: &str,
+        spans: &[(String, Map<String, Value>)],
+    ) {
+        let open_spans = self.open_spans.remove(&thread.id).unwrap_or_default();
+        let common = open_spans
+            .iter()
+            .zip(spans)
+            .take_while(|(open, (name, fields))| open.name == *name && open.fields == *fields)
+            .count();
+
+        let mut open_spans = open_spans;
+        for span in open_spans.drain(common..).rev() {
+            self.push_trace(thread, timestamp, TraceRef::Exit(span.id));
+            self.push_trace(thread, timestamp, TraceRef::Close(span.id));
+        }
+        for (name, fields) in &spans[common..] {
+            let parent = open_spans.last().map(|span| span.id);
+            let span = OpenSpan {
+                id: self.next_span_id(),
+                name: name.clone(),
+                fields: fields.clone(),
+            };
+            // The fmt layer doesn't log the level of spans.
+            self.push_new_span(thread, timestamp, Level::Info, target, parent, &span);
+            self.push_trace(thread, timestamp, TraceRef::Enter(span.id));
+            open_spans.push(span);
+        }
+        self.open_spans.insert(thread.id.clone(), open_spans);
+    }
+
+    /// Closes all the spans which are still open at the end of the logs.
+    fn close_all(&mut self) {
+        let mut threads: Vec<_> = self.open_spans.drain().collect();
+        threads.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (thread_id, open_spans) in threads {
+            let timestamp = self
+                .last_timestamps
+                .get(&thread_id)
+                .copied()
+                .unwrap_or(self.last_timestamp);
+            let thread = LogThread {
+                id: thread_id,
+                name: None,
+            };
+            for span in open_spans.into_iter().rev() {
+                self.push_trace(&thread, timestamp, TraceRef::Exit(span.id));
+                self.push_trace(&thread, timestamp, TraceRef::Close(span.id));
+            }
+        }
+    }
+
+    fn push_new_span(
+        &mut self,
+        thread: &LogThread,
+        timestamp: Duration,
+        level: Level,
+        target: &str,
+        parent: Option<SpanId>,
+        span: &OpenSpan,
+    ) {
+        let fields = convert_fields(span.fields.clone());
+        let metadata = self.metadata(
+            span.name.clone(),
+            target.to_owned(),
+            level,
+            None,
+            None,
+            &fields,
+            Kind::Span,
+        );
+        let parent = parent.map_or(Parent::Root, Parent::Explicit);
+        self.push_trace(
+            thread,
+            timestamp,
+            TraceRef::NewSpan(NewSpanRef {
+                id: span.id,
+                fields,
+                metadata,
+                parent,
+                trace_context: None,
+            }),
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn push_event(
+        &mut self,
+        thread: &LogThread,
+        timestamp: Duration,
+        level: Level,
+        target: String,
+        file: Option<String>,
+        line: Option<u32>,
+        fields: Map<String, Value>,
+    ) {
+        let name = match (&file, line) {
+            (Some(file), Some(line)) => format!("event {file}:{line}"),
+            _ => "event".to_owned(),
+        };
+        let fields = convert_fields(fields);
+        let metadata = self.metadata(name, target, level, file, line, &fields, Kind::Event);
+        self.push_trace(
+            thread,
+            timestamp,
+            TraceRef::Event(EventRef {
+                fields,
+                metadata,
+                parent: Parent::Current,
+            }),
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn metadata(
+        &mut self,
+        name: String,
+        target: String,
+        level: Level,
+        file: Option<String>,
+        line: Option<u32>,
+        fields: &[FieldRef<'static>],
+        kind: Kind,
+    ) -> MetadataRef<'static> {
+        let key = CallsiteKey {
+            name,
+            target,
+            level,
+            file,
+            line,
+            fields: fields
+                .iter()
+                .map(|field| field.name.as_str().to_owned())
+                .collect(),
+            kind,
+        };
+        let next_id = self.callsite_ids.len() as u64 + 1;
+        let id = *self.callsite_ids.entry(key.clone()).or_insert(next_id);
+
+        MetadataRef {
+            id,
+            name: key.name.into(),
+            target: key.target.into(),
+            level: key.level,
+            module_path: None,
+            file: key.file.map(CowStr::from),
+            line: key.line,
+            fields: key.fields.into_iter().map(CowStr::from).collect(),
+            kind: key.kind,
+        }
+    }
+
+    fn push_trace(&mut self, thread: &LogThread, timestamp: Duration, trace: TraceRef<'static>) {
+        self.last_timestamps.insert(thread.id.clone(), timestamp);
+        self.records.push(TraceRecordRef {
+            meta: RecordMetaRef {
+                timestamp_s: timestamp.as_secs(),
+                timestamp_subsec_us: timestamp.subsec_micros(),
+                thread_id: thread.id.clone().into(),
+                thread_name: thread.name.clone().map(CowStr::from),
+            },
+            trace,
+            final_fields: None,
+            sequence: None,
+        });
+    }
+}
+
+/// Returns the span name from a `tracing-bunyan-formatter` message like `[NAME - START]`.
+fn span_marker<'m>(msg: &'m str, marker: &str) -> Option<&'m str> {
+    msg.strip_prefix('[')?
+        .strip_suffix(']')?
+        .strip_suffix(marker)?
+        .strip_suffix(" - ")
+}
+
+fn take_string(log: &mut Map<String, Value>, key: &str) -> Option<String> {
+    match log.remove(key)? {
+        Value::String(value) => Some(value),
+        _ => None,
+    }
+}
+
+fn take_u32(log: &mut Map<String, Value>, key: &str) -> Option<u32> {
+    log.remove(key)?
+        .as_u64()
+        .and_then(|value| u32::try_from(value).ok())
+}
+
+/// Converts logged fields into recorded fields.
+///
+/// The logged JSON types are mapped onto the closest recorded field value, objects and arrays are
+/// recorded as structured values. The `message` field is recorded with `Debug` and comes first,
+/// as it does in `tracing`.
+fn convert_fields(fields: Map<String, Value>) -> Vec<FieldRef<'static>> {
+    let mut fields: Vec<_> = fields.into_iter().collect();
+    fields.sort_by_key(|(name, _)| name != "message");
+    fields
+        .into_iter()
+        .map(|(name, value)| {
+            let value = match value {
+                Value::String(value) if name == "message" => FieldValueRef::Debug(value.into()),
+                Value::String(value) => FieldValueRef::Str(value.into()),
+                Value::Bool(value) => FieldValueRef::Bool(value),
+                Value::Number(value) => {
+                    if let Some(value) = value.as_u64() {
+                        FieldValueRef::U64(value)
+                    } else if let Some(value) = value.as_i64() {
+                        FieldValueRef::I64(value)
+                    } else {
+                        FieldValueRef::F64(value.as_f64().unwrap_or_default())
+                    }
+                }
+                Value::Null => FieldValueRef::Debug(value.to_string().into()),
+                value => FieldValueRef::Json(value),
+            };
+            FieldRef {
+                name: name.into(),
+                value,
+            }
+        })
+        .collect()
+}
+
+fn parse_level_name(level: &str) -> Result<Level, String> {
+    match level.to_ascii_uppercase().as_str() {
+        "TRACE" => Ok(Level::Trace),
+        "DEBUG" => Ok(Level::Debug),
+        "INFO" => Ok(Level::Info),
+        "WARN" => Ok(Level::Warn),
+        "ERROR" => Ok(Level::Error),
+        _ => Err(format!("unknown level `{level}`")),
+    }
+}
+
+/// Parses a bunyan level, bunyan's `fatal` level is treated as an error.
+fn parse_level_number(level: u64) -> Result<Level, String> {
+    match level {
+        10 => Ok(Level::Trace),
+        20 => Ok(Level::Debug),
+        30 => Ok(Level::Info),
+        40 => Ok(Level::Warn),
+        50 | 60 => Ok(Level::Error),
+        _ => Err(format!("unknown level `{level}`")),
+    }
+}
+
+/// Parses an RFC 3339 timestamp such as `2024-05-08T14:09:00.543400Z` into a duration since the
+/// UNIX epoch.
+fn parse_rfc3339(timestamp: &str) -> Option<Duration> {
+    fn number(digits: &str) -> Option<i64> {
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok()
+    }
+
+    let (date, time) = timestamp.split_once(['T', 't', ' '])?;
+    let mut date_parts = date.splitn(3, '-');
+    let year = number(date_parts.next()?)?;
+    let month = number(date_parts.next()?)?;
+    let day = number(date_parts.next()?)?;
+
+    let (time, offset_s) = if let Some(time) = time.strip_suffix(['Z', 'z']) {
+        (time, 0)
+    } else {
+        let sign_position = time.rfind(['+', '-'])?;
+        let (time, offset) = time.split_at(sign_position);
+        let sign = if offset.starts_with('-') { -1 } else { 1 };
+        let (hours, minutes) = offset[1..].split_once(':')?;
+        (time, sign * (number(hours)? * 3600 + number(minutes)? * 60))
+    };
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut time_parts = time.splitn(3, ':');
+    let hour = number(time_parts.next()?)?;
+    let minute = number(time_parts.next()?)?;
+    let second = number(time_parts.next()?)?;
+    let nanos = if fraction.is_empty() {
+        0
+    } else {
+        let digits = &fraction[..fraction.len().min(9)];
+        number(digits)? * 10_i64.pow(9 - digits.len() as u32)
+    };
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+
+    // Days since the UNIX epoch, from Howard Hinnant's `days_from_civil` algorithm.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let seconds = days * 86_400 + hour * 3600 + minute * 60 + second - offset_s;
+    Some(Duration::new(
+        u64::try_from(seconds).ok()?,
+        u32::try_from(nanos).ok()?,
+    ))
+}
@@ -32,6 +32,55 @@
 //! # temp_dir.close().unwrap();
 //! ```
 //!
+//! # Testing
+//!
+//! Instrumentation can be checked to survive a round trip through a recording with
+//! `assert_round_trip`, which records a closure into memory with `tracing-rec`, replays the
+//! recording, and compares what was replayed with what was recorded. It needs the `round-trip`
+//! feature.
+//!
+//! How a formatting pipeline renders a known trace can be snapshot tested with [`render_with`],
+//! which replays a recording into a subscriber writing to memory and returns what it wrote, or
+//! [`render_fmt`] for the default `fmt` subscriber.
+//!
+//! # Crate Features
+//!
+//! - `simd-json`: Parses records with [`simd-json`] instead of `serde_json`. This may speed up
+//!   parsing of large recordings, especially those with long records, on CPUs which support the
+//!   required SIMD instructions.
+//! - `metrics`: Emits metrics about the replay through the [`metrics`] facade, so that long
+//!   running replays can be monitored. The metrics recorder must be installed before the replay is
+//!   created. The following metrics are emitted:
+//!   - `tracing_replay_records_read` (counter): records read from the recording.
+//!   - `tracing_replay_records_dispatched` (counter, per `thread`): records dispatched.
+//!   - `tracing_replay_records_skipped` (counter, per `reason`): records which weren't dispatched,
+//!     either because they were `filtered` out of the replay or because they refer to a span which
+//!     wasn't replayed (`unmapped_span`).
+//!   - `tracing_replay_queue_depth` (gauge, per `thread`): records waiting to be dispatched.
+//!   - `tracing_replay_dispatch_lag_seconds` (histogram, per `thread`): how late each record was
+//!     dispatched compared to its scheduled time.
+//! - `opentelemetry`: Adds `PropagateTraceContext`, a layer which gives replayed spans the
+//!   OpenTelemetry trace context they were recorded with, so that [`tracing-opentelemetry`]
+//!   exports them as part of their original distributed traces. The context is recorded by
+//!   `tracing-rec` with its `opentelemetry` feature.
+//! - `round-trip`: Adds `assert_round_trip` and `round_trip`, which record a closure into memory
+//!   with `tracing-rec`, replay the recording, and compare what was replayed with what was
+//!   recorded. This is the only feature which depends on `tracing-rec`.
+//! - `ffi`: Adds the `ffi` module, a C API to open, configure, start, poll, and close a replay,
+//!   so that test harnesses in other languages can replay recordings into Rust components. The
+//!   `tracing-replay-ffi` crate builds it as a C dynamic library with
+//!   `cargo build -p tracing-replay-ffi --release`, the declarations for C are in
+//!   `include/tracing_replay.h`.
+//!
+//! # WebAssembly
+//!
+//! `tracing-replay` can be built for `wasm32` targets, for example to replay recordings in a
+//! trace viewer running in a browser. Threads can't be spawned there, so traces are dispatched
+//! on the thread which is replaying the recording, and blocking that thread to wait for the next
+//! record isn't possible either. Use [`Replay::replay_bytes_async`] with a timer from the async
+//! runtime in use to replay recordings in real time. Replays which don't wait for the clock, at
+//! infinite speed or in [`ReplayMode::Deterministic`], can also use the blocking methods.
+//!
 //! # Supported Rust Versions
 //!
 //! `tracing-replay` is built against the latest stable release. The minimum supported version is
@@ -52,32 +101,157 @@
 //!
 //! [`Dispatch`]: struct@tracing::Dispatch
 //! [`tracing-subscriber`]: https://docs.rs/tracing-subscriber/latest/tracing_subscriber/
+//! [`metrics`]: https://docs.rs/metrics/latest/metrics/
+//! [`simd-json`]: https://docs.rs/simd-json/latest/simd_json/
+//! [`tracing-opentelemetry`]: https://docs.rs/tracing-opentelemetry/latest/tracing_opentelemetry/
 #![allow(clippy::many_single_char_names)]
 
 use std::{
     any::Any,
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     error, fmt,
-    fs::File,
-    io::{self, BufReader},
-    sync::{mpsc, Arc, Mutex},
+    future::Future,
+    io,
+    ops::{Bound, ControlFlow, RangeBounds},
+    sync::{
+        atomic::{self, AtomicU64},
+        mpsc, Arc, Mutex,
+    },
     thread::{self, JoinHandle},
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime},
 };
 
 use proxy::{EventProxy, RecordProxy};
+use tracing_cassette::Header;
 use tracing_core::{field, span, Metadata};
+use tracing_subscriber::filter::Targets;
 
+mod amplify;
+mod breakpoint;
+mod cache;
 mod callsite;
+mod chaos;
+mod checkpoint;
+mod clock;
+mod env;
+mod fanout;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod index;
+mod ingest;
+mod meta;
+mod pipeline;
+mod preserve;
 mod proxy;
-mod recording;
+mod rate_limit;
+mod reader;
+pub mod recording;
+#[cfg(feature = "round-trip")]
+mod round_trip;
+mod sink;
+mod snapshot;
+mod stepper;
+mod subtree;
+mod telemetry;
+mod thread_map;
+mod thread_span;
+mod time;
+mod timing;
+mod trace_context;
+mod verify;
+mod watchdog;
+
+pub use crate::{
+    breakpoint::Breakpoint,
+    cache::MetadataCache,
+    chaos::Chaos,
+    checkpoint::ReplayCheckpoint,
+    env::ReplayEnvError,
+    index::RecordingIndex,
+    ingest::JsonLogFormat,
+    preserve::PreserveSpanIds,
+    sink::ReplaySink,
+    snapshot::{render_fmt, render_with, SnapshotWriter},
+    stepper::ReplayStepper,
+    subtree::SpanSelector,
+    thread_map::RecordedThread,
+    timing::{LagDistribution, TimingFidelity},
+    trace_context::recorded_trace_context,
+    verify::{VerificationMismatch, VerificationReport},
+    watchdog::{StallCause, StalledDispatcher},
+};
+
+#[cfg(feature = "opentelemetry")]
+pub use crate::trace_context::PropagateTraceContext;
+
+#[cfg(feature = "round-trip")]
+pub use crate::round_trip::{assert_round_trip, round_trip, RoundTrip};
 
 use crate::{
+    amplify::Amplification,
+    breakpoint::Breakpoints,
     callsite::Cs,
+    chaos::ChaosInjector,
+    checkpoint::Checkpointer,
+    clock::ReplayClock,
+    fanout::FanOut,
+    meta::MetaEvents,
     proxy::{DispatchProxy, NewSpanProxy},
-    recording::{Field, Trace, TraceRecord},
+    rate_limit::RateLimiter,
+    reader::Lines,
+    recording::{
+        owned_fields, CowStr, Field, RecordMetaRef, ReplayValue, TraceRecord, TraceRecordRef,
+        TraceRef,
+    },
+    subtree::SubtreeFilter,
+    telemetry::{ReplayMetrics, ThreadMetrics},
+    thread_map::ThreadMapping,
+    thread_span::ThreadSpans,
+    time::Instant,
+    timing::LagHistogram,
+    verify::Verifier,
+    watchdog::{DispatcherProgress, Watchdog},
 };
 
+/// Replays a recording which is embedded in the binary and waits until it has been dispatched.
+///
+/// The recording at `path` is included with [`include_bytes!`], so the path is relative to the
+/// file in which the macro is called. The recording is then replayed with
+/// [`Replay::replay_include`], which returns the [`ReplaySummary`] once every record has been
+/// dispatched.
+///
+/// The recording is replayed by `replay`, if it is given. Otherwise, a new [`Replay`] in
+/// [`ReplayMode::Deterministic`] is used, which replays the recording into the global default
+/// dispatcher and is then closed. In that case the macro panics if any of the dispatcher threads
+/// panicked.
+///
+/// # Examples
+///
+/// A golden recording can be replayed into the subscriber under test:
+///
+/// ```
+/// # let subscriber = tracing_subscriber::fmt().finish();
+/// let mut replay = tracing_replay::Replay::new()
+///     .with_mode(tracing_replay::ReplayMode::Deterministic)
+///     .with_dispatch_targets([tracing::Dispatch::new(subscriber)]);
+/// let result = tracing_replay::replay_embedded!(replay, "../../sample-data/events.tracing");
+/// assert_eq!(result.unwrap().record_count, 19);
+/// ```
+#[macro_export]
+macro_rules! replay_embedded {
+    ($path:expr $(,)?) => {{
+        let mut replay = $crate::Replay::new().with_mode($crate::ReplayMode::Deterministic);
+        let result = replay.replay_include(::core::include_bytes!($path));
+        if let ::core::result::Result::Err(err) = replay.close() {
+            ::core::panic!("{err}");
+        }
+        result
+    }};
+    ($replay:expr, $path:expr $(,)?) => {
+        $replay.replay_include(::core::include_bytes!($path))
+    };
+}
+
 /// Replay coordinator.
 ///
 /// An instantiation of this object can replay a tracing recording. See [`replay_file`] for details
@@ -87,16 +261,85 @@ use crate::{
 #[derive(Debug)]
 pub struct Replay {
     store: Arc<Mutex<HashMap<u64, &'static Metadata<'static>>>>,
-    callsites: Arc<Mutex<HashMap<recording::SpanId, u64>>>,
-    span_ids: Arc<Mutex<HashMap<recording::SpanId, MappedSpanId>>>,
+    /// Where the metadata in the store comes from, which may be shared with other replays.
+    metadata_cache: MetadataCache,
+    callsites: Arc<Mutex<HashMap<SpanKey, u64>>>,
+    /// The callsites which have been registered during the replay.
+    registered_callsites: HashSet<u64>,
+    span_ids: Arc<Mutex<HashMap<SpanKey, MappedSpanId>>>,
+    span_generations: HashMap<recording::SpanId, u32>,
+    /// The recorded span::Ids of the spans which have been created and not yet closed.
+    open_spans: HashSet<recording::SpanId>,
+    span_id_collision_policy: SpanIdCollisionPolicy,
+    /// Whether replayed spans are numbered in the order they were recorded, for
+    /// [`PreserveSpanIds`], instead of keeping their recorded span::Ids.
+    sequential_span_ids: bool,
+    /// The number given to the last span which was replayed, with sequential span::Ids.
+    span_sequence: u64,
+    pending_follows_from: Arc<Mutex<Vec<DispatchableFollowsFrom>>>,
+    pending_records: Arc<Mutex<PendingRecords>>,
+    pending_record_timeout: Duration,
     threads: HashMap<String, ThreadDispatcherHandle>,
-    replay_time_delta: Duration,
+    clock: Arc<ReplayClock>,
+    /// How long replaying the whole recording should take, if the speed is fitted to it.
+    target_duration: Option<Duration>,
+    range_start: Bound<Duration>,
+    range_end: Bound<Duration>,
+    parse_threads: usize,
+    /// How much recorded time passes between checkpoints, if checkpoints are written.
+    checkpoint_interval: Option<Duration>,
+    amplification: Option<Amplification>,
+    mode: ReplayMode,
+    rate_limiter: Option<RateLimiter>,
+    chaos: Option<ChaosInjector>,
+    thread_naming: ThreadNaming,
+    thread_spans: Option<ThreadSpans>,
+    meta_events: Option<MetaEvents>,
+    thread_selectors: Vec<ThreadSelector>,
+    thread_mapping: Option<ThreadMapping>,
+    /// Whether only the lifecycles of spans are replayed, without any events.
+    span_skeleton: bool,
+    subtree: Option<SubtreeFilter>,
+    /// Which spans and events are replayed, by target and level.
+    filter: Option<Targets>,
+    /// The targets to dispatch to, instead of the default dispatcher.
+    dispatch_targets: Vec<tracing::Dispatch>,
+    /// The dispatcher used by the replay threads, built from the dispatch targets.
+    dispatch: Option<tracing::Dispatch>,
+    verifier: Option<Verifier>,
+    on_dispatched: Option<OnDispatched>,
+    watchdog: Option<Watchdog>,
+    /// The dispatcher threads which panicked and were replaced since the last summary.
+    dispatcher_restarts: Vec<DispatcherRestart>,
+    metrics: ReplayMetrics,
+    breakpoints: Breakpoints,
 }
 
-#[derive(Debug)]
+/// A checkpoint to start replaying a recording from.
+#[derive(Clone, Copy, Debug)]
+struct Seek<'c> {
+    /// The timestamp of the first record in the recording.
+    start: Duration,
+    checkpoint: &'c index::Checkpoint,
+}
+
+/// Identifies a single span in a recording.
+///
+/// Subscribers may reuse a span::Id once the span it identified has closed, so a long recording
+/// can contain many different spans with the same recorded span::Id. The generation counts how
+/// many spans with the same recorded span::Id were created before this one.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct SpanKey {
+    id: recording::SpanId,
+    generation: u32,
+}
+
+#[derive(Clone, Debug)]
 enum MappedSpanId {
     Pending,
     Mapped(span::Id),
+    /// The span wasn't created during the replay because the subscriber disabled it.
+    Disabled,
 }
 
 impl Replay {
@@ -104,104 +347,188 @@ impl Replay {
     pub fn new() -> Self {
         Self {
             store: Arc::new(Mutex::new(HashMap::new())),
+            metadata_cache: MetadataCache::new(),
             callsites: Arc::new(Mutex::new(HashMap::new())),
+            registered_callsites: HashSet::new(),
             span_ids: Arc::new(Mutex::new(HashMap::new())),
+            span_generations: HashMap::new(),
+            open_spans: HashSet::new(),
+            span_id_collision_policy: SpanIdCollisionPolicy::default(),
+            sequential_span_ids: false,
+            span_sequence: 0,
+            pending_follows_from: Arc::new(Mutex::new(Vec::new())),
+            pending_records: Arc::new(Mutex::new(PendingRecords::default())),
+            pending_record_timeout: DEFAULT_PENDING_RECORD_TIMEOUT,
             threads: HashMap::new(),
-            replay_time_delta: Duration::from_nanos(0),
+            clock: Arc::new(ReplayClock::new(1.0)),
+            target_duration: None,
+            range_start: Bound::Unbounded,
+            range_end: Bound::Unbounded,
+            parse_threads: 0,
+            checkpoint_interval: None,
+            amplification: None,
+            mode: ReplayMode::Realtime,
+            rate_limiter: None,
+            chaos: None,
+            thread_naming: ThreadNaming::Exact,
+            thread_spans: None,
+            meta_events: None,
+            thread_selectors: Vec::new(),
+            thread_mapping: None,
+            span_skeleton: false,
+            subtree: None,
+            filter: None,
+            dispatch_targets: Vec::new(),
+            dispatch: None,
+            verifier: None,
+            on_dispatched: None,
+            watchdog: None,
+            dispatcher_restarts: Vec::new(),
+            metrics: ReplayMetrics::new(),
+            breakpoints: Breakpoints::default(),
         }
     }
 
-    /// Replays a tracing recording file through the default dispatcher.
+    /// Creates a replayer which is configured from environment variables.
     ///
-    /// The file at `path` is read and the trace records stored in the file are replayed one by
-    /// one.
+    /// This allows the replays in example binaries and applications to be tuned without
+    /// recompiling them. The variables which are read are:
+    ///
+    /// - `TRACING_REPLAY_SPEED`: the replay speed, see [`with_speed`]. `inf` replays without any
+    ///   delay between traces.
+    /// - `TRACING_REPLAY_MODE`: the replay mode, one of `realtime` ([`ReplayMode::Realtime`]),
+    ///   `deterministic` ([`ReplayMode::Deterministic`]), or `instant`, which replays in real time
+    ///   at infinite speed, overriding `TRACING_REPLAY_SPEED`.
+    /// - `TRACING_REPLAY_FILTER`: the spans and events to replay, as a comma separated list of
+    ///   `target=level` directives and default levels, such as `my_app=debug,warn`. See
+    ///   [`with_filter`].
+    ///
+    /// Variables which aren't set, or are empty, leave the default configuration in place. The
+    /// replayer can be configured further with the usual methods, which override the variables.
     ///
     /// # Errors
     ///
-    /// This method will return an error if the file at the provided path cannot be read or if
-    /// individual records cannot be read or deserialized.
+    /// Returns an error if one of the variables has a value which isn't valid.
     ///
     /// # Examples
     ///
     /// ```
-    /// # let temp_dir = tempfile::tempdir().unwrap();
-    /// # let path_buf = temp_dir.path().join("recording.tracing");
-    /// # let recording_path = path_buf.to_str().unwrap();
-    /// # {
-    /// #    use std::io::Write;
-    /// #    let mut file = std::fs::File::create(recording_path).unwrap();
-    /// #    writeln!(file, "{}", r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"RegisterCallsite":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"}}}"#);
-    /// #    writeln!(file, "{}", r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543496,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#);
-    /// # }
+    /// std::env::set_var("TRACING_REPLAY_MODE", "instant");
+    /// std::env::set_var("TRACING_REPLAY_FILTER", "record_events=info");
     ///
-    /// let mut replay = tracing_replay::Replay::new();
-    /// let result = replay.replay_file(recording_path);
-    /// assert!(result.is_ok());
-    /// # temp_dir.close().unwrap();
+    /// let replay = tracing_replay::Replay::from_env().unwrap();
+    /// assert_eq!(replay.handle().speed(), f64::INFINITY);
+    ///
+    /// std::env::set_var("TRACING_REPLAY_SPEED", "fast");
+    /// let err = tracing_replay::Replay::from_env().unwrap_err();
+    /// assert_eq!(
+    ///     err.to_string(),
+    ///     r#"TRACING_REPLAY_SPEED must be a number greater than zero, but is "fast""#,
+    /// );
     /// ```
-    pub fn replay_file(&mut self, path: &str) -> Result<ReplaySummary, ReplayFileError> {
-        use std::io::prelude::*;
-
-        let file =
-            File::open(path).map_err(|io_err| ReplayFileError::CannotOpenFile { inner: io_err })?;
-        let reader = BufReader::new(file);
-
-        let mut record_count = 0;
-        for (line_index, line) in reader.lines().enumerate() {
-            let line = &line.map_err(|io_err| ReplayFileError::CannotReadLine {
-                inner: io_err,
-                line_index,
-            })?;
-            let trace_record: TraceRecord = serde_json::from_str(line).map_err(|err| {
-                ReplayFileError::CannotDeserializeRecord {
-                    inner: err,
-                    line_index,
-                    line: line.clone(),
-                }
-            })?;
-
-            if line_index == 0 {
-                let now_since_epoch =
-                    SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .map_err(|time_err| ReplayFileError::SystemTimeTooEarly {
-                            duration: time_err.duration(),
-                        })?;
-                let recording_since_epoch = Duration::new(
-                    trace_record.meta.timestamp_s,
-                    trace_record.meta.timestamp_subsec_us,
-                );
-
-                // Set the delta between now and the recording time. We'll use this to delay
-                // replays and make them run on the same schedule as the recording.
-                self.replay_time_delta = now_since_epoch.saturating_sub(recording_since_epoch);
-            }
-
-            self.dispatch_trace(trace_record);
-            record_count += 1;
-        }
+    ///
+    /// [`with_speed`]: fn@Self::with_speed
+    /// [`with_filter`]: fn@Self::with_filter
+    #[must_use = "A replayer doesn't do anything until it is given a recording to replay"]
+    pub fn from_env() -> Result<Self, ReplayEnvError> {
+        env::configure(Self::new(), |name| std::env::var(name).ok())
+    }
 
-        Ok(ReplaySummary { record_count })
+    /// Sets the speed at which the recording will be replayed.
+    ///
+    /// A speed of `1.0` (the default) replays traces on the same schedule as they were recorded,
+    /// `2.0` replays them twice as fast and `0.5` at half speed. A speed of [`f64::INFINITY`]
+    /// replays traces without any delay between them.
+    ///
+    /// The speed can also be adjusted while a replay is in progress via a [`ReplayHandle`].
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `speed` is not greater than zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let replay = tracing_replay::Replay::new().with_speed(2.0);
+    /// assert_eq!(replay.handle().speed(), 2.0);
+    /// ```
+    #[must_use]
+    pub fn with_speed(self, speed: f64) -> Self {
+        self.clock.set_speed(speed);
+        self
     }
 
-    /// Close the replay and check for errors.
+    /// Sets the speed so that replaying the recording takes `target_duration`.
     ///
-    /// Since much of the work of replaying a [`tracing`] recording happens on other threads, work
-    /// may be ongoing when [`replay_file`] returns. This is desireable in the case that another
-    /// file constituting more traces from the same recording is to be replayed directly
-    /// afterwards.
+    /// The recorded schedule is scaled uniformly, so the relative spacing of the records is
+    /// preserved: with a target duration of 90 seconds, a recording which lasted 45 minutes is
+    /// replayed at a speed of `30.0`. The speed is calculated from the first and last records of
+    /// each recording passed to [`replay_file`], [`replay_bytes`] and the other methods which are
+    /// given the whole recording, and replaces any speed set with [`with_speed`]. If a
+    /// [`with_time_range`] is set, only the selected part of the recording is fitted into the
+    /// target duration. Recordings which are replayed through a [`sink`] are not fitted, since
+    /// their duration isn't known until they end.
     ///
-    /// Calling this method waits for the dispatcher threads to complete and then tears them down.
+    /// The speed can still be adjusted via a [`ReplayHandle`] once a replay is in progress.
     ///
-    /// # Errors
+    /// # Panics
     ///
-    /// If any of the dispatcher threads panicked, the resulting messages are returned in
-    /// `ReplayCloseError`. All errors are collected into a vec together with their
-    /// recording thread Id.
+    /// This method will panic if `target_duration` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// let mut replay =
+    ///     tracing_replay::Replay::new().with_target_duration(Duration::from_millis(100));
+    /// // The recording lasted a little over 2 seconds.
+    /// let recording = include_bytes!("../../sample-data/delay.tracing");
+    /// replay.replay_include(recording).unwrap();
+    ///
+    /// let speed = replay.handle().speed();
+    /// assert!((20.0..20.1).contains(&speed), "speed: {speed}");
+    /// ```
+    ///
+    /// [`replay_file`]: fn@Self::replay_file
+    /// [`replay_bytes`]: fn@Self::replay_bytes
+    /// [`with_speed`]: fn@Self::with_speed
+    /// [`with_time_range`]: fn@Self::with_time_range
+    /// [`sink`]: fn@Self::sink
+    #[must_use]
+    pub fn with_target_duration(mut self, target_duration: Duration) -> Self {
+        assert!(
+            !target_duration.is_zero(),
+            "the target duration must be greater than zero"
+        );
+        self.target_duration = Some(target_duration);
+        self
+    }
+
+    /// Limits the replay to a time range within the recording.
+    ///
+    /// The range is given as offsets from the first record in the recording, so
+    /// `Duration::from_secs(37 * 60)..` replays everything from minute 37 onwards.
+    ///
+    /// Events which were recorded before the start of the range are skipped. All other records
+    /// before the start of the range are replayed without delay so that the spans which are open
+    /// at the start of the range exist. If a [`RecordingIndex`] sidecar file is present, the
+    /// replay seeks directly to the closest checkpoint before the start of the range instead of
+    /// reading the recording from the beginning.
     ///
     /// # Examples
     ///
     /// ```
+    /// use std::time::Duration;
+    ///
+    /// let replay = tracing_replay::Replay::new()
+    ///     .with_time_range(Duration::from_secs(60)..Duration::from_secs(90));
+    /// ```
+    ///
+    /// An excluded start skips the events recorded exactly at the start of the range as well.
+    ///
+    /// ```
+    /// use std::{ops::Bound, time::Duration};
     /// # let temp_dir = tempfile::tempdir().unwrap();
     /// # let path_buf = temp_dir.path().join("recording.tracing");
     /// # let recording_path = path_buf.to_str().unwrap();
@@ -212,130 +539,2717 @@ impl Replay {
     /// #    writeln!(file, "{}", r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543496,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#);
     /// # }
     ///
-    /// let mut replay = tracing_replay::Replay::new();
-    /// let _replay_result = replay.replay_file(recording_path);
+    /// // The event is recorded 96µs after the start of the recording.
+    /// let event = Duration::from_micros(96);
     ///
-    /// let close_result = replay.close();
-    /// assert!(close_result.is_ok());
+    /// let mut replay = tracing_replay::Replay::new().with_time_range(event..);
+    /// let summary = replay.replay_file(recording_path).unwrap();
+    /// assert_eq!(summary.record_count, 2);
+    ///
+    /// let mut replay = tracing_replay::Replay::new()
+    ///     .with_time_range((Bound::Excluded(event), Bound::Unbounded));
+    /// let summary = replay.replay_file(recording_path).unwrap();
+    /// assert_eq!(summary.record_count, 1);
     /// # temp_dir.close().unwrap();
     /// ```
-    pub fn close(&mut self) -> Result<(), ReplayCloseError> {
-        let mut errors = Vec::new();
-        for (key, handle) in self.threads.drain() {
-            match handle.trace_tx.send(DispatchableContainer::End) {
-                Ok(()) => match handle.join_handle.join() {
-                    Ok(()) => {}
-                    Err(join_error) => errors.push((key, join_error)),
-                },
-                Err(send_error) => {
-                    errors.push((key, Box::new(send_error)));
-                }
-            }
-        }
+    #[must_use]
+    pub fn with_time_range(mut self, range: impl RangeBounds<Duration>) -> Self {
+        self.range_start = range.start_bound().cloned();
+        self.range_end = range.end_bound().cloned();
+        self
+    }
 
-        if errors.is_empty() {
-            Ok(())
-        } else {
-            Err(ReplayCloseError { threads: errors })
-        }
+    /// Sets the mode in which the recording will be replayed.
+    ///
+    /// See [`ReplayMode`] for the available modes. The default mode is [`ReplayMode::Realtime`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tracing_replay::{Replay, ReplayMode};
+    ///
+    /// let replay = Replay::new().with_mode(ReplayMode::Deterministic);
+    /// ```
+    #[must_use]
+    pub fn with_mode(mut self, mode: ReplayMode) -> Self {
+        self.mode = mode;
+        self
     }
-}
 
-#[non_exhaustive]
-#[derive(Debug)]
-pub struct ReplaySummary {
-    pub record_count: usize,
-}
+    /// Sets how a new span whose recorded span::Id is still in use by an open span is handled.
+    ///
+    /// See [`SpanIdCollisionPolicy`] for the available policies. The default policy is
+    /// [`SpanIdCollisionPolicy::Version`]. The number of collisions is reported in the
+    /// [`ReplaySummary`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tracing_replay::{Replay, ReplayFileError, SpanIdCollisionPolicy};
+    ///
+    /// // Two spans with the recorded span::Id 1, the first is never closed.
+    /// let new_span = r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":1,"fields":[],"metadata":{"id":4403349456,"name":"span","target":"record_spans","level":"Info","module_path":"record_spans","file":"tracing-rec/examples/record-spans.rs","line":8,"fields":[],"kind":"Span"},"parent":"Root"}}}"#;
+    /// let recording = format!("{new_span}\n{new_span}\n");
+    ///
+    /// let mut replay = Replay::new();
+    /// let summary = replay.replay_str(&recording).unwrap();
+    /// assert_eq!(summary.span_id_collisions, 1);
+    ///
+    /// let mut replay = Replay::new().with_span_id_collision_policy(SpanIdCollisionPolicy::Error);
+    /// let result = replay.replay_str(&recording);
+    /// assert!(matches!(result, Err(ReplayFileError::SpanIdCollision { span_id: 1 })));
+    /// ```
+    #[must_use]
+    pub fn with_span_id_collision_policy(mut self, policy: SpanIdCollisionPolicy) -> Self {
+        self.span_id_collision_policy = policy;
+        self
+    }
 
-#[non_exhaustive]
-#[derive(Debug)]
-pub enum ReplayFileError {
-    CannotOpenFile {
-        inner: io::Error,
-    },
-    CannotReadLine {
-        inner: io::Error,
-        line_index: usize,
-    },
-    CannotDeserializeRecord {
-        inner: serde_json::Error,
-        line_index: usize,
-        line: String,
-    },
-    SystemTimeTooEarly {
-        duration: Duration,
-    },
-}
+    /// Numbers the replayed spans in the order they were recorded, instead of keeping their
+    /// recorded span::Ids.
+    ///
+    /// Replaying through a subscriber wrapped in [`PreserveSpanIds`] gives replayed spans their
+    /// recorded span::Ids, which depend on the subscriber of the recorded run. With sequential
+    /// span::Ids, [`PreserveSpanIds`] gives the first span in the recording the span::Id 1, the
+    /// second 2, and so on, whichever thread each span is replayed on. Repeated replays of the
+    /// same recording then give each span the same span::Id, so that output which includes
+    /// span::Ids, such as golden files, is identical between replays.
+    ///
+    /// Without [`PreserveSpanIds`], span::Ids are assigned by the subscriber and this has no
+    /// effect. Span::Ids aren't reused, each span which is replayed is given a new number, and
+    /// the numbering continues across recordings replayed by the same replayer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// use tracing::Dispatch;
+    /// use tracing_replay::{PreserveSpanIds, Replay, ReplayMode};
+    /// use tracing_subscriber::{layer::SubscriberExt, Layer};
+    ///
+    /// struct SpanIds(Arc<Mutex<Vec<u64>>>);
+    ///
+    /// impl<S: tracing::Subscriber> Layer<S> for SpanIds {
+    ///     fn on_new_span(
+    ///         &self,
+    ///         _attrs: &tracing::span::Attributes<'_>,
+    ///         id: &tracing::span::Id,
+    ///         _ctx: tracing_subscriber::layer::Context<'_, S>,
+    ///     ) {
+    ///         self.0.lock().unwrap().push(id.into_u64());
+    ///     }
+    /// }
+    ///
+    /// let replay_span_ids = || {
+    ///     let span_ids = Arc::new(Mutex::new(Vec::new()));
+    ///     let subscriber = PreserveSpanIds::new(tracing_subscriber::registry())
+    ///         .with(SpanIds(Arc::clone(&span_ids)));
+    ///     let mut replay = Replay::new()
+    ///         .with_mode(ReplayMode::Deterministic)
+    ///         .with_sequential_span_ids()
+    ///         .with_dispatch_targets([Dispatch::new(subscriber)]);
+    ///     replay
+    ///         .replay_include(include_bytes!("../../sample-data/threads.tracing"))
+    ///         .unwrap();
+    ///     replay.close().unwrap();
+    ///     let span_ids = span_ids.lock().unwrap().clone();
+    ///     span_ids
+    /// };
+    ///
+    /// assert_eq!(replay_span_ids(), vec![1, 2]);
+    /// assert_eq!(replay_span_ids(), replay_span_ids());
+    /// ```
+    #[must_use]
+    pub fn with_sequential_span_ids(mut self) -> Self {
+        self.sequential_span_ids = true;
+        self
+    }
 
-impl fmt::Display for ReplayFileError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{self:?}")
+    /// Sets how long values recorded for a span may wait for the span to be created.
+    ///
+    /// A span may be created on one recorded thread and have values recorded for it on another.
+    /// When the values are replayed before the replay thread which creates the span has caught
+    /// up, they are held back until the span has been created instead of blocking the replay
+    /// thread. Values which are still waiting after `timeout` are dropped once the span is
+    /// created. Values which are dropped or never resolved are reported in the
+    /// [`ReplayCloseSummary`].
+    ///
+    /// By default, the timeout is 5 seconds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// let replay = tracing_replay::Replay::new()
+    ///     .with_pending_record_timeout(Duration::from_millis(500));
+    /// ```
+    #[must_use]
+    pub fn with_pending_record_timeout(mut self, timeout: Duration) -> Self {
+        self.pending_record_timeout = timeout;
+        self
     }
-}
 
-impl error::Error for ReplayFileError {}
+    /// Sets the cache which the metadata of replayed callsites is taken from.
+    ///
+    /// By default, each replay has its own cache. Sharing a cache between replays of the same
+    /// recording means that the metadata of each callsite, which has to be leaked, is only
+    /// created once. See [`MetadataCache`] for details.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tracing_replay::{MetadataCache, Replay};
+    ///
+    /// let cache = MetadataCache::new();
+    /// let first = Replay::new().with_metadata_cache(cache.clone());
+    /// let second = Replay::new().with_metadata_cache(cache);
+    /// ```
+    #[must_use]
+    pub fn with_metadata_cache(mut self, cache: MetadataCache) -> Self {
+        self.metadata_cache = cache;
+        self
+    }
 
-#[derive(Debug)]
-pub struct ReplayCloseError {
-    threads: Vec<(String, Box<dyn Any + Send + 'static>)>,
-}
+    /// Writes a checkpoint of the replay's progress after every `interval` of recorded time.
+    ///
+    /// Checkpoints are only written when replaying a file with [`replay_file`] or
+    /// [`resume_from_checkpoint`], to a sidecar file next to the recording, see
+    /// [`ReplayCheckpoint`]. If the replay is interrupted, it can be continued from the last
+    /// checkpoint with [`resume_from_checkpoint`]. The checkpoint is removed once the replay
+    /// completes.
+    ///
+    /// A checkpoint is only written once all the records before it have been dispatched. In
+    /// [`ReplayMode::Realtime`], this means that the recording is read at most one interval ahead
+    /// of the replay. Records are parsed on the replay thread while checkpoints are written, the
+    /// number of parse threads set with [`with_parse_threads`] is ignored.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `interval` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// let replay = tracing_replay::Replay::new().with_checkpoints(Duration::from_secs(60));
+    /// ```
+    ///
+    /// [`replay_file`]: fn@Self::replay_file
+    /// [`resume_from_checkpoint`]: fn@Self::resume_from_checkpoint
+    /// [`with_parse_threads`]: fn@Self::with_parse_threads
+    #[must_use]
+    pub fn with_checkpoints(mut self, interval: Duration) -> Self {
+        assert!(!interval.is_zero(), "checkpoint interval must not be zero");
+        self.checkpoint_interval = Some(interval);
+        self
+    }
 
-impl fmt::Display for ReplayCloseError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "ReplayCloseError:")?;
-        for (thread_id, error) in &self.threads {
-            write!(f, " - {thread_id}: {error:?}")?;
-        }
+    /// Replays `copies` copies of the recording at the same time, to generate load.
+    ///
+    /// This allows a recording from production to be used to load test a telemetry pipeline at a
+    /// multiple of the recorded volume. The first copy is the recording itself, each of the other
+    /// copies starts at a random offset of up to `max_jitter` after it, so that the copies don't
+    /// arrive in lockstep. The offsets are the same for every replay, so that load tests are
+    /// reproducible.
+    ///
+    /// Each copy is replayed on its own threads, the recorded thread Ids of the copies have
+    /// `#<copy>` appended, such as `ThreadId(1)#2`, while the thread names are kept. Every span
+    /// is given a new span::Id which is unique across the copies, so the span trees of the copies
+    /// don't get mixed up. The copies share the callsites of the recording.
+    ///
+    /// The copies are read from the recording separately, so amplification doesn't need any more
+    /// memory than a single replay does. Amplification applies to the methods which replay a
+    /// whole recording, such as [`replay_file`] and [`replay_bytes`], but not to [`sink`] or
+    /// [`stepper`]. Checkpoints aren't written while amplifying, as the replay couldn't be
+    /// resumed from them, and records are parsed on the replaying thread, even if
+    /// [`with_parse_threads`] was set. The [`ReplaySummary`] counts the records of all copies.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `copies` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// let mut replay = tracing_replay::Replay::new()
+    ///     .with_mode(tracing_replay::ReplayMode::Deterministic)
+    ///     .with_amplification(10, Duration::from_millis(500));
+    /// let recording = include_bytes!("../../sample-data/spans.tracing");
+    /// let summary = replay.replay_include(recording).unwrap();
+    ///
+    /// let single = tracing_replay::Replay::new()
+    ///     .with_mode(tracing_replay::ReplayMode::Deterministic)
+    ///     .replay_include(recording)
+    ///     .unwrap();
+    /// assert_eq!(summary.record_count, 10 * single.record_count);
+    /// assert_eq!(summary.threads.len(), 10 * single.threads.len());
+    /// ```
+    ///
+    /// [`replay_file`]: fn@Self::replay_file
+    /// [`replay_bytes`]: fn@Self::replay_bytes
+    /// [`sink`]: fn@Self::sink
+    /// [`stepper`]: fn@Self::stepper
+    /// [`with_parse_threads`]: fn@Self::with_parse_threads
+    #[must_use]
+    pub fn with_amplification(mut self, copies: usize, max_jitter: Duration) -> Self {
+        assert!(
+            copies > 0,
+            "replay amplification must make at least one copy"
+        );
+        self.amplification = Some(Amplification { copies, max_jitter });
+        self
+    }
 
-        Ok(())
+    /// Limits the rate at which records are dispatched.
+    ///
+    /// At most `records_per_sec` records will be dispatched per second, across all replay
+    /// threads. Records which would exceed the rate are delayed, not dropped, so the replay takes
+    /// longer instead. This prevents a dense recording which is replayed at high speed from
+    /// overwhelming a downstream exporter which has its own rate limits.
+    ///
+    /// By default, the rate is unlimited.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `records_per_sec` is not greater than zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let replay = tracing_replay::Replay::new()
+    ///     .with_speed(f64::INFINITY)
+    ///     .with_max_rate(10_000.0);
+    /// ```
+    #[must_use]
+    pub fn with_max_rate(mut self, records_per_sec: f64) -> Self {
+        self.rate_limiter = Some(RateLimiter::new(records_per_sec));
+        self
     }
-}
 
-impl error::Error for ReplayCloseError {}
+    /// Degrades the replay with random latency and dropped records.
+    ///
+    /// Replaying realistic recorded traffic with degraded telemetry tests how subscribers,
+    /// exporters, and alerting behave when traces arrive late or not at all. See [`Chaos`] for
+    /// what can be degraded. By default, replays aren't degraded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tracing_replay::{Chaos, Replay, ReplayMode};
+    ///
+    /// let recording = include_bytes!("../../sample-data/events.tracing");
+    /// let summary = Replay::new()
+    ///     .with_mode(ReplayMode::Deterministic)
+    ///     .with_chaos(Chaos::new().with_drop_probability(0.5).with_seed(7))
+    ///     .replay_include(recording)
+    ///     .unwrap();
+    ///
+    /// let undegraded = Replay::new()
+    ///     .with_mode(ReplayMode::Deterministic)
+    ///     .replay_include(recording)
+    ///     .unwrap();
+    /// assert!(summary.dropped_records > 0);
+    /// assert_eq!(
+    ///     summary.record_count + summary.dropped_records,
+    ///     undegraded.record_count,
+    /// );
+    /// ```
+    #[must_use]
+    pub fn with_chaos(mut self, chaos: Chaos) -> Self {
+        self.chaos = Some(ChaosInjector::new(chaos));
+        self
+    }
 
-impl Default for Replay {
-    fn default() -> Self {
-        Self::new()
+    /// Wraps the records of each recorded thread in a span representing the thread.
+    ///
+    /// Before the first record of a recorded thread is replayed, a span named after the thread is
+    /// created and entered on its replay thread. It is exited and closed when the replay is
+    /// [`close`]d, at the time of the last record replayed from the thread. This shows when each
+    /// thread started and finished in viewers and subscribers which display spans, and makes the
+    /// thread the parent of the spans and events recorded without any other contextual parent,
+    /// even when the recorded program had no spans for its threads.
+    ///
+    /// The thread spans have the target `tracing_replay::thread`, are at the `INFO` level, and
+    /// have the fields `thread.id` and, if the thread was named, `thread.name`. They are named
+    /// after the recorded thread, or its Id if it wasn't named. They are passed to the
+    /// [`on_dispatched`] hook, but aren't counted in the [`ReplaySummary`]. Spans and events with
+    /// an explicit parent, or which are explicitly root spans, keep their recorded parent.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// use tracing_replay::{recording::Trace, Replay, ReplayMode};
+    ///
+    /// let spans = Arc::new(Mutex::new(Vec::new()));
+    /// let hook_spans = Arc::clone(&spans);
+    /// let mut replay = Replay::new()
+    ///     .with_mode(ReplayMode::Deterministic)
+    ///     .with_thread_spans()
+    ///     .on_dispatched(move |record| {
+    ///         if let Trace::NewSpan(new_span) = &record.trace {
+    ///             if new_span.metadata.target == "tracing_replay::thread" {
+    ///                 hook_spans.lock().unwrap().push(new_span.metadata.name.clone());
+    ///             }
+    ///         }
+    ///     });
+    /// let summary = replay
+    ///     .replay_include(include_bytes!("../../sample-data/threads.tracing"))
+    ///     .unwrap();
+    /// replay.close().unwrap();
+    ///
+    /// let mut spans = spans.lock().unwrap().clone();
+    /// spans.sort();
+    /// assert_eq!(spans, ["main", "other-thread"]);
+    /// assert_eq!(summary.record_count, 14);
+    /// ```
+    ///
+    /// [`close`]: fn@Self::close
+    /// [`on_dispatched`]: fn@Self::on_dispatched
+    #[must_use]
+    pub fn with_thread_spans(mut self) -> Self {
+        self.thread_spans = Some(ThreadSpans::default());
+        self
+    }
+
+    /// Wraps the replay in a synthetic span, and emits events which describe its progress.
+    ///
+    /// This makes the output of a replay describe where it came from. The span is named
+    /// `replay`, it is entered on each recorded thread before its first trace is replayed, and is
+    /// exited and closed when the replay is [`close`]d, so spans and events replayed within it
+    /// have it as their parent. Within the span, each recording that is replayed has the events:
+    ///
+    /// - `replay started`, before its first trace is replayed.
+    /// - `replay progress`, after every `progress_interval` of recorded time.
+    /// - `replay finished`, after its last trace has been replayed.
+    ///
+    /// The events have the fields `message`, `records` with the number of records replayed from
+    /// the recording so far, and `recording.path` with the path of the recording if it was
+    /// replayed from a file. The span and events have the target `tracing_replay::meta` and are
+    /// at the `INFO` level, so they can be filtered out of a subscriber by target. They are
+    /// passed to the [`on_dispatched`] hook, but aren't counted in the [`ReplaySummary`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::{
+    ///     sync::{Arc, Mutex},
+    ///     time::Duration,
+    /// };
+    ///
+    /// use tracing_replay::{recording::Trace, Replay, ReplayMode};
+    ///
+    /// let meta_events = Arc::new(Mutex::new(Vec::new()));
+    /// let hook_meta_events = Arc::clone(&meta_events);
+    /// let mut replay = Replay::new()
+    ///     .with_mode(ReplayMode::Deterministic)
+    ///     .with_meta_events(Duration::from_secs(60))
+    ///     .on_dispatched(move |record| {
+    ///         if let Trace::Event(event) = &record.trace {
+    ///             if event.metadata.target == "tracing_replay::meta" {
+    ///                 hook_meta_events.lock().unwrap().push(event.metadata.name.clone());
+    ///             }
+    ///         }
+    ///     });
+    /// let summary = replay
+    ///     .replay_include(include_bytes!("../../sample-data/events.tracing"))
+    ///     .unwrap();
+    /// replay.close().unwrap();
+    ///
+    /// assert_eq!(*meta_events.lock().unwrap(), ["replay started", "replay finished"]);
+    /// assert_eq!(summary.record_count, 19);
+    /// ```
+    ///
+    /// [`close`]: fn@Self::close
+    /// [`on_dispatched`]: fn@Self::on_dispatched
+    #[must_use]
+    pub fn with_meta_events(mut self, progress_interval: Duration) -> Self {
+        self.meta_events = Some(MetaEvents::new(progress_interval));
+        self
+    }
+
+    /// Sets how the replay threads are named.
+    ///
+    /// Each recorded thread is replayed on its own thread. By default, these threads are given
+    /// exactly the same names as the recorded threads ([`ThreadNaming::Exact`]), so the output of
+    /// subscribers which include thread names matches the original recording. When replaying
+    /// within an application which has its own threads, a [`ThreadNaming::Template`] makes the
+    /// replay threads easier to tell apart.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tracing_replay::{Replay, ThreadNaming};
+    ///
+    /// let replay = Replay::new().with_thread_naming(ThreadNaming::Template("replay:{name}".into()));
+    /// ```
+    #[must_use]
+    pub fn with_thread_naming(mut self, thread_naming: ThreadNaming) -> Self {
+        self.thread_naming = thread_naming;
+        self
+    }
+
+    /// Limits the replay to the selected recorded threads.
+    ///
+    /// Only the records from recorded threads which match at least one of the selectors are
+    /// replayed. By default, all recorded threads are replayed.
+    ///
+    /// Spans which were created on threads which aren't replayed don't exist in the replay.
+    /// Entering, exiting, closing or recording values on such spans from a replayed thread is
+    /// skipped, and spans and events which have such a span as an explicit parent are replayed as
+    /// root spans and events instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tracing_replay::{Replay, ThreadSelector};
+    ///
+    /// let replay = Replay::new().with_threads([
+    ///     ThreadSelector::Name("main".into()),
+    ///     ThreadSelector::Id("ThreadId(7)".into()),
+    /// ]);
+    /// ```
+    #[must_use]
+    pub fn with_threads(mut self, selectors: impl IntoIterator<Item = ThreadSelector>) -> Self {
+        self.thread_selectors = selectors.into_iter().collect();
+        self
+    }
+
+    /// Rewrites the identities of the recorded threads before their traces are dispatched.
+    ///
+    /// The `mapping` is called once for each recorded thread, with its recorded Id and name, and
+    /// can change either of them. Everything after the thread selection of [`with_threads`],
+    /// which matches the recorded identities, sees the rewritten identities instead: the
+    /// threads that traces are replayed on, the spans of [`with_thread_spans`], the
+    /// [`on_dispatched`] hook, and the threads in the [`ReplaySummary`].
+    ///
+    /// Recorded threads which are given the same Id are replayed on the same thread, so a pool
+    /// of worker threads can be collapsed into one logical thread. Renaming threads makes them
+    /// clearer in downstream viewers.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tracing::Dispatch;
+    /// use tracing_replay::{Replay, ReplayMode};
+    ///
+    /// let mut replay = Replay::new()
+    ///     .with_mode(ReplayMode::Deterministic)
+    ///     .with_dispatch_targets([Dispatch::new(tracing_subscriber::registry())])
+    ///     .with_thread_mapping(|thread| {
+    ///         thread.id = "app".into();
+    ///         thread.name = Some("app".into());
+    ///     });
+    /// let summary = replay
+    ///     .replay_include(include_bytes!("../../sample-data/threads.tracing"))
+    ///     .unwrap();
+    /// replay.close().unwrap();
+    ///
+    /// assert_eq!(summary.threads.len(), 1);
+    /// assert_eq!(summary.threads["app"].thread_name.as_deref(), Some("app"));
+    /// assert_eq!(summary.threads["app"].record_count, 14);
+    /// ```
+    ///
+    /// [`with_threads`]: fn@Self::with_threads
+    /// [`with_thread_spans`]: fn@Self::with_thread_spans
+    /// [`on_dispatched`]: fn@Self::on_dispatched
+    #[must_use]
+    pub fn with_thread_mapping<F>(mut self, mapping: F) -> Self
+    where
+        F: Fn(&mut RecordedThread) + Send + Sync + 'static,
+    {
+        self.thread_mapping = Some(ThreadMapping::new(Arc::new(mapping)));
+        self
+    }
+
+    /// Replays only the skeleton of the spans in the recording, without any events.
+    ///
+    /// Only the records of the lifecycles of spans are replayed: their creation, entering and
+    /// exiting them, closing them, and the relationships between them. Events, their callsites,
+    /// and the values recorded on spans after they were created are skipped, and aren't counted
+    /// in the [`ReplaySummary`]. This reproduces the structure and timing of the spans in a
+    /// downstream system quickly, without the volume of the events.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// use tracing::Dispatch;
+    /// use tracing_replay::{recording::Trace, Replay, ReplayMode};
+    ///
+    /// let dispatched = Arc::new(Mutex::new(Vec::new()));
+    /// let hook_dispatched = Arc::clone(&dispatched);
+    /// let mut replay = Replay::new()
+    ///     .with_mode(ReplayMode::Deterministic)
+    ///     .with_dispatch_targets([Dispatch::new(tracing_subscriber::registry())])
+    ///     .with_span_skeleton()
+    ///     .on_dispatched(move |record| hook_dispatched.lock().unwrap().push(record.trace.clone()));
+    /// replay
+    ///     .replay_include(include_bytes!("../../sample-data/threads.tracing"))
+    ///     .unwrap();
+    /// replay.close().unwrap();
+    ///
+    /// let dispatched = dispatched.lock().unwrap();
+    /// assert!(!dispatched.iter().any(|trace| matches!(trace, Trace::Event(_))));
+    /// let new_spans = dispatched
+    ///     .iter()
+    ///     .filter(|trace| matches!(trace, Trace::NewSpan(_)))
+    ///     .count();
+    /// assert_eq!(new_spans, 2);
+    /// ```
+    #[must_use]
+    pub fn with_span_skeleton(mut self) -> Self {
+        self.span_skeleton = true;
+        self
+    }
+
+    /// Limits the replay to the spans and events which `filter` enables, by their target and
+    /// level.
+    ///
+    /// Spans and events which the filter doesn't enable are replayed as though the subscriber had
+    /// disabled them: events aren't dispatched, and spans aren't created, so that the spans and
+    /// events within them are replayed without them. They are still counted in the
+    /// [`ReplaySummary`]. By default, everything is replayed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// use tracing::{Dispatch, Event, Level, Subscriber};
+    /// use tracing_replay::{Replay, ReplayMode};
+    /// use tracing_subscriber::{filter::Targets, layer::Context, prelude::*, Layer};
+    ///
+    /// /// Collects the levels of the events the subscriber receives.
+    /// struct EventLevels(Arc<Mutex<Vec<Level>>>);
+    ///
+    /// impl<S: Subscriber> Layer<S> for EventLevels {
+    ///     fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+    ///         self.0.lock().unwrap().push(*event.metadata().level());
+    ///     }
+    /// }
+    ///
+    /// let levels = Arc::new(Mutex::new(Vec::new()));
+    /// let subscriber = tracing_subscriber::registry().with(EventLevels(Arc::clone(&levels)));
+    /// let filter: Targets = "record_events=warn".parse().unwrap();
+    /// let mut replay = Replay::new()
+    ///     .with_mode(ReplayMode::Deterministic)
+    ///     .with_dispatch_targets([Dispatch::new(subscriber)])
+    ///     .with_filter(filter);
+    /// let summary = replay
+    ///     .replay_include(include_bytes!("../../sample-data/events.tracing"))
+    ///     .unwrap();
+    /// replay.close().unwrap();
+    ///
+    /// // The recording has events at every level, only the error and the warning are replayed.
+    /// assert_eq!(*levels.lock().unwrap(), [Level::ERROR, Level::WARN]);
+    /// assert_eq!(summary.record_count, 19);
+    /// ```
+    #[must_use]
+    pub fn with_filter(mut self, filter: Targets) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Limits the replay to a single span and everything inside it.
+    ///
+    /// Only the selected span, its descendants and the events within them are replayed. The
+    /// selected span is replayed as a root span. This is useful to extract a single request from a
+    /// busy recording.
+    ///
+    /// Descendants are determined by the parent of each span or event, whether that parent was
+    /// specified explicitly or was the current span on the recorded thread.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tracing_replay::{Replay, SpanSelector};
+    ///
+    /// // Replay the third span named "request" (occurrences start at 0).
+    /// let replay = Replay::new().with_span_subtree(SpanSelector::Name {
+    ///     name: "request".into(),
+    ///     occurrence: 2,
+    /// });
+    /// ```
+    #[must_use]
+    pub fn with_span_subtree(mut self, selector: SpanSelector) -> Self {
+        self.subtree = Some(SubtreeFilter::new(selector));
+        self
+    }
+
+    /// Verifies that the replay reproduces the recording.
+    ///
+    /// In verification mode, traces are replayed into an internal subscriber which records them
+    /// instead of into the default dispatcher. When the replay is closed, the traces it received
+    /// are compared with the traces that were replayed from the recording and the result is
+    /// returned in the [`ReplayCloseSummary`].
+    ///
+    /// The comparison ignores timestamps and the span Ids assigned during the replay. Traces are
+    /// compared in order for each recorded thread, while follows from relationships are compared
+    /// without regard for their order. This provides a round-trip fidelity check, for example in
+    /// CI or when validating a new recording format.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let temp_dir = tempfile::tempdir().unwrap();
+    /// # let path_buf = temp_dir.path().join("recording.tracing");
+    /// # let recording_path = path_buf.to_str().unwrap();
+    /// # {
+    /// #    use std::io::Write;
+    /// #    let mut file = std::fs::File::create(recording_path).unwrap();
+    /// #    writeln!(file, "{}", r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"RegisterCallsite":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"}}}"#);
+    /// #    writeln!(file, "{}", r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543496,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#);
+    /// # }
+    ///
+    /// let mut replay = tracing_replay::Replay::new().with_verification();
+    /// replay.replay_file(recording_path).unwrap();
+    ///
+    /// let summary = replay.close_with_summary().unwrap();
+    /// let report = summary.verification.unwrap();
+    /// assert!(report.is_match(), "{report}");
+    /// # temp_dir.close().unwrap();
+    /// ```
+    #[must_use]
+    pub fn with_verification(mut self) -> Self {
+        let verifier = Verifier::new();
+        self.dispatch_targets
+            .push(tracing::Dispatch::new(verifier.subscriber()));
+        self.dispatch = self.targets_dispatch();
+        self.verifier = Some(verifier);
+        self
+    }
+
+    /// Dispatches the replayed traces to each of `targets` instead of the default dispatcher.
+    ///
+    /// Each target receives every trace which it is interested in, so a recording can be viewed
+    /// and exported at the same time, for example. Targets decide independently which spans and
+    /// events they are interested in. A span which one target disabled doesn't exist for that
+    /// target, so its children are created as root spans there. Calling this method more than
+    /// once adds more targets. When verification is enabled, the verifier is one more target.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tracing::Dispatch;
+    ///
+    /// let errors_only = tracing_subscriber::fmt()
+    ///     .with_max_level(tracing::Level::ERROR)
+    ///     .finish();
+    /// let everything = tracing_subscriber::fmt()
+    ///     .with_max_level(tracing::Level::TRACE)
+    ///     .finish();
+    ///
+    /// let replay = tracing_replay::Replay::new()
+    ///     .with_dispatch_targets([Dispatch::new(errors_only), Dispatch::new(everything)]);
+    /// ```
+    #[must_use]
+    pub fn with_dispatch_targets(
+        mut self,
+        targets: impl IntoIterator<Item = tracing::Dispatch>,
+    ) -> Self {
+        self.dispatch_targets.extend(targets);
+        self.dispatch = self.targets_dispatch();
+        self
+    }
+
+    /// Sets a hook which is called after each record has been dispatched.
+    ///
+    /// The hook is called on the replay thread which dispatched the record, once the record has
+    /// been handed over to the dispatcher. This allows test harnesses to assert on the order,
+    /// number, and contents of the replayed records without writing their own subscriber.
+    ///
+    /// Records which aren't replayed aren't passed to the hook. This includes records outside
+    /// the time range, records for threads which aren't selected, and records which refer to
+    /// spans that weren't replayed. Follows from relationships and span values which are held
+    /// back until their spans have been created on other threads are passed to the hook once
+    /// they are dispatched. If
+    /// the recording uses a callsite without registering it first, the hook also receives the
+    /// callsite registration which is dispatched in its place.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let temp_dir = tempfile::tempdir().unwrap();
+    /// # let path_buf = temp_dir.path().join("recording.tracing");
+    /// # let recording_path = path_buf.to_str().unwrap();
+    /// # {
+    /// #    use std::io::Write;
+    /// #    let mut file = std::fs::File::create(recording_path).unwrap();
+    /// #    writeln!(file, "{}", r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"RegisterCallsite":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"}}}"#);
+    /// #    writeln!(file, "{}", r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543496,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#);
+    /// # }
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// use tracing_replay::recording::{FieldValue, Trace};
+    ///
+    /// let messages = Arc::new(Mutex::new(Vec::new()));
+    /// let hook_messages = Arc::clone(&messages);
+    /// let mut replay = tracing_replay::Replay::new()
+    ///     .with_speed(f64::INFINITY)
+    ///     .on_dispatched(move |record| {
+    ///         if let Trace::Event(event) = &record.trace {
+    ///             if let FieldValue::Debug(message) = &event.fields[0].value {
+    ///                 hook_messages.lock().unwrap().push(message.clone());
+    ///             }
+    ///         }
+    ///     });
+    /// replay.replay_file(recording_path).unwrap();
+    /// replay.close().unwrap();
+    ///
+    /// assert_eq!(*messages.lock().unwrap(), vec!["I am an info event!"]);
+    /// # temp_dir.close().unwrap();
+    /// ```
+    #[must_use]
+    pub fn on_dispatched<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&TraceRecord) + Send + Sync + 'static,
+    {
+        self.on_dispatched = Some(OnDispatched(Arc::new(hook)));
+        self
+    }
+
+    /// Watches the dispatcher threads, and calls `on_stall` for each one which hasn't finished
+    /// dispatching a trace within `timeout`.
+    ///
+    /// A dispatcher thread can stall while it waits for the recorded timestamp of a trace, for
+    /// example after a long gap in the recording, while it waits for a span that another
+    /// dispatcher thread creates, or while the subscriber handles the trace. The
+    /// [`StalledDispatcher`] which `on_stall` is called with says which of these it is, together
+    /// with the recorded thread and the trace being dispatched. Each stalled trace is reported
+    /// once, and the replay carries on waiting for it.
+    ///
+    /// The dispatcher threads are watched from a thread of their own, which `on_stall` is called
+    /// on. Traces which are dispatched inline, because threads can't be spawned, aren't watched.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::{
+    ///     sync::{Arc, Mutex},
+    ///     time::Duration,
+    /// };
+    ///
+    /// use tracing::Dispatch;
+    /// use tracing_replay::{Replay, ReplayMode, StallCause};
+    /// use tracing_subscriber::{layer::Context, prelude::*, Layer};
+    ///
+    /// // A layer which is slow to handle events.
+    /// struct Slow;
+    ///
+    /// impl<S: tracing::Subscriber> Layer<S> for Slow {
+    ///     fn on_event(&self, _event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+    ///         std::thread::sleep(Duration::from_millis(100));
+    ///     }
+    /// }
+    ///
+    /// let subscriber = tracing_subscriber::registry().with(Slow);
+    ///
+    /// let stalls = Arc::new(Mutex::new(Vec::new()));
+    /// let hook_stalls = Arc::clone(&stalls);
+    /// let mut replay = Replay::new()
+    ///     .with_mode(ReplayMode::Deterministic)
+    ///     .with_dispatch_targets([Dispatch::new(subscriber)])
+    ///     .with_watchdog(Duration::from_millis(20), move |stalled| {
+    ///         hook_stalls.lock().unwrap().push(stalled.clone());
+    ///     });
+    /// replay
+    ///     .replay_include(include_bytes!("../../sample-data/events.tracing"))
+    ///     .unwrap();
+    /// replay.close().unwrap();
+    ///
+    /// let stalls = stalls.lock().unwrap();
+    /// assert_eq!(stalls[0].thread_id, "ThreadId(1)");
+    /// assert_eq!(stalls[0].cause, StallCause::Subscriber);
+    /// assert_eq!(stalls[0].trace_kind, "Event");
+    /// ```
+    #[must_use]
+    pub fn with_watchdog<F>(mut self, timeout: Duration, on_stall: F) -> Self
+    where
+        F: Fn(&StalledDispatcher) + Send + Sync + 'static,
+    {
+        self.watchdog = Some(Watchdog::new(timeout, Arc::new(on_stall)));
+        self
+    }
+
+    /// Registers a breakpoint which pauses the replay and calls `callback` when it is hit.
+    ///
+    /// Breakpoints are checked as each record is read from the recording, before it is
+    /// dispatched. When a record hits a breakpoint, `callback` is called with the record and no
+    /// further records are read until it returns. The replay's clock is paused while the
+    /// callback runs, so records which are already scheduled aren't dispatched late when the
+    /// replay resumes. This allows a recorded incident to be inspected interactively, for example
+    /// by waiting for input in the callback. If a record hits more than one breakpoint, the
+    /// callbacks are called in the order in which the breakpoints were registered.
+    ///
+    /// When replaying in [`ReplayMode::Realtime`], records which were read before the breakpoint
+    /// was hit may still be dispatched while the callback runs if they were already due.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// use tracing_replay::{recording::Trace, Breakpoint};
+    ///
+    /// let recording = concat!(
+    ///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"RegisterCallsite":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"}}}"#,
+    ///     "\n",
+    ///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543496,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#,
+    ///     "\n",
+    /// );
+    ///
+    /// let hits = Arc::new(Mutex::new(0));
+    /// let callback_hits = Arc::clone(&hits);
+    /// let mut replay = tracing_replay::Replay::new().with_breakpoint(
+    ///     Breakpoint::event_field("message", "I am an info event!"),
+    ///     move |record| {
+    ///         assert!(matches!(record.trace, Trace::Event(_)));
+    ///         *callback_hits.lock().unwrap() += 1;
+    ///     },
+    /// );
+    /// replay.replay_str(recording).unwrap();
+    /// replay.close().unwrap();
+    ///
+    /// assert_eq!(*hits.lock().unwrap(), 1);
+    /// ```
+    #[must_use]
+    pub fn with_breakpoint<F>(mut self, breakpoint: Breakpoint, callback: F) -> Self
+    where
+        F: FnMut(&TraceRecord) + Send + 'static,
+    {
+        self.breakpoints.push(breakpoint, Box::new(callback));
+        self
+    }
+
+    /// Sets the number of threads used to parse records.
+    ///
+    /// By default (`0`), records are parsed on the thread that is replaying the file, in between
+    /// handing records over to the dispatcher threads. With one or more parse threads, parsing
+    /// happens in parallel to dispatching, which improves throughput for large recordings on
+    /// multicore machines, especially when replaying at high speed. Records are always replayed
+    /// in the order they appear in the recording, regardless of the number of parse threads.
+    ///
+    /// If the parse threads can't be spawned, records are parsed on the thread that is replaying
+    /// the file, as they are by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let replay = tracing_replay::Replay::new()
+    ///     .with_speed(f64::INFINITY)
+    ///     .with_parse_threads(2);
+    /// ```
+    #[must_use]
+    pub fn with_parse_threads(mut self, parse_threads: usize) -> Self {
+        self.parse_threads = parse_threads;
+        self
+    }
+
+    /// Returns a handle which can control this replay while it is in progress.
+    ///
+    /// The handle can be sent to another thread, which allows the replay to be controlled while
+    /// [`replay_file`] is blocking the current thread.
+    ///
+    /// [`replay_file`]: fn@Self::replay_file
+    #[must_use]
+    pub fn handle(&self) -> ReplayHandle {
+        ReplayHandle {
+            clock: Arc::clone(&self.clock),
+        }
+    }
+
+    /// Replays a tracing recording file through the default dispatcher.
+    ///
+    /// The file at `path` is read and the trace records stored in the file are replayed one by
+    /// one.
+    ///
+    /// A recording from a process which crashed may end part way through a record. If the final
+    /// record in the file is incomplete, it is skipped and reported in the returned
+    /// [`ReplaySummary`] instead of failing the whole replay.
+    ///
+    /// Recordings stored in a container, see [`tracing_cassette::ContainerWriter`], are decoded
+    /// into memory before they are replayed. A container which is corrupt or was never finished
+    /// is rejected with [`ReplayFileError::CannotDecodeContainer`].
+    ///
+    /// The traces from each recorded thread are dispatched on a replay thread of their own. In
+    /// environments where threads can't be spawned, the traces are instead dispatched inline on
+    /// the current thread, in the order they appear in the recording.
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if the file at the provided path cannot be read or if
+    /// individual records cannot be read or deserialized, or if a span::Id collision is found
+    /// while the [`SpanIdCollisionPolicy::Error`] policy is in use.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let temp_dir = tempfile::tempdir().unwrap();
+    /// # let path_buf = temp_dir.path().join("recording.tracing");
+    /// # let recording_path = path_buf.to_str().unwrap();
+    /// # {
+    /// #    use std::io::Write;
+    /// #    let mut file = std::fs::File::create(recording_path).unwrap();
+    /// #    writeln!(file, "{}", r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"RegisterCallsite":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"}}}"#);
+    /// #    writeln!(file, "{}", r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543496,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#);
+    /// # }
+    ///
+    /// let mut replay = tracing_replay::Replay::new();
+    /// let result = replay.replay_file(recording_path);
+    /// assert!(result.is_ok());
+    /// # temp_dir.close().unwrap();
+    /// ```
+    pub fn replay_file(&mut self, path: &str) -> Result<ReplaySummary, ReplayFileError> {
+        let data = reader::map_file(path)?;
+
+        let index = if self.range_start_offset().is_zero() {
+            None
+        } else {
+            match RecordingIndex::read_sidecar(path) {
+                Ok(index) => Some(index),
+                Err(ReplayFileError::CannotReadIndex { inner })
+                    if inner.kind() == io::ErrorKind::NotFound =>
+                {
+                    None
+                }
+                Err(err) => return Err(err),
+            }
+        };
+
+        let seek = index.as_ref().and_then(|index| {
+            index
+                .checkpoint_before(self.range_start_offset())
+                .map(|checkpoint| Seek {
+                    start: index.start(),
+                    checkpoint,
+                })
+        });
+        self.replay_data(&data, seek, Some(path))
+    }
+
+    /// Resumes an interrupted replay of the tracing recording at the provided path.
+    ///
+    /// The replay continues from the sidecar checkpoint written by a previous replay configured
+    /// with [`with_checkpoints`], see [`ReplayCheckpoint`]. The state at the checkpoint is
+    /// reconstructed by replaying the callsites and the spans which were open at that point, then
+    /// the records after the checkpoint are replayed. If there is no checkpoint, the whole
+    /// recording is replayed, the same as with [`replay_file`].
+    ///
+    /// In [`ReplayMode::Realtime`], the replay continues on the same schedule as the recording
+    /// from the checkpoint onwards. The summary only covers the records which were replayed after
+    /// resuming.
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if the file at the provided path cannot be read, if the
+    /// checkpoint cannot be read or deserialized, or under the same conditions as
+    /// [`replay_file`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let temp_dir = tempfile::tempdir().unwrap();
+    /// # let path_buf = temp_dir.path().join("recording.tracing");
+    /// # let recording_path = path_buf.to_str().unwrap();
+    /// # {
+    /// #    use std::io::Write;
+    /// #    let mut file = std::fs::File::create(recording_path).unwrap();
+    /// #    writeln!(file, "{}", r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"RegisterCallsite":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"}}}"#);
+    /// #    writeln!(file, "{}", r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543496,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#);
+    /// # }
+    /// use std::time::Duration;
+    ///
+    /// let mut replay = tracing_replay::Replay::new().with_checkpoints(Duration::from_secs(60));
+    /// let result = replay.resume_from_checkpoint(recording_path);
+    /// assert!(result.is_ok());
+    /// # temp_dir.close().unwrap();
+    /// ```
+    ///
+    /// [`with_checkpoints`]: fn@Self::with_checkpoints
+    /// [`replay_file`]: fn@Self::replay_file
+    pub fn resume_from_checkpoint(&mut self, path: &str) -> Result<ReplaySummary, ReplayFileError> {
+        let data = reader::map_file(path)?;
+
+        let checkpoint = match ReplayCheckpoint::read_sidecar(path) {
+            Ok(checkpoint) => Some(checkpoint),
+            Err(ReplayFileError::CannotReadCheckpoint { inner })
+                if inner.kind() == io::ErrorKind::NotFound =>
+            {
+                None
+            }
+            Err(err) => return Err(err),
+        };
+        let seek = checkpoint.as_ref().map(|checkpoint| Seek {
+            start: checkpoint.start(),
+            checkpoint: checkpoint.checkpoint(),
+        });
+
+        self.replay_data(&data, seek, Some(path))
+    }
+
+    /// Replays a tracing recording held in a string through the default dispatcher.
+    ///
+    /// This is the same as [`replay_file`], except that the recording is taken from `recording`
+    /// instead of being read from a file. This is convenient for replaying recordings which are
+    /// embedded in tests with [`include_str!`].
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if individual records cannot be deserialized, or if a
+    /// span::Id collision is found while the [`SpanIdCollisionPolicy::Error`] policy is in use.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let recording = concat!(
+    ///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"RegisterCallsite":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"}}}"#,
+    ///     "\n",
+    ///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543496,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#,
+    ///     "\n",
+    /// );
+    ///
+    /// let mut replay = tracing_replay::Replay::new();
+    /// let summary = replay.replay_str(recording).unwrap();
+    /// assert_eq!(summary.record_count, 2);
+    /// ```
+    ///
+    /// [`replay_file`]: fn@Self::replay_file
+    pub fn replay_str(&mut self, recording: &str) -> Result<ReplaySummary, ReplayFileError> {
+        self.replay_bytes(recording.as_bytes())
+    }
+
+    /// Replays a tracing recording held in memory through the default dispatcher.
+    ///
+    /// This is the same as [`replay_file`], except that the recording is taken from `recording`
+    /// instead of being read from a file. This is convenient for replaying recordings which are
+    /// embedded in tests with [`include_bytes!`].
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if individual records cannot be deserialized, or if a
+    /// span::Id collision is found while the [`SpanIdCollisionPolicy::Error`] policy is in use.
+    /// Recordings written in a newer version of the recording format are rejected with
+    /// [`ReplayFileError::RecordingTooNew`].
+    ///
+    /// # Examples
+    ///
+    /// The header at the start of a recording is checked before any records are replayed.
+    ///
+    /// ```
+    /// use tracing_cassette::FORMAT_VERSION;
+    /// use tracing_replay::{Replay, ReplayFileError};
+    ///
+    /// let record = r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"RegisterCallsite":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"}}}"#;
+    ///
+    /// let current = format!("{{\"header\":{{\"version\":{FORMAT_VERSION}}}}}\n{record}\n");
+    /// let summary = Replay::new().replay_bytes(current.as_bytes()).unwrap();
+    /// assert_eq!(summary.record_count, 1);
+    ///
+    /// let too_new = format!("{{\"header\":{{\"version\":1000}}}}\n{record}\n");
+    /// let result = Replay::new().replay_bytes(too_new.as_bytes());
+    /// assert!(matches!(
+    ///     result,
+    ///     Err(ReplayFileError::RecordingTooNew { version: 1000 })
+    /// ));
+    /// ```
+    ///
+    /// Recordings written in an earlier version of the format are migrated to the current one as
+    /// they are replayed.
+    ///
+    /// ```
+    /// # use tracing_replay::Replay;
+    /// # let record = r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"RegisterCallsite":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"}}}"#;
+    /// let v2 = format!("{{\"header\":{{\"version\":2}}}}\n{record}\n");
+    /// let summary = Replay::new().replay_bytes(v2.as_bytes()).unwrap();
+    /// assert_eq!(summary.record_count, 1);
+    /// ```
+    ///
+    /// [`replay_file`]: fn@Self::replay_file
+    pub fn replay_bytes(&mut self, recording: &[u8]) -> Result<ReplaySummary, ReplayFileError> {
+        self.replay_data(recording, None, None)
+    }
+
+    /// Replays a tracing recording held in memory and waits until it has been dispatched.
+    ///
+    /// This is the same as [`replay_bytes`], except that it only returns once every record has
+    /// been handed to the subscriber, instead of once every record has been handed over to the
+    /// dispatcher threads. This makes it simple to replay a golden recording in a test and then
+    /// check what the subscriber received, without having to [`close`] the replay first. The
+    /// [`replay_embedded!`] macro embeds a recording in the test binary and replays it with this
+    /// method.
+    ///
+    /// Records which refer to spans that haven't been replayed yet may still be held back when
+    /// this method returns, these are only resolved by [`close`].
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if individual records cannot be deserialized, or if a
+    /// span::Id collision is found while the [`SpanIdCollisionPolicy::Error`] policy is in use.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::{
+    ///     atomic::{AtomicUsize, Ordering},
+    ///     Arc,
+    /// };
+    ///
+    /// let dispatched = Arc::new(AtomicUsize::new(0));
+    /// let counter = Arc::clone(&dispatched);
+    /// let mut replay = tracing_replay::Replay::new()
+    ///     .with_speed(f64::INFINITY)
+    ///     .with_dispatch_targets([tracing::Dispatch::new(tracing_subscriber::registry())])
+    ///     .on_dispatched(move |_record| {
+    ///         counter.fetch_add(1, Ordering::Relaxed);
+    ///     });
+    /// let recording = include_bytes!("../../sample-data/events.tracing");
+    /// let summary = replay.replay_include(recording).unwrap();
+    /// assert_eq!(dispatched.load(Ordering::Relaxed), summary.record_count);
+    /// ```
+    ///
+    /// [`replay_bytes`]: fn@Self::replay_bytes
+    /// [`close`]: fn@Self::close
+    pub fn replay_include(&mut self, recording: &[u8]) -> Result<ReplaySummary, ReplayFileError> {
+        let summary = self.replay_bytes(recording)?;
+        self.flush_thread_dispatchers();
+
+        Ok(summary)
+    }
+
+    /// Replays structured JSON logs through the default dispatcher.
+    ///
+    /// Logs written by the JSON formatter of `tracing-subscriber` or by `tracing-bunyan-formatter`
+    /// don't contain everything that a recording does, so they are first converted into trace
+    /// records on a best-effort basis, see [`JsonLogFormat`] for the details of each format. In
+    /// particular:
+    ///
+    /// - Spans are reconstructed from the span context of each log line. Spans which are still
+    ///   open at the end of the logs are closed after the last log line.
+    /// - Callsites are identified by their name, target, level, location, and field names, as the
+    ///   logs don't identify them.
+    /// - JSON strings, numbers, and booleans are replayed as the corresponding field values, any
+    ///   other JSON value is replayed as its JSON text with `Debug`.
+    /// - Log lines which don't name their thread are replayed on a single thread.
+    ///
+    /// The records are then replayed with the same configuration as [`replay_file`].
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if a log line isn't a JSON object in the given format,
+    /// which is reported as [`ReplayFileError::CannotDeserializeRecord`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tracing_replay::{JsonLogFormat, Replay};
+    ///
+    /// let logs = concat!(
+    ///     r#"{"timestamp":"2024-05-08T14:09:00.543400Z","level":"INFO","fields":{"message":"I am an info event!","answer":42},"target":"app","span":{"name":"request"},"spans":[{"name":"request"}]}"#,
+    ///     "\n",
+    /// );
+    ///
+    /// let mut replay = Replay::new();
+    /// let summary = replay.replay_json_logs(logs, JsonLogFormat::FmtJson).unwrap();
+    /// // The `request` span is created, entered, exited, and closed around the event.
+    /// assert_eq!(summary.record_count, 5);
+    /// ```
+    ///
+    /// [`replay_file`]: fn@Self::replay_file
+    pub fn replay_json_logs(
+        &mut self,
+        logs: &str,
+        format: JsonLogFormat,
+    ) -> Result<ReplaySummary, ReplayFileError> {
+        let records = ingest::convert(logs, format)?;
+        let mut summary = ReplaySummary::new();
+        self.replay_records(
+            records.into_iter().map(|record| Ok(Some(record))),
+            None,
+            &mut summary,
+        )?;
+        self.complete_summary(&mut summary);
+
+        Ok(summary)
+    }
+
+    /// Replays a tracing recording held in memory without blocking the current thread.
+    ///
+    /// This is the same as [`replay_bytes`], except that the replay waits for records to be due
+    /// by awaiting the futures returned by `sleep`, instead of by blocking a thread. The replay
+    /// doesn't depend on any particular async runtime, `sleep` is called with the duration to
+    /// wait and should return a future which completes once that duration has passed, such as a
+    /// timer from the runtime in use.
+    ///
+    /// This allows recordings to be replayed where threads can't be blocked or aren't available
+    /// at all, such as in a browser on `wasm32`. Where dispatcher threads can't be spawned, traces
+    /// are dispatched on the thread which polls the returned future, see [`replay_file`].
+    ///
+    /// Records are always parsed on the thread which polls the returned future, regardless of
+    /// [`with_parse_threads`], and the rate limit set with [`with_max_rate`] is also applied by
+    /// awaiting `sleep`.
+    ///
+    /// On `wasm32-unknown-unknown` the standard library doesn't provide a clock, so time is only
+    /// considered to have passed once a future returned by `sleep` has completed. Pacing is
+    /// unaffected, but the dispatch lag reported in [`ThreadSummary`] doesn't include any time
+    /// spent dispatching.
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if individual records cannot be deserialized, or if a
+    /// span::Id collision is found while the [`SpanIdCollisionPolicy::Error`] policy is in use.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::{future::Future, pin::pin, sync::Arc, task::{Context, Poll, Wake, Waker}};
+    /// # struct ThreadWaker(std::thread::Thread);
+    /// # impl Wake for ThreadWaker {
+    /// #     fn wake(self: Arc<Self>) {
+    /// #         self.0.unpark();
+    /// #     }
+    /// # }
+    /// # fn block_on<F: Future>(future: F) -> F::Output {
+    /// #     let mut future = pin!(future);
+    /// #     let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    /// #     let mut cx = Context::from_waker(&waker);
+    /// #     loop {
+    /// #         match future.as_mut().poll(&mut cx) {
+    /// #             Poll::Ready(output) => return output,
+    /// #             Poll::Pending => std::thread::park(),
+    /// #         }
+    /// #     }
+    /// # }
+    /// let recording = concat!(
+    ///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"RegisterCallsite":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"}}}"#,
+    ///     "\n",
+    ///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543496,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#,
+    ///     "\n",
+    /// );
+    ///
+    /// let mut replay = tracing_replay::Replay::new();
+    /// // In a browser, this could be a timer such as `gloo_timers::future::sleep`.
+    /// let sleep = |duration| async move { std::thread::sleep(duration) };
+    /// let summary = block_on(replay.replay_bytes_async(recording.as_bytes(), sleep)).unwrap();
+    /// assert_eq!(summary.record_count, 2);
+    /// ```
+    ///
+    /// [`replay_bytes`]: fn@Self::replay_bytes
+    /// [`replay_file`]: fn@Self::replay_file
+    /// [`with_parse_threads`]: fn@Self::with_parse_threads
+    /// [`with_max_rate`]: fn@Self::with_max_rate
+    pub async fn replay_bytes_async<S, F>(
+        &mut self,
+        recording: &[u8],
+        mut sleep: S,
+    ) -> Result<ReplaySummary, ReplayFileError>
+    where
+        S: FnMut(Duration) -> F,
+        F: Future<Output = ()>,
+    {
+        let recording = reader::decode_container(recording)?;
+        self.fit_to_target_duration(&recording);
+        let mut summary = ReplaySummary::new();
+        summary.set_header(reader::header(&recording));
+        // Waiting for the rate limiter while dispatching would block, so it is awaited here
+        // instead.
+        let mut rate_limiter = self.rate_limiter.take();
+        let result = self
+            .replay_lines_async(
+                Lines::new(&recording),
+                &mut summary,
+                rate_limiter.as_mut(),
+                &mut sleep,
+            )
+            .await;
+        self.rate_limiter = rate_limiter;
+        result?;
+        self.complete_summary(&mut summary);
+
+        Ok(summary)
+    }
+
+    /// Replays the recording in `data`, starting from the checkpoint in `seek` if there is one.
+    ///
+    /// Checkpoints are written for the recording at `recording_path`, if there is one and
+    /// checkpoints are enabled. Recordings stored in a container are decoded first, positions in
+    /// `seek` are positions in the decoded recording.
+    fn replay_data(
+        &mut self,
+        data: &[u8],
+        seek: Option<Seek<'_>>,
+        recording_path: Option<&str>,
+    ) -> Result<ReplaySummary, ReplayFileError> {
+        let decoded = reader::decode_container(data)?;
+        let data = &*decoded;
+        self.fit_to_target_duration(data);
+        if let Some(meta_events) = &mut self.meta_events {
+            meta_events.start_recording(recording_path);
+        }
+        let mut summary = ReplaySummary::new();
+        summary.set_header(reader::header(data));
+        let mut lines = Lines::new(data);
+        let mut recording_start = None;
+        let mut checkpointer = self
+            .checkpoint_interval
+            .zip(recording_path)
+            .map(|(interval, recording_path)| Checkpointer::new(recording_path, interval));
+
+        if let Some(Seek { start, checkpoint }) = seek {
+            // Replay the records needed to reconstruct the state at the checkpoint, then continue
+            // reading the recording from the checkpoint onwards.
+            recording_start = Some(start);
+            let checkpoint_offset = Duration::from_micros(checkpoint.offset_us);
+            self.clock
+                .anchor(start + self.range_start_offset().max(checkpoint_offset));
+            if let Some(checkpointer) = &mut checkpointer {
+                checkpointer.resume(start, checkpoint, data);
+            }
+            for position in &checkpoint.prologue {
+                if let Some(line) = Lines::line_at(data, *position) {
+                    let Some(mut trace_record) = line.parse()? else {
+                        continue;
+                    };
+                    self.metrics.record_read();
+                    if !self.is_selected_thread(&trace_record.meta) {
+                        self.metrics.record_filtered();
+                        continue;
+                    }
+                    if !self.is_skeleton_trace(&trace_record.trace) {
+                        self.metrics.record_filtered();
+                        continue;
+                    }
+                    if let Some(thread_mapping) = &mut self.thread_mapping {
+                        thread_mapping.map(&mut trace_record.meta);
+                    }
+                    summary.count_record(&trace_record.meta);
+                    summary.count_callsite(&trace_record.trace);
+                    self.dispatch_trace(trace_record);
+                }
+            }
+            lines = Lines::starting_at(data, checkpoint.position);
+        }
+
+        if let Some(amplification) = self.amplification {
+            let records = amplification.records(&lines);
+            self.replay_records(records, recording_start, &mut summary)?;
+        } else if let Some(mut checkpointer) = checkpointer {
+            self.replay_lines_with_checkpoints(
+                lines,
+                recording_start,
+                &mut summary,
+                &mut checkpointer,
+            )?;
+            checkpointer.complete()?;
+        } else if self.parse_threads == 0 {
+            self.replay_records(
+                lines.map(|line| line.parse()),
+                recording_start,
+                &mut summary,
+            )?;
+        } else {
+            let parse_threads = self.parse_threads;
+            thread::scope(|scope| {
+                match pipeline::parse(scope, lines.clone(), parse_threads) {
+                    Ok(records) => self.replay_records(records, recording_start, &mut summary),
+                    // Without parser threads, the records are parsed on this thread instead.
+                    Err(_spawn_error) => self.replay_records(
+                        lines.map(|line| line.parse()),
+                        recording_start,
+                        &mut summary,
+                    ),
+                }
+            })?;
+        }
+
+        self.complete_summary(&mut summary);
+
+        Ok(summary)
+    }
+
+    /// Returns a sink which replays a recording as it is pushed in.
+    ///
+    /// This allows a recording to be replayed directly from any source, such as a message queue
+    /// or a network connection, as it is received. Records are replayed with the same
+    /// configuration as [`replay_file`], except that a sidecar index is never used. See
+    /// [`ReplaySink`] for details.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let register_callsite = r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"RegisterCallsite":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"}}}"#;
+    /// let event = r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543496,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#;
+    ///
+    /// let mut replay = tracing_replay::Replay::new();
+    /// let mut sink = replay.sink();
+    /// sink.push_line(register_callsite).unwrap();
+    /// // Data can also be pushed in chunks which don't end on a line boundary.
+    /// let (start, end) = event.split_at(100);
+    /// sink.push_bytes(start.as_bytes()).unwrap();
+    /// sink.push_bytes(end.as_bytes()).unwrap();
+    /// sink.push_bytes(b"\n").unwrap();
+    /// let summary = sink.finish().unwrap();
+    ///
+    /// assert_eq!(summary.record_count, 2);
+    /// replay.close().unwrap();
+    /// ```
+    ///
+    /// [`replay_file`]: fn@Self::replay_file
+    pub fn sink(&mut self) -> ReplaySink<'_> {
+        ReplaySink::new(self)
+    }
+
+    /// Returns a stepper which replays the in-memory `recording` one record at a time.
+    ///
+    /// Each record is dispatched only when the caller asks for it, which allows walking through
+    /// a recording in a debugger or interleaving assertions between records in tests. The replay
+    /// is switched to [`ReplayMode::Deterministic`]. Recorded threads which were already replayed
+    /// in another mode by an earlier replay continue to be replayed in that mode. See
+    /// [`ReplayStepper`] for details.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tracing_replay::recording::Trace;
+    ///
+    /// let event = r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543496,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#;
+    /// let recording = format!("{event}\n{event}\n");
+    ///
+    /// let mut replay = tracing_replay::Replay::new();
+    /// let mut stepper = replay.stepper(recording.as_bytes());
+    /// let record = stepper.step().unwrap().unwrap();
+    /// assert!(matches!(record.trace, Trace::Event(_)));
+    /// // The subscriber has received the first event, but not the second.
+    /// assert_eq!(stepper.step_n(5).unwrap(), 1);
+    /// let summary = stepper.finish();
+    ///
+    /// assert_eq!(summary.record_count, 2);
+    /// replay.close().unwrap();
+    /// ```
+    pub fn stepper<'r>(&'r mut self, recording: &'r [u8]) -> ReplayStepper<'r> {
+        ReplayStepper::new(self, recording)
+    }
+
+    /// Close the replay and check for errors.
+    ///
+    /// Since much of the work of replaying a [`tracing`] recording happens on other threads, work
+    /// may be ongoing when [`replay_file`] returns. This is desireable in the case that another
+    /// file constituting more traces from the same recording is to be replayed directly
+    /// afterwards.
+    ///
+    /// Calling this method waits for the dispatcher threads to complete and then tears them down.
+    /// To find out what couldn't be replayed, use [`close_with_summary`] instead.
+    ///
+    /// # Errors
+    ///
+    /// If any of the dispatcher threads panicked, the resulting messages are returned in
+    /// `ReplayCloseError`. All errors are collected into a vec together with their
+    /// recording thread Id.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let temp_dir = tempfile::tempdir().unwrap();
+    /// # let path_buf = temp_dir.path().join("recording.tracing");
+    /// # let recording_path = path_buf.to_str().unwrap();
+    /// # {
+    /// #    use std::io::Write;
+    /// #    let mut file = std::fs::File::create(recording_path).unwrap();
+    /// #    writeln!(file, "{}", r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"RegisterCallsite":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"}}}"#);
+    /// #    writeln!(file, "{}", r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543496,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#);
+    /// # }
+    ///
+    /// let mut replay = tracing_replay::Replay::new();
+    /// let _replay_result = replay.replay_file(recording_path);
+    ///
+    /// let close_result = replay.close();
+    /// assert!(close_result.is_ok());
+    /// # temp_dir.close().unwrap();
+    /// ```
+    ///
+    /// [`close_with_summary`]: fn@Self::close_with_summary
+    pub fn close(&mut self) -> Result<(), ReplayCloseError> {
+        self.close_with_summary().map(|_summary| ())
+    }
+
+    /// Close the replay, check for errors, and summarize what couldn't be replayed.
+    ///
+    /// This does the same as [`close`], and returns a [`ReplayCloseSummary`] as well.
+    ///
+    /// A follows from relationship can only be replayed once both spans involved have been
+    /// replayed, so these relationships are held back until then. Any that are still held back
+    /// once the dispatcher threads have completed are returned in the summary, as are values
+    /// recorded for spans which couldn't be replayed, and the result of verifying the replay.
+    ///
+    /// # Errors
+    ///
+    /// If any of the dispatcher threads panicked, the resulting messages are returned in
+    /// `ReplayCloseError`, the same as for [`close`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let recording = include_bytes!("../../sample-data/threads.tracing");
+    ///
+    /// let mut replay = tracing_replay::Replay::new()
+    ///     .with_mode(tracing_replay::ReplayMode::Deterministic);
+    /// replay.replay_bytes(recording).unwrap();
+    ///
+    /// let summary = replay.close_with_summary().unwrap();
+    /// assert!(summary.unresolved_follows_from.is_empty());
+    /// assert!(summary.unresolved_records.is_empty());
+    /// ```
+    ///
+    /// [`close`]: fn@Self::close
+    pub fn close_with_summary(&mut self) -> Result<ReplayCloseSummary, ReplayCloseError> {
+        self.finish_meta_events();
+        self.finish_thread_spans();
+
+        let replay_threads: HashMap<thread::ThreadId, String> = self
+            .threads
+            .iter()
+            .map(|(rec_thread_id, handle)| (handle.replay_thread_id, rec_thread_id.clone()))
+            .collect();
+
+        let mut errors = Vec::new();
+        for (key, handle) in self.threads.drain() {
+            let DispatchWorker::Thread {
+                join_handle,
+                trace_tx,
+                ..
+            } = handle.worker
+            else {
+                // Traces dispatched inline have all been dispatched already.
+                continue;
+            };
+            match trace_tx.send(DispatchableContainer::End) {
+                Ok(()) => match join_handle.join() {
+                    Ok(()) => {}
+                    Err(join_error) => errors.push((key, join_error)),
+                },
+                Err(send_error) => {
+                    errors.push((key, Box::new(send_error)));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            let pending_follows_from = {
+                let mut guard = self
+                    .pending_follows_from
+                    .lock()
+                    .expect("replay internal state (pending follows from) has become corrupted.");
+                std::mem::take(&mut *guard)
+            };
+            let unresolved_follows_from = pending_follows_from
+                .into_iter()
+                .map(|dis_follows_from| UnresolvedFollowsFrom {
+                    cause_id: dis_follows_from.cause.id.into(),
+                    effect_id: dis_follows_from.effect.id.into(),
+                })
+                .collect();
+
+            let pending_records = {
+                let mut guard = self
+                    .pending_records
+                    .lock()
+                    .expect("replay internal state (pending records) has become corrupted.");
+                std::mem::take(&mut *guard)
+            };
+            let unresolved_records = pending_records
+                .expired
+                .into_iter()
+                .chain(
+                    pending_records
+                        .pending
+                        .into_iter()
+                        .map(|pending| pending.record_values.span_key),
+                )
+                .map(|span_key| UnresolvedRecord {
+                    span_id: span_key.id.into(),
+                })
+                .collect();
+
+            let verification = self
+                .verifier
+                .as_mut()
+                .map(|verifier| verifier.report(&replay_threads));
+
+            Ok(ReplayCloseSummary {
+                unresolved_follows_from,
+                unresolved_records,
+                verification,
+            })
+        } else {
+            Err(ReplayCloseError { threads: errors })
+        }
+    }
+}
+
+/// The mode in which a recording is replayed.
+///
+/// The mode is set with [`Replay::with_mode`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ReplayMode {
+    /// Traces are replayed on the same schedule as they were recorded, scaled by the replay
+    /// speed.
+    ///
+    /// Traces recorded on different threads are dispatched independently of one another, so their
+    /// relative order may differ slightly from the recording.
+    #[default]
+    Realtime,
+    /// Traces are replayed strictly in the order they appear in the recording, without delays.
+    ///
+    /// Each trace is dispatched only once the previous trace has been dispatched, even when they
+    /// were recorded on different threads, and the replay doesn't depend on the wall clock. Two
+    /// replays of the same recording therefore produce the same sequence of calls to the
+    /// subscriber, which is useful for reproducible tests. The replay speed is ignored in this
+    /// mode.
+    Deterministic,
+}
+
+/// How a new span whose recorded span::Id is still in use by an open span is handled.
+///
+/// A subscriber only reuses a span::Id once the span it identified has closed, so a collision
+/// means that the recording is inconsistent, for example because it was stitched together from
+/// more than one recording. The policy is set with [`Replay::with_span_id_collision_policy`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SpanIdCollisionPolicy {
+    /// The replay stops with [`ReplayFileError::SpanIdCollision`].
+    Error,
+    /// The new span replaces the open span.
+    ///
+    /// The mapping to the open span is discarded, so traces referring to it which are still
+    /// queued on a replay thread are dispatched for the new span instead. The open span is never
+    /// closed during the replay.
+    Overwrite,
+    /// The new span is replayed as a separate version of the recorded span::Id.
+    ///
+    /// Traces recorded before the new span was created are dispatched for the open span, later
+    /// traces are dispatched for the new span. The open span is never closed during the replay.
+    #[default]
+    Version,
+}
+
+/// How the threads which replay each recorded thread are named.
+///
+/// The naming is set with [`Replay::with_thread_naming`].
+#[non_exhaustive]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum ThreadNaming {
+    /// Replay threads are given the same name as the recorded thread.
+    ///
+    /// Replay threads for recorded threads without a name are unnamed as well.
+    #[default]
+    Exact,
+    /// Replay threads are named from a template.
+    ///
+    /// The placeholder `{id}` is replaced first, with the recorded thread Id, for example
+    /// `ThreadId(1)`. Then the placeholder `{name}` is replaced with the name of the recorded
+    /// thread, or its recorded thread Id if it had no name. A recorded thread name which contains
+    /// `{id}` is therefore kept as it is.
+    Template(String),
+}
+
+impl ThreadNaming {
+    fn thread_name(&self, rec_thread_id: &str, rec_thread_name: Option<&str>) -> String {
+        match self {
+            Self::Exact => rec_thread_name.unwrap_or_default().to_owned(),
+            // The Id is substituted before the name, so that an `{id}` in a thread name is kept.
+            Self::Template(template) => template
+                .replace("{id}", rec_thread_id)
+                .replace("{name}", rec_thread_name.unwrap_or(rec_thread_id)),
+        }
+    }
+}
+
+/// Selects recorded threads to replay.
+///
+/// Threads are selected with [`Replay::with_threads`].
+#[non_exhaustive]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ThreadSelector {
+    /// Selects the recorded thread with the given Id, for example `ThreadId(1)`.
+    Id(String),
+    /// Selects the recorded threads with the given name.
+    Name(String),
+}
+
+impl ThreadSelector {
+    fn matches(&self, meta: &RecordMetaRef<'_>) -> bool {
+        match self {
+            Self::Id(thread_id) => meta.thread_id.as_str() == thread_id,
+            Self::Name(thread_name) => {
+                meta.thread_name.as_ref().map(CowStr::as_str) == Some(thread_name.as_str())
+            }
+        }
+    }
+}
+
+/// Handle to control a [`Replay`] while it is in progress.
+///
+/// A handle is obtained by calling [`Replay::handle`]. Changes made through the handle take effect
+/// immediately, including for traces which are already waiting to be dispatched.
+#[derive(Clone, Debug)]
+pub struct ReplayHandle {
+    clock: Arc<ReplayClock>,
+}
+
+impl ReplayHandle {
+    /// Returns the current replay speed.
+    #[must_use]
+    pub fn speed(&self) -> f64 {
+        self.clock.speed()
+    }
+
+    /// Sets the replay speed.
+    ///
+    /// The new speed applies from the current position in the recording onwards, so an operator
+    /// can slow playback down to watch a region of interest and then speed back up again. See
+    /// [`Replay::with_speed`] for the meaning of the value.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `speed` is not greater than zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let replay = tracing_replay::Replay::new();
+    /// let handle = replay.handle();
+    ///
+    /// std::thread::spawn(move || {
+    ///     handle.set_speed(0.5);
+    /// })
+    /// .join()
+    /// .unwrap();
+    ///
+    /// assert_eq!(replay.handle().speed(), 0.5);
+    /// ```
+    pub fn set_speed(&self, speed: f64) {
+        self.clock.set_speed(speed);
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub struct ReplaySummary {
+    pub record_count: usize,
+    /// The line index of the final record if it was incomplete and was skipped.
+    pub truncated_final_record: Option<usize>,
+    /// A breakdown of the replayed records per recorded thread, keyed by recorded thread Id.
+    pub threads: HashMap<String, ThreadSummary>,
+    /// The number of new spans whose recorded span::Id was still in use by an open span.
+    ///
+    /// How these spans were replayed depends on the [`SpanIdCollisionPolicy`].
+    pub span_id_collisions: usize,
+    /// The number of records which were dropped on purpose, see [`Chaos::with_drop_probability`].
+    ///
+    /// Dropped records aren't counted in the rest of the summary.
+    pub dropped_records: usize,
+    /// The number of records with a kind of trace that this version of `tracing-replay` doesn't
+    /// know about, which were skipped.
+    ///
+    /// A later version of `tracing-rec` may record new kinds of trace. These are skipped, so that
+    /// the rest of the recording can still be replayed. Unknown records aren't counted in the rest
+    /// of the summary.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let recording = concat!(
+    ///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+    ///     "\n",
+    ///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543450,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Suspend":{"id":1}}}"#,
+    ///     "\n",
+    ///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543500,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Exit":1}}"#,
+    ///     "\n",
+    /// );
+    ///
+    /// let mut replay = tracing_replay::Replay::new();
+    /// let summary = replay.replay_str(recording).unwrap();
+    ///
+    /// assert_eq!(summary.record_count, 2);
+    /// assert_eq!(summary.unknown_records, 1);
+    /// ```
+    pub unknown_records: usize,
+    /// The metadata from the header of the recording, which was added by the application that
+    /// recorded it, such as with `Rec::with_metadata` in `tracing-rec`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let recording = concat!(
+    ///     r#"{"header":{"version":3,"metadata":{"build":"4f2a9c1","scenario":"checkout"}}}"#,
+    ///     "\n",
+    ///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Enter":1}}"#,
+    ///     "\n",
+    /// );
+    ///
+    /// let mut replay = tracing_replay::Replay::new();
+    /// let summary = replay.replay_str(recording).unwrap();
+    ///
+    /// assert_eq!(summary.metadata["build"], "4f2a9c1");
+    /// assert_eq!(summary.metadata["scenario"], "checkout");
+    /// ```
+    pub metadata: BTreeMap<String, String>,
+    /// The dispatcher threads which panicked during the replay and were replaced, in the order
+    /// that the panics were noticed.
+    ///
+    /// When a dispatcher thread panics, for example because the subscriber panicked, a new
+    /// dispatcher thread is spawned for the recorded thread and the replay carries on. The trace
+    /// which was being dispatched when the thread panicked is lost, as are any traces which were
+    /// waiting to be dispatched by it. Spans which were entered on the panicked thread aren't
+    /// entered on the new one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    ///
+    /// use tracing_replay::{Replay, ReplayMode};
+    /// use tracing_subscriber::{filter, prelude::*};
+    ///
+    /// // A subscriber which panics the first time it sees an event.
+    /// static PANICKED: AtomicBool = AtomicBool::new(false);
+    /// let subscriber = tracing_subscriber::registry().with(filter::filter_fn(|metadata| {
+    ///     if metadata.is_event() && !PANICKED.swap(true, Ordering::Relaxed) {
+    ///         panic!("subscriber failed");
+    ///     }
+    ///     true
+    /// }));
+    ///
+    /// let mut replay = Replay::new()
+    ///     .with_mode(ReplayMode::Deterministic)
+    ///     .with_dispatch_targets([tracing::Dispatch::new(subscriber)]);
+    /// let summary = replay
+    ///     .replay_include(include_bytes!("../../sample-data/events.tracing"))
+    ///     .unwrap();
+    /// replay.close().unwrap();
+    ///
+    /// let restart = &summary.dispatcher_restarts[0];
+    /// assert_eq!(restart.thread_id, "ThreadId(1)");
+    /// assert_eq!(restart.message, "subscriber failed");
+    /// ```
+    pub dispatcher_restarts: Vec<DispatcherRestart>,
+    /// A breakdown of the replayed events and spans per callsite, keyed by recorded callsite Id.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let recording = concat!(
+    ///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"RegisterCallsite":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"}}}"#,
+    ///     "\n",
+    ///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543496,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#,
+    ///     "\n",
+    ///     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543496,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#,
+    ///     "\n",
+    /// );
+    ///
+    /// let mut replay = tracing_replay::Replay::new();
+    /// let summary = replay.replay_str(recording).unwrap();
+    ///
+    /// let callsite = &summary.callsites[&4403349456];
+    /// assert_eq!(callsite.target, "record_events");
+    /// assert_eq!(callsite.count, 2);
+    /// ```
+    pub callsites: HashMap<u64, CallsiteSummary>,
+}
+
+impl ReplaySummary {
+    /// Returns how closely the replay kept to the timing of the recording.
+    ///
+    /// For each trace, this measures how much later it was dispatched than it was scheduled to
+    /// be, and reports the distribution of these deviations over all traces, and for each kind
+    /// of trace. This can be used to check that a replay was faithful enough to the recorded
+    /// timing for latency sensitive experiments. As with [`ThreadSummary::max_dispatch_lag`],
+    /// the deviation is only measured in [`ReplayMode::Realtime`] at a finite speed, and traces
+    /// may still be being dispatched until the replay is closed with [`Replay::close`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// let mut replay = tracing_replay::Replay::new().with_speed(100.0);
+    /// let summary = replay
+    ///     .replay_include(include_bytes!("../../sample-data/events.tracing"))
+    ///     .unwrap();
+    /// replay.close().unwrap();
+    ///
+    /// let timing = summary.timing_fidelity();
+    /// assert!(timing.overall.count > 0);
+    /// assert_eq!(timing.kinds["Event"].count, 8);
+    /// assert!(timing.overall.p50 <= timing.overall.p95);
+    /// assert!(timing.overall.p95 <= timing.overall.max);
+    /// ```
+    #[must_use]
+    pub fn timing_fidelity(&self) -> TimingFidelity {
+        let mut overall = LagHistogram::default();
+        let mut kinds = HashMap::<&'static str, LagHistogram>::new();
+        for thread_summary in self.threads.values() {
+            let lags = thread_summary
+                .dispatch_stats
+                .lags
+                .lock()
+                .expect("replay internal state (dispatch stats) has become corrupted.");
+            for (kind, histogram) in lags.iter() {
+                overall.merge(histogram);
+                kinds.entry(kind).or_default().merge(histogram);
+            }
+        }
+
+        TimingFidelity {
+            overall: overall.distribution(),
+            kinds: kinds
+                .into_iter()
+                .map(|(kind, histogram)| (kind, histogram.distribution()))
+                .collect(),
+        }
+    }
+
+    fn new() -> Self {
+        Self {
+            record_count: 0,
+            truncated_final_record: None,
+            threads: HashMap::new(),
+            span_id_collisions: 0,
+            dropped_records: 0,
+            unknown_records: 0,
+            metadata: BTreeMap::new(),
+            dispatcher_restarts: Vec::new(),
+            callsites: HashMap::new(),
+        }
+    }
+
+    /// Takes the metadata from the `header` of the recording, if it has one.
+    fn set_header(&mut self, header: Option<Header>) {
+        if let Some(header) = header {
+            self.metadata = header.metadata;
+        }
+    }
+
+    fn count_callsite(&mut self, trace: &TraceRef<'_>) {
+        let rec_metadata = match trace {
+            TraceRef::Event(rec_event) => &rec_event.metadata,
+            TraceRef::NewSpan(rec_new_span) => &rec_new_span.metadata,
+            _ => return,
+        };
+        self.callsites
+            .entry(rec_metadata.id)
+            .or_insert_with(|| CallsiteSummary {
+                name: rec_metadata.name.as_str().to_owned(),
+                target: rec_metadata.target.as_str().to_owned(),
+                kind: rec_metadata.kind.clone(),
+                count: 0,
+            })
+            .count += 1;
+    }
+
+    fn count_record(&mut self, meta: &RecordMetaRef<'_>) {
+        let timestamp = SystemTime::UNIX_EPOCH + meta.timestamp();
+        self.record_count += 1;
+        if let Some(thread_summary) = self.threads.get_mut(meta.thread_id.as_str()) {
+            thread_summary.record_count += 1;
+            thread_summary.first_timestamp = thread_summary.first_timestamp.min(timestamp);
+            thread_summary.last_timestamp = thread_summary.last_timestamp.max(timestamp);
+        } else {
+            self.threads.insert(
+                meta.thread_id.as_str().to_owned(),
+                ThreadSummary {
+                    thread_name: meta.thread_name.clone().map(CowStr::into_owned),
+                    record_count: 1,
+                    first_timestamp: timestamp,
+                    last_timestamp: timestamp,
+                    replay_thread_id: None,
+                    dispatch_stats: Arc::default(),
+                },
+            );
+        }
+    }
+}
+
+/// A dispatcher thread which panicked during a replay and was replaced, see
+/// [`ReplaySummary::dispatcher_restarts`].
+#[non_exhaustive]
+#[derive(Clone, Debug)]
+pub struct DispatcherRestart {
+    /// The recorded thread whose traces the dispatcher thread was dispatching.
+    pub thread_id: String,
+    /// The message that the dispatcher thread panicked with.
+    pub message: String,
+}
+
+/// Summary of the events or spans replayed from a single callsite.
+#[non_exhaustive]
+#[derive(Debug)]
+pub struct CallsiteSummary {
+    /// The name of the callsite.
+    pub name: String,
+    /// The target of the callsite.
+    pub target: String,
+    /// Whether the callsite is for events or spans.
+    pub kind: recording::Kind,
+    /// The number of events dispatched or spans created from this callsite.
+    pub count: usize,
+}
+
+/// Summary of the records replayed from a single recorded thread.
+#[non_exhaustive]
+#[derive(Debug)]
+pub struct ThreadSummary {
+    /// The name of the recorded thread, if it had one.
+    pub thread_name: Option<String>,
+    /// The number of records replayed from this thread.
+    pub record_count: usize,
+    /// The recorded timestamp of the first record replayed from this thread.
+    pub first_timestamp: SystemTime,
+    /// The recorded timestamp of the last record replayed from this thread.
+    pub last_timestamp: SystemTime,
+    /// The Id of the thread which replayed the traces from this recorded thread.
+    ///
+    /// This can be used to correlate the output of a subscriber which includes thread Ids with
+    /// the original recording. It is `None` if none of the records from this thread needed to be
+    /// dispatched. If a replay thread couldn't be spawned, the traces were dispatched inline and
+    /// this is the Id of the thread which ran the replay.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let temp_dir = tempfile::tempdir().unwrap();
+    /// # let path_buf = temp_dir.path().join("recording.tracing");
+    /// # let recording_path = path_buf.to_str().unwrap();
+    /// # {
+    /// #    use std::io::Write;
+    /// #    let mut file = std::fs::File::create(recording_path).unwrap();
+    /// #    writeln!(file, "{}", r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"RegisterCallsite":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"}}}"#);
+    /// #    writeln!(file, "{}", r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543496,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[{"name":"message","value":{"Debug":"I am an info event!"}}],"metadata":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message"],"kind":"Event"},"parent":"Current"}}}"#);
+    /// # }
+    ///
+    /// let mut replay = tracing_replay::Replay::new();
+    /// let summary = replay.replay_file(recording_path).unwrap();
+    ///
+    /// let main_thread = &summary.threads["ThreadId(1)"];
+    /// assert_eq!(main_thread.thread_name.as_deref(), Some("main"));
+    /// assert!(main_thread.replay_thread_id.is_some());
+    /// assert_ne!(main_thread.replay_thread_id, Some(std::thread::current().id()));
+    /// # temp_dir.close().unwrap();
+    /// ```
+    pub replay_thread_id: Option<thread::ThreadId>,
+    dispatch_stats: Arc<DispatchStats>,
+}
+
+impl ThreadSummary {
+    /// Returns the maximum time by which a trace from this thread was dispatched later than
+    /// scheduled.
+    ///
+    /// A large lag means that the dispatcher thread couldn't keep up with the replay, for example
+    /// because the subscriber is slow or the replay speed is too high. Lag is only measured in
+    /// [`ReplayMode::Realtime`] at a finite speed.
+    ///
+    /// The dispatcher thread may still be dispatching traces when [`Replay::replay_file`]
+    /// returns, so this value keeps being updated until the replay is closed with
+    /// [`Replay::close`].
+    #[must_use]
+    pub fn max_dispatch_lag(&self) -> Duration {
+        self.dispatch_stats.max_lag()
+    }
+}
+
+/// Statistics collected by a dispatcher thread while it dispatches traces.
+#[derive(Debug, Default)]
+struct DispatchStats {
+    max_lag_us: AtomicU64,
+    /// The lags of the traces, by the kind of trace.
+    lags: Mutex<HashMap<&'static str, LagHistogram>>,
+}
+
+impl DispatchStats {
+    fn record_lag(&self, kind: &'static str, lag: Duration) {
+        let lag_us = u64::try_from(lag.as_micros()).unwrap_or(u64::MAX);
+        self.max_lag_us.fetch_max(lag_us, atomic::Ordering::Relaxed);
+        self.lags
+            .lock()
+            .expect("replay internal state (dispatch stats) has become corrupted.")
+            .entry(kind)
+            .or_default()
+            .record(lag);
+    }
+
+    fn max_lag(&self) -> Duration {
+        Duration::from_micros(self.max_lag_us.load(atomic::Ordering::Relaxed))
+    }
+}
+
+/// Summary of a replay which has been closed.
+///
+/// Returned by [`Replay::close_with_summary`].
+#[non_exhaustive]
+#[derive(Debug)]
+pub struct ReplayCloseSummary {
+    /// Follows from relationships which couldn't be replayed.
+    ///
+    /// This happens when one of the spans involved was never replayed, for example because it
+    /// was outside the replayed time range or the subscriber disabled it.
+    pub unresolved_follows_from: Vec<UnresolvedFollowsFrom>,
+    /// Values recorded for spans which couldn't be replayed.
+    ///
+    /// This happens when the values were held back waiting for their span to be created for
+    /// longer than the timeout set with [`Replay::with_pending_record_timeout`], or when the span
+    /// was never created.
+    pub unresolved_records: Vec<UnresolvedRecord>,
+    /// The result of verifying the replay, if it was created with [`Replay::with_verification`].
+    pub verification: Option<VerificationReport>,
+}
+
+/// A recorded follows from relationship which couldn't be replayed.
+#[non_exhaustive]
+#[derive(Debug, Eq, PartialEq)]
+pub struct UnresolvedFollowsFrom {
+    /// The recorded span Id of the cause.
+    pub cause_id: u64,
+    /// The recorded span Id of the effect.
+    pub effect_id: u64,
+}
+
+/// Recorded span values which couldn't be replayed.
+#[non_exhaustive]
+#[derive(Debug, Eq, PartialEq)]
+pub struct UnresolvedRecord {
+    /// The recorded span Id of the span the values were recorded for.
+    pub span_id: u64,
+}
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum ReplayFileError {
+    CannotOpenFile {
+        inner: io::Error,
+    },
+    /// A line of the recording couldn't be read.
+    #[deprecated(
+        note = "recordings are read through a memory map, so this error is no longer returned"
+    )]
+    CannotReadLine {
+        inner: io::Error,
+        line_index: usize,
+    },
+    CannotDeserializeRecord {
+        inner: serde_json::Error,
+        line_index: usize,
+        line: String,
+    },
+    /// The system time was before the Unix epoch when the replay started.
+    #[deprecated(
+        note = "the replay is timed with a monotonic clock, so this error is no longer returned"
+    )]
+    SystemTimeTooEarly {
+        duration: Duration,
+    },
+    CannotReadIndex {
+        inner: io::Error,
+    },
+    CannotDeserializeIndex {
+        inner: serde_json::Error,
+    },
+    UnsupportedIndexVersion {
+        version: u32,
+    },
+    CannotReadCheckpoint {
+        inner: io::Error,
+    },
+    CannotWriteCheckpoint {
+        inner: io::Error,
+    },
+    CannotDeserializeCheckpoint {
+        inner: serde_json::Error,
+    },
+    UnsupportedCheckpointVersion {
+        version: u32,
+    },
+    /// A new span reused the recorded span::Id of an open span and the
+    /// [`SpanIdCollisionPolicy::Error`] policy is in use.
+    SpanIdCollision {
+        span_id: u64,
+    },
+    /// The recording was written in a newer version of the recording format than this version of
+    /// `tracing-replay` can read.
+    RecordingTooNew {
+        version: u32,
+    },
+    /// The recording is stored in a container which couldn't be decoded, for example because it
+    /// is corrupt or was never finished.
+    CannotDecodeContainer {
+        inner: io::Error,
+    },
+}
+
+impl ReplayFileError {
+    /// Returns the line index of the record if this error was caused by a record which ends part
+    /// way through.
+    fn truncated_record_line_index(&self) -> Option<usize> {
+        match self {
+            Self::CannotDeserializeRecord {
+                inner, line_index, ..
+            } if inner.is_eof() => Some(*line_index),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ReplayFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl error::Error for ReplayFileError {}
+
+#[derive(Debug)]
+pub struct ReplayCloseError {
+    threads: Vec<(String, Box<dyn Any + Send + 'static>)>,
+}
+
+impl fmt::Display for ReplayCloseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ReplayCloseError:")?;
+        for (thread_id, error) in &self.threads {
+            write!(f, " - {thread_id}: {error:?}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl error::Error for ReplayCloseError {}
+
+impl Default for Replay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Replay {
+    /// Replays trace records in order, adding the records that were replayed to `summary`.
+    ///
+    /// If `recording_start` is `None`, the recording is assumed to start at the first record.
+    fn replay_records<'a>(
+        &mut self,
+        mut records: impl Iterator<Item = Result<Option<TraceRecordRef<'a>>, ReplayFileError>>,
+        mut recording_start: Option<Duration>,
+        summary: &mut ReplaySummary,
+    ) -> Result<(), ReplayFileError> {
+        while let Some(trace_record) = records.next() {
+            let trace_record = match trace_record {
+                Ok(Some(trace_record)) => trace_record,
+                Ok(None) => {
+                    summary.unknown_records += 1;
+                    continue;
+                }
+                Err(err) => match err.truncated_record_line_index() {
+                    // Only the very last record may be truncated, anywhere else it means that the
+                    // recording is corrupt.
+                    Some(line_index) if records.next().is_none() => {
+                        summary.truncated_final_record = Some(line_index);
+                        break;
+                    }
+                    _ => return Err(err),
+                },
+            };
+
+            if self
+                .replay_record(trace_record, &mut recording_start, summary)?
+                .is_break()
+            {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replays the records in `lines` in order, writing checkpoints along the way.
+    ///
+    /// This is the same as [`replay_records`], except that a checkpoint is written whenever one
+    /// is due, once all the records before it have been dispatched.
+    ///
+    /// [`replay_records`]: fn@Self::replay_records
+    fn replay_lines_with_checkpoints(
+        &mut self,
+        mut lines: Lines<'_>,
+        mut recording_start: Option<Duration>,
+        summary: &mut ReplaySummary,
+        checkpointer: &mut Checkpointer<'_>,
+    ) -> Result<(), ReplayFileError> {
+        while let Some(line) = lines.next() {
+            let trace_record = match line.parse() {
+                Ok(Some(trace_record)) => trace_record,
+                Ok(None) => {
+                    summary.unknown_records += 1;
+                    continue;
+                }
+                Err(err) => match err.truncated_record_line_index() {
+                    // Only the very last record may be truncated, anywhere else it means that the
+                    // recording is corrupt.
+                    Some(line_index) if lines.next().is_none() => {
+                        summary.truncated_final_record = Some(line_index);
+                        break;
+                    }
+                    _ => return Err(err),
+                },
+            };
+
+            if let Some(offset) = checkpointer.due(&trace_record) {
+                self.flush_thread_dispatchers();
+                checkpointer.write(offset, line.position)?;
+            }
+            checkpointer.track(&trace_record, line.position);
+
+            if self
+                .replay_record(trace_record, &mut recording_start, summary)?
+                .is_break()
+            {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replays the records in `lines` in order, awaiting `sleep` until each record is due.
+    ///
+    /// This is the same as [`replay_records`], except that the replay never blocks waiting for
+    /// the clock or for `rate_limiter`.
+    ///
+    /// [`replay_records`]: fn@Self::replay_records
+    async fn replay_lines_async<S, F>(
+        &mut self,
+        mut lines: Lines<'_>,
+        summary: &mut ReplaySummary,
+        mut rate_limiter: Option<&mut RateLimiter>,
+        sleep: &mut S,
+    ) -> Result<(), ReplayFileError>
+    where
+        S: FnMut(Duration) -> F,
+        F: Future<Output = ()>,
+    {
+        let mut recording_start = None;
+        while let Some(line) = lines.next() {
+            let trace_record = match line.parse() {
+                Ok(Some(trace_record)) => trace_record,
+                Ok(None) => {
+                    summary.unknown_records += 1;
+                    continue;
+                }
+                Err(err) => match err.truncated_record_line_index() {
+                    // Only the very last record may be truncated, anywhere else it means that the
+                    // recording is corrupt.
+                    Some(line_index) if lines.next().is_none() => {
+                        summary.truncated_final_record = Some(line_index);
+                        break;
+                    }
+                    _ => return Err(err),
+                },
+            };
+
+            // The clock is anchored at the first record, which is therefore always due.
+            if recording_start.is_some() && self.mode == ReplayMode::Realtime {
+                let timestamp = trace_record.meta.timestamp();
+                loop {
+                    match self.clock.time_until(timestamp) {
+                        Some(delay) if delay.is_zero() => break,
+                        Some(delay) => sleep_for(sleep, delay).await,
+                        // Check again later whether the clock has been resumed.
+                        None => sleep_for(sleep, PAUSED_POLL_INTERVAL).await,
+                    }
+                }
+            }
+            if let Some(rate_limiter) = &mut rate_limiter {
+                sleep_for(sleep, rate_limiter.delay()).await;
+            }
+
+            if self
+                .replay_record(trace_record, &mut recording_start, summary)?
+                .is_break()
+            {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replays a single trace record, adding it to `summary` if it was replayed.
+    ///
+    /// Returns [`ControlFlow::Break`] once the end of the time range has been reached, after
+    /// which no more records should be replayed.
+    fn replay_record(
+        &mut self,
+        mut trace_record: TraceRecordRef<'_>,
+        recording_start: &mut Option<Duration>,
+        summary: &mut ReplaySummary,
+    ) -> Result<ControlFlow<()>, ReplayFileError> {
+        self.metrics.record_read();
+        let timestamp = trace_record.meta.timestamp();
+        let recording_start = *recording_start.get_or_insert_with(|| {
+            // Anchor the start of the recording to now. We'll use this to delay replays and
+            // make them run on the same schedule as the recording.
+            self.clock.anchor(timestamp + self.range_start_offset());
+            timestamp
+        });
+
+        let offset = timestamp.saturating_sub(recording_start);
+        let before_end = match self.range_end {
+            Bound::Included(end) => offset <= end,
+            Bound::Excluded(end) => offset < end,
+            Bound::Unbounded => true,
+        };
+        if !before_end {
+            self.metrics.record_filtered();
+            return Ok(ControlFlow::Break(()));
+        }
+        // A span may be closed on a thread which isn't replayed, so spans are tracked as closed
+        // before any filtering.
+        if let TraceRef::Close(rec_span_id) = &trace_record.trace {
+            self.open_spans.remove(rec_span_id);
+            self.remove_span_id_callsite(self.span_key(*rec_span_id));
+        }
+        let after_start = match self.range_start {
+            Bound::Included(start) => offset >= start,
+            Bound::Excluded(start) => offset > start,
+            Bound::Unbounded => true,
+        };
+        if !after_start && matches!(trace_record.trace, TraceRef::Event(_)) {
+            self.metrics.record_filtered();
+            return Ok(ControlFlow::Continue(()));
+        }
+        if !self.is_selected_thread(&trace_record.meta) {
+            self.metrics.record_filtered();
+            return Ok(ControlFlow::Continue(()));
+        }
+        if !self.is_skeleton_trace(&trace_record.trace) {
+            self.metrics.record_filtered();
+            return Ok(ControlFlow::Continue(()));
+        }
+        if let Some(thread_mapping) = &mut self.thread_mapping {
+            thread_mapping.map(&mut trace_record.meta);
+        }
+        if let Some(subtree) = &mut self.subtree {
+            if !subtree.filter(&mut trace_record) {
+                self.metrics.record_filtered();
+                return Ok(ControlFlow::Continue(()));
+            }
+        }
+
+        if let TraceRef::NewSpan(rec_new_span) = &trace_record.trace {
+            if self.open_spans.contains(&rec_new_span.id) {
+                if self.span_id_collision_policy == SpanIdCollisionPolicy::Error {
+                    return Err(ReplayFileError::SpanIdCollision {
+                        span_id: rec_new_span.id.into(),
+                    });
+                }
+                summary.span_id_collisions += 1;
+            }
+        }
+
+        if let Some(chaos) = &mut self.chaos {
+            if chaos.drops(&trace_record.trace) {
+                self.metrics.record_filtered();
+                summary.dropped_records += 1;
+                return Ok(ControlFlow::Continue(()));
+            }
+        }
+
+        summary.count_record(&trace_record.meta);
+        summary.count_callsite(&trace_record.trace);
+        if !self.breakpoints.is_empty() {
+            let entered_span_name = match &trace_record.trace {
+                TraceRef::Enter(rec_span_id) => self
+                    .get_metadata_by_span_key(self.span_key(*rec_span_id))
+                    .map(Metadata::name),
+                _ => None,
+            };
+            self.breakpoints
+                .hit(&self.clock, &trace_record.trace, entered_span_name, || {
+                    TraceRecord::from(trace_record.clone())
+                });
+        }
+        self.dispatch_trace(trace_record);
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Sets the speed so that the recording in `data` is replayed in the target duration, if
+    /// there is one.
+    fn fit_to_target_duration(&self, data: &[u8]) {
+        let Some(target_duration) = self.target_duration else {
+            return;
+        };
+        let Some((start, end)) = reader::recorded_bounds(data) else {
+            return;
+        };
+        let recorded = end - start;
+        let range_end = match self.range_end {
+            Bound::Included(range_end) | Bound::Excluded(range_end) => range_end.min(recorded),
+            Bound::Unbounded => recorded,
+        };
+        let replayed = range_end.saturating_sub(self.range_start_offset());
+        if !replayed.is_zero() {
+            self.clock
+                .set_speed(replayed.as_secs_f64() / target_duration.as_secs_f64());
+        }
+    }
+
+    /// Returns the dispatcher which dispatches to all the dispatch targets.
+    fn targets_dispatch(&self) -> Option<tracing::Dispatch> {
+        match self.dispatch_targets.as_slice() {
+            [] => None,
+            [target] => Some(target.clone()),
+            targets => Some(tracing::Dispatch::new(FanOut::new(targets.to_vec()))),
+        }
+    }
+
+    /// Fills in the details of the replay threads for each recorded thread in `summary`, and the
+    /// dispatcher threads which were restarted since the last summary.
+    fn complete_summary(&mut self, summary: &mut ReplaySummary) {
+        if let Some(mut meta_events) = self.meta_events.take() {
+            for meta_record in meta_events.finish_recording() {
+                self.dispatch_trace(meta_record);
+            }
+            self.meta_events = Some(meta_events);
+        }
+        summary
+            .dispatcher_restarts
+            .append(&mut self.dispatcher_restarts);
+        for (thread_id, thread_summary) in &mut summary.threads {
+            if let Some(handle) = self.threads.get(thread_id) {
+                thread_summary.replay_thread_id = Some(handle.replay_thread_id);
+                thread_summary.dispatch_stats = Arc::clone(&handle.dispatch_stats);
+            }
+        }
+    }
+
+    /// Returns whether `trace` is replayed, which is only the lifecycles of spans when replaying
+    /// the span skeleton.
+    fn is_skeleton_trace(&self, trace: &TraceRef<'_>) -> bool {
+        !self.span_skeleton
+            || match trace {
+                TraceRef::RegisterCallsite(rec_metadata) => {
+                    rec_metadata.kind == recording::Kind::Span
+                }
+                TraceRef::NewSpan(_)
+                | TraceRef::Enter(_)
+                | TraceRef::Exit(_)
+                | TraceRef::Close(_)
+                | TraceRef::FollowsFrom(_) => true,
+                TraceRef::Event(_) | TraceRef::Record(_) => false,
+            }
+    }
+
+    fn is_selected_thread(&self, meta: &RecordMetaRef<'_>) -> bool {
+        self.thread_selectors.is_empty()
+            || self
+                .thread_selectors
+                .iter()
+                .any(|selector| selector.matches(meta))
+    }
+
+    fn get_or_create_metadata(
+        &self,
+        rec_metadata: recording::MetadataRef<'_>,
+    ) -> &'static Metadata<'static> {
+        let mut guard = self
+            .store
+            .lock()
+            .expect("replay internal state (store) has become corrupted.");
+
+        let metadata: &'static Metadata = (*guard)
+            .entry(rec_metadata.id)
+            .or_insert_with(|| self.metadata_cache.get_or_create(rec_metadata));
+
+        metadata
+    }
+
+    /// Returns the key for a newly created span with the recorded `rec_span_id`.
+    ///
+    /// If the recorded span::Id has been seen before, it has been reused and the new span starts
+    /// a new generation. If the span::Id is still in use by an open span and the
+    /// [`SpanIdCollisionPolicy::Overwrite`] policy is in use, the open span's key is returned
+    /// instead.
+    fn new_span_key(&mut self, rec_span_id: recording::SpanId) -> SpanKey {
+        let is_collision = !self.open_spans.insert(rec_span_id);
+        if is_collision && self.span_id_collision_policy == SpanIdCollisionPolicy::Overwrite {
+            return self.span_key(rec_span_id);
+        }
+
+        let generation = self
+            .span_generations
+            .entry(rec_span_id)
+            .and_modify(|generation| *generation += 1)
+            .or_insert(0);
+
+        SpanKey {
+            id: rec_span_id,
+            generation: *generation,
+        }
+    }
+
+    /// Returns the key for the current span with the recorded `rec_span_id`.
+    fn span_key(&self, rec_span_id: recording::SpanId) -> SpanKey {
+        SpanKey {
+            id: rec_span_id,
+            generation: self
+                .span_generations
+                .get(&rec_span_id)
+                .copied()
+                .unwrap_or_default(),
+        }
+    }
+
+    fn dispatchable_parent(&self, rec_parent: recording::Parent) -> DispatchableParent {
+        match rec_parent {
+            recording::Parent::Root => DispatchableParent::Root,
+            recording::Parent::Current => DispatchableParent::Current,
+            recording::Parent::Explicit(rec_span_id) => {
+                DispatchableParent::Explicit(self.span_key(rec_span_id))
+            }
+        }
     }
-}
 
-impl Replay {
-    fn get_or_create_metadata(
-        &self,
-        rec_metadata: recording::Metadata,
-    ) -> &'static Metadata<'static> {
+    fn set_span_id_callsite(&self, span_key: SpanKey, callsite_id: u64) {
         let mut guard = self
-            .store
+            .callsites
             .lock()
-            .expect("replay internal state (store) has become corrupted.");
-
-        let metadata: &'static Metadata = (*guard)
-            .entry(rec_metadata.id)
-            .or_insert_with(|| Box::leak(Box::new(rec_metadata.into())));
+            .expect("replay internal state (callsites) has become corrupted.");
 
-        metadata
+        (*guard).insert(span_key, callsite_id);
     }
 
-    fn set_span_id_callsite(&self, rec_span_id: recording::SpanId, callsite_id: u64) {
+    fn remove_span_id_callsite(&self, span_key: SpanKey) {
         let mut guard = self
             .callsites
             .lock()
             .expect("replay internal state (callsites) has become corrupted.");
 
-        (*guard).insert(rec_span_id, callsite_id);
+        (*guard).remove(&span_key);
     }
 
-    fn get_metadata_by_span_id(
-        &self,
-        rec_span_id: recording::SpanId,
-    ) -> Option<&'static Metadata<'static>> {
+    fn get_metadata_by_span_key(&self, span_key: SpanKey) -> Option<&'static Metadata<'static>> {
         let callsite_id = {
             let guard = self
                 .callsites
                 .lock()
                 .expect("replay internal state (callsites) has become corrupted.");
 
-            (*guard).get(&rec_span_id).copied()
+            (*guard).get(&span_key).copied()
         }?;
 
         let guard = self
@@ -346,134 +3260,386 @@ impl Replay {
         (*guard).get(&callsite_id).copied()
     }
 
-    fn dispatch_trace(&mut self, record: TraceRecord) {
-        let trace_tx = {
-            let handle = self
-                .threads
-                .entry(record.meta.thread_id)
-                .or_insert_with_key(|thread_id| {
-                    let (tx, rx) = mpsc::channel();
-                    let thread_dispatcher = ThreadDispatcher {
-                        rec_id: thread_id.clone(),
-                        trace_rx: rx,
-                        span_ids: Arc::clone(&self.span_ids),
-                    };
-                    let join_handle = thread::Builder::new()
-                        .name(record.meta.thread_name.unwrap_or_default())
-                        .spawn(move || {
-                            thread_dispatcher.run();
-                        })
-                        .unwrap_or_else(|err| {
-                            panic!(
-                                "failed to create replay thread '{thread_id}'. \
-                                Cannot faithfully reproduce traces. Error: {err}"
-                            );
-                        });
-                    ThreadDispatcherHandle {
-                        trace_tx: tx,
-                        join_handle,
-                    }
-                });
-            handle.trace_tx.clone()
+    /// Returns the metadata of the callsite used by `trace` if it hasn't been registered yet.
+    ///
+    /// Callsite registrations can be filtered out of the replay, in which case they need to be
+    /// synthesized before the callsite is first used.
+    fn unregistered_callsite<'a>(
+        &mut self,
+        trace: &TraceRef<'a>,
+    ) -> Option<recording::MetadataRef<'a>> {
+        let rec_metadata = match trace {
+            TraceRef::RegisterCallsite(rec_metadata) => {
+                self.registered_callsites.insert(rec_metadata.id);
+                return None;
+            }
+            TraceRef::NewSpan(recording::NewSpanRef { metadata, .. })
+            | TraceRef::Event(recording::EventRef { metadata, .. }) => metadata,
+            _ => return None,
         };
 
-        let record_since_epoch =
-            Duration::new(record.meta.timestamp_s, record.meta.timestamp_subsec_us);
-        let replay_since_epoch = record_since_epoch
-            .checked_add(self.replay_time_delta)
-            .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).expect("SystemTime::now() is before the UNIX epoch, is there something wrong with the clock?"));
+        self.registered_callsites
+            .insert(rec_metadata.id)
+            .then(|| rec_metadata.clone())
+    }
+
+    /// Returns the offset of the start of the time range from the start of the recording.
+    fn range_start_offset(&self) -> Duration {
+        match self.range_start {
+            Bound::Included(start) | Bound::Excluded(start) => start,
+            Bound::Unbounded => Duration::ZERO,
+        }
+    }
+
+    fn dispatch_trace(&mut self, record: TraceRecordRef<'_>) {
+        // Taken while dispatching the meta records, so that they aren't tracked themselves.
+        if let Some(mut meta_events) = self.meta_events.take() {
+            for meta_record in meta_events.track(&record.meta) {
+                self.dispatch_trace(meta_record);
+            }
+            self.meta_events = Some(meta_events);
+        }
+        if let Some(thread_spans) = &mut self.thread_spans {
+            for thread_span_record in thread_spans.track(&record.meta) {
+                self.dispatch_trace(thread_span_record);
+            }
+        }
+        if let Some(rec_metadata) = self.unregistered_callsite(&record.trace) {
+            self.dispatch_trace(TraceRecordRef {
+                meta: record.meta.clone(),
+                trace: TraceRef::RegisterCallsite(rec_metadata),
+                final_fields: None,
+                sequence: None,
+            });
+        }
+
+        if let Some(rate_limiter) = &mut self.rate_limiter {
+            rate_limiter.wait();
+        }
 
+        let mut replay_since_epoch = record.meta.timestamp();
+        if let Some(chaos) = &mut self.chaos {
+            replay_since_epoch += chaos.delay();
+        }
+        let hook_record = self
+            .on_dispatched
+            .is_some()
+            .then(|| Box::new(TraceRecord::from(record.clone())));
         let container = match record.trace {
-            Trace::RegisterCallsite(rec_metadata) => {
+            TraceRef::RegisterCallsite(rec_metadata) => {
                 let metadata = self.get_or_create_metadata(rec_metadata);
                 DispatchableContainer::Trace {
                     timestamp: replay_since_epoch,
+                    record: hook_record,
                     trace: DispatchableTrace::RegisterCallsite(DispatchableMetadata(metadata)),
                 }
             }
-            Trace::Event(rec_event) => {
+            TraceRef::Event(rec_event) => {
                 let dis_event = self.event(rec_event);
                 DispatchableContainer::Trace {
                     timestamp: replay_since_epoch,
+                    record: hook_record,
                     trace: DispatchableTrace::Event(dis_event),
                 }
             }
-            Trace::NewSpan(rec_new_span) => {
+            TraceRef::NewSpan(rec_new_span) => {
                 let dis_new_span = self.new_span(rec_new_span);
                 DispatchableContainer::Trace {
                     timestamp: replay_since_epoch,
+                    record: hook_record,
                     trace: DispatchableTrace::NewSpan(dis_new_span),
                 }
             }
-            Trace::Enter(rec_span_id) => DispatchableContainer::Trace {
+            TraceRef::Enter(rec_span_id) => DispatchableContainer::Trace {
                 timestamp: replay_since_epoch,
-                trace: DispatchableTrace::Enter(DispatchableSpanId(rec_span_id)),
+                record: hook_record,
+                trace: DispatchableTrace::Enter(DispatchableSpanId(self.span_key(rec_span_id))),
             },
-            Trace::Exit(rec_span_id) => DispatchableContainer::Trace {
+            TraceRef::Exit(rec_span_id) => DispatchableContainer::Trace {
                 timestamp: replay_since_epoch,
-                trace: DispatchableTrace::Exit(DispatchableSpanId(rec_span_id)),
+                record: hook_record,
+                trace: DispatchableTrace::Exit(DispatchableSpanId(self.span_key(rec_span_id))),
             },
-            Trace::Close(rec_span_id) => DispatchableContainer::Trace {
+            TraceRef::Close(rec_span_id) => DispatchableContainer::Trace {
                 timestamp: replay_since_epoch,
-                trace: DispatchableTrace::Close(DispatchableSpanId(rec_span_id)),
+                record: hook_record,
+                trace: DispatchableTrace::Close(DispatchableSpanId(self.span_key(rec_span_id))),
             },
-            Trace::Record(rec_record_values) => {
-                let Some(metadata) = self.get_metadata_by_span_id(rec_record_values.id) else {
+            TraceRef::Record(rec_record_values) => {
+                let span_key = self.span_key(rec_record_values.id);
+                let Some(metadata) = self.get_metadata_by_span_key(span_key) else {
+                    self.metrics.record_unmapped();
                     return;
                 };
                 DispatchableContainer::Trace {
                     timestamp: replay_since_epoch,
+                    record: hook_record,
                     trace: DispatchableTrace::Record(DispatchableRecordValues {
-                        id: rec_record_values.id,
+                        span_key,
                         metadata,
-                        fields: rec_record_values.fields,
+                        fields: owned_fields(rec_record_values.fields),
                     }),
                 }
             }
-            Trace::FollowsFrom(rec_follows_from) => DispatchableContainer::Trace {
+            TraceRef::FollowsFrom(rec_follows_from) => DispatchableContainer::Trace {
                 timestamp: replay_since_epoch,
+                record: hook_record,
                 trace: DispatchableTrace::FollowsFrom(DispatchableFollowsFrom {
-                    cause_id: rec_follows_from.cause_id,
-                    effect_id: rec_follows_from.effect_id,
+                    cause: self.span_key(rec_follows_from.cause_id),
+                    effect: self.span_key(rec_follows_from.effect_id),
+                    record: None,
                 }),
             },
         };
-        if let Err(err) = trace_tx.send(container) {
-            println!("failed to send container: {err}");
+        if let (Some(verifier), DispatchableContainer::Trace { trace, .. }) =
+            (&mut self.verifier, &container)
+        {
+            verifier.expect(record.meta.thread_id.as_str(), trace);
+        }
+
+        let thread_id = record.meta.thread_id.as_str();
+        if !self.threads.contains_key(thread_id) {
+            let handle = self.spawn_thread_dispatcher(
+                thread_id,
+                record.meta.thread_name.as_ref().map(CowStr::as_str),
+                None,
+            );
+            self.threads.insert(thread_id.to_owned(), handle);
+        }
+
+        if let Err(undelivered) = self.threads[thread_id].send(container) {
+            self.respawn_thread_dispatcher(thread_id);
+            if let Some(container) = undelivered {
+                if self.threads[thread_id].send(container).is_err() {
+                    println!("failed to send container to respawned dispatcher for {thread_id}");
+                }
+            }
+        }
+    }
+
+    /// Replaces the dispatcher thread for the recorded thread `thread_id`, which has panicked,
+    /// with a new one.
+    ///
+    /// The new thread shares the replay state with all the other dispatcher threads, so it carries
+    /// on from where the panicked thread stopped. The restart is recorded, to be reported in the
+    /// next [`ReplaySummary`].
+    fn respawn_thread_dispatcher(&mut self, thread_id: &str) {
+        let Some(previous) = self.threads.remove(thread_id) else {
+            return;
+        };
+        let handle = self.spawn_thread_dispatcher(
+            thread_id,
+            previous.thread_name.as_deref(),
+            Some(&previous),
+        );
+        self.threads.insert(thread_id.to_owned(), handle);
+
+        let message = match previous.worker {
+            DispatchWorker::Thread { join_handle, .. } => match join_handle.join() {
+                Ok(()) => "the dispatcher thread stopped unexpectedly".to_owned(),
+                Err(payload) => panic_message(payload.as_ref()),
+            },
+            // Traces dispatched inline panic on the coordinator thread instead.
+            DispatchWorker::Inline(_) => return,
+        };
+        self.dispatcher_restarts.push(DispatcherRestart {
+            thread_id: thread_id.to_owned(),
+            message,
+        });
+    }
+
+    /// Exits and closes the span which wraps the replay, if there is one.
+    fn finish_meta_events(&mut self) {
+        let Some(mut meta_events) = self.meta_events.take() else {
+            return;
+        };
+        for record in meta_events.finish() {
+            if let TraceRef::Close(rec_span_id) = &record.trace {
+                self.open_spans.remove(rec_span_id);
+                self.remove_span_id_callsite(self.span_key(*rec_span_id));
+            }
+            self.dispatch_trace(record);
+        }
+        self.meta_events = Some(meta_events);
+    }
+
+    /// Exits and closes the spans representing the recorded threads, if there are any.
+    fn finish_thread_spans(&mut self) {
+        // Taken while finishing, so that the records which finish the thread spans don't open
+        // them again.
+        let Some(mut thread_spans) = self.thread_spans.take() else {
+            return;
+        };
+        for record in thread_spans.finish() {
+            if let TraceRef::Close(rec_span_id) = &record.trace {
+                self.open_spans.remove(rec_span_id);
+                self.remove_span_id_callsite(self.span_key(*rec_span_id));
+            }
+            self.dispatch_trace(record);
+        }
+        self.thread_spans = Some(thread_spans);
+    }
+
+    /// Blocks until every dispatcher thread has dispatched all the traces sent to it so far.
+    fn flush_thread_dispatchers(&self) {
+        let flushing: Vec<_> = self
+            .threads
+            .values()
+            .filter_map(|handle| match &handle.worker {
+                DispatchWorker::Thread {
+                    trace_tx,
+                    dispatched_rx,
+                    ..
+                } => trace_tx
+                    .send(DispatchableContainer::Flush)
+                    .is_ok()
+                    .then_some(dispatched_rx),
+                DispatchWorker::Inline(_) => None,
+            })
+            .collect();
+        for dispatched_rx in flushing {
+            // If the dispatcher thread has gone away, there is nothing to wait for.
+            let _ = dispatched_rx.recv();
+        }
+    }
+
+    /// Spawns the thread which dispatches the traces recorded on the thread `thread_id`.
+    ///
+    /// If a thread can't be spawned, for example because the platform doesn't support threads,
+    /// the traces are dispatched inline on the current thread instead. A thread which replaces
+    /// the `previous` dispatcher thread keeps its mode and statistics.
+    fn spawn_thread_dispatcher(
+        &mut self,
+        thread_id: &str,
+        thread_name: Option<&str>,
+        previous: Option<&ThreadDispatcherHandle>,
+    ) -> ThreadDispatcherHandle {
+        let (tx, rx) = mpsc::channel();
+        let (dispatched_tx, dispatched_rx) = mpsc::channel();
+        let (dispatch_stats, metrics, mode) = match previous {
+            Some(previous) => (
+                Arc::clone(&previous.dispatch_stats),
+                previous.metrics.clone(),
+                previous.mode,
+            ),
+            None => (
+                Arc::new(DispatchStats::default()),
+                ThreadMetrics::new(thread_id),
+                self.mode,
+            ),
+        };
+        let progress = match previous {
+            Some(previous) => previous.progress.clone().inspect(|progress| {
+                // The trace which the previous thread was dispatching was lost when it panicked.
+                progress.finish();
+            }),
+            None => self
+                .watchdog
+                .as_mut()
+                .map(|watchdog| watchdog.watch(thread_id)),
+        };
+        let thread_dispatcher = ThreadDispatcher {
+            rec_id: thread_id.to_owned(),
+            trace_rx: rx,
+            dispatched_tx,
+            span_ids: Arc::clone(&self.span_ids),
+            pending_follows_from: Arc::clone(&self.pending_follows_from),
+            pending_records: Arc::clone(&self.pending_records),
+            pending_record_timeout: self.pending_record_timeout,
+            dispatch_stats: Arc::clone(&dispatch_stats),
+            clock: Arc::clone(&self.clock),
+            mode,
+            dispatch: self.dispatch.clone(),
+            filter: self.filter.clone(),
+            on_dispatched: self.on_dispatched.clone(),
+            metrics: metrics.clone(),
+            progress: progress.clone(),
+        };
+        // The thread dispatcher is handed back if the thread can't be spawned.
+        let thread_dispatcher = Arc::new(Mutex::new(Some(thread_dispatcher)));
+        let spawned = thread::Builder::new()
+            .name(self.thread_naming.thread_name(thread_id, thread_name))
+            .spawn({
+                let thread_dispatcher = Arc::clone(&thread_dispatcher);
+                move || {
+                    let thread_dispatcher = thread_dispatcher
+                        .lock()
+                        .expect("replay thread dispatcher has become corrupted.")
+                        .take();
+                    if let Some(thread_dispatcher) = thread_dispatcher {
+                        thread_dispatcher.run();
+                    }
+                }
+            });
+        let (worker, replay_thread_id) = match spawned {
+            Ok(join_handle) => {
+                let replay_thread_id = join_handle.thread().id();
+                let worker = DispatchWorker::Thread {
+                    join_handle,
+                    trace_tx: tx,
+                    dispatched_rx,
+                };
+                (worker, replay_thread_id)
+            }
+            Err(_) => {
+                let thread_dispatcher = thread_dispatcher
+                    .lock()
+                    .expect("replay thread dispatcher has become corrupted.")
+                    .take()
+                    .expect("thread dispatcher is only taken by the spawned thread");
+                let worker = DispatchWorker::Inline(Box::new(thread_dispatcher));
+                (worker, thread::current().id())
+            }
         };
+
+        ThreadDispatcherHandle {
+            worker,
+            replay_thread_id,
+            thread_name: thread_name.map(str::to_owned),
+            dispatch_stats,
+            metrics,
+            mode,
+            progress,
+        }
     }
 
-    fn new_span(&self, rec_new_span: recording::NewSpan) -> DispatchableNewSpan {
+    fn new_span(&mut self, rec_new_span: recording::NewSpanRef<'_>) -> DispatchableNewSpan {
+        let span_key = self.new_span_key(rec_new_span.id);
         let callsite_id = rec_new_span.metadata.id;
         let metadata = self.get_or_create_metadata(rec_new_span.metadata);
-        self.set_span_id_callsite(rec_new_span.id, callsite_id);
+        self.set_span_id_callsite(span_key, callsite_id);
 
         {
             let mut guard = self
                 .span_ids
                 .lock()
                 .expect("replay internal state has become corrupted.");
-            debug_assert!(
-                !(*guard).contains_key(&rec_new_span.id),
-                "new span recorded span::Id that has already been seen!"
-            );
-            (*guard).insert(rec_new_span.id, MappedSpanId::Pending);
+            (*guard).insert(span_key, MappedSpanId::Pending);
         }
 
+        let preserved_id = if self.sequential_span_ids {
+            self.span_sequence += 1;
+            self.span_sequence
+        } else {
+            rec_new_span.id.into()
+        };
+
         DispatchableNewSpan {
-            id: rec_new_span.id,
+            span_key,
+            preserved_id,
             metadata,
-            fields: rec_new_span.fields,
-            parent: rec_new_span.parent,
+            fields: owned_fields(rec_new_span.fields),
+            parent: self.dispatchable_parent(rec_new_span.parent),
+            trace_context: rec_new_span.trace_context.map(Box::new),
         }
     }
 
-    fn event(&self, rec_event: recording::Event) -> DispatchableEvent {
+    fn event(&self, rec_event: recording::EventRef<'_>) -> DispatchableEvent {
         let metadata = self.get_or_create_metadata(rec_event.metadata);
         DispatchableEvent {
             metadata,
-            fields: rec_event.fields,
-            parent: rec_event.parent,
+            fields: owned_fields(rec_event.fields),
+            parent: self.dispatchable_parent(rec_event.parent),
         }
     }
 }
@@ -483,7 +3649,11 @@ enum DispatchableContainer {
     Trace {
         timestamp: Duration,
         trace: DispatchableTrace,
+        /// The original record, which is only kept if there is an [`OnDispatched`] hook.
+        record: Option<Box<TraceRecord>>,
     },
+    /// Asks the dispatcher thread to acknowledge once all the preceding traces are dispatched.
+    Flush,
     End,
 }
 
@@ -499,6 +3669,22 @@ enum DispatchableTrace {
     FollowsFrom(DispatchableFollowsFrom),
 }
 
+impl DispatchableTrace {
+    /// The name of the kind of trace, as it is recorded.
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::RegisterCallsite(_) => "RegisterCallsite",
+            Self::Event(_) => "Event",
+            Self::NewSpan(_) => "NewSpan",
+            Self::Enter(_) => "Enter",
+            Self::Exit(_) => "Exit",
+            Self::Close(_) => "Close",
+            Self::Record(_) => "Record",
+            Self::FollowsFrom(_) => "FollowsFrom",
+        }
+    }
+}
+
 #[derive(Debug)]
 struct DispatchableMetadata(&'static Metadata<'static>);
 
@@ -512,35 +3698,56 @@ impl DispatchableMetadata {
 struct DispatchableEvent {
     metadata: &'static Metadata<'static>,
     fields: Vec<Field>,
-    parent: recording::Parent,
+    parent: DispatchableParent,
 }
 
 #[derive(Debug)]
 struct DispatchableNewSpan {
-    id: recording::SpanId,
+    span_key: SpanKey,
+    /// The span::Id which [`PreserveSpanIds`] gives the span, its recorded span::Id or its
+    /// number with sequential span::Ids.
+    preserved_id: u64,
     metadata: &'static Metadata<'static>,
     fields: Vec<Field>,
-    parent: recording::Parent,
+    parent: DispatchableParent,
+    /// Boxed, as it is rarely present and would otherwise make every trace larger.
+    trace_context: Option<Box<recording::TraceContext>>,
+}
+
+#[derive(Debug)]
+enum DispatchableParent {
+    Root,
+    Current,
+    Explicit(SpanKey),
 }
 
 #[derive(Debug)]
-struct DispatchableSpanId(recording::SpanId);
+struct DispatchableSpanId(SpanKey);
 
 impl DispatchableSpanId {
-    fn into_inner(self) -> recording::SpanId {
+    fn into_inner(self) -> SpanKey {
         self.0
     }
 }
 
 #[derive(Debug)]
 struct DispatchableFollowsFrom {
-    cause_id: recording::SpanId,
-    effect_id: recording::SpanId,
+    cause: SpanKey,
+    effect: SpanKey,
+    /// The original record while the relationship is held back, which is passed to the
+    /// [`OnDispatched`] hook once the relationship has been dispatched.
+    record: Option<Box<TraceRecord>>,
+}
+
+impl DispatchableFollowsFrom {
+    fn refers_to(&self, span_key: SpanKey) -> bool {
+        self.cause == span_key || self.effect == span_key
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct DispatchableRecordValues {
-    id: recording::SpanId,
+    span_key: SpanKey,
     metadata: &'static Metadata<'static>,
     fields: Vec<Field>,
 }
@@ -548,16 +3755,78 @@ pub(crate) struct DispatchableRecordValues {
 struct ThreadDispatcher {
     rec_id: String,
     trace_rx: mpsc::Receiver<DispatchableContainer>,
-    span_ids: Arc<Mutex<HashMap<recording::SpanId, MappedSpanId>>>,
+    dispatched_tx: mpsc::Sender<()>,
+    span_ids: Arc<Mutex<HashMap<SpanKey, MappedSpanId>>>,
+    pending_follows_from: Arc<Mutex<Vec<DispatchableFollowsFrom>>>,
+    pending_records: Arc<Mutex<PendingRecords>>,
+    pending_record_timeout: Duration,
+    dispatch_stats: Arc<DispatchStats>,
+    clock: Arc<ReplayClock>,
+    mode: ReplayMode,
+    dispatch: Option<tracing::Dispatch>,
+    filter: Option<Targets>,
+    on_dispatched: Option<OnDispatched>,
+    metrics: ThreadMetrics,
+    /// The progress of this dispatcher thread, if a watchdog is watching it.
+    progress: Option<Arc<DispatcherProgress>>,
 }
 
 impl ThreadDispatcher {
     fn run(self) {
+        match &self.dispatch {
+            Some(dispatch) => tracing::dispatcher::with_default(dispatch, || self.run_loop()),
+            None => self.run_loop(),
+        }
+    }
+
+    /// Returns whether the replay's filter, if there is one, enables the span or event.
+    fn filter_enables(&self, metadata: &Metadata<'_>) -> bool {
+        match &self.filter {
+            Some(filter) => filter.would_enable(metadata.target(), metadata.level()),
+            None => true,
+        }
+    }
+
+    /// Dispatches a trace on the current thread, instead of on a replay thread.
+    ///
+    /// In [`ReplayMode::Realtime`], this blocks until the trace is due.
+    fn dispatch_inline(&self, container: DispatchableContainer) {
+        let DispatchableContainer::Trace {
+            timestamp,
+            trace,
+            record,
+        } = container
+        else {
+            return;
+        };
+        match &self.dispatch {
+            Some(dispatch) => tracing::dispatcher::with_default(dispatch, || {
+                self.dispatch(timestamp, trace, record);
+            }),
+            None => self.dispatch(timestamp, trace, record),
+        }
+    }
+
+    fn run_loop(&self) {
         let rec_id = &self.rec_id;
         loop {
             match self.trace_rx.recv() {
-                Ok(DispatchableContainer::Trace { timestamp, trace }) => {
-                    self.dispatch(timestamp, trace);
+                Ok(DispatchableContainer::Trace {
+                    timestamp,
+                    trace,
+                    record,
+                }) => {
+                    self.metrics.dequeued();
+                    self.dispatch(timestamp, trace, record);
+                    if self.mode == ReplayMode::Deterministic {
+                        // The coordinator may have gone away, in which case we don't need to
+                        // notify it.
+                        let _ = self.dispatched_tx.send(());
+                    }
+                }
+                Ok(DispatchableContainer::Flush) => {
+                    // All the traces sent before the flush have been dispatched.
+                    let _ = self.dispatched_tx.send(());
                 }
                 Ok(DispatchableContainer::End) => break,
                 Err(err) => {
@@ -568,12 +3837,48 @@ impl ThreadDispatcher {
         }
     }
 
-    fn dispatch(&self, timestamp: Duration, trace: DispatchableTrace) {
-        let delay = timestamp.saturating_sub(SystemTime::now().duration_since(UNIX_EPOCH).expect(
-            "SystemTime::now() is before the UNIX epoch, is there something wrong with the clock?",
-        ));
-        if !delay.is_zero() {
-            thread::sleep(delay);
+    /// Dispatches the trace, while the watchdog watches its progress if there is one.
+    fn dispatch(
+        &self,
+        timestamp: Duration,
+        trace: DispatchableTrace,
+        record: Option<Box<TraceRecord>>,
+    ) {
+        let Some(progress) = &self.progress else {
+            self.dispatch_trace(timestamp, trace, record);
+            return;
+        };
+        let cause = match self.mode {
+            ReplayMode::Realtime => StallCause::WaitingForTimestamp,
+            ReplayMode::Deterministic => StallCause::Subscriber,
+        };
+        progress.start(trace.kind(), timestamp, cause);
+        self.dispatch_trace(timestamp, trace, record);
+        progress.finish();
+    }
+
+    /// Records what the trace being dispatched is waiting for, if a watchdog is watching.
+    fn wait_for(&self, cause: StallCause) {
+        if let Some(progress) = &self.progress {
+            progress.wait_for(cause);
+        }
+    }
+
+    /// Dispatches the trace and then passes the record to the [`OnDispatched`] hook.
+    ///
+    /// Traces which refer to spans that weren't replayed are skipped, and follows from
+    /// relationships may be held back until their spans have been created.
+    fn dispatch_trace(
+        &self,
+        timestamp: Duration,
+        trace: DispatchableTrace,
+        record: Option<Box<TraceRecord>>,
+    ) {
+        if self.mode == ReplayMode::Realtime {
+            let lag = self.clock.wait_until(timestamp);
+            self.dispatch_stats.record_lag(trace.kind(), lag);
+            self.metrics.record_lag(lag);
+            self.wait_for(StallCause::Subscriber);
         }
 
         match trace {
@@ -584,152 +3889,530 @@ impl ThreadDispatcher {
                 });
             }
             DispatchableTrace::Event(dis_event) => {
+                let parent = self.replay_parent(&dis_event.parent);
                 tracing::dispatcher::get_default(move |dispatch| {
-                    let enabled = dispatch.enabled(dis_event.metadata);
+                    let enabled = self.filter_enables(dis_event.metadata)
+                        && dispatch.enabled(dis_event.metadata);
                     if enabled {
-                        let values = create_field_values(dis_event.metadata, &dis_event.fields);
-                        let proxy =
-                            EventProxy::new(dispatch, dis_event.metadata, &dis_event.parent);
+                        let replay_values = replay_values(&dis_event.fields);
+                        let values = create_field_values(
+                            dis_event.metadata,
+                            &dis_event.fields,
+                            &replay_values,
+                        );
+                        let proxy = EventProxy::new(dispatch, dis_event.metadata, &parent);
                         proxy.dispatch_values(values);
                     }
                 });
             }
             DispatchableTrace::NewSpan(dis_new_span) => {
+                let parent = self.replay_parent(&dis_new_span.parent);
                 tracing::dispatcher::get_default(move |dispatch| {
-                    if !dispatch.enabled(dis_new_span.metadata) {
+                    let enabled = self.filter_enables(dis_new_span.metadata)
+                        && dispatch.enabled(dis_new_span.metadata);
+                    if !enabled {
+                        self.set_replay_span_id(dis_new_span.span_key, MappedSpanId::Disabled);
+                        self.dispatch_resolved_records(dispatch, dis_new_span.span_key);
                         return;
                     }
 
-                    let values = create_field_values(dis_new_span.metadata, &dis_new_span.fields);
-                    let proxy =
-                        NewSpanProxy::new(dispatch, dis_new_span.metadata, &dis_new_span.parent);
-                    let span_id = proxy.dispatch_values(values);
+                    let replay_values = replay_values(&dis_new_span.fields);
+                    let values = create_field_values(
+                        dis_new_span.metadata,
+                        &dis_new_span.fields,
+                        &replay_values,
+                    );
+                    let proxy = NewSpanProxy::new(dispatch, dis_new_span.metadata, &parent);
+                    let span_id =
+                        preserve::with_recorded_span_id(dis_new_span.preserved_id, || {
+                            trace_context::with_recorded_trace_context(
+                                dis_new_span.trace_context.as_deref().copied(),
+                                || proxy.dispatch_values(values),
+                            )
+                        });
 
                     // Store a mapping from the recorded span::Id to the one that `tracing` has given us
                     // during this replay. We will need to look up this mapping to replay traces that
                     // reference this new span by Id (enter, exit, ...).
-                    {
-                        let mut guard = self
-                            .span_ids
-                            .lock()
-                            .expect("replay internal state has become corrupted.");
-
-                        // TODO(hds): This should check that the entry is exactly
-                        // `Some(MappedSpanId::Pending)` and nothing else.
-                        let current_value = (*guard).get(&dis_new_span.id);
-                        debug_assert!(
-                            matches!((*guard).get(&dis_new_span.id), Some(MappedSpanId::Pending)),
-                            "new span recorded span::Id should be Pending, but is {current_value:?}",
-                        );
-                        (*guard).insert(dis_new_span.id, MappedSpanId::Mapped(span_id));
-                    }
+                    self.set_replay_span_id(dis_new_span.span_key, MappedSpanId::Mapped(span_id));
+
+                    self.dispatch_resolved_records(dispatch, dis_new_span.span_key);
+                    self.dispatch_resolved_follows_from(dispatch);
                 });
             }
             DispatchableTrace::Enter(dis_span_id) => {
-                let span_id = self
-                    .get_replay_span_id(dis_span_id.into_inner())
-                    .expect("no replay span::Id found, is the recording complete?");
+                let Some(span_id) = self.get_replay_span_id(dis_span_id.into_inner()) else {
+                    self.metrics.record_skipped();
+                    return;
+                };
                 tracing::dispatcher::get_default(|dispatch| dispatch.enter(&span_id));
             }
             DispatchableTrace::Exit(dis_span_id) => {
-                let span_id = self
-                    .get_replay_span_id(dis_span_id.into_inner())
-                    .expect("no replay span::Id found, is the recording complete?");
-                tracing::dispatcher::get_default(|dispatch| dispatch.exit(&span_id));
+                let Some(span_id) = self.get_replay_span_id(dis_span_id.into_inner()) else {
+                    self.metrics.record_skipped();
+                    return;
+                };
+                // A registry closes spans through the default dispatcher when they are exited,
+                // which isn't available from within `get_default`, so the span would be leaked.
+                tracing::dispatcher::get_default(tracing::Dispatch::clone).exit(&span_id);
             }
             DispatchableTrace::Close(dis_span_id) => {
-                let span_id = self
-                    .get_replay_span_id(dis_span_id.into_inner())
-                    .expect("no replay span::Id found, is the recording complete?");
-                tracing::dispatcher::get_default(|dispatch| dispatch.try_close(span_id.clone()));
+                let span_key = dis_span_id.into_inner();
+                let span_id = self.get_replay_span_id(span_key);
+                // Nothing refers to the span once it has been closed, so the mapping is removed to
+                // stop it from being held for the rest of the replay.
+                self.remove_replay_span_id(span_key);
+                let Some(span_id) = span_id else {
+                    self.metrics.record_skipped();
+                    return;
+                };
+                // A registry closes the parent of a span through the default dispatcher when the
+                // span closes, see the exit above.
+                tracing::dispatcher::get_default(tracing::Dispatch::clone).try_close(span_id);
             }
             DispatchableTrace::Record(dis_record_values) => {
-                let Some(span_id) = self.get_replay_span_id(dis_record_values.id) else {
+                // The span may be created on another dispatcher thread which hasn't caught up
+                // yet. Rather than waiting for it, the values are held back until the span has
+                // been mapped. The pending lock is taken first so that a span which is mapped
+                // concurrently can't miss these values.
+                let mut pending = self
+                    .pending_records
+                    .lock()
+                    .expect("replay internal state (pending records) has become corrupted.");
+                match self.try_get_mapped_span_id(dis_record_values.span_key) {
+                    Some(MappedSpanId::Mapped(span_id)) => {
+                        drop(pending);
+                        tracing::dispatcher::get_default(|dispatch| {
+                            Self::dispatch_record_values(dispatch, &span_id, &dis_record_values);
+                        });
+                    }
+                    Some(MappedSpanId::Pending) => {
+                        pending.pending.push(PendingRecordValues {
+                            record_values: dis_record_values,
+                            record,
+                            deadline: Instant::now() + self.pending_record_timeout,
+                        });
+                        return;
+                    }
+                    Some(MappedSpanId::Disabled) | None => {
+                        self.metrics.record_skipped();
+                        return;
+                    }
+                }
+            }
+            DispatchableTrace::FollowsFrom(mut dis_follows_from) => {
+                // The cause and effect spans may be created on other dispatcher threads which
+                // haven't caught up yet. Rather than waiting for them, the relationship is held
+                // back until both spans have been mapped. The pending lock is taken first so that
+                // a span which is mapped concurrently can't miss this relationship.
+                let mut pending = self
+                    .pending_follows_from
+                    .lock()
+                    .expect("replay internal state (pending follows from) has become corrupted.");
+                if let Some((cause_span_id, effect_span_id)) =
+                    self.try_get_follows_from_span_ids(&dis_follows_from)
+                {
+                    drop(pending);
+                    tracing::dispatcher::get_default(move |dispatch| {
+                        dispatch.record_follows_from(&effect_span_id, &cause_span_id);
+                    });
+                } else {
+                    dis_follows_from.record = record;
+                    pending.push(dis_follows_from);
                     return;
-                };
+                }
+            }
+        }
 
-                tracing::dispatcher::get_default(move |dispatch| {
-                    let values =
-                        create_field_values(dis_record_values.metadata, &dis_record_values.fields);
-                    let proxy = RecordProxy::new(dispatch, dis_record_values.metadata, &span_id);
-                    proxy.dispatch_values(values);
-                });
+        self.metrics.record_dispatched();
+        self.call_on_dispatched(record.as_deref());
+    }
+
+    fn call_on_dispatched(&self, record: Option<&TraceRecord>) {
+        if let (Some(on_dispatched), Some(record)) = (&self.on_dispatched, record) {
+            (on_dispatched.0)(record);
+        }
+    }
+
+    fn dispatch_record_values(
+        dispatch: &tracing::Dispatch,
+        span_id: &span::Id,
+        dis_record_values: &DispatchableRecordValues,
+    ) {
+        let replay_values = replay_values(&dis_record_values.fields);
+        let values = create_field_values(
+            dis_record_values.metadata,
+            &dis_record_values.fields,
+            &replay_values,
+        );
+        let proxy = RecordProxy::new(dispatch, dis_record_values.metadata, span_id);
+        proxy.dispatch_values(values);
+    }
+
+    /// Dispatches the held back values for the span which has just been mapped.
+    ///
+    /// Values which have been waiting for longer than the pending record timeout are dropped.
+    fn dispatch_resolved_records(&self, dispatch: &tracing::Dispatch, span_key: SpanKey) {
+        let resolved = {
+            let mut pending = self
+                .pending_records
+                .lock()
+                .expect("replay internal state (pending records) has become corrupted.");
+            if pending.pending.is_empty() {
+                return;
             }
-            DispatchableTrace::FollowsFrom(dis_follows_from) => {
-                let Some(cause_span_id) = self.get_replay_span_id(dis_follows_from.cause_id) else {
-                    return;
-                };
-                let Some(effect_span_id) = self.get_replay_span_id(dis_follows_from.effect_id)
-                else {
-                    return;
-                };
-                tracing::dispatcher::get_default(move |dispatch| {
-                    dispatch.record_follows_from(&effect_span_id, &cause_span_id);
-                });
+
+            let now = Instant::now();
+            let (resolved, still_pending): (Vec<_>, Vec<_>) = std::mem::take(&mut pending.pending)
+                .into_iter()
+                .partition(|pending| pending.record_values.span_key == span_key);
+            pending.pending = still_pending;
+            let (resolved, expired): (Vec<_>, Vec<_>) = resolved
+                .into_iter()
+                .partition(|pending| pending.deadline >= now);
+            pending
+                .expired
+                .extend(expired.into_iter().map(|_| span_key));
+            resolved
+        };
+
+        let mapped_span_id = self.try_get_mapped_span_id(span_key);
+        for pending in resolved {
+            if let Some(MappedSpanId::Mapped(span_id)) = &mapped_span_id {
+                Self::dispatch_record_values(dispatch, span_id, &pending.record_values);
+                self.metrics.record_dispatched();
+                self.call_on_dispatched(pending.record.as_deref());
+            } else {
+                self.metrics.record_skipped();
             }
         }
     }
 
-    fn get_replay_span_id(&self, rec_span_id: recording::SpanId) -> Option<span::Id> {
-        loop {
+    /// Dispatches the held back follows from relationships whose spans have all been mapped.
+    fn dispatch_resolved_follows_from(&self, dispatch: &tracing::Dispatch) {
+        let mut pending = self
+            .pending_follows_from
+            .lock()
+            .expect("replay internal state (pending follows from) has become corrupted.");
+        if pending.is_empty() {
+            return;
+        }
+
+        let mut resolved = Vec::new();
+        pending.retain_mut(|dis_follows_from| {
+            match self.try_get_follows_from_span_ids(dis_follows_from) {
+                Some(span_ids) => {
+                    resolved.push((span_ids, dis_follows_from.record.take()));
+                    false
+                }
+                None => true,
+            }
+        });
+        drop(pending);
+
+        for ((cause_span_id, effect_span_id), record) in resolved {
+            dispatch.record_follows_from(&effect_span_id, &cause_span_id);
+            self.metrics.record_dispatched();
+            self.call_on_dispatched(record.as_deref());
+        }
+    }
+
+    /// Returns the replay span::Ids of the cause and effect, if both have been mapped.
+    ///
+    /// Unlike [`get_replay_span_id`], this method doesn't wait for pending spans.
+    ///
+    /// [`get_replay_span_id`]: fn@Self::get_replay_span_id
+    fn try_get_follows_from_span_ids(
+        &self,
+        dis_follows_from: &DispatchableFollowsFrom,
+    ) -> Option<(span::Id, span::Id)> {
+        let guard = self
+            .span_ids
+            .lock()
+            .expect("replay internal state has become corrupted.");
+
+        let mapped = |span_key| match (*guard).get(span_key) {
+            Some(MappedSpanId::Mapped(span_id)) => Some(span_id.clone()),
+            Some(MappedSpanId::Pending | MappedSpanId::Disabled) | None => None,
+        };
+        Some((
+            mapped(&dis_follows_from.cause)?,
+            mapped(&dis_follows_from.effect)?,
+        ))
+    }
+
+    /// Maps the recorded parent to the parent for the replay.
+    ///
+    /// An explicit parent which has no replay span::Id (because it was never replayed) is
+    /// replaced by a root parent.
+    fn replay_parent(&self, dis_parent: &DispatchableParent) -> proxy::Parent {
+        match dis_parent {
+            DispatchableParent::Root => proxy::Parent::Root,
+            DispatchableParent::Current => proxy::Parent::Current,
+            DispatchableParent::Explicit(span_key) => self
+                .get_replay_span_id(*span_key)
+                .map_or(proxy::Parent::Root, proxy::Parent::Explicit),
+        }
+    }
+
+    /// Returns the current mapping for the recorded span without waiting for it.
+    fn try_get_mapped_span_id(&self, span_key: SpanKey) -> Option<MappedSpanId> {
+        let guard = self
+            .span_ids
+            .lock()
+            .expect("replay internal state has become corrupted.");
+
+        (*guard).get(&span_key).cloned()
+    }
+
+    /// Returns the replay span::Id for the recorded span, waiting for it if it is pending.
+    ///
+    /// Returns `None` if the span wasn't replayed, either because it was disabled or because it
+    /// was created outside of the part of the recording being replayed.
+    fn get_replay_span_id(&self, span_key: SpanKey) -> Option<span::Id> {
+        let mut waiting = false;
+        let span_id = loop {
             let guard = self
                 .span_ids
                 .lock()
                 .expect("replay internal state has become corrupted.");
 
-            match (*guard).get(&rec_span_id) {
-                Some(MappedSpanId::Pending) => {} // Spin lock, it must be coming soon!
+            match (*guard).get(&span_key) {
+                Some(MappedSpanId::Pending) => {
+                    // Spin lock, it must be coming soon!
+                    if !waiting {
+                        waiting = true;
+                        self.wait_for(StallCause::WaitingForSpan);
+                    }
+                }
                 Some(MappedSpanId::Mapped(span_id)) => break Some(span_id.clone()),
-                None => break None,
+                Some(MappedSpanId::Disabled) | None => break None,
             }
+        };
+        if waiting {
+            self.wait_for(StallCause::Subscriber);
+        }
+        span_id
+    }
+
+    /// Removes the mapping for a recorded span which has been closed.
+    ///
+    /// The mapping is kept while a held back follows from relationship still refers to the span,
+    /// and if the span::Id was reused by a new span which hasn't been mapped yet.
+    fn remove_replay_span_id(&self, span_key: SpanKey) {
+        let pending = self
+            .pending_follows_from
+            .lock()
+            .expect("replay internal state (pending follows from) has become corrupted.");
+        if pending
+            .iter()
+            .any(|dis_follows_from| dis_follows_from.refers_to(span_key))
+        {
+            return;
+        }
+
+        let mut guard = self
+            .span_ids
+            .lock()
+            .expect("replay internal state has become corrupted.");
+        if !matches!((*guard).get(&span_key), Some(MappedSpanId::Pending)) {
+            (*guard).remove(&span_key);
         }
     }
+
+    fn set_replay_span_id(&self, span_key: SpanKey, mapped_span_id: MappedSpanId) {
+        let mut guard = self
+            .span_ids
+            .lock()
+            .expect("replay internal state has become corrupted.");
+
+        let current_value = (*guard).get(&span_key);
+        debug_assert!(
+            matches!(current_value, Some(MappedSpanId::Pending)),
+            "new span recorded span::Id should be Pending, but is {current_value:?}",
+        );
+        (*guard).insert(span_key, mapped_span_id);
+    }
+}
+
+/// The default for [`Replay::with_pending_record_timeout`].
+const DEFAULT_PENDING_RECORD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Values recorded for spans which haven't been mapped yet.
+#[derive(Debug, Default)]
+struct PendingRecords {
+    pending: Vec<PendingRecordValues>,
+    /// The spans of values which were dropped because they waited for too long.
+    expired: Vec<SpanKey>,
+}
+
+#[derive(Debug)]
+struct PendingRecordValues {
+    record_values: DispatchableRecordValues,
+    /// The original record, which is passed to the [`OnDispatched`] hook once the values have
+    /// been dispatched.
+    record: Option<Box<TraceRecord>>,
+    deadline: Instant,
+}
+
+/// A hook which is called with each record after it has been dispatched.
+#[derive(Clone)]
+struct OnDispatched(Arc<dyn Fn(&TraceRecord) + Send + Sync>);
+
+impl fmt::Debug for OnDispatched {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OnDispatched(..)")
+    }
 }
 
 #[derive(Debug)]
 struct ThreadDispatcherHandle {
-    join_handle: JoinHandle<()>,
-    trace_tx: mpsc::Sender<DispatchableContainer>,
+    worker: DispatchWorker,
+    /// The thread which the traces are dispatched on.
+    replay_thread_id: thread::ThreadId,
+    /// The name of the recorded thread.
+    thread_name: Option<String>,
+    dispatch_stats: Arc<DispatchStats>,
+    metrics: ThreadMetrics,
+    /// The mode the dispatcher thread was started in.
+    mode: ReplayMode,
+    /// The progress of the dispatcher thread, if a watchdog is watching it.
+    progress: Option<Arc<DispatcherProgress>>,
+}
+
+impl ThreadDispatcherHandle {
+    /// Sends a container to be dispatched.
+    ///
+    /// In [`ReplayMode::Deterministic`], this waits until the trace has been dispatched. If the
+    /// dispatcher thread has gone away, because it panicked, an error is returned with the
+    /// container if it wasn't received, or with `None` if the thread panicked while dispatching
+    /// it.
+    fn send(&self, container: DispatchableContainer) -> Result<(), Option<DispatchableContainer>> {
+        match &self.worker {
+            DispatchWorker::Thread {
+                trace_tx,
+                dispatched_rx,
+                ..
+            } => {
+                self.metrics.queued();
+                if let Err(mpsc::SendError(container)) = trace_tx.send(container) {
+                    return Err(Some(container));
+                }
+                if self.mode == ReplayMode::Deterministic {
+                    // Wait until the trace has been dispatched before moving on to the next one,
+                    // so that traces are dispatched in exactly the order they were recorded in.
+                    dispatched_rx.recv().map_err(|_| None)?;
+                }
+            }
+            DispatchWorker::Inline(thread_dispatcher) => {
+                thread_dispatcher.dispatch_inline(container);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where the traces recorded on a thread are dispatched.
+enum DispatchWorker {
+    /// A replay thread dispatches the traces sent to it.
+    Thread {
+        join_handle: JoinHandle<()>,
+        trace_tx: mpsc::Sender<DispatchableContainer>,
+        dispatched_rx: mpsc::Receiver<()>,
+    },
+    /// A replay thread couldn't be spawned, so traces are dispatched inline on the coordinator
+    /// thread.
+    Inline(Box<ThreadDispatcher>),
+}
+
+impl fmt::Debug for DispatchWorker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Thread { join_handle, .. } => f
+                .debug_struct("Thread")
+                .field("join_handle", join_handle)
+                .finish_non_exhaustive(),
+            Self::Inline(_) => f.write_str("Inline(..)"),
+        }
+    }
+}
+
+/// Returns the message of a panic from its payload.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "the dispatcher thread panicked".to_owned()
+    }
+}
+
+/// How often an async replay checks whether a paused clock has been resumed.
+const PAUSED_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Awaits `sleep` for `delay`, unless it is too short to be worth waiting for.
+async fn sleep_for<S, F>(sleep: &mut S, delay: Duration)
+where
+    S: FnMut(Duration) -> F,
+    F: Future<Output = ()>,
+{
+    if !delay.is_zero() {
+        let start = Instant::now();
+        if delay >= time::MIN_SLEEP {
+            sleep(delay).await;
+        }
+        time::slept_until(start + delay);
+    }
+}
+
+fn replay_values(rec_fields: &[Field]) -> Vec<ReplayValue<'_>> {
+    rec_fields
+        .iter()
+        .map(|rec_field| (&rec_field.value).into())
+        .collect()
 }
 
 fn create_field_values<'a>(
     metadata: &'static Metadata,
-    rec_fields: &'a [Field],
+    rec_fields: &[Field],
+    replay_values: &'a [ReplayValue<'_>],
 ) -> Vec<(field::Field, Option<&'a dyn tracing::Value>)> {
     let fields = metadata.fields();
     rec_fields
         .iter()
-        .filter_map(|rec_field| {
+        .zip(replay_values)
+        .filter_map(|(rec_field, replay_value)| {
             Some((
                 fields.field(&rec_field.name)?,
-                Some((&rec_field.value).into()),
+                Some(replay_value.as_value()),
             ))
         })
         .collect()
 }
 
-impl From<recording::Metadata> for Metadata<'static> {
-    fn from(val: recording::Metadata) -> Self {
-        let cs: &'static Cs = Box::leak(Box::new(Cs::new(val.id)));
+/// Creates the metadata for a recorded callsite, leaking its strings so that they live for the
+/// rest of the program.
+fn replay_metadata(val: recording::Metadata) -> Metadata<'static> {
+    let cs: &'static Cs = Box::leak(Box::new(Cs::new(val.id)));
 
-        // self.fields
-        let fields: Vec<&'static str> = val
-            .fields
-            .into_iter()
-            .map(|f| Box::leak(Box::new(f)) as &'static str)
-            .collect();
+    // self.fields
+    let fields: Vec<&'static str> = val
+        .fields
+        .into_iter()
+        .map(|f| Box::leak(Box::new(f)) as &'static str)
+        .collect();
 
-        tracing::Metadata::new(
-            leak(val.name),
-            leak(val.target),
-            val.level.into(),
-            val.file.map(|s| leak(s) as &'static str),
-            val.line,
-            val.module_path.map(|s| leak(s) as &'static str),
-            tracing::field::FieldSet::new(leak(fields), tracing_core::identify_callsite!(cs)),
-            val.kind.into(),
-        )
-    }
+    tracing::Metadata::new(
+        leak(val.name),
+        leak(val.target),
+        val.level.into(),
+        val.file.map(|s| leak(s) as &'static str),
+        val.line,
+        val.module_path.map(|s| leak(s) as &'static str),
+        tracing::field::FieldSet::new(leak(fields), tracing_core::identify_callsite!(cs)),
+        val.kind.into(),
+    )
 }
 
 fn leak<T>(obj: T) -> &'static T {
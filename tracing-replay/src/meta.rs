@@ -0,0 +1,232 @@
+use std::time::Duration;
+
+use crate::recording::{
+    CowStr, EventRef, FieldRef, FieldValueRef, Kind, Level, MetadataRef, NewSpanRef, Parent,
+    RecordMetaRef, SpanId, TraceRecordRef, TraceRef,
+};
+
+/// The target of the synthetic span and events which describe the replay.
+pub(crate) const META_TARGET: &str = "tracing_replay::meta";
+
+/// The span Id of the synthetic replay span, and the first of the callsite Ids of the meta
+/// callsites.
+///
+/// These count down from below the Ids of the synthetic thread spans, so that they don't collide
+/// with those or with the Ids in the recording.
+const META_ID: u64 = u64::MAX - (1 << 32);
+
+/// The synthetic span which wraps the replay and the events which describe its progress, see
+/// [`Replay::with_meta_events`].
+///
+/// [`Replay::with_meta_events`]: fn@crate::Replay::with_meta_events
+#[derive(Debug)]
+pub(crate) struct MetaEvents {
+    /// How much recorded time passes between progress events.
+    progress_interval: Duration,
+    /// The recorded threads which have entered the replay span, with the time of the latest
+    /// record on each of them, if the span is open.
+    threads: Vec<(RecordMetaRef<'static>, Duration)>,
+    /// The recording being replayed, once its first record has been replayed.
+    recording: Option<RecordingProgress>,
+    /// The path of the next recording to be replayed, if it is a file.
+    next_path: Option<String>,
+}
+
+/// The progress through a single recording.
+#[derive(Debug)]
+struct RecordingProgress {
+    path: Option<String>,
+    /// The number of records which have been replayed from the recording.
+    records: u64,
+    next_progress: Duration,
+    /// The meta of the latest record, which the finished event is emitted with.
+    latest: RecordMetaRef<'static>,
+}
+
+/// The meta callsites, with their offsets from [`META_ID`].
+#[derive(Clone, Copy)]
+enum MetaCallsite {
+    Span = 0,
+    Started = 1,
+    Progress = 2,
+    Finished = 3,
+}
+
+impl MetaEvents {
+    pub(crate) fn new(progress_interval: Duration) -> Self {
+        Self {
+            progress_interval,
+            threads: Vec::new(),
+            recording: None,
+            next_path: None,
+        }
+    }
+
+    /// Sets the path of the recording which is replayed next.
+    pub(crate) fn start_recording(&mut self, path: Option<&str>) {
+        self.next_path = path.map(str::to_owned);
+    }
+
+    /// Tracks a record which is about to be replayed, returning the meta records to replay
+    /// before it.
+    pub(crate) fn track(&mut self, meta: &RecordMetaRef<'_>) -> Vec<TraceRecordRef<'static>> {
+        let meta = RecordMetaRef {
+            timestamp_s: meta.timestamp_s,
+            timestamp_subsec_us: meta.timestamp_subsec_us,
+            thread_id: meta.thread_id.clone().into_static(),
+            thread_name: meta.thread_name.clone().map(CowStr::into_static),
+        };
+        let timestamp = meta.timestamp();
+        let mut records = Vec::new();
+
+        if self.threads.is_empty() {
+            records.push(record(
+                &meta,
+                TraceRef::NewSpan(NewSpanRef {
+                    id: SpanId::from(META_ID),
+                    fields: Vec::new(),
+                    metadata: metadata(MetaCallsite::Span),
+                    parent: Parent::Root,
+                    trace_context: None,
+                }),
+            ));
+        }
+        match self
+            .threads
+            .iter_mut()
+            .find(|(thread, _)| thread.thread_id == meta.thread_id)
+        {
+            Some((_, latest)) => *latest = (*latest).max(timestamp),
+            None => {
+                records.push(record(&meta, TraceRef::Enter(SpanId::from(META_ID))));
+                self.threads.push((meta.clone(), timestamp));
+            }
+        }
+
+        match &mut self.recording {
+            Some(recording) => {
+                recording.latest = meta;
+                if timestamp >= recording.next_progress {
+                    recording.next_progress = timestamp + self.progress_interval;
+                    records.push(recording.event(MetaCallsite::Progress, "replay progress"));
+                }
+                recording.records += 1;
+            }
+            None => {
+                let mut recording = RecordingProgress {
+                    path: self.next_path.take(),
+                    records: 0,
+                    next_progress: timestamp + self.progress_interval,
+                    latest: meta,
+                };
+                records.push(recording.event(MetaCallsite::Started, "replay started"));
+                recording.records += 1;
+                self.recording = Some(recording);
+            }
+        }
+
+        records
+    }
+
+    /// Returns the finished event of the recording which has been replayed, if any of its
+    /// records were.
+    pub(crate) fn finish_recording(&mut self) -> Vec<TraceRecordRef<'static>> {
+        self.next_path = None;
+        self.recording
+            .take()
+            .map(|recording| recording.event(MetaCallsite::Finished, "replay finished"))
+            .into_iter()
+            .collect()
+    }
+
+    /// Returns the records which exit the replay span on each recorded thread, at the time of the
+    /// latest record on the thread, and then close it.
+    pub(crate) fn finish(&mut self) -> Vec<TraceRecordRef<'static>> {
+        let mut records = self.finish_recording();
+        let id = SpanId::from(META_ID);
+        for (meta, latest) in &self.threads {
+            records.push(record(&at(meta, *latest), TraceRef::Exit(id)));
+        }
+        if let Some((meta, _)) = self.threads.first() {
+            let latest = self.threads.iter().map(|(_, latest)| *latest).max();
+            records.push(record(
+                &at(meta, latest.unwrap_or_default()),
+                TraceRef::Close(id),
+            ));
+        }
+        self.threads.clear();
+        records
+    }
+}
+
+impl RecordingProgress {
+    /// Returns an event in the replay span about this recording.
+    fn event(&self, callsite: MetaCallsite, message: &'static str) -> TraceRecordRef<'static> {
+        let mut fields = vec![
+            FieldRef {
+                name: "message".into(),
+                value: FieldValueRef::Debug(message.into()),
+            },
+            FieldRef {
+                name: "records".into(),
+                value: FieldValueRef::U64(self.records),
+            },
+        ];
+        if let Some(path) = &self.path {
+            fields.push(FieldRef {
+                name: "recording.path".into(),
+                value: FieldValueRef::Str(CowStr::from(path.clone())),
+            });
+        }
+        record(
+            &self.latest,
+            TraceRef::Event(EventRef {
+                fields,
+                metadata: metadata(callsite),
+                parent: Parent::Explicit(SpanId::from(META_ID)),
+            }),
+        )
+    }
+}
+
+/// Returns the metadata of one of the meta callsites.
+fn metadata(callsite: MetaCallsite) -> MetadataRef<'static> {
+    let (name, kind, fields): (_, _, &[&'static str]) = match callsite {
+        MetaCallsite::Span => ("replay", Kind::Span, &[]),
+        MetaCallsite::Started => ("replay started", Kind::Event, EVENT_FIELDS),
+        MetaCallsite::Progress => ("replay progress", Kind::Event, EVENT_FIELDS),
+        MetaCallsite::Finished => ("replay finished", Kind::Event, EVENT_FIELDS),
+    };
+    MetadataRef {
+        id: META_ID - callsite as u64,
+        name: name.into(),
+        target: META_TARGET.into(),
+        level: Level::Info,
+        module_path: None,
+        file: None,
+        line: None,
+        fields: fields.iter().map(|field| CowStr::from(*field)).collect(),
+        kind,
+    }
+}
+
+/// The fields of the meta events.
+const EVENT_FIELDS: &[&str] = &["message", "records", "recording.path"];
+
+/// Returns `meta` at the time `timestamp`.
+fn at(meta: &RecordMetaRef<'static>, timestamp: Duration) -> RecordMetaRef<'static> {
+    RecordMetaRef {
+        timestamp_s: timestamp.as_secs(),
+        timestamp_subsec_us: timestamp.subsec_micros(),
+        ..meta.clone()
+    }
+}
+
+fn record(meta: &RecordMetaRef<'static>, trace: TraceRef<'static>) -> TraceRecordRef<'static> {
+    TraceRecordRef {
+        meta: meta.clone(),
+        trace,
+        final_fields: None,
+        sequence: None,
+    }
+}
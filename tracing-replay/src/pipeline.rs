@@ -0,0 +1,112 @@
+use std::{
+    io,
+    sync::mpsc,
+    thread::{self, Scope},
+    vec,
+};
+
+use crate::{
+    reader::{Line, Lines},
+    recording::TraceRecordRef,
+    ReplayFileError,
+};
+
+/// The number of lines which are sent to a parser thread at once.
+const BATCH_SIZE: usize = 256;
+
+/// The number of batches which may be waiting to be parsed or consumed per parser thread.
+const QUEUED_BATCHES: usize = 4;
+
+type ParsedBatch<'a> = Vec<Result<Option<TraceRecordRef<'a>>, ReplayFileError>>;
+
+/// Parses the lines of a recording on `parse_threads` separate threads.
+///
+/// The lines are split into batches which are handed out to the parser threads in turn. The
+/// returned iterator collects the parsed batches in the same order, so records are returned in
+/// the order that they appear in the recording.
+///
+/// If the returned iterator is dropped before it has been exhausted, the parser threads stop
+/// once they next try to hand over a parsed batch.
+///
+/// # Errors
+///
+/// Returns an error if one of the threads can't be spawned. Any threads which were already
+/// spawned stop once they find that there are no lines to parse.
+pub(crate) fn parse<'scope>(
+    scope: &'scope Scope<'scope, '_>,
+    lines: Lines<'scope>,
+    parse_threads: usize,
+) -> io::Result<ParsedRecords<'scope>> {
+    let mut batch_txs = Vec::with_capacity(parse_threads);
+    let mut parsed_rxs = Vec::with_capacity(parse_threads);
+    for _ in 0..parse_threads {
+        let (batch_tx, batch_rx) = mpsc::sync_channel::<Vec<Line<'scope>>>(QUEUED_BATCHES);
+        let (parsed_tx, parsed_rx) = mpsc::sync_channel::<ParsedBatch<'scope>>(QUEUED_BATCHES);
+        spawn(scope, "tracing-replay-parser", move || {
+            for batch in batch_rx {
+                let parsed = batch.iter().map(Line::parse).collect();
+                if parsed_tx.send(parsed).is_err() {
+                    break;
+                }
+            }
+        })?;
+        batch_txs.push(batch_tx);
+        parsed_rxs.push(parsed_rx);
+    }
+
+    spawn(scope, "tracing-replay-splitter", move || {
+        let mut lines = lines.peekable();
+        for batch_tx in batch_txs.iter().cycle() {
+            if lines.peek().is_none() {
+                break;
+            }
+            let batch = lines.by_ref().take(BATCH_SIZE).collect();
+            if batch_tx.send(batch).is_err() {
+                break;
+            }
+        }
+    })?;
+
+    Ok(ParsedRecords {
+        parsed_rxs,
+        next_rx: 0,
+        current: Vec::new().into_iter(),
+    })
+}
+
+fn spawn<'scope>(
+    scope: &'scope Scope<'scope, '_>,
+    name: &str,
+    f: impl FnOnce() + Send + 'scope,
+) -> io::Result<()> {
+    thread::Builder::new()
+        .name(name.into())
+        .spawn_scoped(scope, f)
+        .map(|_join_handle| ())
+}
+
+/// Iterator over the records parsed by the parser threads, in recording order.
+pub(crate) struct ParsedRecords<'a> {
+    parsed_rxs: Vec<mpsc::Receiver<ParsedBatch<'a>>>,
+    next_rx: usize,
+    current: vec::IntoIter<Result<Option<TraceRecordRef<'a>>, ReplayFileError>>,
+}
+
+impl<'a> Iterator for ParsedRecords<'a> {
+    type Item = Result<Option<TraceRecordRef<'a>>, ReplayFileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.current.next() {
+                return Some(record);
+            }
+
+            // Batches are handed out round-robin, so the next batch is always waiting on the
+            // receiver after the one we last read from. Once that receiver is closed, all the
+            // lines have been parsed.
+            let parsed_rx = self.parsed_rxs.get(self.next_rx)?;
+            self.current = parsed_rx.recv().ok()?.into_iter();
+            self.next_rx = (self.next_rx + 1) % self.parsed_rxs.len();
+        }
+    }
+}
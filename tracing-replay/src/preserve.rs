@@ -0,0 +1,247 @@
+use std::{
+    any::TypeId,
+    cell::Cell,
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use tracing::{
+    span::{self, Attributes},
+    subscriber::Interest,
+    Dispatch, Event, Metadata, Subscriber,
+};
+use tracing_core::span::Current;
+
+use crate::proxy::reparent_event;
+
+/// Span Ids assigned to spans which couldn't be given their recorded span Id have this bit set.
+const FALLBACK_ID_BIT: u64 = 1 << 63;
+
+thread_local! {
+    /// The recorded span Id of the span which is currently being created by the replay.
+    static RECORDED_SPAN_ID: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Calls `f` with `rec_span_id` set as the recorded span Id of the span being created.
+pub(crate) fn with_recorded_span_id<R>(rec_span_id: u64, f: impl FnOnce() -> R) -> R {
+    let previous = RECORDED_SPAN_ID.with(|cell| cell.replace(Some(rec_span_id)));
+    let result = f();
+    RECORDED_SPAN_ID.with(|cell| cell.set(previous));
+    result
+}
+
+/// A subscriber which gives replayed spans the same span Ids that they had in the recording.
+///
+/// The span Ids of new spans are normally assigned by the subscriber, so the spans created during
+/// a replay have different Ids to the ones in the original run. Wrapping a subscriber in
+/// `PreserveSpanIds` and replaying through it exposes the recorded span Ids instead, so that
+/// anything which keys off span Id values sees the same values as in the original run.
+///
+/// The wrapped subscriber still assigns its own span Ids, `PreserveSpanIds` translates between
+/// the recorded span Ids and the wrapped subscriber's span Ids. This means that layers within the
+/// wrapped subscriber see the wrapped subscriber's span Ids, while the span Ids returned from the
+/// dispatcher, for example by [`Span::id`] and [`Span::current`], are the recorded span Ids.
+///
+/// If a recorded span Id is still in use by another span when a new span is created, the new
+/// span is given the wrapped subscriber's span Id with the highest bit set instead. The same
+/// applies to spans which aren't created by a replay.
+///
+/// When the replay uses [`Replay::with_sequential_span_ids`], replayed spans are numbered in the
+/// order they were recorded instead of being given their recorded span Ids.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_replay::{PreserveSpanIds, Replay};
+///
+/// let new_span = r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":42,"fields":[],"metadata":{"id":4403349456,"name":"span","target":"record_spans","level":"Info","module_path":"record_spans","file":"tracing-rec/examples/record-spans.rs","line":8,"fields":[],"kind":"Span"},"parent":"Root"}}}"#;
+///
+/// let subscriber = PreserveSpanIds::new(tracing_subscriber::registry());
+/// tracing::subscriber::with_default(subscriber, || {
+///     // The replayed span is given the span Id 42.
+///     let mut replay = Replay::new();
+///     replay.replay_str(new_span).unwrap();
+///     replay.close().unwrap();
+/// });
+/// ```
+///
+/// [`Replay::with_sequential_span_ids`]: fn@crate::Replay::with_sequential_span_ids
+/// [`Span::id`]: fn@tracing::Span::id
+/// [`Span::current`]: fn@tracing::Span::current
+pub struct PreserveSpanIds<S> {
+    inner: S,
+    ids: Arc<Mutex<SpanIdMap>>,
+}
+
+/// The mapping between the span Ids exposed by [`PreserveSpanIds`] and those of the wrapped
+/// subscriber.
+#[derive(Debug, Default)]
+struct SpanIdMap {
+    to_inner: HashMap<u64, span::Id>,
+    from_inner: HashMap<u64, span::Id>,
+}
+
+impl<S> PreserveSpanIds<S>
+where
+    S: Subscriber,
+{
+    /// Wraps `subscriber` so that replayed spans are given their recorded span Ids.
+    pub fn new(subscriber: S) -> Self {
+        Self {
+            inner: subscriber,
+            ids: Arc::new(Mutex::new(SpanIdMap::default())),
+        }
+    }
+
+    fn lock_ids(&self) -> std::sync::MutexGuard<'_, SpanIdMap> {
+        self.ids
+            .lock()
+            .expect("preserved span Id mapping has become corrupted.")
+    }
+
+    /// Returns the wrapped subscriber's span Id for the exposed span Id `id`.
+    fn inner_id(&self, id: &span::Id) -> span::Id {
+        self.lock_ids()
+            .to_inner
+            .get(&id.into_u64())
+            .cloned()
+            .unwrap_or_else(|| id.clone())
+    }
+
+    /// Returns the exposed span Id for the wrapped subscriber's span Id `inner_id`.
+    fn outer_id(&self, inner_id: &span::Id) -> span::Id {
+        self.lock_ids()
+            .from_inner
+            .get(&inner_id.into_u64())
+            .cloned()
+            .unwrap_or_else(|| inner_id.clone())
+    }
+}
+
+impl<S> fmt::Debug for PreserveSpanIds<S>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PreserveSpanIds")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> Subscriber for PreserveSpanIds<S>
+where
+    S: Subscriber,
+{
+    fn on_register_dispatch(&self, subscriber: &Dispatch) {
+        self.inner.on_register_dispatch(subscriber);
+    }
+
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.inner.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn max_level_hint(&self) -> Option<tracing::level_filters::LevelFilter> {
+        self.inner.max_level_hint()
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> span::Id {
+        let inner_id = match span.parent() {
+            Some(parent) => {
+                let attrs =
+                    Attributes::child_of(self.inner_id(parent), span.metadata(), span.values());
+                self.inner.new_span(&attrs)
+            }
+            None => self.inner.new_span(span),
+        };
+
+        let mut ids = self.lock_ids();
+        let outer_id = RECORDED_SPAN_ID
+            .with(Cell::get)
+            .filter(|rec_span_id| *rec_span_id != 0 && !ids.to_inner.contains_key(rec_span_id))
+            .map_or_else(
+                || span::Id::from_u64(inner_id.into_u64() | FALLBACK_ID_BIT),
+                span::Id::from_u64,
+            );
+        ids.to_inner.insert(outer_id.into_u64(), inner_id.clone());
+        ids.from_inner.insert(inner_id.into_u64(), outer_id.clone());
+
+        outer_id
+    }
+
+    fn record(&self, span: &span::Id, values: &span::Record<'_>) {
+        self.inner.record(&self.inner_id(span), values);
+    }
+
+    fn record_follows_from(&self, span: &span::Id, follows: &span::Id) {
+        self.inner
+            .record_follows_from(&self.inner_id(span), &self.inner_id(follows));
+    }
+
+    fn event_enabled(&self, event: &Event<'_>) -> bool {
+        // The event's explicit parent, if any, is an exposed span Id which the wrapped subscriber
+        // doesn't know about. Filtering is decided by `event` instead.
+        event.parent().is_some() || self.inner.event_enabled(event)
+    }
+
+    fn event(&self, event: &Event<'_>) {
+        let Some(parent) = event.parent() else {
+            self.inner.event(event);
+            return;
+        };
+
+        // The parent needs to be translated to the wrapped subscriber's span Id, which means
+        // creating the event again.
+        reparent_event(event, Some(self.inner_id(parent)), |event| {
+            if self.inner.event_enabled(event) {
+                self.inner.event(event);
+            }
+        });
+    }
+
+    fn enter(&self, span: &span::Id) {
+        self.inner.enter(&self.inner_id(span));
+    }
+
+    fn exit(&self, span: &span::Id) {
+        self.inner.exit(&self.inner_id(span));
+    }
+
+    fn clone_span(&self, id: &span::Id) -> span::Id {
+        self.inner.clone_span(&self.inner_id(id));
+        id.clone()
+    }
+
+    fn try_close(&self, id: span::Id) -> bool {
+        let inner_id = self.inner_id(&id);
+        let closed = self.inner.try_close(inner_id.clone());
+        if closed {
+            let mut ids = self.lock_ids();
+            ids.to_inner.remove(&id.into_u64());
+            ids.from_inner.remove(&inner_id.into_u64());
+        }
+
+        closed
+    }
+
+    fn current_span(&self) -> Current {
+        let current = self.inner.current_span();
+        match (current.id(), current.metadata()) {
+            (Some(inner_id), Some(metadata)) => Current::new(self.outer_id(inner_id), metadata),
+            _ => current,
+        }
+    }
+
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            return Some(std::ptr::from_ref(self).cast());
+        }
+
+        self.inner.downcast_raw(id)
+    }
+}
@@ -1,10 +1,32 @@
+use std::fmt;
+
 use tracing::{
-    field,
+    field::{self, Visit},
     span::{self, Attributes},
     Event, Metadata,
 };
 
-use crate::recording;
+use crate::{
+    create_field_values,
+    recording::{Field, FieldValue},
+    replay_values,
+};
+
+/// The parent of a replayed span or event.
+///
+/// Unlike [`recording::Parent`], an explicit parent refers to the span Id assigned during the
+/// replay.
+///
+/// [`recording::Parent`]: crate::recording::Parent
+#[derive(Debug)]
+pub(crate) enum Parent {
+    /// The new span or event will be a root.
+    Root,
+    /// The new span or event will be rooted in the current span.
+    Current,
+    /// The new span or event has an explicitly-specified parent.
+    Explicit(span::Id),
+}
 
 pub(crate) trait DispatchProxy {
     type Output;
@@ -659,14 +681,14 @@ pub(crate) trait DispatchProxy {
 pub(crate) struct NewSpanProxy<'a> {
     dispatch: &'a tracing::Dispatch,
     metadata: &'static Metadata<'static>,
-    parent: &'a recording::Parent,
+    parent: &'a Parent,
 }
 
 impl<'a> NewSpanProxy<'a> {
     pub(crate) fn new(
         dispatch: &'a tracing::Dispatch,
         metadata: &'static Metadata<'static>,
-        parent: &'a recording::Parent,
+        parent: &'a Parent,
     ) -> Self {
         Self {
             dispatch,
@@ -685,10 +707,10 @@ impl<'a> DispatchProxy for NewSpanProxy<'a> {
     ) -> Self::Output {
         let value_set = self.metadata.fields().value_set(&values);
         let attr = match self.parent {
-            recording::Parent::Current => Attributes::new(self.metadata, &value_set),
-            recording::Parent::Root => Attributes::new_root(self.metadata, &value_set),
-            &recording::Parent::Explicit(parent_id) => {
-                Attributes::child_of(span::Id::from_u64(parent_id), self.metadata, &value_set)
+            Parent::Current => Attributes::new(self.metadata, &value_set),
+            Parent::Root => Attributes::new_root(self.metadata, &value_set),
+            Parent::Explicit(parent_id) => {
+                Attributes::child_of(parent_id.clone(), self.metadata, &value_set)
             }
         };
         self.dispatch.new_span(&attr)
@@ -698,14 +720,14 @@ impl<'a> DispatchProxy for NewSpanProxy<'a> {
 pub(crate) struct EventProxy<'a> {
     dispatch: &'a tracing::Dispatch,
     metadata: &'static Metadata<'static>,
-    parent: &'a recording::Parent,
+    parent: &'a Parent,
 }
 
 impl<'a> EventProxy<'a> {
     pub(crate) fn new(
         dispatch: &'a tracing::Dispatch,
         metadata: &'static Metadata<'static>,
-        parent: &'a recording::Parent,
+        parent: &'a Parent,
     ) -> Self {
         Self {
             dispatch,
@@ -724,13 +746,11 @@ impl<'a> DispatchProxy for EventProxy<'a> {
     ) -> Self::Output {
         let value_set = self.metadata.fields().value_set(&values);
         let event = match self.parent {
-            recording::Parent::Current => Event::new(self.metadata, &value_set),
-            recording::Parent::Root => Event::new_child_of(None, self.metadata, &value_set),
-            recording::Parent::Explicit(parent_id) => Event::new_child_of(
-                Some(span::Id::from_u64(*parent_id)),
-                self.metadata,
-                &value_set,
-            ),
+            Parent::Current => Event::new(self.metadata, &value_set),
+            Parent::Root => Event::new_child_of(None, self.metadata, &value_set),
+            Parent::Explicit(parent_id) => {
+                Event::new_child_of(Some(parent_id.clone()), self.metadata, &value_set)
+            }
         };
         self.dispatch.event(&event);
     }
@@ -768,3 +788,98 @@ impl<'a> DispatchProxy for RecordProxy<'a> {
         self.dispatch.record(self.span_id, &record);
     }
 }
+
+/// Creates a copy of `event` with `parent` as its explicit parent and passes it to
+/// `dispatch_event`.
+///
+/// This is needed by subscribers which wrap other subscribers with different span Ids, as the
+/// parent of an event can't be changed once it has been created. The field values of the copy
+/// are recorded from the original event in the same way as they would be in a recording.
+pub(crate) fn reparent_event(
+    event: &Event<'_>,
+    parent: Option<span::Id>,
+    dispatch_event: impl Fn(&Event<'_>),
+) {
+    let mut visitor = FieldVisitor::default();
+    event.record(&mut visitor);
+    let replay_values = replay_values(&visitor.fields);
+    let values = create_field_values(event.metadata(), &visitor.fields, &replay_values);
+    let proxy = ReparentedEventProxy {
+        metadata: event.metadata(),
+        parent,
+        dispatch_event: &dispatch_event,
+    };
+    proxy.dispatch_values(values);
+}
+
+/// Collects the field values of an event so that it can be dispatched again.
+#[derive(Default)]
+struct FieldVisitor {
+    fields: Vec<Field>,
+}
+
+impl FieldVisitor {
+    fn push(&mut self, field: &field::Field, value: FieldValue) {
+        self.fields.push(Field {
+            name: field.name().to_owned(),
+            value,
+        });
+    }
+}
+
+impl Visit for FieldVisitor {
+    fn record_f64(&mut self, field: &field::Field, value: f64) {
+        self.push(field, FieldValue::F64(value));
+    }
+
+    fn record_i64(&mut self, field: &field::Field, value: i64) {
+        self.push(field, FieldValue::I64(value));
+    }
+
+    fn record_u64(&mut self, field: &field::Field, value: u64) {
+        self.push(field, FieldValue::U64(value));
+    }
+
+    fn record_i128(&mut self, field: &field::Field, value: i128) {
+        self.push(field, FieldValue::I128(value));
+    }
+
+    fn record_u128(&mut self, field: &field::Field, value: u128) {
+        self.push(field, FieldValue::U128(value));
+    }
+
+    fn record_bool(&mut self, field: &field::Field, value: bool) {
+        self.push(field, FieldValue::Bool(value));
+    }
+
+    fn record_str(&mut self, field: &field::Field, value: &str) {
+        self.push(field, FieldValue::Str(value.to_owned()));
+    }
+
+    fn record_debug(&mut self, field: &field::Field, value: &dyn fmt::Debug) {
+        self.push(field, FieldValue::Debug(format!("{value:?}")));
+    }
+}
+
+/// Creates a copy of an event with a different explicit parent.
+struct ReparentedEventProxy<'a, F> {
+    metadata: &'static Metadata<'static>,
+    parent: Option<span::Id>,
+    dispatch_event: &'a F,
+}
+
+impl<'a, F> DispatchProxy for ReparentedEventProxy<'a, F>
+where
+    F: Fn(&Event<'_>),
+{
+    type Output = ();
+
+    fn dispatch<const N: usize>(
+        &self,
+        values: [(&field::Field, Option<&dyn tracing::Value>); N],
+    ) -> Self::Output {
+        let value_set = self.metadata.fields().value_set(&values);
+        let event = Event::new_child_of(self.parent.clone(), self.metadata, &value_set);
+        (self.dispatch_event)(&event);
+    }
+}
@@ -0,0 +1,51 @@
+use std::{thread, time::Duration};
+
+use crate::time::Instant;
+
+/// Limits the rate at which records are handed over to the dispatcher threads.
+///
+/// Records over the limit are delayed rather than dropped, by blocking the thread that is
+/// replaying the recording until the next record may be dispatched.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    /// The minimum time between two records.
+    interval: Duration,
+    /// The instant at which the next record may be dispatched.
+    next: Option<Instant>,
+}
+
+impl RateLimiter {
+    /// Creates a rate limiter which allows at most `records_per_sec` records per second.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `records_per_sec` is not greater than zero.
+    pub(crate) fn new(records_per_sec: f64) -> Self {
+        assert!(
+            records_per_sec > 0.0,
+            "replay max rate must be greater than zero, but got {records_per_sec}"
+        );
+
+        Self {
+            interval: Duration::try_from_secs_f64(1.0 / records_per_sec).unwrap_or(Duration::ZERO),
+            next: None,
+        }
+    }
+
+    /// Blocks the current thread until another record may be dispatched.
+    pub(crate) fn wait(&mut self) {
+        let delay = self.delay();
+        if !delay.is_zero() {
+            thread::sleep(delay);
+        }
+    }
+
+    /// Returns how long to wait until another record may be dispatched, reserving the slot for
+    /// that record.
+    pub(crate) fn delay(&mut self) -> Duration {
+        let now = Instant::now();
+        let scheduled = self.next.map_or(now, |next| next.max(now));
+        self.next = scheduled.checked_add(self.interval);
+        scheduled.saturating_duration_since(now)
+    }
+}
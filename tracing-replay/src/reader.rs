@@ -0,0 +1,306 @@
+#[cfg(feature = "simd-json")]
+use std::cell::RefCell;
+use std::{borrow::Cow, collections::HashMap, fs::File, io::Read, time::Duration};
+
+use memmap2::Mmap;
+use serde::{de::IgnoredAny, Deserialize};
+use tracing_cassette::{
+    ContainerReader, Header, CONTAINER_MAGIC, FORMAT_VERSION, UNVERSIONED_FORMAT_VERSION,
+};
+
+use crate::{
+    index::Position,
+    recording::{RecordMetaRef, TraceRecordRef},
+    ReplayFileError,
+};
+
+/// Maps the recording file at `path` into memory.
+pub(crate) fn map_file(path: &str) -> Result<Mmap, ReplayFileError> {
+    let file =
+        File::open(path).map_err(|io_err| ReplayFileError::CannotOpenFile { inner: io_err })?;
+
+    // SAFETY: The mapping is only ever read from. If the recording file is modified (e.g.
+    // truncated) by another process while it is being replayed, the results are undefined. This
+    // is the same restriction that applies to reading a file which is still being written.
+    unsafe { Mmap::map(&file) }.map_err(|io_err| ReplayFileError::CannotOpenFile { inner: io_err })
+}
+
+/// Decodes the recording in `data` if it is stored in a container.
+///
+/// The lines of a container can't be borrowed from the data, so the whole recording is decoded
+/// into memory. Recordings of JSON lines are borrowed as they are.
+pub(crate) fn decode_container(data: &[u8]) -> Result<Cow<'_, [u8]>, ReplayFileError> {
+    if !data.starts_with(&CONTAINER_MAGIC) {
+        return Ok(Cow::Borrowed(data));
+    }
+
+    let mut decoded = Vec::new();
+    ContainerReader::new(data)
+        .read_to_end(&mut decoded)
+        .map_err(|inner| ReplayFileError::CannotDecodeContainer { inner })?;
+
+    Ok(Cow::Owned(decoded))
+}
+
+/// Returns the header of the recording in `data`, or `None` if it doesn't have one.
+pub(crate) fn header(data: &[u8]) -> Option<Header> {
+    let first_line = data.split(|b| *b == b'\n').next()?;
+    Header::from_line(first_line)
+}
+
+/// Returns the timestamps of the first and the last record in the recording in `data`, or `None`
+/// if it has no records.
+///
+/// Only the first and last lines are parsed. If the last record was truncated, the one before it
+/// is used instead.
+pub(crate) fn recorded_bounds(data: &[u8]) -> Option<(Duration, Duration)> {
+    let mut lines = Lines::new(data);
+    let first = lines
+        .by_ref()
+        .find_map(|line| line.parse().ok().flatten())?;
+    let start = first.meta.timestamp();
+
+    let mut previous = None;
+    let mut last = None;
+    for line in lines {
+        previous = last.replace(line);
+    }
+    let end = [last, previous]
+        .into_iter()
+        .flatten()
+        .find_map(|line| line.parse().ok().flatten())
+        .map_or(start, |record| record.meta.timestamp());
+
+    Some((start, end.max(start)))
+}
+
+/// A single line of a recording, borrowed from the underlying data.
+#[derive(Debug)]
+pub(crate) struct Line<'a> {
+    pub(crate) bytes: &'a [u8],
+    pub(crate) position: Position,
+    /// The version of the format that the recording was written in.
+    pub(crate) version: u32,
+}
+
+impl<'a> Line<'a> {
+    /// Deserializes the trace record stored in this line.
+    ///
+    /// Records written in an earlier version of the format are migrated to the current version
+    /// first. Returns `None` for a record with a kind of trace that this version of the format
+    /// doesn't know about, see [`UnknownTraceRecord`].
+    pub(crate) fn parse(&self) -> Result<Option<TraceRecordRef<'a>>, ReplayFileError> {
+        if self.version != FORMAT_VERSION {
+            tracing_cassette::check_version(self.version).map_err(|_| {
+                ReplayFileError::RecordingTooNew {
+                    version: self.version,
+                }
+            })?;
+            if tracing_cassette::needs_migration(self.version) {
+                return self.parse_migrated().map(Some);
+            }
+        }
+
+        match self.parse_current() {
+            Ok(record) => Ok(Some(record)),
+            Err(_) if self.has_unknown_trace() => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Returns whether this line is a complete record with a kind of trace that this version of
+    /// the format doesn't know about.
+    ///
+    /// This is only checked once a line has failed to parse, so that it costs nothing for
+    /// recordings which only have known kinds of trace.
+    fn has_unknown_trace(&self) -> bool {
+        serde_json::from_slice::<UnknownTraceRecord<'_>>(self.bytes)
+            .is_ok_and(|record| record.trace.is_unknown())
+    }
+
+    /// Deserializes a record which needs to be migrated, by way of a [`serde_json::Value`].
+    fn parse_migrated(&self) -> Result<TraceRecordRef<'a>, ReplayFileError> {
+        let mut value: serde_json::Value =
+            serde_json::from_slice(self.bytes).map_err(|err| self.deserialize_error(err))?;
+        tracing_cassette::migrate(self.version, &mut value).map_err(|_| {
+            ReplayFileError::RecordingTooNew {
+                version: self.version,
+            }
+        })?;
+
+        TraceRecordRef::deserialize(value).map_err(|err| self.deserialize_error(err))
+    }
+
+    /// Deserializes the trace record stored in this line, which is in the current version.
+    ///
+    /// The returned record borrows its strings from the line.
+    #[cfg(not(feature = "simd-json"))]
+    fn parse_current(&self) -> Result<TraceRecordRef<'a>, ReplayFileError> {
+        serde_json::from_slice(self.bytes).map_err(|err| self.deserialize_error(err))
+    }
+
+    /// Deserializes the trace record stored in this line with `simd-json`.
+    ///
+    /// `simd-json` needs a mutable copy of the line to parse, so the returned record owns its
+    /// strings. The copy and the parser's buffers are reused for each line parsed on a thread.
+    /// Lines which `simd-json` can't parse are parsed again with `serde_json`, so that
+    /// both the records which are accepted and the errors for invalid lines are the same as
+    /// without the `simd-json` feature.
+    #[cfg(feature = "simd-json")]
+    fn parse_current(&self) -> Result<TraceRecordRef<'a>, ReplayFileError> {
+        thread_local! {
+            static SCRATCH: RefCell<(Vec<u8>, simd_json::Buffers)> =
+                RefCell::new((Vec::new(), simd_json::Buffers::default()));
+        }
+
+        let parsed = SCRATCH.with(|scratch| {
+            let (line, buffers) = &mut *scratch.borrow_mut();
+            line.clear();
+            line.extend_from_slice(self.bytes);
+            simd_json::serde::from_slice_with_buffers::<TraceRecordRef<'_>>(line, buffers)
+                .map(TraceRecordRef::into_static)
+                .ok()
+        });
+        match parsed {
+            Some(record) => Ok(record),
+            None => serde_json::from_slice(self.bytes).map_err(|err| self.deserialize_error(err)),
+        }
+    }
+
+    fn deserialize_error(&self, err: serde_json::Error) -> ReplayFileError {
+        ReplayFileError::CannotDeserializeRecord {
+            inner: err,
+            line_index: self.position.line_index,
+            line: String::from_utf8_lossy(self.bytes).into_owned(),
+        }
+    }
+}
+
+/// The kinds of trace which this version of the format knows about.
+const KNOWN_TRACES: [&str; 8] = [
+    "RegisterCallsite",
+    "Event",
+    "NewSpan",
+    "Enter",
+    "Exit",
+    "Close",
+    "Record",
+    "FollowsFrom",
+];
+
+/// A record with a kind of trace which may be unknown to this version of the format.
+///
+/// A later version of `tracing-rec` may record a new kind of trace without changing the version
+/// of the format, as the records which are already known are unchanged. These records are
+/// skipped, so that a recording made by a later version can still be replayed, without the new
+/// traces.
+#[derive(Deserialize)]
+struct UnknownTraceRecord<'a> {
+    // The meta is still required, so that a corrupt record isn't mistaken for an unknown one.
+    #[serde(borrow, rename = "meta")]
+    _meta: RecordMetaRef<'a>,
+    trace: UnknownTrace,
+}
+
+/// The trace of an [`UnknownTraceRecord`], as its name and its ignored data.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum UnknownTrace {
+    /// A kind of trace without data, which is serialized as only its name.
+    Unit(String),
+    /// A kind of trace with data, which is serialized as a map from its name to its data.
+    Data(HashMap<String, IgnoredAny>),
+}
+
+impl UnknownTrace {
+    fn is_unknown(&self) -> bool {
+        let is_unknown_name = |name: &String| !KNOWN_TRACES.contains(&name.as_str());
+        match self {
+            Self::Unit(name) => is_unknown_name(name),
+            Self::Data(data) => data.len() == 1 && data.keys().all(is_unknown_name),
+        }
+    }
+}
+
+/// Iterator over the lines of a recording.
+///
+/// Lines are borrowed from the recording data, no allocations are made while iterating. The
+/// header of the recording, if it has one, isn't returned as a line.
+#[derive(Clone, Debug)]
+pub(crate) struct Lines<'a> {
+    data: &'a [u8],
+    position: Position,
+    version: u32,
+}
+
+impl<'a> Lines<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self::starting_at(
+            data,
+            Position {
+                byte_offset: 0,
+                line_index: 0,
+            },
+        )
+    }
+
+    /// Iterates over the lines in `data`, starting from `position`.
+    ///
+    /// The version of the format is read from the header at the start of `data`.
+    pub(crate) fn starting_at(data: &'a [u8], position: Position) -> Self {
+        let mut lines = Self {
+            data,
+            position: Position {
+                byte_offset: 0,
+                line_index: 0,
+            },
+            version: UNVERSIONED_FORMAT_VERSION,
+        };
+        let header = lines
+            .next()
+            .and_then(|first_line| Header::from_line(first_line.bytes));
+        match header {
+            Some(header) => {
+                lines.version = header.version;
+                if position.byte_offset != 0 {
+                    lines.position = position;
+                }
+            }
+            None => lines.position = position,
+        }
+
+        lines
+    }
+
+    /// Returns the line at `position` in `data`.
+    pub(crate) fn line_at(data: &'a [u8], position: Position) -> Option<Line<'a>> {
+        Self::starting_at(data, position).next()
+    }
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = Line<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = usize::try_from(self.position.byte_offset).ok()?;
+        let remaining = self.data.get(start..).filter(|rest| !rest.is_empty())?;
+
+        let (bytes, len) = match remaining.iter().position(|b| *b == b'\n') {
+            Some(newline) => (&remaining[..newline], newline + 1),
+            None => (remaining, remaining.len()),
+        };
+        let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+
+        let line = Line {
+            bytes,
+            position: self.position,
+            version: self.version,
+        };
+        self.position = Position {
+            byte_offset: self.position.byte_offset + len as u64,
+            line_index: self.position.line_index + 1,
+        };
+
+        Some(line)
+    }
+}
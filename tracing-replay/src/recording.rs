@@ -1,150 +1,88 @@
-use serde::Deserialize;
-use tracing::field;
-
-#[derive(Debug, Deserialize)]
-pub(crate) struct TraceRecord {
-    pub(crate) meta: RecordMeta,
-    pub(crate) trace: Trace,
-}
-
-#[derive(Debug, Deserialize)]
-pub(crate) struct RecordMeta {
-    pub(crate) timestamp_s: u64,
-    pub(crate) timestamp_subsec_us: u32,
-    pub(crate) thread_id: String,
-    pub(crate) thread_name: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
-pub(crate) enum Trace {
-    RegisterCallsite(Metadata),
-    Event(Event),
-    NewSpan(NewSpan),
-    Enter(SpanId),
-    Exit(SpanId),
-    Close(SpanId),
-    Record(RecordValues),
-    FollowsFrom(FollowsFrom),
-}
+//! The records which make up a recording.
+//!
+//! Each line of a recording produced by `tracing-rec` is a single [`TraceRecord`]. These types are
+//! passed to the hook set with [`Replay::on_dispatched`]. They are defined in the
+//! [`tracing_cassette`] crate, which describes the format of recordings.
+//!
+//! Recordings may use any encoding of a [`Field`] which the format accepts, such as a pair of
+//! the field name and a plain JSON value:
+//!
+//! ```
+//! let recording = concat!(
+//!     r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543496,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Event":{"fields":[["message","I am an info event!"],["answer",42]],"metadata":{"id":4403349456,"name":"event tracing-rec/examples/record-events.rs:8","target":"record_events","level":"Info","module_path":"record_events","file":"tracing-rec/examples/record-events.rs","line":8,"fields":["message","answer"],"kind":"Event"},"parent":"Current"}}}"#,
+//!     "\n",
+//! );
+//!
+//! let mut replay = tracing_replay::Replay::new();
+//! let summary = replay.replay_str(recording).unwrap();
+//! assert_eq!(summary.record_count, 1);
+//! ```
+//!
+//! [`Replay::on_dispatched`]: fn@crate::Replay::on_dispatched
+use std::fmt;
 
-#[derive(Debug, Deserialize)]
-pub(crate) enum Level {
-    Trace,
-    Debug,
-    Info,
-    Warn,
-    Error,
-}
-
-impl From<Level> for tracing::Level {
-    fn from(value: Level) -> Self {
-        match value {
-            Level::Trace => Self::TRACE,
-            Level::Debug => Self::DEBUG,
-            Level::Info => Self::INFO,
-            Level::Warn => Self::WARN,
-            Level::Error => Self::ERROR,
-        }
-    }
-}
-
-#[derive(Debug, Deserialize)]
-pub(crate) enum Kind {
-    Span,
-    Event,
-}
+use tracing::field;
 
-impl From<Kind> for tracing::metadata::Kind {
-    fn from(value: Kind) -> Self {
-        match value {
-            Kind::Event => Self::EVENT,
-            Kind::Span => Self::SPAN,
+pub use tracing_cassette::{
+    Event, Field, FieldValue, FollowsFrom, Kind, Level, Metadata, NewSpan, Parent, RecordMeta,
+    RecordValues, SpanId, Trace, TraceContext, TraceRecord,
+};
+
+pub(crate) use tracing_cassette::{
+    owned_fields, CowStr, EventRef, FieldRef, FieldValueRef, MetadataRef, NewSpanRef,
+    RecordMetaRef, TraceRecordRef, TraceRef,
+};
+
+/// A recorded field value in the form that it is passed to the subscriber.
+pub(crate) enum ReplayValue<'a> {
+    /// A value which was recorded with its `Debug` implementation is replayed as a `Debug` value
+    /// which formats exactly like the original value.
+    Debug(field::DebugValue<RecordedDebug<'a>>),
+    /// A structured value is replayed as a `Debug` value which formats as its JSON text.
+    Json(field::DebugValue<RecordedJson<'a>>),
+    Value(&'a dyn field::Value),
+}
+
+impl<'a> ReplayValue<'a> {
+    pub(crate) fn as_value(&self) -> &dyn field::Value {
+        match self {
+            Self::Debug(val) => val,
+            Self::Json(val) => val,
+            Self::Value(val) => *val,
         }
     }
 }
 
-#[derive(Debug, Deserialize)]
-pub(crate) struct Metadata {
-    pub(crate) id: u64,
-    pub(crate) name: String,
-    pub(crate) target: String,
-    pub(crate) level: Level,
-    pub(crate) module_path: Option<String>,
-    pub(crate) file: Option<String>,
-    pub(crate) line: Option<u32>,
-    pub(crate) fields: Vec<String>,
-    pub(crate) kind: Kind,
-}
-
-#[derive(Debug, Deserialize)]
-pub(crate) enum Parent {
-    /// The new span will be a root span.
-    Root,
-    /// The new span will be rooted in the current span.
-    Current,
-    /// The new span has an explicitly-specified parent.
-    Explicit(u64),
-}
-
-#[derive(Debug, Deserialize)]
-pub(crate) struct Field {
-    pub(crate) name: String,
-    pub(crate) value: FieldValue,
-}
-
-#[derive(Debug, Deserialize)]
-pub(crate) enum FieldValue {
-    Debug(String),
-    F64(f64),
-    I64(i64),
-    U64(u64),
-    I128(i128),
-    U128(u128),
-    Bool(bool),
-    Str(String),
-}
-
-impl<'a> From<&'a FieldValue> for &'a dyn field::Value {
+impl<'a> From<&'a FieldValue> for ReplayValue<'a> {
     fn from(value: &'a FieldValue) -> Self {
         match value {
-            FieldValue::Debug(val) => val as &dyn field::Value,
-            FieldValue::F64(val) => val as &dyn field::Value,
-            FieldValue::I64(val) => val as &dyn field::Value,
-            FieldValue::U64(val) => val as &dyn field::Value,
-            FieldValue::I128(val) => val as &dyn field::Value,
-            FieldValue::U128(val) => val as &dyn field::Value,
-            FieldValue::Bool(val) => val as &dyn field::Value,
-            FieldValue::Str(val) => val as &dyn field::Value,
+            FieldValue::Debug(val) => Self::Debug(field::debug(RecordedDebug(val))),
+            FieldValue::F64(val) => Self::Value(val),
+            FieldValue::I64(val) => Self::Value(val),
+            FieldValue::U64(val) => Self::Value(val),
+            FieldValue::I128(val) => Self::Value(val),
+            FieldValue::U128(val) => Self::Value(val),
+            FieldValue::Bool(val) => Self::Value(val),
+            FieldValue::Str(val) => Self::Value(val),
+            FieldValue::Json(val) => Self::Json(field::debug(RecordedJson(val))),
         }
     }
 }
 
-#[derive(Debug, Deserialize)]
-pub(crate) struct Event {
-    pub(crate) fields: Vec<Field>,
-    pub(crate) metadata: Metadata,
-    pub(crate) parent: Parent,
-}
+/// The recorded `Debug` representation of a value, which is written out as is.
+pub(crate) struct RecordedDebug<'a>(&'a str);
 
-#[derive(Debug, Deserialize)]
-pub(crate) struct NewSpan {
-    pub(crate) id: SpanId,
-    pub(crate) fields: Vec<Field>,
-    pub(crate) metadata: Metadata,
-    pub(crate) parent: Parent,
+impl fmt::Debug for RecordedDebug<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Hash)]
-pub(crate) struct SpanId(u64);
+/// A recorded structured value, which is written out as its JSON text.
+pub(crate) struct RecordedJson<'a>(&'a serde_json::Value);
 
-#[derive(Debug, Deserialize)]
-pub(crate) struct RecordValues {
-    pub(crate) id: SpanId,
-    pub(crate) fields: Vec<Field>,
-}
-
-#[derive(Debug, Deserialize)]
-pub(crate) struct FollowsFrom {
-    pub(crate) cause_id: SpanId,
-    pub(crate) effect_id: SpanId,
+impl fmt::Debug for RecordedJson<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.0, f)
+    }
 }
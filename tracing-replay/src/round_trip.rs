@@ -0,0 +1,137 @@
+use std::fmt;
+
+use tracing_cassette::Difference;
+use tracing_subscriber::layer::SubscriberExt;
+
+use crate::{
+    recording::{Trace, TraceRecord},
+    Replay, ReplayMode,
+};
+
+/// The result of recording a closure and replaying the recording, see [`round_trip`].
+#[non_exhaustive]
+#[derive(Debug)]
+pub struct RoundTrip {
+    /// The records captured while the closure ran.
+    pub recorded: Vec<TraceRecord>,
+    /// The records captured while the recording was replayed.
+    pub replayed: Vec<TraceRecord>,
+    /// The differences between the recorded and the replayed records.
+    pub differences: Vec<Difference>,
+}
+
+impl RoundTrip {
+    /// Returns whether the replay was the same as the recording.
+    #[must_use]
+    pub fn is_faithful(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+impl fmt::Display for RoundTrip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_faithful() {
+            return write!(f, "replay matches the recording");
+        }
+
+        write!(f, "replay differs from the recording:")?;
+        for difference in &self.differences {
+            write!(f, "\n - {difference}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Records the traces of `f`, replays them, and compares the replay with the recording.
+///
+/// The closure is run with a subscriber which records into memory with a `tracing-rec` layer as
+/// the default on the current thread, so only the traces of the current thread are recorded,
+/// unless `f` sets the default on the threads that it spawns too. The recording is then replayed
+/// in [`ReplayMode::Deterministic`] into a second subscriber which records into memory, and the
+/// two recordings are compared with [`tracing_cassette::diff`]. The comparison ignores when the
+/// traces were recorded, the Ids they were given, and which thread they were recorded on.
+///
+/// Callsites are compared through the spans and events that use them. The callsites which were
+/// registered when each subscriber was set as the default, but weren't used, aren't compared.
+///
+/// This checks that instrumentation survives the round trip through a recording, use
+/// [`assert_round_trip`] to do so in a test.
+///
+/// # Panics
+///
+/// Panics if the recording can't be replayed, or if a dispatcher thread panics while replaying
+/// it.
+///
+/// # Examples
+///
+/// ```
+/// let round_trip = tracing_replay::round_trip(|| {
+///     let span = tracing::info_span!("request", user_id = 7, status = tracing::field::Empty);
+///     let _guard = span.enter();
+///     tracing::warn!(retries = 2, "slow response");
+///     span.record("status", 200);
+/// });
+///
+/// assert!(round_trip.is_faithful(), "{round_trip}");
+/// assert_eq!(round_trip.recorded.len(), round_trip.replayed.len());
+/// ```
+pub fn round_trip<F: FnOnce()>(f: F) -> RoundTrip {
+    let recording = tracing_rec::record_scope(f);
+    let recorded = recording.to_bytes();
+
+    let (layer, replayed) = tracing_rec::rec_memory_layer();
+    let mut replay = Replay::new()
+        .with_mode(ReplayMode::Deterministic)
+        .with_dispatch_targets([tracing::Dispatch::new(
+            tracing_subscriber::registry().with(layer),
+        )]);
+    if let Err(err) = replay.replay_include(&recorded) {
+        panic!("replaying the recording failed: {err}");
+    }
+    if let Err(err) = replay.close() {
+        panic!("{err}");
+    }
+    // Spans which are still held by the replay are only closed once it is dropped.
+    drop(replay);
+
+    let recorded = used_records(recording.records());
+    let replayed = used_records(replayed.records());
+    let differences = tracing_cassette::diff(&recorded, &replayed);
+    RoundTrip {
+        recorded,
+        replayed,
+        differences,
+    }
+}
+
+/// Asserts that the traces of `f` are the same when they are recorded and replayed.
+///
+/// See [`round_trip`] for how the traces are recorded, replayed, and compared.
+///
+/// # Panics
+///
+/// Panics, listing the differences, if the replay differs from the recording. Also panics if the
+/// recording can't be replayed.
+///
+/// # Examples
+///
+/// ```
+/// tracing_replay::assert_round_trip(|| {
+///     let span = tracing::debug_span!("outer");
+///     span.in_scope(|| {
+///         tracing::error!(code = 500_u64, ok = false, "it broke");
+///     });
+/// });
+/// ```
+pub fn assert_round_trip<F: FnOnce()>(f: F) {
+    let round_trip = round_trip(f);
+    assert!(round_trip.is_faithful(), "{round_trip}");
+}
+
+/// Removes the registered callsites, keeping the records of the spans and events.
+fn used_records(records: Vec<TraceRecord>) -> Vec<TraceRecord> {
+    records
+        .into_iter()
+        .filter(|record| !matches!(record.trace, Trace::RegisterCallsite(_)))
+        .collect()
+}
@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use tracing_cassette::{Header, UNVERSIONED_FORMAT_VERSION};
+
+use crate::{index::Position, reader::Line, Replay, ReplayFileError, ReplaySummary};
+
+/// Replays a recording which is fed in incrementally.
+///
+/// A sink is created with [`Replay::sink`]. Recording data can then be pushed into the sink one
+/// line at a time with [`push_line`], or in arbitrarily sized chunks with [`push_bytes`]. Each
+/// record is replayed as soon as it has been pushed in its entirety. This allows a recording to
+/// be replayed as it is received over a transport such as a message queue or a websocket.
+///
+/// Once all the data has been pushed, call [`finish`] to get the summary of the replay. The
+/// records are replayed in the same way as by [`Replay::replay_file`], except that a sidecar
+/// index is never used.
+///
+/// [`push_line`]: fn@Self::push_line
+/// [`push_bytes`]: fn@Self::push_bytes
+/// [`finish`]: fn@Self::finish
+/// [`Replay::sink`]: fn@crate::Replay::sink
+/// [`Replay::replay_file`]: fn@crate::Replay::replay_file
+#[derive(Debug)]
+pub struct ReplaySink<'r> {
+    replay: &'r mut Replay,
+    /// Data from [`push_bytes`] which doesn't yet make up a whole line.
+    ///
+    /// [`push_bytes`]: fn@Self::push_bytes
+    partial_line: Vec<u8>,
+    position: Position,
+    /// The version of the format the recording was written in, read from its header.
+    version: u32,
+    recording_start: Option<Duration>,
+    summary: ReplaySummary,
+    /// Whether the end of the time range has been reached.
+    reached_end: bool,
+}
+
+impl<'r> ReplaySink<'r> {
+    pub(crate) fn new(replay: &'r mut Replay) -> Self {
+        Self {
+            replay,
+            partial_line: Vec::new(),
+            position: Position {
+                byte_offset: 0,
+                line_index: 0,
+            },
+            version: UNVERSIONED_FORMAT_VERSION,
+            recording_start: None,
+            summary: ReplaySummary::new(),
+            reached_end: false,
+        }
+    }
+
+    /// Replays a single line of a recording.
+    ///
+    /// The line may end with a newline. It must not be pushed in the middle of a line started
+    /// with [`push_bytes`].
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if the line cannot be deserialized into a trace record,
+    /// or if it creates a span whose span::Id collides with an open span while the
+    /// [`SpanIdCollisionPolicy::Error`] policy is in use.
+    ///
+    /// [`push_bytes`]: fn@Self::push_bytes
+    /// [`SpanIdCollisionPolicy::Error`]: crate::SpanIdCollisionPolicy::Error
+    pub fn push_line(&mut self, line: &str) -> Result<(), ReplayFileError> {
+        let bytes = line.strip_suffix('\n').unwrap_or(line).as_bytes();
+        self.replay_line(bytes, line.len())
+    }
+
+    /// Replays the lines of a recording contained in `bytes`.
+    ///
+    /// The bytes don't need to end on a line boundary, the final incomplete line is held back
+    /// until the rest of it is pushed.
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if any of the complete lines cannot be deserialized into
+    /// a trace record, or if one of them creates a span whose span::Id collides with an open span
+    /// while the [`SpanIdCollisionPolicy::Error`] policy is in use.
+    ///
+    /// [`SpanIdCollisionPolicy::Error`]: crate::SpanIdCollisionPolicy::Error
+    pub fn push_bytes(&mut self, bytes: &[u8]) -> Result<(), ReplayFileError> {
+        let mut rest = bytes;
+        while let Some(newline) = rest.iter().position(|b| *b == b'\n') {
+            let (line, next) = rest.split_at(newline + 1);
+            rest = next;
+            if self.partial_line.is_empty() {
+                self.replay_line(&line[..newline], line.len())?;
+            } else {
+                let mut partial_line = std::mem::take(&mut self.partial_line);
+                partial_line.extend_from_slice(&line[..newline]);
+                self.replay_line(&partial_line, partial_line.len() + 1)?;
+            }
+        }
+        self.partial_line.extend_from_slice(rest);
+
+        Ok(())
+    }
+
+    /// Finishes replaying the data which has been pushed and returns the summary of the replay.
+    ///
+    /// If the data pushed with [`push_bytes`] doesn't end with a newline, the remaining data is
+    /// replayed as the final line of the recording. As with [`Replay::replay_file`], a final
+    /// record which ends part way through is skipped and reported in the summary.
+    ///
+    /// The replay isn't closed, work may still be ongoing on the replay threads. Call
+    /// [`Replay::close`] to wait for it to complete.
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if the final line cannot be deserialized into a trace
+    /// record for any reason other than being truncated.
+    ///
+    /// [`push_bytes`]: fn@Self::push_bytes
+    /// [`Replay::replay_file`]: fn@crate::Replay::replay_file
+    /// [`Replay::close`]: fn@crate::Replay::close
+    pub fn finish(mut self) -> Result<ReplaySummary, ReplayFileError> {
+        let partial_line = std::mem::take(&mut self.partial_line);
+        if !partial_line.is_empty() {
+            if let Err(err) = self.replay_line(&partial_line, partial_line.len()) {
+                match err.truncated_record_line_index() {
+                    Some(line_index) => self.summary.truncated_final_record = Some(line_index),
+                    None => return Err(err),
+                }
+            }
+        }
+
+        self.replay.complete_summary(&mut self.summary);
+        Ok(self.summary)
+    }
+
+    /// Replays a single line, which took up `len` bytes of the recording including the newline.
+    fn replay_line(&mut self, bytes: &[u8], len: usize) -> Result<(), ReplayFileError> {
+        let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+        let line = Line {
+            bytes,
+            position: self.position,
+            version: self.version,
+        };
+        self.position = Position {
+            byte_offset: self.position.byte_offset + len as u64,
+            line_index: self.position.line_index + 1,
+        };
+        if line.position.line_index == 0 {
+            if let Some(header) = Header::from_line(bytes) {
+                self.version = header.version;
+                self.summary.set_header(Some(header));
+                return Ok(());
+            }
+        }
+        if self.reached_end {
+            return Ok(());
+        }
+
+        let Some(trace_record) = line.parse()? else {
+            self.summary.unknown_records += 1;
+            return Ok(());
+        };
+        self.reached_end = self
+            .replay
+            .replay_record(trace_record, &mut self.recording_start, &mut self.summary)?
+            .is_break();
+
+        Ok(())
+    }
+}
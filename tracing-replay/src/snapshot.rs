@@ -0,0 +1,149 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::{Replay, ReplayMode};
+
+/// A writer which captures what a subscriber writes in memory, see [`render_with`].
+///
+/// Clones of the writer share the same buffer, so it can be handed to a subscriber with
+/// `with_writer` as a [`MakeWriter`].
+#[derive(Clone, Debug, Default)]
+pub struct SnapshotWriter {
+    buffer: Arc<Mutex<Vec<u8>>>,
+}
+
+impl SnapshotWriter {
+    /// Creates a writer with an empty buffer.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns what has been written so far, with any invalid UTF-8 replaced.
+    #[must_use]
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.buffer.lock().expect("lock poisoned")).into_owned()
+    }
+}
+
+impl io::Write for SnapshotWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer
+            .lock()
+            .expect("lock poisoned")
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for SnapshotWriter {
+    type Writer = Self;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Replays `recording` into a `fmt` subscriber and returns what it wrote.
+///
+/// The subscriber writes all levels, without timestamps or ANSI colors, so that the output only
+/// depends on the recording. Use [`render_with`] to render the recording with a formatting
+/// pipeline of your own.
+///
+/// # Panics
+///
+/// Panics if the recording can't be replayed, or if a dispatcher thread panics while replaying
+/// it.
+///
+/// # Examples
+///
+/// ```
+/// let output = tracing_replay::render_fmt(include_bytes!("../../sample-data/delay.tracing"));
+///
+/// assert_eq!(
+///     output,
+///     " INFO record_delay: event before delay\n INFO record_delay: event after delay\n",
+/// );
+/// ```
+#[must_use]
+pub fn render_fmt(recording: &[u8]) -> String {
+    render_with(recording, |writer| {
+        tracing::Dispatch::new(
+            tracing_subscriber::fmt()
+                .with_max_level(tracing::Level::TRACE)
+                .with_writer(writer)
+                .with_ansi(false)
+                .without_time()
+                .finish(),
+        )
+    })
+}
+
+/// Replays `recording` into the subscriber returned by `make_dispatch` and returns what the
+/// subscriber wrote to the [`SnapshotWriter`] that it was given.
+///
+/// This renders a known trace with the formatting pipeline under test, so that the output can
+/// be checked against a snapshot. The recording is replayed in [`ReplayMode::Deterministic`],
+/// so that the traces reach the subscriber in the order they were recorded, even when they were
+/// recorded on different threads. The replay is closed and dropped before the output is
+/// returned, so spans which were still open at the end of the recording have been closed.
+///
+/// The output only depends on the recording if the subscriber doesn't write anything which
+/// depends on the replay itself. Timestamps are the time of the replay rather than of the
+/// recording, so they should be disabled, and thread Ids and names are those of the threads
+/// which dispatch the replay.
+///
+/// # Panics
+///
+/// Panics if the recording can't be replayed, or if a dispatcher thread panics while replaying
+/// it.
+///
+/// # Examples
+///
+/// ```
+/// use tracing_subscriber::fmt::format::FmtSpan;
+///
+/// let output = tracing_replay::render_with(
+///     include_bytes!("../../sample-data/events.tracing"),
+///     |writer| {
+///         tracing::Dispatch::new(
+///             tracing_subscriber::fmt()
+///                 .compact()
+///                 .with_writer(writer)
+///                 .with_ansi(false)
+///                 .with_span_events(FmtSpan::CLOSE)
+///                 .without_time()
+///                 .finish(),
+///         )
+///     },
+/// );
+///
+/// assert!(output.contains(" INFO span: record_events: close\n"));
+/// ```
+pub fn render_with<F>(recording: &[u8], make_dispatch: F) -> String
+where
+    F: FnOnce(SnapshotWriter) -> tracing::Dispatch,
+{
+    let writer = SnapshotWriter::new();
+    let mut replay = Replay::new()
+        .with_mode(ReplayMode::Deterministic)
+        .with_dispatch_targets([make_dispatch(writer.clone())]);
+    if let Err(err) = replay.replay_include(recording) {
+        panic!("replaying the recording failed: {err}");
+    }
+    if let Err(err) = replay.close() {
+        panic!("{err}");
+    }
+    // Spans which are still held by the replay are only closed once it is dropped.
+    drop(replay);
+
+    writer.contents()
+}
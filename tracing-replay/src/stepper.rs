@@ -0,0 +1,136 @@
+use std::{iter::Peekable, time::Duration};
+
+use crate::{
+    reader::{self, Lines},
+    recording::TraceRecord,
+    Replay, ReplayFileError, ReplayMode, ReplaySummary,
+};
+
+/// Replays a recording one record at a time under the caller's control.
+///
+/// A stepper is created with [`Replay::stepper`]. Each call to [`step`] dispatches the next
+/// record of the recording and only returns once the subscriber has received it, so assertions
+/// about the state of the subscriber can be made between records. [`step_n`] dispatches several
+/// records at once.
+///
+/// Records are replayed in [`ReplayMode::Deterministic`], whatever the mode the replay was
+/// configured with, and otherwise with the same configuration as [`Replay::replay_file`]. Records
+/// which are excluded from the replay, for example by [`Replay::with_threads`], are skipped over
+/// and don't count as a step.
+///
+/// Once done, call [`finish`] to get the summary of the replay.
+///
+/// [`step`]: fn@Self::step
+/// [`step_n`]: fn@Self::step_n
+/// [`finish`]: fn@Self::finish
+/// [`Replay::stepper`]: fn@crate::Replay::stepper
+/// [`Replay::replay_file`]: fn@crate::Replay::replay_file
+/// [`Replay::with_threads`]: fn@crate::Replay::with_threads
+#[derive(Debug)]
+pub struct ReplayStepper<'r> {
+    replay: &'r mut Replay,
+    lines: Peekable<Lines<'r>>,
+    recording_start: Option<Duration>,
+    summary: ReplaySummary,
+    /// Whether the end of the recording or of the time range has been reached.
+    reached_end: bool,
+}
+
+impl<'r> ReplayStepper<'r> {
+    pub(crate) fn new(replay: &'r mut Replay, recording: &'r [u8]) -> Self {
+        replay.mode = ReplayMode::Deterministic;
+        let mut summary = ReplaySummary::new();
+        summary.set_header(reader::header(recording));
+        Self {
+            replay,
+            lines: Lines::new(recording).peekable(),
+            recording_start: None,
+            summary,
+            reached_end: false,
+        }
+    }
+
+    /// Dispatches the next record of the recording.
+    ///
+    /// Returns the record which was dispatched, or `None` if the end of the recording has been
+    /// reached. A final record which ends part way through is skipped and reported in the
+    /// summary, as with [`Replay::replay_file`].
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if the next record cannot be deserialized, or if it
+    /// creates a span whose span::Id collides with an open span while the
+    /// [`SpanIdCollisionPolicy::Error`] policy is in use.
+    ///
+    /// [`Replay::replay_file`]: fn@crate::Replay::replay_file
+    /// [`SpanIdCollisionPolicy::Error`]: crate::SpanIdCollisionPolicy::Error
+    pub fn step(&mut self) -> Result<Option<TraceRecord>, ReplayFileError> {
+        while !self.reached_end {
+            let Some(line) = self.lines.next() else {
+                self.reached_end = true;
+                break;
+            };
+            let trace_record = match line.parse() {
+                Ok(Some(trace_record)) => trace_record,
+                Ok(None) => {
+                    self.summary.unknown_records += 1;
+                    continue;
+                }
+                Err(err) => match err.truncated_record_line_index() {
+                    // Only the very last record may be truncated, anywhere else it means that the
+                    // recording is corrupt.
+                    Some(line_index) if self.lines.peek().is_none() => {
+                        self.summary.truncated_final_record = Some(line_index);
+                        self.reached_end = true;
+                        break;
+                    }
+                    _ => return Err(err),
+                },
+            };
+
+            let record = TraceRecord::from(trace_record.clone());
+            let record_count = self.summary.record_count;
+            self.reached_end = self
+                .replay
+                .replay_record(trace_record, &mut self.recording_start, &mut self.summary)?
+                .is_break();
+            if self.summary.record_count > record_count {
+                return Ok(Some(record));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Dispatches up to `n` records of the recording.
+    ///
+    /// Returns the number of records which were dispatched, which is less than `n` if the end of
+    /// the recording was reached.
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error under the same conditions as [`step`].
+    ///
+    /// [`step`]: fn@Self::step
+    pub fn step_n(&mut self, n: usize) -> Result<usize, ReplayFileError> {
+        for dispatched in 0..n {
+            if self.step()?.is_none() {
+                return Ok(dispatched);
+            }
+        }
+
+        Ok(n)
+    }
+
+    /// Finishes stepping through the recording and returns the summary of the replay.
+    ///
+    /// Records which haven't been stepped through yet are not replayed. Call [`Replay::close`] to
+    /// shut down the replay threads.
+    ///
+    /// [`Replay::close`]: fn@crate::Replay::close
+    pub fn finish(self) -> ReplaySummary {
+        let mut summary = self.summary;
+        self.replay.complete_summary(&mut summary);
+        summary
+    }
+}
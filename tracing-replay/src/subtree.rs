@@ -0,0 +1,149 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::recording::{self, Parent, TraceRecordRef, TraceRef};
+
+/// Selects the root span of a subtree to replay.
+///
+/// See [`Replay::with_span_subtree`] for details.
+///
+/// [`Replay::with_span_subtree`]: fn@crate::Replay::with_span_subtree
+#[non_exhaustive]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SpanSelector {
+    /// Selects the span with the given recorded span Id.
+    ///
+    /// If span Ids were reused during the recording, the first span with this Id is selected.
+    Id(u64),
+    /// Selects a span by name.
+    ///
+    /// The `occurrence` is the index of the span amongst all the spans with the same name in the
+    /// recording, starting at 0 for the first span with that name.
+    Name {
+        /// The name of the span.
+        name: String,
+        /// Which of the spans with this name to select.
+        occurrence: usize,
+    },
+}
+
+impl SpanSelector {
+    /// Selects the first span with the given name.
+    pub fn name(name: impl Into<String>) -> Self {
+        Self::Name {
+            name: name.into(),
+            occurrence: 0,
+        }
+    }
+}
+
+/// Filters the records in a recording down to a single span subtree.
+///
+/// The filter is applied to records in the order they were recorded and keeps track of which
+/// spans are part of the subtree. The root of the subtree is replayed as a root span.
+#[derive(Debug)]
+pub(crate) struct SubtreeFilter {
+    selector: SpanSelector,
+    state: SubtreeState,
+    /// The number of spans seen so far which match the selector's name.
+    name_occurrences: usize,
+    /// The spans which are part of the subtree and haven't been closed yet.
+    selected: HashSet<recording::SpanId>,
+    /// The stack of entered spans for each recorded thread, used to determine the contextual
+    /// parent of new spans and events.
+    entered: HashMap<String, Vec<recording::SpanId>>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum SubtreeState {
+    Searching,
+    Replaying,
+    Complete,
+}
+
+impl SubtreeFilter {
+    pub(crate) fn new(selector: SpanSelector) -> Self {
+        Self {
+            selector,
+            state: SubtreeState::Searching,
+            name_occurrences: 0,
+            selected: HashSet::new(),
+            entered: HashMap::new(),
+        }
+    }
+
+    /// Returns whether the record is part of the selected subtree.
+    ///
+    /// The record may be modified so that the root of the subtree is replayed as a root span.
+    pub(crate) fn filter(&mut self, record: &mut TraceRecordRef<'_>) -> bool {
+        let thread_id = record.meta.thread_id.as_str();
+        match &mut record.trace {
+            TraceRef::RegisterCallsite(_) => false,
+            TraceRef::NewSpan(new_span) => {
+                if self.state == SubtreeState::Searching && self.is_root(new_span) {
+                    self.state = SubtreeState::Replaying;
+                    new_span.parent = Parent::Root;
+                    self.selected.insert(new_span.id);
+                    true
+                } else if self.has_selected_parent(thread_id, &new_span.parent) {
+                    self.selected.insert(new_span.id);
+                    true
+                } else {
+                    false
+                }
+            }
+            TraceRef::Event(event) => self.has_selected_parent(thread_id, &event.parent),
+            TraceRef::Enter(span_id) => {
+                match self.entered.get_mut(thread_id) {
+                    Some(stack) => stack.push(*span_id),
+                    None => {
+                        self.entered.insert(thread_id.to_owned(), vec![*span_id]);
+                    }
+                }
+                self.selected.contains(span_id)
+            }
+            TraceRef::Exit(span_id) => {
+                if let Some(stack) = self.entered.get_mut(thread_id) {
+                    if let Some(idx) = stack.iter().rposition(|entered| entered == span_id) {
+                        stack.remove(idx);
+                    }
+                }
+                self.selected.contains(span_id)
+            }
+            TraceRef::Close(span_id) => {
+                let selected = self.selected.remove(span_id);
+                if selected && self.selected.is_empty() {
+                    self.state = SubtreeState::Complete;
+                }
+                selected
+            }
+            TraceRef::Record(record_values) => self.selected.contains(&record_values.id),
+            TraceRef::FollowsFrom(follows_from) => {
+                self.selected.contains(&follows_from.cause_id)
+                    && self.selected.contains(&follows_from.effect_id)
+            }
+        }
+    }
+
+    fn is_root(&mut self, new_span: &recording::NewSpanRef<'_>) -> bool {
+        match &self.selector {
+            SpanSelector::Id(id) => new_span.id == recording::SpanId::from(*id),
+            SpanSelector::Name { name, occurrence } => {
+                if new_span.metadata.name.as_str() != name {
+                    return false;
+                }
+                self.name_occurrences += 1;
+                self.name_occurrences - 1 == *occurrence
+            }
+        }
+    }
+
+    fn has_selected_parent(&self, thread_id: &str, parent: &Parent) -> bool {
+        let parent_id = match parent {
+            Parent::Root => None,
+            Parent::Current => self.entered.get(thread_id).and_then(|stack| stack.last()),
+            Parent::Explicit(span_id) => Some(span_id),
+        };
+
+        parent_id.is_some_and(|parent_id| self.selected.contains(parent_id))
+    }
+}
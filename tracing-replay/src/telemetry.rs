@@ -0,0 +1,128 @@
+//! Metrics about the replay itself.
+//!
+//! With the `metrics` feature enabled, the replay emits metrics through the [`metrics`] facade.
+//! Without it, all the methods here do nothing.
+//!
+//! [`metrics`]: https://docs.rs/metrics/latest/metrics/
+use std::time::Duration;
+
+/// Metrics recorded by the replay coordinator.
+#[derive(Debug)]
+pub(crate) struct ReplayMetrics {
+    #[cfg(feature = "metrics")]
+    records_read: metrics::Counter,
+    #[cfg(feature = "metrics")]
+    records_filtered: metrics::Counter,
+    #[cfg(feature = "metrics")]
+    records_unmapped: metrics::Counter,
+}
+
+impl ReplayMetrics {
+    pub(crate) fn new() -> Self {
+        Self {
+            #[cfg(feature = "metrics")]
+            records_read: metrics::counter!("tracing_replay_records_read"),
+            #[cfg(feature = "metrics")]
+            records_filtered: metrics::counter!(
+                "tracing_replay_records_skipped",
+                "reason" => "filtered",
+            ),
+            #[cfg(feature = "metrics")]
+            records_unmapped: metrics::counter!(
+                "tracing_replay_records_skipped",
+                "reason" => "unmapped_span",
+            ),
+        }
+    }
+
+    /// A record has been read from the recording.
+    pub(crate) fn record_read(&self) {
+        #[cfg(feature = "metrics")]
+        self.records_read.increment(1);
+    }
+
+    /// A record has been filtered out of the replay.
+    pub(crate) fn record_filtered(&self) {
+        #[cfg(feature = "metrics")]
+        self.records_filtered.increment(1);
+    }
+
+    /// A record has been skipped because it refers to a span which wasn't replayed.
+    pub(crate) fn record_unmapped(&self) {
+        #[cfg(feature = "metrics")]
+        self.records_unmapped.increment(1);
+    }
+}
+
+/// Metrics recorded for a single dispatcher thread, labelled with the recorded thread Id.
+#[derive(Clone, Debug)]
+pub(crate) struct ThreadMetrics {
+    #[cfg(feature = "metrics")]
+    records_dispatched: metrics::Counter,
+    #[cfg(feature = "metrics")]
+    records_skipped: metrics::Counter,
+    #[cfg(feature = "metrics")]
+    queue_depth: metrics::Gauge,
+    #[cfg(feature = "metrics")]
+    dispatch_lag: metrics::Histogram,
+}
+
+impl ThreadMetrics {
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    pub(crate) fn new(rec_thread_id: &str) -> Self {
+        Self {
+            #[cfg(feature = "metrics")]
+            records_dispatched: metrics::counter!(
+                "tracing_replay_records_dispatched",
+                "thread" => rec_thread_id.to_owned(),
+            ),
+            #[cfg(feature = "metrics")]
+            records_skipped: metrics::counter!(
+                "tracing_replay_records_skipped",
+                "reason" => "unmapped_span",
+                "thread" => rec_thread_id.to_owned(),
+            ),
+            #[cfg(feature = "metrics")]
+            queue_depth: metrics::gauge!(
+                "tracing_replay_queue_depth",
+                "thread" => rec_thread_id.to_owned(),
+            ),
+            #[cfg(feature = "metrics")]
+            dispatch_lag: metrics::histogram!(
+                "tracing_replay_dispatch_lag_seconds",
+                "thread" => rec_thread_id.to_owned(),
+            ),
+        }
+    }
+
+    /// A record has been dispatched.
+    pub(crate) fn record_dispatched(&self) {
+        #[cfg(feature = "metrics")]
+        self.records_dispatched.increment(1);
+    }
+
+    /// A record has been skipped because it refers to a span which wasn't replayed.
+    pub(crate) fn record_skipped(&self) {
+        #[cfg(feature = "metrics")]
+        self.records_skipped.increment(1);
+    }
+
+    /// A record has been sent to the dispatcher thread.
+    pub(crate) fn queued(&self) {
+        #[cfg(feature = "metrics")]
+        self.queue_depth.increment(1.0);
+    }
+
+    /// A record has been received by the dispatcher thread.
+    pub(crate) fn dequeued(&self) {
+        #[cfg(feature = "metrics")]
+        self.queue_depth.decrement(1.0);
+    }
+
+    /// A record was dispatched `lag` after its scheduled time.
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    pub(crate) fn record_lag(&self, lag: Duration) {
+        #[cfg(feature = "metrics")]
+        self.dispatch_lag.record(lag);
+    }
+}
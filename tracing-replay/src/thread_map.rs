@@ -0,0 +1,63 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use crate::recording::{CowStr, RecordMetaRef};
+
+/// The identity of a recorded thread, which can be rewritten before its traces are dispatched,
+/// see [`Replay::with_thread_mapping`].
+///
+/// [`Replay::with_thread_mapping`]: fn@crate::Replay::with_thread_mapping
+#[non_exhaustive]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct RecordedThread {
+    /// The recorded thread Id, for example `ThreadId(1)`.
+    ///
+    /// Each thread Id is replayed on its own thread, so recorded threads which are given the same
+    /// Id are replayed as one thread.
+    pub id: String,
+    /// The recorded thread name, if the thread had one.
+    pub name: Option<String>,
+}
+
+/// Rewrites the identities of recorded threads with a hook, calling the hook once for each
+/// recorded identity.
+#[derive(Clone)]
+pub(crate) struct ThreadMapping {
+    hook: Arc<dyn Fn(&mut RecordedThread) + Send + Sync>,
+    /// The rewritten identity of each recorded identity.
+    mapped: HashMap<RecordedThread, RecordedThread>,
+}
+
+impl ThreadMapping {
+    pub(crate) fn new(hook: Arc<dyn Fn(&mut RecordedThread) + Send + Sync>) -> Self {
+        Self {
+            hook,
+            mapped: HashMap::new(),
+        }
+    }
+
+    /// Rewrites the thread identity of a record.
+    pub(crate) fn map(&mut self, meta: &mut RecordMetaRef<'_>) {
+        let recorded = RecordedThread {
+            id: meta.thread_id.as_str().to_owned(),
+            name: meta
+                .thread_name
+                .as_ref()
+                .map(|name| name.as_str().to_owned()),
+        };
+        let mapped = self.mapped.entry(recorded).or_insert_with_key(|recorded| {
+            let mut mapped = recorded.clone();
+            (self.hook)(&mut mapped);
+            mapped
+        });
+        meta.thread_id = CowStr::from(mapped.id.clone());
+        meta.thread_name = mapped.name.clone().map(CowStr::from);
+    }
+}
+
+impl fmt::Debug for ThreadMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ThreadMapping")
+            .field("mapped", &self.mapped)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,149 @@
+use std::{collections::HashMap, time::Duration};
+
+use crate::recording::{
+    CowStr, FieldRef, FieldValueRef, Kind, Level, MetadataRef, NewSpanRef, Parent, RecordMetaRef,
+    SpanId, TraceRecordRef, TraceRef,
+};
+
+/// The target of the synthetic spans which represent recorded threads.
+pub(crate) const THREAD_SPAN_TARGET: &str = "tracing_replay::thread";
+
+/// The synthetic spans which represent the lifetimes of recorded threads, see
+/// [`Replay::with_thread_spans`].
+///
+/// Synthetic spans and their callsites are given Ids counting down from [`u64::MAX`], so that
+/// they don't collide with the Ids in the recording.
+///
+/// [`Replay::with_thread_spans`]: fn@crate::Replay::with_thread_spans
+#[derive(Debug, Default)]
+pub(crate) struct ThreadSpans {
+    /// The open thread spans of each recorded thread.
+    threads: HashMap<String, ThreadSpan>,
+    /// The recorded threads, in the order that their thread spans were opened.
+    order: Vec<String>,
+    next_offset: u64,
+}
+
+/// The synthetic span of a single recorded thread.
+#[derive(Debug)]
+struct ThreadSpan {
+    id: u64,
+    thread_name: Option<String>,
+    /// The time of the latest record on the thread.
+    latest: Duration,
+}
+
+impl ThreadSpans {
+    /// Tracks a record on a recorded thread, returning the records which open the thread span if
+    /// this is the first record on the thread.
+    pub(crate) fn track(&mut self, meta: &RecordMetaRef<'_>) -> Vec<TraceRecordRef<'static>> {
+        let timestamp = meta.timestamp();
+        if let Some(thread_span) = self.threads.get_mut(meta.thread_id.as_str()) {
+            thread_span.latest = thread_span.latest.max(timestamp);
+            return Vec::new();
+        }
+
+        let id = u64::MAX - self.next_offset;
+        self.next_offset += 1;
+        let thread_id = meta.thread_id.as_str().to_owned();
+        let thread_name = meta
+            .thread_name
+            .as_ref()
+            .map(|thread_name| thread_name.as_str().to_owned());
+        let thread_span = ThreadSpan {
+            id,
+            thread_name,
+            latest: timestamp,
+        };
+
+        let metadata = thread_span.metadata(&thread_id);
+        let mut fields = vec![FieldRef {
+            name: "thread.id".into(),
+            value: FieldValueRef::Str(CowStr::from(thread_id.clone())),
+        }];
+        if let Some(thread_name) = &thread_span.thread_name {
+            fields.push(FieldRef {
+                name: "thread.name".into(),
+                value: FieldValueRef::Str(CowStr::from(thread_name.clone())),
+            });
+        }
+        let records = [
+            TraceRef::RegisterCallsite(metadata.clone()),
+            TraceRef::NewSpan(NewSpanRef {
+                id: SpanId::from(id),
+                fields,
+                metadata,
+                parent: Parent::Root,
+                trace_context: None,
+            }),
+            TraceRef::Enter(SpanId::from(id)),
+        ]
+        .into_iter()
+        .map(|trace| thread_span.record(&thread_id, timestamp, trace))
+        .collect();
+
+        self.threads.insert(thread_id.clone(), thread_span);
+        self.order.push(thread_id);
+        records
+    }
+
+    /// Returns the records which exit and close all the open thread spans, each at the time of
+    /// the latest record on its thread.
+    pub(crate) fn finish(&mut self) -> Vec<TraceRecordRef<'static>> {
+        let mut records = Vec::new();
+        for thread_id in self.order.drain(..) {
+            let Some(thread_span) = self.threads.remove(&thread_id) else {
+                continue;
+            };
+            let id = SpanId::from(thread_span.id);
+            for trace in [TraceRef::Exit(id), TraceRef::Close(id)] {
+                records.push(thread_span.record(&thread_id, thread_span.latest, trace));
+            }
+        }
+        records
+    }
+}
+
+impl ThreadSpan {
+    /// Returns the metadata of the thread span's callsite, which is named after the thread.
+    fn metadata(&self, thread_id: &str) -> MetadataRef<'static> {
+        let name = self.thread_name.as_deref().unwrap_or(thread_id);
+        let mut fields = vec![CowStr::from("thread.id".to_owned())];
+        if self.thread_name.is_some() {
+            fields.push(CowStr::from("thread.name".to_owned()));
+        }
+        MetadataRef {
+            id: self.id,
+            name: CowStr::from(name.to_owned()),
+            target: CowStr::from(THREAD_SPAN_TARGET.to_owned()),
+            level: Level::Info,
+            module_path: None,
+            file: None,
+            line: None,
+            fields,
+            kind: Kind::Span,
+        }
+    }
+
+    fn record(
+        &self,
+        thread_id: &str,
+        timestamp: Duration,
+        trace: TraceRef<'static>,
+    ) -> TraceRecordRef<'static> {
+        TraceRecordRef {
+            meta: RecordMetaRef {
+                timestamp_s: timestamp.as_secs(),
+                timestamp_subsec_us: timestamp.subsec_micros(),
+                thread_id: CowStr::from(thread_id.to_owned()),
+                thread_name: self
+                    .thread_name
+                    .as_ref()
+                    .map(|thread_name| CowStr::from(thread_name.clone())),
+            },
+            trace,
+            final_fields: None,
+            sequence: None,
+        }
+    }
+}
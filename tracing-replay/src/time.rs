@@ -0,0 +1,91 @@
+//! The monotonic time used to pace replays.
+//!
+//! On most targets this is [`std::time::Instant`]. On `wasm32-unknown-unknown` the standard
+//! library has no monotonic clock, so an [`Instant`] which only moves forward when a replay has
+//! slept is used instead, see [`slept_until`].
+
+use std::time::Duration;
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) use std::time::Instant;
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub(crate) use self::virtual_time::Instant;
+
+/// The shortest time worth sleeping for.
+///
+/// Virtual time doesn't pass while records are being replayed, so records which were recorded
+/// close together would each need their own sleep. Timers in a browser have a resolution of a
+/// few milliseconds at best, so shorter delays are skipped instead.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) const MIN_SLEEP: Duration = Duration::ZERO;
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub(crate) const MIN_SLEEP: Duration = Duration::from_millis(1);
+
+/// Records that a replay has slept until `instant`.
+///
+/// This is only needed where time doesn't pass on its own, on all other targets it does nothing.
+pub(crate) fn slept_until(instant: Instant) {
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    virtual_time::advance_to(instant);
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    let _ = instant;
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod virtual_time {
+    use std::{
+        ops::{Add, Sub},
+        sync::Mutex,
+        time::Duration,
+    };
+
+    /// The time that has passed according to the replays which have slept.
+    static ELAPSED: Mutex<Duration> = Mutex::new(Duration::ZERO);
+
+    /// A point in virtual time.
+    #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+    pub(crate) struct Instant(Duration);
+
+    impl Instant {
+        pub(crate) fn now() -> Self {
+            Self(*lock())
+        }
+
+        pub(crate) fn checked_add(&self, duration: Duration) -> Option<Self> {
+            self.0.checked_add(duration).map(Self)
+        }
+
+        pub(crate) fn saturating_duration_since(&self, earlier: Self) -> Duration {
+            self.0.saturating_sub(earlier.0)
+        }
+    }
+
+    impl Add<Duration> for Instant {
+        type Output = Self;
+
+        fn add(self, rhs: Duration) -> Self {
+            Self(self.0 + rhs)
+        }
+    }
+
+    impl Sub for Instant {
+        type Output = Duration;
+
+        fn sub(self, rhs: Self) -> Duration {
+            self.saturating_duration_since(rhs)
+        }
+    }
+
+    /// Moves virtual time forward to `instant`, unless it has already passed.
+    pub(super) fn advance_to(instant: Instant) {
+        let mut elapsed = lock();
+        *elapsed = (*elapsed).max(instant.0);
+    }
+
+    fn lock() -> std::sync::MutexGuard<'static, Duration> {
+        ELAPSED
+            .lock()
+            .expect("replay internal state (virtual time) has become corrupted.")
+    }
+}
@@ -0,0 +1,130 @@
+//! Measuring how closely a replay keeps to the timing of the recording.
+
+use std::{collections::HashMap, time::Duration};
+
+/// The number of bits of each lag which are kept, which bounds the error of a percentile to an
+/// eighth of its value.
+const PRECISION_BITS: u32 = 3;
+const SUB_BUCKETS: u64 = 1 << PRECISION_BITS;
+
+/// How closely a replay kept to the timing of the recording, see
+/// [`ReplaySummary::timing_fidelity`].
+///
+/// The deviation of each trace is how much later it was dispatched than it was scheduled to be,
+/// given the replay speed.
+///
+/// [`ReplaySummary::timing_fidelity`]: fn@crate::ReplaySummary::timing_fidelity
+#[non_exhaustive]
+#[derive(Clone, Debug, Default)]
+pub struct TimingFidelity {
+    /// The deviation of all the traces.
+    pub overall: LagDistribution,
+    /// The deviation of each kind of trace, keyed by the name of the kind, such as `"Event"` or
+    /// `"NewSpan"`.
+    pub kinds: HashMap<&'static str, LagDistribution>,
+}
+
+/// The distribution of the deviation of the dispatch time of traces from their scheduled time.
+///
+/// Percentiles are within an eighth of their exact value, the maximum is exact.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct LagDistribution {
+    /// The number of traces which were dispatched.
+    pub count: u64,
+    /// The median deviation.
+    pub p50: Duration,
+    /// The 95th percentile of the deviation.
+    pub p95: Duration,
+    /// The largest deviation.
+    pub max: Duration,
+}
+
+/// A histogram of the lag of dispatched traces, in microseconds.
+///
+/// Lags below [`SUB_BUCKETS`] microseconds have a bucket each, larger lags share a bucket with
+/// the lags which have the same [`PRECISION_BITS`] most significant bits.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LagHistogram {
+    counts: Vec<u64>,
+    count: u64,
+    max_us: u64,
+}
+
+impl LagHistogram {
+    pub(crate) fn record(&mut self, lag: Duration) {
+        let lag_us = u64::try_from(lag.as_micros()).unwrap_or(u64::MAX);
+        let bucket = bucket(lag_us);
+        if self.counts.len() <= bucket {
+            self.counts.resize(bucket + 1, 0);
+        }
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.max_us = self.max_us.max(lag_us);
+    }
+
+    pub(crate) fn merge(&mut self, other: &Self) {
+        if self.counts.len() < other.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (count, other_count) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other_count;
+        }
+        self.count += other.count;
+        self.max_us = self.max_us.max(other.max_us);
+    }
+
+    pub(crate) fn distribution(&self) -> LagDistribution {
+        LagDistribution {
+            count: self.count,
+            p50: self.percentile(0.5),
+            p95: self.percentile(0.95),
+            max: Duration::from_micros(self.max_us),
+        }
+    }
+
+    /// Returns the lag which the fraction `quantile` of the traces were dispatched within.
+    fn percentile(&self, quantile: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        // The rank is at least 1 and at most the count, so the conversions don't truncate.
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let rank = ((quantile * self.count as f64).ceil() as u64).clamp(1, self.count);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let end_us = bucket_start(bucket + 1).saturating_sub(1);
+                return Duration::from_micros(end_us.max(bucket_start(bucket)).min(self.max_us));
+            }
+        }
+
+        Duration::from_micros(self.max_us)
+    }
+}
+
+/// Returns the bucket of a lag of `lag_us` microseconds.
+fn bucket(lag_us: u64) -> usize {
+    let bucket = if lag_us < SUB_BUCKETS {
+        lag_us
+    } else {
+        let exponent = u64::from(63 - lag_us.leading_zeros());
+        let sub_bucket = (lag_us >> (exponent - u64::from(PRECISION_BITS))) & (SUB_BUCKETS - 1);
+        SUB_BUCKETS + (exponent - u64::from(PRECISION_BITS)) * SUB_BUCKETS + sub_bucket
+    };
+    usize::try_from(bucket).expect("there are fewer than 1024 buckets")
+}
+
+/// Returns the smallest lag in microseconds which falls into `bucket`.
+fn bucket_start(bucket: usize) -> u64 {
+    let bucket = bucket as u64;
+    if bucket < SUB_BUCKETS {
+        bucket
+    } else {
+        let shift = (bucket - SUB_BUCKETS) / SUB_BUCKETS;
+        let sub_bucket = (bucket - SUB_BUCKETS) % SUB_BUCKETS;
+        // The start of the buckets past the largest lag doesn't fit into a `u64`.
+        u64::try_from(u128::from(SUB_BUCKETS + sub_bucket) << shift).unwrap_or(u64::MAX)
+    }
+}
@@ -0,0 +1,204 @@
+use std::cell::Cell;
+
+use tracing_cassette::TraceContext;
+
+thread_local! {
+    /// The recorded trace context of the span which is currently being created by the replay.
+    static RECORDED_TRACE_CONTEXT: Cell<Option<TraceContext>> = const { Cell::new(None) };
+}
+
+/// Calls `f` with `trace_context` set as the recorded trace context of the span being created.
+pub(crate) fn with_recorded_trace_context<R>(
+    trace_context: Option<TraceContext>,
+    f: impl FnOnce() -> R,
+) -> R {
+    let previous = RECORDED_TRACE_CONTEXT.with(|cell| cell.replace(trace_context));
+    let result = f();
+    RECORDED_TRACE_CONTEXT.with(|cell| cell.set(previous));
+    result
+}
+
+/// Returns the distributed trace context that the span which is being created by the replay was
+/// recorded with.
+///
+/// Recordings made by `tracing-rec` with its `opentelemetry` feature have the W3C trace Id and
+/// span Id of each span. While a replayed span is being created, a layer can call this function
+/// from [`Layer::on_new_span`] to give the span the identity it had in the distributed trace,
+/// instead of a new one. With the `opentelemetry` feature, `PropagateTraceContext` does this
+/// for `tracing-opentelemetry`.
+///
+/// Returns `None` outside of the creation of a replayed span, and for spans which were recorded
+/// without a trace context.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::{Arc, Mutex};
+///
+/// use tracing::Dispatch;
+/// use tracing_replay::{recording::TraceContext, Replay, ReplayMode};
+/// use tracing_subscriber::{layer::SubscriberExt, Layer};
+///
+/// struct Contexts(Arc<Mutex<Vec<TraceContext>>>);
+///
+/// impl<S: tracing::Subscriber> Layer<S> for Contexts {
+///     fn on_new_span(
+///         &self,
+///         _attrs: &tracing::span::Attributes<'_>,
+///         _id: &tracing::span::Id,
+///         _ctx: tracing_subscriber::layer::Context<'_, S>,
+///     ) {
+///         if let Some(trace_context) = tracing_replay::recorded_trace_context() {
+///             self.0.lock().unwrap().push(trace_context);
+///         }
+///     }
+/// }
+///
+/// let new_span = r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":1,"fields":[],"metadata":{"id":4403349456,"name":"request","target":"app","level":"Info","module_path":"app","file":"src/main.rs","line":8,"fields":[],"kind":"Span"},"parent":"Root","trace_context":{"trace_id":"4bf92f3577b34da6a3ce929d0e0e4736","span_id":"00f067aa0ba902b7"}}}}"#;
+///
+/// let contexts = Arc::new(Mutex::new(Vec::new()));
+/// let subscriber = tracing_subscriber::registry().with(Contexts(Arc::clone(&contexts)));
+/// let mut replay = Replay::new()
+///     .with_mode(ReplayMode::Deterministic)
+///     .with_dispatch_targets([Dispatch::new(subscriber)]);
+/// replay.replay_str(new_span).unwrap();
+/// replay.close().unwrap();
+///
+/// let contexts = contexts.lock().unwrap();
+/// assert_eq!(contexts[0].trace_id, 0x4bf9_2f35_77b3_4da6_a3ce_929d_0e0e_4736);
+/// assert_eq!(contexts[0].span_id, 0x00f0_67aa_0ba9_02b7);
+/// ```
+///
+/// [`Layer::on_new_span`]: fn@tracing_subscriber::Layer::on_new_span
+#[must_use]
+pub fn recorded_trace_context() -> Option<TraceContext> {
+    RECORDED_TRACE_CONTEXT.with(Cell::get)
+}
+
+#[cfg(feature = "opentelemetry")]
+pub use otel::PropagateTraceContext;
+
+#[cfg(feature = "opentelemetry")]
+mod otel {
+    use opentelemetry::trace::{SpanId, TraceId};
+    use tracing::{span, Subscriber};
+    use tracing_opentelemetry::OtelData;
+    use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+    /// A layer which gives replayed spans the OpenTelemetry trace context they were recorded with.
+    ///
+    /// Without it, `tracing-opentelemetry` starts a new distributed trace for each replayed root
+    /// span. With it, each replayed span which has a recorded trace context is exported with its
+    /// recorded trace Id, and by default its recorded span Id, so an OTLP exporter downstream
+    /// attributes the replayed spans to their original distributed traces. Spans which were
+    /// recorded without a trace context are left alone, but are still part of the trace of their
+    /// parent.
+    ///
+    /// The layer changes the data which `tracing-opentelemetry` keeps for each span, so it must be
+    /// added after the `tracing-opentelemetry` layer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::{
+    ///     future::{self, Future},
+    ///     pin::Pin,
+    ///     sync::{Arc, Mutex},
+    /// };
+    ///
+    /// use opentelemetry::trace::TracerProvider as _;
+    /// use opentelemetry_sdk::{
+    ///     export::trace::{ExportResult, SpanData, SpanExporter},
+    ///     trace::TracerProvider,
+    /// };
+    /// use tracing::Dispatch;
+    /// use tracing_replay::{PropagateTraceContext, Replay, ReplayMode};
+    /// use tracing_subscriber::layer::SubscriberExt;
+    ///
+    /// /// Keeps the exported spans in memory.
+    /// #[derive(Clone, Debug, Default)]
+    /// struct InMemoryExporter(Arc<Mutex<Vec<SpanData>>>);
+    ///
+    /// impl SpanExporter for InMemoryExporter {
+    ///     fn export(
+    ///         &mut self,
+    ///         batch: Vec<SpanData>,
+    ///     ) -> Pin<Box<dyn Future<Output = ExportResult> + Send>> {
+    ///         self.0.lock().unwrap().extend(batch);
+    ///         Box::pin(future::ready(Ok(())))
+    ///     }
+    /// }
+    ///
+    /// let new_span = r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543400,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"NewSpan":{"id":1,"fields":[],"metadata":{"id":4403349456,"name":"request","target":"app","level":"Info","module_path":"app","file":"src/main.rs","line":8,"fields":[],"kind":"Span"},"parent":"Root","trace_context":{"trace_id":"4bf92f3577b34da6a3ce929d0e0e4736","span_id":"00f067aa0ba902b7"}}}}"#;
+    /// let close = r#"{"meta":{"timestamp_s":1715177340,"timestamp_subsec_us":543500,"thread_id":"ThreadId(1)","thread_name":"main"},"trace":{"Close":1}}"#;
+    ///
+    /// let exporter = InMemoryExporter::default();
+    /// let provider = TracerProvider::builder()
+    ///     .with_simple_exporter(exporter.clone())
+    ///     .build();
+    /// let subscriber = tracing_subscriber::registry()
+    ///     .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("replay")))
+    ///     .with(PropagateTraceContext::new());
+    /// let mut replay = Replay::new()
+    ///     .with_mode(ReplayMode::Deterministic)
+    ///     .with_dispatch_targets([Dispatch::new(subscriber)]);
+    /// replay.replay_str(&format!("{new_span}\n{close}")).unwrap();
+    /// replay.close().unwrap();
+    ///
+    /// let spans = exporter.0.lock().unwrap();
+    /// let span_context = &spans[0].span_context;
+    /// assert_eq!(span_context.trace_id().to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+    /// assert_eq!(span_context.span_id().to_string(), "00f067aa0ba902b7");
+    /// ```
+    #[derive(Clone, Debug)]
+    pub struct PropagateTraceContext {
+        span_ids: bool,
+    }
+
+    impl PropagateTraceContext {
+        /// Creates a layer which gives replayed spans their recorded trace Id and span Id.
+        #[must_use]
+        pub fn new() -> Self {
+            Self { span_ids: true }
+        }
+
+        /// Gives replayed spans new span Ids, and only keeps their recorded trace Id.
+        ///
+        /// This avoids exporting spans with the same span Id as the spans which were exported
+        /// when the recording was made, such as when replaying into the same tracing backend.
+        #[must_use]
+        pub fn with_new_span_ids(mut self) -> Self {
+            self.span_ids = false;
+            self
+        }
+    }
+
+    impl Default for PropagateTraceContext {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<S> Layer<S> for PropagateTraceContext
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_new_span(&self, _attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+            let Some(trace_context) = super::recorded_trace_context() else {
+                return;
+            };
+            let Some(span) = ctx.span(id) else {
+                return;
+            };
+            let mut extensions = span.extensions_mut();
+            if let Some(otel_data) = extensions.get_mut::<OtelData>() {
+                otel_data.builder.trace_id =
+                    Some(TraceId::from_bytes(trace_context.trace_id.to_be_bytes()));
+                if self.span_ids {
+                    otel_data.builder.span_id =
+                        Some(SpanId::from_bytes(trace_context.span_id.to_be_bytes()));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,736 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{self, AtomicU64},
+        Arc, Mutex, MutexGuard,
+    },
+    thread,
+};
+
+use tracing::{field::Visit, span, subscriber::Interest, Event, Metadata, Subscriber};
+
+use crate::{
+    recording::{Field, FieldValue},
+    DispatchableParent, DispatchableTrace, SpanKey,
+};
+
+/// The result of verifying a replay.
+///
+/// A verification report is included in the [`ReplayCloseSummary`] of a replay which was created
+/// with [`Replay::with_verification`]. See that method for details of what is compared.
+///
+/// [`ReplayCloseSummary`]: struct@crate::ReplayCloseSummary
+/// [`Replay::with_verification`]: fn@crate::Replay::with_verification
+#[non_exhaustive]
+#[derive(Debug, Default)]
+pub struct VerificationReport {
+    /// The differences found between the recording and the replay.
+    pub mismatches: Vec<VerificationMismatch>,
+}
+
+impl VerificationReport {
+    /// Returns whether the replay matched the recording.
+    #[must_use]
+    pub fn is_match(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl fmt::Display for VerificationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_match() {
+            return write!(f, "replay matches the recording");
+        }
+
+        write!(f, "replay differs from the recording:")?;
+        for mismatch in &self.mismatches {
+            write!(f, "\n - {mismatch}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A difference between the recording and the replay.
+///
+/// Traces are rendered in a normalized form, in which span Ids are replaced by the recorded thread
+/// the span was created on and the number of spans created on that thread before it.
+#[non_exhaustive]
+#[derive(Debug, Eq, PartialEq)]
+pub enum VerificationMismatch {
+    /// The traces replayed for a recorded thread diverge from the recording.
+    ///
+    /// Only the first divergence for each recorded thread is reported, since everything after it
+    /// is likely to differ as well.
+    Thread {
+        /// The recorded thread Id.
+        thread_id: String,
+        /// The index of the first trace which differs, counting only the traces which were
+        /// replayed for this thread.
+        index: usize,
+        /// The trace from the recording, or `None` if the replay contains additional traces.
+        expected: Option<String>,
+        /// The trace from the replay, or `None` if the replay is missing traces.
+        actual: Option<String>,
+    },
+    /// A follows from relationship is only present in either the recording or the replay.
+    FollowsFrom {
+        /// The relationship from the recording, if it is missing from the replay.
+        expected: Option<String>,
+        /// The relationship from the replay, if it is missing from the recording.
+        actual: Option<String>,
+    },
+}
+
+impl fmt::Display for VerificationMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_nothing = |trace: &Option<String>| trace.clone().unwrap_or("<nothing>".into());
+        match self {
+            Self::Thread {
+                thread_id,
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{thread_id} trace {index}: expected {expected}, got {actual}",
+                expected = or_nothing(expected),
+                actual = or_nothing(actual),
+            ),
+            Self::FollowsFrom { expected, actual } => write!(
+                f,
+                "follows from: expected {expected}, got {actual}",
+                expected = or_nothing(expected),
+                actual = or_nothing(actual),
+            ),
+        }
+    }
+}
+
+/// Collects the traces sent by a replay and the traces received by the verifying subscriber and
+/// compares them.
+#[derive(Debug)]
+pub(crate) struct Verifier {
+    expected: Traces,
+    span_refs: HashMap<SpanKey, SpanRef>,
+    capture: Arc<Capture>,
+}
+
+impl Verifier {
+    pub(crate) fn new() -> Self {
+        Self {
+            expected: Traces::default(),
+            span_refs: HashMap::new(),
+            capture: Arc::new(Capture::default()),
+        }
+    }
+
+    /// The subscriber which the replay must be dispatched to.
+    pub(crate) fn subscriber(&self) -> VerifyingSubscriber {
+        VerifyingSubscriber {
+            capture: Arc::clone(&self.capture),
+        }
+    }
+
+    /// Adds a trace which is about to be dispatched for the recorded thread `rec_thread_id`.
+    pub(crate) fn expect(&mut self, rec_thread_id: &str, trace: &DispatchableTrace) {
+        let verify_trace = match trace {
+            DispatchableTrace::RegisterCallsite(dis_metadata) => {
+                VerifyTrace::RegisterCallsite(Callsite(dis_metadata.0))
+            }
+            DispatchableTrace::Event(dis_event) => VerifyTrace::Event {
+                callsite: Callsite(dis_event.metadata),
+                fields: expected_fields(dis_event.metadata, &dis_event.fields),
+                parent: self.expected_parent(&dis_event.parent),
+            },
+            DispatchableTrace::NewSpan(dis_new_span) => {
+                let parent = self.expected_parent(&dis_new_span.parent);
+                let span = self.expected.new_span_ref(rec_thread_id);
+                self.span_refs.insert(dis_new_span.span_key, span.clone());
+                VerifyTrace::NewSpan {
+                    span,
+                    callsite: Callsite(dis_new_span.metadata),
+                    fields: expected_fields(dis_new_span.metadata, &dis_new_span.fields),
+                    parent,
+                }
+            }
+            DispatchableTrace::Enter(dis_span_id) => {
+                VerifyTrace::Enter(self.span_ref(dis_span_id.0))
+            }
+            DispatchableTrace::Exit(dis_span_id) => VerifyTrace::Exit(self.span_ref(dis_span_id.0)),
+            DispatchableTrace::Close(dis_span_id) => {
+                VerifyTrace::Close(self.span_ref(dis_span_id.0))
+            }
+            DispatchableTrace::Record(dis_record_values) => VerifyTrace::Record {
+                span: self.span_ref(dis_record_values.span_key),
+                fields: expected_fields(dis_record_values.metadata, &dis_record_values.fields),
+            },
+            DispatchableTrace::FollowsFrom(dis_follows_from) => {
+                self.expected.follows_from.push(FollowsFrom {
+                    cause: self.span_ref(dis_follows_from.cause),
+                    effect: self.span_ref(dis_follows_from.effect),
+                });
+                return;
+            }
+        };
+
+        self.expected.push(rec_thread_id, verify_trace);
+    }
+
+    /// Compares the expected traces with those received by the subscriber.
+    ///
+    /// Must only be called once all the dispatcher threads have completed. The `replay_threads`
+    /// map from the replay thread Ids to the recorded thread Ids.
+    pub(crate) fn report(
+        &mut self,
+        replay_threads: &HashMap<thread::ThreadId, String>,
+    ) -> VerificationReport {
+        let rec_thread_ids: HashMap<String, String> = replay_threads
+            .iter()
+            .map(|(replay_thread_id, rec_thread_id)| {
+                (format!("{replay_thread_id:?}"), rec_thread_id.clone())
+            })
+            .collect();
+        let expected = std::mem::take(&mut self.expected);
+        let actual = std::mem::take(&mut *self.capture.lock()).into_recorded(&rec_thread_ids);
+
+        let mut mismatches = Vec::new();
+        let mut thread_ids: Vec<&String> = expected.threads.keys().collect();
+        thread_ids.extend(
+            actual
+                .threads
+                .keys()
+                .filter(|thread_id| !expected.threads.contains_key(*thread_id)),
+        );
+        thread_ids.sort();
+        for thread_id in thread_ids {
+            let expected_traces = expected
+                .threads
+                .get(thread_id)
+                .map_or(&[][..], Vec::as_slice);
+            let actual_traces = actual.threads.get(thread_id).map_or(&[][..], Vec::as_slice);
+
+            let len = expected_traces.len().max(actual_traces.len());
+            if let Some(index) =
+                (0..len).find(|idx| expected_traces.get(*idx) != actual_traces.get(*idx))
+            {
+                mismatches.push(VerificationMismatch::Thread {
+                    thread_id: thread_id.clone(),
+                    index,
+                    expected: expected_traces.get(index).map(ToString::to_string),
+                    actual: actual_traces.get(index).map(ToString::to_string),
+                });
+            }
+        }
+
+        // Follows from relationships may be held back until both spans have been replayed, so
+        // they are compared without regard for their order.
+        let mut unmatched_actual = actual.follows_from;
+        for follows_from in expected.follows_from {
+            if let Some(idx) = unmatched_actual
+                .iter()
+                .position(|actual| *actual == follows_from)
+            {
+                unmatched_actual.swap_remove(idx);
+            } else {
+                mismatches.push(VerificationMismatch::FollowsFrom {
+                    expected: Some(follows_from.to_string()),
+                    actual: None,
+                });
+            }
+        }
+        mismatches.extend(unmatched_actual.into_iter().map(|follows_from| {
+            VerificationMismatch::FollowsFrom {
+                expected: None,
+                actual: Some(follows_from.to_string()),
+            }
+        }));
+
+        VerificationReport { mismatches }
+    }
+
+    fn span_ref(&self, span_key: SpanKey) -> SpanRef {
+        self.span_refs.get(&span_key).cloned().unwrap_or(SpanRef {
+            thread_id: "<unknown>".into(),
+            ordinal: 0,
+        })
+    }
+
+    /// The parent that the subscriber is expected to see.
+    ///
+    /// Explicit parents which were never replayed are replaced by a root parent, in the same way
+    /// that the dispatcher does.
+    fn expected_parent(&self, dis_parent: &DispatchableParent) -> VerifyParent {
+        match dis_parent {
+            DispatchableParent::Root => VerifyParent::Root,
+            DispatchableParent::Current => VerifyParent::Current,
+            DispatchableParent::Explicit(span_key) => self
+                .span_refs
+                .get(span_key)
+                .map_or(VerifyParent::Root, |span| {
+                    VerifyParent::Explicit(span.clone())
+                }),
+        }
+    }
+}
+
+/// The fields that the subscriber is expected to see, which excludes recorded fields that aren't
+/// part of the callsite.
+fn expected_fields(
+    metadata: &'static Metadata<'static>,
+    fields: &[Field],
+) -> Vec<(String, VerifyValue)> {
+    fields
+        .iter()
+        .filter(|field| metadata.fields().field(&field.name).is_some())
+        .map(|field| (field.name.clone(), VerifyValue::from(&field.value)))
+        .collect()
+}
+
+/// The traces received by the verifying subscriber.
+#[derive(Debug, Default)]
+struct Capture {
+    state: Mutex<CaptureState>,
+    next_span_id: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct CaptureState {
+    /// Traces keyed by the Id of the replay thread that they were received on.
+    traces: Traces,
+    span_refs: HashMap<u64, SpanRef>,
+}
+
+impl Capture {
+    fn lock(&self) -> MutexGuard<'_, CaptureState> {
+        self.state
+            .lock()
+            .expect("replay internal state (verification) has become corrupted.")
+    }
+}
+
+impl CaptureState {
+    fn span_ref(&self, id: &span::Id) -> SpanRef {
+        self.span_refs
+            .get(&id.into_u64())
+            .cloned()
+            .unwrap_or(SpanRef {
+                thread_id: "<unknown>".into(),
+                ordinal: 0,
+            })
+    }
+
+    /// Converts the captured traces to be keyed by the recorded thread Ids.
+    ///
+    /// Traces received on threads which weren't replaying a recorded thread are discarded.
+    fn into_recorded(self, rec_thread_ids: &HashMap<String, String>) -> Traces {
+        let to_recorded = |span: &mut SpanRef| {
+            if let Some(rec_thread_id) = rec_thread_ids.get(&span.thread_id) {
+                span.thread_id.clone_from(rec_thread_id);
+            }
+        };
+
+        let mut traces = self.traces;
+        traces
+            .threads
+            .retain(|replay_thread_id, _| rec_thread_ids.contains_key(replay_thread_id));
+        for verify_traces in traces.threads.values_mut() {
+            for verify_trace in verify_traces {
+                verify_trace.span_refs_mut(to_recorded);
+            }
+        }
+        for follows_from in &mut traces.follows_from {
+            to_recorded(&mut follows_from.cause);
+            to_recorded(&mut follows_from.effect);
+        }
+        traces.threads = traces
+            .threads
+            .into_iter()
+            .filter_map(|(replay_thread_id, verify_traces)| {
+                Some((
+                    rec_thread_ids.get(&replay_thread_id)?.clone(),
+                    verify_traces,
+                ))
+            })
+            .collect();
+
+        traces
+    }
+}
+
+/// Subscriber which captures everything that is dispatched to it for verification.
+pub(crate) struct VerifyingSubscriber {
+    capture: Arc<Capture>,
+}
+
+impl VerifyingSubscriber {
+    fn push(&self, verify_trace: VerifyTrace) {
+        self.capture
+            .lock()
+            .traces
+            .push(&current_thread_id(), verify_trace);
+    }
+}
+
+fn current_thread_id() -> String {
+    format!("{:?}", thread::current().id())
+}
+
+impl Subscriber for VerifyingSubscriber {
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.push(VerifyTrace::RegisterCallsite(Callsite(metadata)));
+        Interest::always()
+    }
+
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, attrs: &span::Attributes<'_>) -> span::Id {
+        let id = span::Id::from_u64(
+            self.capture
+                .next_span_id
+                .fetch_add(1, atomic::Ordering::Relaxed)
+                + 1,
+        );
+        let mut fields = FieldsVisitor::default();
+        attrs.record(&mut fields);
+
+        let thread_id = current_thread_id();
+        let mut state = self.capture.lock();
+        let parent = if attrs.is_root() {
+            VerifyParent::Root
+        } else if let Some(parent) = attrs.parent() {
+            VerifyParent::Explicit(state.span_ref(parent))
+        } else {
+            VerifyParent::Current
+        };
+        let span = state.traces.new_span_ref(&thread_id);
+        state.span_refs.insert(id.into_u64(), span.clone());
+        state.traces.push(
+            &thread_id,
+            VerifyTrace::NewSpan {
+                span,
+                callsite: Callsite(attrs.metadata()),
+                fields: fields.fields,
+                parent,
+            },
+        );
+
+        id
+    }
+
+    fn record(&self, span: &span::Id, values: &span::Record<'_>) {
+        let mut fields = FieldsVisitor::default();
+        values.record(&mut fields);
+
+        let mut state = self.capture.lock();
+        let span = state.span_ref(span);
+        state.traces.push(
+            &current_thread_id(),
+            VerifyTrace::Record {
+                span,
+                fields: fields.fields,
+            },
+        );
+    }
+
+    fn record_follows_from(&self, span: &span::Id, follows: &span::Id) {
+        let mut state = self.capture.lock();
+        let follows_from = FollowsFrom {
+            cause: state.span_ref(follows),
+            effect: state.span_ref(span),
+        };
+        state.traces.follows_from.push(follows_from);
+    }
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = FieldsVisitor::default();
+        event.record(&mut fields);
+
+        let mut state = self.capture.lock();
+        let parent = if event.is_root() {
+            VerifyParent::Root
+        } else if let Some(parent) = event.parent() {
+            VerifyParent::Explicit(state.span_ref(parent))
+        } else {
+            VerifyParent::Current
+        };
+        state.traces.push(
+            &current_thread_id(),
+            VerifyTrace::Event {
+                callsite: Callsite(event.metadata()),
+                fields: fields.fields,
+                parent,
+            },
+        );
+    }
+
+    fn enter(&self, span: &span::Id) {
+        let mut state = self.capture.lock();
+        let span = state.span_ref(span);
+        state
+            .traces
+            .push(&current_thread_id(), VerifyTrace::Enter(span));
+    }
+
+    fn exit(&self, span: &span::Id) {
+        let mut state = self.capture.lock();
+        let span = state.span_ref(span);
+        state
+            .traces
+            .push(&current_thread_id(), VerifyTrace::Exit(span));
+    }
+
+    fn try_close(&self, id: span::Id) -> bool {
+        let mut state = self.capture.lock();
+        let span = state.span_ref(&id);
+        state
+            .traces
+            .push(&current_thread_id(), VerifyTrace::Close(span));
+        true
+    }
+}
+
+/// Traces in a normalized form, grouped by thread.
+#[derive(Debug, Default)]
+struct Traces {
+    threads: HashMap<String, Vec<VerifyTrace>>,
+    new_span_counts: HashMap<String, usize>,
+    follows_from: Vec<FollowsFrom>,
+}
+
+impl Traces {
+    fn push(&mut self, thread_id: &str, verify_trace: VerifyTrace) {
+        if let Some(verify_traces) = self.threads.get_mut(thread_id) {
+            verify_traces.push(verify_trace);
+        } else {
+            self.threads.insert(thread_id.into(), vec![verify_trace]);
+        }
+    }
+
+    fn new_span_ref(&mut self, thread_id: &str) -> SpanRef {
+        let count = self.new_span_counts.entry(thread_id.into()).or_default();
+        let span = SpanRef {
+            thread_id: thread_id.into(),
+            ordinal: *count,
+        };
+        *count += 1;
+        span
+    }
+}
+
+/// Identifies a span independently of the span Ids assigned by a subscriber.
+///
+/// A span is identified by the thread it was created on and the number of spans created on that
+/// thread before it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct SpanRef {
+    thread_id: String,
+    ordinal: usize,
+}
+
+impl fmt::Display for SpanRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}#{}", self.thread_id, self.ordinal)
+    }
+}
+
+/// A callsite, identified by its metadata.
+///
+/// The replay passes the same metadata to the subscriber that it creates from the recording, so
+/// callsites are compared by address.
+#[derive(Clone, Copy, Debug)]
+struct Callsite(&'static Metadata<'static>);
+
+impl PartialEq for Callsite {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.0, other.0)
+    }
+}
+
+impl fmt::Display for Callsite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}::{}", self.0.target(), self.0.name())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum VerifyParent {
+    Root,
+    Current,
+    Explicit(SpanRef),
+}
+
+impl fmt::Display for VerifyParent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Root => write!(f, "root"),
+            Self::Current => write!(f, "current"),
+            Self::Explicit(span) => write!(f, "{span}"),
+        }
+    }
+}
+
+/// A field value, with floating point values stored as their `Debug` representation so that
+/// `NaN` values compare equal.
+#[derive(Clone, Debug, PartialEq)]
+enum VerifyValue {
+    Debug(String),
+    F64(String),
+    I64(i64),
+    U64(u64),
+    I128(i128),
+    U128(u128),
+    Bool(bool),
+    Str(String),
+}
+
+impl From<&FieldValue> for VerifyValue {
+    fn from(value: &FieldValue) -> Self {
+        match value {
+            FieldValue::Debug(val) => Self::Debug(val.clone()),
+            FieldValue::F64(val) => Self::F64(format!("{val:?}")),
+            FieldValue::I64(val) => Self::I64(*val),
+            FieldValue::U64(val) => Self::U64(*val),
+            FieldValue::I128(val) => Self::I128(*val),
+            FieldValue::U128(val) => Self::U128(*val),
+            FieldValue::Bool(val) => Self::Bool(*val),
+            FieldValue::Str(val) => Self::Str(val.clone()),
+            // Structured values are replayed as their JSON text.
+            FieldValue::Json(val) => Self::Debug(val.to_string()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct FieldsVisitor {
+    fields: Vec<(String, VerifyValue)>,
+}
+
+impl FieldsVisitor {
+    fn push(&mut self, field: &tracing::field::Field, value: VerifyValue) {
+        self.fields.push((field.name().into(), value));
+    }
+}
+
+impl Visit for FieldsVisitor {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn fmt::Debug) {
+        self.push(field, VerifyValue::Debug(format!("{value:?}")));
+    }
+
+    fn record_f64(&mut self, field: &tracing::field::Field, value: f64) {
+        self.push(field, VerifyValue::F64(format!("{value:?}")));
+    }
+
+    fn record_i64(&mut self, field: &tracing::field::Field, value: i64) {
+        self.push(field, VerifyValue::I64(value));
+    }
+
+    fn record_u64(&mut self, field: &tracing::field::Field, value: u64) {
+        self.push(field, VerifyValue::U64(value));
+    }
+
+    fn record_i128(&mut self, field: &tracing::field::Field, value: i128) {
+        self.push(field, VerifyValue::I128(value));
+    }
+
+    fn record_u128(&mut self, field: &tracing::field::Field, value: u128) {
+        self.push(field, VerifyValue::U128(value));
+    }
+
+    fn record_bool(&mut self, field: &tracing::field::Field, value: bool) {
+        self.push(field, VerifyValue::Bool(value));
+    }
+
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        self.push(field, VerifyValue::Str(value.into()));
+    }
+}
+
+/// A trace in a normalized form which doesn't depend on timestamps or span Ids.
+#[derive(Clone, Debug, PartialEq)]
+enum VerifyTrace {
+    RegisterCallsite(Callsite),
+    Event {
+        callsite: Callsite,
+        fields: Vec<(String, VerifyValue)>,
+        parent: VerifyParent,
+    },
+    NewSpan {
+        span: SpanRef,
+        callsite: Callsite,
+        fields: Vec<(String, VerifyValue)>,
+        parent: VerifyParent,
+    },
+    Enter(SpanRef),
+    Exit(SpanRef),
+    Close(SpanRef),
+    Record {
+        span: SpanRef,
+        fields: Vec<(String, VerifyValue)>,
+    },
+}
+
+impl VerifyTrace {
+    fn span_refs_mut(&mut self, mut f: impl FnMut(&mut SpanRef)) {
+        match self {
+            Self::RegisterCallsite(_) => {}
+            Self::Event { parent, .. } => {
+                if let VerifyParent::Explicit(span) = parent {
+                    f(span);
+                }
+            }
+            Self::NewSpan { span, parent, .. } => {
+                f(span);
+                if let VerifyParent::Explicit(span) = parent {
+                    f(span);
+                }
+            }
+            Self::Enter(span)
+            | Self::Exit(span)
+            | Self::Close(span)
+            | Self::Record { span, .. } => {
+                f(span);
+            }
+        }
+    }
+}
+
+impl fmt::Display for VerifyTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RegisterCallsite(callsite) => write!(f, "RegisterCallsite({callsite})"),
+            Self::Event {
+                callsite,
+                fields,
+                parent,
+            } => write!(f, "Event({callsite}, parent: {parent}, fields: {fields:?})"),
+            Self::NewSpan {
+                span,
+                callsite,
+                fields,
+                parent,
+            } => write!(
+                f,
+                "NewSpan({span}, {callsite}, parent: {parent}, fields: {fields:?})"
+            ),
+            Self::Enter(span) => write!(f, "Enter({span})"),
+            Self::Exit(span) => write!(f, "Exit({span})"),
+            Self::Close(span) => write!(f, "Close({span})"),
+            Self::Record { span, fields } => write!(f, "Record({span}, fields: {fields:?})"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct FollowsFrom {
+    cause: SpanRef,
+    effect: SpanRef,
+}
+
+impl fmt::Display for FollowsFrom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} follows from {}", self.effect, self.cause)
+    }
+}
@@ -0,0 +1,201 @@
+use std::{
+    fmt,
+    sync::{mpsc, Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use crate::time::Instant;
+
+/// A dispatcher thread which hasn't finished dispatching a trace for longer than the watchdog's
+/// timeout, see [`Replay::with_watchdog`].
+///
+/// [`Replay::with_watchdog`]: fn@crate::Replay::with_watchdog
+#[non_exhaustive]
+#[derive(Clone, Debug)]
+pub struct StalledDispatcher {
+    /// The recorded thread whose traces the dispatcher thread is dispatching.
+    pub thread_id: String,
+    /// What the dispatcher thread is waiting for.
+    pub cause: StallCause,
+    /// The kind of the trace being dispatched, such as `Event` or `Enter`.
+    pub trace_kind: &'static str,
+    /// The recorded timestamp of the trace being dispatched.
+    pub timestamp: SystemTime,
+    /// How long the dispatcher thread has been dispatching the trace for.
+    pub stalled_for: Duration,
+}
+
+/// What a [`StalledDispatcher`] is waiting for.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StallCause {
+    /// Waiting for the recorded timestamp of the trace to be reached, which takes a long time if
+    /// the recording has a long gap between traces or the replay is slow or paused.
+    WaitingForTimestamp,
+    /// Waiting for a span which the trace refers to be created by another dispatcher thread.
+    WaitingForSpan,
+    /// Waiting for the subscriber, which is handling the trace.
+    Subscriber,
+}
+
+/// A hook which is called with each dispatcher thread that stalls.
+type OnStall = Arc<dyn Fn(&StalledDispatcher) + Send + Sync>;
+
+/// Watches the progress of the dispatcher threads from a thread of its own, and reports those
+/// which stall.
+pub(crate) struct Watchdog {
+    timeout: Duration,
+    on_stall: OnStall,
+    dispatchers: Arc<Mutex<Vec<Arc<DispatcherProgress>>>>,
+    /// Stops the watchdog thread when dropped.
+    stop_tx: Option<mpsc::Sender<()>>,
+    join_handle: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    pub(crate) fn new(timeout: Duration, on_stall: OnStall) -> Self {
+        Self {
+            timeout,
+            on_stall,
+            dispatchers: Arc::default(),
+            stop_tx: None,
+            join_handle: None,
+        }
+    }
+
+    /// Returns the progress of a new dispatcher thread, which the watchdog watches.
+    ///
+    /// The watchdog thread is started with the first dispatcher thread.
+    pub(crate) fn watch(&mut self, thread_id: &str) -> Arc<DispatcherProgress> {
+        if self.join_handle.is_none() {
+            self.start();
+        }
+        let progress = Arc::new(DispatcherProgress {
+            thread_id: thread_id.to_owned(),
+            current: Mutex::new(None),
+        });
+        self.lock_dispatchers().push(Arc::clone(&progress));
+        progress
+    }
+
+    fn start(&mut self) {
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let timeout = self.timeout;
+        let on_stall = Arc::clone(&self.on_stall);
+        let dispatchers = Arc::clone(&self.dispatchers);
+        // Checking more often than the timeout bounds how late a stall is reported.
+        let interval = (timeout / 4).max(Duration::from_millis(1));
+        let spawned = thread::Builder::new()
+            .name("tracing-replay-watchdog".into())
+            .spawn(move || {
+                while let Err(mpsc::RecvTimeoutError::Timeout) = stop_rx.recv_timeout(interval) {
+                    let stalled: Vec<_> = dispatchers
+                        .lock()
+                        .expect("replay watchdog state has become corrupted.")
+                        .iter()
+                        .filter_map(|progress| progress.check(timeout))
+                        .collect();
+                    for stalled in &stalled {
+                        on_stall(stalled);
+                    }
+                }
+            });
+        // Without threads, nothing can be watched.
+        if let Ok(join_handle) = spawned {
+            self.stop_tx = Some(stop_tx);
+            self.join_handle = Some(join_handle);
+        }
+    }
+
+    fn lock_dispatchers(&self) -> std::sync::MutexGuard<'_, Vec<Arc<DispatcherProgress>>> {
+        self.dispatchers
+            .lock()
+            .expect("replay watchdog state has become corrupted.")
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        drop(self.stop_tx.take());
+        if let Some(join_handle) = self.join_handle.take() {
+            // A panic in the hook has already been reported by the panic hook.
+            let _ = join_handle.join();
+        }
+    }
+}
+
+impl fmt::Debug for Watchdog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watchdog")
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The trace which a dispatcher thread is dispatching, if any, shared with the watchdog.
+#[derive(Debug)]
+pub(crate) struct DispatcherProgress {
+    thread_id: String,
+    current: Mutex<Option<InProgress>>,
+}
+
+#[derive(Debug)]
+struct InProgress {
+    started: Instant,
+    cause: StallCause,
+    trace_kind: &'static str,
+    timestamp: Duration,
+    /// Whether the stall has been reported, each trace is only reported once.
+    reported: bool,
+}
+
+impl DispatcherProgress {
+    /// Starts dispatching the trace with the recorded `timestamp`, since the Unix epoch.
+    pub(crate) fn start(&self, trace_kind: &'static str, timestamp: Duration, cause: StallCause) {
+        *self.lock() = Some(InProgress {
+            started: Instant::now(),
+            cause,
+            trace_kind,
+            timestamp,
+            reported: false,
+        });
+    }
+
+    /// Moves the trace being dispatched on to waiting for `cause`.
+    pub(crate) fn wait_for(&self, cause: StallCause) {
+        if let Some(current) = &mut *self.lock() {
+            current.cause = cause;
+        }
+    }
+
+    /// Finishes dispatching the current trace.
+    pub(crate) fn finish(&self) {
+        *self.lock() = None;
+    }
+
+    /// Returns the stall to report, if the current trace has stalled since it was last checked.
+    fn check(&self, timeout: Duration) -> Option<StalledDispatcher> {
+        let mut current = self.lock();
+        let current = current.as_mut()?;
+        let stalled_for = Instant::now().saturating_duration_since(current.started);
+        if current.reported || stalled_for < timeout {
+            return None;
+        }
+        current.reported = true;
+
+        Some(StalledDispatcher {
+            thread_id: self.thread_id.clone(),
+            cause: current.cause,
+            trace_kind: current.trace_kind,
+            timestamp: SystemTime::UNIX_EPOCH + current.timestamp,
+            stalled_for,
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<InProgress>> {
+        self.current
+            .lock()
+            .expect("replay watchdog state has become corrupted.")
+    }
+}